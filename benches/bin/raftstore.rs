@@ -11,14 +11,19 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::mpsc;
+use std::time::Instant;
+
 use test::BenchSamples;
 use test_util::*;
 use cluster::*;
 use node::new_node_cluster;
 use server::new_server_cluster;
+use util::{new_put_cmd, new_request};
 
 use rocksdb::{Writable, WriteBatch, DB};
 use tikv::raftstore::store::*;
+use tikv::util::HandyRwLock;
 
 use super::print_result;
 
@@ -65,6 +70,56 @@ fn bench_set<T: Simulator>(mut cluster: Cluster<T>, value_size: usize) -> BenchS
     }
 }
 
+// Puts a key to every region in each round, so the regions are applied concurrently by the
+// apply pool. The raft side of all regions is still handled by the store thread, so it only
+// shows how the apply throughput scales.
+fn bench_set_regions<T: Simulator>(mut cluster: Cluster<T>, region_cnt: usize) -> BenchSamples {
+    prepare_cluster(&mut cluster, &[]);
+    for i in 1..region_cnt {
+        let key = format!("k{:04}", i).into_bytes();
+        let region = cluster.get_region(&key);
+        cluster.must_split(&region, &key);
+    }
+
+    let mut targets = Vec::with_capacity(region_cnt);
+    for i in 0..region_cnt {
+        let key = format!("k{:04}", i).into_bytes();
+        cluster.must_put(&key, b"v");
+        let region = cluster.get_region(&key);
+        let leader = cluster.leader_of_region(region.get_id()).unwrap();
+        let ch = cluster
+            .sim
+            .rl()
+            .get_store_sendch(leader.get_store_id())
+            .unwrap();
+        let mut req = new_request(
+            region.get_id(),
+            region.get_region_epoch().clone(),
+            vec![new_put_cmd(&key, b"v")],
+            false,
+        );
+        req.mut_header().set_peer(leader);
+        targets.push((req, ch));
+    }
+
+    bench!{
+            let (tx, rx) = mpsc::channel();
+            for &(ref req, ref ch) in &targets {
+                let tx = tx.clone();
+                let msg = Msg::RaftCmd {
+                    send_time: Instant::now(),
+                    request: req.clone(),
+                    callback: box move |resp| tx.send(resp).unwrap(),
+                };
+                ch.try_send(msg).unwrap();
+            };
+            for _ in 0..targets.len() {
+                let resp = rx.recv().unwrap();
+                assert!(!resp.get_header().has_error(), "{:?}", resp);
+            }
+    }
+}
+
 fn bench_get<T: Simulator>(mut cluster: Cluster<T>) -> BenchSamples {
     let mut kvs = generate_random_kvs(DEFAULT_DATA_SIZE, 100, 128);
     prepare_cluster(&mut cluster, &kvs);
@@ -115,7 +170,30 @@ where
     }
 }
 
+fn bench_apply_pool<T, F>(factory: F, tag: &'static str)
+where
+    T: Simulator,
+    F: Fn(u64, usize) -> Cluster<T>,
+{
+    let pool_sizes = vec![1, 2, 4, 8];
+    let region_cnt = 16;
+
+    for size in pool_sizes {
+        printf!(
+            "benching Set on {},\tregions: {}, apply pool size: {}\t...",
+            tag,
+            region_cnt,
+            size
+        );
+        let mut cluster = factory(1, 3);
+        cluster.cfg.raft_store.apply_pool_size = size;
+        print_result(bench_set_regions(cluster, region_cnt));
+    }
+}
+
 pub fn bench_raftstore() {
     bench_raft_cluster(new_node_cluster, "raft cluster (channel)");
     bench_raft_cluster(new_server_cluster, "raft cluster (tcp)");
+    bench_apply_pool(new_node_cluster, "raft cluster (channel)");
+    bench_apply_pool(new_server_cluster, "raft cluster (tcp)");
 }
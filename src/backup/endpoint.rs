@@ -0,0 +1,305 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use futures::sync::mpsc::UnboundedSender;
+use kvproto::backup::{BackupRequest, BackupResponse, File};
+use kvproto::kvrpcpb::{Context, IsolationLevel};
+use kvproto::metapb::{Peer, Region};
+use protobuf::RepeatedField;
+use tempdir::TempDir;
+
+use pd::PdClient;
+use raftstore::store::util::find_peer;
+use storage::{Engine, Key, ScanMode, Statistics, CF_DEFAULT, CF_WRITE};
+use storage::mvcc::{Error as MvccError, LockType, MvccReader, WriteType};
+use util::io_limiter::IOLimiter;
+use util::worker::Runnable;
+use super::{create_storage, BackupWriter, Error, ExternalStorage, Result};
+
+// The number of keys scanned in a batch.
+const SCAN_BATCH_SIZE: usize = 1024;
+
+/// Backs up the range `[start_key, end_key)` at the ts of the request, the results of the
+/// regions are sent to `sink`.
+pub struct Task {
+    request: BackupRequest,
+    sink: UnboundedSender<BackupResponse>,
+}
+
+impl Task {
+    pub fn new(request: BackupRequest, sink: UnboundedSender<BackupResponse>) -> Task {
+        Task {
+            request: request,
+            sink: sink,
+        }
+    }
+}
+
+impl Display for Task {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "backup [{:?}, {:?}) at {} to {}",
+            self.request.get_start_key(),
+            self.request.get_end_key(),
+            self.request.get_end_version(),
+            self.request.get_path()
+        )
+    }
+}
+
+/// Backs up the regions led by this store. Each store walks all the regions in the range, and
+/// the ones not led by it are skipped, so the range is covered once by the whole cluster.
+pub struct Endpoint<C: PdClient> {
+    store_id: u64,
+    pd_client: Arc<C>,
+    engine: Box<Engine>,
+    // The directory of the temporary SST files.
+    tmp_dir: PathBuf,
+}
+
+impl<C: PdClient> Endpoint<C> {
+    pub fn new(
+        store_id: u64,
+        pd_client: Arc<C>,
+        engine: Box<Engine>,
+        tmp_dir: &Path,
+    ) -> Endpoint<C> {
+        Endpoint {
+            store_id: store_id,
+            pd_client: pd_client,
+            engine: engine,
+            tmp_dir: tmp_dir.to_owned(),
+        }
+    }
+
+    fn handle_backup(&self, task: Task) {
+        let Task { request, sink } = task;
+        let mut resp = BackupResponse::new();
+        let storage = match create_storage(request.get_path()) {
+            Ok(s) => s,
+            Err(e) => {
+                resp.set_error(Error::from(e).into_pb());
+                let _ = sink.unbounded_send(resp);
+                return;
+            }
+        };
+        let tmp_dir = match TempDir::new_in(&self.tmp_dir, "backup") {
+            Ok(dir) => dir,
+            Err(e) => {
+                resp.set_error(Error::from(e).into_pb());
+                let _ = sink.unbounded_send(resp);
+                return;
+            }
+        };
+        let mut limiter = IOLimiter::new(request.get_rate_limit());
+        let backup_ts = request.get_end_version();
+
+        let start = Key::from_raw(request.get_start_key()).encoded().clone();
+        let end = if request.get_end_key().is_empty() {
+            vec![]
+        } else {
+            Key::from_raw(request.get_end_key()).encoded().clone()
+        };
+        let mut key = start.clone();
+        loop {
+            let region = match self.pd_client.get_region(&key) {
+                Ok(region) => region,
+                Err(e) => {
+                    resp.set_error(Error::from(e).into_pb());
+                    let _ = sink.unbounded_send(resp);
+                    return;
+                }
+            };
+            if let Some(peer) = find_peer(&region, self.store_id) {
+                let range_start = if region.get_start_key() > start.as_slice() {
+                    region.get_start_key().to_vec()
+                } else {
+                    start.clone()
+                };
+                let range_end = if end.is_empty()
+                    || (!region.get_end_key().is_empty() && region.get_end_key() < end.as_slice())
+                {
+                    region.get_end_key().to_vec()
+                } else {
+                    end.clone()
+                };
+                let res = self.backup_region(
+                    &region,
+                    peer,
+                    &range_start,
+                    &range_end,
+                    backup_ts,
+                    tmp_dir.path(),
+                    storage.as_ref(),
+                    &mut limiter,
+                );
+                match res {
+                    Err(ref e) if e.is_not_leader() => {}
+                    res => {
+                        let resp = new_response(&range_start, &range_end, res);
+                        if sink.unbounded_send(resp).is_err() {
+                            warn!("backup of region {} is canceled", region.get_id());
+                            return;
+                        }
+                    }
+                }
+            }
+            if region.get_end_key().is_empty()
+                || (!end.is_empty() && region.get_end_key() >= end.as_slice())
+            {
+                return;
+            }
+            key = region.get_end_key().to_vec();
+        }
+    }
+
+    #[allow(too_many_arguments)]
+    fn backup_region(
+        &self,
+        region: &Region,
+        peer: &Peer,
+        start: &[u8],
+        end: &[u8],
+        backup_ts: u64,
+        tmp_dir: &Path,
+        storage: &ExternalStorage,
+        limiter: &mut IOLimiter,
+    ) -> Result<Vec<File>> {
+        let mut ctx = Context::new();
+        ctx.set_region_id(region.get_id());
+        ctx.set_region_epoch(region.get_region_epoch().clone());
+        ctx.set_peer(peer.clone());
+        let snapshot = try!(self.engine.snapshot(&ctx));
+
+        let name = format!(
+            "{}_{}_{}",
+            self.store_id,
+            region.get_id(),
+            region.get_region_epoch().get_version()
+        );
+        let mut writer = try!(BackupWriter::new(tmp_dir, name));
+        let mut statistics = Statistics::default();
+        let mut reader = MvccReader::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            Some(ScanMode::Forward),
+            false,
+            None,
+            IsolationLevel::SI,
+        );
+
+        // The data isn't consistent at `backup_ts` if there are locks before it.
+        let (locks, _) = try!(reader.scan_lock(
+            Some(Key::from_encoded(start.to_vec())),
+            |lock| lock.ts <= backup_ts && lock.lock_type != LockType::Pessimistic,
+            Some(1)
+        ));
+        if let Some(&(ref key, ref lock)) = locks.first() {
+            if end.is_empty() || key.encoded().as_slice() < end {
+                return Err(Error::Mvcc(MvccError::KeyIsLocked {
+                    key: try!(key.raw()),
+                    primary: lock.primary.clone(),
+                    ts: lock.ts,
+                    ttl: lock.ttl,
+                }));
+            }
+        }
+
+        let mut next = Some(Key::from_encoded(start.to_vec()));
+        while next.is_some() {
+            let (keys, next_start) = try!(reader.scan_keys(next.take(), SCAN_BATCH_SIZE));
+            next = next_start;
+            for key in keys {
+                if !end.is_empty() && key.encoded().as_slice() >= end {
+                    next = None;
+                    break;
+                }
+                try!(backup_key(&mut reader, &mut writer, &key, backup_ts));
+            }
+        }
+        writer.save(storage, limiter)
+    }
+}
+
+// Writes the version of `key` visible at `backup_ts`, deleted keys are skipped.
+fn backup_key(
+    reader: &mut MvccReader,
+    writer: &mut BackupWriter,
+    key: &Key,
+    backup_ts: u64,
+) -> Result<()> {
+    let mut ts = backup_ts;
+    loop {
+        let (commit_ts, write) = match try!(reader.seek_write(key, ts)) {
+            Some(res) => res,
+            None => return Ok(()),
+        };
+        match write.write_type {
+            WriteType::Put => {
+                if write.short_value.is_none() {
+                    let value = try!(reader.load_data(key, write.start_ts));
+                    try!(writer.put(
+                        CF_DEFAULT,
+                        key.append_ts(write.start_ts).encoded(),
+                        &value
+                    ));
+                }
+                let k = key.append_ts(commit_ts);
+                return writer.put(CF_WRITE, k.encoded(), &write.to_bytes());
+            }
+            WriteType::Delete => return Ok(()),
+            WriteType::Lock | WriteType::Rollback => ts = commit_ts - 1,
+        }
+    }
+}
+
+fn new_response(start: &[u8], end: &[u8], res: Result<Vec<File>>) -> BackupResponse {
+    let mut resp = BackupResponse::new();
+    match raw_range(start, end) {
+        Ok((start, end)) => {
+            resp.set_start_key(start);
+            resp.set_end_key(end);
+        }
+        Err(e) => {
+            resp.set_error(e.into_pb());
+            return resp;
+        }
+    }
+    match res {
+        Ok(files) => resp.set_files(RepeatedField::from_vec(files)),
+        Err(e) => resp.set_error(e.into_pb()),
+    }
+    resp
+}
+
+fn raw_range(start: &[u8], end: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let start = try!(Key::from_encoded(start.to_vec()).raw());
+    let end = if end.is_empty() {
+        vec![]
+    } else {
+        try!(Key::from_encoded(end.to_vec()).raw())
+    };
+    Ok((start, end))
+}
+
+impl<C: PdClient> Runnable<Task> for Endpoint<C> {
+    fn run(&mut self, task: Task) {
+        info!("start {}", task);
+        self.handle_backup(task);
+    }
+}
@@ -0,0 +1,112 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::error;
+use std::io::Error as IoError;
+use std::result;
+
+use kvproto::backup::Error as ErrorPb;
+use kvproto::errorpb;
+use kvproto::kvrpcpb::{KeyError, LockInfo};
+
+use pd::Error as PdError;
+use storage::engine::Error as EngineError;
+use storage::mvcc::Error as MvccError;
+use util::codec::Error as CodecError;
+
+quick_error!{
+    #[derive(Debug)]
+    pub enum Error {
+        Request(err: errorpb::Error) {
+            from()
+            description(err.get_message())
+            display("{:?}", err)
+        }
+        Engine(err: EngineError) {
+            from()
+            cause(err)
+            display("{:?}", err)
+            description(err.description())
+        }
+        Mvcc(err: MvccError) {
+            from()
+            cause(err)
+            display("{:?}", err)
+            description(err.description())
+        }
+        Codec(err: CodecError) {
+            from()
+            cause(err)
+            display("{:?}", err)
+            description(err.description())
+        }
+        Io(err: IoError) {
+            from()
+            cause(err)
+            display("{:?}", err)
+            description(err.description())
+        }
+        Pd(err: PdError) {
+            from()
+            cause(err)
+            display("{:?}", err)
+            description(err.description())
+        }
+        Other(err: Box<error::Error + Sync + Send>) {
+            from()
+            cause(err.as_ref())
+            description(err.description())
+            display("{:?}", err)
+        }
+    }
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl Error {
+    /// Returns whether the region is not led by this store, so it's backed up by another one.
+    pub fn is_not_leader(&self) -> bool {
+        match *self {
+            Error::Request(ref e) |
+            Error::Engine(EngineError::Request(ref e)) |
+            Error::Mvcc(MvccError::Engine(EngineError::Request(ref e))) => e.has_not_leader(),
+            _ => false,
+        }
+    }
+
+    pub fn into_pb(self) -> ErrorPb {
+        let mut err = ErrorPb::new();
+        match self {
+            Error::Request(e) |
+            Error::Engine(EngineError::Request(e)) |
+            Error::Mvcc(MvccError::Engine(EngineError::Request(e))) => err.set_region_error(e),
+            Error::Mvcc(MvccError::KeyIsLocked {
+                key,
+                primary,
+                ts,
+                ttl,
+            }) => {
+                let mut lock_info = LockInfo::new();
+                lock_info.set_key(key);
+                lock_info.set_primary_lock(primary);
+                lock_info.set_lock_version(ts);
+                lock_info.set_lock_ttl(ttl);
+                let mut key_error = KeyError::new();
+                key_error.set_locked(lock_info);
+                err.set_kv_error(key_error);
+            }
+            e => err.set_msg(format!("{:?}", e)),
+        }
+        err
+    }
+}
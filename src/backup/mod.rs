@@ -0,0 +1,33 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Distributed backup.
+//!
+//! A backup request is sent to all the stores. Every store walks the regions in the range and
+//! backs up the ones it leads: the versions visible at the backup ts are scanned from a
+//! snapshot of the region and written to a SST file per cf, which is then saved to the
+//! `ExternalStorage` of the request at a limited rate. The metadata of the files, including
+//! their checksums, is streamed back per region. A region with locks before the backup ts
+//! fails with the lock, so the client can resolve it and retry.
+
+mod endpoint;
+mod errors;
+mod service;
+mod storage;
+mod writer;
+
+pub use self::endpoint::{Endpoint, Task};
+pub use self::errors::{Error, Result};
+pub use self::service::Service;
+pub use self::storage::{create_storage, ExternalStorage, LocalStorage};
+pub use self::writer::BackupWriter;
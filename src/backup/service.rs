@@ -0,0 +1,59 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use futures::{Future, Sink, Stream};
+use futures::sync::mpsc;
+use grpc::{Error as GrpcError, RpcContext, RpcStatus, RpcStatusCode, ServerStreamingSink,
+           WriteFlags};
+use kvproto::backup::{BackupRequest, BackupResponse};
+use kvproto::backup_grpc;
+
+use util::worker::Scheduler;
+use super::Task;
+
+#[derive(Clone)]
+pub struct Service {
+    scheduler: Scheduler<Task>,
+}
+
+impl Service {
+    pub fn new(scheduler: Scheduler<Task>) -> Service {
+        Service {
+            scheduler: scheduler,
+        }
+    }
+}
+
+impl backup_grpc::Backup for Service {
+    fn backup(
+        &self,
+        ctx: RpcContext,
+        req: BackupRequest,
+        sink: ServerStreamingSink<BackupResponse>,
+    ) {
+        let (tx, rx) = mpsc::unbounded();
+        if let Err(e) = self.scheduler.schedule(Task::new(req, tx)) {
+            let status = RpcStatus::new(RpcStatusCode::Unavailable, Some(format!("{}", e)));
+            ctx.spawn(sink.fail(status).map_err(|e| {
+                error!("failed to fail backup: {:?}", e);
+            }));
+            return;
+        }
+        // The stream ends when the endpoint finishes the backup and drops the sender.
+        let resps = rx.map(|resp| (resp, WriteFlags::default()))
+            .map_err(|()| GrpcError::RemoteStopped);
+        ctx.spawn(sink.send_all(resps).map(|_| ()).map_err(|e| {
+            warn!("backup stream is closed: {:?}", e);
+        }));
+    }
+}
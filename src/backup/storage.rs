@@ -0,0 +1,108 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use url::Url;
+
+/// The storage the backup files are saved to.
+pub trait ExternalStorage: Send + Sync {
+    /// Writes all the content of `reader` to the file `name`.
+    fn write(&self, name: &str, reader: &mut Read) -> io::Result<()>;
+
+    fn read(&self, name: &str) -> io::Result<Box<Read>>;
+}
+
+/// Creates the storage for the url, the scheme is the type of the storage. A url without
+/// scheme is a local path.
+pub fn create_storage(url: &str) -> io::Result<Arc<ExternalStorage>> {
+    let (scheme, path) = match Url::parse(url) {
+        Ok(u) => (u.scheme().to_owned(), u.path().to_owned()),
+        Err(_) => ("local".to_owned(), url.to_owned()),
+    };
+    match scheme.as_str() {
+        "local" | "file" => {
+            let s = try!(LocalStorage::new(Path::new(&path)));
+            Ok(Arc::new(s))
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("unsupported storage {}", url),
+        )),
+    }
+}
+
+/// Saves the files in a directory of the local file system.
+pub struct LocalStorage {
+    base: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(base: &Path) -> io::Result<LocalStorage> {
+        try!(fs::create_dir_all(base));
+        Ok(LocalStorage {
+            base: base.to_owned(),
+        })
+    }
+}
+
+impl ExternalStorage for LocalStorage {
+    fn write(&self, name: &str, reader: &mut Read) -> io::Result<()> {
+        // Write to a temporary file first, so that no partial file is left.
+        let tmp_path = self.base.join(format!("{}.tmp", name));
+        {
+            let mut f = try!(File::create(&tmp_path));
+            try!(io::copy(reader, &mut f));
+            try!(f.sync_all());
+        }
+        fs::rename(tmp_path, self.base.join(name))
+    }
+
+    fn read(&self, name: &str) -> io::Result<Box<Read>> {
+        let f = try!(File::open(self.base.join(name)));
+        Ok(box f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_local_storage() {
+        let dir = TempDir::new("_backup_test_local_storage").unwrap();
+        let path = dir.path().join("backup");
+        let url = format!("local://{}", path.display());
+        let storage = create_storage(&url).unwrap();
+
+        storage.write("a", &mut b"hello".as_ref()).unwrap();
+        let mut buf = vec![];
+        storage.read("a").unwrap().read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"hello");
+        assert!(!path.join("a.tmp").exists());
+        assert!(storage.read("b").is_err());
+
+        // A path without scheme is local too.
+        let storage = create_storage(path.to_str().unwrap()).unwrap();
+        buf.clear();
+        storage.read("a").unwrap().read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"hello");
+
+        assert!(create_storage("s3://bucket/backup").is_err());
+    }
+}
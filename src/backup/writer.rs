@@ -0,0 +1,267 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::{self, File as StdFile};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crc::crc32::{self, Digest, Hasher32};
+use kvproto::backup::File;
+use rocksdb::{ColumnFamilyOptions, EnvOptions, SstFileWriter};
+
+use raftstore::store::keys;
+use storage::{Key, CF_DEFAULT, CF_WRITE};
+use storage::types::split_encoded_key_on_ts;
+use util::io_limiter::{IOLimiter, LimitReader};
+use super::{ExternalStorage, Result};
+
+struct Writer {
+    cf: &'static str,
+    path: PathBuf,
+    writer: SstFileWriter,
+    total_kvs: u64,
+    total_bytes: u64,
+    // The first and last keys written.
+    start_key: Vec<u8>,
+    end_key: Vec<u8>,
+}
+
+impl Writer {
+    fn new(cf: &'static str, path: PathBuf) -> Result<Writer> {
+        let mut writer = SstFileWriter::new(EnvOptions::new(), ColumnFamilyOptions::new());
+        box_try!(writer.open(path.to_str().unwrap()));
+        Ok(Writer {
+            cf: cf,
+            path: path,
+            writer: writer,
+            total_kvs: 0,
+            total_bytes: 0,
+            start_key: vec![],
+            end_key: vec![],
+        })
+    }
+
+    // `key` is an encoded key with ts.
+    fn write(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        box_try!(self.writer.add(&keys::data_key(key), value));
+        if self.total_kvs == 0 {
+            self.start_key = key.to_vec();
+        }
+        self.end_key = key.to_vec();
+        self.total_kvs += 1;
+        self.total_bytes += (key.len() + value.len()) as u64;
+        Ok(())
+    }
+
+    // Saves the file to the storage, returns `None` if nothing is written.
+    fn save(
+        mut self,
+        name: String,
+        storage: &ExternalStorage,
+        limiter: &mut IOLimiter,
+    ) -> Result<Option<File>> {
+        if self.total_kvs == 0 {
+            return Ok(None);
+        }
+        box_try!(self.writer.finish());
+        let mut digest = Digest::new(crc32::IEEE);
+        {
+            let f = try!(StdFile::open(&self.path));
+            let mut reader = ChecksumReader {
+                digest: &mut digest,
+                inner: LimitReader::new(limiter, f),
+            };
+            try!(storage.write(&name, &mut reader));
+        }
+        try!(fs::remove_file(&self.path));
+
+        let mut file = File::new();
+        file.set_name(name);
+        file.set_cf(self.cf.to_owned());
+        file.set_crc32(digest.sum32());
+        file.set_start_key(try!(raw_key(&self.start_key)));
+        file.set_end_key(try!(raw_key(&self.end_key)));
+        file.set_total_kvs(self.total_kvs);
+        file.set_total_bytes(self.total_bytes);
+        Ok(Some(file))
+    }
+}
+
+fn raw_key(key: &[u8]) -> Result<Vec<u8>> {
+    let (key, _) = try!(split_encoded_key_on_ts(key));
+    let key = try!(Key::from_encoded(key.to_vec()).raw());
+    Ok(key)
+}
+
+struct ChecksumReader<'a, R> {
+    digest: &'a mut Digest,
+    inner: R,
+}
+
+impl<'a, R: Read> Read for ChecksumReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = try!(self.inner.read(buf));
+        self.digest.write(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Writes the MVCC data of a region to a SST file per cf. The keys in the files are data keys,
+/// so the files can be ingested directly.
+pub struct BackupWriter {
+    name: String,
+    default: Writer,
+    write: Writer,
+}
+
+impl BackupWriter {
+    /// Creates the writer with the temporary files in `dir`, `name` is the prefix of the files.
+    pub fn new(dir: &Path, name: String) -> Result<BackupWriter> {
+        let default = try!(Writer::new(
+            CF_DEFAULT,
+            dir.join(format!("{}_{}.sst", name, CF_DEFAULT))
+        ));
+        let write = try!(Writer::new(
+            CF_WRITE,
+            dir.join(format!("{}_{}.sst", name, CF_WRITE))
+        ));
+        Ok(BackupWriter {
+            name: name,
+            default: default,
+            write: write,
+        })
+    }
+
+    /// Writes a key with ts to the file of `cf`, the keys of a cf must be written in
+    /// ascending order.
+    pub fn put(&mut self, cf: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        match cf {
+            CF_DEFAULT => self.default.write(key, value),
+            CF_WRITE => self.write.write(key, value),
+            _ => Err(box_err!("unexpected cf {}", cf)),
+        }
+    }
+
+    /// Saves the files to the storage, empty files are skipped.
+    pub fn save(self, storage: &ExternalStorage, limiter: &mut IOLimiter) -> Result<Vec<File>> {
+        let mut files = Vec::with_capacity(2);
+        for w in vec![self.default, self.write] {
+            let name = format!("{}_{}.sst", self.name, w.cf);
+            if let Some(file) = try!(w.save(name, storage, limiter)) {
+                files.push(file);
+            }
+        }
+        Ok(files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use rand::{self, Rng};
+    use tempdir::TempDir;
+
+    use backup::LocalStorage;
+    use storage::{make_key, CF_LOCK};
+    use super::*;
+
+    fn read_checksum(storage: &ExternalStorage, file: &File) -> u32 {
+        let mut buf = vec![];
+        storage
+            .read(file.get_name())
+            .unwrap()
+            .read_to_end(&mut buf)
+            .unwrap();
+        let mut digest = Digest::new(crc32::IEEE);
+        digest.write(&buf);
+        digest.sum32()
+    }
+
+    #[test]
+    fn test_backup_writer() {
+        let tmp_dir = TempDir::new("_backup_test_writer_tmp").unwrap();
+        let dir = TempDir::new("_backup_test_writer").unwrap();
+        let storage = LocalStorage::new(dir.path()).unwrap();
+
+        // Nothing is saved if no key is written.
+        let writer = BackupWriter::new(tmp_dir.path(), "empty".to_owned()).unwrap();
+        let files = writer.save(&storage, &mut IOLimiter::new(0)).unwrap();
+        assert!(files.is_empty());
+
+        let mut writer = BackupWriter::new(tmp_dir.path(), "1_2_3".to_owned()).unwrap();
+        assert!(writer.put(CF_LOCK, b"k", b"v").is_err());
+        let k1 = make_key(b"k1").append_ts(10);
+        let k2 = make_key(b"k2").append_ts(10);
+        let k2_write = make_key(b"k2").append_ts(11);
+        writer.put(CF_DEFAULT, k1.encoded(), b"v1").unwrap();
+        writer.put(CF_DEFAULT, k2.encoded(), b"v2").unwrap();
+        writer.put(CF_WRITE, k2_write.encoded(), b"w2").unwrap();
+        let files = writer.save(&storage, &mut IOLimiter::new(0)).unwrap();
+        assert_eq!(files.len(), 2);
+
+        assert_eq!(files[0].get_name(), "1_2_3_default.sst");
+        assert_eq!(files[0].get_cf(), CF_DEFAULT);
+        assert_eq!(files[0].get_start_key(), b"k1");
+        assert_eq!(files[0].get_end_key(), b"k2");
+        assert_eq!(files[0].get_total_kvs(), 2);
+        let total_bytes = k1.encoded().len() + k2.encoded().len() + 4;
+        assert_eq!(files[0].get_total_bytes(), total_bytes as u64);
+
+        assert_eq!(files[1].get_name(), "1_2_3_write.sst");
+        assert_eq!(files[1].get_cf(), CF_WRITE);
+        assert_eq!(files[1].get_start_key(), b"k2");
+        assert_eq!(files[1].get_end_key(), b"k2");
+        assert_eq!(files[1].get_total_kvs(), 1);
+        let total_bytes = k2_write.encoded().len() + 2;
+        assert_eq!(files[1].get_total_bytes(), total_bytes as u64);
+
+        // The checksums are calculated from the saved files, and the temporary files are
+        // removed.
+        for file in &files {
+            assert_eq!(read_checksum(&storage, file), file.get_crc32());
+        }
+        assert_eq!(fs::read_dir(tmp_dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_backup_writer_rate_limit() {
+        let rate_limit = 256 * 1024;
+        let tmp_dir = TempDir::new("_backup_test_writer_rate_limit_tmp").unwrap();
+        let dir = TempDir::new("_backup_test_writer_rate_limit").unwrap();
+        let storage = LocalStorage::new(dir.path()).unwrap();
+
+        // Random values can't be compressed.
+        let mut rng = rand::thread_rng();
+        let mut writer = BackupWriter::new(tmp_dir.path(), "1_2_3".to_owned()).unwrap();
+        for i in 0..100 {
+            let key = make_key(format!("k{:03}", i).as_bytes()).append_ts(10);
+            let value: Vec<u8> = rng.gen_iter().take(4096).collect();
+            writer.put(CF_DEFAULT, key.encoded(), &value).unwrap();
+        }
+        let start = Instant::now();
+        let mut limiter = IOLimiter::new(rate_limit);
+        let files = writer.save(&storage, &mut limiter).unwrap();
+        let elapsed = start.elapsed();
+        assert_eq!(files.len(), 1);
+
+        let size = dir.path()
+            .join(files[0].get_name())
+            .metadata()
+            .unwrap()
+            .len();
+        assert!(size > rate_limit, "{}", size);
+        assert!(elapsed >= Duration::from_millis(size * 1000 / rate_limit));
+        assert_eq!(read_checksum(&storage, &files[0]), files[0].get_crc32());
+    }
+}
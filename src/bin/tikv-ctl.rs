@@ -35,6 +35,7 @@ use kvproto::eraftpb::Entry;
 use rocksdb::{ReadOptions, SeekKey, DB};
 use tikv::util::{self, escape, unescape};
 use tikv::util::codec::bytes::encode_bytes;
+use tikv::raft_engine::{copy_raft_logs, Config as RaftLogConfig, RaftLogEngine};
 use tikv::raftstore::store::keys;
 use tikv::raftstore::store::engine::{IterOption, Iterable, Peekable};
 use tikv::storage::{CfName, ALL_CFS, CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE};
@@ -232,6 +233,25 @@ fn main() {
                         .takes_value(true)
                         .help("specify region id"),
                 ),
+        )
+        .subcommand(
+            SubCommand::with_name("convert")
+                .about("convert the raft logs between raftdb and the raft log engine")
+                .arg(
+                    Arg::with_name("log-engine")
+                        .short("l")
+                        .takes_value(true)
+                        .required(true)
+                        .help("set the path of the raft log engine"),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .short("t")
+                        .takes_value(true)
+                        .possible_values(&["rocksdb", "log-engine"])
+                        .default_value("log-engine")
+                        .help("set the engine the raft logs are converted to"),
+                ),
         );
     let matches = app.clone().get_matches();
 
@@ -339,6 +359,16 @@ fn main() {
         let db_path2 = matches.value_of("to").unwrap();
         let db2 = util::rocksdb::open(db_path2, ALL_CFS).unwrap();
         dump_diff(&db, &db2, region_id);
+    } else if let Some(matches) = matches.subcommand_matches("convert") {
+        let mut cfg = RaftLogConfig::default();
+        cfg.dir = matches.value_of("log-engine").unwrap().to_owned();
+        let log_engine = RaftLogEngine::open(&cfg).unwrap();
+        let count = if matches.value_of("to").unwrap() == "rocksdb" {
+            copy_raft_logs(&log_engine, &raft_db).unwrap()
+        } else {
+            copy_raft_logs(&raft_db, &log_engine).unwrap()
+        };
+        println!("{} regions are converted", count);
     } else {
         let _ = app.print_help();
     }
@@ -47,7 +47,7 @@ use std::process;
 use std::fs::File;
 use std::usize;
 use std::path::Path;
-use std::sync::{mpsc, Arc};
+use std::sync::{mpsc, Arc, RwLock};
 use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
 use std::io::Read;
 use std::env;
@@ -58,17 +58,27 @@ use fs2::FileExt;
 
 use tikv::config::{MetricConfig, TiKvConfig};
 use tikv::util::{self, panic_hook, rocksdb as rocksdb_util};
-use tikv::util::collections::HashMap;
+use tikv::util::collections::{HashMap, HashSet};
 use tikv::util::logger::{self, StderrLogger};
 use tikv::util::file_log::RotatingFileLogger;
 use tikv::util::transport::SendCh;
+use tikv::util::worker::{FutureWorker, Worker};
 use tikv::storage::DEFAULT_ROCKSDB_SUB_DIR;
-use tikv::server::{create_raft_storage, Node, Server, DEFAULT_CLUSTER_ID};
+use tikv::server::{create_raft_storage, GcManager, GcObserver, Node, ResolvedTsAdvancer,
+                   Server, DEFAULT_CLUSTER_ID};
 use tikv::server::transport::ServerRaftStoreRouter;
 use tikv::server::resolve;
+use tikv::server::deadlock::{self, DeadlockObserver, Detector};
+use tikv::server::max_ts::{MaxTsObserver, MaxTsSyncer};
 use tikv::raftstore::store::{self, Engines, SnapManager};
+use tikv::raft_engine::{RaftEngine, RaftLogEngine};
+use tikv::raftstore::coprocessor::CoprocessorHost;
 use tikv::pd::{PdClient, RpcClient};
+use tikv::cdc::{self, CdcObserver};
+use tikv::backup;
+use tikv::import::{ImportModeSwitcher, ImportSSTService, SSTImporter};
 use tikv::util::time::Monitor;
+use tikv::util::rocksdb::gc_filter::GcContext;
 use tikv::util::rocksdb::metrics_flusher::{MetricsFlusher, DEFAULT_FLUSER_INTERVAL};
 
 const RESERVED_OPEN_FDS: u64 = 1000;
@@ -147,6 +157,8 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig) {
     let lock_path = store_path.join(Path::new("LOCK"));
     let db_path = store_path.join(Path::new(DEFAULT_ROCKSDB_SUB_DIR));
     let snap_path = store_path.join(Path::new("snap"));
+    let backup_path = store_path.join(Path::new("backup"));
+    let import_path = store_path.join(Path::new("import"));
     let raft_db_path = Path::new(&cfg.raft_store.raftdb_path);
 
     let f = File::create(lock_path.as_path()).unwrap_or_else(|e| {
@@ -168,7 +180,13 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig) {
 
     // Create kv engine, storage.
     let kv_db_opts = cfg.rocksdb.build_opt();
-    let kv_cfs_opts = cfg.rocksdb.build_cf_opts();
+    let gc_context = if cfg.storage.gc_enable_compaction_filter {
+        Some(GcContext::new())
+    } else {
+        None
+    };
+    let kv_cfs_opts = cfg.rocksdb
+        .build_cf_opts(cfg.storage.enable_ttl, gc_context.as_ref());
     let kv_engine = Arc::new(
         rocksdb_util::new_engine_opt(db_path.to_str().unwrap(), kv_db_opts, kv_cfs_opts)
             .unwrap_or_else(|s| fatal!("failed to create kv engine: {:?}", s)),
@@ -186,7 +204,22 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig) {
             raft_db_cf_opts,
         ).unwrap_or_else(|s| fatal!("failed to create raft engine: {:?}", s)),
     );
-    let engines = Engines::new(kv_engine.clone(), raft_engine.clone());
+    let engines = if cfg.raft_engine.enable {
+        let log_engine = RaftLogEngine::open(&cfg.raft_engine)
+            .unwrap_or_else(|e| fatal!("failed to create raft log engine: {:?}", e));
+        let log_groups = log_engine
+            .raft_groups()
+            .unwrap_or_else(|e| fatal!("failed to load raft log engine: {:?}", e));
+        let db_groups = raft_engine
+            .raft_groups()
+            .unwrap_or_else(|e| fatal!("failed to load raft engine: {:?}", e));
+        if log_groups.is_empty() && !db_groups.is_empty() {
+            fatal!("the raft logs are still in raftdb, convert them by tikv-ctl first");
+        }
+        Engines::with_raft_log(kv_engine.clone(), raft_engine.clone(), Arc::new(log_engine))
+    } else {
+        Engines::new(kv_engine.clone(), raft_engine.clone())
+    };
 
     // Create pd client, snapshot manager, server.
     let pd_client = Arc::new(pd_client);
@@ -196,26 +229,85 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig) {
         snap_path.as_path().to_str().unwrap().to_owned(),
         Some(store_sendch),
     );
+    let mut cdc_worker = FutureWorker::new("cdc");
+    let cdc_observed_regions = Arc::new(RwLock::new(HashSet::default()));
+    let mut backup_worker = Worker::new("backup");
+    let mut detector_worker = FutureWorker::new("deadlock-detector");
+    let detector_leader = Arc::new(AtomicBool::new(false));
+    let mut max_ts_worker = FutureWorker::new("max-ts-syncer");
+    let gc_leader_regions = Arc::new(RwLock::new(HashSet::default()));
+    storage.set_deadlock_detector(detector_worker.scheduler());
+    if let Some(interval) = cfg.raft_store.load_report_interval() {
+        storage.set_load_report_interval(interval);
+    }
+    let importer = Arc::new(
+        SSTImporter::new(&import_path)
+            .unwrap_or_else(|e| fatal!("failed to create sst importer: {:?}", e)),
+    );
+    let import_switcher = Arc::new(ImportModeSwitcher::new(
+        kv_engine.clone(),
+        cfg.server.import_mode_timeout.0,
+    ));
+    let import_service = ImportSSTService::new(
+        raft_router.clone(),
+        kv_engine.clone(),
+        importer.clone(),
+        import_switcher.clone(),
+    );
     let mut server = Server::new(
         &cfg.server,
         cfg.raft_store.region_split_size.0 as usize,
         storage.clone(),
-        raft_router,
+        raft_router.clone(),
         significant_msg_sender,
         resolver,
         snap_mgr.clone(),
         Some(engines.clone()),
+        Some(cdc_worker.scheduler()),
+        Some(backup_worker.scheduler()),
+        Some(import_service),
+        Some(deadlock::Service::new(
+            detector_worker.scheduler(),
+            detector_leader.clone(),
+        )),
     ).unwrap_or_else(|e| fatal!("failed to create server: {:?}", e));
     let trans = server.transport();
 
     // Create node.
-    let mut node = Node::new(&mut event_loop, &cfg.server, &cfg.raft_store, pd_client);
+    let mut node = Node::new(
+        &mut event_loop,
+        &cfg.server,
+        &cfg.raft_store,
+        pd_client.clone(),
+    );
+    let mut coprocessor_host = CoprocessorHost::new();
+    coprocessor_host.registry.register_observer(
+        200,
+        Box::new(CdcObserver::new(cdc_worker.scheduler(), cdc_observed_regions.clone())),
+    );
+    coprocessor_host.registry.register_observer(
+        300,
+        Box::new(DeadlockObserver::new(detector_worker.scheduler())),
+    );
+    coprocessor_host.registry.register_observer(
+        400,
+        Box::new(MaxTsObserver::new(
+            storage.get_concurrency_manager(),
+            max_ts_worker.scheduler(),
+        )),
+    );
+    coprocessor_host.registry.register_observer(
+        500,
+        Box::new(GcObserver::new(gc_leader_regions.clone())),
+    );
     node.start(
         event_loop,
         engines.clone(),
         trans,
         snap_mgr,
         significant_msg_receiver,
+        coprocessor_host,
+        importer,
     ).unwrap_or_else(|e| fatal!("failed to start node: {:?}", e));
     initial_metric(&cfg.metric, Some(node.id()));
 
@@ -225,6 +317,89 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig) {
         fatal!("failed to start storage, error: {:?}", e);
     }
 
+    // Start syncing the max ts of the new leaders.
+    let max_ts_syncer = MaxTsSyncer::new(
+        pd_client.clone(),
+        storage.get_concurrency_manager(),
+        max_ts_worker.scheduler(),
+    );
+    if let Err(e) = max_ts_worker.start(max_ts_syncer) {
+        fatal!("failed to start max ts syncer, error: {:?}", e);
+    }
+
+    // Start the deadlock detector, it serves the cluster once this store leads the first region.
+    let detector = Detector::new(
+        pd_client.clone(),
+        storage.get_sched_ch(),
+        detector_worker.scheduler(),
+        detector_leader,
+    );
+    if let Err(e) = detector_worker.start(detector) {
+        fatal!("failed to start deadlock detector, error: {:?}", e);
+    }
+
+    // Start change data capture.
+    let cdc_endpoint = cdc::Endpoint::new(
+        node.id(),
+        pd_client.clone(),
+        storage.get_engine(),
+        storage.get_concurrency_manager(),
+        cdc_worker.scheduler(),
+        cdc_observed_regions,
+    );
+    if let Err(e) = cdc_worker.start(cdc_endpoint) {
+        fatal!("failed to start cdc endpoint, error: {:?}", e);
+    }
+    if let Err(e) = cdc_worker.schedule(cdc::Task::RegisterMinTsEvent) {
+        fatal!("failed to schedule cdc min ts event, error: {:?}", e);
+    }
+
+    // Start backup, the temporary files are written to the store directory.
+    let backup_endpoint = backup::Endpoint::new(
+        node.id(),
+        pd_client.clone(),
+        storage.get_engine(),
+        &backup_path,
+    );
+    if let Err(e) = backup_worker.start(backup_endpoint) {
+        fatal!("failed to start backup endpoint, error: {:?}", e);
+    }
+
+    // Start advancing the resolved ts for stale reads.
+    let mut resolved_ts_advancer = None;
+    if cfg.raft_store.advance_resolved_ts_interval.as_millis() > 0 {
+        let mut advancer = ResolvedTsAdvancer::new(
+            cfg.raft_store.advance_resolved_ts_interval.0,
+            pd_client.clone(),
+            storage.get_concurrency_manager(),
+            raft_router,
+        );
+        if let Err(e) = advancer.start() {
+            fatal!("failed to start resolved ts advancer, error: {:?}", e);
+        }
+        resolved_ts_advancer = Some(advancer);
+    }
+
+    // Start GC manager.
+    let mut gc_manager = GcManager::new(
+        &cfg.storage,
+        node.id(),
+        storage.clone(),
+        kv_engine.clone(),
+        pd_client,
+        gc_leader_regions,
+    );
+    if let Some(ref ctx) = gc_context {
+        gc_manager.set_gc_context(ctx.clone());
+    }
+    if let Err(e) = gc_manager.start() {
+        fatal!("failed to start gc manager, error: {:?}", e);
+    }
+    let gc_filter_worker = gc_context.map(|ctx| {
+        ctx.start(kv_engine.clone())
+            .unwrap_or_else(|e| fatal!("failed to start gc filter worker: {:?}", e))
+    });
+
     let mut metrics_flusher = MetricsFlusher::new(
         engines.clone(),
         Duration::from_millis(DEFAULT_FLUSER_INTERVAL),
@@ -248,6 +423,32 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig) {
 
     metrics_flusher.stop();
 
+    // Restore the options of normal mode in case the import client never switches back.
+    if let Err(e) = import_switcher.enter_normal_mode() {
+        error!("failed to leave import mode: {:?}", e);
+    }
+
+    if let Some(Err(e)) = cdc_worker.stop().map(|j| j.join()) {
+        info!("ignore failure when stopping cdc endpoint: {:?}", e);
+    }
+    if let Some(Err(e)) = backup_worker.stop().map(|j| j.join()) {
+        info!("ignore failure when stopping backup endpoint: {:?}", e);
+    }
+    if let Some(Err(e)) = detector_worker.stop().map(|j| j.join()) {
+        info!("ignore failure when stopping deadlock detector: {:?}", e);
+    }
+    if let Some(Err(e)) = max_ts_worker.stop().map(|j| j.join()) {
+        info!("ignore failure when stopping max ts syncer: {:?}", e);
+    }
+
+    if let Some(mut advancer) = resolved_ts_advancer {
+        advancer.stop();
+    }
+    gc_manager.stop();
+    if let Some(Some(Err(e))) = gc_filter_worker.map(|mut w| w.stop().map(|j| j.join())) {
+        info!("ignore failure when stopping gc filter worker: {:?}", e);
+    }
+
     node.stop()
         .unwrap_or_else(|e| fatal!("failed to stop node: {:?}", e));
     if let Some(Err(e)) = worker.stop().map(|j| j.join()) {
@@ -0,0 +1,545 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::mem;
+
+use futures::sync::mpsc::UnboundedSender;
+use kvproto::cdcpb::{ChangeDataEvent, Event, Event_Entries, Event_Error, Event_LogType,
+                     Event_Row, Event_Row_OpType};
+use kvproto::raft_cmdpb::{CmdType, Request};
+use protobuf::RepeatedField;
+
+use raftstore::store::Resolver;
+use raftstore::store::engine::IterOption;
+use storage::{split_encoded_key_on_ts, Key, CF_DEFAULT, CF_LOCK, CF_WRITE};
+use storage::engine::{CFStatistics, ScanMode, Snapshot};
+use storage::mvcc::{Lock, LockType, Write, WriteType};
+use util::collections::{HashMap, HashSet};
+use super::Result;
+
+// The max number of rows in an event sent by the incremental scan.
+const SCAN_BATCH_SIZE: usize = 128;
+
+/// Streams the changes of a region to a downstream.
+///
+/// Changes are buffered until the incremental scan finishes. The scan sends the rows committed
+/// after `checkpoint_ts` and loads the locks, then the buffered changes are replayed with the
+/// rows already scanned skipped.
+pub struct Delegate {
+    pub id: u64,
+    pub region_id: u64,
+    request_id: u64,
+    checkpoint_ts: u64,
+    sink: UnboundedSender<ChangeDataEvent>,
+    // `None` means the incremental scan is finished.
+    pending: Option<Vec<(u64, Vec<Request>)>>,
+    // Encoded key -> the row of the prewrite which is not committed yet.
+    prewrites: HashMap<Vec<u8>, Event_Row>,
+    resolver: Resolver,
+}
+
+impl Delegate {
+    pub fn new(
+        id: u64,
+        region_id: u64,
+        request_id: u64,
+        checkpoint_ts: u64,
+        sink: UnboundedSender<ChangeDataEvent>,
+    ) -> Delegate {
+        Delegate {
+            id: id,
+            region_id: region_id,
+            request_id: request_id,
+            checkpoint_ts: checkpoint_ts,
+            sink: sink,
+            pending: Some(vec![]),
+            prewrites: HashMap::default(),
+            resolver: Resolver::new(),
+        }
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.pending.is_none()
+    }
+
+    fn new_event(&self, index: u64) -> Event {
+        let mut event = Event::new();
+        event.set_region_id(self.region_id);
+        event.set_request_id(self.request_id);
+        event.set_index(index);
+        event
+    }
+
+    // Returns false if the downstream is gone.
+    fn send(&self, events: Vec<Event>) -> bool {
+        let mut change = ChangeDataEvent::new();
+        change.set_events(RepeatedField::from_vec(events));
+        self.sink.unbounded_send(change).is_ok()
+    }
+
+    fn send_rows(&self, index: u64, rows: Vec<Event_Row>) -> bool {
+        if rows.is_empty() {
+            return true;
+        }
+        let mut entries = Event_Entries::new();
+        entries.set_entries(RepeatedField::from_vec(rows));
+        let mut event = self.new_event(index);
+        event.set_entries(entries);
+        self.send(vec![event])
+    }
+
+    /// Sends the error to the downstream, the delegate should be removed after that.
+    pub fn fail(&self, err: Event_Error) {
+        let mut event = self.new_event(0);
+        event.set_error(err);
+        self.send(vec![event]);
+    }
+
+    /// Handles the requests applied at log `index`, returns false if the downstream is gone.
+    pub fn on_change_log(&mut self, index: u64, requests: Vec<Request>) -> bool {
+        if let Some(ref mut pending) = self.pending {
+            pending.push((index, requests));
+            return true;
+        }
+        let rows = self.sink_requests(requests, &HashSet::default());
+        self.send_rows(index, rows)
+    }
+
+    /// Advances the resolved ts and sends it, returns false if the downstream is gone.
+    pub fn on_min_ts(&mut self, min_ts: u64) -> bool {
+        if !self.is_initialized() {
+            return true;
+        }
+        let resolved_ts = self.resolver.resolve(min_ts);
+        let mut event = self.new_event(0);
+        event.set_resolved_ts(resolved_ts);
+        self.send(vec![event])
+    }
+
+    /// Runs the incremental scan on `snap` and replays the buffered changes, returns false if
+    /// the downstream is gone.
+    pub fn initialize(&mut self, snap: &Snapshot) -> Result<bool> {
+        let mut statistics = CFStatistics::default();
+        let mut cursor = try!(snap.iter_cf(CF_LOCK, IterOption::default(), ScanMode::Forward));
+        cursor.seek_to_first(&mut statistics);
+        while cursor.valid() {
+            let lock = try!(Lock::parse(cursor.value()));
+            let key = cursor.key().to_vec();
+            let value = if lock.lock_type == LockType::Put && lock.short_value.is_none() {
+                let k = Key::from_encoded(key.clone()).append_ts(lock.ts);
+                try!(snap.get_cf(CF_DEFAULT, &k))
+            } else {
+                None
+            };
+            self.on_lock(key, lock, value);
+            cursor.next(&mut statistics);
+        }
+
+        // (encoded key, start ts) of the rows committed after `checkpoint_ts`.
+        let mut scanned = HashSet::default();
+        let mut rows = Vec::with_capacity(SCAN_BATCH_SIZE);
+        let mut cursor = try!(snap.iter_cf(CF_WRITE, IterOption::default(), ScanMode::Forward));
+        cursor.seek_to_first(&mut statistics);
+        while cursor.valid() {
+            let (key, commit_ts) = {
+                let (key, commit_ts) = try!(split_encoded_key_on_ts(cursor.key()));
+                (key.to_vec(), commit_ts)
+            };
+            let write = try!(Write::parse(cursor.value()));
+            if commit_ts > self.checkpoint_ts {
+                let value = if write.write_type == WriteType::Put && write.short_value.is_none() {
+                    let k = Key::from_encoded(key.clone()).append_ts(write.start_ts);
+                    try!(snap.get_cf(CF_DEFAULT, &k))
+                } else {
+                    None
+                };
+                scanned.insert((key.clone(), write.start_ts));
+                if let Some(row) = new_committed_row(key, commit_ts, write, value) {
+                    rows.push(row);
+                }
+                if rows.len() >= SCAN_BATCH_SIZE {
+                    let rows = mem::replace(&mut rows, Vec::with_capacity(SCAN_BATCH_SIZE));
+                    if !self.send_rows(0, rows) {
+                        return Ok(false);
+                    }
+                }
+            }
+            cursor.next(&mut statistics);
+        }
+        let mut initialized = Event_Row::new();
+        initialized.set_field_type(Event_LogType::Initialized);
+        rows.push(initialized);
+        if !self.send_rows(0, rows) {
+            return Ok(false);
+        }
+
+        for (index, requests) in self.pending.take().unwrap() {
+            let rows = self.sink_requests(requests, &scanned);
+            if !self.send_rows(index, rows) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    // Assembles the committed rows from the requests, the rows in `scanned` are skipped.
+    fn sink_requests(
+        &mut self,
+        requests: Vec<Request>,
+        scanned: &HashSet<(Vec<u8>, u64)>,
+    ) -> Vec<Event_Row> {
+        // Values of the default cf are written with the locks or the writes of 1PC.
+        let mut values = HashMap::default();
+        let mut puts = vec![];
+        for mut req in requests {
+            match req.get_cmd_type() {
+                CmdType::Put => {
+                    let mut put = req.take_put();
+                    if put.get_cf().is_empty() || put.get_cf() == CF_DEFAULT {
+                        values.insert(put.take_key(), put.take_value());
+                    } else {
+                        puts.push(put);
+                    }
+                }
+                CmdType::Delete => if req.get_delete().get_cf() == CF_LOCK {
+                    self.resolver.untrack_lock(req.get_delete().get_key());
+                },
+                _ => {}
+            }
+        }
+
+        let mut rows = vec![];
+        for mut put in puts {
+            let key = put.take_key();
+            if put.get_cf() == CF_LOCK {
+                let lock = match Lock::parse(put.get_value()) {
+                    Ok(lock) => lock,
+                    Err(e) => {
+                        error!("failed to parse lock of key {:?}: {:?}", key, e);
+                        continue;
+                    }
+                };
+                if scanned.contains(&(key.clone(), lock.ts)) {
+                    continue;
+                }
+                let value = values.remove(Key::from_encoded(key.clone())
+                    .append_ts(lock.ts)
+                    .encoded());
+                self.on_lock(key, lock, value);
+            } else if put.get_cf() == CF_WRITE {
+                let (key, commit_ts) = match split_encoded_key_on_ts(&key) {
+                    Ok((key, commit_ts)) => (key.to_vec(), commit_ts),
+                    Err(e) => {
+                        error!("failed to decode write key {:?}: {:?}", key, e);
+                        continue;
+                    }
+                };
+                let write = match Write::parse(put.get_value()) {
+                    Ok(write) => write,
+                    Err(e) => {
+                        error!("failed to parse write of key {:?}: {:?}", key, e);
+                        continue;
+                    }
+                };
+                if scanned.contains(&(key.clone(), write.start_ts)) {
+                    continue;
+                }
+                let value = values.remove(Key::from_encoded(key.clone())
+                    .append_ts(write.start_ts)
+                    .encoded());
+                if let Some(row) = self.on_write(key, commit_ts, write, value) {
+                    rows.push(row);
+                }
+            }
+        }
+        rows
+    }
+
+    fn on_lock(&mut self, key: Vec<u8>, lock: Lock, value: Option<Vec<u8>>) {
+        let op_type = match lock.lock_type {
+            LockType::Put => Event_Row_OpType::Put,
+            LockType::Delete => Event_Row_OpType::Delete,
+            LockType::Lock => {
+                self.resolver.track_lock(lock.ts, key);
+                return;
+            }
+            // Pessimistic locks are prewritten before commit.
+            LockType::Pessimistic => return,
+        };
+        self.resolver.track_lock(lock.ts, key.clone());
+        let mut row = Event_Row::new();
+        row.set_start_ts(lock.ts);
+        row.set_field_type(Event_LogType::Prewrite);
+        row.set_op_type(op_type);
+        if let Some(value) = lock.short_value.or(value) {
+            row.set_value(value);
+        }
+        self.prewrites.insert(key, row);
+    }
+
+    fn on_write(
+        &mut self,
+        key: Vec<u8>,
+        commit_ts: u64,
+        write: Write,
+        value: Option<Vec<u8>>,
+    ) -> Option<Event_Row> {
+        let prewrite = match self.prewrites.remove(&key) {
+            Some(row) => if row.get_start_ts() == write.start_ts {
+                Some(row)
+            } else {
+                // The write belongs to another transaction.
+                self.prewrites.insert(key.clone(), row);
+                None
+            },
+            None => None,
+        };
+        match (prewrite, write.write_type) {
+            (Some(mut row), WriteType::Put) | (Some(mut row), WriteType::Delete) => {
+                let raw_key = match decode_key(&key) {
+                    Some(k) => k,
+                    None => return None,
+                };
+                row.set_key(raw_key);
+                row.set_commit_ts(commit_ts);
+                row.set_field_type(Event_LogType::Committed);
+                Some(row)
+            }
+            // Written by 1PC without prewrites.
+            (None, WriteType::Put) | (None, WriteType::Delete) => {
+                new_committed_row(key, commit_ts, write, value)
+            }
+            // Rollbacks and locks don't change any row.
+            _ => None,
+        }
+    }
+}
+
+fn decode_key(key: &[u8]) -> Option<Vec<u8>> {
+    match Key::from_encoded(key.to_vec()).raw() {
+        Ok(k) => Some(k),
+        Err(e) => {
+            error!("failed to decode key {:?}: {:?}", key, e);
+            None
+        }
+    }
+}
+
+fn new_committed_row(
+    key: Vec<u8>,
+    commit_ts: u64,
+    write: Write,
+    value: Option<Vec<u8>>,
+) -> Option<Event_Row> {
+    let op_type = match write.write_type {
+        WriteType::Put => Event_Row_OpType::Put,
+        WriteType::Delete => Event_Row_OpType::Delete,
+        WriteType::Rollback | WriteType::Lock => return None,
+    };
+    let mut row = Event_Row::new();
+    row.set_key(try_opt!(decode_key(&key)));
+    row.set_start_ts(write.start_ts);
+    row.set_commit_ts(commit_ts);
+    row.set_field_type(Event_LogType::Committed);
+    row.set_op_type(op_type);
+    if let Some(value) = write.short_value.or(value) {
+        row.set_value(value);
+    }
+    Some(row)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{Future, Stream};
+    use futures::sync::mpsc::{self, UnboundedReceiver};
+    use kvproto::kvrpcpb::Context;
+    use kvproto::raft_cmdpb::{DeleteRequest, PutRequest};
+
+    use storage::{make_key, new_local_engine, ALL_CFS};
+    use storage::engine::{Modify, TEMP_DIR};
+    use super::*;
+
+    fn new_put(cf: &str, key: Vec<u8>, value: Vec<u8>) -> Request {
+        let mut put = PutRequest::new();
+        put.set_cf(cf.to_owned());
+        put.set_key(key);
+        put.set_value(value);
+        let mut req = Request::new();
+        req.set_cmd_type(CmdType::Put);
+        req.set_put(put);
+        req
+    }
+
+    fn new_delete(cf: &str, key: Vec<u8>) -> Request {
+        let mut delete = DeleteRequest::new();
+        delete.set_cf(cf.to_owned());
+        delete.set_key(key);
+        let mut req = Request::new();
+        req.set_cmd_type(CmdType::Delete);
+        req.set_delete(delete);
+        req
+    }
+
+    fn prewrite(k: &[u8], v: &[u8], start_ts: u64) -> Vec<Request> {
+        let lock = Lock::new(LockType::Put, k.to_vec(), start_ts, 0, None);
+        vec![
+            new_put(
+                CF_DEFAULT,
+                make_key(k).append_ts(start_ts).encoded().clone(),
+                v.to_vec(),
+            ),
+            new_put(CF_LOCK, make_key(k).encoded().clone(), lock.to_bytes()),
+        ]
+    }
+
+    fn commit(k: &[u8], write_type: WriteType, start_ts: u64, commit_ts: u64) -> Vec<Request> {
+        let write = Write::new(write_type, start_ts, None);
+        vec![
+            new_delete(CF_LOCK, make_key(k).encoded().clone()),
+            new_put(
+                CF_WRITE,
+                make_key(k).append_ts(commit_ts).encoded().clone(),
+                write.to_bytes(),
+            ),
+        ]
+    }
+
+    fn recv_event(
+        rx: UnboundedReceiver<ChangeDataEvent>,
+    ) -> (Event, UnboundedReceiver<ChangeDataEvent>) {
+        let (change, rx) = match rx.into_future().wait() {
+            Ok((Some(change), rx)) => (change, rx),
+            _ => panic!("downstream is closed"),
+        };
+        let mut events = change.get_events().to_vec();
+        assert_eq!(events.len(), 1);
+        (events.pop().unwrap(), rx)
+    }
+
+    fn must_recv_row(
+        rx: UnboundedReceiver<ChangeDataEvent>,
+        index: u64,
+        k: &[u8],
+        v: &[u8],
+        start_ts: u64,
+        commit_ts: u64,
+    ) -> UnboundedReceiver<ChangeDataEvent> {
+        let (event, rx) = recv_event(rx);
+        assert_eq!(event.get_index(), index);
+        let rows = event.get_entries().get_entries();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].get_field_type(), Event_LogType::Committed);
+        assert_eq!(rows[0].get_key(), k);
+        assert_eq!(rows[0].get_value(), v);
+        assert_eq!(rows[0].get_start_ts(), start_ts);
+        assert_eq!(rows[0].get_commit_ts(), commit_ts);
+        rx
+    }
+
+    #[test]
+    fn test_assemble_rows() {
+        let (tx, rx) = mpsc::unbounded();
+        let mut delegate = Delegate::new(1, 1, 1, 0, tx);
+        // Skip the incremental scan.
+        delegate.pending = None;
+
+        // Prewrites are sent after they are committed.
+        assert!(delegate.on_change_log(1, prewrite(b"k1", b"v1", 10)));
+        assert!(delegate.on_change_log(2, prewrite(b"k2", b"v2", 15)));
+        assert!(delegate.on_change_log(3, commit(b"k1", WriteType::Put, 10, 20)));
+        let rx = must_recv_row(rx, 3, b"k1", b"v1", 10, 20);
+
+        // The lock of k2 is not resolved yet.
+        assert!(delegate.on_min_ts(30));
+        let (event, rx) = recv_event(rx);
+        assert_eq!(event.get_resolved_ts(), 15);
+
+        // Rolled back prewrites are dropped.
+        assert!(delegate.on_change_log(4, commit(b"k2", WriteType::Rollback, 15, 15)));
+        // Writes of 1PC carry the values with them.
+        let mut requests = commit(b"k3", WriteType::Put, 25, 26);
+        requests.push(new_put(
+            CF_DEFAULT,
+            make_key(b"k3").append_ts(25).encoded().clone(),
+            b"v3".to_vec(),
+        ));
+        assert!(delegate.on_change_log(5, requests));
+        let rx = must_recv_row(rx, 5, b"k3", b"v3", 25, 26);
+
+        assert!(delegate.on_min_ts(30));
+        let (event, rx) = recv_event(rx);
+        assert_eq!(event.get_resolved_ts(), 30);
+
+        // Sending fails after the downstream is gone.
+        drop(rx);
+        assert!(!delegate.on_change_log(6, commit(b"k4", WriteType::Put, 27, 28)));
+    }
+
+    #[test]
+    fn test_initialize() {
+        let engine = new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let ctx = Context::new();
+        // Written before the registration: k0 is committed before the checkpoint ts, k1 after
+        // it, and k2 is prewritten.
+        let k2_lock = Lock::new(LockType::Put, b"k2".to_vec(), 25, 0, None);
+        let modifies = vec![
+            Modify::Put(
+                CF_WRITE,
+                make_key(b"k0").append_ts(5),
+                Write::new(WriteType::Put, 3, Some(b"v0".to_vec())).to_bytes(),
+            ),
+            Modify::Put(
+                CF_WRITE,
+                make_key(b"k1").append_ts(20),
+                Write::new(WriteType::Put, 10, Some(b"v1".to_vec())).to_bytes(),
+            ),
+            Modify::Put(CF_DEFAULT, make_key(b"k2").append_ts(25), b"v2".to_vec()),
+            Modify::Put(CF_LOCK, make_key(b"k2"), k2_lock.to_bytes()),
+        ];
+        engine.write(&ctx, modifies).unwrap();
+        let snap = engine.snapshot(&ctx).unwrap();
+
+        let (tx, rx) = mpsc::unbounded();
+        let mut delegate = Delegate::new(1, 1, 1, 15, tx);
+        // Changes are buffered and no resolved ts is sent before the scan finishes. The commit
+        // of k1 is in the snapshot too, so it's skipped when replayed.
+        assert!(delegate.on_change_log(6, commit(b"k1", WriteType::Put, 10, 20)));
+        assert!(delegate.on_change_log(7, commit(b"k2", WriteType::Put, 25, 30)));
+        assert!(delegate.on_min_ts(18));
+        assert!(!delegate.is_initialized());
+
+        assert!(delegate.initialize(snap.as_ref()).unwrap());
+        assert!(delegate.is_initialized());
+        let (event, rx) = recv_event(rx);
+        assert_eq!(event.get_index(), 0);
+        let rows = event.get_entries().get_entries();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].get_field_type(), Event_LogType::Committed);
+        assert_eq!(rows[0].get_key(), b"k1");
+        assert_eq!(rows[0].get_value(), b"v1");
+        assert_eq!(rows[1].get_field_type(), Event_LogType::Initialized);
+        // The prewrite of k2 is loaded by the scan and committed by the replayed change.
+        let rx = must_recv_row(rx, 7, b"k2", b"v2", 25, 30);
+
+        // Written after the scan.
+        assert!(delegate.on_change_log(8, prewrite(b"k3", b"v3", 35)));
+        assert!(delegate.on_min_ts(40));
+        let (event, rx) = recv_event(rx);
+        assert_eq!(event.get_resolved_ts(), 35);
+        assert!(delegate.on_change_log(9, commit(b"k3", WriteType::Put, 35, 45)));
+        let rx = must_recv_row(rx, 9, b"k3", b"v3", 35, 45);
+        assert!(delegate.on_min_ts(50));
+        let (event, _) = recv_event(rx);
+        assert_eq!(event.get_resolved_ts(), 50);
+    }
+}
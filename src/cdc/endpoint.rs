@@ -0,0 +1,354 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp;
+use std::fmt::{self, Display, Formatter};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use futures::Future;
+use futures::sync::mpsc::UnboundedSender;
+use kvproto::cdcpb::{ChangeDataEvent, ChangeDataRequest};
+use kvproto::kvrpcpb::Context;
+use kvproto::metapb::{Region, RegionEpoch};
+use kvproto::raft_cmdpb::Request;
+use tokio_core::reactor::Handle;
+use tokio_timer::Timer;
+
+use pd::PdClient;
+use raftstore::Error as RaftStoreError;
+use raftstore::store::util::find_peer;
+use storage::engine::{Engine, Snapshot};
+use storage::txn::ConcurrencyManager;
+use util::collections::{HashMap, HashSet};
+use util::worker::{FutureRunnable, FutureScheduler};
+use super::{Error, Result};
+use super::delegate::Delegate;
+
+// The interval to advance the resolved ts of the regions.
+const MIN_TS_INTERVAL_MS: u64 = 1000;
+
+pub enum Task {
+    Register {
+        request: ChangeDataRequest,
+        sink: UnboundedSender<ChangeDataEvent>,
+    },
+    Deregister { region_id: u64, err: Error },
+    ChangeLog {
+        region_id: u64,
+        index: u64,
+        requests: Vec<Request>,
+    },
+    InitDownstream {
+        region_id: u64,
+        id: u64,
+        snapshot: Result<Box<Snapshot>>,
+    },
+    MinTs { min_ts: u64 },
+    RegisterMinTsEvent,
+}
+
+impl Display for Task {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Task::Register { ref request, .. } => write!(
+                f,
+                "register downstream of region {} from ts {}",
+                request.get_region_id(),
+                request.get_checkpoint_ts()
+            ),
+            Task::Deregister { region_id, ref err } => {
+                write!(f, "deregister region {}: {:?}", region_id, err)
+            }
+            Task::ChangeLog {
+                region_id, index, ..
+            } => write!(f, "change log of region {} at index {}", region_id, index),
+            Task::InitDownstream { region_id, id, .. } => {
+                write!(f, "initialize downstream {} of region {}", id, region_id)
+            }
+            Task::MinTs { min_ts } => write!(f, "min ts {}", min_ts),
+            Task::RegisterMinTsEvent => write!(f, "register min ts event"),
+        }
+    }
+}
+
+/// Manages the downstreams of all the observed regions on the store.
+pub struct Endpoint<C: PdClient + 'static> {
+    store_id: u64,
+    pd_client: Arc<C>,
+    engine: Box<Engine>,
+    concurrency_manager: Arc<ConcurrencyManager>,
+    scheduler: FutureScheduler<Task>,
+    observed_regions: Arc<RwLock<HashSet<u64>>>,
+    delegates: HashMap<u64, Vec<Delegate>>,
+    next_id: u64,
+    timer: Timer,
+}
+
+impl<C: PdClient + 'static> Endpoint<C> {
+    pub fn new(
+        store_id: u64,
+        pd_client: Arc<C>,
+        engine: Box<Engine>,
+        concurrency_manager: Arc<ConcurrencyManager>,
+        scheduler: FutureScheduler<Task>,
+        observed_regions: Arc<RwLock<HashSet<u64>>>,
+    ) -> Endpoint<C> {
+        Endpoint {
+            store_id: store_id,
+            pd_client: pd_client,
+            engine: engine,
+            concurrency_manager: concurrency_manager,
+            scheduler: scheduler,
+            observed_regions: observed_regions,
+            delegates: HashMap::default(),
+            next_id: 0,
+            timer: Timer::default(),
+        }
+    }
+
+    fn on_register(
+        &mut self,
+        mut request: ChangeDataRequest,
+        sink: UnboundedSender<ChangeDataEvent>,
+        handle: &Handle,
+    ) {
+        let region_id = request.get_region_id();
+        self.next_id += 1;
+        let id = self.next_id;
+        info!(
+            "register downstream {} of region {} from ts {}",
+            id,
+            region_id,
+            request.get_checkpoint_ts()
+        );
+        let delegate = Delegate::new(
+            id,
+            region_id,
+            request.get_request_id(),
+            request.get_checkpoint_ts(),
+            sink,
+        );
+        // Changes applied from now on are buffered until the snapshot is scanned.
+        self.observed_regions.write().unwrap().insert(region_id);
+        self.delegates
+            .entry(region_id)
+            .or_insert_with(Vec::new)
+            .push(delegate);
+
+        let epoch = request.take_region_epoch();
+        let store_id = self.store_id;
+        let engine = self.engine.clone();
+        let scheduler = self.scheduler.clone();
+        let f = self.pd_client.get_region_by_id(region_id).then(move |res| {
+            let res = res.map_err(Error::from).and_then(|region| {
+                let scheduler = scheduler.clone();
+                async_snapshot(engine.as_ref(), store_id, region_id, epoch, region, scheduler, id)
+            });
+            if let Err(e) = res {
+                let task = Task::InitDownstream {
+                    region_id: region_id,
+                    id: id,
+                    snapshot: Err(e),
+                };
+                if let Err(e) = scheduler.schedule(task) {
+                    error!("failed to schedule cdc task: {:?}", e);
+                }
+            }
+            Ok(())
+        });
+        handle.spawn(f);
+    }
+
+    // Removes the downstream `id` of the region, or all of them if `id` is `None`.
+    fn remove_delegates(&mut self, region_id: u64, id: Option<u64>) -> Vec<Delegate> {
+        let mut removed = vec![];
+        let is_empty = match self.delegates.get_mut(&region_id) {
+            Some(delegates) => {
+                let mut i = 0;
+                while i < delegates.len() {
+                    if id.map_or(true, |id| delegates[i].id == id) {
+                        removed.push(delegates.swap_remove(i));
+                    } else {
+                        i += 1;
+                    }
+                }
+                delegates.is_empty()
+            }
+            None => false,
+        };
+        if is_empty {
+            self.delegates.remove(&region_id);
+            self.observed_regions.write().unwrap().remove(&region_id);
+        }
+        removed
+    }
+
+    fn on_deregister(&mut self, region_id: u64, id: Option<u64>, err: Error) {
+        info!("deregister downstreams of region {}: {:?}", region_id, err);
+        let err = err.into_event_error();
+        for delegate in self.remove_delegates(region_id, id) {
+            delegate.fail(err.clone());
+        }
+    }
+
+    fn on_change_log(&mut self, region_id: u64, index: u64, requests: Vec<Request>) {
+        let mut gone = vec![];
+        if let Some(delegates) = self.delegates.get_mut(&region_id) {
+            for delegate in delegates.iter_mut() {
+                if !delegate.on_change_log(index, requests.clone()) {
+                    gone.push(delegate.id);
+                }
+            }
+        }
+        for id in gone {
+            info!("downstream {} of region {} is gone", id, region_id);
+            self.remove_delegates(region_id, Some(id));
+        }
+    }
+
+    fn on_init_downstream(&mut self, region_id: u64, id: u64, snapshot: Result<Box<Snapshot>>) {
+        let res = {
+            let delegate = match self.delegates.get_mut(&region_id) {
+                Some(delegates) => delegates.iter_mut().find(|d| d.id == id),
+                None => None,
+            };
+            let delegate = match delegate {
+                Some(delegate) => delegate,
+                // The downstream has been deregistered.
+                None => return,
+            };
+            // TODO: scan in another thread so that the changes of other regions are not blocked.
+            snapshot.and_then(|snap| delegate.initialize(snap.as_ref()))
+        };
+        match res {
+            Ok(true) => info!("downstream {} of region {} is initialized", id, region_id),
+            Ok(false) => {
+                info!("downstream {} of region {} is gone", id, region_id);
+                self.remove_delegates(region_id, Some(id));
+            }
+            Err(e) => self.on_deregister(region_id, Some(id), e),
+        }
+    }
+
+    fn on_min_ts(&mut self, min_ts: u64) {
+        // New async commit locks will be committed after `min_ts`.
+        self.concurrency_manager.update_max_read_ts(min_ts);
+        let min_ts = match self.concurrency_manager.global_min_lock_ts() {
+            Some(lock_ts) => cmp::min(lock_ts, min_ts),
+            None => min_ts,
+        };
+        let mut gone = vec![];
+        for (region_id, delegates) in &mut self.delegates {
+            for delegate in delegates.iter_mut() {
+                if !delegate.on_min_ts(min_ts) {
+                    gone.push((*region_id, delegate.id));
+                }
+            }
+        }
+        for (region_id, id) in gone {
+            info!("downstream {} of region {} is gone", id, region_id);
+            self.remove_delegates(region_id, Some(id));
+        }
+    }
+
+    fn register_min_ts_event(&self, handle: &Handle) {
+        let pd_client = self.pd_client.clone();
+        let scheduler = self.scheduler.clone();
+        let f = self.timer
+            .sleep(Duration::from_millis(MIN_TS_INTERVAL_MS))
+            .then(move |_| pd_client.get_tso())
+            .then(move |res| {
+                match res {
+                    Ok(ts) => {
+                        if let Err(e) = scheduler.schedule(Task::MinTs { min_ts: ts }) {
+                            debug!("failed to schedule min ts: {:?}", e);
+                        }
+                    }
+                    Err(e) => warn!("failed to get tso: {:?}", e),
+                }
+                // Fails if the endpoint is stopped.
+                if let Err(e) = scheduler.schedule(Task::RegisterMinTsEvent) {
+                    debug!("failed to register min ts event: {:?}", e);
+                }
+                Ok(())
+            });
+        handle.spawn(f);
+    }
+}
+
+impl<C: PdClient + 'static> FutureRunnable<Task> for Endpoint<C> {
+    fn run(&mut self, task: Task, handle: &Handle) {
+        match task {
+            Task::Register { request, sink } => self.on_register(request, sink, handle),
+            Task::Deregister { region_id, err } => self.on_deregister(region_id, None, err),
+            Task::ChangeLog {
+                region_id,
+                index,
+                requests,
+            } => self.on_change_log(region_id, index, requests),
+            Task::InitDownstream {
+                region_id,
+                id,
+                snapshot,
+            } => self.on_init_downstream(region_id, id, snapshot),
+            Task::MinTs { min_ts } => self.on_min_ts(min_ts),
+            Task::RegisterMinTsEvent => self.register_min_ts_event(handle),
+        }
+    }
+
+    fn shutdown(&mut self) {
+        let region_ids: Vec<_> = self.delegates.keys().cloned().collect();
+        for region_id in region_ids {
+            let err = RaftStoreError::RegionNotFound(region_id);
+            self.on_deregister(region_id, None, Error::Request(err.into()));
+        }
+    }
+}
+
+// Takes a snapshot of the region on the leader, the result is sent back to the endpoint.
+fn async_snapshot(
+    engine: &Engine,
+    store_id: u64,
+    region_id: u64,
+    epoch: RegionEpoch,
+    region: Option<Region>,
+    scheduler: FutureScheduler<Task>,
+    id: u64,
+) -> Result<()> {
+    let peer = match region.as_ref().and_then(|r| find_peer(r, store_id)) {
+        Some(peer) => peer.clone(),
+        None => {
+            let err = RaftStoreError::RegionNotFound(region_id);
+            return Err(Error::Request(err.into()));
+        }
+    };
+    let mut ctx = Context::new();
+    ctx.set_region_id(region_id);
+    ctx.set_region_epoch(epoch);
+    ctx.set_peer(peer);
+    try!(engine.async_snapshot(
+        &ctx,
+        box move |(_, res)| {
+            let task = Task::InitDownstream {
+                region_id: region_id,
+                id: id,
+                snapshot: res.map_err(Error::from),
+            };
+            if let Err(e) = scheduler.schedule(task) {
+                error!("failed to schedule cdc task: {:?}", e);
+            }
+        }
+    ));
+    Ok(())
+}
@@ -0,0 +1,92 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::error;
+use std::result;
+
+use kvproto::cdcpb::Event_Error;
+use kvproto::errorpb;
+
+use pd::Error as PdError;
+use storage::engine::Error as EngineError;
+use storage::mvcc::Error as MvccError;
+use util::codec::Error as CodecError;
+
+quick_error!{
+    #[derive(Debug)]
+    pub enum Error {
+        Request(err: errorpb::Error) {
+            from()
+            description(err.get_message())
+            display("{:?}", err)
+        }
+        Engine(err: EngineError) {
+            from()
+            cause(err)
+            display("{:?}", err)
+            description(err.description())
+        }
+        Mvcc(err: MvccError) {
+            from()
+            cause(err)
+            display("{:?}", err)
+            description(err.description())
+        }
+        Codec(err: CodecError) {
+            from()
+            cause(err)
+            display("{:?}", err)
+            description(err.description())
+        }
+        Pd(err: PdError) {
+            from()
+            cause(err)
+            display("{:?}", err)
+            description(err.description())
+        }
+        Other(err: Box<error::Error + Sync + Send>) {
+            from()
+            cause(err.as_ref())
+            description(err.description())
+            display("{:?}", err)
+        }
+    }
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl Error {
+    /// Converts the error to the one sent to the downstream, which should subscribe
+    /// the region again after fixing its region info.
+    pub fn into_event_error(self) -> Event_Error {
+        let mut err = match self {
+            Error::Request(e) | Error::Engine(EngineError::Request(e)) => e,
+            e => {
+                let mut err = errorpb::Error::new();
+                err.set_message(format!("{:?}", e));
+                err
+            }
+        };
+        let mut event_err = Event_Error::new();
+        if err.has_not_leader() {
+            event_err.set_not_leader(err.take_not_leader());
+        } else if err.has_region_not_found() {
+            event_err.set_region_not_found(err.take_region_not_found());
+        } else if err.has_stale_epoch() {
+            event_err.set_epoch_not_match(err.take_stale_epoch());
+        } else {
+            event_err.set_message(err.take_message());
+        }
+        event_err
+    }
+}
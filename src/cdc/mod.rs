@@ -0,0 +1,32 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Change data capture of regions.
+//!
+//! `CdcObserver` sends the writes applied to the observed regions to the `Endpoint`, which
+//! assembles the prewrites and commits into committed rows and streams them to the
+//! downstreams subscribed by `event_feed`. A downstream starts with an incremental scan of the
+//! rows committed after its checkpoint ts, and then receives the later changes. The resolved ts
+//! of a region is the min start ts of its locks, bounded by a recent ts from PD, all the rows
+//! committed before it have been sent.
+
+mod delegate;
+mod endpoint;
+mod errors;
+mod observer;
+mod service;
+
+pub use self::endpoint::{Endpoint, Task};
+pub use self::errors::{Error, Result};
+pub use self::observer::CdcObserver;
+pub use self::service::Service;
@@ -0,0 +1,104 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::{Arc, RwLock};
+
+use kvproto::raft_cmdpb::{AdminCmdType, AdminRequest, Request};
+use raft::StateRole;
+
+use raftstore::Error as RaftStoreError;
+use raftstore::coprocessor::{Coprocessor, ObserverContext, RegionObserver};
+use util::collections::HashSet;
+use util::worker::FutureScheduler;
+use super::{Error, Task};
+
+/// Sends the changes applied to the observed regions to the cdc endpoint.
+pub struct CdcObserver {
+    scheduler: FutureScheduler<Task>,
+    observed_regions: Arc<RwLock<HashSet<u64>>>,
+}
+
+impl CdcObserver {
+    pub fn new(
+        scheduler: FutureScheduler<Task>,
+        observed_regions: Arc<RwLock<HashSet<u64>>>,
+    ) -> CdcObserver {
+        CdcObserver {
+            scheduler: scheduler,
+            observed_regions: observed_regions,
+        }
+    }
+
+    fn is_observed(&self, region_id: u64) -> bool {
+        self.observed_regions.read().unwrap().contains(&region_id)
+    }
+
+    fn schedule(&self, task: Task) {
+        if let Err(e) = self.scheduler.schedule(task) {
+            error!("failed to schedule cdc task: {:?}", e);
+        }
+    }
+}
+
+impl Coprocessor for CdcObserver {}
+
+impl RegionObserver for CdcObserver {
+    fn post_apply_query(&self, ctx: &mut ObserverContext, index: u64, requests: &[Request]) {
+        let region_id = ctx.region().get_id();
+        if !self.is_observed(region_id) {
+            return;
+        }
+        self.schedule(Task::ChangeLog {
+            region_id: region_id,
+            index: index,
+            requests: requests.to_vec(),
+        });
+    }
+
+    fn post_apply_admin(&self, ctx: &mut ObserverContext, req: &AdminRequest) {
+        let region_id = ctx.region().get_id();
+        if !self.is_observed(region_id) {
+            return;
+        }
+        match req.get_cmd_type() {
+            // The range of the region is changed, downstreams should subscribe the new
+            // regions instead.
+            AdminCmdType::Split |
+            AdminCmdType::PrepareMerge |
+            AdminCmdType::CommitMerge |
+            AdminCmdType::RollbackMerge => {
+                let err = RaftStoreError::StaleEpoch(
+                    format!("region {} is changed by {:?}", region_id, req.get_cmd_type()),
+                    vec![],
+                );
+                self.schedule(Task::Deregister {
+                    region_id: region_id,
+                    err: Error::Request(err.into()),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    fn on_role_change(&self, ctx: &mut ObserverContext, role: StateRole) {
+        let region_id = ctx.region().get_id();
+        if role == StateRole::Leader || !self.is_observed(region_id) {
+            return;
+        }
+        let err = RaftStoreError::NotLeader(region_id, None);
+        self.schedule(Task::Deregister {
+            region_id: region_id,
+            err: Error::Request(err.into()),
+        });
+    }
+}
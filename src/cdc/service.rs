@@ -0,0 +1,65 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use futures::{Future, Sink, Stream};
+use futures::sync::mpsc;
+use grpc::{Error as GrpcError, RpcContext, RpcStatus, RpcStatusCode, ServerStreamingSink,
+           WriteFlags};
+use kvproto::cdcpb::{ChangeDataEvent, ChangeDataRequest};
+use kvproto::cdcpb_grpc;
+
+use util::worker::FutureScheduler;
+use super::Task;
+
+#[derive(Clone)]
+pub struct Service {
+    scheduler: FutureScheduler<Task>,
+}
+
+impl Service {
+    pub fn new(scheduler: FutureScheduler<Task>) -> Service {
+        Service {
+            scheduler: scheduler,
+        }
+    }
+}
+
+impl cdcpb_grpc::ChangeData for Service {
+    fn event_feed(
+        &self,
+        ctx: RpcContext,
+        req: ChangeDataRequest,
+        sink: ServerStreamingSink<ChangeDataEvent>,
+    ) {
+        let region_id = req.get_region_id();
+        let (tx, rx) = mpsc::unbounded();
+        let task = Task::Register {
+            request: req,
+            sink: tx,
+        };
+        if let Err(e) = self.scheduler.schedule(task) {
+            let status = RpcStatus::new(RpcStatusCode::Unavailable, Some(format!("{}", e)));
+            ctx.spawn(sink.fail(status).map_err(move |e| {
+                error!("failed to fail event feed of region {}: {:?}", region_id, e);
+            }));
+            return;
+        }
+        // `UnboundedReceiver` never returns an error, the stream ends when the endpoint drops
+        // the downstream.
+        let events = rx.map(|e| (e, WriteFlags::default()))
+            .map_err(|()| GrpcError::RemoteStopped);
+        ctx.spawn(sink.send_all(events).map(|_| ()).map_err(move |e| {
+            warn!("event feed of region {} is closed: {:?}", region_id, e);
+        }));
+    }
+}
@@ -21,6 +21,7 @@ use rocksdb::{BlockBasedOptions, ColumnFamilyOptions, CompactionPriority, DBComp
 use sys_info;
 
 use server::Config as ServerConfig;
+use raft_engine::Config as RaftEngineConfig;
 use raftstore::store::Config as RaftstoreConfig;
 use raftstore::store::keys::region_raft_prefix_len;
 use storage::{Config as StorageConfig, CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE, DEFAULT_DATA_DIR,
@@ -29,6 +30,8 @@ use util::config::{self, compression_type_level_serde, ReadableDuration, Readabl
 use util::properties::{MvccPropertiesCollectorFactory, SizePropertiesCollectorFactory};
 use util::rocksdb::{db_exist, CFOptions, EventListener, FixedPrefixSliceTransform,
                     FixedSuffixSliceTransform, NoopSliceTransform};
+use util::rocksdb::gc_filter::GcContext;
+use util::rocksdb::ttl::TTLCompactionFilter;
 
 const LOCKCF_MIN_MEM: usize = 256 * MB as usize;
 const LOCKCF_MAX_MEM: usize = GB as usize;
@@ -154,10 +157,19 @@ impl Default for DefaultCfConfig {
 }
 
 impl DefaultCfConfig {
-    pub fn build_opt(&self) -> ColumnFamilyOptions {
+    pub fn build_opt(&self, enable_ttl: bool) -> ColumnFamilyOptions {
         let mut cf_opts = build_cf_opt!(self);
         let f = Box::new(SizePropertiesCollectorFactory::default());
         cf_opts.add_table_properties_collector_factory("tikv.size-properties-collector", f);
+        if enable_ttl {
+            cf_opts
+                .set_compaction_filter(
+                    "tikv.ttl-compaction-filter",
+                    false,
+                    Box::new(TTLCompactionFilter::default()),
+                )
+                .unwrap();
+        }
         cf_opts
     }
 }
@@ -200,7 +212,7 @@ impl Default for WriteCfConfig {
 }
 
 impl WriteCfConfig {
-    pub fn build_opt(&self) -> ColumnFamilyOptions {
+    pub fn build_opt(&self, gc_context: Option<&GcContext>) -> ColumnFamilyOptions {
         let mut cf_opts = build_cf_opt!(self);
         // Prefix extractor(trim the timestamp at tail) for write cf.
         let e = Box::new(FixedSuffixSliceTransform::new(8));
@@ -214,6 +226,15 @@ impl WriteCfConfig {
         cf_opts.add_table_properties_collector_factory("tikv.mvcc-properties-collector", f);
         let f = Box::new(SizePropertiesCollectorFactory::default());
         cf_opts.add_table_properties_collector_factory("tikv.size-properties-collector", f);
+        if let Some(ctx) = gc_context {
+            cf_opts
+                .set_compaction_filter(
+                    "tikv.gc-compaction-filter",
+                    false,
+                    Box::new(ctx.new_filter()),
+                )
+                .unwrap();
+        }
         cf_opts
     }
 }
@@ -409,11 +430,17 @@ impl DbConfig {
         opts
     }
 
-    pub fn build_cf_opts(&self) -> Vec<CFOptions> {
+    /// Builds the options of the column families, the write cf drops the old versions in
+    /// compactions with `gc_context` if it's given.
+    pub fn build_cf_opts(
+        &self,
+        enable_ttl: bool,
+        gc_context: Option<&GcContext>,
+    ) -> Vec<CFOptions> {
         vec![
-            CFOptions::new(CF_DEFAULT, self.defaultcf.build_opt()),
+            CFOptions::new(CF_DEFAULT, self.defaultcf.build_opt(enable_ttl)),
             CFOptions::new(CF_LOCK, self.lockcf.build_opt()),
-            CFOptions::new(CF_WRITE, self.writecf.build_opt()),
+            CFOptions::new(CF_WRITE, self.writecf.build_opt(gc_context)),
             CFOptions::new(CF_RAFT, self.raftcf.build_opt()),
         ]
     }
@@ -644,6 +671,7 @@ pub struct TiKvConfig {
     pub raft_store: RaftstoreConfig,
     pub rocksdb: DbConfig,
     pub raftdb: RaftDbConfig,
+    pub raft_engine: RaftEngineConfig,
 }
 
 impl Default for TiKvConfig {
@@ -657,6 +685,7 @@ impl Default for TiKvConfig {
             pd: PdConfig::default(),
             rocksdb: DbConfig::default(),
             raftdb: RaftDbConfig::default(),
+            raft_engine: RaftEngineConfig::default(),
             storage: StorageConfig::default(),
         }
     }
@@ -681,6 +710,15 @@ impl TiKvConfig {
             try!(config::canonicalize_path(&self.raft_store.raftdb_path))
         };
 
+        self.raft_engine.dir = if self.raft_engine.dir.is_empty() {
+            try!(config::canonicalize_sub_path(
+                &self.storage.data_dir,
+                "raft-engine"
+            ))
+        } else {
+            try!(config::canonicalize_path(&self.raft_engine.dir))
+        };
+
         let kv_db_path = try!(config::canonicalize_sub_path(
             &self.storage.data_dir,
             DEFAULT_ROCKSDB_SUB_DIR
@@ -701,6 +739,7 @@ impl TiKvConfig {
         try!(self.rocksdb.validate());
         try!(self.server.validate());
         try!(self.raft_store.validate());
+        try!(self.raft_engine.validate());
         try!(self.pd.validate());
         Ok(())
     }
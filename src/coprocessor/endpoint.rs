@@ -16,6 +16,7 @@ use std::time::Duration;
 use std::rc::Rc;
 use std::fmt::{self, Debug, Display, Formatter};
 use std::mem;
+use std::sync::Arc;
 
 use tipb::select::{self, Chunk, DAGRequest, SelectRequest};
 use tipb::analyze::{AnalyzeReq, AnalyzeType};
@@ -30,8 +31,10 @@ use util::time::{duration_to_sec, Instant};
 use util::worker::{BatchRunnable, Scheduler};
 use util::collections::HashMap;
 use util::threadpool::{Context, ContextFactory, ThreadPool, ThreadPoolBuilder};
+use raftstore::store::LoadStats;
 use server::{Config, OnResponse};
-use storage::{self, engine, Engine, FlowStatistics, Snapshot, Statistics, StatisticsSummary};
+use storage::{self, engine, ConcurrencyManager, Engine, FlowStatistics, Key, Snapshot, Statistics,
+              StatisticsSummary};
 use storage::engine::Error as EngineError;
 
 use super::codec::mysql;
@@ -65,6 +68,7 @@ const ENDPOINT_IS_BUSY: &'static str = "endpoint is busy";
 
 pub struct Host<R: CopSender + 'static> {
     engine: Box<Engine>,
+    concurrency_manager: Arc<ConcurrencyManager>,
     sched: Scheduler<Task>,
     reqs: HashMap<u64, Vec<RequestTask>>,
     last_req_id: u64,
@@ -78,6 +82,7 @@ pub type CopRequestStatistics = HashMap<u64, FlowStatistics>;
 
 pub trait CopSender: Send + Clone {
     fn send(&self, CopRequestStatistics) -> Result<()>;
+    fn send_load_stats(&self, LoadStats) -> Result<()>;
 }
 
 struct CopContextFactory<R: CopSender + 'static> {
@@ -94,6 +99,7 @@ where
             select_stats: Default::default(),
             index_stats: Default::default(),
             request_stats: HashMap::default(),
+            load_stats: LoadStats::default(),
         }
     }
 }
@@ -103,6 +109,7 @@ struct CopContext<R: CopSender + 'static> {
     select_stats: StatisticsSummary,
     index_stats: StatisticsSummary,
     request_stats: CopRequestStatistics,
+    load_stats: LoadStats,
     sender: R,
 }
 
@@ -129,6 +136,11 @@ impl<R: CopSender + 'static> CopContext<R> {
         flow_stats.add(&stats.write.flow_stats);
         flow_stats.add(&stats.data.flow_stats);
     }
+
+    // `key` is the encoded start key of the request.
+    fn add_load_by_region(&mut self, region_id: u64, key: Option<&[u8]>) {
+        self.load_stats.add_request(region_id, key);
+    }
 }
 
 impl<R: CopSender + 'static> Context for CopContext<R> {
@@ -155,14 +167,27 @@ impl<R: CopSender + 'static> Context for CopContext<R> {
                 error!("send coprocessor statistics: {:?}", e);
             };
         }
+        if !self.load_stats.is_empty() {
+            let load_stats = mem::replace(&mut self.load_stats, LoadStats::default());
+            if let Err(e) = self.sender.send_load_stats(load_stats) {
+                error!("send coprocessor load stats: {:?}", e);
+            }
+        }
 
     }
 }
 
 impl<R: CopSender + 'static> Host<R> {
-    pub fn new(engine: Box<Engine>, scheduler: Scheduler<Task>, cfg: &Config, r: R) -> Host<R> {
+    pub fn new(
+        engine: Box<Engine>,
+        concurrency_manager: Arc<ConcurrencyManager>,
+        scheduler: Scheduler<Task>,
+        cfg: &Config,
+        r: R,
+    ) -> Host<R> {
         Host {
             engine: engine,
+            concurrency_manager: concurrency_manager,
             sched: scheduler,
             reqs: HashMap::default(),
             last_req_id: 0,
@@ -185,6 +210,30 @@ impl<R: CopSender + 'static> Host<R> {
         }
     }
 
+    /// Bumps the max read ts before the snapshot is taken, see `storage::ConcurrencyManager`.
+    fn read_check(&self, req: &RequestTask) -> Result<()> {
+        let start_ts = match req.start_ts {
+            Some(ts) => ts,
+            None => return Ok(()),
+        };
+        if req.ctx.isolation_level == IsolationLevel::RC {
+            self.concurrency_manager.update_max_read_ts(start_ts);
+            return Ok(());
+        }
+        for range in req.req.get_ranges() {
+            let start_key = Key::from_raw(range.get_start());
+            let end_key = Key::from_raw(range.get_end());
+            if let Err(e) = self.concurrency_manager.read_range_check(
+                Some(&start_key),
+                Some(&end_key),
+                start_ts,
+            ) {
+                return Err(Error::from(storage::txn::Error::from(e)));
+            }
+        }
+        Ok(())
+    }
+
     fn running_task_count(&self) -> usize {
         self.pool.get_task_count() + self.low_priority_pool.get_task_count() +
             self.high_priority_pool.get_task_count()
@@ -222,9 +271,14 @@ impl<R: CopSender + 'static> Host<R> {
             };
             pool.execute(move |ctx: &mut CopContext<R>| {
                 let region_id = req.req.get_context().get_region_id();
+                let start_key = req.req
+                    .get_ranges()
+                    .first()
+                    .map(|r| Key::from_raw(r.get_start()).encoded().clone());
                 let stats = end_point.handle_request(req);
                 ctx.add_statistics(type_str, &stats);
                 ctx.add_statistics_by_region(region_id, &stats);
+                ctx.add_load_by_region(region_id, start_key.as_ref().map(Vec::as_slice));
                 COPR_PENDING_REQS
                     .with_label_values(&[type_str, pri_str])
                     .dec();
@@ -297,7 +351,7 @@ pub struct RequestTask {
 }
 
 impl RequestTask {
-    pub fn new(req: Request, on_resp: OnResponse) -> RequestTask {
+    pub fn new(mut req: Request, on_resp: OnResponse) -> RequestTask {
         let timer = Instant::now_coarse();
         let deadline = timer + Duration::from_secs(REQUEST_MAX_HANDLE_SECS);
         let mut start_ts = None;
@@ -345,6 +399,9 @@ impl RequestTask {
 
             _ => Err(box_err!("unsupported tp {}", tp)),
         };
+        if let Some(ts) = start_ts {
+            storage::fill_read_ts(req.mut_context(), ts);
+        }
         let req_ctx = ReqContext {
             deadline: deadline,
             isolation_level: req.get_context().get_isolation_level(),
@@ -445,12 +502,20 @@ impl<R: CopSender + 'static> BatchRunnable<Task> for Host<R> {
                         on_error(e, req);
                         continue;
                     }
+                    if let Err(e) = self.read_check(&req) {
+                        on_error(e, req);
+                        continue;
+                    }
+                    // Stale reads at different ts or replica reads can't share a snapshot
+                    // with the others.
                     let key = {
                         let ctx = req.req.get_context();
                         (
                             ctx.get_region_id(),
                             ctx.get_region_epoch().get_version(),
                             ctx.get_peer().get_id(),
+                            ctx.get_read_ts(),
+                            ctx.get_replica_read(),
                         )
                     };
                     let group = grouped_reqs.entry(key).or_insert_with(Vec::new);
@@ -762,6 +827,10 @@ mod tests {
         fn send(&self, _stats: CopRequestStatistics) -> Result<()> {
             Ok(())
         }
+
+        fn send_load_stats(&self, _stats: LoadStats) -> Result<()> {
+            Ok(())
+        }
     }
 
     #[test]
@@ -783,7 +852,13 @@ mod tests {
         let engine = engine::new_local_engine(TEMP_DIR, &[]).unwrap();
         let mut cfg = Config::default();
         cfg.end_point_concurrency = 1;
-        let end_point = Host::new(engine, worker.scheduler(), &cfg, MockCopSender::new());
+        let end_point = Host::new(
+            engine,
+            Arc::new(ConcurrencyManager::new()),
+            worker.scheduler(),
+            &cfg,
+            MockCopSender::new(),
+        );
         worker.start_batch(end_point, 30).unwrap();
         let (tx, rx) = mpsc::channel();
         let mut task = RequestTask::new(Request::new(), box move |msg| { tx.send(msg).unwrap(); });
@@ -800,7 +875,13 @@ mod tests {
         let engine = engine::new_local_engine(TEMP_DIR, &[]).unwrap();
         let mut cfg = Config::default();
         cfg.end_point_concurrency = 1;
-        let mut end_point = Host::new(engine, worker.scheduler(), &cfg, MockCopSender::new());
+        let mut end_point = Host::new(
+            engine,
+            Arc::new(ConcurrencyManager::new()),
+            worker.scheduler(),
+            &cfg,
+            MockCopSender::new(),
+        );
         end_point.max_running_task_count = 3;
         worker.start_batch(end_point, 30).unwrap();
         let (tx, rx) = mpsc::channel();
@@ -0,0 +1,77 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::error;
+use std::io::Error as IoError;
+use std::path::PathBuf;
+use std::result;
+
+use grpc::Error as GrpcError;
+
+use raftstore::Error as RaftStoreError;
+use util::codec::Error as CodecError;
+
+quick_error!{
+    #[derive(Debug)]
+    pub enum Error {
+        Io(err: IoError) {
+            from()
+            cause(err)
+            display("{:?}", err)
+            description(err.description())
+        }
+        RocksDB(msg: String) {
+            from()
+            display("RocksDB {}", msg)
+            description("RocksDB error")
+        }
+        Codec(err: CodecError) {
+            from()
+            cause(err)
+            display("{:?}", err)
+            description(err.description())
+        }
+        Grpc(err: GrpcError) {
+            from()
+            cause(err)
+            display("{:?}", err)
+            description(err.description())
+        }
+        RaftStore(err: RaftStoreError) {
+            from()
+            cause(err)
+            display("{:?}", err)
+            description(err.description())
+        }
+        FileExists(path: PathBuf) {
+            display("file {} exists", path.display())
+            description("file exists")
+        }
+        FileNotFound(path: PathBuf) {
+            display("file {} not found", path.display())
+            description("file not found")
+        }
+        FileCorrupted(path: PathBuf, reason: String) {
+            display("file {} is corrupted: {}", path.display(), reason)
+            description("file is corrupted")
+        }
+        Other(err: Box<error::Error + Sync + Send>) {
+            from()
+            cause(err.as_ref())
+            description(err.description())
+            display("{:?}", err)
+        }
+    }
+}
+
+pub type Result<T> = result::Result<T, Error>;
@@ -0,0 +1,271 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rocksdb::DB;
+
+use util::rocksdb as rocksdb_util;
+use super::Result;
+
+// Compactions and flushes can be run in parallel with the many files ingested.
+const IMPORT_MODE_MAX_BACKGROUND_JOBS: i32 = 32;
+// Large enough that the writes are never stalled by the level 0 files.
+const IMPORT_MODE_LEVEL0_WRITES_TRIGGER: i32 = 1 << 30;
+
+#[derive(Clone, Debug, PartialEq)]
+struct DBOptions {
+    max_background_jobs: i32,
+}
+
+impl DBOptions {
+    fn from_db(db: &DB) -> DBOptions {
+        let opts = db.get_db_options();
+        DBOptions {
+            max_background_jobs: opts.get_max_background_jobs(),
+        }
+    }
+
+    fn import_mode(&self) -> DBOptions {
+        DBOptions {
+            max_background_jobs: cmp::max(
+                self.max_background_jobs,
+                IMPORT_MODE_MAX_BACKGROUND_JOBS,
+            ),
+        }
+    }
+
+    fn apply(&self, db: &DB) -> Result<()> {
+        let jobs = self.max_background_jobs.to_string();
+        try!(db.set_db_options(&[("max_background_jobs", jobs.as_str())]));
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct CFOptions {
+    level0_slowdown_writes_trigger: i32,
+    level0_stop_writes_trigger: i32,
+    soft_pending_compaction_bytes_limit: u64,
+    hard_pending_compaction_bytes_limit: u64,
+}
+
+impl CFOptions {
+    fn from_db(db: &DB, cf: &str) -> Result<CFOptions> {
+        let handle = try!(rocksdb_util::get_cf_handle(db, cf));
+        let opts = db.get_options_cf(handle);
+        Ok(CFOptions {
+            level0_slowdown_writes_trigger: opts.get_level_zero_slowdown_writes_trigger(),
+            level0_stop_writes_trigger: opts.get_level_zero_stop_writes_trigger(),
+            soft_pending_compaction_bytes_limit: opts.get_soft_pending_compaction_bytes_limit(),
+            hard_pending_compaction_bytes_limit: opts.get_hard_pending_compaction_bytes_limit(),
+        })
+    }
+
+    // The pending compaction bytes limits are disabled by 0.
+    fn import_mode(&self) -> CFOptions {
+        CFOptions {
+            level0_slowdown_writes_trigger: cmp::max(
+                self.level0_slowdown_writes_trigger,
+                IMPORT_MODE_LEVEL0_WRITES_TRIGGER,
+            ),
+            level0_stop_writes_trigger: cmp::max(
+                self.level0_stop_writes_trigger,
+                IMPORT_MODE_LEVEL0_WRITES_TRIGGER,
+            ),
+            soft_pending_compaction_bytes_limit: 0,
+            hard_pending_compaction_bytes_limit: 0,
+        }
+    }
+
+    fn apply(&self, db: &DB, cf: &str) -> Result<()> {
+        let handle = try!(rocksdb_util::get_cf_handle(db, cf));
+        let slowdown = self.level0_slowdown_writes_trigger.to_string();
+        let stop = self.level0_stop_writes_trigger.to_string();
+        let soft = self.soft_pending_compaction_bytes_limit.to_string();
+        let hard = self.hard_pending_compaction_bytes_limit.to_string();
+        let opts = [
+            ("level0_slowdown_writes_trigger", slowdown.as_str()),
+            ("level0_stop_writes_trigger", stop.as_str()),
+            ("soft_pending_compaction_bytes_limit", soft.as_str()),
+            ("hard_pending_compaction_bytes_limit", hard.as_str()),
+        ];
+        try!(db.set_options_cf(handle, &opts));
+        Ok(())
+    }
+}
+
+// The options of normal mode, saved when entering import mode.
+struct NormalOptions {
+    db: DBOptions,
+    cfs: Vec<(String, CFOptions)>,
+}
+
+impl NormalOptions {
+    fn from_db(db: &DB) -> Result<NormalOptions> {
+        let mut cfs = vec![];
+        for cf in db.cf_names() {
+            cfs.push((cf.to_owned(), try!(CFOptions::from_db(db, cf))));
+        }
+        Ok(NormalOptions {
+            db: DBOptions::from_db(db),
+            cfs: cfs,
+        })
+    }
+
+    fn apply_import_mode(&self, db: &DB) -> Result<()> {
+        try!(self.db.import_mode().apply(db));
+        for &(ref cf, ref opts) in &self.cfs {
+            try!(opts.import_mode().apply(db, cf));
+        }
+        Ok(())
+    }
+
+    fn apply(&self, db: &DB) -> Result<()> {
+        try!(self.db.apply(db));
+        for &(ref cf, ref opts) in &self.cfs {
+            try!(opts.apply(db, cf));
+        }
+        Ok(())
+    }
+}
+
+struct Inner {
+    normal: Option<NormalOptions>,
+    // Import mode is left after the deadline if it isn't renewed.
+    deadline: Instant,
+}
+
+/// Switches the kv engine between normal mode and import mode. In import mode the write stall
+/// triggers are relaxed and more background jobs are allowed, so bulk loading isn't slowed
+/// down by the pending compactions. The options of normal mode are the ones the engine is
+/// opened with, they are restored when leaving import mode, and also when import mode times
+/// out, so a crashed client can't leave the engine in import mode.
+pub struct ImportModeSwitcher {
+    db: Arc<DB>,
+    timeout: Duration,
+    inner: Mutex<Inner>,
+}
+
+impl ImportModeSwitcher {
+    pub fn new(db: Arc<DB>, timeout: Duration) -> ImportModeSwitcher {
+        ImportModeSwitcher {
+            db: db,
+            timeout: timeout,
+            inner: Mutex::new(Inner {
+                normal: None,
+                deadline: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn is_import_mode(&self) -> bool {
+        self.inner.lock().unwrap().normal.is_some()
+    }
+
+    /// Enters import mode, or renews the timeout if it's in import mode already.
+    pub fn enter_import_mode(&self) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.deadline = Instant::now() + self.timeout;
+        if inner.normal.is_some() {
+            return Ok(());
+        }
+
+        let normal = try!(NormalOptions::from_db(&self.db));
+        let res = normal.apply_import_mode(&self.db);
+        // Saved even if some options fail to be set, so that they are restored later.
+        inner.normal = Some(normal);
+        try!(res);
+        info!("enter import mode");
+        Ok(())
+    }
+
+    pub fn enter_normal_mode(&self) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        self.restore(&mut inner)
+    }
+
+    /// Leaves import mode if it isn't renewed in time, returns whether it's left.
+    pub fn check_timeout(&self) -> Result<bool> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.normal.is_none() || Instant::now() < inner.deadline {
+            return Ok(false);
+        }
+        warn!("import mode times out after {:?}", self.timeout);
+        try!(self.restore(&mut inner));
+        Ok(true)
+    }
+
+    // The options are kept if they fail to be restored, so it can be retried.
+    fn restore(&self, inner: &mut Inner) -> Result<()> {
+        match inner.normal {
+            Some(ref normal) => try!(normal.apply(&self.db)),
+            None => return Ok(()),
+        }
+        inner.normal = None;
+        info!("enter normal mode");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use tempdir::TempDir;
+
+    use storage::{CF_DEFAULT, CF_WRITE};
+    use super::*;
+
+    fn check_options(db: &DB, db_opts: &DBOptions, cf_opts: &[CFOptions]) {
+        assert_eq!(DBOptions::from_db(db), *db_opts);
+        for (cf, opts) in db.cf_names().into_iter().zip(cf_opts) {
+            assert_eq!(CFOptions::from_db(db, cf).unwrap(), *opts);
+        }
+    }
+
+    #[test]
+    fn test_import_mode_switcher() {
+        let dir = TempDir::new("test_import_mode_switcher").unwrap();
+        let db = rocksdb_util::new_engine(dir.path().to_str().unwrap(), &[CF_DEFAULT, CF_WRITE])
+            .unwrap();
+        let db = Arc::new(db);
+        let normal_db = DBOptions::from_db(&db);
+        let normal_cfs: Vec<_> = db.cf_names()
+            .into_iter()
+            .map(|cf| CFOptions::from_db(&db, cf).unwrap())
+            .collect();
+        let import_cfs: Vec<_> = normal_cfs.iter().map(|o| o.import_mode()).collect();
+
+        let switcher = ImportModeSwitcher::new(db.clone(), Duration::from_millis(200));
+        assert!(!switcher.is_import_mode());
+        switcher.enter_import_mode().unwrap();
+        assert!(switcher.is_import_mode());
+        check_options(&db, &normal_db.import_mode(), &import_cfs);
+        // Entering again doesn't overwrite the saved options.
+        switcher.enter_import_mode().unwrap();
+        switcher.enter_normal_mode().unwrap();
+        assert!(!switcher.is_import_mode());
+        check_options(&db, &normal_db, &normal_cfs);
+        switcher.enter_normal_mode().unwrap();
+
+        switcher.enter_import_mode().unwrap();
+        assert!(!switcher.check_timeout().unwrap());
+        thread::sleep(Duration::from_millis(300));
+        assert!(switcher.check_timeout().unwrap());
+        assert!(!switcher.is_import_mode());
+        check_options(&db, &normal_db, &normal_cfs);
+    }
+}
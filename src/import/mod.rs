@@ -0,0 +1,38 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Restore from backup files.
+//!
+//! The client walks the regions overlapping a backup file and restores it region by region.
+//! The file is downloaded by `download` to every store with a peer of the region: the crc32
+//! is verified, the key prefixes are rewritten, for example to map a table to a new id, and
+//! the keys out of the region are dropped. Then `ingest` proposes `CmdType::IngestSST` on the
+//! leader, and every peer ingests its own copy of the file when applying it. A file whose
+//! region epoch is stale is rejected by all the peers, and the client downloads it again for
+//! the new regions.
+//!
+//! Bulk loading works the same way, except that the files are written by `write` from the
+//! sorted key-value pairs uploaded by the client, as the data committed at the commit ts of
+//! the batches. The client switches the stores to import mode first, in which the writes
+//! aren't stalled by the many ingested files, and switches them back when it's done. Import
+//! mode is left automatically if it isn't renewed within the timeout.
+
+mod errors;
+mod import_mode;
+mod service;
+mod sst_importer;
+
+pub use self::errors::{Error, Result};
+pub use self::import_mode::ImportModeSwitcher;
+pub use self::service::ImportSSTService;
+pub use self::sst_importer::{SSTImporter, TxnSSTWriter};
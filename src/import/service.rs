@@ -0,0 +1,269 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{future, Future, Stream};
+use futures::sync::oneshot;
+use futures_cpupool::{Builder, CpuPool};
+use grpc::{ClientStreamingSink, RequestStream, RpcContext, RpcStatus, RpcStatusCode, UnarySink};
+use kvproto::import_sstpb::{DownloadRequest, DownloadResponse, Error as ErrorPb, IngestRequest,
+                            IngestResponse, SwitchMode, SwitchModeRequest, SwitchModeResponse,
+                            WriteRequest, WriteResponse};
+use kvproto::import_sstpb::SSTMeta;
+use kvproto::import_sstpb_grpc::ImportSst;
+use kvproto::metapb::Region;
+use kvproto::raft_cmdpb::{CmdType, RaftCmdRequest, RaftCmdResponse, RaftRequestHeader,
+                          Request};
+use kvproto::raft_serverpb::{PeerState, RegionLocalState};
+use protobuf::RepeatedField;
+use rocksdb::DB;
+use tokio_timer::Timer;
+
+use backup::create_storage;
+use raftstore::Error as RaftStoreError;
+use raftstore::store::keys;
+use raftstore::store::engine::Peekable;
+use server::transport::RaftStoreRouter;
+use storage::CF_RAFT;
+use super::{Error, ImportModeSwitcher, Result, SSTImporter};
+
+// The interval to check whether import mode times out.
+const CHECK_IMPORT_MODE_INTERVAL_SECS: u64 = 10;
+
+/// Downloads the backup files or receives the uploaded key-value pairs to this store, and
+/// ingests them into the regions led by it.
+#[derive(Clone)]
+pub struct ImportSSTService<T: RaftStoreRouter> {
+    router: T,
+    engine: Arc<DB>,
+    importer: Arc<SSTImporter>,
+    switcher: Arc<ImportModeSwitcher>,
+    // Downloading, rewriting and writing the files are blocking.
+    pool: CpuPool,
+}
+
+impl<T: RaftStoreRouter> ImportSSTService<T> {
+    pub fn new(
+        router: T,
+        engine: Arc<DB>,
+        importer: Arc<SSTImporter>,
+        switcher: Arc<ImportModeSwitcher>,
+    ) -> ImportSSTService<T> {
+        let pool = Builder::new()
+            .name_prefix(thd_name!("sst-importer"))
+            .pool_size(1)
+            .create();
+        let s = switcher.clone();
+        let check = Timer::default()
+            .interval(Duration::from_secs(CHECK_IMPORT_MODE_INTERVAL_SECS))
+            .for_each(move |_| {
+                if let Err(e) = s.check_timeout() {
+                    error!("failed to leave import mode: {:?}", e);
+                }
+                Ok(())
+            })
+            .map_err(|e| error!("import mode checker is stopped: {:?}", e));
+        pool.spawn(check).forget();
+        ImportSSTService {
+            router: router,
+            engine: engine,
+            importer: importer,
+            switcher: switcher,
+            pool: pool,
+        }
+    }
+}
+
+// Reads the region of `meta` from the local region state, so the downloaded file is cut to
+// the actual range of the region instead of the range sent by the client.
+fn local_region(engine: &DB, meta: &SSTMeta) -> Result<Region> {
+    let region_id = meta.get_region_id();
+    let key = keys::region_state_key(region_id);
+    let mut state: RegionLocalState = match try!(engine.get_msg_cf(CF_RAFT, &key)) {
+        Some(state) => state,
+        None => return Err(Error::from(RaftStoreError::RegionNotFound(region_id))),
+    };
+    if state.get_state() == PeerState::Tombstone {
+        return Err(Error::from(RaftStoreError::RegionNotFound(region_id)));
+    }
+    let region = state.take_region();
+    let (epoch, region_epoch) = (meta.get_region_epoch(), region.get_region_epoch().clone());
+    if epoch.get_conf_ver() != region_epoch.get_conf_ver() ||
+        epoch.get_version() != region_epoch.get_version()
+    {
+        let msg = format!(
+            "epoch of region {} is {:?}, but the sst has {:?}",
+            region_id,
+            region_epoch,
+            epoch
+        );
+        return Err(Error::from(RaftStoreError::StaleEpoch(msg, vec![region])));
+    }
+    Ok(region)
+}
+
+fn new_error(e: Error) -> ErrorPb {
+    let mut err = ErrorPb::new();
+    err.set_message(format!("{:?}", e));
+    err
+}
+
+impl<T: RaftStoreRouter + 'static> ImportSst for ImportSSTService<T> {
+    fn switch_mode(
+        &self,
+        ctx: RpcContext,
+        req: SwitchModeRequest,
+        sink: UnarySink<SwitchModeResponse>,
+    ) {
+        let res = match req.get_mode() {
+            SwitchMode::Normal => self.switcher.enter_normal_mode(),
+            SwitchMode::Import => self.switcher.enter_import_mode(),
+        };
+        let f = match res {
+            Ok(_) => sink.success(SwitchModeResponse::new()),
+            Err(e) => {
+                warn!("failed to switch to {:?} mode: {:?}", req.get_mode(), e);
+                let status = RpcStatus::new(RpcStatusCode::Unknown, Some(format!("{:?}", e)));
+                sink.fail(status)
+            }
+        };
+        ctx.spawn(f.map_err(|e| {
+            warn!("failed to send switch mode response: {:?}", e);
+        }));
+    }
+
+    fn download(&self, ctx: RpcContext, req: DownloadRequest, sink: UnarySink<DownloadResponse>) {
+        let importer = self.importer.clone();
+        let engine = self.engine.clone();
+        let f = self.pool.spawn_fn(move || {
+            let res = local_region(&engine, req.get_sst()).and_then(|region| {
+                let storage = try!(create_storage(req.get_url()));
+                importer.download(
+                    req.get_sst(),
+                    &region,
+                    storage.as_ref(),
+                    req.get_name(),
+                    req.get_rewrite_rule(),
+                )
+            });
+            let mut resp = DownloadResponse::new();
+            match res {
+                Ok(Some(range)) => resp.set_range(range),
+                Ok(None) => resp.set_is_empty(true),
+                Err(e) => {
+                    warn!("failed to download {}: {:?}", req.get_name(), e);
+                    resp.set_error(new_error(e));
+                }
+            }
+            Ok(resp)
+        });
+        ctx.spawn(f.and_then(|resp| sink.success(resp)).map_err(|e| {
+            warn!("failed to send download response: {:?}", e);
+        }));
+    }
+
+    fn ingest(&self, ctx: RpcContext, mut req: IngestRequest, sink: UnarySink<IngestResponse>) {
+        // The followers must have downloaded the file too, or they fail to apply it.
+        if !self.importer.exist(req.get_sst()) {
+            let mut resp = IngestResponse::new();
+            let mut err = ErrorPb::new();
+            err.set_message(format!("{:?} is not downloaded", req.get_sst()));
+            resp.set_error(err);
+            ctx.spawn(sink.success(resp).map_err(|e| {
+                warn!("failed to send ingest response: {:?}", e);
+            }));
+            return;
+        }
+
+        let context = req.take_context();
+        let mut header = RaftRequestHeader::new();
+        header.set_region_id(context.get_region_id());
+        header.set_peer(context.get_peer().clone());
+        header.set_region_epoch(context.get_region_epoch().clone());
+        let mut request = Request::new();
+        request.set_cmd_type(CmdType::IngestSST);
+        request.mut_ingest_sst().set_sst(req.take_sst());
+        let mut cmd = RaftCmdRequest::new();
+        cmd.set_header(header);
+        cmd.set_requests(RepeatedField::from_vec(vec![request]));
+
+        let (tx, rx) = oneshot::channel();
+        let cb = box move |resp: RaftCmdResponse| { let _ = tx.send(resp); };
+        if let Err(e) = self.router.send_command(cmd, cb) {
+            let mut resp = IngestResponse::new();
+            resp.set_error(new_error(Error::from(e)));
+            ctx.spawn(sink.success(resp).map_err(|e| {
+                warn!("failed to send ingest response: {:?}", e);
+            }));
+            return;
+        }
+        let f = rx.map_err(|e| {
+            warn!("ingest command is canceled: {:?}", e);
+        }).and_then(|mut res| {
+            let mut resp = IngestResponse::new();
+            if res.get_header().has_error() {
+                resp.mut_error().set_store_error(res.mut_header().take_error());
+            }
+            sink.success(resp).map_err(|e| {
+                warn!("failed to send ingest response: {:?}", e);
+            })
+        });
+        ctx.spawn(f);
+    }
+    fn write(
+        &self,
+        ctx: RpcContext,
+        stream: RequestStream<WriteRequest>,
+        sink: ClientStreamingSink<WriteResponse>,
+    ) {
+        let importer = self.importer.clone();
+        // The first chunk is the meta, and the others are the batches.
+        let f = stream
+            .into_future()
+            .map_err(|(e, _)| Error::from(e))
+            .and_then(move |(chunk, stream)| -> Result<_> {
+                let meta = match chunk {
+                    Some(ref chunk) if chunk.has_meta() => chunk.get_meta(),
+                    _ => return Err(box_err!("the first chunk isn't the meta")),
+                };
+                let writer = try!(importer.new_txn_writer(meta));
+                Ok((writer, stream))
+            })
+            .and_then(|(writer, stream)| {
+                stream
+                    .map_err(Error::from)
+                    .fold(writer, |mut writer, chunk| {
+                        future::result(writer.write(chunk.get_batch()).map(|_| writer))
+                    })
+            })
+            .and_then(|writer| writer.finish())
+            .then(|res| {
+                let mut resp = WriteResponse::new();
+                match res {
+                    Ok(metas) => resp.set_metas(RepeatedField::from_vec(metas)),
+                    Err(e) => {
+                        warn!("failed to write: {:?}", e);
+                        resp.set_error(new_error(e));
+                    }
+                }
+                Ok::<_, ()>(resp)
+            });
+        ctx.spawn(self.pool.spawn(f).and_then(|resp| {
+            sink.success(resp).map_err(|e| {
+                warn!("failed to send write response: {:?}", e);
+            })
+        }));
+    }
+}
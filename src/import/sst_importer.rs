@@ -0,0 +1,604 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crc::crc32::{self, Digest, Hasher32};
+use kvproto::import_sstpb::{Range, RewriteRule, SSTMeta, WriteBatch};
+use kvproto::metapb::Region;
+use rocksdb::{ColumnFamilyOptions, EnvOptions, IngestExternalFileOptions, SeekKey,
+              SstFileWriter, DB};
+
+use backup::ExternalStorage;
+use raftstore::store::keys;
+use raftstore::store::engine::{IterOption, Iterable};
+use storage::{is_short_value, Key, CF_DEFAULT, CF_WRITE};
+use storage::mvcc::{Write, WriteType};
+use storage::types::split_encoded_key_on_ts;
+use util::rocksdb as rocksdb_util;
+use super::{Error, Result};
+
+const DOWNLOAD_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Keeps the SST files to be ingested. A file is downloaded to every store with a peer of the
+/// region, and then ingested by all the peers when the ingest command is applied.
+pub struct SSTImporter {
+    dir: PathBuf,
+}
+
+impl SSTImporter {
+    pub fn new(dir: &Path) -> Result<SSTImporter> {
+        try!(fs::create_dir_all(dir));
+        Ok(SSTImporter {
+            dir: dir.to_owned(),
+        })
+    }
+
+    fn path(&self, meta: &SSTMeta) -> PathBuf {
+        let uuid: String = meta.get_uuid()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let epoch = meta.get_region_epoch();
+        self.dir.join(format!(
+            "{}_{}_{}_{}_{}.sst",
+            uuid,
+            meta.get_region_id(),
+            epoch.get_conf_ver(),
+            epoch.get_version(),
+            meta.get_cf_name()
+        ))
+    }
+
+    pub fn exist(&self, meta: &SSTMeta) -> bool {
+        self.path(meta).exists()
+    }
+
+    /// Downloads the backup file `name` from `storage` as the file of `meta`. The crc32 of the
+    /// backup file is verified if `meta.crc32` isn't 0. The prefixes of the keys are rewritten
+    /// by `rewrite_rule` and the keys without the old prefix are dropped, then only the keys
+    /// in both `meta.range` and `region` are kept, so the file can be ingested into the region.
+    /// `region` is the local region of `meta`, the range sent by the client may be wider.
+    ///
+    /// Returns the first and the last keys of the file, or `None` if no key is left.
+    pub fn download(
+        &self,
+        meta: &SSTMeta,
+        region: &Region,
+        storage: &ExternalStorage,
+        name: &str,
+        rewrite_rule: &RewriteRule,
+    ) -> Result<Option<Range>> {
+        let path = self.path(meta);
+        if path.exists() {
+            return Err(Error::FileExists(path));
+        }
+        let download_path = path.with_extension("download");
+        let db_path = path.with_extension("db");
+        let tmp_path = path.with_extension("tmp");
+        let res = self.do_download(
+            meta,
+            region,
+            storage,
+            name,
+            rewrite_rule,
+            &download_path,
+            &db_path,
+            &tmp_path,
+        );
+        let _ = fs::remove_file(&download_path);
+        let _ = fs::remove_dir_all(&db_path);
+        match res {
+            Ok(Some(range)) => {
+                try!(fs::rename(&tmp_path, &path));
+                Ok(Some(range))
+            }
+            res => {
+                let _ = fs::remove_file(&tmp_path);
+                res
+            }
+        }
+    }
+
+    #[allow(too_many_arguments)]
+    fn do_download(
+        &self,
+        meta: &SSTMeta,
+        region: &Region,
+        storage: &ExternalStorage,
+        name: &str,
+        rewrite_rule: &RewriteRule,
+        download_path: &Path,
+        db_path: &Path,
+        tmp_path: &Path,
+    ) -> Result<Option<Range>> {
+        let mut reader = try!(storage.read(name));
+        let mut digest = Digest::new(crc32::IEEE);
+        {
+            let mut f = try!(File::create(download_path));
+            let mut buf = vec![0; DOWNLOAD_BUFFER_SIZE];
+            loop {
+                let n = try!(reader.read(&mut buf));
+                if n == 0 {
+                    break;
+                }
+                digest.write(&buf[..n]);
+                try!(f.write_all(&buf[..n]));
+            }
+            try!(f.sync_all());
+        }
+        if meta.get_crc32() != 0 && digest.sum32() != meta.get_crc32() {
+            let reason = format!(
+                "crc32 {} of {} mismatches {}",
+                digest.sum32(),
+                name,
+                meta.get_crc32()
+            );
+            return Err(Error::FileCorrupted(download_path.to_owned(), reason));
+        }
+
+        // There is no reader of SST files, so the file is read by ingesting it into a scratch
+        // DB.
+        let db = try!(rocksdb_util::new_engine(
+            db_path.to_str().unwrap(),
+            &[CF_DEFAULT]
+        ));
+        let mut opt = IngestExternalFileOptions::new();
+        opt.move_files(true);
+        try!(db.ingest_external_file(&opt, &[download_path.to_str().unwrap()]));
+
+        let mut writer = SstFileWriter::new(EnvOptions::new(), ColumnFamilyOptions::new());
+        try!(writer.open(tmp_path.to_str().unwrap()));
+        let (start, end) = intersect(
+            (meta.get_range().get_start(), meta.get_range().get_end()),
+            (region.get_start_key(), region.get_end_key()),
+        );
+        let mut range: Option<Range> = None;
+        let mut iter = db.new_iterator(IterOption::default());
+        let mut valid = iter.seek(keys::DATA_MIN_KEY.into());
+        while valid {
+            if let Some((key, ts)) = try!(rewrite_key(iter.key(), rewrite_rule)) {
+                if !end.is_empty() && key.encoded().as_slice() >= end {
+                    break;
+                }
+                if key.encoded().as_slice() >= start {
+                    let data_key = keys::data_key(key.append_ts(ts).encoded());
+                    try!(writer.add(&data_key, iter.value()));
+                    let r = range.get_or_insert_with(|| {
+                        let mut r = Range::new();
+                        r.set_start(key.encoded().clone());
+                        r
+                    });
+                    r.set_end(key.encoded().clone());
+                }
+            }
+            valid = iter.next();
+        }
+        // An empty SST file can't be finished.
+        if range.is_some() {
+            try!(writer.finish());
+        }
+        Ok(range)
+    }
+
+    /// Returns the first and the last keys in the file of `meta`. They are read from the file
+    /// itself, so unlike `meta.range` they can be trusted.
+    pub fn key_range(&self, meta: &SSTMeta) -> Result<(Vec<u8>, Vec<u8>)> {
+        let path = self.path(meta);
+        if !path.exists() {
+            return Err(Error::FileNotFound(path));
+        }
+        let link_path = path.with_extension("link");
+        let db_path = path.with_extension("check");
+        let res = read_key_range(&path, &link_path, &db_path);
+        let _ = fs::remove_file(&link_path);
+        let _ = fs::remove_dir_all(&db_path);
+        res
+    }
+
+    /// Ingests the file of `meta` into `db`, returns the size of the file.
+    pub fn ingest(&self, meta: &SSTMeta, db: &DB) -> Result<u64> {
+        let path = self.path(meta);
+        if !path.exists() {
+            return Err(Error::FileNotFound(path));
+        }
+        let size = try!(fs::metadata(&path)).len();
+        let handle = try!(rocksdb_util::get_cf_handle(db, meta.get_cf_name()));
+        let mut opt = IngestExternalFileOptions::new();
+        opt.move_files(true);
+        try!(db.ingest_external_file_cf(handle, &opt, &[path.to_str().unwrap()]));
+        // The file is left if it's copied instead of moved.
+        if path.exists() {
+            try!(fs::remove_file(&path));
+        }
+        Ok(size)
+    }
+
+    pub fn delete(&self, meta: &SSTMeta) -> Result<()> {
+        match fs::remove_file(self.path(meta)) {
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            res => res.map_err(Error::from),
+        }
+    }
+
+    /// Creates a writer saving the uploaded key-value pairs as the files of `meta` in the
+    /// default cf and the write cf.
+    pub fn new_txn_writer(&self, meta: &SSTMeta) -> Result<TxnSSTWriter> {
+        let default = try!(self.new_file_writer(meta, CF_DEFAULT));
+        let write = try!(self.new_file_writer(meta, CF_WRITE));
+        Ok(TxnSSTWriter {
+            range: meta.get_range().clone(),
+            default: default,
+            write: write,
+        })
+    }
+
+    fn new_file_writer(&self, meta: &SSTMeta, cf: &str) -> Result<FileWriter> {
+        let mut meta = meta.clone();
+        meta.set_cf_name(cf.to_owned());
+        let path = self.path(&meta);
+        if path.exists() {
+            return Err(Error::FileExists(path));
+        }
+        let tmp_path = path.with_extension("tmp");
+        let mut writer = SstFileWriter::new(EnvOptions::new(), ColumnFamilyOptions::new());
+        try!(writer.open(tmp_path.to_str().unwrap()));
+        Ok(FileWriter {
+            meta: meta,
+            path: path,
+            tmp_path: tmp_path,
+            writer: writer,
+            entries: 0,
+        })
+    }
+}
+
+struct FileWriter {
+    meta: SSTMeta,
+    path: PathBuf,
+    tmp_path: PathBuf,
+    writer: SstFileWriter,
+    entries: u64,
+}
+
+impl FileWriter {
+    fn put(&mut self, key: &Key, ts: u64, value: &[u8]) -> Result<()> {
+        let data_key = keys::data_key(key.append_ts(ts).encoded());
+        try!(self.writer.add(&data_key, value));
+        if self.entries == 0 {
+            self.meta.mut_range().set_start(key.encoded().clone());
+        }
+        self.meta.mut_range().set_end(key.encoded().clone());
+        self.entries += 1;
+        Ok(())
+    }
+
+    // Returns the meta with the length and the range of the file, `None` if nothing is
+    // written. The range is of the first and the last keys, like a downloaded file.
+    fn finish(mut self) -> Result<Option<SSTMeta>> {
+        if self.entries == 0 {
+            let _ = fs::remove_file(&self.tmp_path);
+            return Ok(None);
+        }
+        try!(self.writer.finish());
+        try!(fs::rename(&self.tmp_path, &self.path));
+        let length = try!(fs::metadata(&self.path)).len();
+        self.meta.set_length(length);
+        Ok(Some(self.meta))
+    }
+}
+
+/// Writes the key-value pairs uploaded in import mode as the data committed at the commit ts of
+/// their batch. Like a commit, a short value is saved in the write cf and a long one is saved
+/// in the default cf.
+pub struct TxnSSTWriter {
+    range: Range,
+    default: FileWriter,
+    write: FileWriter,
+}
+
+impl TxnSSTWriter {
+    /// Writes a batch, the keys must be in ascending order and in the range of the meta.
+    pub fn write(&mut self, batch: &WriteBatch) -> Result<()> {
+        let commit_ts = batch.get_commit_ts();
+        for pair in batch.get_pairs() {
+            let key = Key::from_raw(pair.get_key());
+            {
+                let (start, end) = (self.range.get_start(), self.range.get_end());
+                let k = key.encoded().as_slice();
+                if k < start || (!end.is_empty() && k >= end) {
+                    return Err(box_err!("key {} is out of range {:?}", key, self.range));
+                }
+            }
+            let value = pair.get_value();
+            let write = if is_short_value(value) {
+                Write::new(WriteType::Put, commit_ts, Some(value.to_vec()))
+            } else {
+                try!(self.default.put(&key, commit_ts, value));
+                Write::new(WriteType::Put, commit_ts, None)
+            };
+            try!(self.write.put(&key, commit_ts, &write.to_bytes()));
+        }
+        Ok(())
+    }
+
+    /// Finishes the files, returns the metas of the non-empty ones. They are ingested like the
+    /// downloaded files.
+    pub fn finish(self) -> Result<Vec<SSTMeta>> {
+        let mut metas = Vec::with_capacity(2);
+        for w in vec![self.default, self.write] {
+            if let Some(meta) = try!(w.finish()) {
+                metas.push(meta);
+            }
+        }
+        Ok(metas)
+    }
+}
+
+// Returns the intersection of the two ranges, an empty end means the range is unbounded.
+fn intersect<'a>(a: (&'a [u8], &'a [u8]), b: (&'a [u8], &'a [u8])) -> (&'a [u8], &'a [u8]) {
+    let start = cmp::max(a.0, b.0);
+    let end = if a.1.is_empty() {
+        b.1
+    } else if b.1.is_empty() {
+        a.1
+    } else {
+        cmp::min(a.1, b.1)
+    };
+    (start, end)
+}
+
+// Reads the first and the last keys of the SST file at `path` by ingesting a link of it into a
+// scratch DB at `db_path`, the file itself is left untouched.
+fn read_key_range(path: &Path, link_path: &Path, db_path: &Path) -> Result<(Vec<u8>, Vec<u8>)> {
+    try!(fs::hard_link(path, link_path));
+    let db = try!(rocksdb_util::new_engine(
+        db_path.to_str().unwrap(),
+        &[CF_DEFAULT]
+    ));
+    let mut opt = IngestExternalFileOptions::new();
+    opt.move_files(true);
+    try!(db.ingest_external_file(&opt, &[link_path.to_str().unwrap()]));
+
+    let mut iter = db.new_iterator(IterOption::default());
+    if !iter.seek(SeekKey::Start) {
+        return Err(Error::FileCorrupted(path.to_owned(), "no key".to_owned()));
+    }
+    let first = keys::origin_key(iter.key()).to_vec();
+    iter.seek(SeekKey::End);
+    let last = keys::origin_key(iter.key()).to_vec();
+    Ok((first, last))
+}
+
+// Returns the key without ts and the ts of the data key, `None` if the key doesn't have the
+// old prefix of the rule.
+fn rewrite_key(data_key: &[u8], rewrite_rule: &RewriteRule) -> Result<Option<(Key, u64)>> {
+    let (encoded, ts) = try!(split_encoded_key_on_ts(keys::origin_key(data_key)));
+    let (old_prefix, new_prefix) = (
+        rewrite_rule.get_old_key_prefix(),
+        rewrite_rule.get_new_key_prefix(),
+    );
+    if old_prefix.is_empty() && new_prefix.is_empty() {
+        return Ok(Some((Key::from_encoded(encoded.to_vec()), ts)));
+    }
+    // The prefixes are of raw keys, which don't keep the order in the encoded keys.
+    let raw = try!(Key::from_encoded(encoded.to_vec()).raw());
+    if !raw.starts_with(old_prefix) {
+        return Ok(None);
+    }
+    let mut key = new_prefix.to_vec();
+    key.extend_from_slice(&raw[old_prefix.len()..]);
+    Ok(Some((Key::from_raw(&key), ts)))
+}
+
+#[cfg(test)]
+mod tests {
+    use kvproto::import_sstpb::Pair;
+    use kvproto::metapb::RegionEpoch;
+    use tempdir::TempDir;
+
+    use backup::LocalStorage;
+    use raftstore::store::engine::Peekable;
+    use storage::SHORT_VALUE_MAX_LEN;
+    use super::*;
+
+    fn new_meta(range: (&[u8], &[u8])) -> SSTMeta {
+        let mut meta = SSTMeta::new();
+        meta.set_uuid(vec![1, 2, 3]);
+        meta.set_region_id(1);
+        let mut epoch = RegionEpoch::new();
+        epoch.set_conf_ver(1);
+        epoch.set_version(2);
+        meta.set_region_epoch(epoch);
+        meta.set_cf_name(CF_DEFAULT.to_owned());
+        meta.mut_range().set_start(Key::from_raw(range.0).encoded().clone());
+        if !range.1.is_empty() {
+            meta.mut_range().set_end(Key::from_raw(range.1).encoded().clone());
+        }
+        meta
+    }
+
+    #[test]
+    fn test_download_and_ingest() {
+        let dir = TempDir::new("test_download_and_ingest").unwrap();
+        let storage = LocalStorage::new(&dir.path().join("backup")).unwrap();
+        let importer = SSTImporter::new(&dir.path().join("import")).unwrap();
+
+        // Write a backup file.
+        let backup_path = dir.path().join("backup").join("a.sst");
+        let mut writer = SstFileWriter::new(EnvOptions::new(), ColumnFamilyOptions::new());
+        writer.open(backup_path.to_str().unwrap()).unwrap();
+        for k in &[b"t1_a", b"t1_b", b"t1_c", b"t2_a"] {
+            let key = keys::data_key(Key::from_raw(*k).append_ts(5).encoded());
+            writer.add(&key, *k).unwrap();
+        }
+        writer.finish().unwrap();
+        let mut checksum = Digest::new(crc32::IEEE);
+        let mut content = vec![];
+        File::open(&backup_path)
+            .unwrap()
+            .read_to_end(&mut content)
+            .unwrap();
+        checksum.write(&content);
+
+        let mut rule = RewriteRule::new();
+        rule.set_old_key_prefix(b"t1_".to_vec());
+        rule.set_new_key_prefix(b"t9_".to_vec());
+        let region = Region::new();
+
+        // The checksum mismatches.
+        let mut meta = new_meta((b"t9_b", b""));
+        meta.set_crc32(checksum.sum32().wrapping_add(1));
+        match importer.download(&meta, &region, &storage, "a.sst", &rule) {
+            Err(Error::FileCorrupted(..)) => {}
+            res => panic!("expect file corrupted, got {:?}", res),
+        }
+        assert!(!importer.exist(&meta));
+
+        // Nothing is in the range.
+        let meta = new_meta((b"t0", b"t1"));
+        assert!(importer.download(&meta, &region, &storage, "a.sst", &rule).unwrap().is_none());
+        assert!(!importer.exist(&meta));
+
+        let mut meta = new_meta((b"t9_b", b""));
+        meta.set_crc32(checksum.sum32());
+        let range = importer
+            .download(&meta, &region, &storage, "a.sst", &rule)
+            .unwrap()
+            .unwrap();
+        assert_eq!(range.get_start(), Key::from_raw(b"t9_b").encoded().as_slice());
+        assert_eq!(range.get_end(), Key::from_raw(b"t9_c").encoded().as_slice());
+        assert!(importer.exist(&meta));
+        match importer.download(&meta, &region, &storage, "a.sst", &rule) {
+            Err(Error::FileExists(_)) => {}
+            res => panic!("expect file exists, got {:?}", res),
+        }
+        let (first, last) = importer.key_range(&meta).unwrap();
+        assert_eq!(first, Key::from_raw(b"t9_b").append_ts(5).encoded().as_slice());
+        assert_eq!(last, Key::from_raw(b"t9_c").append_ts(5).encoded().as_slice());
+        // The range is read from the file, not from the meta.
+        let mut wrong_meta = meta.clone();
+        wrong_meta.mut_range().set_end(Key::from_raw(b"t9_b").encoded().clone());
+        assert_eq!(importer.key_range(&wrong_meta).unwrap().1, last);
+        assert!(importer.exist(&meta));
+
+        // The keys out of the local region are dropped even if the client asks for them.
+        let mut region_meta = new_meta((b"", b""));
+        region_meta.set_uuid(vec![4, 5, 6]);
+        let mut region = Region::new();
+        region.set_start_key(Key::from_raw(b"t9_b").encoded().clone());
+        region.set_end_key(Key::from_raw(b"t9_c").encoded().clone());
+        let range = importer
+            .download(&region_meta, &region, &storage, "a.sst", &rule)
+            .unwrap()
+            .unwrap();
+        assert_eq!(range.get_start(), Key::from_raw(b"t9_b").encoded().as_slice());
+        assert_eq!(range.get_end(), Key::from_raw(b"t9_b").encoded().as_slice());
+        importer.delete(&region_meta).unwrap();
+
+        let db_path = dir.path().join("db");
+        let db = rocksdb_util::new_engine(db_path.to_str().unwrap(), &[CF_DEFAULT]).unwrap();
+        assert!(importer.ingest(&meta, &db).unwrap() > 0);
+        assert!(!importer.exist(&meta));
+        for &(k, v) in &[(b"t9_b", Some(b"t1_b")), (b"t9_c", Some(b"t1_c"))] {
+            let key = keys::data_key(Key::from_raw(k).append_ts(5).encoded());
+            let value = db.get_value(&key).unwrap().map(|v| v.to_vec());
+            assert_eq!(value, v.map(|v| v.to_vec()));
+        }
+        for k in &[b"t9_a", b"t1_b", b"t2_a"] {
+            let key = keys::data_key(Key::from_raw(*k).append_ts(5).encoded());
+            assert!(db.get_value(&key).unwrap().is_none());
+        }
+        match importer.ingest(&meta, &db) {
+            Err(Error::FileNotFound(_)) => {}
+            res => panic!("expect file not found, got {:?}", res),
+        }
+        match importer.key_range(&meta) {
+            Err(Error::FileNotFound(_)) => {}
+            res => panic!("expect file not found, got {:?}", res),
+        }
+        importer.delete(&meta).unwrap();
+    }
+
+    fn new_batch(commit_ts: u64, pairs: &[(&[u8], &[u8])]) -> WriteBatch {
+        let mut batch = WriteBatch::new();
+        batch.set_commit_ts(commit_ts);
+        for &(k, v) in pairs {
+            let mut pair = Pair::new();
+            pair.set_key(k.to_vec());
+            pair.set_value(v.to_vec());
+            batch.mut_pairs().push(pair);
+        }
+        batch
+    }
+
+    #[test]
+    fn test_txn_sst_writer() {
+        let dir = TempDir::new("test_txn_sst_writer").unwrap();
+        let importer = SSTImporter::new(&dir.path().join("import")).unwrap();
+        let meta = new_meta((b"k1", b"k9"));
+        let long_value = vec![b'v'; SHORT_VALUE_MAX_LEN + 1];
+
+        let mut writer = importer.new_txn_writer(&meta).unwrap();
+        let pairs = [(&b"k1"[..], &b"v1"[..]), (&b"k2"[..], long_value.as_slice())];
+        writer.write(&new_batch(10, &pairs)).unwrap();
+        // The key is out of the range.
+        let pairs = [(&b"k9"[..], &b"v9"[..])];
+        assert!(writer.write(&new_batch(10, &pairs)).is_err());
+        match importer.new_txn_writer(&meta) {
+            Err(Error::FileExists(_)) => {}
+            Err(e) => panic!("expect file exists, got {:?}", e),
+            Ok(_) => panic!("expect file exists"),
+        }
+        let metas = writer.finish().unwrap();
+        assert_eq!(metas.len(), 2);
+        assert_eq!(metas[0].get_cf_name(), CF_DEFAULT);
+        assert_eq!(metas[0].get_range().get_start(), Key::from_raw(b"k2").encoded().as_slice());
+        assert_eq!(metas[0].get_range().get_end(), Key::from_raw(b"k2").encoded().as_slice());
+        assert_eq!(metas[1].get_cf_name(), CF_WRITE);
+        assert_eq!(metas[1].get_range().get_start(), Key::from_raw(b"k1").encoded().as_slice());
+        assert_eq!(metas[1].get_range().get_end(), Key::from_raw(b"k2").encoded().as_slice());
+
+        let db_path = dir.path().join("db");
+        let db = rocksdb_util::new_engine(db_path.to_str().unwrap(), &[CF_DEFAULT, CF_WRITE])
+            .unwrap();
+        for m in &metas {
+            assert!(m.get_length() > 0);
+            assert!(importer.exist(m));
+            importer.ingest(m, &db).unwrap();
+        }
+
+        let k1 = keys::data_key(Key::from_raw(b"k1").append_ts(10).encoded());
+        let k2 = keys::data_key(Key::from_raw(b"k2").append_ts(10).encoded());
+        let w = db.get_value_cf(CF_WRITE, &k1).unwrap().unwrap();
+        let write = Write::parse(&w).unwrap();
+        assert_eq!(write.write_type, WriteType::Put);
+        assert_eq!(write.start_ts, 10);
+        assert_eq!(write.short_value, Some(b"v1".to_vec()));
+        assert!(db.get_value(&k1).unwrap().is_none());
+        let w = db.get_value_cf(CF_WRITE, &k2).unwrap().unwrap();
+        let write = Write::parse(&w).unwrap();
+        assert_eq!(write.start_ts, 10);
+        assert!(write.short_value.is_none());
+        assert_eq!(&*db.get_value(&k2).unwrap().unwrap(), long_value.as_slice());
+
+        // Nothing is written.
+        let mut meta = new_meta((b"k1", b"k9"));
+        meta.set_uuid(vec![4, 5, 6]);
+        let writer = importer.new_txn_writer(&meta).unwrap();
+        assert!(writer.finish().unwrap().is_empty());
+    }
+}
@@ -84,5 +84,9 @@ pub mod raftstore;
 pub mod pd;
 pub mod server;
 pub mod coprocessor;
+pub mod cdc;
+pub mod backup;
+pub mod import;
+pub mod raft_engine;
 
 pub use storage::Storage;
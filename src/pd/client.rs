@@ -22,6 +22,7 @@ use grpc::{CallOption, EnvBuilder, WriteFlags};
 use kvproto::metapb;
 use kvproto::pdpb::{self, Member};
 
+use storage::mvcc::compose_ts;
 use util::{Either, HandyRwLock};
 use pd::PdFuture;
 use super::{Error, PdClient, RegionStat, Result, REQUEST_TIMEOUT};
@@ -201,6 +202,28 @@ impl PdClient for RpcClient {
         Ok(resp.take_region())
     }
 
+    fn get_region_leader(&self, key: &[u8]) -> Result<Option<metapb::Peer>> {
+        let mut req = pdpb::GetRegionRequest::new();
+        req.set_header(self.header());
+        req.set_region_key(key.to_vec());
+
+        let mut resp = try!(sync_request(
+            &self.leader_client,
+            LEADER_CHANGE_RETRY,
+            |client| {
+                let option = CallOption::default().timeout(Duration::from_secs(REQUEST_TIMEOUT));
+                client.get_region_opt(req.clone(), option)
+            }
+        ));
+        try!(check_resp_header(resp.get_header()));
+
+        if resp.has_leader() {
+            Ok(Some(resp.take_leader()))
+        } else {
+            Ok(None)
+        }
+    }
+
     fn get_region_by_id(&self, region_id: u64) -> PdFuture<Option<metapb::Region>> {
         let mut req = pdpb::GetRegionByIDRequest::new();
         req.set_header(self.header());
@@ -344,4 +367,55 @@ impl PdClient for RpcClient {
             .request(req, executor, LEADER_CHANGE_RETRY)
             .execute()
     }
+
+    fn get_gc_safe_point(&self) -> PdFuture<u64> {
+        let mut req = pdpb::GetGCSafePointRequest::new();
+        req.set_header(self.header());
+
+        let executor = |client: &RwLock<Inner>, req: pdpb::GetGCSafePointRequest| {
+            let option = CallOption::default().timeout(Duration::from_secs(REQUEST_TIMEOUT));
+            let handler = client.rl().client.get_gc_safe_point_async_opt(req, option);
+            Box::new(handler.map_err(Error::Grpc).and_then(|resp| {
+                try!(check_resp_header(resp.get_header()));
+                Ok(resp.get_safe_point())
+            })) as PdFuture<_>
+        };
+
+        self.leader_client
+            .request(req, executor, LEADER_CHANGE_RETRY)
+            .execute()
+    }
+
+    fn get_tso(&self) -> PdFuture<u64> {
+        let mut req = pdpb::TsoRequest::new();
+        req.set_header(self.header());
+        req.set_count(1);
+
+        let executor = |client: &RwLock<Inner>, req: pdpb::TsoRequest| {
+            let (tx, rx) = client.rl().client.tso();
+            Box::new(
+                tx.send((req, WriteFlags::default()))
+                    .map_err(Error::Grpc)
+                    .and_then(|tx| {
+                        // Keep the sender until the response is received.
+                        rx.into_future()
+                            .map_err(|(e, _)| Error::Grpc(e))
+                            .map(move |(resp, _)| (tx, resp))
+                    })
+                    .and_then(|(_, resp)| {
+                        let resp = match resp {
+                            Some(resp) => resp,
+                            None => return Err(box_err!("tso stream is closed")),
+                        };
+                        try!(check_resp_header(resp.get_header()));
+                        let ts = resp.get_timestamp();
+                        Ok(compose_ts(ts.get_physical() as u64, ts.get_logical() as u64))
+                    }),
+            ) as PdFuture<_>
+        };
+
+        self.leader_client
+            .request(req, executor, LEADER_CHANGE_RETRY)
+            .execute()
+    }
 }
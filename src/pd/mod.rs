@@ -116,6 +116,9 @@ pub trait PdClient: Send + Sync {
     // Get region which the key belong to.
     fn get_region(&self, key: &[u8]) -> Result<metapb::Region>;
 
+    // Get the leader of the region which the key belongs to, `None` if pd doesn't know it.
+    fn get_region_leader(&self, key: &[u8]) -> Result<Option<metapb::Peer>>;
+
     // Get region by region id.
     fn get_region_by_id(&self, region_id: u64) -> PdFuture<Option<metapb::Region>>;
 
@@ -142,6 +145,12 @@ pub trait PdClient: Send + Sync {
 
     // Report pd the split region.
     fn report_split(&self, left: metapb::Region, right: metapb::Region) -> PdFuture<()>;
+
+    // Get the GC safe point, data older than it can be collected.
+    fn get_gc_safe_point(&self) -> PdFuture<u64>;
+
+    // Get a timestamp from the TSO of PD.
+    fn get_tso(&self) -> PdFuture<u64>;
 }
 
 const REQUEST_TIMEOUT: u64 = 2; // 2s
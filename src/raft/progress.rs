@@ -79,6 +79,14 @@ pub struct Progress {
     // When a leader receives a reply, the previous inflights should
     // be freed by calling inflights.freeTo.
     pub ins: Inflights,
+
+    // is_learner is true if the peer is a learner. A learner receives log entries
+    // like a follower, but it does not vote and is not counted in the quorum.
+    //
+    // In joint consensus, the voters being removed or demoted from the outgoing
+    // configuration are marked as learners too, they still count in the quorum of
+    // the outgoing configuration until the joint state is left.
+    pub is_learner: bool,
 }
 
 
@@ -188,6 +196,21 @@ impl Progress {
     }
 }
 
+// quorum_matched returns the largest index that has been replicated to a majority
+// of the given voters. It returns 0 if there is no voter.
+pub fn quorum_matched<'a, I>(voters: I) -> u64
+where
+    I: Iterator<Item = &'a Progress>,
+{
+    let mut mis: Vec<u64> = voters.map(|p| p.matched).collect();
+    if mis.is_empty() {
+        return 0;
+    }
+    // reverse sort
+    mis.sort_by(|a, b| b.cmp(a));
+    mis[mis.len() / 2]
+}
+
 
 #[derive(Debug, Default, Clone)]
 pub struct Inflights {
@@ -26,19 +26,20 @@
 // limitations under the License.
 
 
-use std::cmp;
+use std::{cmp, mem};
 
 use rand::{self, Rng};
-use kvproto::eraftpb::{Entry, EntryType, HardState, Message, MessageType, Snapshot};
+use kvproto::eraftpb::{ConfChangeSingle, ConfChangeType, Entry, EntryType, HardState, Message,
+                       MessageType, Snapshot};
 use protobuf::repeated::RepeatedField;
 
 use raft::storage::Storage;
-use raft::progress::{Inflights, Progress, ProgressState};
+use raft::progress::{self, Inflights, Progress, ProgressState};
 use raft::errors::{Error, Result, StorageError};
 use raft::raft_log::{self, RaftLog};
 use raft::read_only::{ReadOnly, ReadOnlyOption, ReadState};
 
-use super::FlatMap;
+use super::{FlatMap, HashSet};
 
 // CAMPAIGN_PRE_ELECTION represents the first phase of a normal election when
 // Config.pre_vote is true.
@@ -82,6 +83,11 @@ pub struct Config {
     /// peer is private and only used for testing right now.
     pub peers: Vec<u64>,
 
+    /// learners contains the IDs of all learner nodes (including self if the local
+    /// node is a learner) in the raft cluster. Learners only receive entries from
+    /// the leader node; they do not vote or promote themselves.
+    pub learners: Vec<u64>,
+
     /// ElectionTick is the number of node.tick invocations that must pass between
     /// elections. That is, if a follower does not receive any message from the
     /// leader of current term before ElectionTick has elapsed, it will become
@@ -182,10 +188,19 @@ pub struct Raft<T: Storage> {
 
     pub max_inflight: usize,
     pub max_msg_size: u64,
+    /// the progress of all the voters and learners, learners are marked by `is_learner`.
     pub prs: FlatMap<u64, Progress>,
 
     pub state: StateRole,
 
+    /// whether the local node is a learner.
+    pub is_learner: bool,
+
+    /// the voters of the outgoing configuration, it's empty unless in joint consensus.
+    pub voters_outgoing: HashSet<u64>,
+    /// the outgoing voters which will become learners when leaving joint consensus.
+    pub learners_next: HashSet<u64>,
+
     pub votes: FlatMap<u64, bool>,
 
     pub msgs: Vec<Message>,
@@ -264,23 +279,34 @@ pub fn quorum(total: usize) -> usize {
     total / 2 + 1
 }
 
+fn is_conf_change(e: &Entry) -> bool {
+    e.get_entry_type() == EntryType::EntryConfChange ||
+        e.get_entry_type() == EntryType::EntryConfChangeV2
+}
+
 impl<T: Storage> Raft<T> {
     pub fn new(c: &Config, store: T) -> Raft<T> {
         c.validate().expect("configuration is invalid");
         let rs = store.initial_state().expect("");
         let raft_log = RaftLog::new(store, c.tag.clone());
         let mut peers: &[u64] = &c.peers;
-        if !rs.conf_state.get_nodes().is_empty() {
-            if !peers.is_empty() {
+        let mut learners: &[u64] = &c.learners;
+        let mut voters_outgoing: &[u64] = &[];
+        let mut learners_next: &[u64] = &[];
+        if !rs.conf_state.get_nodes().is_empty() || !rs.conf_state.get_learners().is_empty() {
+            if !peers.is_empty() || !learners.is_empty() {
                 // TODO: the peers argument is always nil except in
                 // tests; the argument should be removed and these tests should be
                 // updated to specify their nodes through a snap
                 panic!(
-                    "{} cannot specify both new(peers) and ConfState.Nodes",
+                    "{} cannot specify both new(peers/learners) and ConfState.(Nodes/Learners)",
                     c.tag
                 )
             }
             peers = rs.conf_state.get_nodes();
+            learners = rs.conf_state.get_learners();
+            voters_outgoing = rs.conf_state.get_voters_outgoing();
+            learners_next = rs.conf_state.get_learners_next();
         }
         let mut r = Raft {
             id: c.id,
@@ -288,8 +314,11 @@ impl<T: Storage> Raft<T> {
             raft_log: raft_log,
             max_inflight: c.max_inflight_msgs,
             max_msg_size: c.max_size_per_msg,
-            prs: FlatMap::with_capacity(peers.len()),
+            prs: FlatMap::with_capacity(peers.len() + learners.len()),
             state: StateRole::Follower,
+            is_learner: false,
+            voters_outgoing: HashSet::default(),
+            learners_next: HashSet::default(),
             check_quorum: c.check_quorum,
             pre_vote: c.pre_vote,
             read_only: ReadOnly::new(c.read_only_option),
@@ -312,6 +341,27 @@ impl<T: Storage> Raft<T> {
         for p in peers {
             r.prs.insert(*p, new_progress(1, r.max_inflight));
         }
+        for p in learners {
+            if r.prs.contains_key(p) {
+                panic!("{} node {} is in both learner and peer list", r.tag, p);
+            }
+            let mut pr = new_progress(1, r.max_inflight);
+            pr.is_learner = true;
+            r.prs.insert(*p, pr);
+            if *p == r.id {
+                r.is_learner = true;
+            }
+        }
+        for p in voters_outgoing {
+            r.voters_outgoing.insert(*p);
+            if !r.prs.contains_key(p) {
+                // a voter being removed or demoted in joint consensus.
+                let mut pr = new_progress(1, r.max_inflight);
+                pr.is_learner = true;
+                r.prs.insert(*p, pr);
+            }
+        }
+        r.learners_next.extend(learners_next);
         if rs.hard_state != HardState::new() {
             r.load_state(rs.hard_state);
         }
@@ -379,7 +429,48 @@ impl<T: Storage> Raft<T> {
     }
 
     fn quorum(&self) -> usize {
-        quorum(self.prs.len())
+        quorum(self.prs.values().filter(|p| !p.is_learner).count())
+    }
+
+    // count_voters counts the voters accepted by `f`, it returns the count and total
+    // number of the voters in the incoming and outgoing configuration respectively.
+    fn count_voters<F>(&self, f: F) -> ((usize, usize), (usize, usize))
+    where
+        F: Fn(u64) -> bool,
+    {
+        let (mut incoming, mut incoming_total) = (0, 0);
+        for (id, _) in self.prs.iter().filter(|&(_, p)| !p.is_learner) {
+            incoming_total += 1;
+            if f(*id) {
+                incoming += 1;
+            }
+        }
+        let outgoing = self.voters_outgoing.iter().filter(|id| f(**id)).count();
+        (
+            (incoming, incoming_total),
+            (outgoing, self.voters_outgoing.len()),
+        )
+    }
+
+    // has_quorum returns true if the voters accepted by `f` form a quorum. In joint
+    // consensus they must form a quorum of both the incoming and outgoing configuration.
+    fn has_quorum<F: Fn(u64) -> bool>(&self, f: F) -> bool {
+        let ((incoming, incoming_total), (outgoing, outgoing_total)) = self.count_voters(f);
+        incoming >= quorum(incoming_total) &&
+            (outgoing_total == 0 || outgoing >= quorum(outgoing_total))
+    }
+
+    // is_quorum_lost returns true if the voters accepted by `f` form a quorum of either
+    // the incoming or outgoing configuration, so that the others can never form one.
+    fn is_quorum_lost<F: Fn(u64) -> bool>(&self, f: F) -> bool {
+        let ((incoming, incoming_total), (outgoing, outgoing_total)) = self.count_voters(f);
+        incoming >= quorum(incoming_total) ||
+            (outgoing_total > 0 && outgoing >= quorum(outgoing_total))
+    }
+
+    /// Returns true if the raft group is in joint consensus.
+    pub fn is_in_joint(&self) -> bool {
+        !self.voters_outgoing.is_empty()
     }
 
     // for testing leader lease
@@ -399,10 +490,53 @@ impl<T: Storage> Raft<T> {
         self.randomized_election_timeout
     }
 
+    /// Returns the ids of all the voters.
     pub fn nodes(&self) -> Vec<u64> {
         let mut nodes = Vec::with_capacity(self.prs.len());
-        nodes.extend(self.prs.keys());
+        nodes.extend(
+            self.prs
+                .iter()
+                .filter(|&(_, p)| !p.is_learner)
+                .map(|(id, _)| *id),
+        );
+        nodes.sort();
+        nodes
+    }
+
+    /// Returns the ids of all the learners, the outgoing voters are excluded.
+    pub fn learner_nodes(&self) -> Vec<u64> {
+        let mut nodes = Vec::with_capacity(self.prs.len());
+        nodes.extend(
+            self.prs
+                .iter()
+                .filter(|&(id, p)| p.is_learner && !self.voters_outgoing.contains(id))
+                .map(|(id, _)| *id),
+        );
+        nodes.sort();
+        nodes
+    }
+
+    /// Returns the ids of the voters of the outgoing configuration.
+    pub fn outgoing_nodes(&self) -> Vec<u64> {
+        let mut nodes: Vec<u64> = self.voters_outgoing.iter().cloned().collect();
+        nodes.sort();
+        nodes
+    }
+
+    /// Returns the ids of the outgoing voters which will become learners.
+    pub fn learners_next_nodes(&self) -> Vec<u64> {
+        let mut nodes: Vec<u64> = self.learners_next.iter().cloned().collect();
+        nodes.sort();
+        nodes
+    }
+
+    // voter_nodes returns the ids of the voters in both the incoming and outgoing
+    // configuration.
+    fn voter_nodes(&self) -> Vec<u64> {
+        let mut nodes = self.nodes();
+        nodes.extend(self.voters_outgoing.iter());
         nodes.sort();
+        nodes.dedup();
         nodes
     }
 
@@ -600,14 +734,11 @@ impl<T: Storage> Raft<T> {
     // the commit index changed (in which case the caller should call
     // r.bcast_append).
     pub fn maybe_commit(&mut self) -> bool {
-        // TODO: optimize
-        let mut mis = Vec::with_capacity(self.prs.len());
-        for p in self.prs.values() {
-            mis.push(p.matched);
-        }
-        // reverse sort
-        mis.sort_by(|a, b| b.cmp(a));
-        let mci = mis[self.quorum() - 1];
+        let mut mci = progress::quorum_matched(self.prs.values().filter(|p| !p.is_learner));
+        if self.is_in_joint() {
+            let outgoing = self.voters_outgoing.iter().filter_map(|id| self.prs.get(id));
+            mci = cmp::min(mci, progress::quorum_matched(outgoing));
+        }
         let term = self.term;
         self.raft_log.maybe_commit(mci, term)
     }
@@ -628,7 +759,9 @@ impl<T: Storage> Raft<T> {
         let (last_index, max_inflight) = (self.raft_log.last_index(), self.max_inflight);
         let self_id = self.id;
         for (id, p) in &mut self.prs {
+            let is_learner = p.is_learner;
             *p = new_progress(last_index + 1, max_inflight);
+            p.is_learner = is_learner;
             if id == &self_id {
                 p.matched = last_index;
             }
@@ -773,7 +906,7 @@ impl<T: Storage> Raft<T> {
 
     fn num_pending_conf(&self, ents: &[Entry]) -> usize {
         ents.into_iter()
-            .filter(|e| e.get_entry_type() == EntryType::EntryConfChange)
+            .filter(|e| is_conf_change(e))
             .count()
     }
 
@@ -787,7 +920,8 @@ impl<T: Storage> Raft<T> {
             (MessageType::MsgRequestVote, self.term)
         };
         let id = self.id;
-        if self.quorum() == self.poll(id, vote_resp_msg_type(vote_msg), true) {
+        self.poll(id, vote_resp_msg_type(vote_msg), true);
+        if self.has_quorum(|id| self.votes.get(&id) == Some(&true)) {
             // We won the election after voting for ourselves (which must mean that
             // this is a single-node cluster). Advance to the next state.
             if campaign_type == CAMPAIGN_PRE_ELECTION {
@@ -797,8 +931,8 @@ impl<T: Storage> Raft<T> {
             }
             return;
         }
-        let ids: Vec<_> = self.prs.keys().cloned().collect();
-        for id in ids {
+        // Learners don't vote, so there is no need to ask them.
+        for id in self.voter_nodes() {
             if id == self.id {
                 continue;
             }
@@ -952,7 +1086,9 @@ impl<T: Storage> Raft<T> {
 
 
         match m.get_msg_type() {
-            MessageType::MsgHup => if self.state != StateRole::Leader {
+            MessageType::MsgHup => if self.is_learner {
+                debug!("{} is learner and can not campaign, ignoring MsgHup", self.tag);
+            } else if self.state != StateRole::Leader {
                 let ents = self.raft_log
                     .slice(
                         self.raft_log.applied + 1,
@@ -985,6 +1121,24 @@ impl<T: Storage> Raft<T> {
                 debug!("{} ignoring MsgHup because already leader", self.tag);
             },
             MessageType::MsgRequestVote | MessageType::MsgRequestPreVote => {
+                if self.is_learner {
+                    // Learners never vote, otherwise a learner which is not counted
+                    // in the quorum may help a stale candidate win the election.
+                    info!(
+                        "{} [logterm: {}, index: {}, vote: {}] ignored {:?} from {} \
+                         [logterm: {}, index: {}] at term {}: learner can not vote",
+                        self.tag,
+                        self.raft_log.last_term(),
+                        self.raft_log.last_index(),
+                        self.vote,
+                        m.get_msg_type(),
+                        m.get_from(),
+                        m.get_log_term(),
+                        m.get_index(),
+                        self.term
+                    );
+                    return Ok(());
+                }
                 // The m.get_term() > self.term clause is for MsgRequestPreVote. For MsgRequestVote
                 // m.get_term() should always equal self.term
                 if (self.vote == INVALID_ID || m.get_term() > self.term ||
@@ -1156,6 +1310,14 @@ impl<T: Storage> Raft<T> {
             );
             return;
         }
+        if self.prs[&lead_transferee].is_learner {
+            debug!(
+                "{} ignored transferring leadership to learner {}",
+                self.tag,
+                lead_transferee
+            );
+            return;
+        }
         // Transfer leadership to third party.
         info!(
             "{} [term {}] starts to transfer leadership to {}",
@@ -1242,8 +1404,13 @@ impl<T: Storage> Raft<T> {
                     return;
                 }
 
-                let ack_count = self.read_only.recv_ack(m);
-                if ack_count < self.quorum() {
+                let mut acks = self.read_only.recv_ack(m);
+                if acks.is_empty() {
+                    return;
+                }
+                // include an ack from local node
+                acks.insert(self.id);
+                if !self.has_quorum(|id| acks.contains(&id)) {
                     return;
                 }
 
@@ -1334,7 +1501,7 @@ impl<T: Storage> Raft<T> {
                 }
 
                 for e in m.mut_entries().iter_mut() {
-                    if e.get_entry_type() == EntryType::EntryConfChange {
+                    if is_conf_change(e) {
                         if self.pending_conf {
                             info!(
                                 "propose conf {:?} ignored since pending unapplied \
@@ -1358,7 +1525,7 @@ impl<T: Storage> Raft<T> {
                     return;
                 }
 
-                if self.quorum() > 1 {
+                if self.quorum() > 1 || self.voters_outgoing.iter().any(|id| *id != self.id) {
                     // thinking: use an interally defined context instead of the user given context.
                     // We can express this in terms of the term and index instead of
                     // a user-supplied value.
@@ -1476,14 +1643,14 @@ impl<T: Storage> Raft<T> {
                     m.get_msg_type(),
                     self.votes.len() - gr
                 );
-                if self.quorum() == gr {
+                if self.has_quorum(|id| self.votes.get(&id) == Some(&true)) {
                     if self.state == StateRole::PreCandidate {
                         self.campaign(CAMPAIGN_ELECTION);
                     } else {
                         self.become_leader();
                         self.bcast_append();
                     }
-                } else if self.quorum() == self.votes.len() - gr {
+                } else if self.is_quorum_lost(|id| self.votes.get(&id) == Some(&false)) {
                     self.become_follower(term, INVALID_ID);
                 }
             }
@@ -1705,11 +1872,28 @@ impl<T: Storage> Raft<T> {
             meta.get_index(),
             meta.get_term()
         );
-        self.prs = FlatMap::with_capacity(meta.get_conf_state().get_nodes().len());
-        for &n in meta.get_conf_state().get_nodes() {
+        let cs = meta.get_conf_state();
+        let nodes = cs.get_nodes();
+        let learners = cs.get_learners();
+        self.prs = FlatMap::with_capacity(nodes.len() + learners.len());
+        self.voters_outgoing = cs.get_voters_outgoing().iter().cloned().collect();
+        self.learners_next = cs.get_learners_next().iter().cloned().collect();
+        // The outgoing voters which are not in the incoming configuration are kept
+        // as learners until leaving joint consensus.
+        let outgoing_only: Vec<u64> = cs.get_voters_outgoing()
+            .iter()
+            .filter(|n| !nodes.contains(*n))
+            .cloned()
+            .collect();
+        let all_nodes = nodes
+            .iter()
+            .map(|n| (*n, false))
+            .chain(learners.iter().map(|n| (*n, true)))
+            .chain(outgoing_only.into_iter().map(|n| (n, true)));
+        for (n, is_learner) in all_nodes {
             let next_idx = self.raft_log.last_index() + 1;
             let matched = if n == self.id { next_idx - 1 } else { 0 };
-            self.set_progress(n, matched, next_idx);
+            self.insert_progress(n, matched, next_idx, is_learner);
             info!(
                 "{} restored progress of {} [{:?}]",
                 self.tag,
@@ -1717,6 +1901,7 @@ impl<T: Storage> Raft<T> {
                 self.prs[&n]
             );
         }
+        self.is_learner = learners.contains(&self.id);
         None
     }
 
@@ -1738,20 +1923,47 @@ impl<T: Storage> Raft<T> {
     }
 
     // promotable indicates whether state machine can be promoted to leader,
-    // which is true when its own id is in progress list.
+    // which is true when it's a voter of either the incoming or outgoing configuration.
     pub fn promotable(&self) -> bool {
-        self.prs.contains_key(&self.id)
+        self.prs.get(&self.id).map_or(false, |p| !p.is_learner) ||
+            self.voters_outgoing.contains(&self.id)
     }
 
+    // add_node adds a voter, or promotes the learner to a voter if it exists.
     pub fn add_node(&mut self, id: u64) {
+        self.add_voter_or_learner(id, false);
+    }
+
+    pub fn add_learner(&mut self, id: u64) {
+        self.add_voter_or_learner(id, true);
+    }
+
+    fn add_voter_or_learner(&mut self, id: u64, is_learner: bool) {
         self.pending_conf = false;
-        if self.prs.contains_key(&id) {
+        if let Some(pr) = self.prs.get_mut(&id) {
+            if is_learner && !pr.is_learner {
+                // A voter can't be demoted to a learner directly.
+                info!(
+                    "{} ignored add learner: do not support changing {} from voter to learner",
+                    self.tag,
+                    id
+                );
+            } else if !is_learner && pr.is_learner {
+                info!("{} promoted learner {} to voter", self.tag, id);
+                pr.is_learner = false;
+                if id == self.id {
+                    self.is_learner = false;
+                }
+            }
             // Ignore any redundant addNode calls (which can happen because the
             // initial bootstrapping entries are applied twice).
             return;
         }
         let last_index = self.raft_log.last_index();
-        self.set_progress(id, 0, last_index + 1);
+        self.insert_progress(id, 0, last_index + 1, is_learner);
+        if id == self.id {
+            self.is_learner = is_learner;
+        }
     }
 
     pub fn remove_node(&mut self, id: u64) {
@@ -1774,13 +1986,131 @@ impl<T: Storage> Raft<T> {
         }
     }
 
+    /// Enters joint consensus by applying `changes` to the incoming configuration, the
+    /// current voters become the outgoing configuration. Voters being removed or
+    /// demoted keep their votes in the outgoing configuration until `leave_joint`.
+    pub fn enter_joint(&mut self, changes: &[ConfChangeSingle]) {
+        self.pending_conf = false;
+        if self.is_in_joint() {
+            warn!(
+                "{} ignored entering joint consensus: already in joint state with outgoing \
+                 voters {:?}",
+                self.tag,
+                self.outgoing_nodes()
+            );
+            return;
+        }
+        let outgoing: HashSet<u64> = self.nodes().into_iter().collect();
+        let last_index = self.raft_log.last_index();
+        for cc in changes {
+            let id = cc.get_node_id();
+            match cc.get_change_type() {
+                ConfChangeType::AddNode => {
+                    if self.prs.contains_key(&id) {
+                        self.prs.get_mut(&id).unwrap().is_learner = false;
+                    } else {
+                        self.insert_progress(id, 0, last_index + 1, false);
+                    }
+                    self.learners_next.remove(&id);
+                    if id == self.id {
+                        self.is_learner = false;
+                    }
+                }
+                ConfChangeType::AddLearnerNode => if outgoing.contains(&id) {
+                    // The demoted voter keeps voting in the outgoing configuration.
+                    self.prs.get_mut(&id).unwrap().is_learner = true;
+                    self.learners_next.insert(id);
+                } else if !self.prs.contains_key(&id) {
+                    self.insert_progress(id, 0, last_index + 1, true);
+                    if id == self.id {
+                        self.is_learner = true;
+                    }
+                },
+                ConfChangeType::RemoveNode => if outgoing.contains(&id) {
+                    // The removed voter keeps voting in the outgoing configuration.
+                    self.prs.get_mut(&id).unwrap().is_learner = true;
+                    self.learners_next.remove(&id);
+                } else {
+                    self.del_progress(id);
+                },
+            }
+        }
+        self.voters_outgoing = outgoing;
+        info!(
+            "{} entered joint consensus: incoming {:?}, outgoing {:?}, learners {:?}",
+            self.tag,
+            self.nodes(),
+            self.outgoing_nodes(),
+            self.learner_nodes()
+        );
+
+        // The quorum has changed, so see if any pending entries can be committed.
+        if self.state == StateRole::Leader && self.maybe_commit() {
+            self.bcast_append();
+        }
+    }
+
+    /// Leaves joint consensus, the outgoing voters which are not in the incoming
+    /// configuration are removed, or become learners if they are demoted.
+    pub fn leave_joint(&mut self) {
+        self.pending_conf = false;
+        if !self.is_in_joint() {
+            warn!("{} ignored leaving joint consensus: not in joint state", self.tag);
+            return;
+        }
+        let outgoing = mem::replace(&mut self.voters_outgoing, HashSet::default());
+        let learners_next = mem::replace(&mut self.learners_next, HashSet::default());
+        for id in outgoing {
+            if !self.prs.get(&id).map_or(false, |p| p.is_learner) {
+                continue;
+            }
+            if learners_next.contains(&id) {
+                if id == self.id {
+                    self.is_learner = true;
+                }
+            } else {
+                self.del_progress(id);
+            }
+        }
+        info!(
+            "{} left joint consensus: voters {:?}, learners {:?}",
+            self.tag,
+            self.nodes(),
+            self.learner_nodes()
+        );
+
+        if self.prs.is_empty() {
+            return;
+        }
+        if self.state == StateRole::Leader && self.maybe_commit() {
+            self.bcast_append();
+        }
+        // If the lead_transferee is not a voter any more, then abort the leadership transferring.
+        if self.state == StateRole::Leader {
+            if let Some(id) = self.lead_transferee {
+                if self.prs.get(&id).map_or(true, |p| p.is_learner) {
+                    self.abort_leader_transfer();
+                }
+            }
+        }
+    }
+
     pub fn reset_pending_conf(&mut self) {
         self.pending_conf = false;
     }
 
     pub fn set_progress(&mut self, id: u64, matched: u64, next_idx: u64) {
+        self.insert_progress(id, matched, next_idx, false);
+    }
+
+    pub fn set_learner_progress(&mut self, id: u64, matched: u64, next_idx: u64) {
+        self.insert_progress(id, matched, next_idx, true);
+    }
+
+    fn insert_progress(&mut self, id: u64, matched: u64, next_idx: u64, is_learner: bool) {
         let mut p = new_progress(next_idx, self.max_inflight);
         p.matched = matched;
+        p.is_learner = is_learner;
         self.prs.insert(id, p);
     }
 
@@ -1832,22 +2162,22 @@ impl<T: Storage> Raft<T> {
     // false.
     // check_quorum_active also resets all recent_active to false.
     fn check_quorum_active(&mut self) -> bool {
-        let mut act = 0;
+        let mut active = HashSet::default();
         let self_id = self.id;
         for (id, p) in &mut self.prs {
             if id == &self_id {
                 // self is always active
-                act += 1;
+                active.insert(*id);
                 continue;
             }
 
             if p.recent_active {
-                act += 1;
+                active.insert(*id);
             }
 
             p.recent_active = false;
         }
-        act >= self.quorum()
+        self.has_quorum(|id| active.contains(&id))
     }
 
     pub fn send_timeout_now(&mut self, to: u64) {
@@ -29,8 +29,8 @@
 use raft::errors::{Error, Result};
 use raft::Storage;
 use protobuf::{self, RepeatedField};
-use kvproto::eraftpb::{ConfChange, ConfChangeType, ConfState, Entry, EntryType, HardState,
-                       Message, MessageType, Snapshot};
+use kvproto::eraftpb::{ConfChange, ConfChangeType, ConfChangeV2, ConfState, Entry, EntryType,
+                       HardState, Message, MessageType, Snapshot};
 use raft::raft::{Config, Raft, SoftState, INVALID_ID};
 use raft::Status;
 use raft::read_only::ReadState;
@@ -268,10 +268,21 @@ impl<T: Storage> RawNode<T> {
     // ProposeConfChange proposes a config change.
     pub fn propose_conf_change(&mut self, cc: ConfChange) -> Result<()> {
         let data = box_try!(protobuf::Message::write_to_bytes(&cc));
+        self.propose_conf_change_entry(EntryType::EntryConfChange, data)
+    }
+
+    // ProposeConfChangeV2 proposes a joint consensus config change. An empty
+    // change list means leaving the joint state.
+    pub fn propose_conf_change_v2(&mut self, cc: ConfChangeV2) -> Result<()> {
+        let data = box_try!(protobuf::Message::write_to_bytes(&cc));
+        self.propose_conf_change_entry(EntryType::EntryConfChangeV2, data)
+    }
+
+    fn propose_conf_change_entry(&mut self, entry_type: EntryType, data: Vec<u8>) -> Result<()> {
         let mut m = Message::new();
         m.set_msg_type(MessageType::MsgPropose);
         let mut e = Entry::new();
-        e.set_entry_type(EntryType::EntryConfChange);
+        e.set_entry_type(entry_type);
         e.set_data(data);
         e.set_sync_log(true);
         m.set_entries(RepeatedField::from_vec(vec![e]));
@@ -281,18 +292,35 @@ impl<T: Storage> RawNode<T> {
     pub fn apply_conf_change(&mut self, cc: &ConfChange) -> ConfState {
         if cc.get_node_id() == INVALID_ID {
             self.raft.reset_pending_conf();
-            let mut cs = ConfState::new();
-            cs.set_nodes(self.raft.nodes());
-            return cs;
+            return self.conf_state();
         }
         let nid = cc.get_node_id();
         assert!(cc.has_change_type(), "unexpected conf type");
         match cc.get_change_type() {
             ConfChangeType::AddNode => self.raft.add_node(nid),
+            ConfChangeType::AddLearnerNode => self.raft.add_learner(nid),
             ConfChangeType::RemoveNode => self.raft.remove_node(nid),
         }
+        self.conf_state()
+    }
+
+    // ApplyConfChangeV2 enters joint consensus with the given changes, or leaves
+    // the joint state if there is no change.
+    pub fn apply_conf_change_v2(&mut self, cc: &ConfChangeV2) -> ConfState {
+        if cc.get_changes().is_empty() {
+            self.raft.leave_joint();
+        } else {
+            self.raft.enter_joint(cc.get_changes());
+        }
+        self.conf_state()
+    }
+
+    fn conf_state(&self) -> ConfState {
         let mut cs = ConfState::new();
         cs.set_nodes(self.raft.nodes());
+        cs.set_learners(self.raft.learner_nodes());
+        cs.set_voters_outgoing(self.raft.outgoing_nodes());
+        cs.set_learners_next(self.raft.learners_next_nodes());
         cs
     }
 
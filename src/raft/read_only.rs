@@ -104,14 +104,14 @@ impl ReadOnly {
 
     /// rev_ack notifies the ReadOnly struct that the raft state machine received
     /// an acknowledgment of the heartbeat that attached with the read only request
-    /// context.
-    pub fn recv_ack(&mut self, m: &Message) -> usize {
+    /// context. It returns the nodes that have acknowledged the request, excluding
+    /// the local node.
+    pub fn recv_ack(&mut self, m: &Message) -> HashSet<u64> {
         match self.pending_read_index.get_mut(m.get_context()) {
-            None => 0,
+            None => HashSet::default(),
             Some(rs) => {
                 rs.acks.insert(m.get_from());
-                // add one to include an ack from local node
-                rs.acks.len() + 1
+                rs.acks.clone()
             }
         }
     }
@@ -0,0 +1,107 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use super::{LogBatch, RaftEngine, Result};
+
+// The number of entries copied in a batch.
+const CONVERT_BATCH_SIZE: u64 = 1024;
+
+/// Copies the raft logs and raft states of all the regions from `src` to `dst`, the data of
+/// the regions in `dst` is replaced. Returns the number of the copied regions. Both engines
+/// must not be written by the store during the copy.
+pub fn copy_raft_logs(src: &RaftEngine, dst: &RaftEngine) -> Result<usize> {
+    let regions = try!(src.raft_groups());
+    let mut batch = LogBatch::new();
+    for &region_id in &regions {
+        batch.clean(region_id);
+        if let Some((first, last)) = try!(src.entry_range(region_id)) {
+            let mut low = first;
+            while low <= last {
+                let high = if last - low < CONVERT_BATCH_SIZE {
+                    last + 1
+                } else {
+                    low + CONVERT_BATCH_SIZE
+                };
+                let mut entries = Vec::with_capacity((high - low) as usize);
+                try!(src.fetch_entries_to(region_id, low, high, None, &mut entries));
+                batch.add_entries(region_id, low - 1, entries);
+                try!(dst.consume(&mut batch, false));
+                low = high;
+            }
+        }
+        // The raft state is written last, so a region is copied completely if it has one.
+        if let Some(state) = try!(src.get_raft_state(region_id)) {
+            batch.put_raft_state(region_id, &state);
+        }
+        try!(dst.consume(&mut batch, true));
+    }
+    Ok(regions.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use kvproto::eraftpb::Entry;
+    use kvproto::raft_serverpb::RaftLocalState;
+    use tempdir::TempDir;
+
+    use storage::CF_DEFAULT;
+    use util::rocksdb::new_engine;
+    use super::*;
+    use super::super::{Config, RaftLogEngine};
+
+    fn new_entry(index: u64) -> Entry {
+        let mut e = Entry::new();
+        e.set_index(index);
+        e.set_term(index);
+        e
+    }
+
+    #[test]
+    fn test_copy_raft_logs() {
+        let dir = TempDir::new("test_copy_raft_logs").unwrap();
+        let src = new_engine(dir.path().join("src").to_str().unwrap(), &[CF_DEFAULT]).unwrap();
+        let mut batch = LogBatch::new();
+        for region_id in 1..4 {
+            let last = region_id * CONVERT_BATCH_SIZE;
+            batch.add_entries(region_id, 0, (5..last + 1).map(new_entry).collect());
+            let mut state = RaftLocalState::new();
+            state.set_last_index(last);
+            batch.put_raft_state(region_id, &state);
+        }
+        src.consume(&mut batch, true).unwrap();
+
+        let cfg = Config {
+            dir: dir.path().join("log").to_str().unwrap().to_owned(),
+            ..Config::default()
+        };
+        let log_engine = RaftLogEngine::open(&cfg).unwrap();
+        assert_eq!(copy_raft_logs(&src, &log_engine).unwrap(), 3);
+        let dst = new_engine(dir.path().join("dst").to_str().unwrap(), &[CF_DEFAULT]).unwrap();
+        assert_eq!(copy_raft_logs(&log_engine, &dst).unwrap(), 3);
+
+        assert_eq!(dst.raft_groups().unwrap(), vec![1, 2, 3]);
+        for region_id in 1..4 {
+            let last = region_id * CONVERT_BATCH_SIZE;
+            assert_eq!(
+                dst.get_raft_state(region_id).unwrap(),
+                src.get_raft_state(region_id).unwrap()
+            );
+            assert_eq!(dst.entry_range(region_id).unwrap(), Some((5, last)));
+            let mut entries = vec![];
+            dst.fetch_entries_to(region_id, 5, last + 1, None, &mut entries)
+                .unwrap();
+            assert_eq!(entries, (5..last + 1).map(new_entry).collect::<Vec<_>>());
+        }
+    }
+}
@@ -0,0 +1,506 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use std::cmp;
+use std::path::Path;
+use std::sync::{Mutex, RwLock};
+
+use kvproto::eraftpb::Entry;
+use kvproto::raft_serverpb::RaftLocalState;
+use protobuf::Message;
+
+use util::codec::number::{NumberDecoder, NumberEncoder};
+use util::collections::HashMap;
+use util::config::ReadableSize;
+use super::memtable::{EntryIndex, MemTable};
+use super::pipe::{self, Pipe};
+use super::{Error, LogBatch, LogItem, RaftEngine, Result};
+
+const TAG_ENTRIES: u8 = 1;
+const TAG_RAFT_STATE: u8 = 2;
+const TAG_COMPACT: u8 = 3;
+const TAG_CLEAN: u8 = 4;
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    /// Stores the raft logs in the raft log engine instead of raftdb.
+    pub enable: bool,
+    pub dir: String,
+    /// A new log file is created after the active one exceeds the size.
+    pub target_file_size: ReadableSize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            enable: false,
+            dir: String::new(),
+            target_file_size: ReadableSize::mb(128),
+        }
+    }
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<()> {
+        if self.target_file_size.0 == 0 {
+            return Err(box_err!("raft engine target file size should be greater than 0."));
+        }
+        Ok(())
+    }
+}
+
+// The changes of a record to the memtables, the offsets of the entries are relative to the
+// payload of the record.
+enum Update {
+    Entries(u64, Vec<EntryIndex>),
+    RaftState(u64, RaftLocalState),
+    Compact(u64, u64),
+    Clean(u64),
+}
+
+fn encode_items(items: Vec<LogItem>) -> Result<(Vec<u8>, Vec<Update>)> {
+    let mut buf = vec![];
+    let mut updates = Vec::with_capacity(items.len());
+    for item in items {
+        match item {
+            LogItem::Entries { region_id, entries, .. } => {
+                buf.push(TAG_ENTRIES);
+                try!(buf.encode_u64_le(region_id));
+                try!(buf.encode_u32_le(entries.len() as u32));
+                let mut indexes = Vec::with_capacity(entries.len());
+                for e in &entries {
+                    let data = try!(e.write_to_bytes());
+                    try!(buf.encode_u64_le(e.get_index()));
+                    try!(buf.encode_u32_le(data.len() as u32));
+                    indexes.push(EntryIndex {
+                        index: e.get_index(),
+                        file_num: 0,
+                        offset: buf.len() as u64,
+                        len: data.len() as u64,
+                    });
+                    buf.extend_from_slice(&data);
+                }
+                updates.push(Update::Entries(region_id, indexes));
+            }
+            LogItem::RaftState { region_id, state } => {
+                let data = try!(state.write_to_bytes());
+                buf.push(TAG_RAFT_STATE);
+                try!(buf.encode_u64_le(region_id));
+                try!(buf.encode_u32_le(data.len() as u32));
+                buf.extend_from_slice(&data);
+                updates.push(Update::RaftState(region_id, state));
+            }
+            LogItem::Compact { region_id, index, .. } => {
+                buf.push(TAG_COMPACT);
+                try!(buf.encode_u64_le(region_id));
+                try!(buf.encode_u64_le(index));
+                updates.push(Update::Compact(region_id, index));
+            }
+            LogItem::Clean { region_id } => {
+                buf.push(TAG_CLEAN);
+                try!(buf.encode_u64_le(region_id));
+                updates.push(Update::Clean(region_id));
+            }
+        }
+    }
+    Ok((buf, updates))
+}
+
+fn decode_updates(payload: &[u8]) -> Result<Vec<Update>> {
+    let mut updates = vec![];
+    let mut buf = payload;
+    while !buf.is_empty() {
+        let tag = buf[0];
+        buf = &buf[1..];
+        let region_id = try!(buf.decode_u64_le());
+        match tag {
+            TAG_ENTRIES => {
+                let count = try!(buf.decode_u32_le());
+                let mut indexes = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    let index = try!(buf.decode_u64_le());
+                    let len = try!(buf.decode_u32_le()) as usize;
+                    if buf.len() < len {
+                        return Err(Error::Corruption(format!("entry {} is truncated", index)));
+                    }
+                    indexes.push(EntryIndex {
+                        index: index,
+                        file_num: 0,
+                        offset: (payload.len() - buf.len()) as u64,
+                        len: len as u64,
+                    });
+                    buf = &buf[len..];
+                }
+                updates.push(Update::Entries(region_id, indexes));
+            }
+            TAG_RAFT_STATE => {
+                let len = try!(buf.decode_u32_le()) as usize;
+                if buf.len() < len {
+                    return Err(Error::Corruption(format!(
+                        "raft state of region {} is truncated",
+                        region_id
+                    )));
+                }
+                let mut state = RaftLocalState::new();
+                try!(state.merge_from_bytes(&buf[..len]));
+                buf = &buf[len..];
+                updates.push(Update::RaftState(region_id, state));
+            }
+            TAG_COMPACT => {
+                let index = try!(buf.decode_u64_le());
+                updates.push(Update::Compact(region_id, index));
+            }
+            TAG_CLEAN => updates.push(Update::Clean(region_id)),
+            _ => return Err(Error::Corruption(format!("unknown tag {}", tag))),
+        }
+    }
+    Ok(updates)
+}
+
+// Applies the updates of the record at `offset` of the file to the memtables.
+fn apply_updates(
+    memtables: &mut HashMap<u64, MemTable>,
+    updates: Vec<Update>,
+    file_num: u64,
+    offset: u64,
+) {
+    for update in updates {
+        match update {
+            Update::Entries(region_id, mut indexes) => {
+                for ei in &mut indexes {
+                    ei.file_num = file_num;
+                    ei.offset += offset;
+                }
+                memtables
+                    .entry(region_id)
+                    .or_insert_with(MemTable::default)
+                    .append(indexes);
+            }
+            Update::RaftState(region_id, state) => {
+                memtables
+                    .entry(region_id)
+                    .or_insert_with(MemTable::default)
+                    .set_state(state, file_num);
+            }
+            Update::Compact(region_id, index) => {
+                if let Some(memtable) = memtables.get_mut(&region_id) {
+                    memtable.compact_to(index);
+                }
+            }
+            Update::Clean(region_id) => {
+                memtables.remove(&region_id);
+            }
+        }
+    }
+}
+
+/// A raft log engine appending the batches of all the regions to the log files in `dir`. The
+/// entries are read from the files by the index in memory, which is rebuilt by replaying the
+/// files on open.
+pub struct RaftLogEngine {
+    pipe: RwLock<Pipe>,
+    memtables: RwLock<HashMap<u64, MemTable>>,
+    // The end of the synced data, the writes before it are synced by a previous fsync.
+    synced: Mutex<(u64, u64)>,
+}
+
+impl RaftLogEngine {
+    pub fn open(cfg: &Config) -> Result<RaftLogEngine> {
+        let mut memtables = HashMap::default();
+        let pipe = try!(Pipe::open(
+            Path::new(&cfg.dir),
+            cfg.target_file_size.0,
+            |file_num, offset, payload| {
+                let updates = try!(decode_updates(payload));
+                apply_updates(&mut memtables, updates, file_num, offset);
+                Ok(())
+            }
+        ));
+        info!(
+            "raft log engine recovers {} regions from files [{}, {}]",
+            memtables.len(),
+            pipe.first_file_num(),
+            pipe.active_file_num()
+        );
+        let synced = (pipe.active_file_num(), pipe.active_size());
+        Ok(RaftLogEngine {
+            pipe: RwLock::new(pipe),
+            memtables: RwLock::new(memtables),
+            synced: Mutex::new(synced),
+        })
+    }
+
+    // Appends the record, returns the end of it.
+    fn append(&self, pipe: &mut Pipe, payload: &[u8], updates: Vec<Update>) -> Result<(u64, u64)> {
+        let (file_num, offset) = try!(pipe.append(payload));
+        apply_updates(
+            &mut self.memtables.write().unwrap(),
+            updates,
+            file_num,
+            offset,
+        );
+        Ok((file_num, pipe.active_size()))
+    }
+
+    // Syncs the active file if the data before `(file_num, size)` isn't synced yet, the
+    // concurrent writes are synced together by the first of them.
+    fn sync_to(&self, file_num: u64, size: u64) -> Result<()> {
+        let mut synced = self.synced.lock().unwrap();
+        if *synced >= (file_num, size) {
+            return Ok(());
+        }
+        let (active_num, active_size, file) = {
+            let pipe = self.pipe.read().unwrap();
+            (pipe.active_file_num(), pipe.active_size(), pipe.active_file())
+        };
+        try!(file.sync_data());
+        *synced = (active_num, active_size);
+        Ok(())
+    }
+
+    fn read_entry(&self, region_id: u64, ei: &EntryIndex) -> Result<Entry> {
+        let file = match self.pipe.read().unwrap().file(ei.file_num) {
+            Some(file) => file,
+            None => return Err(Error::EntryNotFound(region_id, ei.index)),
+        };
+        let data = try!(pipe::read_at(&file, ei.offset, ei.len as usize));
+        let mut entry = Entry::new();
+        try!(entry.merge_from_bytes(&data));
+        Ok(entry)
+    }
+}
+
+impl RaftEngine for RaftLogEngine {
+    fn get_raft_state(&self, region_id: u64) -> Result<Option<RaftLocalState>> {
+        let memtables = self.memtables.read().unwrap();
+        Ok(memtables.get(&region_id).and_then(|m| m.state().cloned()))
+    }
+
+    fn get_entry(&self, region_id: u64, index: u64) -> Result<Option<Entry>> {
+        let ei = match self.memtables
+            .read()
+            .unwrap()
+            .get(&region_id)
+            .and_then(|m| m.get(index))
+        {
+            Some(ei) => ei,
+            None => return Ok(None),
+        };
+        self.read_entry(region_id, &ei).map(Some)
+    }
+
+    fn fetch_entries_to(
+        &self,
+        region_id: u64,
+        low: u64,
+        high: u64,
+        max_size: Option<usize>,
+        buf: &mut Vec<Entry>,
+    ) -> Result<usize> {
+        let max_size = max_size.unwrap_or(usize::max_value());
+        let mut indexes = Vec::with_capacity((high - low) as usize);
+        {
+            let memtables = self.memtables.read().unwrap();
+            let memtable = match memtables.get(&region_id) {
+                Some(m) => m,
+                None => return Err(Error::EntryNotFound(region_id, low)),
+            };
+            let mut total_size = 0;
+            for index in low..high {
+                let ei = match memtable.get(index) {
+                    Some(ei) => ei,
+                    None => return Err(Error::EntryNotFound(region_id, index)),
+                };
+                if index != low && total_size + ei.len as usize > max_size {
+                    break;
+                }
+                total_size += ei.len as usize;
+                indexes.push(ei);
+            }
+        }
+
+        let mut total_size = 0;
+        for ei in &indexes {
+            buf.push(try!(self.read_entry(region_id, ei)));
+            total_size += ei.len as usize;
+        }
+        Ok(total_size)
+    }
+
+    fn entry_range(&self, region_id: u64) -> Result<Option<(u64, u64)>> {
+        let memtables = self.memtables.read().unwrap();
+        Ok(memtables.get(&region_id).and_then(|m| m.entry_range()))
+    }
+
+    fn raft_groups(&self) -> Result<Vec<u64>> {
+        let memtables = self.memtables.read().unwrap();
+        let mut groups: Vec<u64> = memtables
+            .iter()
+            .filter(|&(_, m)| !m.is_empty())
+            .map(|(&region_id, _)| region_id)
+            .collect();
+        groups.sort();
+        Ok(groups)
+    }
+
+    fn consume(&self, batch: &mut LogBatch, sync: bool) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let (payload, updates) = try!(encode_items(batch.take_items()));
+        let (file_num, size) = {
+            let mut pipe = self.pipe.write().unwrap();
+            try!(self.append(&mut pipe, &payload, updates))
+        };
+        if sync {
+            try!(self.sync_to(file_num, size));
+        }
+        Ok(())
+    }
+
+    // Deletes the files before the first entries of all the regions, the raft states in them
+    // are rewritten to the active file first.
+    fn purge_expired_files(&self) -> Result<usize> {
+        let mut pipe = self.pipe.write().unwrap();
+        let mut states = vec![];
+        let purge_to = {
+            let memtables = self.memtables.read().unwrap();
+            let purge_to = memtables
+                .values()
+                .filter_map(|m| m.min_file_num())
+                .fold(pipe.active_file_num(), cmp::min);
+            if purge_to <= pipe.first_file_num() {
+                return Ok(0);
+            }
+            for (&region_id, memtable) in memtables.iter() {
+                if memtable.state_file_num() >= purge_to {
+                    continue;
+                }
+                if let Some(state) = memtable.state() {
+                    states.push(LogItem::RaftState {
+                        region_id: region_id,
+                        state: state.clone(),
+                    });
+                }
+            }
+            purge_to
+        };
+
+        if !states.is_empty() {
+            let (payload, updates) = try!(encode_items(states));
+            try!(self.append(&mut pipe, &payload, updates));
+            try!(pipe.active_file().sync_data());
+        }
+        let count = try!(pipe.purge_to(purge_to));
+        info!("purge {} raft log files before {}", count, purge_to);
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn new_entry(index: u64, term: u64) -> Entry {
+        let mut e = Entry::new();
+        e.set_index(index);
+        e.set_term(term);
+        e.set_data(vec![b'x'; 100]);
+        e
+    }
+
+    fn new_engine(dir: &TempDir) -> RaftLogEngine {
+        let cfg = Config {
+            dir: dir.path().to_str().unwrap().to_owned(),
+            // A file holds about 10 entries.
+            target_file_size: ReadableSize(1024),
+            ..Config::default()
+        };
+        RaftLogEngine::open(&cfg).unwrap()
+    }
+
+    fn must_fetch_entries(engine: &RaftLogEngine, region_id: u64, low: u64, high: u64) {
+        let mut entries = vec![];
+        engine
+            .fetch_entries_to(region_id, low, high, None, &mut entries)
+            .unwrap();
+        assert_eq!(entries.len() as u64, high - low);
+        for (i, e) in (low..high).zip(&entries) {
+            assert_eq!(e.get_index(), i);
+        }
+    }
+
+    #[test]
+    fn test_raft_log_engine() {
+        let dir = TempDir::new("test_raft_log_engine").unwrap();
+        let engine = new_engine(&dir);
+
+        let mut state = RaftLocalState::new();
+        state.set_last_index(30);
+        let mut batch = LogBatch::new();
+        batch.put_raft_state(1, &state);
+        batch.put_raft_state(2, &state);
+        for i in 1..31 {
+            batch.add_entries(1, i - 1, vec![new_entry(i, 1)]);
+            batch.add_entries(2, i - 1, vec![new_entry(i, 1)]);
+            engine.consume(&mut batch, i % 10 == 0).unwrap();
+        }
+        assert_eq!(engine.raft_groups().unwrap(), vec![1, 2]);
+        assert_eq!(engine.entry_range(1).unwrap(), Some((1, 30)));
+        assert_eq!(engine.get_entry(2, 15).unwrap(), Some(new_entry(15, 1)));
+        assert_eq!(engine.get_entry(2, 31).unwrap(), None);
+        must_fetch_entries(&engine, 1, 1, 31);
+        let mut entries = vec![];
+        engine
+            .fetch_entries_to(1, 1, 31, Some(0), &mut entries)
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+
+        // The conflicting entries are replaced.
+        batch.add_entries(1, 30, (21..26).map(|i| new_entry(i, 2)).collect());
+        engine.consume(&mut batch, false).unwrap();
+        assert_eq!(engine.entry_range(1).unwrap(), Some((1, 25)));
+        assert_eq!(engine.get_entry(1, 25).unwrap(), Some(new_entry(25, 2)));
+
+        // Nothing is purged until both regions are compacted.
+        batch.compact_to(1, 1, 25);
+        engine.consume(&mut batch, false).unwrap();
+        assert_eq!(engine.purge_expired_files().unwrap(), 0);
+        batch.compact_to(2, 0, 25);
+        engine.consume(&mut batch, false).unwrap();
+        assert!(engine.purge_expired_files().unwrap() > 0);
+        assert!(engine.fetch_entries_to(1, 1, 25, None, &mut entries).is_err());
+        must_fetch_entries(&engine, 1, 25, 26);
+        must_fetch_entries(&engine, 2, 25, 31);
+        // The raft states in the purged files are rewritten.
+        assert_eq!(engine.get_raft_state(1).unwrap(), Some(state.clone()));
+
+        batch.clean(2);
+        engine.consume(&mut batch, true).unwrap();
+        assert_eq!(engine.raft_groups().unwrap(), vec![1]);
+        drop(engine);
+
+        // The index is recovered from the files.
+        let engine = new_engine(&dir);
+        assert_eq!(engine.raft_groups().unwrap(), vec![1]);
+        assert_eq!(engine.get_raft_state(1).unwrap(), Some(state));
+        assert_eq!(engine.entry_range(1).unwrap(), Some((25, 25)));
+        assert_eq!(engine.get_entry(1, 25).unwrap(), Some(new_entry(25, 2)));
+        assert_eq!(engine.get_raft_state(2).unwrap(), None);
+    }
+}
@@ -0,0 +1,148 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use std::collections::VecDeque;
+
+use kvproto::raft_serverpb::RaftLocalState;
+
+/// The location of an entry in the log files.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EntryIndex {
+    pub index: u64,
+    pub file_num: u64,
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// The in-memory index of the raft logs of a region.
+#[derive(Debug, Default)]
+pub struct MemTable {
+    entries: VecDeque<EntryIndex>,
+    state: Option<RaftLocalState>,
+    // The file of the raft state, the state is rewritten before the file is purged.
+    state_file_num: u64,
+}
+
+impl MemTable {
+    /// Appends the continuous entries, the entries at or after the first of them are replaced.
+    pub fn append(&mut self, entries: Vec<EntryIndex>) {
+        let first = match entries.first() {
+            Some(e) => e.index,
+            None => return,
+        };
+        while self.entries.back().map_or(false, |e| e.index >= first) {
+            self.entries.pop_back();
+        }
+        // The entries before are stale if they can't be continued, e.g. after a snapshot.
+        if self.entries.back().map_or(false, |e| e.index + 1 != first) {
+            self.entries.clear();
+        }
+        self.entries.extend(entries);
+    }
+
+    /// Removes the entries before `index`, returns the number of the removed entries.
+    pub fn compact_to(&mut self, index: u64) -> usize {
+        let mut count = 0;
+        while self.entries.front().map_or(false, |e| e.index < index) {
+            self.entries.pop_front();
+            count += 1;
+        }
+        count
+    }
+
+    pub fn get(&self, index: u64) -> Option<EntryIndex> {
+        let first = match self.entries.front() {
+            Some(e) => e.index,
+            None => return None,
+        };
+        if index < first {
+            return None;
+        }
+        self.entries.get((index - first) as usize).cloned()
+    }
+
+    pub fn entry_range(&self) -> Option<(u64, u64)> {
+        match (self.entries.front(), self.entries.back()) {
+            (Some(first), Some(last)) => Some((first.index, last.index)),
+            _ => None,
+        }
+    }
+
+    /// Returns the first file with the entries of the region.
+    pub fn min_file_num(&self) -> Option<u64> {
+        self.entries.front().map(|e| e.file_num)
+    }
+
+    pub fn set_state(&mut self, state: RaftLocalState, file_num: u64) {
+        self.state = Some(state);
+        self.state_file_num = file_num;
+    }
+
+    pub fn state(&self) -> Option<&RaftLocalState> {
+        self.state.as_ref()
+    }
+
+    pub fn state_file_num(&self) -> u64 {
+        self.state_file_num
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty() && self.state.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_entry_indexes(low: u64, high: u64, file_num: u64) -> Vec<EntryIndex> {
+        (low..high)
+            .map(|i| {
+                EntryIndex {
+                    index: i,
+                    file_num: file_num,
+                    offset: i * 10,
+                    len: 10,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_memtable() {
+        let mut memtable = MemTable::default();
+        assert!(memtable.is_empty());
+        memtable.append(new_entry_indexes(1, 11, 1));
+        assert_eq!(memtable.entry_range(), Some((1, 10)));
+        assert_eq!(memtable.get(5).unwrap().offset, 50);
+        assert_eq!(memtable.get(11), None);
+
+        // The conflicting entries are replaced.
+        memtable.append(new_entry_indexes(6, 8, 2));
+        assert_eq!(memtable.entry_range(), Some((1, 7)));
+        assert_eq!(memtable.get(6).unwrap().file_num, 2);
+        assert_eq!(memtable.min_file_num(), Some(1));
+
+        assert_eq!(memtable.compact_to(3), 2);
+        assert_eq!(memtable.get(2), None);
+        assert_eq!(memtable.entry_range(), Some((3, 7)));
+
+        // The entries after a gap start over.
+        memtable.append(new_entry_indexes(20, 22, 3));
+        assert_eq!(memtable.entry_range(), Some((20, 21)));
+        assert_eq!(memtable.min_file_num(), Some(3));
+        assert_eq!(memtable.compact_to(30), 2);
+        assert!(memtable.is_empty());
+    }
+}
@@ -0,0 +1,197 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! The storage of the raft logs and raft states of the regions.
+//!
+//! `RaftEngine` is implemented by the raft RocksDB, which is the default, and by
+//! `RaftLogEngine`, an append-only engine built for the raft logs. The logs of all the regions
+//! are appended to segmented log files, and an in-memory index per region locates the entries
+//! in the files, so appending doesn't pay for the memtables and compactions of RocksDB. The
+//! fsyncs of the concurrent writes are batched. A file is purged after the entries in it are
+//! compacted by all the regions, and the files are replayed to rebuild the index on restart.
+//! The raft log engine is used by the store if `raft-engine.enable` is true.
+//!
+//! `copy_raft_logs` converts the raft logs between the engines while the store is stopped.
+
+mod convert;
+mod engine;
+mod errors;
+mod memtable;
+mod pipe;
+mod rocks;
+
+use std::cmp;
+
+use kvproto::eraftpb::Entry;
+use kvproto::raft_serverpb::RaftLocalState;
+
+pub use self::convert::copy_raft_logs;
+pub use self::engine::{Config, RaftLogEngine};
+pub use self::errors::{Error, Result};
+
+#[derive(Debug, PartialEq)]
+pub enum LogItem {
+    // Appends the entries, the entries after them up to `prev_last_index` are removed.
+    Entries {
+        region_id: u64,
+        prev_last_index: u64,
+        entries: Vec<Entry>,
+    },
+    RaftState {
+        region_id: u64,
+        state: RaftLocalState,
+    },
+    // Removes the entries in `[first_index, index)`, `first_index` is 0 if it's unknown.
+    Compact {
+        region_id: u64,
+        first_index: u64,
+        index: u64,
+    },
+    // Removes all the entries and the raft state.
+    Clean { region_id: u64 },
+}
+
+impl LogItem {
+    pub fn region_id(&self) -> u64 {
+        match *self {
+            LogItem::Entries { region_id, .. } |
+            LogItem::RaftState { region_id, .. } |
+            LogItem::Compact { region_id, .. } |
+            LogItem::Clean { region_id } => region_id,
+        }
+    }
+}
+
+/// The changes to the raft logs of the regions, written atomically by `RaftEngine::consume`.
+#[derive(Debug, Default)]
+pub struct LogBatch {
+    items: Vec<LogItem>,
+}
+
+impl LogBatch {
+    pub fn new() -> LogBatch {
+        LogBatch::default()
+    }
+
+    /// Appends the continuous entries, the entries at or after the first of them are replaced.
+    /// `prev_last_index` is the last index of the region before the append, so the engines
+    /// that can't look up the entries cheaply know which ones to remove.
+    pub fn add_entries(&mut self, region_id: u64, prev_last_index: u64, entries: Vec<Entry>) {
+        let first = match entries.first() {
+            Some(e) => e.get_index(),
+            None => return,
+        };
+        let mut prev_last_index = prev_last_index;
+        for item in &mut self.items {
+            if let LogItem::Entries {
+                region_id: ref id,
+                prev_last_index: ref last,
+                entries: ref mut es,
+            } = *item
+            {
+                if *id == region_id {
+                    // The earlier entries may be dropped here, so the ones they replace are
+                    // removed by this append.
+                    prev_last_index = cmp::max(prev_last_index, *last);
+                    es.retain(|e| e.get_index() < first);
+                }
+            }
+        }
+        self.items.push(LogItem::Entries {
+            region_id: region_id,
+            prev_last_index: prev_last_index,
+            entries: entries,
+        });
+    }
+
+    pub fn put_raft_state(&mut self, region_id: u64, state: &RaftLocalState) {
+        self.items.push(LogItem::RaftState {
+            region_id: region_id,
+            state: state.clone(),
+        });
+    }
+
+    /// Removes the entries before `index`. `first_index` is the first index of the region if
+    /// it's known, or 0.
+    pub fn compact_to(&mut self, region_id: u64, first_index: u64, index: u64) {
+        for item in &mut self.items {
+            if let LogItem::Entries {
+                region_id: ref id,
+                entries: ref mut es,
+            } = *item
+            {
+                if *id == region_id {
+                    es.retain(|e| e.get_index() >= index);
+                }
+            }
+        }
+        self.items.push(LogItem::Compact {
+            region_id: region_id,
+            first_index: first_index,
+            index: index,
+        });
+    }
+
+    /// Removes all the entries and the raft state of the region, e.g. when it's destroyed.
+    pub fn clean(&mut self, region_id: u64) {
+        self.items.retain(|item| item.region_id() != region_id);
+        self.items.push(LogItem::Clean {
+            region_id: region_id,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn items(&self) -> &[LogItem] {
+        &self.items
+    }
+
+    fn take_items(&mut self) -> Vec<LogItem> {
+        self.items.drain(..).collect()
+    }
+}
+
+pub trait RaftEngine: Send + Sync {
+    fn get_raft_state(&self, region_id: u64) -> Result<Option<RaftLocalState>>;
+
+    fn get_entry(&self, region_id: u64, index: u64) -> Result<Option<Entry>>;
+
+    /// Fetches the entries in `[low, high)` to `buf` and returns their total size. It stops
+    /// before the size exceeds `max_size`, but at least one entry is fetched.
+    fn fetch_entries_to(
+        &self,
+        region_id: u64,
+        low: u64,
+        high: u64,
+        max_size: Option<usize>,
+        buf: &mut Vec<Entry>,
+    ) -> Result<usize>;
+
+    /// Returns the indexes of the first and the last entries of the region.
+    fn entry_range(&self, region_id: u64) -> Result<Option<(u64, u64)>>;
+
+    /// Returns the regions with a raft state or entries.
+    fn raft_groups(&self) -> Result<Vec<u64>>;
+
+    /// Writes the batch and clears it, the write is synced to disk if `sync` is true.
+    fn consume(&self, batch: &mut LogBatch, sync: bool) -> Result<()>;
+
+    /// Deletes the files holding only the compacted entries, returns the number of the deleted
+    /// files. RocksDB removes the deleted entries by compactions, so it deletes nothing here.
+    fn purge_expired_files(&self) -> Result<usize> {
+        Ok(0)
+    }
+}
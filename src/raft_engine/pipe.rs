@@ -0,0 +1,319 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crc::crc32;
+
+use util::codec::number::{NumberDecoder, NumberEncoder};
+use super::{Error, Result};
+
+const LOG_SUFFIX: &'static str = ".raftlog";
+const FILE_MAGIC: &'static [u8] = b"RAFTLOG1";
+// A record is the length and the crc32 of the payload, followed by the payload.
+const RECORD_HEADER_LEN: usize = 8;
+
+fn file_name(num: u64) -> String {
+    format!("{:016}{}", num, LOG_SUFFIX)
+}
+
+fn parse_file_num(name: &str) -> Option<u64> {
+    if !name.ends_with(LOG_SUFFIX) {
+        return None;
+    }
+    name[..name.len() - LOG_SUFFIX.len()].parse().ok()
+}
+
+// Syncs the directory, so the files created or deleted in it are kept after a crash.
+fn sync_dir(dir: &Path) -> Result<()> {
+    try!(try!(File::open(dir)).sync_all());
+    Ok(())
+}
+
+// Reads the records in the content of a file, returns the length of the valid part.
+fn read_records<F>(num: u64, content: &[u8], f: &mut F) -> Result<usize>
+where
+    F: FnMut(u64, u64, &[u8]) -> Result<()>,
+{
+    if !content.starts_with(FILE_MAGIC) {
+        return Ok(0);
+    }
+    let mut offset = FILE_MAGIC.len();
+    while offset + RECORD_HEADER_LEN <= content.len() {
+        let mut header = &content[offset..offset + RECORD_HEADER_LEN];
+        let len = try!(header.decode_u32_le()) as usize;
+        let checksum = try!(header.decode_u32_le());
+        let start = offset + RECORD_HEADER_LEN;
+        if start + len > content.len() {
+            break;
+        }
+        let payload = &content[start..start + len];
+        if crc32::checksum_ieee(payload) != checksum {
+            break;
+        }
+        try!(f(num, start as u64, payload));
+        offset = start + len;
+    }
+    Ok(offset)
+}
+
+/// The log files numbered from 1, the records are appended to the last one, which is active.
+/// A new file is created when the active one exceeds the target size.
+pub struct Pipe {
+    dir: PathBuf,
+    target_file_size: u64,
+    first_file_num: u64,
+    // The files from the first one, they are kept open for reading.
+    files: VecDeque<Arc<File>>,
+    active_size: u64,
+}
+
+impl Pipe {
+    /// Opens the log files in `dir`, the payloads of the records are passed to `f` with the
+    /// file number and the offset in order. The torn tail of the last file, which is left by a
+    /// crash during writing, is truncated.
+    pub fn open<F>(dir: &Path, target_file_size: u64, mut f: F) -> Result<Pipe>
+    where
+        F: FnMut(u64, u64, &[u8]) -> Result<()>,
+    {
+        if !dir.exists() {
+            try!(fs::create_dir_all(dir));
+        }
+        let mut nums = vec![];
+        for e in try!(fs::read_dir(dir)) {
+            let e = try!(e);
+            if let Some(num) = e.file_name().to_str().and_then(parse_file_num) {
+                nums.push(num);
+            }
+        }
+        nums.sort();
+        for w in nums.windows(2) {
+            if w[0] + 1 != w[1] {
+                return Err(Error::Corruption(format!("log file {} is missing", w[0] + 1)));
+            }
+        }
+
+        let mut pipe = Pipe {
+            dir: dir.to_owned(),
+            target_file_size: target_file_size,
+            first_file_num: nums.first().cloned().unwrap_or(1),
+            files: VecDeque::with_capacity(nums.len()),
+            active_size: 0,
+        };
+        let last_num = match nums.last() {
+            Some(&num) => num,
+            None => {
+                try!(pipe.new_file(1));
+                return Ok(pipe);
+            }
+        };
+        for num in nums {
+            let path = pipe.dir.join(file_name(num));
+            let mut content = vec![];
+            try!(File::open(&path).and_then(|mut file| file.read_to_end(&mut content)));
+            let valid_len = try!(read_records(num, &content, &mut f));
+            if num != last_num {
+                if valid_len == 0 || valid_len < content.len() {
+                    return Err(Error::Corruption(format!(
+                        "log file {} is corrupted at {}",
+                        path.display(),
+                        valid_len
+                    )));
+                }
+                pipe.files.push_back(Arc::new(try!(File::open(&path))));
+                continue;
+            }
+
+            let mut file = try!(OpenOptions::new().read(true).append(true).open(&path));
+            if valid_len < content.len() {
+                warn!(
+                    "truncate the torn tail of log file {} from {} to {}",
+                    path.display(),
+                    content.len(),
+                    valid_len
+                );
+                try!(file.set_len(valid_len as u64));
+            }
+            pipe.active_size = valid_len as u64;
+            if valid_len == 0 {
+                try!(file.write_all(FILE_MAGIC));
+                pipe.active_size = FILE_MAGIC.len() as u64;
+            }
+            pipe.files.push_back(Arc::new(file));
+        }
+        Ok(pipe)
+    }
+
+    fn new_file(&mut self, num: u64) -> Result<()> {
+        let path = self.dir.join(file_name(num));
+        let mut file = try!(
+            OpenOptions::new()
+                .read(true)
+                .append(true)
+                .create_new(true)
+                .open(&path)
+        );
+        try!(file.write_all(FILE_MAGIC));
+        try!(sync_dir(&self.dir));
+        self.files.push_back(Arc::new(file));
+        self.active_size = FILE_MAGIC.len() as u64;
+        Ok(())
+    }
+
+    pub fn first_file_num(&self) -> u64 {
+        self.first_file_num
+    }
+
+    pub fn active_file_num(&self) -> u64 {
+        self.first_file_num + self.files.len() as u64 - 1
+    }
+
+    pub fn active_size(&self) -> u64 {
+        self.active_size
+    }
+
+    pub fn active_file(&self) -> Arc<File> {
+        self.files.back().unwrap().clone()
+    }
+
+    pub fn file(&self, num: u64) -> Option<Arc<File>> {
+        if num < self.first_file_num {
+            return None;
+        }
+        self.files
+            .get((num - self.first_file_num) as usize)
+            .cloned()
+    }
+
+    /// Appends a record, returns the file number and the offset of the payload.
+    pub fn append(&mut self, payload: &[u8]) -> Result<(u64, u64)> {
+        if self.active_size >= self.target_file_size {
+            // The active file is synced before rotating, so only the new one needs syncing.
+            try!(self.active_file().sync_all());
+            let num = self.active_file_num() + 1;
+            try!(self.new_file(num));
+        }
+        let mut buf = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+        try!(buf.encode_u32_le(payload.len() as u32));
+        try!(buf.encode_u32_le(crc32::checksum_ieee(payload)));
+        buf.extend_from_slice(payload);
+        let file = self.active_file();
+        try!((&*file).write_all(&buf));
+        let offset = self.active_size + RECORD_HEADER_LEN as u64;
+        self.active_size += buf.len() as u64;
+        Ok((self.active_file_num(), offset))
+    }
+
+    /// Deletes the files before `num`, the active file is kept. Returns the number of the
+    /// deleted files.
+    pub fn purge_to(&mut self, num: u64) -> Result<usize> {
+        let mut count = 0;
+        while self.first_file_num < num && self.files.len() > 1 {
+            try!(fs::remove_file(self.dir.join(file_name(self.first_file_num))));
+            self.files.pop_front();
+            self.first_file_num += 1;
+            count += 1;
+        }
+        if count > 0 {
+            try!(sync_dir(&self.dir));
+        }
+        Ok(count)
+    }
+}
+
+/// Reads `len` bytes at `offset` of the file.
+pub fn read_at(file: &File, offset: u64, len: usize) -> Result<Vec<u8>> {
+    let mut buf = vec![0; len];
+    let mut read = 0;
+    while read < len {
+        let n = try!(file.read_at(&mut buf[read..], offset + read as u64));
+        if n == 0 {
+            return Err(Error::Corruption(format!(
+                "read {} bytes at {}, only {} are read",
+                len,
+                offset,
+                read
+            )));
+        }
+        read += n;
+    }
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn open_pipe(dir: &Path, target_file_size: u64) -> (Pipe, Vec<(u64, u64, Vec<u8>)>) {
+        let mut records = vec![];
+        let pipe = Pipe::open(dir, target_file_size, |num, offset, payload| {
+            records.push((num, offset, payload.to_vec()));
+            Ok(())
+        }).unwrap();
+        (pipe, records)
+    }
+
+    #[test]
+    fn test_pipe() {
+        let dir = TempDir::new("test_pipe").unwrap();
+        let (mut pipe, records) = open_pipe(dir.path(), 40);
+        assert!(records.is_empty());
+        assert_eq!(pipe.active_file_num(), 1);
+
+        let mut positions = vec![];
+        for i in 0..4 {
+            let payload = vec![i; 10];
+            positions.push(pipe.append(&payload).unwrap());
+        }
+        // A file holds 2 records after the magic.
+        assert_eq!(pipe.active_file_num(), 2);
+        for (i, &(num, offset)) in positions.iter().enumerate() {
+            let file = pipe.file(num).unwrap();
+            assert_eq!(read_at(&file, offset, 10).unwrap(), vec![i as u8; 10]);
+        }
+        drop(pipe);
+
+        let (mut pipe, records) = open_pipe(dir.path(), 40);
+        let expect: Vec<_> = positions
+            .iter()
+            .enumerate()
+            .map(|(i, &(num, offset))| (num, offset, vec![i as u8; 10]))
+            .collect();
+        assert_eq!(records, expect);
+
+        // The torn tail is truncated.
+        let size = pipe.active_size();
+        (&*pipe.active_file()).write_all(&[1, 2, 3]).unwrap();
+        drop(pipe);
+        let (pipe, records) = open_pipe(dir.path(), 40);
+        assert_eq!(records.len(), 4);
+        assert_eq!(pipe.active_size(), size);
+
+        let mut pipe = pipe;
+        assert_eq!(pipe.purge_to(2).unwrap(), 1);
+        assert_eq!(pipe.first_file_num(), 2);
+        assert!(pipe.file(1).is_none());
+        assert_eq!(pipe.purge_to(3).unwrap(), 0);
+        drop(pipe);
+        let (_, records) = open_pipe(dir.path(), 40);
+        assert_eq!(records.len(), 2);
+    }
+}
@@ -0,0 +1,229 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use std::mem;
+use std::u64;
+
+use byteorder::{BigEndian, ByteOrder};
+use kvproto::eraftpb::Entry;
+use kvproto::raft_serverpb::RaftLocalState;
+use protobuf::Message;
+use rocksdb::{Writable, WriteBatch, DB};
+use rocksdb::rocksdb_options::WriteOptions;
+
+use raftstore::store::keys;
+use raftstore::store::engine::{IterOption, Iterable, Mutable, Peekable};
+use super::{Error, LogBatch, LogItem, RaftEngine, Result};
+
+// Deletes the entries of the region in `[from, to)`.
+fn delete_entries(wb: &WriteBatch, region_id: u64, from: u64, to: u64) -> Result<()> {
+    for index in from..to {
+        try!(wb.delete(&keys::raft_log_key(region_id, index)));
+    }
+    Ok(())
+}
+
+impl RaftEngine for DB {
+    fn get_raft_state(&self, region_id: u64) -> Result<Option<RaftLocalState>> {
+        let state = try!(self.get_msg(&keys::raft_state_key(region_id)));
+        Ok(state)
+    }
+
+    fn get_entry(&self, region_id: u64, index: u64) -> Result<Option<Entry>> {
+        let entry = try!(self.get_msg(&keys::raft_log_key(region_id, index)));
+        Ok(entry)
+    }
+
+    fn fetch_entries_to(
+        &self,
+        region_id: u64,
+        low: u64,
+        high: u64,
+        max_size: Option<usize>,
+        buf: &mut Vec<Entry>,
+    ) -> Result<usize> {
+        let max_size = max_size.unwrap_or(usize::max_value());
+        let mut total_size = 0;
+        let mut next_index = low;
+        let mut exceeded_max_size = false;
+        let start_key = keys::raft_log_key(region_id, low);
+        let end_key = keys::raft_log_key(region_id, high);
+        try!(self.scan(&start_key, &end_key, true, &mut |_, value| {
+            let mut entry = Entry::new();
+            try!(entry.merge_from_bytes(value));
+            // May meet gap or has been compacted.
+            if entry.get_index() != next_index {
+                return Ok(false);
+            }
+            if next_index != low && total_size + value.len() > max_size {
+                exceeded_max_size = true;
+                return Ok(false);
+            }
+            next_index += 1;
+            total_size += value.len();
+            buf.push(entry);
+            Ok(true)
+        }));
+
+        if next_index < high && !exceeded_max_size {
+            return Err(Error::EntryNotFound(region_id, next_index));
+        }
+        Ok(total_size)
+    }
+
+    fn entry_range(&self, region_id: u64) -> Result<Option<(u64, u64)>> {
+        let start_key = keys::raft_log_key(region_id, 0);
+        let end_key = keys::raft_log_key(region_id, u64::MAX);
+        // Seeks to both ends instead of scanning all the entries.
+        let mut it = self.new_iterator(IterOption::new(None, false));
+        if !it.seek(start_key.as_slice().into()) || it.key() > end_key.as_slice() {
+            return Ok(None);
+        }
+        let first = try!(keys::raft_log_index(it.key()));
+        if !it.seek_for_prev(end_key.as_slice().into()) || it.key() < start_key.as_slice() {
+            return Ok(None);
+        }
+        let last = try!(keys::raft_log_index(it.key()));
+        Ok(Some((first, last)))
+    }
+
+    fn raft_groups(&self) -> Result<Vec<u64>> {
+        let mut groups = vec![];
+        let prefix_len = keys::REGION_RAFT_PREFIX_KEY.len();
+        let mut key = keys::region_raft_prefix(0);
+        while let Some((k, _)) = try!(self.seek(&key)) {
+            if !k.starts_with(keys::REGION_RAFT_PREFIX_KEY) {
+                break;
+            }
+            if k.len() < prefix_len + mem::size_of::<u64>() {
+                return Err(Error::Corruption(format!("invalid raft key {:?}", k)));
+            }
+            let region_id = BigEndian::read_u64(&k[prefix_len..prefix_len + mem::size_of::<u64>()]);
+            groups.push(region_id);
+            if region_id == u64::MAX {
+                break;
+            }
+            key = keys::region_raft_prefix(region_id + 1);
+        }
+        Ok(groups)
+    }
+
+    fn consume(&self, batch: &mut LogBatch, sync: bool) -> Result<()> {
+        let wb = WriteBatch::new();
+        for item in batch.take_items() {
+            match item {
+                LogItem::Entries { region_id, prev_last_index, entries } => {
+                    let last_index = match entries.last() {
+                        Some(e) => e.get_index(),
+                        None => continue,
+                    };
+                    for e in &entries {
+                        try!(wb.put_msg(&keys::raft_log_key(region_id, e.get_index()), e));
+                    }
+                    // The previously appended entries after the new ones, which are never
+                    // committed, are deleted.
+                    try!(delete_entries(&wb, region_id, last_index + 1, prev_last_index + 1));
+                }
+                LogItem::RaftState { region_id, state } => {
+                    try!(wb.put_msg(&keys::raft_state_key(region_id), &state));
+                }
+                LogItem::Compact { region_id, first_index, index } => {
+                    let first_index = if first_index > 0 {
+                        first_index
+                    } else {
+                        match try!(self.entry_range(region_id)) {
+                            Some((first, _)) => first,
+                            None => continue,
+                        }
+                    };
+                    try!(delete_entries(&wb, region_id, first_index, index));
+                }
+                LogItem::Clean { region_id } => {
+                    try!(wb.delete(&keys::raft_state_key(region_id)));
+                    if let Some((first, last)) = try!(self.entry_range(region_id)) {
+                        try!(delete_entries(&wb, region_id, first, last + 1));
+                    }
+                }
+            }
+        }
+        if wb.is_empty() {
+            return Ok(());
+        }
+        let mut write_opts = WriteOptions::new();
+        write_opts.set_sync(sync);
+        try!(self.write_opt(wb, &write_opts));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use storage::CF_DEFAULT;
+    use util::rocksdb::new_engine;
+    use super::*;
+
+    pub fn new_entry(index: u64, term: u64) -> Entry {
+        let mut e = Entry::new();
+        e.set_index(index);
+        e.set_term(term);
+        e
+    }
+
+    #[test]
+    fn test_rocks_raft_engine() {
+        let path = TempDir::new("test_rocks_raft_engine").unwrap();
+        let db = new_engine(path.path().to_str().unwrap(), &[CF_DEFAULT]).unwrap();
+
+        let mut batch = LogBatch::new();
+        batch.add_entries(1, 0, (1..11).map(|i| new_entry(i, 1)).collect());
+        batch.add_entries(2, 0, vec![new_entry(5, 1)]);
+        let mut state = RaftLocalState::new();
+        state.set_last_index(10);
+        batch.put_raft_state(1, &state);
+        db.consume(&mut batch, true).unwrap();
+        assert!(batch.is_empty());
+        assert_eq!(db.raft_groups().unwrap(), vec![1, 2]);
+        assert_eq!(db.get_raft_state(1).unwrap(), Some(state));
+        assert_eq!(db.entry_range(1).unwrap(), Some((1, 10)));
+        assert_eq!(db.get_entry(2, 5).unwrap(), Some(new_entry(5, 1)));
+
+        // The conflicting entries are replaced.
+        batch.add_entries(1, 10, (6..8).map(|i| new_entry(i, 2)).collect());
+        batch.compact_to(1, 1, 3);
+        db.consume(&mut batch, false).unwrap();
+        assert_eq!(db.entry_range(1).unwrap(), Some((3, 7)));
+        let mut entries = vec![];
+        db.fetch_entries_to(1, 3, 8, None, &mut entries).unwrap();
+        assert_eq!(entries.len(), 5);
+        assert_eq!(entries[4], new_entry(7, 2));
+        // At least one entry is fetched.
+        entries.clear();
+        db.fetch_entries_to(1, 3, 8, Some(0), &mut entries).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(db.fetch_entries_to(1, 1, 8, None, &mut entries).is_err());
+
+        // The first index is looked up if it's unknown.
+        batch.compact_to(1, 0, 5);
+        db.consume(&mut batch, false).unwrap();
+        assert_eq!(db.entry_range(1).unwrap(), Some((5, 7)));
+        assert_eq!(db.entry_range(3).unwrap(), None);
+
+        batch.clean(1);
+        db.consume(&mut batch, false).unwrap();
+        assert_eq!(db.raft_groups().unwrap(), vec![2]);
+        assert_eq!(db.get_raft_state(1).unwrap(), None);
+    }
+}
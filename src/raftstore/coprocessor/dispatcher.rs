@@ -13,8 +13,9 @@
 
 use super::{ObserverContext, RegionObserver, Result};
 
-use kvproto::raft_cmdpb::RaftCmdRequest;
+use kvproto::raft_cmdpb::{RaftCmdRequest, RaftCmdResponse};
 use kvproto::metapb::Region;
+use raft::StateRole;
 
 struct ObserverEntry {
     priority: u32,
@@ -98,6 +99,56 @@ impl CoprocessorHost {
         }
     }
 
+    /// Call all post apply hook until bypass is set to true, failed requests are skipped.
+    pub fn post_apply(
+        &self,
+        region: &Region,
+        index: u64,
+        req: &RaftCmdRequest,
+        resp: &RaftCmdResponse,
+    ) {
+        if resp.get_header().has_error() {
+            return;
+        }
+        let mut ctx = ObserverContext::new(region);
+        for entry in &self.registry.observers {
+            if req.has_admin_request() {
+                entry
+                    .observer
+                    .post_apply_admin(&mut ctx, req.get_admin_request());
+            } else {
+                entry
+                    .observer
+                    .post_apply_query(&mut ctx, index, req.get_requests());
+            }
+            if ctx.bypass {
+                break;
+            }
+        }
+    }
+
+    /// Call all role change hook until bypass is set to true.
+    pub fn on_role_change(&self, region: &Region, role: StateRole) {
+        let mut ctx = ObserverContext::new(region);
+        for entry in &self.registry.observers {
+            entry.observer.on_role_change(&mut ctx, role);
+            if ctx.bypass {
+                break;
+            }
+        }
+    }
+
+    /// Call all replica read index hook until bypass is set to true.
+    pub fn on_replica_read_index(&self, region: &Region, start_ts: u64) {
+        let mut ctx = ObserverContext::new(region);
+        for entry in &self.registry.observers {
+            entry.observer.on_replica_read_index(&mut ctx, start_ts);
+            if ctx.bypass {
+                break;
+            }
+        }
+    }
+
     pub fn shutdown(&self) {
         for entry in &self.registry.observers {
             entry.observer.stop();
@@ -113,7 +164,8 @@ mod test {
     use protobuf::RepeatedField;
 
     use kvproto::metapb::Region;
-    use kvproto::raft_cmdpb::{AdminRequest, RaftCmdRequest, Request};
+    use kvproto::raft_cmdpb::{AdminRequest, RaftCmdRequest, RaftCmdResponse, Request};
+    use raftstore::store::cmd_resp;
 
     struct TestCoprocessor {
         bypass: Arc<AtomicBool>,
@@ -165,6 +217,16 @@ mod test {
             self.called.fetch_add(3, Ordering::SeqCst);
             ctx.bypass = self.bypass.load(Ordering::SeqCst);
         }
+
+        fn post_apply_query(&self, ctx: &mut ObserverContext, _: u64, _: &[Request]) {
+            self.called.fetch_add(4, Ordering::SeqCst);
+            ctx.bypass = self.bypass.load(Ordering::SeqCst);
+        }
+
+        fn post_apply_admin(&self, ctx: &mut ObserverContext, _: &AdminRequest) {
+            self.called.fetch_add(5, Ordering::SeqCst);
+            ctx.bypass = self.bypass.load(Ordering::SeqCst);
+        }
     }
 
     fn share_bool() -> Arc<AtomicBool> {
@@ -247,4 +309,39 @@ mod test {
         assert!(host.pre_propose(&region, &mut admin_req).is_err());
         assert_all!(&[&called1, &called2], &[0, 1]);
     }
+
+    #[test]
+    fn test_post_apply() {
+        let (bypass1, called1, r1) = (share_bool(), share_usize(), share_bool());
+        let (bypass2, called2, r2) = (share_bool(), share_usize(), share_bool());
+        let mut host = CoprocessorHost::default();
+        host.registry.register_observer(
+            1,
+            Box::new(TestCoprocessor::new(bypass1.clone(), called1.clone(), r1)),
+        );
+        host.registry.register_observer(
+            2,
+            Box::new(TestCoprocessor::new(bypass2.clone(), called2.clone(), r2)),
+        );
+        let region = Region::new();
+        let mut admin_req = RaftCmdRequest::new();
+        admin_req.set_admin_request(AdminRequest::new());
+        let mut query_req = RaftCmdRequest::new();
+        query_req.set_requests(RepeatedField::from_vec(vec![Request::new()]));
+        let resp = RaftCmdResponse::new();
+
+        host.post_apply(&region, 1, &query_req, &resp);
+        assert_all!(&[&called1, &called2], &[4, 4]);
+        host.post_apply(&region, 2, &admin_req, &resp);
+        assert_all!(&[&called1, &called2], &[9, 9]);
+
+        // Failed requests are skipped.
+        let err_resp = cmd_resp::new_error(box_err!("error"));
+        host.post_apply(&region, 3, &query_req, &err_resp);
+        assert_all!(&[&called1, &called2], &[9, 9]);
+
+        set_all!(&[&bypass1], true);
+        host.post_apply(&region, 4, &query_req, &resp);
+        assert_all!(&[&called1, &called2], &[13, 9]);
+    }
 }
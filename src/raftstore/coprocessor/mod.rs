@@ -22,6 +22,7 @@ pub use self::dispatcher::{CoprocessorHost, Registry};
 use kvproto::raft_cmdpb::{AdminRequest, Request};
 use kvproto::metapb::Region;
 use protobuf::RepeatedField;
+use raft::StateRole;
 
 pub use self::error::{Error, Result};
 
@@ -69,4 +70,18 @@ pub trait RegionObserver: Coprocessor {
     ///
     /// Please note that improper implementation can lead to data inconsistency.
     fn pre_apply_query(&self, _: &mut ObserverContext, _: &mut RepeatedField<Request>) {}
+
+    /// Hook to call after read/write requests at log `index` are applied successfully.
+    ///
+    /// Please note that the write batch may not be written to the engine yet.
+    fn post_apply_query(&self, _: &mut ObserverContext, _: u64, _: &[Request]) {}
+
+    /// Hook to call after an admin request is applied successfully.
+    fn post_apply_admin(&self, _: &mut ObserverContext, _: &AdminRequest) {}
+
+    /// Hook to call when the raft role of the peer changes.
+    fn on_role_change(&self, _: &mut ObserverContext, _: StateRole) {}
+
+    /// Hook to call before the leader returns the read index to a replica read at `start_ts`.
+    fn on_replica_read_index(&self, _: &mut ObserverContext, _: u64) {}
 }
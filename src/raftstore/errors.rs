@@ -59,6 +59,17 @@ quick_error!{
                     escape(region.get_end_key()),
                     region.get_id())
         }
+        DataIsNotReady(region_id: u64, peer_id: u64, safe_ts: u64) {
+            description("data is not ready")
+            display("peer {} of region {} is not ready for stale reads after safe ts {}",
+                    peer_id,
+                    region_id,
+                    safe_ts)
+        }
+        ReadIndexNotReady(reason: &'static str, region_id: u64) {
+            description("read index not ready")
+            display("read index of region {} is not ready: {}", region_id, reason)
+        }
         Other(err: Box<error::Error + Sync + Send>) {
             from()
             cause(err.as_ref())
@@ -178,6 +189,19 @@ impl Into<errorpb::Error> for Error {
                 e.set_new_regions(RepeatedField::from_vec(new_regions));
                 errorpb.set_stale_epoch(e);
             }
+            Error::DataIsNotReady(region_id, peer_id, safe_ts) => {
+                let mut e = errorpb::DataIsNotReady::new();
+                e.set_region_id(region_id);
+                e.set_peer_id(peer_id);
+                e.set_safe_ts(safe_ts);
+                errorpb.set_data_is_not_ready(e);
+            }
+            Error::ReadIndexNotReady(reason, region_id) => {
+                let mut e = errorpb::ReadIndexNotReady::new();
+                e.set_reason(reason.to_owned());
+                e.set_region_id(region_id);
+                errorpb.set_read_index_not_ready(e);
+            }
             Error::StaleCommand => {
                 errorpb.set_stale_command(errorpb::StaleCommand::new());
             }
@@ -14,6 +14,7 @@
 use rocksdb::{Writable, WriteBatch, DB};
 use kvproto::raft_serverpb::{RegionLocalState, StoreIdent};
 use kvproto::metapb;
+use raft_engine::LogBatch;
 use raftstore::Result;
 use super::keys;
 use super::engine::{Iterable, Mutable};
@@ -60,6 +61,12 @@ pub fn bootstrap_store(engines: &Engines, cluster_id: u64, store_id: u64) -> Res
         ));
     }
 
+    if !box_try!(engines.raft_log.raft_groups()).is_empty() {
+        return Err(box_err!(
+            "raft log engine is not empty and has already had data."
+        ));
+    }
+
     let ident_key = keys::store_ident_key();
 
     ident.set_cluster_id(cluster_id);
@@ -91,17 +98,17 @@ pub fn write_prepare_bootstrap(engines: &Engines, region: &metapb::Region) -> Re
     try!(engines.kv_engine.write(wb));
     try!(engines.kv_engine.sync_wal());
 
-    let raft_wb = WriteBatch::new();
-    try!(write_initial_raft_state(&raft_wb, region.get_id()));
-    try!(engines.raft_engine.write(raft_wb));
-    try!(engines.raft_engine.sync_wal());
+    let mut raft_wb = LogBatch::new();
+    try!(write_initial_raft_state(&mut raft_wb, region.get_id()));
+    box_try!(engines.raft_log.consume(&mut raft_wb, true));
     Ok(())
 }
 
 // Clear first region meta and prepare state.
 pub fn clear_prepare_bootstrap(engines: &Engines, region_id: u64) -> Result<()> {
-    try!(engines.raft_engine.delete(&keys::raft_state_key(region_id)));
-    try!(engines.raft_engine.sync_wal());
+    let mut raft_wb = LogBatch::new();
+    raft_wb.clean(region_id);
+    box_try!(engines.raft_log.consume(&mut raft_wb, true));
 
     let wb = WriteBatch::new();
     try!(wb.delete(&keys::prepare_bootstrap_key()));
@@ -12,12 +12,16 @@
 // limitations under the License.
 
 use std::u64;
+use std::time::Duration;
 
 use time::Duration as TimeDuration;
 
 use raftstore::Result;
 use util::config::{ReadableDuration, ReadableSize};
 
+// The number of times the load of the reads is reported in a window of load split.
+const LOAD_REPORTS_PER_CHECK: u32 = 4;
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(default)]
 #[serde(rename_all = "kebab-case")]
@@ -99,6 +103,31 @@ pub struct Config {
     pub right_derive_when_split: bool,
 
     pub allow_remove_leader: bool,
+
+    // Max log gap allowed to propose merge.
+    pub merge_max_log_gap: u64,
+    // Interval to re-propose merge.
+    pub merge_check_tick_interval: ReadableDuration,
+
+    // Stop ticking idle regions, so that the leader doesn't send heartbeats to the followers.
+    pub hibernate_regions: bool,
+    // Interval for a hibernated follower to wake up and check whether its leader is alive. It
+    // also wakes up once the store of its leader, probed with every store heartbeat, is
+    // unreachable.
+    pub hibernate_wake_up_interval: ReadableDuration,
+
+    // Interval to advance the resolved ts of the leaders for stale reads, 0 means disabled.
+    pub advance_resolved_ts_interval: ReadableDuration,
+
+    // The number of threads applying the committed logs, the regions are applied concurrently
+    // if it's larger than 1. It doesn't change the single thread driving the peers.
+    pub apply_pool_size: usize,
+
+    // A region is split by its load if its QPS exceeds the threshold in `load_split_detect_times`
+    // consecutive windows of `load_split_check_interval`, 0 means disabled.
+    pub load_split_qps_threshold: u64,
+    pub load_split_check_interval: ReadableDuration,
+    pub load_split_detect_times: u64,
 }
 
 impl Default for Config {
@@ -145,6 +174,16 @@ impl Default for Config {
             raft_store_max_leader_lease: ReadableDuration::secs(9),
             right_derive_when_split: true,
             allow_remove_leader: false,
+            merge_max_log_gap: 10,
+            merge_check_tick_interval: ReadableDuration::secs(10),
+            hibernate_regions: false,
+            hibernate_wake_up_interval: ReadableDuration::minutes(1),
+            advance_resolved_ts_interval: ReadableDuration::secs(1),
+            apply_pool_size: 2,
+            // It changes where the regions are split, so it's off unless asked for.
+            load_split_qps_threshold: 0,
+            load_split_check_interval: ReadableDuration::secs(1),
+            load_split_detect_times: 10,
         }
     }
 }
@@ -206,8 +245,41 @@ impl Config {
             ));
         }
 
+        if self.hibernate_regions &&
+            self.hibernate_wake_up_interval.0 >= self.max_peer_down_duration.0
+        {
+            return Err(box_err!(
+                "hibernate wake up interval {:?} must be less than max peer down duration {:?}",
+                self.hibernate_wake_up_interval.0,
+                self.max_peer_down_duration.0
+            ));
+        }
+
+        if self.apply_pool_size == 0 {
+            return Err(box_err!("apply pool size should be greater than 0."));
+        }
+
+        if self.load_split_qps_threshold > 0 &&
+            (self.load_split_check_interval.as_millis() == 0 || self.load_split_detect_times == 0)
+        {
+            return Err(box_err!(
+                "load split check interval and detect times should be greater than 0."
+            ));
+        }
+
         Ok(())
     }
+
+    /// Returns the interval to report the load of the reads to the store, `None` if load split
+    /// is disabled. The reports are sent several times in a window of
+    /// `load_split_check_interval`, so only a small part of the load is counted in the next
+    /// window.
+    pub fn load_report_interval(&self) -> Option<Duration> {
+        if self.load_split_qps_threshold == 0 {
+            return None;
+        }
+        Some(self.load_split_check_interval.0 / LOAD_REPORTS_PER_CHECK)
+    }
 }
 
 #[cfg(test)]
@@ -250,5 +322,33 @@ mod tests {
         cfg.raft_election_timeout_ticks = 10;
         cfg.raft_store_max_leader_lease = ReadableDuration::secs(20);
         assert!(cfg.validate().is_err());
+
+        cfg = Config::new();
+        cfg.hibernate_regions = true;
+        assert!(cfg.validate().is_ok());
+        cfg.hibernate_wake_up_interval = ReadableDuration::minutes(5);
+        assert!(cfg.validate().is_err());
+
+        cfg = Config::new();
+        cfg.apply_pool_size = 0;
+        assert!(cfg.validate().is_err());
+
+        cfg = Config::new();
+        cfg.load_split_detect_times = 0;
+        assert!(cfg.validate().is_ok());
+        cfg.load_split_qps_threshold = 3000;
+        assert!(cfg.validate().is_err());
+    }
+
+    #[test]
+    fn test_load_report_interval() {
+        let mut cfg = Config::new();
+        assert_eq!(cfg.load_report_interval(), None);
+        cfg.load_split_qps_threshold = 3000;
+        cfg.load_split_check_interval = ReadableDuration::secs(1);
+        assert_eq!(
+            cfg.load_report_interval(),
+            Some(Duration::from_millis(1000 / LOAD_REPORTS_PER_CHECK as u64))
+        );
     }
 }
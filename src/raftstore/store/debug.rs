@@ -16,6 +16,7 @@ use kvproto::debugpb::DB as DBType;
 use kvproto::{eraftpb, raft_serverpb};
 
 use rocksdb::DB;
+use raft_engine::RaftEngine;
 use raftstore::store::{keys, Engines, Iterable, Peekable};
 use storage::{CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE};
 use util::rocksdb::{compact_range, get_cf_handle};
@@ -73,8 +74,7 @@ impl Debugger {
     }
 
     pub fn raft_log(&self, region_id: u64, log_index: u64) -> Result<eraftpb::Entry> {
-        let key = keys::raft_log_key(region_id, log_index);
-        match self.engines.raft_engine.get_msg(&key) {
+        match self.engines.raft_log.get_entry(region_id, log_index) {
             Ok(Some(entry)) => Ok(entry),
             Ok(None) => Err(Error::NotFound(format!(
                 "raft log for region {} at index {}",
@@ -95,12 +95,7 @@ impl Debugger {
             Option<raft_serverpb::RegionLocalState>,
         ),
     > {
-        let raft_state_key = keys::raft_state_key(region_id);
-        let raft_state = box_try!(
-            self.engines
-                .raft_engine
-                .get_msg::<raft_serverpb::RaftLocalState>(&raft_state_key)
-        );
+        let raft_state = box_try!(self.engines.raft_log.get_raft_state(region_id));
 
         let apply_state_key = keys::apply_state_key(region_id);
         let apply_state = box_try!(
@@ -70,6 +70,13 @@ lazy_static! {
             &["type"]
         ).unwrap();
 
+    pub static ref HIBERNATED_PEER_STATE_GAUGE: GaugeVec =
+        register_gauge_vec!(
+            "tikv_raftstore_hibernated_peer_state",
+            "Number of peers in hibernated state.",
+            &["state"]
+        ).unwrap();
+
     pub static ref STORE_SIZE_GAUGE_VEC: GaugeVec =
         register_gauge_vec!(
             "tikv_raftstore_store_size_bytes",
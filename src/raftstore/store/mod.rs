@@ -28,12 +28,17 @@ mod snap;
 mod worker;
 mod metrics;
 mod local_metrics;
+mod resolver;
+mod read_progress;
+mod split_controller;
 
 pub use self::msg::{BatchCallback, Callback, CopFlowStatistics, Msg, SignificantMsg, Tick};
 pub use self::store::{create_event_loop, Engines, Store, StoreChannel};
 pub use self::config::Config;
 pub use self::transport::Transport;
 pub use self::peer::Peer;
+pub use self::resolver::Resolver;
+pub use self::split_controller::{LoadStats, RegionLoad, SplitController};
 pub use self::bootstrap::{bootstrap_store, clear_prepare_bootstrap, clear_prepare_bootstrap_state,
                           prepare_bootstrap, write_prepare_bootstrap};
 pub use self::engine::{Iterable, Mutable, Peekable};
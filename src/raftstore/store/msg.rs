@@ -21,6 +21,7 @@ use kvproto::metapb::RegionEpoch;
 use raft::SnapshotStatus;
 use util::collections::HashMap;
 use storage::FlowStatistics;
+use super::split_controller::LoadStats;
 
 use util::escape;
 
@@ -39,6 +40,8 @@ pub enum Tick {
     SnapGc,
     CompactLockCf,
     ConsistencyCheck,
+    CheckMerge,
+    LoadSplitCheck,
 }
 
 #[derive(Debug, PartialEq)]
@@ -49,6 +52,8 @@ pub enum SignificantMsg {
         status: SnapshotStatus,
     },
     Unreachable { region_id: u64, to_peer_id: u64 },
+    // The connection to the store is broken.
+    StoreUnreachable { store_id: u64 },
 }
 
 pub enum Msg {
@@ -83,12 +88,19 @@ pub enum Msg {
 
     CoprocessorStats { request_stats: CopFlowStatistics },
 
+    // The sampled load of the regions, for splitting the hot regions.
+    LoadStats { stats: LoadStats },
+
     // For consistency check
     ComputeHashResult {
         region_id: u64,
         index: u64,
         hash: Vec<u8>,
     },
+
+    // Resolve the ts of the regions led by the store, no lock before `min_ts` will be
+    // written later.
+    AdvanceResolvedTs { min_ts: u64 },
 }
 
 impl fmt::Debug for Msg {
@@ -100,6 +112,7 @@ impl fmt::Debug for Msg {
             Msg::BatchRaftSnapCmds { .. } => write!(fmt, "Batch Raft Commands"),
             Msg::SnapshotStats => write!(fmt, "Snapshot stats"),
             Msg::CoprocessorStats { .. } => write!(fmt, "Coperocessor stats"),
+            Msg::LoadStats { .. } => write!(fmt, "Load stats"),
             Msg::ComputeHashResult {
                 region_id,
                 index,
@@ -116,6 +129,7 @@ impl fmt::Debug for Msg {
                 ref split_key,
                 ..
             } => write!(fmt, "Split region {} at key {:?}", region_id, split_key),
+            Msg::AdvanceResolvedTs { min_ts } => write!(fmt, "Advance resolved ts to {}", min_ts),
         }
     }
 }
@@ -23,13 +23,17 @@ use rocksdb::{WriteBatch, DB};
 use rocksdb::rocksdb_options::WriteOptions;
 use protobuf::{self, Message, MessageStatic};
 use kvproto::metapb;
-use kvproto::eraftpb::{self, ConfChangeType, MessageType};
-use kvproto::raft_cmdpb::{AdminCmdType, AdminResponse, CmdType, RaftCmdRequest, RaftCmdResponse,
-                          TransferLeaderRequest, TransferLeaderResponse};
-use kvproto::raft_serverpb::{PeerState, RaftMessage};
+use kvproto::eraftpb::{self, ConfChangeType, EntryType, MessageType};
+use kvproto::raft_cmdpb::{AdminCmdType, AdminResponse, ChangePeerV2Request, CmdType,
+                          RaftCmdRequest, RaftCmdResponse, TransferLeaderRequest,
+                          TransferLeaderResponse};
+use kvproto::raft_serverpb::{ExtraMessage, ExtraMessageType, MergeState, PeerState,
+                             RaftMessage};
 use kvproto::pdpb::PeerStats;
 
-use raft::{self, Progress, ProgressState, RawNode, Ready, SnapshotStatus, StateRole, INVALID_INDEX};
+use raft::{self, Progress, ProgressState, RawNode, Ready, SnapshotStatus, StateRole, INVALID_INDEX,
+           NO_LIMIT};
+use raft_engine::{LogBatch, RaftEngine};
 use raftstore::{Error, Result};
 use raftstore::coprocessor::CoprocessorHost;
 use raftstore::store::Config;
@@ -39,6 +43,7 @@ use raftstore::store::worker::apply::ExecResult;
 use util::worker::{FutureWorker, Scheduler};
 use raftstore::store::worker::{Apply, ApplyRes, ApplyTask};
 use util::Either;
+use util::codec::number::{NumberDecoder, NumberEncoder};
 use util::time::monotonic_raw_now;
 use util::collections::{FlatMap, FlatMapValues as Values, HashSet};
 
@@ -53,14 +58,16 @@ use super::transport::Transport;
 use super::engine::Snapshot;
 use super::metrics::*;
 use super::local_metrics::{RaftMessageMetrics, RaftMetrics, RaftProposeMetrics, RaftReadyMetrics};
+use super::read_progress::ReadProgress;
 
 const TRANSFER_LEADER_ALLOW_LOG_LAG: u64 = 10;
-const DEFAULT_APPEND_WB_SIZE: usize = 4 * 1024;
 
 struct ReadIndexRequest {
     id: u64,
     cmds: Vec<(RaftCmdRequest, Callback)>,
     renew_lease_time: Timespec,
+    // The read index returned by the leader, only used by followers.
+    read_index: Option<u64>,
 }
 
 impl ReadIndexRequest {
@@ -100,6 +107,26 @@ impl ReadIndexQueue {
             }
         }
     }
+
+    fn clear_all(&mut self, term: u64) {
+        self.ready_cnt = 0;
+        self.clear_uncommitted(term);
+    }
+
+    // Sets the read index returned by the leader for follower reads. The responses of the
+    // earlier reads may be dropped, and the index is safe for them too as they are sent
+    // before this one.
+    fn advance_replica_reads(&mut self, ctx: &[u8], read_index: u64) {
+        let pos = match self.reads.iter().position(|r| ctx.starts_with(r.binary_id())) {
+            Some(pos) => pos,
+            None => return,
+        };
+        for read in self.reads.iter_mut().take(pos + 1) {
+            if read.read_index.is_none() {
+                read.read_index = Some(read_index);
+            }
+        }
+    }
 }
 
 /// The returned states of the peer after checking whether it is stale
@@ -109,6 +136,16 @@ pub enum StaleState {
     ToValidate,
 }
 
+/// The hibernation state of a peer. A hibernated peer doesn't tick raft, so the leader
+/// stops sending heartbeats and the followers stop election ticks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HibernateState {
+    Awake,
+    // The leader has asked the followers to hibernate, and is waiting for their responses.
+    Hibernating,
+    Hibernated,
+}
+
 pub struct ProposalMeta {
     pub index: u64,
     pub term: u64,
@@ -143,7 +180,7 @@ impl ProposalQueue {
 
 pub struct ReadyContext<'a, T: 'a> {
     pub kv_wb: WriteBatch,
-    pub raft_wb: WriteBatch,
+    pub raft_wb: LogBatch,
     pub sync_log: bool,
     pub metrics: &'a mut RaftMetrics,
     pub trans: &'a T,
@@ -154,7 +191,7 @@ impl<'a, T> ReadyContext<'a, T> {
     pub fn new(metrics: &'a mut RaftMetrics, t: &'a T, cap: usize) -> ReadyContext<'a, T> {
         ReadyContext {
             kv_wb: WriteBatch::new(),
-            raft_wb: WriteBatch::with_capacity(DEFAULT_APPEND_WB_SIZE),
+            raft_wb: LogBatch::new(),
             sync_log: false,
             metrics: metrics,
             trans: t,
@@ -203,7 +240,7 @@ pub struct PeerStat {
 
 pub struct Peer {
     kv_engine: Arc<DB>,
-    raft_engine: Arc<DB>,
+    raft_engine: Arc<RaftEngine>,
     cfg: Rc<Config>,
     peer_cache: RefCell<FlatMap<u64, metapb::Peer>>,
     pub peer: metapb::Peer,
@@ -259,6 +296,22 @@ pub struct Peer {
     leader_lease_expired_time: Option<Either<Timespec, Timespec>>,
 
     pub peer_stat: PeerStat,
+
+    // The merge state of the region, set when PrepareMerge is applied and cleared
+    // when the merge is rolled back. All proposals but RollbackMerge are rejected
+    // while it's set.
+    pub pending_merge_state: Option<MergeState>,
+
+    pub hibernate_state: HibernateState,
+    // Ticks since the leader becomes idle.
+    idle_ticks: usize,
+    // Peers that have agreed to hibernate, only used by the leader.
+    hibernate_responses: HashSet<u64>,
+    // The instant when the peer gets hibernated.
+    hibernated_time: Option<Instant>,
+
+    // Stale reads at or before the safe ts can be served without the leader.
+    read_progress: ReadProgress,
 }
 
 impl Peer {
@@ -368,6 +421,12 @@ impl Peer {
             cfg: cfg,
             leader_lease_expired_time: None,
             peer_stat: PeerStat::default(),
+            pending_merge_state: None,
+            hibernate_state: HibernateState::Awake,
+            idle_ticks: 0,
+            hibernate_responses: HashSet::default(),
+            hibernated_time: None,
+            read_progress: ReadProgress::default(),
         };
 
         // If this region has only one peer and I am the one, campaign directly.
@@ -390,7 +449,9 @@ impl Peer {
         }
     }
 
-    pub fn destroy(&mut self) -> Result<()> {
+    /// Destroys the peer. If `keep_data` is true, the data of the region is left in the kv
+    /// engine, which is the case when the region has been merged into another region.
+    pub fn destroy(&mut self, keep_data: bool) -> Result<()> {
         let t = Instant::now();
 
         let region = self.get_store().get_region().clone();
@@ -398,21 +459,22 @@ impl Peer {
 
         // Set Tombstone state explicitly
         let kv_wb = WriteBatch::new();
-        let raft_wb = WriteBatch::new();
-        try!(self.mut_store().clear_meta(&kv_wb, &raft_wb));
+        let mut raft_wb = LogBatch::new();
+        try!(self.mut_store().clear_meta(&kv_wb, &mut raft_wb));
         try!(write_peer_state(
             &self.kv_engine,
             &kv_wb,
             &region,
-            PeerState::Tombstone
+            PeerState::Tombstone,
+            None
         ));
         // write kv rocksdb first in case of restart happen between two write
         let mut write_opts = WriteOptions::new();
         write_opts.set_sync(self.cfg.sync_log);
         try!(self.kv_engine.write_opt(kv_wb, &write_opts));
-        try!(self.raft_engine.write_opt(raft_wb, &write_opts));
+        box_try!(self.raft_engine.consume(&mut raft_wb, self.cfg.sync_log));
 
-        if self.get_store().is_initialized() {
+        if self.get_store().is_initialized() && !keep_data {
             // If we meet panic when deleting data and raft log, the dirty data
             // will be cleared by a newer snapshot applying or restart.
             if let Err(e) = self.get_store().clear_data() {
@@ -443,7 +505,7 @@ impl Peer {
         self.kv_engine.clone()
     }
 
-    pub fn raft_engine(&self) -> Arc<DB> {
+    pub fn raft_engine(&self) -> Arc<RaftEngine> {
         self.raft_engine.clone()
     }
 
@@ -543,6 +605,12 @@ impl Peer {
         if self.is_leader() && m.get_from() != INVALID_ID {
             self.peer_heartbeats.insert(m.get_from(), Instant::now());
         }
+        if self.is_leader() && m.get_msg_type() == MessageType::MsgReadIndex {
+            if let Some(start_ts) = replica_read_ts(&m) {
+                self.coprocessor_host
+                    .on_replica_read_index(self.region(), start_ts);
+            }
+        }
         try!(self.raft_group.step(m));
         Ok(())
     }
@@ -665,6 +733,11 @@ impl Peer {
                 }
                 _ => {}
             }
+            // The safe ts is published by the leader, the new one publishes it after its
+            // locks are reloaded.
+            self.read_progress.reset();
+            self.coprocessor_host
+                .on_role_change(self.region(), ss.raft_state);
         }
     }
 
@@ -837,6 +910,11 @@ impl Peer {
     }
 
     fn apply_reads(&mut self, ready: &Ready) {
+        if !self.is_leader() {
+            self.apply_replica_reads(ready);
+            return;
+        }
+
         let mut propose_time = None;
         if self.ready_to_handle_read() {
             for state in &ready.read_states {
@@ -876,6 +954,70 @@ impl Peer {
         }
     }
 
+    fn apply_replica_reads(&mut self, ready: &Ready) {
+        for state in &ready.read_states {
+            self.pending_reads
+                .advance_replica_reads(&state.request_ctx, state.index);
+        }
+        self.handle_replica_reads();
+        // The reads can't be responded by the old leader anymore.
+        if ready.ss.is_some() {
+            let term = self.term();
+            self.pending_reads.clear_all(term);
+        }
+    }
+
+    /// Fails the replica reads which don't get the read index in a lease, the request or the
+    /// response may be dropped, so the client should retry them.
+    pub fn check_replica_reads(&mut self) {
+        if self.is_leader() {
+            return;
+        }
+        let deadline = monotonic_raw_now() - self.cfg.raft_store_max_leader_lease();
+        let (region_id, term) = (self.region_id, self.term());
+        let timed_out: Vec<_> = {
+            let reads = &mut self.pending_reads.reads;
+            // The reads without the read index are behind the others, and in the order of
+            // their propose time.
+            let start = match reads.iter().position(|r| r.read_index.is_none()) {
+                Some(start) => start,
+                None => return,
+            };
+            let end = reads
+                .iter()
+                .skip(start)
+                .position(|r| r.renew_lease_time >= deadline)
+                .map_or(reads.len(), |n| start + n);
+            reads.drain(start..end).collect()
+        };
+        for mut read in timed_out {
+            for (_, cb) in read.cmds.drain(..) {
+                let err = Error::ReadIndexNotReady("read index timed out", region_id);
+                let mut resp = cmd_resp::new_error(err);
+                cmd_resp::bind_term(&mut resp, term);
+                cb(resp);
+            }
+        }
+    }
+
+    // Serves the follower reads whose read index has been applied.
+    fn handle_replica_reads(&mut self) {
+        if self.is_applying_snapshot() {
+            return;
+        }
+        let applied_index = self.get_store().applied_index();
+        loop {
+            match self.pending_reads.reads.front() {
+                Some(read) if read.read_index.map_or(false, |idx| idx <= applied_index) => {}
+                _ => break,
+            }
+            let mut read = self.pending_reads.reads.pop_front().unwrap();
+            for (req, cb) in read.cmds.drain(..) {
+                cb(self.handle_read(req));
+            }
+        }
+    }
+
     pub fn post_apply(
         &mut self,
         res: &ApplyRes,
@@ -922,6 +1064,10 @@ impl Peer {
             }
             self.pending_reads.ready_cnt = 0;
         }
+
+        if !self.is_leader() {
+            self.handle_replica_reads();
+        }
     }
 
     fn update_lease_with(&mut self, propose_time: Timespec) {
@@ -1092,6 +1238,25 @@ impl Peer {
         }
     }
 
+    // Checks whether the data at `read_ts` is complete on the peer, no matter it's the leader
+    // or not.
+    fn check_stale_read(&mut self, read_ts: u64) -> Result<()> {
+        let safe_ts = if self.is_applying_snapshot() || !self.is_initialized() {
+            0
+        } else {
+            let applied_index = self.get_store().applied_index();
+            self.read_progress.safe_ts(applied_index)
+        };
+        if read_ts > safe_ts {
+            return Err(Error::DataIsNotReady(
+                self.region_id,
+                self.peer.get_id(),
+                safe_ts,
+            ));
+        }
+        Ok(())
+    }
+
     fn post_propose(&mut self, mut meta: ProposalMeta, is_conf_change: bool, cb: Callback) {
         // Try to renew leader lease on every consistent read/write request.
         meta.renew_lease_time = Some(monotonic_raw_now());
@@ -1104,7 +1269,9 @@ impl Peer {
 
     fn get_handle_policy(&mut self, req: &RaftCmdRequest) -> Result<RequestPolicy> {
         if req.has_admin_request() {
-            if apply::get_change_peer_cmd(req).is_some() {
+            if apply::get_change_peer_cmd(req).is_some() ||
+                apply::get_change_peer_v2_cmd(req).is_some()
+            {
                 return Ok(RequestPolicy::ProposeConfChange);
             }
             if get_transfer_leader_cmd(req).is_some() {
@@ -1118,7 +1285,10 @@ impl Peer {
         for r in req.get_requests() {
             match r.get_cmd_type() {
                 CmdType::Get | CmdType::Snap => is_read = true,
-                CmdType::Delete | CmdType::Put | CmdType::DeleteRange => is_write = true,
+                CmdType::Delete |
+                CmdType::Put |
+                CmdType::DeleteRange |
+                CmdType::IngestSST => is_write = true,
                 CmdType::Prewrite | CmdType::Invalid => {
                     return Err(box_err!(
                         "invalid cmd type {:?}, message maybe currupted",
@@ -1132,6 +1302,25 @@ impl Peer {
             }
         }
 
+        if req.get_header().get_replica_read() && !self.is_leader() {
+            if is_write {
+                return Err(Error::NotLeader(
+                    self.region_id,
+                    self.get_peer_from_cache(self.leader_id()),
+                ));
+            }
+            // Ask the leader for the read index and read after applying to it.
+            return Ok(RequestPolicy::ReadIndex);
+        }
+
+        if req.get_header().get_stale_read() {
+            if is_write {
+                return Err(box_err!("write can't be served as a stale read."));
+            }
+            try!(self.check_stale_read(req.get_header().get_read_ts()));
+            return Ok(RequestPolicy::ReadLocal);
+        }
+
         if is_write {
             return Ok(RequestPolicy::ProposeNormal);
         }
@@ -1180,7 +1369,7 @@ impl Peer {
     ///    it cannot works as a node in the quorum to receive replicating logs from leader.
     fn count_healthy_node(&self, progress: Values<u64, Progress>) -> usize {
         let mut healthy = 0;
-        for pr in progress {
+        for pr in progress.filter(|pr| !pr.is_learner) {
             if pr.matched >= self.get_store().truncated_index() {
                 healthy += 1;
             }
@@ -1199,7 +1388,13 @@ impl Peer {
     ///    Then at least '(total - 1)/2 + 1' other nodes (the node about to be removed is excluded)
     ///    need to be up to date for now. If 'allow_remove_leader' is false then
     ///    the peer to be removed should not be the leader.
+    /// 3. A `AddLearnerNode` request
+    ///    It's always safe since learners are not counted in the quorum. Promoting a
+    ///    learner by `AddNode` is checked like adding a new voter.
     fn check_conf_change(&self, cmd: &RaftCmdRequest) -> Result<()> {
+        if let Some(change_peer_v2) = apply::get_change_peer_v2_cmd(cmd) {
+            return self.check_conf_change_v2(change_peer_v2);
+        }
         let change_peer = apply::get_change_peer_cmd(cmd).unwrap();
 
         let change_type = change_peer.get_change_type();
@@ -1217,7 +1412,7 @@ impl Peer {
         }
 
         let mut status = self.raft_group.status();
-        let total = status.progress.len();
+        let total = status.progress.values().filter(|pr| !pr.is_learner).count();
         if total == 1 {
             // It's always safe if there is only one node in the cluster.
             return Ok(());
@@ -1225,17 +1420,25 @@ impl Peer {
 
         match change_type {
             ConfChangeType::AddNode => {
-                status.progress.insert(peer.get_id(), Progress::default());
+                status
+                    .progress
+                    .entry(peer.get_id())
+                    .or_insert_with(Progress::default)
+                    .is_learner = false;
             }
+            ConfChangeType::AddLearnerNode => return Ok(()),
             ConfChangeType::RemoveNode => {
-                if status.progress.remove(&peer.get_id()).is_none() {
-                    // It's always safe to remove a unexisting node.
-                    return Ok(());
+                match status.progress.remove(&peer.get_id()) {
+                    // It's always safe to remove a unexisting node or a learner.
+                    None => return Ok(()),
+                    Some(ref pr) if pr.is_learner => return Ok(()),
+                    Some(_) => {}
                 }
             }
         }
         let healthy = self.count_healthy_node(status.progress.values());
-        let quorum_after_change = raft::quorum(status.progress.len());
+        let voters = status.progress.values().filter(|pr| !pr.is_learner).count();
+        let quorum_after_change = raft::quorum(voters);
         if healthy >= quorum_after_change {
             return Ok(());
         }
@@ -1263,6 +1466,83 @@ impl Peer {
         ))
     }
 
+    /// Check whether it's safe to propose the specified joint consensus conf change.
+    /// An empty change list leaves the joint state, which is always safe. Otherwise the
+    /// region must not be in joint state, voters can only be removed after being demoted,
+    /// the leader can't be demoted, and the quorum of the incoming configuration must be
+    /// healthy like `check_conf_change`.
+    fn check_conf_change_v2(&self, change_peer: &ChangePeerV2Request) -> Result<()> {
+        let in_joint = self.raft_group.raft.is_in_joint();
+        let changes = change_peer.get_changes();
+        if changes.is_empty() {
+            if !in_joint {
+                return Err(box_err!("{} not in joint state, ignore leaving", self.tag));
+            }
+            return Ok(());
+        }
+        if in_joint {
+            return Err(box_err!("{} already in joint state, leave it first", self.tag));
+        }
+
+        let mut status = self.raft_group.status();
+        for cp in changes {
+            let peer = cp.get_peer();
+            match cp.get_change_type() {
+                ConfChangeType::AddNode => {
+                    status
+                        .progress
+                        .entry(peer.get_id())
+                        .or_insert_with(Progress::default)
+                        .is_learner = false;
+                }
+                ConfChangeType::AddLearnerNode => {
+                    if peer.get_id() == self.peer_id() {
+                        return Err(box_err!("{} ignore demoting leader", self.tag));
+                    }
+                    status
+                        .progress
+                        .entry(peer.get_id())
+                        .or_insert_with(Progress::default)
+                        .is_learner = true;
+                }
+                ConfChangeType::RemoveNode => match status.progress.remove(&peer.get_id()) {
+                    Some(ref pr) if pr.is_learner => {}
+                    _ => {
+                        return Err(box_err!(
+                            "{} only learners can be removed in joint consensus, got {:?}",
+                            self.tag,
+                            peer
+                        ))
+                    }
+                },
+            }
+        }
+        let healthy = self.count_healthy_node(status.progress.values());
+        let voters = status.progress.values().filter(|pr| !pr.is_learner).count();
+        let quorum_after_change = raft::quorum(voters);
+        if healthy >= quorum_after_change {
+            return Ok(());
+        }
+
+        PEER_ADMIN_CMD_COUNTER_VEC
+            .with_label_values(&["conf_change", "reject_unsafe"])
+            .inc();
+
+        info!(
+            "{} rejects unsafe conf change request {:?}, healthy {}, quorum after change {}",
+            self.tag,
+            change_peer,
+            healthy,
+            quorum_after_change
+        );
+        Err(box_err!(
+            "unsafe to perform conf change {:?}, healthy {}, quorum after change {}",
+            change_peer,
+            healthy,
+            quorum_after_change
+        ))
+    }
+
     fn transfer_leader(&mut self, peer: &metapb::Peer) {
         info!("{} transfer leader to {:?}", self.tag, peer);
 
@@ -1301,11 +1581,23 @@ impl Peer {
         metrics.read_index += 1;
 
         let renew_lease_time = monotonic_raw_now();
-        if let Some(read) = self.pending_reads.reads.back_mut() {
-            if read.renew_lease_time + self.cfg.raft_store_max_leader_lease() > renew_lease_time {
-                read.cmds.push((req, cb));
-                return false;
+        let is_leader = self.is_leader();
+        // A follower can't merge the read into the previous one, whose read index may be
+        // decided by the leader before this read arrives.
+        if is_leader {
+            if let Some(read) = self.pending_reads.reads.back_mut() {
+                if read.renew_lease_time + self.cfg.raft_store_max_leader_lease() >
+                    renew_lease_time
+                {
+                    read.cmds.push((req, cb));
+                    return false;
+                }
             }
+        } else if self.leader_id() == INVALID_ID {
+            let mut resp = cmd_resp::new_error(Error::NotLeader(self.region_id, None));
+            cmd_resp::bind_term(&mut resp, self.term());
+            cb(resp);
+            return false;
         }
 
         // Should we call pre_propose here?
@@ -1314,12 +1606,19 @@ impl Peer {
 
         let id = self.pending_reads.next_id();
         let ctx: [u8; 8] = unsafe { mem::transmute(id) };
-        self.raft_group.read_index(ctx.to_vec());
+        let mut ctx = ctx.to_vec();
+        if !is_leader {
+            // The leader updates its max read ts with the ts of the replica read, so that no
+            // transaction is committed at or before it after the read index is returned.
+            ctx.encode_u64(req.get_header().get_read_ts()).unwrap();
+        }
+        self.raft_group.read_index(ctx);
 
         let pending_read_count = self.raft_group.raft.pending_read_count();
         let ready_read_count = self.raft_group.raft.ready_read_count();
 
-        if pending_read_count == last_pending_read_count &&
+        // The request of a follower is sent to the leader.
+        if is_leader && pending_read_count == last_pending_read_count &&
             ready_read_count == last_ready_read_count
         {
             // The message gets dropped silently, can't be handled anymore.
@@ -1331,6 +1630,7 @@ impl Peer {
             id: id,
             cmds: vec![(req, cb)],
             renew_lease_time: renew_lease_time,
+            read_index: None,
         });
 
         match self.leader_lease_expired_time {
@@ -1354,13 +1654,83 @@ impl Peer {
         true
     }
 
+    /// Returns the minimal matched index of all the followers.
+    pub fn get_min_progress(&self) -> u64 {
+        self.raft_group
+            .status()
+            .progress
+            .values()
+            .map(|pr| pr.matched)
+            .min()
+            .unwrap_or_default()
+    }
+
+    fn pre_propose_prepare_merge(&self, req: &mut RaftCmdRequest) -> Result<()> {
+        if util::is_region_in_joint(self.region()) {
+            return Err(box_err!("region is in joint state, skip merge"));
+        }
+        let last_index = self.raft_group.raft.raft_log.last_index();
+        let min_progress = self.get_min_progress();
+        let min_index = min_progress + 1;
+        if min_progress == 0 || last_index - min_progress > self.cfg.merge_max_log_gap {
+            return Err(box_err!(
+                "log gap ({}, {}] is too large, skip merge",
+                min_progress,
+                last_index
+            ));
+        }
+        // The logs in the gap will be applied by the target region on behalf of the
+        // source region, so they can't change the region meta.
+        let entries = try!(self.raft_group.raft.raft_log.entries(min_index, NO_LIMIT));
+        for entry in entries {
+            if entry.get_entry_type() == EntryType::EntryConfChange ||
+                entry.get_entry_type() == EntryType::EntryConfChangeV2
+            {
+                return Err(box_err!("log gap contains conf change, skip merging."));
+            }
+            if entry.get_data().is_empty() {
+                continue;
+            }
+            let cmd: RaftCmdRequest = parse_data_at(entry.get_data(), entry.get_index(), &self.tag);
+            if !cmd.has_admin_request() {
+                continue;
+            }
+            let cmd_type = cmd.get_admin_request().get_cmd_type();
+            match cmd_type {
+                AdminCmdType::TransferLeader |
+                AdminCmdType::ComputeHash |
+                AdminCmdType::VerifyHash |
+                AdminCmdType::InvalidAdmin => continue,
+                _ => {}
+            }
+            return Err(box_err!(
+                "log gap contains admin request {:?}, skip merging.",
+                cmd_type
+            ));
+        }
+        req.mut_admin_request()
+            .mut_prepare_merge()
+            .set_min_index(min_index);
+        Ok(())
+    }
+
     fn propose_normal(
         &mut self,
         mut req: RaftCmdRequest,
         metrics: &mut RaftProposeMetrics,
     ) -> Result<u64> {
+        if self.pending_merge_state.is_some() &&
+            req.get_admin_request().get_cmd_type() != AdminCmdType::RollbackMerge
+        {
+            return Err(box_err!("{} peer in merging mode, can't do proposal.", self.tag));
+        }
+
         metrics.normal += 1;
 
+        if req.get_admin_request().has_prepare_merge() {
+            try!(self.pre_propose_prepare_merge(&mut req));
+        }
+
         // TODO: validate request for unexpected changes.
         try!(self.coprocessor_host.pre_propose(self.region(), &mut req));
         let data = try!(req.write_to_bytes());
@@ -1438,22 +1808,39 @@ impl Peer {
         // TODO: use local histogram metrics
         PEER_PROPOSE_LOG_SIZE_HISTOGRAM.observe(data.len() as f64);
 
-        let change_peer = apply::get_change_peer_cmd(&req).unwrap();
+        let propose_index = self.next_proposal_index();
+        if let Some(change_peer) = apply::get_change_peer_v2_cmd(&req) {
+            let mut cc = eraftpb::ConfChangeV2::new();
+            for cp in change_peer.get_changes() {
+                let mut change = eraftpb::ConfChangeSingle::new();
+                change.set_change_type(cp.get_change_type());
+                change.set_node_id(cp.get_peer().get_id());
+                cc.mut_changes().push(change);
+            }
+            cc.set_context(data);
 
-        let mut cc = eraftpb::ConfChange::new();
-        cc.set_change_type(change_peer.get_change_type());
-        cc.set_node_id(change_peer.get_peer().get_id());
-        cc.set_context(data);
+            info!(
+                "{} propose conf change v2 {:?}",
+                self.tag,
+                change_peer.get_changes()
+            );
+            try!(self.raft_group.propose_conf_change_v2(cc));
+        } else {
+            let change_peer = apply::get_change_peer_cmd(&req).unwrap();
 
-        info!(
-            "{} propose conf change {:?} peer {:?}",
-            self.tag,
-            cc.get_change_type(),
-            cc.get_node_id()
-        );
+            let mut cc = eraftpb::ConfChange::new();
+            cc.set_change_type(change_peer.get_change_type());
+            cc.set_node_id(change_peer.get_peer().get_id());
+            cc.set_context(data);
 
-        let propose_index = self.next_proposal_index();
-        try!(self.raft_group.propose_conf_change(cc));
+            info!(
+                "{} propose conf change {:?} peer {:?}",
+                self.tag,
+                cc.get_change_type(),
+                cc.get_node_id()
+            );
+            try!(self.raft_group.propose_conf_change(cc));
+        }
         if self.next_proposal_index() == propose_index {
             // The message is dropped silently, this usually due to leader absence
             // or transferring leader. Both cases can be considered as NotLeader error.
@@ -1480,6 +1867,46 @@ impl Peer {
         self.raft_group.raft.term
     }
 
+    /// Returns whether the peer is the leader and no other leader can be elected before
+    /// the lease expires.
+    pub fn has_valid_leader_lease(&self) -> bool {
+        if !self.is_leader() || !self.raft_group.raft.in_lease() {
+            return false;
+        }
+        match self.leader_lease_expired_time {
+            Some(Either::Left(safe_expired_time)) => monotonic_raw_now() <= safe_expired_time,
+            _ => false,
+        }
+    }
+
+    /// Called when the ts is resolved by the leader at `applied_index`, the followers can
+    /// serve stale reads at the ts after applying the logs up to the index too.
+    pub fn on_resolved_ts<T: Transport>(
+        &mut self,
+        applied_index: u64,
+        resolved_ts: u64,
+        trans: &T,
+    ) {
+        self.read_progress.update(applied_index, resolved_ts);
+        let mut msg = new_extra_message(ExtraMessageType::MsgSafeTs);
+        msg.set_safe_ts(resolved_ts);
+        msg.set_applied_index(applied_index);
+        self.bcast_extra_message(msg, trans);
+    }
+
+    pub fn on_safe_ts(&mut self, from: &metapb::Peer, applied_index: u64, safe_ts: u64) {
+        // Only the leader knows all the locks of the region.
+        if self.is_leader() || from.get_id() != self.leader_id() {
+            return;
+        }
+        self.read_progress.update(applied_index, safe_ts);
+    }
+
+    /// Forgets the safe ts, it must be called once the range of the region is enlarged.
+    pub fn reset_read_progress(&mut self) {
+        self.read_progress.reset();
+    }
+
     pub fn stop(&mut self) {
         self.mut_store().cancel_applying_snap();
         for mut read in self.pending_reads.reads.drain(..) {
@@ -1488,6 +1915,26 @@ impl Peer {
     }
 }
 
+// Returns the ts of the replica read carried by the read index request of a follower.
+fn replica_read_ts(m: &eraftpb::Message) -> Option<u64> {
+    let ctx = match m.get_entries().first() {
+        Some(entry) => entry.get_data(),
+        None => return None,
+    };
+    // The context starts with the id of the read.
+    if ctx.len() != 16 {
+        return None;
+    }
+    let mut ts = &ctx[8..];
+    ts.decode_u64().ok()
+}
+
+fn new_extra_message(msg_type: ExtraMessageType) -> ExtraMessage {
+    let mut msg = ExtraMessage::new();
+    msg.set_field_type(msg_type);
+    msg
+}
+
 pub fn check_epoch(region: &metapb::Region, req: &RaftCmdRequest) -> Result<()> {
     let (mut check_ver, mut check_conf_ver) = (false, false);
     if req.has_admin_request() {
@@ -1497,8 +1944,11 @@ pub fn check_epoch(region: &metapb::Region, req: &RaftCmdRequest) -> Result<()>
             AdminCmdType::ComputeHash |
             AdminCmdType::VerifyHash => {}
             AdminCmdType::Split => check_ver = true,
-            AdminCmdType::ChangePeer => check_conf_ver = true,
-            AdminCmdType::TransferLeader => {
+            AdminCmdType::ChangePeer | AdminCmdType::ChangePeerV2 => check_conf_ver = true,
+            AdminCmdType::TransferLeader |
+            AdminCmdType::PrepareMerge |
+            AdminCmdType::CommitMerge |
+            AdminCmdType::RollbackMerge => {
                 check_ver = true;
                 check_conf_ver = true;
             }
@@ -1663,6 +2113,198 @@ impl Peer {
         Ok(())
     }
 
+    // Returns false if the message can't be sent.
+    fn send_extra_message<T: Transport>(
+        &self,
+        extra_msg: ExtraMessage,
+        to_peer: metapb::Peer,
+        trans: &T,
+    ) -> bool {
+        let mut send_msg = RaftMessage::new();
+        send_msg.set_region_id(self.region_id);
+        send_msg.set_region_epoch(self.region().get_region_epoch().clone());
+        send_msg.set_from_peer(self.peer.clone());
+        let to_peer_id = to_peer.get_id();
+        send_msg.set_to_peer(to_peer);
+        let msg_type = extra_msg.get_field_type();
+        send_msg.set_extra_msg(extra_msg);
+        if let Err(e) = trans.send(send_msg) {
+            warn!(
+                "{} failed to send {:?} to {}, err: {:?}",
+                self.tag,
+                msg_type,
+                to_peer_id,
+                e
+            );
+            return false;
+        }
+        true
+    }
+
+    fn bcast_extra_message<T: Transport>(&self, extra_msg: ExtraMessage, trans: &T) {
+        for peer in self.region().get_peers() {
+            if peer.get_id() != self.peer.get_id() {
+                self.send_extra_message(extra_msg.clone(), peer.clone(), trans);
+            }
+        }
+    }
+
+    #[inline]
+    pub fn is_hibernated(&self) -> bool {
+        self.hibernate_state == HibernateState::Hibernated
+    }
+
+    // The leader is idle if all the logs are replicated to and applied by all the peers,
+    // and there is nothing in flight.
+    fn is_idle(&self) -> bool {
+        let raft = &self.raft_group.raft;
+        let last_index = raft.raft_log.last_index();
+        if !self.is_leader() || raft.lead_transferee.is_some() || raft.pending_conf ||
+            raft.is_in_joint() || raft.pending_read_count() > 0 ||
+            self.pending_merge_state.is_some() || !self.proposals.queue.is_empty() ||
+            !self.apply_proposals.is_empty() || !self.pending_reads.reads.is_empty()
+        {
+            return false;
+        }
+        raft.raft_log.committed == last_index && self.get_store().applied_index() == last_index &&
+            raft.prs.values().all(|pr| pr.matched == last_index)
+    }
+
+    /// Called on every raft base tick, returns true if the peer is hibernated and should
+    /// not tick raft.
+    pub fn check_hibernate<T: Transport>(&mut self, trans: &T) -> bool {
+        match self.hibernate_state {
+            HibernateState::Hibernated => {
+                let wake_up_interval = self.cfg.hibernate_wake_up_interval.0;
+                if self.is_leader() ||
+                    self.hibernated_time
+                        .map_or(true, |t| t.elapsed() < wake_up_interval)
+                {
+                    return true;
+                }
+                debug!("{} wakes up to check the leader", self.tag);
+                self.wake_up_to_check_leader(trans);
+                false
+            }
+            _ if !self.is_leader() => false,
+            state => {
+                if !self.is_idle() {
+                    if state == HibernateState::Hibernating {
+                        self.wake_up(trans);
+                    }
+                    self.idle_ticks = 0;
+                    return false;
+                }
+                self.idle_ticks += 1;
+                if self.idle_ticks >= self.cfg.raft_election_timeout_ticks {
+                    // Ask again periodically in case some followers missed the request.
+                    self.idle_ticks = 0;
+                    self.hibernate_state = HibernateState::Hibernating;
+                    self.hibernate_responses.clear();
+                    let msg = new_extra_message(ExtraMessageType::MsgHibernateRequest);
+                    self.bcast_extra_message(msg, trans);
+                }
+                false
+            }
+        }
+    }
+
+    /// Wakes up the peer. The leader also wakes up all the followers.
+    pub fn wake_up<T: Transport>(&mut self, trans: &T) {
+        if self.hibernate_state == HibernateState::Awake {
+            return;
+        }
+        debug!("{} wakes up from {:?}", self.tag, self.hibernate_state);
+        self.hibernate_state = HibernateState::Awake;
+        self.hibernated_time = None;
+        self.idle_ticks = 0;
+        self.hibernate_responses.clear();
+        if self.is_leader() {
+            let msg = new_extra_message(ExtraMessageType::MsgRegionWakeUp);
+            self.bcast_extra_message(msg, trans);
+        }
+    }
+
+    // The leader may be dead, start ticking so a new leader can be elected if it doesn't
+    // respond.
+    fn wake_up_to_check_leader<T: Transport>(&mut self, trans: &T) {
+        self.wake_up(trans);
+        if let Some(leader) = self.get_peer_from_cache(self.leader_id()) {
+            let msg = new_extra_message(ExtraMessageType::MsgRegionWakeUp);
+            self.send_extra_message(msg, leader, trans);
+        }
+    }
+
+    /// Returns the leader if the peer is a hibernated follower.
+    pub fn hibernated_leader(&self) -> Option<metapb::Peer> {
+        if !self.is_hibernated() || self.is_leader() {
+            return None;
+        }
+        self.get_peer_from_cache(self.leader_id())
+    }
+
+    /// Sends a hibernate response to the leader as a probe of its store, which is ignored
+    /// unless the leader is asking to hibernate. Returns false if it can't be sent.
+    pub fn probe_leader<T: Transport>(&self, leader: metapb::Peer, trans: &T) -> bool {
+        let msg = new_extra_message(ExtraMessageType::MsgHibernateResponse);
+        self.send_extra_message(msg, leader, trans)
+    }
+
+    /// Wakes up the hibernated follower if its leader is on the unreachable store, returns
+    /// true if it's woken up.
+    pub fn on_store_unreachable<T: Transport>(&mut self, store_id: u64, trans: &T) -> bool {
+        if !self.hibernated_leader().map_or(false, |l| l.get_store_id() == store_id) {
+            return false;
+        }
+        debug!(
+            "{} wakes up since the store {} of the leader is unreachable",
+            self.tag,
+            store_id
+        );
+        self.wake_up_to_check_leader(trans);
+        true
+    }
+
+    pub fn on_hibernate_request<T: Transport>(&mut self, from: &metapb::Peer, trans: &T) {
+        if self.is_leader() || from.get_id() != self.leader_id() {
+            return;
+        }
+        // A follower that has not caught up with the leader stays awake.
+        let raft_log = &self.raft_group.raft.raft_log;
+        if raft_log.committed != raft_log.last_index() {
+            return;
+        }
+        if !self.is_hibernated() {
+            debug!("{} hibernates", self.tag);
+            self.hibernate_state = HibernateState::Hibernated;
+            self.hibernated_time = Some(Instant::now());
+        }
+        let msg = new_extra_message(ExtraMessageType::MsgHibernateResponse);
+        self.send_extra_message(msg, from.clone(), trans);
+    }
+
+    pub fn on_hibernate_response(&mut self, from: &metapb::Peer) {
+        if !self.is_leader() || self.hibernate_state != HibernateState::Hibernating {
+            return;
+        }
+        // Hibernated followers send no heartbeat responses, take this one instead so
+        // that they are not reported as down peers.
+        self.peer_heartbeats.insert(from.get_id(), Instant::now());
+        self.hibernate_responses.insert(from.get_id());
+        let peer_id = self.peer.get_id();
+        let responses = &self.hibernate_responses;
+        if self.raft_group
+            .raft
+            .prs
+            .keys()
+            .all(|id| *id == peer_id || responses.contains(id))
+        {
+            debug!("{} hibernates", self.tag);
+            self.hibernate_state = HibernateState::Hibernated;
+            self.hibernated_time = Some(Instant::now());
+        }
+    }
+
     fn exec_read(&mut self, req: &RaftCmdRequest) -> Result<RaftCmdResponse> {
         try!(check_epoch(self.region(), req));
         let mut snap = None;
@@ -1688,6 +2330,7 @@ impl Peer {
                 CmdType::Put |
                 CmdType::Delete |
                 CmdType::DeleteRange |
+                CmdType::IngestSST |
                 CmdType::Invalid => unreachable!(),
             };
 
@@ -1718,6 +2361,7 @@ fn get_sync_log_from_request(msg: &RaftCmdRequest) -> bool {
     if msg.has_admin_request() {
         let req = msg.get_admin_request();
         return req.get_cmd_type() == AdminCmdType::ChangePeer ||
+            req.get_cmd_type() == AdminCmdType::ChangePeerV2 ||
             req.get_cmd_type() == AdminCmdType::Split;
     }
 
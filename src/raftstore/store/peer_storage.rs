@@ -25,17 +25,19 @@ use protobuf::Message;
 
 use kvproto::metapb::{self, Region};
 use kvproto::eraftpb::{ConfState, Entry, HardState, Snapshot};
-use kvproto::raft_serverpb::{PeerState, RaftApplyState, RaftLocalState, RaftSnapshotData,
-                             RegionLocalState};
+use kvproto::raft_serverpb::{MergeState, PeerState, RaftApplyState, RaftLocalState,
+                             RaftSnapshotData, RegionLocalState};
 use util::worker::Scheduler;
 use util::{self, rocksdb};
 use raft::{self, Error as RaftError, RaftState, Ready, Storage, StorageError};
+use raft_engine::{Error as RaftEngineError, LogBatch, RaftEngine};
 use raftstore::{Error, Result};
 use super::worker::RegionTask;
 use super::keys::{self, enc_end_key, enc_start_key};
 use super::engine::{Iterable, Mutable, Peekable, Snapshot as DbSnapshot};
 use super::peer::ReadyContext;
 use super::metrics::*;
+use super::util::conf_state_from_region;
 use super::{SnapEntry, SnapKey, SnapManager, SnapshotStatistics};
 use storage::CF_RAFT;
 
@@ -258,7 +260,7 @@ impl CacheQueryStats {
 
 pub struct PeerStorage {
     pub kv_engine: Arc<DB>,
-    pub raft_engine: Arc<DB>,
+    pub raft_engine: Arc<RaftEngine>,
 
     pub region: metapb::Region,
     pub raft_state: RaftLocalState,
@@ -326,8 +328,8 @@ impl InvokeContext {
     }
 
     #[inline]
-    pub fn save_raft_state_to(&self, raft_wb: &mut WriteBatch) -> Result<()> {
-        try!(raft_wb.put_msg(&keys::raft_state_key(self.region_id), &self.raft_state));
+    pub fn save_raft_state_to(&self, raft_wb: &mut LogBatch) -> Result<()> {
+        raft_wb.put_raft_state(self.region_id, &self.raft_state);
         Ok(())
     }
 
@@ -367,8 +369,8 @@ impl InvokeContext {
 
 pub fn recover_from_applying_state(
     kv_engine: &DB,
-    raft_engine: &DB,
-    raft_wb: &WriteBatch,
+    raft_engine: &RaftEngine,
+    raft_wb: &mut LogBatch,
     region_id: u64,
 ) -> Result<()> {
     let snapshot_raft_state_key = keys::snapshot_raft_state_key(region_id);
@@ -384,8 +386,7 @@ pub fn recover_from_applying_state(
             }
         };
 
-    let raft_state_key = keys::raft_state_key(region_id);
-    let raft_state: RaftLocalState = match box_try!(raft_engine.get_msg(&raft_state_key)) {
+    let raft_state = match box_try!(raft_engine.get_raft_state(region_id)) {
         Some(state) => state,
         None => RaftLocalState::new(),
     };
@@ -397,14 +398,13 @@ pub fn recover_from_applying_state(
     // (snapshot_raft_state), and set snapshot_raft_state.last_index = snapshot_index.
     // after restart, we need check last_index.
     if last_index(&snapshot_raft_state) > last_index(&raft_state) {
-        try!(raft_wb.put_msg(&raft_state_key, &snapshot_raft_state));
+        raft_wb.put_raft_state(region_id, &snapshot_raft_state);
     }
     Ok(())
 }
 
-fn init_raft_state(raft_engine: &DB, region: &Region) -> Result<RaftLocalState> {
-    let state_key = keys::raft_state_key(region.get_id());
-    Ok(match try!(raft_engine.get_msg(&state_key)) {
+fn init_raft_state(raft_engine: &RaftEngine, region: &Region) -> Result<RaftLocalState> {
+    Ok(match box_try!(raft_engine.get_raft_state(region.get_id())) {
         Some(s) => s,
         None => {
             let mut raft_state = RaftLocalState::new();
@@ -413,7 +413,9 @@ fn init_raft_state(raft_engine: &DB, region: &Region) -> Result<RaftLocalState>
                 raft_state.set_last_index(RAFT_INIT_LOG_INDEX);
                 raft_state.mut_hard_state().set_term(RAFT_INIT_LOG_TERM);
                 raft_state.mut_hard_state().set_commit(RAFT_INIT_LOG_INDEX);
-                try!(raft_engine.put_msg(&state_key, &raft_state));
+                let mut raft_wb = LogBatch::new();
+                raft_wb.put_raft_state(region.get_id(), &raft_state);
+                box_try!(raft_engine.consume(&mut raft_wb, false));
             }
             raft_state
         }
@@ -439,7 +441,7 @@ fn init_apply_state(kv_engine: &DB, region: &Region) -> Result<RaftApplyState> {
 }
 
 fn init_last_term(
-    raft_engine: &DB,
+    raft_engine: &RaftEngine,
     region: &Region,
     raft_state: &RaftLocalState,
     apply_state: &RaftApplyState,
@@ -454,8 +456,7 @@ fn init_last_term(
     } else {
         assert!(last_idx > RAFT_INIT_LOG_INDEX);
     }
-    let last_log_key = keys::raft_log_key(region.get_id(), last_idx);
-    Ok(match try!(raft_engine.get_msg::<Entry>(&last_log_key)) {
+    Ok(match box_try!(raft_engine.get_entry(region.get_id(), last_idx)) {
         None => {
             return Err(box_err!(
                 "[region {}] entry at {} doesn't exist, may lose data.",
@@ -470,7 +471,7 @@ fn init_last_term(
 impl PeerStorage {
     pub fn new(
         kv_engine: Arc<DB>,
-        raft_engine: Arc<DB>,
+        raft_engine: Arc<RaftEngine>,
         region: &metapb::Region,
         region_sched: Scheduler<RegionTask>,
         tag: String,
@@ -517,7 +518,6 @@ impl PeerStorage {
 
     pub fn initial_state(&self) -> raft::Result<RaftState> {
         let hard_state = self.raft_state.get_hard_state().clone();
-        let mut conf_state = ConfState::new();
         if hard_state == HardState::new() {
             assert!(
                 !self.is_initialized(),
@@ -529,17 +529,13 @@ impl PeerStorage {
 
             return Ok(RaftState {
                 hard_state: hard_state,
-                conf_state: conf_state,
+                conf_state: ConfState::new(),
             });
         }
 
-        for p in self.region.get_peers() {
-            conf_state.mut_nodes().push(p.get_id());
-        }
-
         Ok(RaftState {
             hard_state: hard_state,
-            conf_state: conf_state,
+            conf_state: conf_state_from_region(&self.region),
         })
     }
 
@@ -577,7 +573,7 @@ impl PeerStorage {
         let begin_idx = if low < cache_low {
             self.stats.borrow_mut().miss += 1;
             fetched_size = try!(self.fetch_entries_to(low, cache_low, max_size, &mut ents));
-            if fetched_size > max_size {
+            if fetched_size > max_size || ents.len() < (cache_low - low) as usize {
                 // max_size exceed.
                 return Ok(ents);
             }
@@ -599,66 +595,21 @@ impl PeerStorage {
         max_size: u64,
         buf: &mut Vec<Entry>,
     ) -> raft::Result<u64> {
-        let mut total_size: u64 = 0;
-        let mut next_index = low;
-        let mut exceeded_max_size = false;
-        if high - low <= RAFT_LOG_MULTI_GET_CNT {
-            // If election happens in inactive regions, they will just try
-            // to fetch one empty log.
-            for i in low..high {
-                let key = keys::raft_log_key(self.get_region_id(), i);
-                match box_try!(self.raft_engine.get(&key)) {
-                    None => return Err(RaftError::Store(StorageError::Unavailable)),
-                    Some(v) => {
-                        let mut entry = Entry::new();
-                        box_try!(entry.merge_from_bytes(&v));
-                        assert_eq!(entry.get_index(), i);
-                        total_size += v.len() as u64;
-                        if buf.is_empty() || total_size <= max_size {
-                            buf.push(entry);
-                        }
-                        if total_size > max_size {
-                            break;
-                        }
-                    }
-                }
-            }
-            return Ok(total_size);
-        }
-
-        let start_key = keys::raft_log_key(self.get_region_id(), low);
-        let end_key = keys::raft_log_key(self.get_region_id(), high);
-        try!(self.raft_engine.scan(
-            &start_key,
-            &end_key,
-            true, // fill_cache
-            &mut |_, value| {
-                let mut entry = Entry::new();
-                try!(entry.merge_from_bytes(value));
-
-                // May meet gap or has been compacted.
-                if entry.get_index() != next_index {
-                    return Ok(false);
-                }
-                next_index += 1;
-
-                total_size += value.len() as u64;
-                exceeded_max_size = total_size > max_size;
-                if !exceeded_max_size || buf.is_empty() {
-                    buf.push(entry);
-                }
-                Ok(!exceeded_max_size)
+        let res = self.raft_engine.fetch_entries_to(
+            self.get_region_id(),
+            low,
+            high,
+            Some(max_size as usize),
+            buf,
+        );
+        match res {
+            Ok(size) => Ok(size as u64),
+            // May meet gap or has been compacted.
+            Err(RaftEngineError::EntryNotFound(..)) => {
+                Err(RaftError::Store(StorageError::Unavailable))
             }
-        ));
-
-        // If we get the correct number of entries, returns,
-        // or the total size almost exceeds max_size, returns.
-        if buf.len() == (high - low) as usize || exceeded_max_size {
-            return Ok(total_size);
+            Err(e) => Err(storage_error(e)),
         }
-
-        // Here means we don't fetch enough entries.
-        Err(RaftError::Store(StorageError::Unavailable))
     }
 
     pub fn term(&self, idx: u64) -> raft::Result<u64> {
@@ -852,20 +803,11 @@ impl PeerStorage {
             if entry.get_sync_log() {
                 ready_ctx.sync_log = true;
             }
-            try!(ready_ctx.raft_wb.put_msg(
-                &keys::raft_log_key(self.get_region_id(), entry.get_index()),
-                entry
-            ));
-        }
-
-        // Delete any previously appended log entries which never committed.
-        for i in (last_index + 1)..(prev_last_index + 1) {
-            try!(
-                ready_ctx
-                    .raft_wb
-                    .delete(&keys::raft_log_key(self.get_region_id(), i))
-            );
         }
+        // Any previously appended log entries after them, which never committed, are deleted.
+        ready_ctx
+            .raft_wb
+            .add_entries(self.get_region_id(), prev_last_index, entries.to_vec());
 
         invoke_ctx.raft_state.set_last_index(last_index);
         invoke_ctx.last_term = last_term;
@@ -885,7 +827,7 @@ impl PeerStorage {
         ctx: &mut InvokeContext,
         snap: &Snapshot,
         kv_wb: &WriteBatch,
-        raft_wb: &WriteBatch,
+        raft_wb: &mut LogBatch,
     ) -> Result<()> {
         info!("{} begin to apply snapshot", self.tag);
 
@@ -912,7 +854,8 @@ impl PeerStorage {
             &self.kv_engine,
             kv_wb,
             &region,
-            PeerState::Applying
+            PeerState::Applying,
+            None
         ));
 
         let last_index = snap.get_metadata().get_index();
@@ -940,16 +883,9 @@ impl PeerStorage {
     }
 
     /// Delete all meta belong to the region. Results are stored in `wb`.
-    pub fn clear_meta(&mut self, kv_wb: &WriteBatch, raft_wb: &WriteBatch) -> Result<()> {
+    pub fn clear_meta(&mut self, kv_wb: &WriteBatch, raft_wb: &mut LogBatch) -> Result<()> {
         let region_id = self.get_region_id();
-        try!(clear_meta(
-            &self.kv_engine,
-            &self.raft_engine,
-            kv_wb,
-            raft_wb,
-            region_id,
-            &self.raft_state
-        ));
+        try!(clear_meta(&self.kv_engine, kv_wb, raft_wb, region_id));
         self.cache = EntryCache::default();
         Ok(())
     }
@@ -992,7 +928,7 @@ impl PeerStorage {
         Ok(())
     }
 
-    pub fn get_raft_engine(&self) -> Arc<DB> {
+    pub fn get_raft_engine(&self) -> Arc<RaftEngine> {
         self.raft_engine.clone()
     }
 
@@ -1112,7 +1048,7 @@ impl PeerStorage {
                 &mut ctx,
                 &ready.snapshot,
                 &ready_ctx.kv_wb,
-                &ready_ctx.raft_wb
+                &mut ready_ctx.raft_wb
             ));
             last_index(&ctx.raft_state)
         };
@@ -1195,42 +1131,22 @@ impl PeerStorage {
     }
 }
 
-/// Delete all meta belong to the region. Results are stored in `wb`.
+/// Delete all meta belong to the region. Results are stored in `kv_wb` and `raft_wb`.
 pub fn clear_meta(
     kv_engine: &DB,
-    raft_engine: &DB,
     kv_wb: &WriteBatch,
-    raft_wb: &WriteBatch,
+    raft_wb: &mut LogBatch,
     region_id: u64,
-    raft_state: &RaftLocalState,
 ) -> Result<()> {
     let t = Instant::now();
     let handle = try!(rocksdb::get_cf_handle(kv_engine, CF_RAFT));
     try!(kv_wb.delete_cf(handle, &keys::region_state_key(region_id)));
     try!(kv_wb.delete_cf(handle, &keys::apply_state_key(region_id)));
-
-    let last_index = last_index(raft_state);
-    let mut first_index = last_index + 1;
-    let begin_log_key = keys::raft_log_key(region_id, 0);
-    let end_log_key = keys::raft_log_key(region_id, first_index);
-    try!(raft_engine.scan(
-        &begin_log_key,
-        &end_log_key,
-        false,
-        &mut |key, _| {
-            first_index = keys::raft_log_index(key).unwrap();
-            Ok(false)
-        }
-    ));
-    for id in first_index..last_index + 1 {
-        try!(raft_wb.delete(&keys::raft_log_key(region_id, id)));
-    }
-    try!(raft_wb.delete(&keys::raft_state_key(region_id)));
+    raft_wb.clean(region_id);
 
     info!(
-        "[region {}] clear peer 1 meta key, 1 apply key, 1 raft key and {} raft logs, takes {:?}",
+        "[region {}] clear peer 1 meta key, 1 apply key, the raft state and raft logs, takes {:?}",
         region_id,
-        last_index + 1 - first_index,
         t.elapsed()
     );
     Ok(())
@@ -1238,7 +1154,7 @@ pub fn clear_meta(
 
 pub fn do_snapshot(
     mgr: SnapManager,
-    raft_db: &DB,
+    raft_engine: &RaftEngine,
     snap: &DbSnapshot,
     region_id: u64,
 ) -> raft::Result<Snapshot> {
@@ -1259,7 +1175,7 @@ pub fn do_snapshot(
     let term = if idx == apply_state.get_truncated_state().get_index() {
         apply_state.get_truncated_state().get_term()
     } else {
-        match try!(raft_db.get_msg::<Entry>(&keys::raft_log_key(region_id, idx))) {
+        match box_try!(raft_engine.get_entry(region_id, idx)) {
             None => return Err(box_err!("entry {} of {} not found.", idx, region_id)),
             Some(entry) => entry.get_term(),
         }
@@ -1290,11 +1206,7 @@ pub fn do_snapshot(
     snapshot.mut_metadata().set_index(key.idx);
     snapshot.mut_metadata().set_term(key.term);
 
-    let mut conf_state = ConfState::new();
-    for p in state.get_region().get_peers() {
-        conf_state.mut_nodes().push(p.get_id());
-    }
-
+    let conf_state = conf_state_from_region(state.get_region());
     snapshot.mut_metadata().set_conf_state(conf_state);
 
     let mut s = try!(mgr.get_snapshot_for_building(&key, snap));
@@ -1320,13 +1232,13 @@ pub fn do_snapshot(
 }
 
 // When we bootstrap the region we must call this to initialize region local state first.
-pub fn write_initial_raft_state<T: Mutable>(raft_wb: &T, region_id: u64) -> Result<()> {
+pub fn write_initial_raft_state(raft_wb: &mut LogBatch, region_id: u64) -> Result<()> {
     let mut raft_state = RaftLocalState::new();
     raft_state.set_last_index(RAFT_INIT_LOG_INDEX);
     raft_state.mut_hard_state().set_term(RAFT_INIT_LOG_TERM);
     raft_state.mut_hard_state().set_commit(RAFT_INIT_LOG_INDEX);
 
-    try!(raft_wb.put_msg(&keys::raft_state_key(region_id), &raft_state));
+    raft_wb.put_raft_state(region_id, &raft_state);
     Ok(())
 }
 
@@ -1356,11 +1268,15 @@ pub fn write_peer_state<T: Mutable>(
     kv_wb: &T,
     region: &metapb::Region,
     state: PeerState,
+    merge_state: Option<MergeState>,
 ) -> Result<()> {
     let region_id = region.get_id();
     let mut region_state = RegionLocalState::new();
     region_state.set_state(state);
     region_state.set_region(region.clone());
+    if let Some(state) = merge_state {
+        region_state.set_merge_state(state);
+    }
     let handle = try!(rocksdb::get_cf_handle(kv_engine, CF_RAFT));
     try!(kv_wb.put_msg_cf(handle, &keys::region_state_key(region_id), &region_state));
     Ok(())
@@ -1412,23 +1328,46 @@ mod test {
     use raftstore::store::local_metrics::RaftMetrics;
     use util::worker::{Scheduler, Worker};
     use util::rocksdb::new_engine;
+    use raft_engine::{Config as RaftLogConfig, RaftLogEngine};
     use storage::{ALL_CFS, CF_DEFAULT};
     use kvproto::eraftpb::HardState;
     use rocksdb::WriteBatch;
 
     use super::*;
 
-    fn new_storage(sched: Scheduler<RegionTask>, path: &TempDir) -> PeerStorage {
+    fn new_engines(path: &TempDir, raft_log_engine: bool) -> Engines {
         let kv_db = Arc::new(new_engine(path.path().to_str().unwrap(), ALL_CFS).unwrap());
         let raft_path = path.path().join(Path::new("raft"));
         let raft_db = Arc::new(
             new_engine(raft_path.to_str().unwrap(), &[CF_DEFAULT]).unwrap(),
         );
-        let engines = Engines::new(kv_db.clone(), raft_db.clone());
+        if !raft_log_engine {
+            return Engines::new(kv_db, raft_db);
+        }
+        let cfg = RaftLogConfig {
+            dir: path.path().join("raft-log").to_str().unwrap().to_owned(),
+            ..RaftLogConfig::default()
+        };
+        let raft_log = Arc::new(RaftLogEngine::open(&cfg).unwrap());
+        Engines::with_raft_log(kv_db, raft_db, raft_log)
+    }
+
+    fn new_storage_with_engines(sched: Scheduler<RegionTask>, engines: Engines) -> PeerStorage {
         bootstrap::bootstrap_store(&engines, 1, 1).expect("");
         let region = bootstrap::prepare_bootstrap(&engines, 1, 1, 1).expect("");
         let metrics = Rc::new(RefCell::new(CacheQueryStats::default()));
-        PeerStorage::new(kv_db, raft_db, &region, sched, "".to_owned(), metrics).unwrap()
+        PeerStorage::new(
+            engines.kv_engine,
+            engines.raft_log,
+            &region,
+            sched,
+            "".to_owned(),
+            metrics,
+        ).unwrap()
+    }
+
+    fn new_storage(sched: Scheduler<RegionTask>, path: &TempDir) -> PeerStorage {
+        new_storage_with_engines(sched, new_engines(path, false))
     }
 
     fn new_storage_from_ents(
@@ -1455,7 +1394,10 @@ mod test {
             .set_applied_index(ents.last().unwrap().get_index());
         ctx.save_apply_state_to(&store.kv_engine, &mut kv_wb)
             .unwrap();
-        store.raft_engine.write(ready_ctx.raft_wb).expect("");
+        store
+            .raft_engine
+            .consume(&mut ready_ctx.raft_wb, true)
+            .expect("");
         store.kv_engine.write(kv_wb).expect("");
         store.raft_state = ctx.raft_state;
         store.apply_state = ctx.apply_state;
@@ -1469,17 +1411,21 @@ mod test {
         let mut ready_ctx = ReadyContext::new(&mut metrics, &trans, ents.len());
         store.append(&mut ctx, ents, &mut ready_ctx).unwrap();
         ctx.save_raft_state_to(&mut ready_ctx.raft_wb).unwrap();
-        store.raft_engine.write(ready_ctx.raft_wb).expect("");
+        store
+            .raft_engine
+            .consume(&mut ready_ctx.raft_wb, true)
+            .expect("");
         store.raft_state = ctx.raft_state;
     }
 
     fn validate_cache(store: &PeerStorage, exp_ents: &[Entry]) {
         assert_eq!(store.cache.cache, exp_ents);
         for e in exp_ents {
-            let key = keys::raft_log_key(store.get_region_id(), e.get_index());
-            let bytes = store.raft_engine.get(&key).unwrap().unwrap();
-            let mut entry = Entry::new();
-            entry.merge_from_bytes(&bytes).unwrap();
+            let entry = store
+                .raft_engine
+                .get_entry(store.get_region_id(), e.get_index())
+                .unwrap()
+                .unwrap();
             assert_eq!(entry, *e);
         }
     }
@@ -1544,13 +1490,12 @@ mod test {
             })
             .unwrap();
 
-        store
-            .raft_engine
-            .scan(&raft_start, &raft_end, false, &mut |_, _| {
-                count += 1;
-                Ok(true)
-            })
-            .unwrap();
+        if let Some((first, last)) = store.raft_engine.entry_range(region_id).unwrap() {
+            count += (last + 1 - first) as usize;
+        }
+        if store.raft_engine.get_raft_state(region_id).unwrap().is_some() {
+            count += 1;
+        }
 
         count
     }
@@ -1566,14 +1511,52 @@ mod test {
         assert_eq!(6, get_meta_key_count(&store));
 
         let kv_wb = WriteBatch::new();
-        let raft_wb = WriteBatch::new();
-        store.clear_meta(&kv_wb, &raft_wb).unwrap();
+        let mut raft_wb = LogBatch::new();
+        store.clear_meta(&kv_wb, &mut raft_wb).unwrap();
         store.kv_engine.write(kv_wb).unwrap();
-        store.raft_engine.write(raft_wb).unwrap();
+        store.raft_engine.consume(&mut raft_wb, true).unwrap();
 
         assert_eq!(0, get_meta_key_count(&store));
     }
 
+    #[test]
+    fn test_storage_raft_log_engine() {
+        let td = TempDir::new("tikv-store-test").unwrap();
+        let worker = Worker::new("snap_manager");
+        let engines = new_engines(&td, true);
+        let mut store = new_storage_with_engines(worker.scheduler(), engines);
+        append_ents(
+            &mut store,
+            &[new_entry(6, 5), new_entry(7, 5), new_entry(8, 5)],
+        );
+        // The conflicting entries are replaced.
+        append_ents(&mut store, &[new_entry(7, 6)]);
+        assert_eq!(store.last_index(), 7);
+
+        // Read from the engine instead of the cache.
+        store.cache = EntryCache::default();
+        assert_eq!(
+            store.entries(6, 8, u64::max_value()),
+            Ok(vec![new_entry(6, 5), new_entry(7, 6)])
+        );
+        assert_eq!(store.term(7), Ok(6));
+
+        let raft_engine = store.raft_engine.clone();
+        assert_eq!(
+            raft_engine.get_raft_state(1).unwrap(),
+            Some(store.raft_state.clone())
+        );
+        assert_eq!(raft_engine.entry_range(1).unwrap(), Some((6, 7)));
+
+        let kv_wb = WriteBatch::new();
+        let mut raft_wb = LogBatch::new();
+        store.clear_meta(&kv_wb, &mut raft_wb).unwrap();
+        store.kv_engine.write(kv_wb).unwrap();
+        raft_engine.consume(&mut raft_wb, true).unwrap();
+        assert_eq!(0, get_meta_key_count(&store));
+        assert!(raft_engine.raft_groups().unwrap().is_empty());
+    }
+
     #[test]
     fn test_storage_entries() {
         let ents = vec![
@@ -1741,7 +1724,9 @@ mod test {
         ctx.save_raft_state_to(&mut ready_ctx.raft_wb).unwrap();
         ctx.save_apply_state_to(&s.kv_engine, &mut kv_wb).unwrap();
         s.kv_engine.write(kv_wb).unwrap();
-        s.raft_engine.write(ready_ctx.raft_wb).unwrap();
+        s.raft_engine
+            .consume(&mut ready_ctx.raft_wb, true)
+            .unwrap();
         s.apply_state = ctx.apply_state;
         s.raft_state = ctx.raft_state;
         ctx = InvokeContext::new(&s);
@@ -1992,8 +1977,8 @@ mod test {
         let mut ctx = InvokeContext::new(&s2);
         assert_ne!(ctx.last_term, snap1.get_metadata().get_term());
         let kv_wb = WriteBatch::new();
-        let raft_wb = WriteBatch::new();
-        s2.apply_snapshot(&mut ctx, &snap1, &kv_wb, &raft_wb)
+        let mut raft_wb = LogBatch::new();
+        s2.apply_snapshot(&mut ctx, &snap1, &kv_wb, &mut raft_wb)
             .unwrap();
         assert_eq!(ctx.last_term, snap1.get_metadata().get_term());
         assert_eq!(ctx.apply_state.get_applied_index(), 6);
@@ -2010,8 +1995,8 @@ mod test {
         let mut ctx = InvokeContext::new(&s3);
         assert_ne!(ctx.last_term, snap1.get_metadata().get_term());
         let kv_wb = WriteBatch::new();
-        let raft_wb = WriteBatch::new();
-        s3.apply_snapshot(&mut ctx, &snap1, &kv_wb, &raft_wb)
+        let mut raft_wb = LogBatch::new();
+        s3.apply_snapshot(&mut ctx, &snap1, &kv_wb, &mut raft_wb)
             .unwrap();
         assert_eq!(ctx.last_term, snap1.get_metadata().get_term());
         assert_eq!(ctx.apply_state.get_applied_index(), 6);
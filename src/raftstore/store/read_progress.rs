@@ -0,0 +1,109 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp;
+use std::collections::VecDeque;
+
+// The max number of resolved ts waiting for their logs to be applied.
+const MAX_PENDING_RESOLVED_TS: usize = 16;
+
+/// Tracks the safe ts of a peer, reads at or before it can be served by the peer without
+/// contacting the leader.
+///
+/// The leader resolves the ts at an applied index, and the ts is safe on a peer only after the
+/// peer applies the logs up to the index, so it's pending until then.
+#[derive(Default, Debug)]
+pub struct ReadProgress {
+    safe_ts: u64,
+    // (applied index, resolved ts) in ascending order.
+    pending: VecDeque<(u64, u64)>,
+}
+
+impl ReadProgress {
+    pub fn update(&mut self, applied_index: u64, resolved_ts: u64) {
+        if resolved_ts <= self.safe_ts {
+            return;
+        }
+        if let Some(&(index, ts)) = self.pending.back() {
+            // Outdated messages are ignored.
+            if applied_index < index || resolved_ts <= ts {
+                return;
+            }
+        }
+        if self.pending.len() >= MAX_PENDING_RESOLVED_TS {
+            // Replace the newest one so that the oldest ones still take effect in time.
+            *self.pending.back_mut().unwrap() = (applied_index, resolved_ts);
+        } else {
+            self.pending.push_back((applied_index, resolved_ts));
+        }
+    }
+
+    /// Returns the safe ts after the logs up to `applied_index` are applied.
+    pub fn safe_ts(&mut self, applied_index: u64) -> u64 {
+        loop {
+            let (index, ts) = match self.pending.front() {
+                Some(&(index, ts)) => (index, ts),
+                None => break,
+            };
+            if index > applied_index {
+                break;
+            }
+            self.safe_ts = cmp::max(self.safe_ts, ts);
+            self.pending.pop_front();
+        }
+        self.safe_ts
+    }
+
+    pub fn reset(&mut self) {
+        self.safe_ts = 0;
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_progress() {
+        let mut progress = ReadProgress::default();
+        progress.update(10, 100);
+        assert_eq!(progress.safe_ts(9), 0);
+        assert_eq!(progress.safe_ts(10), 100);
+
+        // Outdated ones are ignored.
+        progress.update(12, 100);
+        progress.update(15, 120);
+        progress.update(14, 130);
+        progress.update(16, 110);
+        assert_eq!(progress.safe_ts(20), 120);
+
+        progress.update(21, 130);
+        progress.update(22, 140);
+        assert_eq!(progress.safe_ts(21), 130);
+        assert_eq!(progress.safe_ts(21), 130);
+        assert_eq!(progress.safe_ts(22), 140);
+
+        // The newest one is replaced when there are too many pending.
+        for i in 0..MAX_PENDING_RESOLVED_TS as u64 + 1 {
+            progress.update(30 + i, 150 + i);
+        }
+        assert_eq!(progress.safe_ts(30), 150);
+        let last = MAX_PENDING_RESOLVED_TS as u64;
+        assert_eq!(progress.safe_ts(30 + last - 1), 150 + last - 2);
+        assert_eq!(progress.safe_ts(30 + last), 150 + last);
+
+        progress.reset();
+        assert_eq!(progress.safe_ts(100), 0);
+    }
+}
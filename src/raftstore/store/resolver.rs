@@ -0,0 +1,162 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp;
+use std::collections::BTreeMap;
+
+use util::collections::{HashMap, HashSet};
+
+/// Tracks the locks of a region to calculate its resolved ts.
+///
+/// A lock is committed with a ts larger than its start ts, so no transaction can be committed
+/// at or before the resolved ts, which is the min of the start ts of the locks and the given
+/// `min_ts`.
+#[derive(Default, Debug)]
+pub struct Resolver {
+    // start ts -> encoded keys
+    locks: BTreeMap<u64, HashSet<Vec<u8>>>,
+    // encoded key -> start ts
+    lock_ts: HashMap<Vec<u8>, u64>,
+    resolved_ts: u64,
+}
+
+impl Resolver {
+    pub fn new() -> Resolver {
+        Resolver::default()
+    }
+
+    pub fn resolved_ts(&self) -> u64 {
+        self.resolved_ts
+    }
+
+    pub fn track_lock(&mut self, start_ts: u64, key: Vec<u8>) {
+        if let Some(ts) = self.lock_ts.insert(key.clone(), start_ts) {
+            self.remove_lock(ts, &key);
+        }
+        self.locks
+            .entry(start_ts)
+            .or_insert_with(HashSet::default)
+            .insert(key);
+    }
+
+    pub fn untrack_lock(&mut self, key: &[u8]) {
+        if let Some(ts) = self.lock_ts.remove(key) {
+            self.remove_lock(ts, key);
+        }
+    }
+
+    fn remove_lock(&mut self, start_ts: u64, key: &[u8]) {
+        let empty = match self.locks.get_mut(&start_ts) {
+            Some(keys) => {
+                keys.remove(key);
+                keys.is_empty()
+            }
+            None => false,
+        };
+        if empty {
+            self.locks.remove(&start_ts);
+        }
+    }
+
+    /// Advances the resolved ts with `min_ts`, it never goes back.
+    pub fn resolve(&mut self, min_ts: u64) -> u64 {
+        let ts = match self.locks.keys().next() {
+            Some(&start_ts) => cmp::min(start_ts, min_ts),
+            None => min_ts,
+        };
+        if ts > self.resolved_ts {
+            self.resolved_ts = ts;
+        }
+        self.resolved_ts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let mut resolver = Resolver::new();
+        assert_eq!(resolver.resolve(10), 10);
+
+        resolver.track_lock(20, b"k1".to_vec());
+        resolver.track_lock(15, b"k2".to_vec());
+        assert_eq!(resolver.resolve(30), 15);
+        // The resolved ts never goes back.
+        assert_eq!(resolver.resolve(12), 15);
+
+        resolver.untrack_lock(b"k2");
+        assert_eq!(resolver.resolve(30), 20);
+        // A lock is replaced by the one with the same key.
+        resolver.track_lock(25, b"k1".to_vec());
+        assert_eq!(resolver.resolve(30), 25);
+        resolver.untrack_lock(b"k1");
+        resolver.untrack_lock(b"k3");
+        assert_eq!(resolver.resolve(30), 30);
+        assert_eq!(resolver.resolved_ts(), 30);
+    }
+
+    #[test]
+    fn test_track_lock() {
+        let mut resolver = Resolver::new();
+        resolver.track_lock(10, b"k1".to_vec());
+        resolver.track_lock(10, b"k2".to_vec());
+        resolver.track_lock(20, b"k3".to_vec());
+        assert_eq!(resolver.locks[&10].len(), 2);
+        assert_eq!(resolver.lock_ts[&b"k3".to_vec()], 20);
+
+        // The ts stays tracked until all the keys locked at it are untracked.
+        resolver.untrack_lock(b"k1");
+        assert_eq!(resolver.locks[&10].len(), 1);
+        resolver.untrack_lock(b"k2");
+        assert!(!resolver.locks.contains_key(&10));
+        assert!(!resolver.lock_ts.contains_key(&b"k2".to_vec()));
+
+        // Relocking a key moves it to the new ts.
+        resolver.track_lock(30, b"k3".to_vec());
+        assert!(!resolver.locks.contains_key(&20));
+        assert_eq!(resolver.lock_ts[&b"k3".to_vec()], 30);
+        resolver.untrack_lock(b"k3");
+        assert!(resolver.locks.is_empty());
+        assert!(resolver.lock_ts.is_empty());
+    }
+
+    #[test]
+    fn test_resolve_min_lock() {
+        let mut resolver = Resolver::new();
+        resolver.track_lock(30, b"k1".to_vec());
+        resolver.track_lock(10, b"k2".to_vec());
+        resolver.track_lock(20, b"k3".to_vec());
+        assert_eq!(resolver.resolve(50), 10);
+        // Bounded by `min_ts` when it's smaller than all the locks.
+        let mut resolver = Resolver::new();
+        resolver.track_lock(30, b"k1".to_vec());
+        assert_eq!(resolver.resolve(25), 25);
+        assert_eq!(resolver.resolve(35), 30);
+    }
+
+    #[test]
+    fn test_resolve_after_rollback() {
+        let mut resolver = Resolver::new();
+        resolver.track_lock(10, b"k1".to_vec());
+        resolver.track_lock(20, b"k2".to_vec());
+        assert_eq!(resolver.resolve(50), 10);
+
+        // A rolled back lock is deleted from the lock cf, the resolved ts advances past it.
+        resolver.untrack_lock(b"k1");
+        assert_eq!(resolver.resolve(50), 20);
+        resolver.untrack_lock(b"k2");
+        assert_eq!(resolver.resolve(50), 50);
+    }
+}
@@ -0,0 +1,303 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use std::mem;
+use std::time::Duration;
+
+use kvproto::raft_cmdpb::{CmdType, RaftCmdRequest};
+use rand::{self, Rng};
+
+use storage::{CF_DEFAULT, CF_LOCK};
+use util::collections::HashMap;
+use util::time::duration_to_sec;
+
+// The max number of the sampled keys of a region.
+const MAX_SAMPLE_NUM: usize = 20;
+
+/// The requests of a region in a period, and the keys of some of them.
+#[derive(Clone, Debug, Default)]
+pub struct RegionLoad {
+    pub requests: u64,
+    // The number of the requests with a key, the keys are sampled from them.
+    sampled_requests: u64,
+    pub keys: Vec<Vec<u8>>,
+}
+
+impl RegionLoad {
+    // Samples the key, which stands for `weight` requests.
+    fn sample(&mut self, key: &[u8], weight: u64) {
+        self.sampled_requests += weight;
+        if self.keys.len() < MAX_SAMPLE_NUM {
+            self.keys.push(key.to_vec());
+            return;
+        }
+        let mut rng = rand::thread_rng();
+        if rng.gen_range(0, self.sampled_requests) < weight * MAX_SAMPLE_NUM as u64 {
+            let idx = rng.gen_range(0, MAX_SAMPLE_NUM);
+            self.keys[idx] = key.to_vec();
+        }
+    }
+
+    fn merge(&mut self, other: RegionLoad) {
+        self.requests += other.requests;
+        if other.keys.is_empty() {
+            return;
+        }
+        let weight = other.sampled_requests / other.keys.len() as u64;
+        for key in &other.keys {
+            self.sample(key, weight);
+        }
+        self.sampled_requests += other.sampled_requests % other.keys.len() as u64;
+    }
+}
+
+/// The load of the regions, collected by the paths serving the requests and sent to the store
+/// periodically.
+#[derive(Clone, Debug, Default)]
+pub struct LoadStats {
+    regions: HashMap<u64, RegionLoad>,
+}
+
+impl LoadStats {
+    /// Adds a request of the region, `key` is the encoded key it accesses first.
+    pub fn add_request(&mut self, region_id: u64, key: Option<&[u8]>) {
+        let load = self.regions
+            .entry(region_id)
+            .or_insert_with(RegionLoad::default);
+        load.requests += 1;
+        if let Some(key) = key {
+            load.sample(key, 1);
+        }
+    }
+
+    pub fn merge(&mut self, other: LoadStats) {
+        for (region_id, load) in other.regions {
+            self.regions
+                .entry(region_id)
+                .or_insert_with(RegionLoad::default)
+                .merge(load);
+        }
+    }
+
+    pub fn get(&self, region_id: u64) -> Option<&RegionLoad> {
+        self.regions.get(&region_id)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+}
+
+/// Returns the key sampled for a write command. The keys in the lock cf are preferred, they
+/// don't have a ts appended and every transactional write touches the lock cf.
+pub fn write_sample_key(req: &RaftCmdRequest) -> Option<&[u8]> {
+    let mut sample = None;
+    for r in req.get_requests() {
+        let (cf, key) = match r.get_cmd_type() {
+            CmdType::Put => (r.get_put().get_cf(), r.get_put().get_key()),
+            CmdType::Delete => (r.get_delete().get_cf(), r.get_delete().get_key()),
+            _ => continue,
+        };
+        if cf == CF_LOCK {
+            return Some(key);
+        }
+        if sample.is_none() && (cf.is_empty() || cf == CF_DEFAULT) {
+            sample = Some(key);
+        }
+    }
+    sample
+}
+
+// Returns the sampled key dividing the samples most evenly, it's the first key of the right
+// part. `None` is returned if all the samples are the same.
+fn balanced_split_key(mut keys: Vec<Vec<u8>>) -> Option<Vec<u8>> {
+    keys.sort();
+    let mut best = None;
+    let mut best_diff = keys.len();
+    for i in 1..keys.len() {
+        if keys[i] == keys[i - 1] {
+            continue;
+        }
+        let (left, right) = (i, keys.len() - i);
+        let diff = if left > right {
+            left - right
+        } else {
+            right - left
+        };
+        if diff < best_diff {
+            best = Some(i);
+            best_diff = diff;
+        }
+    }
+    best.map(|i| keys.swap_remove(i))
+}
+
+/// Splits the regions whose QPS exceeds the threshold in a number of consecutive windows, at
+/// the key balancing the sampled requests of both sides. A region with a single hot key can't
+/// be split by its load.
+pub struct SplitController {
+    qps_threshold: u64,
+    detect_times: u64,
+    window: Duration,
+    // The load of the current window.
+    load: LoadStats,
+    // The number of the consecutive hot windows of the regions.
+    hot_times: HashMap<u64, u64>,
+}
+
+impl SplitController {
+    pub fn new(qps_threshold: u64, detect_times: u64, window: Duration) -> SplitController {
+        SplitController {
+            qps_threshold: qps_threshold,
+            detect_times: detect_times,
+            window: window,
+            load: LoadStats::default(),
+            hot_times: HashMap::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.qps_threshold > 0
+    }
+
+    pub fn add_request(&mut self, region_id: u64, key: Option<&[u8]>) {
+        if self.is_enabled() {
+            self.load.add_request(region_id, key);
+        }
+    }
+
+    pub fn add_load(&mut self, stats: LoadStats) {
+        if self.is_enabled() {
+            self.load.merge(stats);
+        }
+    }
+
+    /// Ends the current window, returns the regions to be split with the split keys.
+    pub fn flush(&mut self) -> Vec<(u64, Vec<u8>)> {
+        let load = mem::replace(&mut self.load, LoadStats::default());
+        let mut hot_times = HashMap::default();
+        let mut splits = vec![];
+        let secs = duration_to_sec(self.window);
+        for (region_id, region_load) in load.regions {
+            if (region_load.requests as f64) < self.qps_threshold as f64 * secs {
+                continue;
+            }
+            let times = self.hot_times.get(&region_id).cloned().unwrap_or(0) + 1;
+            if times < self.detect_times {
+                hot_times.insert(region_id, times);
+                continue;
+            }
+            if let Some(key) = balanced_split_key(region_load.keys) {
+                splits.push((region_id, key));
+            }
+        }
+        self.hot_times = hot_times;
+        splits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kvproto::raft_cmdpb::Request;
+
+    use storage::CF_WRITE;
+    use super::*;
+
+    fn new_put(cf: &str, key: &[u8]) -> Request {
+        let mut req = Request::new();
+        req.set_cmd_type(CmdType::Put);
+        req.mut_put().set_cf(cf.to_owned());
+        req.mut_put().set_key(key.to_vec());
+        req
+    }
+
+    #[test]
+    fn test_write_sample_key() {
+        let mut req = RaftCmdRequest::new();
+        assert_eq!(write_sample_key(&req), None);
+        req.mut_requests().push(new_put(CF_WRITE, b"k1"));
+        assert_eq!(write_sample_key(&req), None);
+        req.mut_requests().push(new_put("", b"k2"));
+        assert_eq!(write_sample_key(&req), Some(&b"k2"[..]));
+        req.mut_requests().push(new_put(CF_LOCK, b"k3"));
+        assert_eq!(write_sample_key(&req), Some(&b"k3"[..]));
+    }
+
+    #[test]
+    fn test_load_stats() {
+        let mut stats = LoadStats::default();
+        for i in 0..100 {
+            stats.add_request(1, Some(format!("k{}", i).as_bytes()));
+        }
+        stats.add_request(2, None);
+        let load = stats.get(1).unwrap();
+        assert_eq!(load.requests, 100);
+        assert_eq!(load.keys.len(), MAX_SAMPLE_NUM);
+
+        let mut other = LoadStats::default();
+        other.add_request(2, Some(b"k"));
+        stats.merge(other);
+        let load = stats.get(2).unwrap();
+        assert_eq!(load.requests, 2);
+        assert_eq!(load.keys, vec![b"k".to_vec()]);
+    }
+
+    fn to_keys(keys: &[&str]) -> Vec<Vec<u8>> {
+        keys.iter().map(|k| k.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_balanced_split_key() {
+        assert_eq!(balanced_split_key(vec![]), None);
+        assert_eq!(balanced_split_key(to_keys(&["a", "a", "a"])), None);
+        assert_eq!(
+            balanced_split_key(to_keys(&["d", "a", "c", "b"])),
+            Some(b"c".to_vec())
+        );
+        // The hot key is kept on one side.
+        assert_eq!(
+            balanced_split_key(to_keys(&["b", "b", "b", "a", "c"])),
+            Some(b"b".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_split_controller() {
+        let mut controller = SplitController::new(10, 3, Duration::from_secs(1));
+        let add_load = |controller: &mut SplitController, region_id: u64, requests: u64| {
+            let mut stats = LoadStats::default();
+            for i in 0..requests {
+                stats.add_request(region_id, Some(format!("k{:02}", i % 10).as_bytes()));
+            }
+            controller.add_load(stats);
+        };
+
+        for _ in 0..2 {
+            add_load(&mut controller, 1, 20);
+            add_load(&mut controller, 2, 20);
+            assert!(controller.flush().is_empty());
+        }
+        // The windows of region 2 aren't consecutive.
+        add_load(&mut controller, 1, 20);
+        add_load(&mut controller, 2, 5);
+        assert_eq!(controller.flush(), vec![(1, b"k05".to_vec())]);
+        add_load(&mut controller, 2, 20);
+        assert!(controller.flush().is_empty());
+
+        let mut controller = SplitController::new(0, 3, Duration::from_secs(1));
+        assert!(!controller.is_enabled());
+        controller.add_request(1, Some(b"k"));
+        assert!(controller.flush().is_empty());
+    }
+}
@@ -24,11 +24,11 @@ use std::u64;
 use rocksdb::{WriteBatch, DB};
 use rocksdb::rocksdb_options::WriteOptions;
 use mio::{self, EventLoop, EventLoopConfig, Sender};
-use protobuf;
+use protobuf::{self, RepeatedField};
 use time::{self, Timespec};
 
-use kvproto::raft_serverpb::{PeerState, RaftMessage, RaftSnapshotData, RaftTruncatedState,
-                             RegionLocalState};
+use kvproto::raft_serverpb::{ExtraMessageType, MergeState, PeerState, RaftMessage,
+                             RaftSnapshotData, RaftTruncatedState, RegionLocalState};
 use kvproto::eraftpb::{ConfChangeType, MessageType};
 use kvproto::pdpb::StoreStats;
 use util::{escape, rocksdb};
@@ -37,7 +37,8 @@ use pd::PdClient;
 use kvproto::raft_cmdpb::{AdminCmdType, AdminRequest, RaftCmdRequest, RaftCmdResponse,
                           StatusCmdType, StatusResponse};
 use protobuf::Message;
-use raft::{self, SnapshotStatus, INVALID_INDEX};
+use raft::{self, SnapshotStatus, INVALID_INDEX, NO_LIMIT};
+use raft_engine::{LogBatch, RaftEngine};
 use raftstore::{Error, Result};
 use kvproto::metapb;
 use util::worker::{FutureWorker, Scheduler, Stopped, Worker};
@@ -46,12 +47,13 @@ use util::RingQueue;
 use util::collections::{HashMap, HashSet};
 use storage::{CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE};
 use raftstore::coprocessor::CoprocessorHost;
+use import::SSTImporter;
 use raftstore::coprocessor::split_observer::SplitObserver;
 use super::worker::{ApplyRunner, ApplyTask, ApplyTaskRes, CompactRunner, CompactTask,
                     ConsistencyCheckRunner, ConsistencyCheckTask, PdRunner, PdTask,
                     RaftlogGcRunner, RaftlogGcTask, RegionRunner, RegionTask, SplitCheckRunner,
                     SplitCheckTask};
-use super::worker::apply::{ChangePeer, ExecResult};
+use super::worker::apply::{ChangePeer, ChangePeerV2, ExecResult};
 use super::{util, Msg, SignificantMsg, SnapManager, SnapshotDeleter, Tick};
 use super::keys::{self, data_end_key, data_key, enc_end_key, enc_start_key};
 use super::engine::{Iterable, Peekable, Snapshot as EngineSnapshot};
@@ -63,26 +65,46 @@ use super::cmd_resp::{bind_term, new_error};
 use super::transport::Transport;
 use super::metrics::*;
 use super::local_metrics::RaftMetrics;
+use super::split_controller::{write_sample_key, SplitController};
 use prometheus::local::LocalHistogram;
 
 type Key = Vec<u8>;
 
 const MIO_TICK_RATIO: u64 = 10;
 const PENDING_VOTES_CAP: usize = 20;
+// The max number of the raft log gc tasks handled together, the raft log engine purges its
+// files after each batch.
+const RAFTLOG_GC_BATCH_SIZE: usize = 64;
 
 #[derive(Clone)]
 pub struct Engines {
     pub kv_engine: Arc<DB>,
     pub raft_engine: Arc<DB>,
+    // Stores the raft logs and raft states of the regions. It's `raft_engine` unless the raft
+    // log engine is enabled.
+    pub raft_log: Arc<RaftEngine>,
 }
 
 impl Engines {
     pub fn new(kv_engine: Arc<DB>, raft_engine: Arc<DB>) -> Engines {
         Engines {
             kv_engine: kv_engine,
+            raft_log: raft_engine.clone(),
             raft_engine: raft_engine,
         }
     }
+
+    pub fn with_raft_log(
+        kv_engine: Arc<DB>,
+        raft_engine: Arc<DB>,
+        raft_log: Arc<RaftEngine>,
+    ) -> Engines {
+        Engines {
+            kv_engine: kv_engine,
+            raft_engine: raft_engine,
+            raft_log: raft_log,
+        }
+    }
 }
 
 // A helper structure to bundle all channels for messages to `Store`.
@@ -135,10 +157,14 @@ pub struct StoreInfo {
     pub capacity: u64,
 }
 
+// All the peers of the store are driven by this event loop and get their messages from the
+// shared `SendCh`. Only the committed logs are applied concurrently, by the apply pool of
+// `raftstore.apply-pool-size` threads; the peers are not scheduled on a pool with per-region
+// mailboxes yet.
 pub struct Store<T, C: 'static> {
     cfg: Rc<Config>,
     kv_engine: Arc<DB>,
-    raft_engine: Arc<DB>,
+    raft_engine: Arc<RaftEngine>,
     store: metapb::Store,
     sendch: SendCh<Msg>,
 
@@ -164,6 +190,7 @@ pub struct Store<T, C: 'static> {
     pd_client: Arc<C>,
 
     pub coprocessor_host: Arc<CoprocessorHost>,
+    pub importer: Arc<SSTImporter>,
 
     snap_mgr: SnapManager,
 
@@ -178,6 +205,8 @@ pub struct Store<T, C: 'static> {
     pending_votes: RingQueue<RaftMessage>,
 
     store_stat: StoreStat,
+    // Splits the hot regions by the sampled load.
+    split_controller: SplitController,
 }
 
 pub fn create_event_loop<T, C>(cfg: &Config) -> Result<EventLoop<Store<T, C>>>
@@ -195,6 +224,7 @@ where
 }
 
 impl<T, C> Store<T, C> {
+    #[allow(too_many_arguments)]
     pub fn new(
         ch: StoreChannel,
         meta: metapb::Store,
@@ -203,6 +233,8 @@ impl<T, C> Store<T, C> {
         trans: T,
         pd_client: Arc<C>,
         mgr: SnapManager,
+        mut coprocessor_host: CoprocessorHost,
+        importer: Arc<SSTImporter>,
     ) -> Result<Store<T, C>> {
         // TODO: we can get cluster meta regularly too later.
         try!(cfg.validate());
@@ -210,17 +242,21 @@ impl<T, C> Store<T, C> {
         let sendch = SendCh::new(ch.sender, "raftstore");
         let tag = format!("[store {}]", meta.get_id());
 
-        let mut coprocessor_host = CoprocessorHost::new();
         // TODO load coprocessors from configuration
         coprocessor_host
             .registry
             .register_observer(100, box SplitObserver);
 
+        let split_controller = SplitController::new(
+            cfg.load_split_qps_threshold,
+            cfg.load_split_detect_times,
+            cfg.load_split_check_interval.0,
+        );
         let mut s = Store {
             cfg: Rc::new(cfg),
             store: meta,
             kv_engine: engines.kv_engine,
-            raft_engine: engines.raft_engine,
+            raft_engine: engines.raft_log,
             sendch: sendch,
             significant_msg_receiver: ch.significant_msg_receiver,
             region_peers: HashMap::default(),
@@ -238,6 +274,7 @@ impl<T, C> Store<T, C> {
             trans: trans,
             pd_client: pd_client,
             coprocessor_host: Arc::new(coprocessor_host),
+            importer: importer,
             snap_mgr: mgr,
             raft_metrics: RaftMetrics::default(),
             entry_cache_metries: Rc::new(RefCell::new(CacheQueryStats::default())),
@@ -246,6 +283,7 @@ impl<T, C> Store<T, C> {
             start_time: time::get_time(),
             is_busy: false,
             store_stat: StoreStat::default(),
+            split_controller: split_controller,
         };
         try!(s.init());
         Ok(s)
@@ -265,7 +303,7 @@ impl<T, C> Store<T, C> {
 
         let t = Instant::now();
         let mut kv_wb = WriteBatch::new();
-        let mut raft_wb = WriteBatch::new();
+        let mut raft_wb = LogBatch::new();
         let mut applying_regions = vec![];
         try!(kv_engine.scan_cf(
             CF_RAFT,
@@ -297,8 +335,8 @@ impl<T, C> Store<T, C> {
                     // but not write raft_local_state to raft rocksdb in time.
                     try!(peer_storage::recover_from_applying_state(
                         &self.kv_engine,
-                        &self.raft_engine,
-                        &raft_wb,
+                        &*self.raft_engine,
+                        &mut raft_wb,
                         region_id
                     ));
                     applying_count += 1;
@@ -307,6 +345,14 @@ impl<T, C> Store<T, C> {
                 }
 
                 let mut peer = try!(Peer::create(self, region));
+                if local_state.get_state() == PeerState::Merging {
+                    info!(
+                        "region {:?} is merging in store {}",
+                        region,
+                        self.store_id()
+                    );
+                    peer.pending_merge_state = Some(local_state.get_merge_state().clone());
+                }
                 if self.cfg.region_split_check_after_initialization {
                     // Check if it needs to split ASAP.
                     peer.size_diff_hint = self.cfg.region_split_check_diff.0;
@@ -324,8 +370,7 @@ impl<T, C> Store<T, C> {
             self.kv_engine.sync_wal().unwrap();
         }
         if !raft_wb.is_empty() {
-            self.raft_engine.write(raft_wb).unwrap();
-            self.raft_engine.sync_wal().unwrap();
+            self.raft_engine.consume(&mut raft_wb, true).unwrap();
         }
 
         // schedule applying snapshot after raft writebatch were written.
@@ -360,26 +405,26 @@ impl<T, C> Store<T, C> {
     fn clear_stale_meta(
         &mut self,
         kv_wb: &mut WriteBatch,
-        raft_wb: &mut WriteBatch,
+        raft_wb: &mut LogBatch,
         region: &metapb::Region,
     ) {
-        let raft_key = keys::raft_state_key(region.get_id());
-        let raft_state = match self.raft_engine.get_msg(&raft_key).unwrap() {
+        if self.raft_engine
+            .get_raft_state(region.get_id())
+            .unwrap()
+            .is_none()
+        {
             // it has been cleaned up.
-            None => return,
-            Some(value) => value,
-        };
+            return;
+        }
 
-        peer_storage::clear_meta(
+        peer_storage::clear_meta(&self.kv_engine, kv_wb, raft_wb, region.get_id()).unwrap();
+        peer_storage::write_peer_state(
             &self.kv_engine,
-            &self.raft_engine,
             kv_wb,
-            raft_wb,
-            region.get_id(),
-            &raft_state,
+            region,
+            PeerState::Tombstone,
+            None,
         ).unwrap();
-        peer_storage::write_peer_state(&self.kv_engine, kv_wb, region, PeerState::Tombstone)
-            .unwrap();
     }
 
     /// `clear_stale_data` clean up all possible garbage data.
@@ -432,7 +477,7 @@ impl<T, C> Store<T, C> {
         self.kv_engine.clone()
     }
 
-    pub fn raft_engine(&self) -> Arc<DB> {
+    pub fn raft_engine(&self) -> Arc<RaftEngine> {
         self.raft_engine.clone()
     }
 
@@ -463,9 +508,21 @@ impl<T, C> Store<T, C> {
                 Ok(SignificantMsg::Unreachable {
                     region_id,
                     to_peer_id,
-                }) => if let Some(peer) = self.region_peers.get_mut(&region_id) {
-                    peer.raft_group.report_unreachable(to_peer_id);
-                },
+                }) => {
+                    let to_store_id = match self.region_peers.get_mut(&region_id) {
+                        Some(peer) => {
+                            peer.raft_group.report_unreachable(to_peer_id);
+                            peer.get_peer_from_cache(to_peer_id).map(|p| p.get_store_id())
+                        }
+                        None => None,
+                    };
+                    if let Some(store_id) = to_store_id {
+                        self.on_store_unreachable(store_id);
+                    }
+                }
+                Ok(SignificantMsg::StoreUnreachable { store_id }) => {
+                    self.on_store_unreachable(store_id);
+                }
                 Err(TryRecvError::Empty) => {
                     // The snapshot status receiver channel is empty
                     return;
@@ -521,6 +578,8 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         self.register_snap_mgr_gc_tick(event_loop);
         self.register_compact_lock_cf_tick(event_loop);
         self.register_consistency_check_tick(event_loop);
+        self.register_merge_check_tick(event_loop);
+        self.register_load_split_check_tick(event_loop);
 
         let split_check_runner = SplitCheckRunner::new(
             self.kv_engine.clone(),
@@ -539,7 +598,10 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         box_try!(self.region_worker.start(runner));
 
         let raftlog_gc_runner = RaftlogGcRunner::new(None);
-        box_try!(self.raftlog_gc_worker.start(raftlog_gc_runner));
+        box_try!(
+            self.raftlog_gc_worker
+                .start_batch(raftlog_gc_runner, RAFTLOG_GC_BATCH_SIZE)
+        );
 
         let compact_runner = CompactRunner::new(self.kv_engine.clone());
         box_try!(self.compact_worker.start(compact_runner));
@@ -610,6 +672,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
 
     fn on_raft_base_tick(&mut self, event_loop: &mut EventLoop<Self>) {
         let timer = self.raft_metrics.process_tick.start_coarse_timer();
+        let mut hibernated_count = 0;
         for peer in &mut self.region_peers.values_mut() {
             if peer.pending_remove {
                 continue;
@@ -623,7 +686,10 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                 continue;
             }
 
-            if peer.raft_group.tick() {
+            peer.check_replica_reads();
+            if self.cfg.hibernate_regions && peer.check_hibernate(&self.trans) {
+                hibernated_count += 1;
+            } else if peer.raft_group.tick() {
                 peer.mark_to_be_checked(&mut self.pending_raft_groups);
             }
 
@@ -660,6 +726,15 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             }
         }
 
+        // Hibernate requests and wake up messages may be sent during ticks.
+        self.trans.flush();
+        HIBERNATED_PEER_STATE_GAUGE
+            .with_label_values(&["hibernated"])
+            .set(hibernated_count as f64);
+        HIBERNATED_PEER_STATE_GAUGE
+            .with_label_values(&["awake"])
+            .set((self.region_peers.len() - hibernated_count) as f64);
+
         self.poll_significant_msg();
 
         timer.observe_duration();
@@ -683,8 +758,16 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                 },
                 Ok(ApplyTaskRes::Destroy(p)) => {
                     let store_id = self.store_id();
-                    self.destroy_peer(p.region_id(), util::new_peer(store_id, p.id()));
+                    self.destroy_peer(p.region_id(), util::new_peer(store_id, p.id()), false);
                 }
+                Ok(ApplyTaskRes::ResolvedTs(resolved)) => for r in resolved {
+                    if let Some(p) = self.region_peers.get_mut(&r.region_id) {
+                        // The locks may be incomplete after the leadership is changed.
+                        if p.is_leader() && p.term() == r.term {
+                            p.on_resolved_ts(r.applied_index, r.resolved_ts, &self.trans);
+                        }
+                    }
+                },
                 Err(TryRecvError::Empty) => break,
                 Err(e) => panic!("unexpected error {:?}", e),
             }
@@ -752,7 +835,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                 return Ok(false);
             }
             info!("[region {}] destroying stale peer {:?}", region_id, p);
-            self.destroy_peer(region_id, p);
+            self.destroy_peer(region_id, p, false);
             has_peer = false;
         }
 
@@ -813,6 +896,11 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             return Ok(());
         }
 
+        if msg.has_extra_msg() {
+            self.on_extra_message(msg);
+            return Ok(());
+        }
+
         if !try!(self.maybe_create_peer(region_id, &msg)) {
             return Ok(());
         }
@@ -823,6 +911,11 @@ impl<T: Transport, C: PdClient> Store<T, C> {
 
         let peer = self.region_peers.get_mut(&region_id).unwrap();
         peer.insert_peer_cache(msg.take_from_peer());
+        // Heartbeats keep a hibernating region asleep, anything else wakes it up.
+        let msg_type = msg.get_message().get_msg_type();
+        if msg_type != MessageType::MsgHeartbeat && msg_type != MessageType::MsgHeartbeatResponse {
+            peer.wake_up(&self.trans);
+        }
         try!(peer.step(msg.take_message()));
 
         // Add into pending raft groups for later handling ready.
@@ -831,6 +924,24 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         Ok(())
     }
 
+    fn on_extra_message(&mut self, msg: RaftMessage) {
+        let peer = match self.region_peers.get_mut(&msg.get_region_id()) {
+            Some(peer) => peer,
+            None => return,
+        };
+        let from = msg.get_from_peer();
+        match msg.get_extra_msg().get_field_type() {
+            ExtraMessageType::MsgRegionWakeUp => peer.wake_up(&self.trans),
+            ExtraMessageType::MsgHibernateRequest => peer.on_hibernate_request(from, &self.trans),
+            ExtraMessageType::MsgHibernateResponse => peer.on_hibernate_response(from),
+            ExtraMessageType::MsgSafeTs => {
+                let extra_msg = msg.get_extra_msg();
+                peer.on_safe_ts(from, extra_msg.get_applied_index(), extra_msg.get_safe_ts());
+            }
+        }
+        peer.mark_to_be_checked(&mut self.pending_raft_groups);
+    }
+
     // return false means the message is invalid, and can be ignored.
     fn validate_raft_msg(&mut self, msg: &RaftMessage) -> bool {
         let region_id = msg.get_region_id();
@@ -1039,7 +1150,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                     .schedule(ApplyTask::destroy(region_id))
                     .unwrap();
             } else {
-                self.destroy_peer(region_id, msg.get_to_peer().clone());
+                self.destroy_peer(region_id, msg.get_to_peer().clone(), false);
             }
         }
     }
@@ -1108,7 +1219,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         self.raft_metrics.ready.pending_region += pending_count as u64;
 
         let mut region_proposals = Vec::with_capacity(pending_count);
-        let (kv_wb, raft_wb, append_res, sync_log) = {
+        let (kv_wb, mut raft_wb, append_res, sync_log) = {
             let mut ctx = ReadyContext::new(&mut self.raft_metrics, &self.trans, pending_count);
             for region_id in self.pending_raft_groups.drain() {
                 if let Some(peer) = self.region_peers.get_mut(&region_id) {
@@ -1150,10 +1261,8 @@ impl<T: Transport, C: PdClient> Store<T, C> {
 
         if !raft_wb.is_empty() {
             // RaftLocalState, Raft Log Entry
-            let mut write_opts = WriteOptions::new();
-            write_opts.set_sync(self.cfg.sync_log || sync_log);
             self.raft_engine
-                .write_opt(raft_wb, &write_opts)
+                .consume(&mut raft_wb, self.cfg.sync_log || sync_log)
                 .unwrap_or_else(|e| {
                     panic!("{} failed to save raft append result: {:?}", self.tag, e);
                 });
@@ -1225,7 +1334,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         slow_log!(t, "{} on {} regions raft ready", self.tag, pending_count);
     }
 
-    fn destroy_peer(&mut self, region_id: u64, peer: metapb::Peer) {
+    fn destroy_peer(&mut self, region_id: u64, peer: metapb::Peer, keep_data: bool) {
         // Can we destroy it in another thread later?
 
         // Suppose cluster removes peer a from store and then add a new
@@ -1251,7 +1360,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         assert!(!p.is_applying_snapshot());
 
         let is_initialized = p.is_initialized();
-        if let Err(e) = p.destroy() {
+        if let Err(e) = p.destroy(keep_data) {
             // If not panic here, the peer will be recreated in the next restart,
             // then it will be gc again. But if some overlap region is created
             // before restarting, the gc action will delete the overlap region's
@@ -1301,7 +1410,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             }
 
             match change_type {
-                ConfChangeType::AddNode => {
+                ConfChangeType::AddNode | ConfChangeType::AddLearnerNode => {
                     // Add this peer to cache.
                     let peer = cp.peer.clone();
                     p.peer_heartbeats.insert(peer.get_id(), Instant::now());
@@ -1324,13 +1433,100 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         // We only care remove itself now.
         if change_type == ConfChangeType::RemoveNode && peer.get_store_id() == self.store_id() {
             if my_peer_id == peer.get_id() {
-                self.destroy_peer(region_id, peer)
+                self.destroy_peer(region_id, peer, false)
             } else {
                 panic!("{} trying to remove unknown peer {:?}", self.tag, peer);
             }
         }
     }
 
+    fn on_ready_change_peer_v2(&mut self, region_id: u64, cp: ChangePeerV2) {
+        let my_peer_id;
+        let is_leader;
+        if let Some(p) = self.region_peers.get_mut(&region_id) {
+            p.raft_group.apply_conf_change_v2(&cp.conf_change);
+            p.mut_store().region = cp.region;
+            is_leader = p.is_leader();
+            if is_leader {
+                // Notify pd immediately.
+                info!(
+                    "{} notify pd with change peer region {:?}",
+                    p.tag,
+                    p.region()
+                );
+                p.heartbeat_pd(&self.pd_worker);
+            }
+
+            for change in &cp.changes {
+                let peer_id = change.get_peer().get_id();
+                match change.get_change_type() {
+                    ConfChangeType::AddNode | ConfChangeType::AddLearnerNode => {
+                        // Add this peer to cache, use the one in region to keep its role.
+                        let peer = p.region()
+                            .get_peers()
+                            .iter()
+                            .find(|peer| peer.get_id() == peer_id)
+                            .cloned()
+                            .unwrap_or_else(|| change.get_peer().clone());
+                        p.peer_heartbeats.insert(peer_id, Instant::now());
+                        p.insert_peer_cache(peer);
+                    }
+                    ConfChangeType::RemoveNode => {
+                        // Remove this peer from cache.
+                        p.peer_heartbeats.remove(&peer_id);
+                        p.remove_peer_from_cache(peer_id);
+                    }
+                }
+            }
+
+            my_peer_id = p.peer_id();
+        } else {
+            panic!("{} missing region {}", self.tag, region_id);
+        }
+
+        // We only care remove itself now.
+        for change in &cp.changes {
+            let peer = change.get_peer();
+            if change.get_change_type() == ConfChangeType::RemoveNode &&
+                peer.get_store_id() == self.store_id()
+            {
+                if my_peer_id == peer.get_id() {
+                    self.destroy_peer(region_id, peer.clone(), false);
+                    return;
+                } else {
+                    panic!("{} trying to remove unknown peer {:?}", self.tag, peer);
+                }
+            }
+        }
+
+        if is_leader {
+            // Leave the joint state as soon as possible.
+            self.leave_joint(region_id);
+        }
+    }
+
+    fn leave_joint(&mut self, region_id: u64) {
+        let request = {
+            let peer = &self.region_peers[&region_id];
+            if !peer.is_leader() || !util::is_region_in_joint(peer.region()) ||
+                peer.raft_group.raft.pending_conf
+            {
+                return;
+            }
+            info!("{} propose to leave joint state", peer.tag);
+            let mut request = new_admin_request(region_id, peer.peer.clone());
+            request
+                .mut_header()
+                .set_region_epoch(peer.region().get_region_epoch().clone());
+            let mut admin = AdminRequest::new();
+            admin.set_cmd_type(AdminCmdType::ChangePeerV2);
+            admin.mut_change_peer_v2();
+            request.set_admin_request(admin);
+            request
+        };
+        self.propose_raft_command(request, Box::new(|_| {}));
+    }
+
     fn on_ready_compact_log(
         &mut self,
         region_id: u64,
@@ -1484,6 +1680,218 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         }
     }
 
+    fn on_ready_prepare_merge(
+        &mut self,
+        region_id: u64,
+        region: metapb::Region,
+        state: MergeState,
+    ) {
+        {
+            let peer = self.region_peers.get_mut(&region_id).unwrap();
+            peer.mut_store().region = region;
+            peer.pending_merge_state = Some(state);
+        }
+        self.on_check_merge(region_id);
+    }
+
+    fn on_check_merge(&mut self, region_id: u64) {
+        if let Err(e) = self.schedule_merge(region_id) {
+            info!(
+                "[region {}] failed to schedule merge, rollback: {:?}",
+                region_id,
+                e
+            );
+            self.rollback_merge(region_id);
+        }
+    }
+
+    /// Checks whether the target region on this store is the one the source region expects.
+    ///
+    /// Returns false if the target region hasn't caught up yet.
+    fn validate_merge_target(&self, target_region: &metapb::Region) -> Result<bool> {
+        let region_id = target_region.get_id();
+        let exist_region = match self.region_peers.get(&region_id) {
+            Some(peer) => peer.region(),
+            None => {
+                let state_key = keys::region_state_key(region_id);
+                if let Some(state) = try!(
+                    self.kv_engine
+                        .get_msg_cf::<RegionLocalState>(CF_RAFT, &state_key)
+                ) {
+                    if state.get_state() == PeerState::Tombstone {
+                        return Err(box_err!("target region {} is tombstone", region_id));
+                    }
+                }
+                return Ok(false);
+            }
+        };
+        let exist_epoch = exist_region.get_region_epoch();
+        let expect_epoch = target_region.get_region_epoch();
+        if util::is_epoch_stale(expect_epoch, exist_epoch) {
+            return Err(box_err!(
+                "target region changed {:?} -> {:?}",
+                target_region,
+                exist_region
+            ));
+        }
+        if util::is_epoch_stale(exist_epoch, expect_epoch) {
+            info!(
+                "[region {}] target region still not catch up {:?} < {:?}, skip.",
+                region_id,
+                exist_epoch,
+                expect_epoch
+            );
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn schedule_merge(&mut self, region_id: u64) -> Result<()> {
+        let request = {
+            let peer = &self.region_peers[&region_id];
+            let state = peer.pending_merge_state.as_ref().unwrap();
+            let target_region = state.get_target();
+            if !try!(self.validate_merge_target(target_region)) {
+                return Ok(());
+            }
+            let target_peer = match util::find_peer(target_region, self.store_id()) {
+                Some(p) => p.clone(),
+                None => {
+                    return Err(box_err!(
+                        "target region {:?} has no peer on store {}",
+                        target_region,
+                        self.store_id()
+                    ))
+                }
+            };
+            let entries = try!(peer.get_store().entries(
+                state.get_min_index(),
+                state.get_commit() + 1,
+                NO_LIMIT
+            ));
+
+            let mut request = new_admin_request(target_region.get_id(), target_peer);
+            request
+                .mut_header()
+                .set_region_epoch(target_region.get_region_epoch().clone());
+            let mut admin = AdminRequest::new();
+            admin.set_cmd_type(AdminCmdType::CommitMerge);
+            admin.mut_commit_merge().set_source(peer.region().clone());
+            admin.mut_commit_merge().set_commit(state.get_commit());
+            admin
+                .mut_commit_merge()
+                .set_entries(RepeatedField::from_vec(entries));
+            request.set_admin_request(admin);
+            request
+        };
+        // Every peer of the source region sends the request to the target peer on the same
+        // store, only the one whose target peer is the leader will be proposed. This assumes
+        // the source and the target region have peers on the same stores.
+        if let Err(e) = self.sendch
+            .try_send(Msg::new_raft_cmd(request, Box::new(|_| {})))
+        {
+            error!(
+                "[region {}] failed to schedule commit merge: {:?}",
+                region_id,
+                e
+            );
+        }
+        Ok(())
+    }
+
+    fn rollback_merge(&mut self, region_id: u64) {
+        let request = {
+            let peer = &self.region_peers[&region_id];
+            if !peer.is_leader() {
+                return;
+            }
+            let state = peer.pending_merge_state.as_ref().unwrap();
+            let mut request = new_admin_request(region_id, peer.peer.clone());
+            request
+                .mut_header()
+                .set_region_epoch(peer.region().get_region_epoch().clone());
+            let mut admin = AdminRequest::new();
+            admin.set_cmd_type(AdminCmdType::RollbackMerge);
+            admin.mut_rollback_merge().set_commit(state.get_commit());
+            request.set_admin_request(admin);
+            request
+        };
+        self.propose_raft_command(request, Box::new(|_| {}));
+    }
+
+    fn on_advance_resolved_ts(&mut self, min_ts: u64) {
+        // Followers learn the resolved ts from the leader. A new leader waits until the logs
+        // of the old leaders are applied, which may contain the locks it doesn't know.
+        let region_ids: Vec<_> = self.region_peers
+            .iter()
+            .filter(|&(_, p)| p.has_valid_leader_lease() && p.ready_to_handle_read())
+            .map(|(region_id, _)| *region_id)
+            .collect();
+        if region_ids.is_empty() {
+            return;
+        }
+        if let Err(e) = self.apply_worker
+            .schedule(ApplyTask::resolve_ts(min_ts, region_ids))
+        {
+            error!("{} failed to schedule resolving ts: {:?}", self.tag, e);
+        }
+    }
+
+    fn on_ready_commit_merge(&mut self, region: metapb::Region, source: metapb::Region) {
+        let source_peer = match self.region_peers.get(&source.get_id()) {
+            Some(p) => p.peer.clone(),
+            None => panic!("{} missing source region {:?} of merge", self.tag, source),
+        };
+        // The data of the source region now belongs to the target region.
+        self.destroy_peer(source.get_id(), source_peer, true);
+
+        let region_id = region.get_id();
+        let peer = self.region_peers.get_mut(&region_id).unwrap();
+        if self.region_ranges
+            .remove(&enc_end_key(peer.region()))
+            .is_none()
+        {
+            panic!("{} region should exist {:?}", peer.tag, peer.region());
+        }
+        self.region_ranges.insert(enc_end_key(&region), region_id);
+        peer.mut_store().region = region;
+        // The locks of the source region are not known by the safe ts.
+        peer.reset_read_progress();
+        if peer.is_leader() {
+            info!(
+                "{} notify pd with merge {:?} into {:?}",
+                peer.tag,
+                source,
+                peer.region()
+            );
+            peer.heartbeat_pd(&self.pd_worker);
+        }
+    }
+
+    fn on_ready_rollback_merge(&mut self, region_id: u64, region: metapb::Region, commit: u64) {
+        let peer = self.region_peers.get_mut(&region_id).unwrap();
+        if let Some(ref state) = peer.pending_merge_state {
+            if state.get_commit() != commit {
+                panic!(
+                    "{} rollback merge at {} doesn't match {:?}",
+                    peer.tag,
+                    commit,
+                    state
+                );
+            }
+        }
+        peer.pending_merge_state = None;
+        peer.mut_store().region = region;
+        if peer.is_leader() {
+            info!(
+                "{} notify pd with rollback merge {:?}",
+                peer.tag,
+                peer.region()
+            );
+            peer.heartbeat_pd(&self.pd_worker);
+        }
+    }
+
     fn on_ready_apply_snapshot(&mut self, apply_result: ApplySnapResult) {
         let prev_region = apply_result.prev_region;
         let region = apply_result.region;
@@ -1524,6 +1932,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         for result in exec_results {
             match result {
                 ExecResult::ChangePeer(cp) => self.on_ready_change_peer(region_id, cp),
+                ExecResult::ChangePeerV2(cp) => self.on_ready_change_peer_v2(region_id, cp),
                 ExecResult::CompactLog { first_index, state } => {
                     self.on_ready_compact_log(region_id, first_index, state)
                 }
@@ -1543,6 +1952,15 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                 ExecResult::DeleteRange { .. } => {
                     // TODO: clean user properties?
                 }
+                ExecResult::PrepareMerge { region, state } => {
+                    self.on_ready_prepare_merge(region_id, region, state)
+                }
+                ExecResult::CommitMerge { region, source } => {
+                    self.on_ready_commit_merge(region, source)
+                }
+                ExecResult::RollbackMerge { region, commit } => {
+                    self.on_ready_rollback_merge(region_id, region, commit)
+                }
             }
         }
     }
@@ -1581,7 +1999,11 @@ impl<T: Transport, C: PdClient> Store<T, C> {
 
         let mut resp = RaftCmdResponse::new();
         let region_id = msg.get_header().get_region_id();
+        if let Some(key) = write_sample_key(&msg) {
+            self.split_controller.add_request(region_id, Some(key));
+        }
         let peer = self.region_peers.get_mut(&region_id).unwrap();
+        peer.wake_up(&self.trans);
         let term = peer.term();
         bind_term(&mut resp, term);
         if peer.propose(cb, msg, resp, &mut self.raft_metrics.propose) {
@@ -1615,6 +2037,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
 
             let region_id = msg.get_header().get_region_id();
             let peer = self.region_peers.get_mut(&region_id).unwrap();
+            peer.wake_up(&self.trans);
             ret.push(peer.propose_snapshot(msg, &mut self.raft_metrics.propose));
         }
         on_finished.call_box((ret,));
@@ -1636,7 +2059,9 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             Some(peer) => peer,
             None => return Err(Error::RegionNotFound(region_id)),
         };
-        if !peer.is_leader() {
+        // Stale reads and replica reads can be served by any peer.
+        let header = msg.get_header();
+        if !peer.is_leader() && !header.get_stale_read() && !header.get_replica_read() {
             return Err(Error::NotLeader(
                 region_id,
                 peer.get_peer_from_cache(peer.leader_id()),
@@ -1650,7 +2075,6 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             ));
         }
 
-        let header = msg.get_header();
         // If header's term is 2 verions behind current term, leadership may have been changed away.
         if header.get_term() > 0 && peer.term() > header.get_term() + 1 {
             return Err(Error::StaleCommand);
@@ -1980,12 +2404,20 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             peer.check_peers();
         }
         let mut leader_count = 0;
+        let mut joint_regions = vec![];
         for peer in self.region_peers.values() {
             if peer.is_leader() {
                 leader_count += 1;
                 peer.heartbeat_pd(&self.pd_worker);
+                if util::is_region_in_joint(peer.region()) {
+                    joint_regions.push(peer.region().get_id());
+                }
             }
         }
+        // Retry leaving the joint state in case the proposal was dropped.
+        for region_id in joint_regions {
+            self.leave_joint(region_id);
+        }
         self.on_update_region_flow();
         STORE_PD_HEARTBEAT_GAUGE_VEC
             .with_label_values(&["leader"])
@@ -2076,9 +2508,50 @@ impl<T: Transport, C: PdClient> Store<T, C> {
 
     fn on_pd_store_heartbeat_tick(&mut self, event_loop: &mut EventLoop<Self>) {
         self.store_heartbeat_pd();
+        if self.cfg.hibernate_regions {
+            self.probe_hibernated_leaders();
+        }
         self.register_pd_store_heartbeat_tick(event_loop);
     }
 
+    // Sends a probe to each store holding the leaders of the hibernated followers, so a broken
+    // connection to it is noticed without waiting for the followers to wake up by themselves.
+    fn probe_hibernated_leaders(&mut self) {
+        let mut probed = HashSet::default();
+        let mut unreachable = vec![];
+        for peer in self.region_peers.values() {
+            let leader = match peer.hibernated_leader() {
+                Some(leader) => leader,
+                None => continue,
+            };
+            let store_id = leader.get_store_id();
+            if probed.insert(store_id) && !peer.probe_leader(leader, &self.trans) {
+                unreachable.push(store_id);
+            }
+        }
+        for store_id in unreachable {
+            self.on_store_unreachable(store_id);
+        }
+        self.trans.flush();
+    }
+
+    // Wakes up the hibernated followers led by the store, so a new leader can be elected soon
+    // if the store is down.
+    fn on_store_unreachable(&mut self, store_id: u64) {
+        if !self.cfg.hibernate_regions {
+            return;
+        }
+        let mut woken = false;
+        for peer in self.region_peers.values_mut() {
+            if peer.on_store_unreachable(store_id, &self.trans) {
+                woken = true;
+            }
+        }
+        if woken {
+            self.trans.flush();
+        }
+    }
+
     fn handle_snap_mgr_gc(&mut self) -> Result<()> {
         let snap_keys = try!(self.snap_mgr.list_idle_snap());
         if snap_keys.is_empty() {
@@ -2200,6 +2673,62 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         }
     }
 
+    fn register_merge_check_tick(&self, event_loop: &mut EventLoop<Self>) {
+        if let Err(e) = register_timer(
+            event_loop,
+            Tick::CheckMerge,
+            self.cfg.merge_check_tick_interval.as_millis(),
+        ) {
+            error!("{} register merge check tick err: {:?}", self.tag, e);
+        }
+    }
+
+    fn on_check_merge_tick(&mut self, event_loop: &mut EventLoop<Self>) {
+        let merging_regions: Vec<_> = self.region_peers
+            .iter()
+            .filter(|&(_, peer)| peer.pending_merge_state.is_some())
+            .map(|(&region_id, _)| region_id)
+            .collect();
+        for region_id in merging_regions {
+            self.on_check_merge(region_id);
+        }
+        self.register_merge_check_tick(event_loop);
+    }
+
+    fn register_load_split_check_tick(&self, event_loop: &mut EventLoop<Self>) {
+        if !self.split_controller.is_enabled() {
+            return;
+        }
+        if let Err(e) = register_timer(
+            event_loop,
+            Tick::LoadSplitCheck,
+            self.cfg.load_split_check_interval.as_millis(),
+        ) {
+            error!("{} register load split check tick err: {:?}", self.tag, e);
+        }
+    }
+
+    fn on_load_split_check_tick(&mut self, event_loop: &mut EventLoop<Self>) {
+        for (region_id, split_key) in self.split_controller.flush() {
+            let region_epoch = match self.region_peers.get(&region_id) {
+                Some(peer) if peer.is_leader() => {
+                    let region = peer.region();
+                    // The key may be sampled before the region is split or merged.
+                    if split_key.as_slice() <= region.get_start_key() ||
+                        util::check_key_in_region(&split_key, region).is_err()
+                    {
+                        continue;
+                    }
+                    info!("{} split at {} by its load", peer.tag, escape(&split_key));
+                    region.get_region_epoch().clone()
+                }
+                _ => continue,
+            };
+            self.on_prepare_split_region(region_id, region_epoch, split_key, None);
+        }
+        self.register_load_split_check_tick(event_loop);
+    }
+
     fn handle_coprocessor_msg(&mut self, request_stats: CopFlowStatistics) {
         for (region_id, stats) in &request_stats {
             if let Some(peer) = self.region_peers.get_mut(region_id) {
@@ -2510,6 +3039,7 @@ impl<T: Transport, C: PdClient> mio::Handler for Store<T, C> {
             }
             Msg::SnapshotStats => self.store_heartbeat_pd(),
             Msg::CoprocessorStats { request_stats } => self.handle_coprocessor_msg(request_stats),
+            Msg::LoadStats { stats } => self.split_controller.add_load(stats),
             Msg::ComputeHashResult {
                 region_id,
                 index,
@@ -2530,6 +3060,7 @@ impl<T: Transport, C: PdClient> mio::Handler for Store<T, C> {
                 );
                 self.on_prepare_split_region(region_id, region_epoch, split_key, callback);
             }
+            Msg::AdvanceResolvedTs { min_ts } => self.on_advance_resolved_ts(min_ts),
         }
     }
 
@@ -2545,6 +3076,8 @@ impl<T: Transport, C: PdClient> mio::Handler for Store<T, C> {
             Tick::SnapGc => self.on_snap_mgr_gc(event_loop),
             Tick::CompactLockCf => self.on_compact_lock_cf(event_loop),
             Tick::ConsistencyCheck => self.on_consistency_check_tick(event_loop),
+            Tick::CheckMerge => self.on_check_merge_tick(event_loop),
+            Tick::LoadSplitCheck => self.on_load_split_check_tick(event_loop),
         }
         slow_log!(t, "{} handle timeout {:?}", self.tag, timeout);
     }
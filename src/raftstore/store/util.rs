@@ -13,7 +13,7 @@
 
 use std::option::Option;
 
-use kvproto::metapb;
+use kvproto::metapb::{self, PeerRole};
 use kvproto::eraftpb::{self, ConfChangeType, MessageType};
 use kvproto::raft_serverpb::RaftMessage;
 use raftstore::{Error, Result};
@@ -82,14 +82,52 @@ pub fn is_first_vote_msg(msg: &RaftMessage) -> bool {
 
 const STR_CONF_CHANGE_ADD_NODE: &'static str = "AddNode";
 const STR_CONF_CHANGE_REMOVE_NODE: &'static str = "RemoveNode";
+const STR_CONF_CHANGE_ADDLEARNER_NODE: &'static str = "AddLearner";
 
 pub fn conf_change_type_str(conf_type: &eraftpb::ConfChangeType) -> &'static str {
     match *conf_type {
         ConfChangeType::AddNode => STR_CONF_CHANGE_ADD_NODE,
         ConfChangeType::RemoveNode => STR_CONF_CHANGE_REMOVE_NODE,
+        ConfChangeType::AddLearnerNode => STR_CONF_CHANGE_ADDLEARNER_NODE,
     }
 }
 
+/// Returns true if the region is in joint consensus, that is, some of its peers are
+/// being promoted or demoted.
+pub fn is_region_in_joint(region: &metapb::Region) -> bool {
+    region.get_peers().iter().any(|p| {
+        p.get_role() == PeerRole::IncomingVoter || p.get_role() == PeerRole::DemotingVoter
+    })
+}
+
+/// Builds the raft `ConfState` of the region, learners are kept apart from voters.
+/// If the region is in joint consensus, the outgoing voters are filled too.
+pub fn conf_state_from_region(region: &metapb::Region) -> eraftpb::ConfState {
+    let in_joint = is_region_in_joint(region);
+    let mut conf_state = eraftpb::ConfState::new();
+    for p in region.get_peers() {
+        if p.get_is_learner() {
+            conf_state.mut_learners().push(p.get_id());
+            continue;
+        }
+        match p.get_role() {
+            PeerRole::IncomingVoter => conf_state.mut_nodes().push(p.get_id()),
+            PeerRole::DemotingVoter => {
+                conf_state.mut_voters_outgoing().push(p.get_id());
+                conf_state.mut_learners_next().push(p.get_id());
+            }
+            PeerRole::Learner => conf_state.mut_learners().push(p.get_id()),
+            PeerRole::Voter => {
+                conf_state.mut_nodes().push(p.get_id());
+                if in_joint {
+                    conf_state.mut_voters_outgoing().push(p.get_id());
+                }
+            }
+        }
+    }
+    conf_state
+}
+
 const MAX_DELETE_KEYS_COUNT: usize = 10000;
 
 pub fn delete_all_in_range(db: &DB, start_key: &[u8], end_key: &[u8]) -> Result<()> {
@@ -180,7 +218,7 @@ pub fn get_region_approximate_size(db: &DB, region: &metapb::Region) -> Result<u
 mod tests {
     use std::process;
 
-    use kvproto::metapb;
+    use kvproto::metapb::{self, PeerRole};
     use kvproto::raft_serverpb::RaftMessage;
     use kvproto::eraftpb::{ConfChangeType, Message, MessageType};
 
@@ -274,6 +312,37 @@ mod tests {
             conf_change_type_str(&ConfChangeType::RemoveNode),
             STR_CONF_CHANGE_REMOVE_NODE
         );
+        assert_eq!(
+            conf_change_type_str(&ConfChangeType::AddLearnerNode),
+            STR_CONF_CHANGE_ADDLEARNER_NODE
+        );
+    }
+
+    #[test]
+    fn test_conf_state_from_region() {
+        let mut region = metapb::Region::new();
+        region.mut_peers().push(new_peer(1, 1));
+        let mut learner = new_peer(2, 2);
+        learner.set_is_learner(true);
+        region.mut_peers().push(learner);
+        region.mut_peers().push(new_peer(3, 3));
+
+        let cs = conf_state_from_region(&region);
+        assert_eq!(cs.get_nodes(), &[1, 3]);
+        assert_eq!(cs.get_learners(), &[2]);
+        assert!(cs.get_voters_outgoing().is_empty());
+        assert!(!is_region_in_joint(&region));
+
+        // Promote 2 and demote 3 in joint consensus.
+        region.mut_peers()[1].set_is_learner(false);
+        region.mut_peers()[1].set_role(PeerRole::IncomingVoter);
+        region.mut_peers()[2].set_role(PeerRole::DemotingVoter);
+        assert!(is_region_in_joint(&region));
+        let cs = conf_state_from_region(&region);
+        assert_eq!(cs.get_nodes(), &[1, 2]);
+        assert!(cs.get_learners().is_empty());
+        assert_eq!(cs.get_voters_outgoing(), &[1, 3]);
+        assert_eq!(cs.get_learners_next(), &[3]);
     }
 
     #[test]
@@ -16,27 +16,37 @@ use std::sync::Arc;
 use std::sync::mpsc::Sender;
 use std::fmt::{self, Debug, Display, Formatter};
 use std::collections::VecDeque;
+use std::{cmp, mem};
+
+use futures::Future;
+use futures_cpupool::{Builder, CpuPool};
 
 use rocksdb::{Writable, WriteBatch, DB};
 use rocksdb::rocksdb_options::WriteOptions;
 use protobuf::RepeatedField;
 
-use kvproto::metapb::{Peer as PeerMeta, Region};
-use kvproto::eraftpb::{ConfChange, ConfChangeType, Entry, EntryType};
-use kvproto::raft_serverpb::{PeerState, RaftApplyState, RaftTruncatedState};
-use kvproto::raft_cmdpb::{AdminCmdType, AdminRequest, AdminResponse, ChangePeerRequest, CmdType,
-                          RaftCmdRequest, RaftCmdResponse, Request, Response};
+use kvproto::metapb::{Peer as PeerMeta, PeerRole, Region};
+use kvproto::eraftpb::{ConfChange, ConfChangeType, ConfChangeV2, Entry, EntryType};
+use kvproto::raft_serverpb::{MergeState, PeerState, RaftApplyState, RaftTruncatedState,
+                             RegionLocalState};
+use kvproto::raft_cmdpb::{AdminCmdType, AdminRequest, AdminResponse, ChangePeerRequest,
+                          ChangePeerV2Request, CmdType, CommitMergeRequest, RaftCmdRequest,
+                          RaftCmdResponse, Request, Response};
+use kvproto::import_sstpb::SSTMeta;
 
+use import::SSTImporter;
 use util::worker::Runnable;
 use util::{escape, rocksdb};
 use util::time::{duration_to_sec, SlowTimer};
-use util::collections::{HashMap, HashMapEntry as MapEntry};
+use util::collections::{HashMap, HashMapEntry as MapEntry, HashSet};
 use storage::{ALL_CFS, CF_DEFAULT, CF_LOCK, CF_RAFT};
+use storage::mvcc::{Lock, LockType};
+use raft_engine::RaftEngine;
 use raftstore::{Error, Result};
 use raftstore::coprocessor::CoprocessorHost;
-use raftstore::store::{cmd_resp, keys, util, Store};
+use raftstore::store::{cmd_resp, keys, util, Resolver, Store};
 use raftstore::store::msg::Callback;
-use raftstore::store::engine::{Mutable, Peekable, Snapshot};
+use raftstore::store::engine::{Iterable, Mutable, Peekable, Snapshot};
 use raftstore::store::peer_storage::{self, compact_raft_log, write_initial_apply_state,
                                      write_peer_state};
 use raftstore::store::peer::{check_epoch, parse_data_at, Peer};
@@ -127,6 +137,13 @@ pub struct ChangePeer {
     pub region: Region,
 }
 
+#[derive(Default, Debug)]
+pub struct ChangePeerV2 {
+    pub conf_change: ConfChangeV2,
+    pub changes: Vec<ChangePeerRequest>,
+    pub region: Region,
+}
+
 #[derive(Debug)]
 pub struct Range {
     pub cf: String,
@@ -147,6 +164,7 @@ impl Range {
 #[derive(Debug)]
 pub enum ExecResult {
     ChangePeer(ChangePeer),
+    ChangePeerV2(ChangePeerV2),
     CompactLog {
         state: RaftTruncatedState,
         first_index: u64,
@@ -163,26 +181,45 @@ pub enum ExecResult {
     },
     VerifyHash { index: u64, hash: Vec<u8> },
     DeleteRange { ranges: Vec<Range> },
+    PrepareMerge { region: Region, state: MergeState },
+    CommitMerge { region: Region, source: Region },
+    RollbackMerge { region: Region, commit: u64 },
 }
 
 struct ApplyContext<'a> {
     pub host: &'a CoprocessorHost,
+    pub importer: &'a SSTImporter,
+    // Used to load the logs of the source region when committing merge.
+    pub raft_engine: &'a RaftEngine,
     pub wb: Option<WriteBatch>,
     pub cbs: Vec<(Callback, RaftCmdResponse)>,
     pub wb_last_bytes: u64,
     pub wb_last_keys: u64,
     pub sync_log: bool,
+    // Regions being applied by the other threads of the pool. A merge from one of them has
+    // to wait until the source region is applied.
+    pub busy_regions: HashSet<u64>,
+    // The entries left to apply after the busy source region of a merge.
+    pub deferred: Vec<Entry>,
 }
 
 impl<'a> ApplyContext<'a> {
-    fn new(host: &CoprocessorHost) -> ApplyContext {
+    fn new(
+        host: &'a CoprocessorHost,
+        importer: &'a SSTImporter,
+        raft_engine: &'a RaftEngine,
+    ) -> ApplyContext<'a> {
         ApplyContext {
             host: host,
+            importer: importer,
+            raft_engine: raft_engine,
             wb: Some(WriteBatch::with_capacity(DEFAULT_APPLY_WB_SIZE)),
             cbs: vec![],
             wb_last_bytes: 0,
             wb_last_keys: 0,
             sync_log: false,
+            busy_regions: HashSet::default(),
+            deferred: vec![],
         }
     }
 
@@ -251,11 +288,15 @@ pub fn notify_stale_req(term: u64, cb: Callback) {
 }
 
 fn should_flush_to_engine(cmd: &RaftCmdRequest, wb_keys: usize) -> bool {
-    // When encounter ComputeHash cmd, we must flush the write batch to engine immediately.
-    if cmd.has_admin_request() &&
-        cmd.get_admin_request().get_cmd_type() == AdminCmdType::ComputeHash
-    {
-        return true;
+    if cmd.has_admin_request() {
+        match cmd.get_admin_request().get_cmd_type() {
+            // When encounter ComputeHash cmd, we must flush the write batch to engine immediately.
+            AdminCmdType::ComputeHash |
+            // When encounter CommitMerge cmd, the source region is caught up by reading its
+            // states from engine, so all the pending writes must be flushed first.
+            AdminCmdType::CommitMerge => return true,
+            _ => {}
+        }
     }
 
     // When write batch contains more than `recommended` keys, flush the batch to engine.
@@ -263,10 +304,10 @@ fn should_flush_to_engine(cmd: &RaftCmdRequest, wb_keys: usize) -> bool {
         return true;
     }
 
-    // When encounter DeleteRange command, we must flush current write batch to engine first,
-    // because current write batch may contains keys are covered by DeleteRange.
+    // When encounter DeleteRange or IngestSST command, we must flush current write batch to
+    // engine first, because current write batch may contains keys are covered by them.
     for req in cmd.get_requests() {
-        if req.has_delete_range() {
+        if req.has_delete_range() || req.has_ingest_sst() {
             return true;
         }
     }
@@ -285,6 +326,9 @@ pub struct ApplyDelegate {
     // if we remove ourself in ChangePeer remove, we should set this flag, then
     // any following committed logs in same Ready should be applied failed.
     pending_remove: bool,
+    // set after PrepareMerge is applied, all the following committed logs except
+    // RollbackMerge should be applied failed.
+    is_merging: bool,
     // we write apply_state to kv rocksdb, in one writebatch together with kv data.
     // because if we write it to raft rocksdb, apply_state and kv data (Put, Delete) are in
     // separate WAL file. when power failure, for current raft log, apply_index may synced
@@ -294,6 +338,12 @@ pub struct ApplyDelegate {
     term: u64,
     pending_cmds: PendingCmdQueue,
     metrics: ApplyMetrics,
+    // Tracks the applied locks to calculate the resolved ts, `None` means the locks are not
+    // loaded yet, it's loaded the first time the resolved ts is asked.
+    resolver: Option<Resolver>,
+    // The term in which the resolver is loaded, a new leader reloads the locks instead of
+    // using the ones tracked before it's elected.
+    resolver_term: u64,
 }
 
 impl ApplyDelegate {
@@ -317,11 +367,14 @@ impl ApplyDelegate {
             engine: db,
             region: reg.region,
             pending_remove: false,
+            is_merging: reg.is_merging,
             apply_state: reg.apply_state,
             applied_index_term: reg.applied_index_term,
             term: reg.term,
             pending_cmds: Default::default(),
             metrics: Default::default(),
+            resolver: None,
+            resolver_term: 0,
         }
     }
 
@@ -337,7 +390,8 @@ impl ApplyDelegate {
         // others will be saved as a normal entry with no data, so we must re-propose these
         // commands again.
         let mut results = vec![];
-        for entry in committed_entries {
+        let mut entries = committed_entries.into_iter();
+        while let Some(entry) = entries.next() {
             if self.pending_remove {
                 // This peer is about to be destroyed, skip everything.
                 break;
@@ -356,11 +410,19 @@ impl ApplyDelegate {
             let res = match entry.get_entry_type() {
                 EntryType::EntryNormal => self.handle_raft_entry_normal(apply_ctx, entry),
                 EntryType::EntryConfChange => self.handle_raft_entry_conf_change(apply_ctx, entry),
+                EntryType::EntryConfChangeV2 => {
+                    self.handle_raft_entry_conf_change_v2(apply_ctx, entry)
+                }
             };
 
             if let Some(res) = res {
                 results.push(res);
             }
+
+            if !apply_ctx.deferred.is_empty() {
+                apply_ctx.deferred.extend(entries.by_ref());
+                break;
+            }
         }
 
         if !self.pending_remove {
@@ -404,10 +466,17 @@ impl ApplyDelegate {
     ) -> Option<ExecResult> {
         let index = entry.get_index();
         let term = entry.get_term();
-        let data = entry.get_data();
 
-        if !data.is_empty() {
-            let cmd = parse_data_at(data, index, &self.tag);
+        if !entry.get_data().is_empty() {
+            let cmd = parse_data_at(entry.get_data(), index, &self.tag);
+
+            if cmd.get_admin_request().has_commit_merge() && !self.is_merging {
+                let source = cmd.get_admin_request().get_commit_merge().get_source();
+                if apply_ctx.busy_regions.contains(&source.get_id()) {
+                    apply_ctx.deferred.push(entry);
+                    return None;
+                }
+            }
 
             if should_flush_to_engine(&cmd, apply_ctx.wb_ref().count()) {
                 self.write_apply_state(apply_ctx.wb_mut());
@@ -429,6 +498,12 @@ impl ApplyDelegate {
                 apply_ctx.mark_last_bytes_and_keys();
             }
 
+            if cmd.get_admin_request().has_commit_merge() && !self.is_merging &&
+                check_epoch(&self.region, &cmd).is_ok()
+            {
+                self.catch_up_logs_for_merge(apply_ctx, cmd.get_admin_request().get_commit_merge());
+            }
+
             return self.process_raft_cmd(apply_ctx, index, term, cmd);
         }
 
@@ -448,6 +523,87 @@ impl ApplyDelegate {
         None
     }
 
+    /// Applies the logs of the source region which are not applied on this store yet, so
+    /// that the source region is at the same state as the one committing merge.
+    fn catch_up_logs_for_merge(&self, apply_ctx: &mut ApplyContext, merge: &CommitMergeRequest) {
+        let source_region_id = merge.get_source().get_id();
+        let state_key = keys::region_state_key(source_region_id);
+        let region_state: RegionLocalState = match self.engine.get_msg_cf(CF_RAFT, &state_key) {
+            Ok(Some(s)) => s,
+            e => panic!(
+                "{} failed to load region state of source region {}: {:?}",
+                self.tag,
+                source_region_id,
+                e
+            ),
+        };
+        let apply_state_key = keys::apply_state_key(source_region_id);
+        let apply_state: RaftApplyState = match self.engine.get_msg_cf(CF_RAFT, &apply_state_key)
+        {
+            Ok(Some(s)) => s,
+            e => panic!(
+                "{} failed to load apply state of source region {}: {:?}",
+                self.tag,
+                source_region_id,
+                e
+            ),
+        };
+        let applied_index = apply_state.get_applied_index();
+        let commit = merge.get_commit();
+        if applied_index >= commit {
+            return;
+        }
+
+        // Logs before the ones carried by the request should have been persisted locally.
+        let low = merge
+            .get_entries()
+            .first()
+            .map_or(commit + 1, |e| e.get_index());
+        let mut entries: Vec<Entry> = Vec::with_capacity((commit - applied_index) as usize);
+        for idx in applied_index + 1..low {
+            match apply_ctx.raft_engine.get_entry(source_region_id, idx) {
+                Ok(Some(entry)) => entries.push(entry),
+                e => panic!(
+                    "{} failed to load log {} of source region {}: {:?}",
+                    self.tag,
+                    idx,
+                    source_region_id,
+                    e
+                ),
+            }
+        }
+        for entry in merge.get_entries() {
+            if entry.get_index() > applied_index && entry.get_index() <= commit {
+                entries.push(entry.clone());
+            }
+        }
+
+        let store_id = self.region
+            .get_peers()
+            .iter()
+            .find(|p| p.get_id() == self.id)
+            .map(|p| p.get_store_id())
+            .unwrap();
+        let source_region = region_state.get_region();
+        let reg = Registration {
+            id: util::find_peer(source_region, store_id).map_or(0, |p| p.get_id()),
+            term: 0,
+            apply_state: apply_state,
+            applied_index_term: 0,
+            region: source_region.clone(),
+            is_merging: region_state.get_state() == PeerState::Merging,
+        };
+        let mut delegate = ApplyDelegate::from_registration(self.engine.clone(), reg);
+        info!(
+            "{} catch up logs ({}, {}] of source region {}",
+            self.tag,
+            applied_index,
+            commit,
+            source_region_id
+        );
+        delegate.handle_raft_committed_entries(apply_ctx, entries);
+    }
+
     fn handle_raft_entry_conf_change(
         &mut self,
         apply_ctx: &mut ApplyContext,
@@ -482,8 +638,43 @@ impl ApplyDelegate {
         )
     }
 
+    fn handle_raft_entry_conf_change_v2(
+        &mut self,
+        apply_ctx: &mut ApplyContext,
+        entry: Entry,
+    ) -> Option<ExecResult> {
+        let index = entry.get_index();
+        let term = entry.get_term();
+        let conf_change: ConfChangeV2 = parse_data_at(entry.get_data(), index, &self.tag);
+        let cmd = parse_data_at(conf_change.get_context(), index, &self.tag);
+        Some(
+            self.process_raft_cmd(apply_ctx, index, term, cmd)
+                .map_or_else(
+                    || {
+                        // If failed, tell raft that the config change was aborted. An empty
+                        // ConfChangeV2 means leaving joint, so use an empty ConfChange instead.
+                        ExecResult::ChangePeer(Default::default())
+                    },
+                    |mut res| {
+                        if let ExecResult::ChangePeerV2(ref mut cp) = res {
+                            cp.conf_change = conf_change;
+                        } else {
+                            panic!(
+                                "{} unexpected result {:?} for conf change {:?} at {}",
+                                self.tag,
+                                res,
+                                conf_change,
+                                index
+                            );
+                        }
+                        res
+                    },
+                ),
+        )
+    }
+
     fn find_cb(&mut self, index: u64, term: u64, cmd: &RaftCmdRequest) -> Option<Callback> {
-        if get_change_peer_cmd(cmd).is_some() {
+        if get_change_peer_cmd(cmd).is_some() || get_change_peer_v2_cmd(cmd).is_some() {
             if let Some(mut cmd) = self.pending_cmds.take_conf_change() {
                 if cmd.index == index && cmd.term == term {
                     return Some(cmd.cb.take().unwrap());
@@ -524,7 +715,10 @@ impl ApplyDelegate {
 
         let cmd_cb = self.find_cb(index, term, &cmd);
         apply_ctx.host.pre_apply(&self.region, &mut cmd);
-        let (mut resp, exec_result) = self.apply_raft_cmd(apply_ctx.wb_mut(), index, term, &cmd);
+        let importer = apply_ctx.importer;
+        let (mut resp, exec_result) =
+            self.apply_raft_cmd(apply_ctx.wb_mut(), importer, index, term, &cmd);
+        apply_ctx.host.post_apply(&self.region, index, &cmd, &resp);
 
         debug!("{} applied command at log index {}", self.tag, index);
 
@@ -533,7 +727,6 @@ impl ApplyDelegate {
             Some(cb) => cb,
         };
 
-        // TODO: Involve post apply hook.
         // TODO: if we have exec_result, maybe we should return this callback too. Outer
         // store will call it after handing exec result.
         cmd_resp::bind_term(&mut resp, self.term);
@@ -551,6 +744,7 @@ impl ApplyDelegate {
     fn apply_raft_cmd(
         &mut self,
         wb: &mut WriteBatch,
+        importer: &SSTImporter,
         index: u64,
         term: u64,
         req: &RaftCmdRequest,
@@ -558,7 +752,7 @@ impl ApplyDelegate {
         // if pending remove, apply should be aborted already.
         assert!(!self.pending_remove);
 
-        let mut ctx = self.new_ctx(wb, index, term, req);
+        let mut ctx = self.new_ctx(wb, importer, index, term, req);
         ctx.wb.set_save_point();
         let (resp, exec_result) = self.exec_raft_cmd(&mut ctx).unwrap_or_else(|e| {
             // clear dirty values.
@@ -580,6 +774,9 @@ impl ApplyDelegate {
                 ExecResult::ChangePeer(ref cp) => {
                     self.region = cp.region.clone();
                 }
+                ExecResult::ChangePeerV2(ref cp) => {
+                    self.region = cp.region.clone();
+                }
                 ExecResult::ComputeHash { .. } |
                 ExecResult::VerifyHash { .. } |
                 ExecResult::CompactLog { .. } |
@@ -597,6 +794,17 @@ impl ApplyDelegate {
                     self.metrics.size_diff_hint = 0;
                     self.metrics.delete_keys_hint = 0;
                 }
+                ExecResult::PrepareMerge { ref region, .. } => {
+                    self.region = region.clone();
+                    self.is_merging = true;
+                }
+                ExecResult::CommitMerge { ref region, .. } => {
+                    self.region = region.clone();
+                }
+                ExecResult::RollbackMerge { ref region, .. } => {
+                    self.region = region.clone();
+                    self.is_merging = false;
+                }
             }
         }
 
@@ -645,6 +853,7 @@ impl ApplyDelegate {
     fn new_ctx<'a>(
         &self,
         wb: &'a mut WriteBatch,
+        importer: &'a SSTImporter,
         index: u64,
         term: u64,
         req: &'a RaftCmdRequest,
@@ -652,6 +861,7 @@ impl ApplyDelegate {
         ExecContext {
             apply_state: self.apply_state.clone(),
             wb: wb,
+            importer: importer,
             req: req,
             index: index,
             term: term,
@@ -662,6 +872,7 @@ impl ApplyDelegate {
 struct ExecContext<'a> {
     apply_state: RaftApplyState,
     wb: &'a mut WriteBatch,
+    importer: &'a SSTImporter,
     req: &'a RaftCmdRequest,
     index: u64,
     term: u64,
@@ -675,6 +886,11 @@ impl ApplyDelegate {
         ctx: &mut ExecContext,
     ) -> Result<(RaftCmdResponse, Option<ExecResult>)> {
         try!(check_epoch(&self.region, ctx.req));
+        if self.is_merging &&
+            ctx.req.get_admin_request().get_cmd_type() != AdminCmdType::RollbackMerge
+        {
+            return Err(box_err!("{} is in merging mode, skip.", self.tag));
+        }
         if ctx.req.has_admin_request() {
             self.exec_admin_cmd(ctx)
         } else {
@@ -698,11 +914,15 @@ impl ApplyDelegate {
 
         let (mut response, exec_result) = try!(match cmd_type {
             AdminCmdType::ChangePeer => self.exec_change_peer(ctx, request),
+            AdminCmdType::ChangePeerV2 => self.exec_change_peer_v2(ctx, request),
             AdminCmdType::Split => self.exec_split(ctx, request),
             AdminCmdType::CompactLog => self.exec_compact_log(ctx, request),
             AdminCmdType::TransferLeader => Err(box_err!("transfer leader won't exec")),
             AdminCmdType::ComputeHash => self.exec_compute_hash(ctx, request),
             AdminCmdType::VerifyHash => self.exec_verify_hash(ctx, request),
+            AdminCmdType::PrepareMerge => self.exec_prepare_merge(ctx, request),
+            AdminCmdType::CommitMerge => self.exec_commit_merge(ctx, request),
+            AdminCmdType::RollbackMerge => self.exec_rollback_merge(ctx, request),
             AdminCmdType::InvalidAdmin => Err(box_err!("unsupported admin command type")),
         });
         response.set_cmd_type(cmd_type);
@@ -730,8 +950,17 @@ impl ApplyDelegate {
             region.get_region_epoch()
         );
 
+        if util::is_region_in_joint(&region) {
+            return Err(box_err!(
+                "can't exec ConfChange {:?} for region {:?} in joint state",
+                change_type,
+                region
+            ));
+        }
+
         // TODO: we should need more check, like peer validation, duplicated id, etc.
-        let exists = util::find_peer(&region, store_id).is_some();
+        let exist_peer = util::find_peer(&region, store_id).cloned();
+        let exists = exist_peer.is_some();
         let conf_ver = region.get_region_epoch().get_conf_ver() + 1;
 
         region.mut_region_epoch().set_conf_ver(conf_ver);
@@ -742,30 +971,88 @@ impl ApplyDelegate {
                     .with_label_values(&["add_peer", "all"])
                     .inc();
 
+                match exist_peer {
+                    Some(ref p) if p.get_is_learner() && p.get_id() == peer.get_id() => {
+                        // Promote the learner to a voter.
+                        for p in region.mut_peers().iter_mut() {
+                            if p.get_id() == peer.get_id() {
+                                p.set_is_learner(false);
+                                p.set_role(PeerRole::Voter);
+                            }
+                        }
+
+                        PEER_ADMIN_CMD_COUNTER_VEC
+                            .with_label_values(&["promote_learner", "success"])
+                            .inc();
+
+                        info!(
+                            "{} promote learner {:?} to voter in region {:?}",
+                            self.tag,
+                            peer,
+                            self.region
+                        );
+                    }
+                    Some(_) => {
+                        error!(
+                            "{} can't add duplicated peer {:?} to region {:?}",
+                            self.tag,
+                            peer,
+                            self.region
+                        );
+                        return Err(box_err!(
+                            "can't add duplicated peer {:?} to region {:?}",
+                            peer,
+                            self.region
+                        ));
+                    }
+                    None => {
+                        // TODO: Do we allow adding peer in same node?
+
+                        region.mut_peers().push(peer.clone());
+
+                        PEER_ADMIN_CMD_COUNTER_VEC
+                            .with_label_values(&["add_peer", "success"])
+                            .inc();
+
+                        info!(
+                            "{} add peer {:?} to region {:?}",
+                            self.tag,
+                            peer,
+                            self.region
+                        );
+                    }
+                }
+            }
+            ConfChangeType::AddLearnerNode => {
+                PEER_ADMIN_CMD_COUNTER_VEC
+                    .with_label_values(&["add_learner", "all"])
+                    .inc();
+
                 if exists {
                     error!(
-                        "{} can't add duplicated peer {:?} to region {:?}",
+                        "{} can't add duplicated learner {:?} to region {:?}",
                         self.tag,
                         peer,
                         self.region
                     );
                     return Err(box_err!(
-                        "can't add duplicated peer {:?} to region {:?}",
+                        "can't add duplicated learner {:?} to region {:?}",
                         peer,
                         self.region
                     ));
                 }
 
-                // TODO: Do we allow adding peer in same node?
-
-                region.mut_peers().push(peer.clone());
+                let mut learner = peer.clone();
+                learner.set_is_learner(true);
+                learner.set_role(PeerRole::Learner);
+                region.mut_peers().push(learner);
 
                 PEER_ADMIN_CMD_COUNTER_VEC
-                    .with_label_values(&["add_peer", "success"])
+                    .with_label_values(&["add_learner", "success"])
                     .inc();
 
                 info!(
-                    "{} add peer {:?} to region {:?}",
+                    "{} add learner {:?} to region {:?}",
                     self.tag,
                     peer,
                     self.region
@@ -816,23 +1103,182 @@ impl ApplyDelegate {
         } else {
             PeerState::Normal
         };
-        if let Err(e) = write_peer_state(&self.engine, ctx.wb, &region, state) {
+        if let Err(e) = write_peer_state(&self.engine, ctx.wb, &region, state, None) {
             panic!("{} failed to update region state: {:?}", self.tag, e);
         }
 
         let mut resp = AdminResponse::new();
         resp.mut_change_peer().set_region(region.clone());
 
+        // Use the peer in the region so that the learner flag is kept.
+        let peer = util::find_peer(&region, store_id)
+            .cloned()
+            .unwrap_or_else(|| peer.clone());
         Ok((
             resp,
             Some(ExecResult::ChangePeer(ChangePeer {
                 conf_change: Default::default(),
-                peer: peer.clone(),
+                peer: peer,
                 region: region,
             })),
         ))
     }
 
+    fn exec_change_peer_v2(
+        &mut self,
+        ctx: &ExecContext,
+        request: &AdminRequest,
+    ) -> Result<(AdminResponse, Option<ExecResult>)> {
+        PEER_ADMIN_CMD_COUNTER_VEC
+            .with_label_values(&["change_peer_v2", "all"])
+            .inc();
+
+        let changes = request.get_change_peer_v2().get_changes();
+        let mut region = self.region.clone();
+        info!(
+            "{} exec ConfChangeV2 {:?}, epoch: {:?}",
+            self.tag,
+            changes,
+            region.get_region_epoch()
+        );
+
+        if changes.is_empty() {
+            try!(self.apply_leave_joint(&mut region));
+        } else {
+            try!(self.apply_enter_joint(&mut region, changes));
+        }
+
+        let conf_ver = region.get_region_epoch().get_conf_ver() + 1;
+        region.mut_region_epoch().set_conf_ver(conf_ver);
+
+        let state = if self.pending_remove {
+            PeerState::Tombstone
+        } else {
+            PeerState::Normal
+        };
+        if let Err(e) = write_peer_state(&self.engine, ctx.wb, &region, state, None) {
+            panic!("{} failed to update region state: {:?}", self.tag, e);
+        }
+
+        PEER_ADMIN_CMD_COUNTER_VEC
+            .with_label_values(&["change_peer_v2", "success"])
+            .inc();
+
+        let mut resp = AdminResponse::new();
+        resp.mut_change_peer_v2().set_region(region.clone());
+        Ok((
+            resp,
+            Some(ExecResult::ChangePeerV2(ChangePeerV2 {
+                conf_change: Default::default(),
+                changes: changes.to_vec(),
+                region: region,
+            })),
+        ))
+    }
+
+    // A voter can only be removed from the region after it has been demoted to a learner,
+    // so a peer never needs to be kept in the region only for the outgoing configuration.
+    fn apply_enter_joint(
+        &mut self,
+        region: &mut Region,
+        changes: &[ChangePeerRequest],
+    ) -> Result<()> {
+        if util::is_region_in_joint(region) {
+            return Err(box_err!(
+                "can't enter joint consensus for region {:?} in joint state",
+                region
+            ));
+        }
+        let mut remove_self = false;
+        for cp in changes {
+            let peer = cp.get_peer();
+            let change_type = cp.get_change_type();
+            let exist_peer = util::find_peer(region, peer.get_store_id()).cloned();
+            match (change_type, exist_peer) {
+                (ConfChangeType::AddNode, None) => {
+                    let mut p = peer.clone();
+                    p.set_is_learner(false);
+                    p.set_role(PeerRole::IncomingVoter);
+                    region.mut_peers().push(p);
+                }
+                (ConfChangeType::AddNode, Some(ref p))
+                    if p.get_is_learner() && p.get_id() == peer.get_id() =>
+                {
+                    set_peer_role(region, peer.get_id(), PeerRole::IncomingVoter);
+                }
+                (ConfChangeType::AddLearnerNode, None) => {
+                    let mut p = peer.clone();
+                    p.set_is_learner(true);
+                    p.set_role(PeerRole::Learner);
+                    region.mut_peers().push(p);
+                }
+                (ConfChangeType::AddLearnerNode, Some(ref p))
+                    if !p.get_is_learner() && p.get_id() == peer.get_id() =>
+                {
+                    set_peer_role(region, peer.get_id(), PeerRole::DemotingVoter);
+                }
+                (ConfChangeType::RemoveNode, Some(ref p))
+                    if p.get_is_learner() && p.get_id() == peer.get_id() =>
+                {
+                    remove_self |= self.id == peer.get_id();
+                    util::remove_peer(region, peer.get_store_id()).unwrap();
+                }
+                (change_type, exist_peer) => {
+                    error!(
+                        "{} invalid conf change {:?} of peer {:?}, exist peer {:?}, region {:?}",
+                        self.tag,
+                        change_type,
+                        peer,
+                        exist_peer,
+                        self.region
+                    );
+                    return Err(box_err!(
+                        "invalid conf change {:?} of peer {:?} in region {:?}",
+                        change_type,
+                        peer,
+                        self.region
+                    ));
+                }
+            }
+        }
+        if remove_self {
+            // Remove ourself, we will destroy all region data later.
+            // So we need not to apply following logs.
+            self.pending_remove = true;
+        }
+        info!(
+            "{} enter joint consensus, region changes to {:?}",
+            self.tag,
+            region
+        );
+        Ok(())
+    }
+
+    fn apply_leave_joint(&self, region: &mut Region) -> Result<()> {
+        if !util::is_region_in_joint(region) {
+            return Err(box_err!(
+                "can't leave joint consensus for region {:?} not in joint state",
+                region
+            ));
+        }
+        for p in region.mut_peers().iter_mut() {
+            match p.get_role() {
+                PeerRole::IncomingVoter => p.set_role(PeerRole::Voter),
+                PeerRole::DemotingVoter => {
+                    p.set_role(PeerRole::Learner);
+                    p.set_is_learner(true);
+                }
+                PeerRole::Voter | PeerRole::Learner => {}
+            }
+        }
+        info!(
+            "{} leave joint consensus, region changes to {:?}",
+            self.tag,
+            region
+        );
+        Ok(())
+    }
+
     fn exec_split(
         &mut self,
         ctx: &ExecContext,
@@ -896,9 +1342,9 @@ impl ApplyDelegate {
         let region_ver = region.get_region_epoch().get_version() + 1;
         region.mut_region_epoch().set_version(region_ver);
         new_region.mut_region_epoch().set_version(region_ver);
-        write_peer_state(&self.engine, ctx.wb, &region, PeerState::Normal)
+        write_peer_state(&self.engine, ctx.wb, &region, PeerState::Normal, None)
             .and_then(|_| {
-                write_peer_state(&self.engine, ctx.wb, &new_region, PeerState::Normal)
+                write_peer_state(&self.engine, ctx.wb, &new_region, PeerState::Normal, None)
             })
             .and_then(|_| {
                 write_initial_apply_state(&self.engine, ctx.wb, new_region.get_id())
@@ -946,6 +1392,174 @@ impl ApplyDelegate {
         }
     }
 
+    fn exec_prepare_merge(
+        &mut self,
+        ctx: &ExecContext,
+        req: &AdminRequest,
+    ) -> Result<(AdminResponse, Option<ExecResult>)> {
+        PEER_ADMIN_CMD_COUNTER_VEC
+            .with_label_values(&["prepare_merge", "all"])
+            .inc();
+
+        let prepare_merge = req.get_prepare_merge();
+        let min_index = prepare_merge.get_min_index();
+        let first_index = peer_storage::first_index(&ctx.apply_state);
+        if min_index < first_index {
+            // Logs in the gap may be compacted after the request is proposed.
+            return Err(box_err!(
+                "first index {} > min index {}, skip prepare merge",
+                first_index,
+                min_index
+            ));
+        }
+
+        let mut region = self.region.clone();
+        let version = region.get_region_epoch().get_version() + 1;
+        region.mut_region_epoch().set_version(version);
+        // Increase conf version too so that no conf change can be done during merging.
+        let conf_ver = region.get_region_epoch().get_conf_ver() + 1;
+        region.mut_region_epoch().set_conf_ver(conf_ver);
+
+        let mut merge_state = MergeState::new();
+        merge_state.set_min_index(min_index);
+        merge_state.set_target(prepare_merge.get_target().clone());
+        merge_state.set_commit(ctx.index);
+        write_peer_state(
+            &self.engine,
+            ctx.wb,
+            &region,
+            PeerState::Merging,
+            Some(merge_state.clone()),
+        ).unwrap_or_else(|e| {
+            panic!(
+                "{} failed to save merging state {:?} for region {:?}: {:?}",
+                self.tag,
+                merge_state,
+                region,
+                e
+            )
+        });
+
+        info!(
+            "{} prepare merge into region {:?}, state: {:?}",
+            self.tag,
+            prepare_merge.get_target(),
+            merge_state
+        );
+
+        PEER_ADMIN_CMD_COUNTER_VEC
+            .with_label_values(&["prepare_merge", "success"])
+            .inc();
+
+        Ok((
+            AdminResponse::new(),
+            Some(ExecResult::PrepareMerge {
+                region: region,
+                state: merge_state,
+            }),
+        ))
+    }
+
+    fn exec_commit_merge(
+        &mut self,
+        ctx: &ExecContext,
+        req: &AdminRequest,
+    ) -> Result<(AdminResponse, Option<ExecResult>)> {
+        PEER_ADMIN_CMD_COUNTER_VEC
+            .with_label_values(&["commit_merge", "all"])
+            .inc();
+
+        let source = req.get_commit_merge().get_source();
+        let mut region = self.region.clone();
+        if keys::enc_end_key(&region) == keys::enc_start_key(source) {
+            region.set_end_key(source.get_end_key().to_vec());
+        } else if keys::enc_start_key(&region) == keys::enc_end_key(source) {
+            region.set_start_key(source.get_start_key().to_vec());
+        } else {
+            return Err(box_err!(
+                "source region {:?} is not adjacent to {:?}",
+                source,
+                region
+            ));
+        }
+        // Use the larger version so that the merged region is newer than both the
+        // source and the target.
+        let version = cmp::max(
+            source.get_region_epoch().get_version(),
+            region.get_region_epoch().get_version(),
+        ) + 1;
+        region.mut_region_epoch().set_version(version);
+
+        write_peer_state(&self.engine, ctx.wb, &region, PeerState::Normal, None)
+            .and_then(|_| {
+                write_peer_state(&self.engine, ctx.wb, source, PeerState::Tombstone, None)
+            })
+            .unwrap_or_else(|e| {
+                panic!(
+                    "{} failed to save merged region {:?}: {:?}",
+                    self.tag,
+                    region,
+                    e
+                )
+            });
+
+        info!("{} merge {:?} into {:?}", self.tag, source, region);
+        // The locks of the source region are not tracked.
+        self.resolver = None;
+
+        PEER_ADMIN_CMD_COUNTER_VEC
+            .with_label_values(&["commit_merge", "success"])
+            .inc();
+
+        Ok((
+            AdminResponse::new(),
+            Some(ExecResult::CommitMerge {
+                region: region,
+                source: source.clone(),
+            }),
+        ))
+    }
+
+    fn exec_rollback_merge(
+        &mut self,
+        ctx: &ExecContext,
+        req: &AdminRequest,
+    ) -> Result<(AdminResponse, Option<ExecResult>)> {
+        PEER_ADMIN_CMD_COUNTER_VEC
+            .with_label_values(&["rollback_merge", "all"])
+            .inc();
+
+        if !self.is_merging {
+            return Err(box_err!("region is not in merging mode, skip rollback"));
+        }
+
+        let mut region = self.region.clone();
+        // Increase version so that duplicated rollback requests are rejected.
+        let version = region.get_region_epoch().get_version() + 1;
+        region.mut_region_epoch().set_version(version);
+        write_peer_state(&self.engine, ctx.wb, &region, PeerState::Normal, None)
+            .unwrap_or_else(|e| {
+                panic!(
+                    "{} failed to rollback merge for region {:?}: {:?}",
+                    self.tag,
+                    region,
+                    e
+                )
+            });
+
+        PEER_ADMIN_CMD_COUNTER_VEC
+            .with_label_values(&["rollback_merge", "success"])
+            .inc();
+
+        Ok((
+            AdminResponse::new(),
+            Some(ExecResult::RollbackMerge {
+                region: region,
+                commit: req.get_rollback_merge().get_commit(),
+            }),
+        ))
+    }
+
     fn exec_compact_log(
         &mut self,
         ctx: &mut ExecContext,
@@ -1018,6 +1632,7 @@ impl ApplyDelegate {
                 CmdType::Put => self.handle_put(ctx, req),
                 CmdType::Delete => self.handle_delete(ctx, req),
                 CmdType::DeleteRange => self.handle_delete_range(req, &mut ranges),
+                CmdType::IngestSST => self.handle_ingest_sst(ctx.importer, req),
                 // Readonly commands are handled in raftstore directly.
                 // Don't panic here in case there are old entries need to be applied.
                 // It's also safe to skip them here, because a restart must have happened,
@@ -1062,6 +1677,7 @@ impl ApplyDelegate {
             if cf == CF_LOCK {
                 self.metrics.lock_cf_written_bytes += key.len() as u64;
                 self.metrics.lock_cf_written_bytes += value.len() as u64;
+                self.track_lock(req.get_put().get_key(), value);
             }
             // TODO: check whether cf exists or not.
             rocksdb::get_cf_handle(&self.engine, cf)
@@ -1110,6 +1726,9 @@ impl ApplyDelegate {
             if cf == CF_LOCK {
                 // delete is a kind of write for RocksDB.
                 self.metrics.lock_cf_written_bytes += key.len() as u64;
+                if let Some(ref mut resolver) = self.resolver {
+                    resolver.untrack_lock(req.get_delete().get_key());
+                }
             } else {
                 self.metrics.delete_keys_hint += 1;
             }
@@ -1177,10 +1796,76 @@ impl ApplyDelegate {
             );
         });
 
+        if cf == CF_LOCK {
+            // The locks are reloaded the next time the resolved ts is asked.
+            self.resolver = None;
+        }
         ranges.push(Range::new(cf.to_owned(), start_key, end_key));
 
         Ok(resp)
     }
+
+    fn handle_ingest_sst(&mut self, importer: &SSTImporter, req: &Request) -> Result<Response> {
+        let sst = req.get_ingest_sst().get_sst();
+        try!(check_sst_for_ingestion(sst, &self.region));
+        // The file may be missing, for example it's ingested before a restart and the log is
+        // applied again, so the command fails instead of the peer.
+        let (first, last) = match importer.key_range(sst) {
+            Ok(range) => range,
+            Err(e) => {
+                error!("{} failed to read {:?}: {:?}", self.tag, sst, e);
+                return Err(box_err!("failed to read {:?}: {:?}", sst, e));
+            }
+        };
+        // The range in the meta is sent by the client, the keys of the file are checked.
+        try!(check_data_key(&first, &self.region));
+        try!(check_data_key(&last, &self.region));
+        let size = match importer.ingest(sst, &self.engine) {
+            Ok(size) => size,
+            Err(e) => {
+                error!("{} failed to ingest {:?}: {:?}", self.tag, sst, e);
+                return Err(box_err!("failed to ingest {:?}: {:?}", sst, e));
+            }
+        };
+        self.metrics.size_diff_hint += size as i64;
+        if sst.get_cf_name() == CF_LOCK {
+            // The locks are reloaded the next time the resolved ts is asked.
+            self.resolver = None;
+        }
+        Ok(Response::new())
+    }
+
+    fn track_lock(&mut self, key: &[u8], value: &[u8]) {
+        let resolver = match self.resolver {
+            Some(ref mut resolver) => resolver,
+            None => return,
+        };
+        match Lock::parse(value) {
+            // Pessimistic locks are never committed directly.
+            Ok(ref lock) if lock.lock_type == LockType::Pessimistic => {}
+            Ok(lock) => resolver.track_lock(lock.ts, key.to_vec()),
+            Err(e) => panic!("{} failed to parse lock {}: {:?}", self.tag, escape(key), e),
+        }
+    }
+
+    // Loads the locks of the region from the engine, all the applied logs must have been
+    // written to the engine.
+    fn init_resolver(&mut self) {
+        let mut resolver = Resolver::new();
+        let start_key = keys::enc_start_key(&self.region);
+        let end_key = keys::enc_end_key(&self.region);
+        self.engine
+            .scan_cf(CF_LOCK, &start_key, &end_key, false, &mut |key, value| {
+                let lock = box_try!(Lock::parse(value));
+                if lock.lock_type != LockType::Pessimistic {
+                    resolver.track_lock(lock.ts, keys::origin_key(key).to_vec());
+                }
+                Ok(true)
+            })
+            .unwrap_or_else(|e| panic!("{} failed to load locks: {:?}", self.tag, e));
+        self.resolver = Some(resolver);
+        self.resolver_term = self.term;
+    }
 }
 
 pub fn get_change_peer_cmd(msg: &RaftCmdRequest) -> Option<&ChangePeerRequest> {
@@ -1195,6 +1880,27 @@ pub fn get_change_peer_cmd(msg: &RaftCmdRequest) -> Option<&ChangePeerRequest> {
     Some(req.get_change_peer())
 }
 
+pub fn get_change_peer_v2_cmd(msg: &RaftCmdRequest) -> Option<&ChangePeerV2Request> {
+    if !msg.has_admin_request() {
+        return None;
+    }
+    let req = msg.get_admin_request();
+    if !req.has_change_peer_v2() {
+        return None;
+    }
+
+    Some(req.get_change_peer_v2())
+}
+
+fn set_peer_role(region: &mut Region, peer_id: u64, role: PeerRole) {
+    for p in region.mut_peers().iter_mut() {
+        if p.get_id() == peer_id {
+            p.set_is_learner(role == PeerRole::Learner);
+            p.set_role(role);
+        }
+    }
+}
+
 fn check_data_key(key: &[u8], region: &Region) -> Result<()> {
     // region key range has no data prefix, so we must use origin key to check.
     try!(util::check_key_in_region(key, region));
@@ -1202,6 +1908,27 @@ fn check_data_key(key: &[u8], region: &Region) -> Result<()> {
     Ok(())
 }
 
+// The file must be downloaded with the current epoch of the region, so that all the peers
+// have it.
+fn check_sst_for_ingestion(sst: &SSTMeta, region: &Region) -> Result<()> {
+    let epoch = sst.get_region_epoch();
+    let region_epoch = region.get_region_epoch();
+    if epoch.get_conf_ver() != region_epoch.get_conf_ver() ||
+        epoch.get_version() != region_epoch.get_version()
+    {
+        return Err(Error::StaleEpoch(
+            format!(
+                "epoch of region {} is {:?}, but the sst has {:?}",
+                region.get_id(),
+                region_epoch,
+                epoch
+            ),
+            vec![region.to_owned()],
+        ));
+    }
+    Ok(())
+}
+
 pub fn do_get(tag: &str, region: &Region, snap: &Snapshot, req: &Request) -> Result<Response> {
     // TODO: the get_get looks wried, maybe we should figure out a better name later.
     let key = req.get_get().get_key();
@@ -1303,6 +2030,7 @@ pub struct Registration {
     pub apply_state: RaftApplyState,
     pub applied_index_term: u64,
     pub region: Region,
+    pub is_merging: bool,
 }
 
 impl Registration {
@@ -1313,6 +2041,7 @@ impl Registration {
             apply_state: peer.get_store().apply_state.clone(),
             applied_index_term: peer.get_store().applied_index_term,
             region: peer.region().clone(),
+            is_merging: peer.pending_merge_state.is_some(),
         }
     }
 }
@@ -1355,12 +2084,18 @@ pub struct Destroy {
     region_id: u64,
 }
 
+pub struct ResolveTs {
+    min_ts: u64,
+    region_ids: Vec<u64>,
+}
+
 /// region related task.
 pub enum Task {
     Applies(Vec<Apply>),
     Registration(Registration),
     Proposals(Vec<RegionProposal>),
     Destroy(Destroy),
+    ResolveTs(ResolveTs),
 }
 
 impl Task {
@@ -1377,6 +2112,13 @@ impl Task {
             region_id: region_id,
         })
     }
+
+    pub fn resolve_ts(min_ts: u64, region_ids: Vec<u64>) -> Task {
+        Task::ResolveTs(ResolveTs {
+            min_ts: min_ts,
+            region_ids: region_ids,
+        })
+    }
 }
 
 impl Display for Task {
@@ -1388,6 +2130,12 @@ impl Display for Task {
                 write!(f, "[region {}] Reg {:?}", r.region.get_id(), r.apply_state)
             }
             Task::Destroy(ref d) => write!(f, "[region {}] destroy", d.region_id),
+            Task::ResolveTs(ref r) => write!(
+                f,
+                "resolve ts {} for region count {}",
+                r.min_ts,
+                r.region_ids.len()
+            ),
         }
     }
 }
@@ -1413,19 +2161,116 @@ pub struct ApplyRes {
     pub metrics: ApplyMetrics,
 }
 
+/// The resolved ts of a region at the applied index.
+#[derive(Debug, PartialEq)]
+pub struct ResolvedTs {
+    pub region_id: u64,
+    pub resolved_ts: u64,
+    pub applied_index: u64,
+    // The term of the applied logs.
+    pub term: u64,
+}
+
 #[derive(Debug)]
 pub enum TaskRes {
     Applys(Vec<ApplyRes>),
     Destroy(ApplyDelegate),
+    ResolvedTs(Vec<ResolvedTs>),
+}
+
+// Applies the committed entries of the regions in order, the applies of the regions are
+// skipped if they are merged by the ones before them. The applies left by the merges waiting
+// for the busy source regions are returned with the results.
+fn apply_committed_entries(
+    apply_ctx: &mut ApplyContext,
+    delegates: &mut HashMap<u64, ApplyDelegate>,
+    applys: Vec<Apply>,
+) -> (Vec<ApplyRes>, Vec<Apply>) {
+    let mut applys_res = Vec::with_capacity(applys.len());
+    let mut deferred: Vec<Apply> = vec![];
+    // Source regions which have been merged into other regions in this batch.
+    let mut merged_regions = vec![];
+    for apply in applys {
+        if apply.entries.is_empty() || merged_regions.contains(&apply.region_id) {
+            continue;
+        }
+        if deferred.iter().any(|a| a.region_id == apply.region_id) {
+            deferred.push(apply);
+            continue;
+        }
+        let mut e = match delegates.entry(apply.region_id) {
+            MapEntry::Vacant(_) => {
+                error!("[region {}] is missing", apply.region_id);
+                continue;
+            }
+            MapEntry::Occupied(e) => e,
+        };
+        {
+            let delegate = e.get_mut();
+            delegate.metrics = ApplyMetrics::default();
+            delegate.term = apply.term;
+            let results = delegate.handle_raft_committed_entries(apply_ctx, apply.entries);
+            for res in &results {
+                if let ExecResult::CommitMerge { ref source, .. } = *res {
+                    merged_regions.push(source.get_id());
+                }
+            }
+            if !apply_ctx.deferred.is_empty() {
+                let entries = mem::replace(&mut apply_ctx.deferred, vec![]);
+                deferred.push(Apply::new(apply.region_id, apply.term, entries));
+            }
+
+            if delegate.pending_remove {
+                delegate.destroy();
+            }
+
+            applys_res.push(ApplyRes {
+                region_id: apply.region_id,
+                apply_state: delegate.apply_state.clone(),
+                exec_res: results,
+                metrics: delegate.metrics.clone(),
+                applied_index_term: delegate.applied_index_term,
+            });
+        }
+        if e.get().pending_remove {
+            e.remove();
+        }
+    }
+
+    // The source regions are caught up by their targets, so their delegates are stale.
+    for region_id in merged_regions {
+        if let Some(mut delegate) = delegates.remove(&region_id) {
+            info!("{} is merged, remove from apply delegates", delegate.tag);
+            delegate.destroy();
+        }
+    }
+
+    (applys_res, deferred)
+}
+
+// The results of the applies on a thread of the apply pool, the write batch and the
+// callbacks are handled by the runner after all the threads are done.
+struct PoolApplyRes {
+    delegates: HashMap<u64, ApplyDelegate>,
+    applys_res: Vec<ApplyRes>,
+    deferred: Vec<Apply>,
+    wb: WriteBatch,
+    cbs: Vec<(Callback, RaftCmdResponse)>,
+    sync_log: bool,
 }
 
-// TODO: use threadpool to do task concurrently
 pub struct Runner {
     db: Arc<DB>,
+    raft_engine: Arc<RaftEngine>,
     host: Arc<CoprocessorHost>,
+    importer: Arc<SSTImporter>,
     delegates: HashMap<u64, ApplyDelegate>,
     notifier: Sender<TaskRes>,
     sync_log: bool,
+    // The regions of a batch are applied concurrently by the pool, `None` means the batches
+    // are applied on the runner thread.
+    pool: Option<CpuPool>,
+    pool_size: usize,
     tag: String,
 }
 
@@ -1436,12 +2281,26 @@ impl Runner {
         for (&region_id, p) in store.get_peers() {
             delegates.insert(region_id, ApplyDelegate::from_peer(p));
         }
+        let pool_size = store.config().apply_pool_size;
+        let pool = if pool_size > 1 {
+            let pool = Builder::new()
+                .name_prefix(thd_name!("apply"))
+                .pool_size(pool_size)
+                .create();
+            Some(pool)
+        } else {
+            None
+        };
         Runner {
             db: store.kv_engine(),
+            raft_engine: store.raft_engine(),
             host: store.coprocessor_host.clone(),
+            importer: store.importer.clone(),
             delegates: delegates,
             notifier: notifier,
             sync_log: sync_log,
+            pool: pool,
+            pool_size: pool_size,
             tag: format!("[store {}]", store.store_id()),
         }
     }
@@ -1449,44 +2308,41 @@ impl Runner {
     fn handle_applies(&mut self, applys: Vec<Apply>) {
         let t = SlowTimer::new();
 
-        let mut applys_res = Vec::with_capacity(applys.len());
-        let mut apply_ctx = ApplyContext::new(self.host.as_ref());
-        let mut committed_count = 0;
-        for apply in applys {
-            if apply.entries.is_empty() {
-                continue;
-            }
-            let mut e = match self.delegates.entry(apply.region_id) {
-                MapEntry::Vacant(_) => {
-                    error!("[region {}] is missing", apply.region_id);
-                    continue;
-                }
-                MapEntry::Occupied(e) => e,
-            };
-            {
-                let delegate = e.get_mut();
-                delegate.metrics = ApplyMetrics::default();
-                delegate.term = apply.term;
-                committed_count += apply.entries.len();
-                let results = delegate.handle_raft_committed_entries(&mut apply_ctx, apply.entries);
-
-                if delegate.pending_remove {
-                    delegate.destroy();
+        let committed_count: usize = applys.iter().map(|a| a.entries.len()).sum();
+        let applys_res = match self.pool.clone() {
+            Some(ref pool) if applys.len() > 1 => {
+                let (mut applys_res, deferred) = self.apply_on_pool(pool, applys);
+                if !deferred.is_empty() {
+                    // The source regions of the merges are applied now.
+                    applys_res.extend(self.apply_on_runner(deferred));
                 }
-
-                applys_res.push(ApplyRes {
-                    region_id: apply.region_id,
-                    apply_state: delegate.apply_state.clone(),
-                    exec_res: results,
-                    metrics: delegate.metrics.clone(),
-                    applied_index_term: delegate.applied_index_term,
-                });
-            }
-            if e.get().pending_remove {
-                e.remove();
+                applys_res
             }
+            _ => self.apply_on_runner(applys),
+        };
+
+        if !applys_res.is_empty() {
+            self.notifier.send(TaskRes::Applys(applys_res)).unwrap();
         }
 
+        STORE_APPLY_LOG_HISTOGRAM.observe(duration_to_sec(t.elapsed()) as f64);
+
+        slow_log!(
+            t,
+            "{} handle ready {} committed entries",
+            self.tag,
+            committed_count
+        );
+    }
+
+    fn apply_on_runner(&mut self, applys: Vec<Apply>) -> Vec<ApplyRes> {
+        let mut apply_ctx = ApplyContext::new(
+            self.host.as_ref(),
+            self.importer.as_ref(),
+            &*self.raft_engine,
+        );
+        let (applys_res, _) = apply_committed_entries(&mut apply_ctx, &mut self.delegates, applys);
+
         // Write to engine
         // raftsotre.sync-log = true means we need prevent data loss when power failure.
         // take raft log gc for example, we write kv WAL first, then write raft WAL,
@@ -1503,18 +2359,88 @@ impl Runner {
             cb(resp);
         }
 
-        if !applys_res.is_empty() {
-            self.notifier.send(TaskRes::Applys(applys_res)).unwrap();
+        applys_res
+    }
+
+    // Spreads the regions over the threads of the pool, the applies of a region are on the
+    // same thread so that they are applied in order. The applies of the merges whose source
+    // regions are on the other threads are returned, they should be applied after the results
+    // of the pool are written.
+    fn apply_on_pool(&mut self, pool: &CpuPool, applys: Vec<Apply>) -> (Vec<ApplyRes>, Vec<Apply>) {
+        let mut batches: Vec<(HashMap<u64, ApplyDelegate>, Vec<Apply>)> = (0..self.pool_size)
+            .map(|_| (HashMap::default(), vec![]))
+            .collect();
+        let mut owners = HashMap::default();
+        for apply in applys {
+            let next = owners.len() % self.pool_size;
+            let idx = *owners.entry(apply.region_id).or_insert(next);
+            if let Some(delegate) = self.delegates.remove(&apply.region_id) {
+                batches[idx].0.insert(apply.region_id, delegate);
+            }
+            batches[idx].1.push(apply);
         }
 
-        STORE_APPLY_LOG_HISTOGRAM.observe(duration_to_sec(t.elapsed()) as f64);
+        let mut futures = Vec::with_capacity(self.pool_size);
+        for (idx, (mut delegates, applys)) in batches.into_iter().enumerate() {
+            if applys.is_empty() {
+                continue;
+            }
+            let busy_regions: HashSet<u64> = owners
+                .iter()
+                .filter(|&(_, &i)| i != idx)
+                .map(|(&region_id, _)| region_id)
+                .collect();
+            let host = self.host.clone();
+            let importer = self.importer.clone();
+            let raft_engine = self.raft_engine.clone();
+            let f = pool.spawn_fn(move || {
+                let mut apply_ctx = ApplyContext::new(&host, &importer, &*raft_engine);
+                apply_ctx.busy_regions = busy_regions;
+                let (applys_res, deferred) =
+                    apply_committed_entries(&mut apply_ctx, &mut delegates, applys);
+                let res = PoolApplyRes {
+                    delegates: delegates,
+                    applys_res: applys_res,
+                    deferred: deferred,
+                    wb: apply_ctx.wb.take().unwrap(),
+                    cbs: mem::replace(&mut apply_ctx.cbs, vec![]),
+                    sync_log: apply_ctx.sync_log,
+                };
+                Ok::<_, ()>(res)
+            });
+            futures.push(f);
+        }
+
+        let mut applys_res = vec![];
+        let mut deferred = vec![];
+        let mut wbs = Vec::with_capacity(futures.len());
+        let mut cbs = vec![];
+        let mut sync_log = false;
+        for f in futures {
+            let res = f.wait().unwrap();
+            self.delegates.extend(res.delegates);
+            applys_res.extend(res.applys_res);
+            deferred.extend(res.deferred);
+            wbs.push(res.wb);
+            cbs.extend(res.cbs);
+            sync_log |= res.sync_log;
+        }
+
+        // The write batch of a thread holds both the data and the apply states of its regions.
+        // Every batch is synced, so a batch isn't lost on a crash once it's written.
+        let mut write_opts = WriteOptions::new();
+        write_opts.set_sync(self.sync_log && sync_log);
+        for wb in wbs {
+            self.db
+                .write_opt(wb, &write_opts)
+                .unwrap_or_else(|e| panic!("failed to write to engine, error: {:?}", e));
+        }
 
-        slow_log!(
-            t,
-            "{} handle ready {} committed entries",
-            self.tag,
-            committed_count
-        );
+        for (cb, resp) in cbs {
+            cb(resp);
+        }
+
+        (applys_res, deferred)
     }
 
     fn handle_proposals(&mut self, proposals: Vec<RegionProposal>) {
@@ -1578,6 +2504,29 @@ impl Runner {
         }
     }
 
+    fn handle_resolve_ts(&mut self, r: ResolveTs) {
+        let mut res = Vec::with_capacity(r.region_ids.len());
+        for region_id in r.region_ids {
+            let delegate = match self.delegates.get_mut(&region_id) {
+                Some(delegate) => delegate,
+                None => continue,
+            };
+            if delegate.resolver.is_none() || delegate.resolver_term != delegate.term {
+                delegate.init_resolver();
+            }
+            let resolved_ts = delegate.resolver.as_mut().unwrap().resolve(r.min_ts);
+            res.push(ResolvedTs {
+                region_id: region_id,
+                resolved_ts: resolved_ts,
+                applied_index: delegate.apply_state.get_applied_index(),
+                term: delegate.term,
+            });
+        }
+        if !res.is_empty() {
+            self.notifier.send(TaskRes::ResolvedTs(res)).unwrap();
+        }
+    }
+
     fn handle_shutdown(&mut self) {
         for p in self.delegates.values_mut() {
             p.clear_pending_commands();
@@ -1592,6 +2541,7 @@ impl Runnable<Task> for Runner {
             Task::Proposals(props) => self.handle_proposals(props),
             Task::Registration(s) => self.handle_registration(s),
             Task::Destroy(d) => self.handle_destroy(d),
+            Task::ResolveTs(r) => self.handle_resolve_ts(r),
         }
     }
 
@@ -1605,13 +2555,15 @@ mod tests {
     use std::sync::*;
 
     use tempdir::TempDir;
-    use rocksdb::{Writable, WriteBatch, DB};
+    use rocksdb::{ColumnFamilyOptions, EnvOptions, SstFileWriter, Writable, WriteBatch, DB};
     use protobuf::Message;
     use kvproto::metapb::RegionEpoch;
     use kvproto::raft_cmdpb::CmdType;
+    use kvproto::import_sstpb::{Pair, RewriteRule, WriteBatch as ImportWriteBatch};
 
     use super::*;
-    use storage::{ALL_CFS, CF_WRITE};
+    use backup::LocalStorage;
+    use storage::{Key, ALL_CFS, CF_WRITE};
     use util::collections::HashMap;
 
     pub fn create_tmp_engine(path: &str) -> (TempDir, Arc<DB>) {
@@ -1622,13 +2574,28 @@ mod tests {
         (path, db)
     }
 
-    fn new_runner(db: Arc<DB>, host: Arc<CoprocessorHost>, tx: Sender<TaskRes>) -> Runner {
+    pub fn create_tmp_importer(path: &str) -> (TempDir, Arc<SSTImporter>) {
+        let path = TempDir::new(path).unwrap();
+        let importer = Arc::new(SSTImporter::new(path.path()).unwrap());
+        (path, importer)
+    }
+
+    fn new_runner(
+        db: Arc<DB>,
+        host: Arc<CoprocessorHost>,
+        importer: Arc<SSTImporter>,
+        tx: Sender<TaskRes>,
+    ) -> Runner {
         Runner {
+            raft_engine: db.clone(),
             db: db,
             host: host,
+            importer: importer,
             delegates: HashMap::default(),
             notifier: tx,
             sync_log: false,
+            pool: None,
+            pool_size: 1,
             tag: "".to_owned(),
         }
     }
@@ -1673,8 +2640,9 @@ mod tests {
     fn test_basic_flow() {
         let (tx, rx) = mpsc::channel();
         let (_tmp, db) = create_tmp_engine("apply-basic");
+        let (_import_dir, importer) = create_tmp_importer("apply-basic-import");
         let host = Arc::new(CoprocessorHost::new());
-        let mut runner = new_runner(db.clone(), host, tx);
+        let mut runner = new_runner(db.clone(), host, importer, tx);
 
         let mut reg = Registration::default();
         reg.id = 1;
@@ -1891,6 +2859,14 @@ mod tests {
             self
         }
 
+        fn ingest_sst(mut self, meta: &SSTMeta) -> EntryBuilder {
+            let mut cmd = Request::new();
+            cmd.set_cmd_type(CmdType::IngestSST);
+            cmd.mut_ingest_sst().set_sst(meta.clone());
+            self.req.mut_requests().push(cmd);
+            self
+        }
+
         fn build(mut self) -> Entry {
             self.entry.set_data(self.req.write_to_bytes().unwrap());
             self.entry
@@ -1900,6 +2876,7 @@ mod tests {
     #[test]
     fn test_handle_raft_committed_entries() {
         let (_path, db) = create_tmp_engine("test-delegate");
+        let (_import_dir, importer) = create_tmp_importer("test-delegate-import");
         let mut reg = Registration::default();
         reg.region.set_end_key(b"k5".to_vec());
         reg.region.mut_region_epoch().set_version(3);
@@ -1914,7 +2891,7 @@ mod tests {
             .capture_resp(&mut delegate, tx.clone())
             .build();
         let host = CoprocessorHost::new();
-        let mut apply_ctx = ApplyContext::new(&host);
+        let mut apply_ctx = ApplyContext::new(&host, &importer, &*db);
         let res = delegate.handle_raft_committed_entries(&mut apply_ctx, vec![put_entry]);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -1941,7 +2918,7 @@ mod tests {
             .put_cf(CF_LOCK, b"k1", b"v1")
             .epoch(1, 3)
             .build();
-        let mut apply_ctx = ApplyContext::new(&host);
+        let mut apply_ctx = ApplyContext::new(&host, &importer, &*db);
         delegate.handle_raft_committed_entries(&mut apply_ctx, vec![put_entry]);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -1964,7 +2941,7 @@ mod tests {
             .epoch(1, 1)
             .capture_resp(&mut delegate, tx.clone())
             .build();
-        let mut apply_ctx = ApplyContext::new(&host);
+        let mut apply_ctx = ApplyContext::new(&host, &importer, &*db);
         delegate.handle_raft_committed_entries(&mut apply_ctx, vec![put_entry]);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -1981,7 +2958,7 @@ mod tests {
             .epoch(1, 3)
             .capture_resp(&mut delegate, tx.clone())
             .build();
-        let mut apply_ctx = ApplyContext::new(&host);
+        let mut apply_ctx = ApplyContext::new(&host, &importer, &*db);
         delegate.handle_raft_committed_entries(&mut apply_ctx, vec![put_entry]);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -2007,7 +2984,7 @@ mod tests {
         let lock_written_bytes = delegate.metrics.lock_cf_written_bytes;
         let delete_keys_hint = delegate.metrics.delete_keys_hint;
         let size_diff_hint = delegate.metrics.size_diff_hint;
-        let mut apply_ctx = ApplyContext::new(&host);
+        let mut apply_ctx = ApplyContext::new(&host, &importer, &*db);
         delegate.handle_raft_committed_entries(&mut apply_ctx, vec![put_entry]);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -2031,7 +3008,7 @@ mod tests {
             .epoch(1, 3)
             .capture_resp(&mut delegate, tx.clone())
             .build();
-        let mut apply_ctx = ApplyContext::new(&host);
+        let mut apply_ctx = ApplyContext::new(&host, &importer, &*db);
         delegate.handle_raft_committed_entries(&mut apply_ctx, vec![delete_entry]);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -2045,7 +3022,7 @@ mod tests {
             .epoch(1, 3)
             .capture_resp(&mut delegate, tx.clone())
             .build();
-        let mut apply_ctx = ApplyContext::new(&host);
+        let mut apply_ctx = ApplyContext::new(&host, &importer, &*db);
         delegate.handle_raft_committed_entries(&mut apply_ctx, vec![delete_range_entry]);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -2062,7 +3039,7 @@ mod tests {
             .epoch(1, 3)
             .capture_resp(&mut delegate, tx.clone())
             .build();
-        let mut apply_ctx = ApplyContext::new(&host);
+        let mut apply_ctx = ApplyContext::new(&host, &importer, &*db);
         delegate.handle_raft_committed_entries(&mut apply_ctx, vec![delete_range_entry]);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -2083,7 +3060,7 @@ mod tests {
                 .build();
             entries.push(put_entry);
         }
-        let mut apply_ctx = ApplyContext::new(&host);
+        let mut apply_ctx = ApplyContext::new(&host, &importer, &*db);
         delegate.handle_raft_committed_entries(&mut apply_ctx, entries);
         db.write(apply_ctx.wb.take().unwrap()).unwrap();
         for (cb, resp) in apply_ctx.cbs.drain(..) {
@@ -2097,4 +3074,288 @@ mod tests {
             WRITE_BATCH_MAX_KEYS as u64 + 8
         );
     }
+
+    #[test]
+    fn test_handle_ingest_sst() {
+        let (_path, db) = create_tmp_engine("test-ingest-sst");
+        let (_import_dir, importer) = create_tmp_importer("test-ingest-sst-import");
+        let mut reg = Registration::default();
+        reg.region.set_end_key(b"k5".to_vec());
+        reg.region.mut_region_epoch().set_version(3);
+        let region = reg.region.clone();
+        let mut delegate = ApplyDelegate::from_registration(db.clone(), reg);
+        let (tx, rx) = mpsc::channel();
+        let host = CoprocessorHost::new();
+
+        // Download a file to the importer.
+        let backup_dir = TempDir::new("test-ingest-sst-backup").unwrap();
+        let storage = LocalStorage::new(backup_dir.path()).unwrap();
+        let key = keys::data_key(Key::from_raw(b"k1").append_ts(5).encoded());
+        let mut writer = SstFileWriter::new(EnvOptions::new(), ColumnFamilyOptions::new());
+        let path = backup_dir.path().join("a.sst");
+        writer.open(path.to_str().unwrap()).unwrap();
+        writer.add(&key, b"v1").unwrap();
+        writer.finish().unwrap();
+        let mut meta = SSTMeta::new();
+        meta.set_uuid(vec![1]);
+        meta.set_cf_name(CF_DEFAULT.to_owned());
+        meta.mut_region_epoch().set_version(3);
+        let range = importer
+            .download(&meta, &region, &storage, "a.sst", &RewriteRule::new())
+            .unwrap()
+            .unwrap();
+        meta.set_range(range);
+
+        // The file is rejected if the region epoch changes.
+        let mut stale_meta = meta.clone();
+        stale_meta.mut_region_epoch().set_version(2);
+        let entry = EntryBuilder::new(1, 1)
+            .ingest_sst(&stale_meta)
+            .epoch(1, 3)
+            .capture_resp(&mut delegate, tx.clone())
+            .build();
+        let mut apply_ctx = ApplyContext::new(&host, &importer, &*db);
+        delegate.handle_raft_committed_entries(&mut apply_ctx, vec![entry]);
+        db.write(apply_ctx.wb.take().unwrap()).unwrap();
+        for (cb, resp) in apply_ctx.cbs.drain(..) {
+            cb(resp);
+        }
+        let resp = rx.try_recv().unwrap();
+        assert!(resp.get_header().get_error().has_stale_epoch(), "{:?}", resp);
+        assert!(importer.exist(&meta));
+
+        let entry = EntryBuilder::new(2, 1)
+            .ingest_sst(&meta)
+            .epoch(1, 3)
+            .capture_resp(&mut delegate, tx.clone())
+            .build();
+        let mut apply_ctx = ApplyContext::new(&host, &importer, &*db);
+        delegate.handle_raft_committed_entries(&mut apply_ctx, vec![entry]);
+        db.write(apply_ctx.wb.take().unwrap()).unwrap();
+        for (cb, resp) in apply_ctx.cbs.drain(..) {
+            cb(resp);
+        }
+        let resp = rx.try_recv().unwrap();
+        assert!(!resp.get_header().has_error(), "{:?}", resp);
+        assert_eq!(db.get(&key).unwrap().unwrap(), b"v1");
+        assert!(!importer.exist(&meta));
+        assert!(delegate.metrics.size_diff_hint > 0);
+
+        // The file is gone once it's ingested, applying the command again fails.
+        let entry = EntryBuilder::new(3, 1)
+            .ingest_sst(&meta)
+            .epoch(1, 3)
+            .capture_resp(&mut delegate, tx.clone())
+            .build();
+        let mut apply_ctx = ApplyContext::new(&host, &importer, &*db);
+        delegate.handle_raft_committed_entries(&mut apply_ctx, vec![entry]);
+        db.write(apply_ctx.wb.take().unwrap()).unwrap();
+        for (cb, resp) in apply_ctx.cbs.drain(..) {
+            cb(resp);
+        }
+        let resp = rx.try_recv().unwrap();
+        assert!(resp.get_header().has_error(), "{:?}", resp);
+        assert_eq!(delegate.apply_state.get_applied_index(), 3);
+
+        // A file with a key out of the region is rejected even if its meta says otherwise.
+        let mut meta = SSTMeta::new();
+        meta.set_uuid(vec![2]);
+        meta.mut_region_epoch().set_version(3);
+        let mut writer = importer.new_txn_writer(&meta).unwrap();
+        let mut batch = ImportWriteBatch::new();
+        batch.set_commit_ts(5);
+        for k in &[b"k4", b"k6"] {
+            let mut pair = Pair::new();
+            pair.set_key(k.to_vec());
+            pair.set_value(b"v".to_vec());
+            batch.mut_pairs().push(pair);
+        }
+        writer.write(&batch).unwrap();
+        let mut metas = writer.finish().unwrap();
+        assert_eq!(metas.len(), 1);
+        let mut meta = metas.pop().unwrap();
+        meta.mut_range().set_end(Key::from_raw(b"k4").encoded().clone());
+        let entry = EntryBuilder::new(4, 1)
+            .ingest_sst(&meta)
+            .epoch(1, 3)
+            .capture_resp(&mut delegate, tx.clone())
+            .build();
+        let mut apply_ctx = ApplyContext::new(&host, &importer, &*db);
+        delegate.handle_raft_committed_entries(&mut apply_ctx, vec![entry]);
+        db.write(apply_ctx.wb.take().unwrap()).unwrap();
+        for (cb, resp) in apply_ctx.cbs.drain(..) {
+            cb(resp);
+        }
+        let resp = rx.try_recv().unwrap();
+        assert!(resp.get_header().get_error().has_key_not_in_region(), "{:?}", resp);
+        assert!(importer.exist(&meta));
+        let key = keys::data_key(Key::from_raw(b"k4").append_ts(5).encoded());
+        assert!(db.get_cf(db.cf_handle(CF_WRITE).unwrap(), &key).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_apply_on_pool() {
+        let (tx, rx) = mpsc::channel();
+        let (_tmp, db) = create_tmp_engine("apply-on-pool");
+        let (_import_dir, importer) = create_tmp_importer("apply-on-pool-import");
+        let host = Arc::new(CoprocessorHost::new());
+        let mut runner = new_runner(db.clone(), host.clone(), importer.clone(), tx);
+        runner.pool = Some(Builder::new().pool_size(2).create());
+        runner.pool_size = 2;
+
+        let bounds = [
+            (&b""[..], &b"k3"[..]),
+            (&b"k3"[..], &b"k6"[..]),
+            (&b"k6"[..], &b""[..]),
+        ];
+        let mut applys = vec![];
+        for (i, &(start, end)) in bounds.iter().enumerate() {
+            let region_id = i as u64 + 1;
+            let mut reg = Registration::default();
+            reg.id = region_id;
+            reg.region.set_id(region_id);
+            reg.region.set_start_key(start.to_vec());
+            reg.region.set_end_key(end.to_vec());
+            reg.region.mut_region_epoch().set_version(3);
+            runner.run(Task::Registration(reg));
+            let key = format!("k{}", i * 3 + 1);
+            let entry = EntryBuilder::new(1, 1)
+                .put(key.as_bytes(), b"v")
+                .epoch(1, 3)
+                .build();
+            applys.push(Apply::new(region_id, 1, vec![entry]));
+        }
+        runner.run(Task::applies(applys));
+        let res = match rx.try_recv() {
+            Ok(TaskRes::Applys(res)) => res,
+            e => panic!("unexpected apply result: {:?}", e),
+        };
+        assert_eq!(res.len(), 3);
+        for region_id in 1..4 {
+            assert_eq!(runner.delegates[&region_id].apply_state.get_applied_index(), 1);
+        }
+        for key in &[&b"k1"[..], &b"k4"[..], &b"k7"[..]] {
+            assert_eq!(db.get(&keys::data_key(key)).unwrap().unwrap(), b"v");
+        }
+
+        // The merge from a region applied by another thread waits for it.
+        let mut reg = Registration::default();
+        reg.region.set_id(1);
+        reg.region.set_end_key(b"k3".to_vec());
+        reg.region.mut_region_epoch().set_version(3);
+        let mut delegate = ApplyDelegate::from_registration(db.clone(), reg);
+        let mut req = RaftCmdRequest::new();
+        req.mut_admin_request()
+            .set_cmd_type(AdminCmdType::CommitMerge);
+        req.mut_admin_request()
+            .mut_commit_merge()
+            .mut_source()
+            .set_id(2);
+        let mut merge = Entry::new();
+        merge.set_index(2);
+        merge.set_term(1);
+        merge.set_data(req.write_to_bytes().unwrap());
+        let entries = vec![
+            EntryBuilder::new(1, 1).put(b"k1", b"v1").epoch(1, 3).build(),
+            merge,
+            EntryBuilder::new(3, 1).put(b"k2", b"v1").epoch(1, 3).build(),
+        ];
+        let mut apply_ctx = ApplyContext::new(&host, &importer, &*db);
+        apply_ctx.busy_regions.insert(2);
+        delegate.handle_raft_committed_entries(&mut apply_ctx, entries);
+        assert_eq!(delegate.apply_state.get_applied_index(), 1);
+        let deferred: Vec<_> = apply_ctx.deferred.drain(..).map(|e| e.get_index()).collect();
+        assert_eq!(deferred, vec![2, 3]);
+        db.write(apply_ctx.wb.take().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_ts() {
+        let (tx, rx) = mpsc::channel();
+        let (_tmp, db) = create_tmp_engine("apply-resolve-ts");
+        let (_import_dir, importer) = create_tmp_importer("apply-resolve-ts-import");
+        let host = Arc::new(CoprocessorHost::new());
+        let mut runner = new_runner(db.clone(), host, importer, tx);
+
+        let mut reg = Registration::default();
+        reg.id = 1;
+        reg.region.set_id(2);
+        reg.region.mut_region_epoch().set_version(3);
+        reg.apply_state.set_applied_index(3);
+        reg.term = 4;
+        reg.applied_index_term = 4;
+        runner.run(Task::Registration(reg));
+
+        // Locks in the engine are loaded the first time.
+        let lock_handle = db.cf_handle(CF_LOCK).unwrap();
+        let lock = Lock::new(LockType::Put, b"k1".to_vec(), 10, 0, None);
+        db.put_cf(lock_handle, &keys::data_key(b"k1"), &lock.to_bytes())
+            .unwrap();
+        runner.run(Task::resolve_ts(30, vec![2, 3]));
+        let expect = ResolvedTs {
+            region_id: 2,
+            resolved_ts: 10,
+            applied_index: 3,
+            term: 4,
+        };
+        match rx.try_recv() {
+            Ok(TaskRes::ResolvedTs(res)) => assert_eq!(res, vec![expect]),
+            e => panic!("unexpected result {:?}", e),
+        }
+
+        // Applied locks are tracked, pessimistic locks are ignored.
+        let pessimistic_lock = Lock::new(LockType::Pessimistic, b"k2".to_vec(), 5, 0, None);
+        let lock = Lock::new(LockType::Put, b"k3".to_vec(), 20, 0, None);
+        let entry = EntryBuilder::new(4, 4)
+            .delete_cf(CF_LOCK, b"k1")
+            .put_cf(CF_LOCK, b"k2", &pessimistic_lock.to_bytes())
+            .put_cf(CF_LOCK, b"k3", &lock.to_bytes())
+            .epoch(1, 3)
+            .build();
+        runner.run(Task::applies(vec![Apply::new(2, 4, vec![entry])]));
+        match rx.try_recv() {
+            Ok(TaskRes::Applys(_)) => {}
+            e => panic!("unexpected result {:?}", e),
+        }
+        runner.run(Task::resolve_ts(30, vec![2]));
+        let expect = ResolvedTs {
+            region_id: 2,
+            resolved_ts: 20,
+            applied_index: 4,
+            term: 4,
+        };
+        match rx.try_recv() {
+            Ok(TaskRes::ResolvedTs(res)) => assert_eq!(res, vec![expect]),
+            e => panic!("unexpected result {:?}", e),
+        }
+
+        // The locks are not reloaded in the same term.
+        let lock = Lock::new(LockType::Put, b"k4".to_vec(), 15, 0, None);
+        db.put_cf(lock_handle, &keys::data_key(b"k4"), &lock.to_bytes())
+            .unwrap();
+        runner.run(Task::resolve_ts(30, vec![2]));
+        match rx.try_recv() {
+            Ok(TaskRes::ResolvedTs(res)) => assert_eq!(res[0].resolved_ts, 20),
+            e => panic!("unexpected result {:?}", e),
+        }
+
+        // They are reloaded once the logs of a new term are applied.
+        let entry = EntryBuilder::new(5, 5).put(b"k5", b"v5").epoch(1, 3).build();
+        runner.run(Task::applies(vec![Apply::new(2, 5, vec![entry])]));
+        match rx.try_recv() {
+            Ok(TaskRes::Applys(_)) => {}
+            e => panic!("unexpected result {:?}", e),
+        }
+        runner.run(Task::resolve_ts(30, vec![2]));
+        let expect = ResolvedTs {
+            region_id: 2,
+            resolved_ts: 15,
+            applied_index: 5,
+            term: 5,
+        };
+        match rx.try_recv() {
+            Ok(TaskRes::ResolvedTs(res)) => assert_eq!(res, vec![expect]),
+            e => panic!("unexpected result {:?}", e),
+        }
+    }
 }
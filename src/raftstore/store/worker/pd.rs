@@ -383,6 +383,19 @@ impl<T: PdClient> Runner<T> {
                     );
                     let req = new_transfer_leader_request(transfer_leader.take_peer());
                     send_admin_request(&ch, region_id, epoch, peer, req, None)
+                } else if resp.has_merge() {
+                    PD_HEARTBEAT_COUNTER_VEC
+                        .with_label_values(&["merge"])
+                        .inc();
+
+                    let mut merge = resp.take_merge();
+                    info!(
+                        "[region {}] try to merge into {:?}",
+                        region_id,
+                        merge.get_target()
+                    );
+                    let req = new_prepare_merge_request(merge.take_target());
+                    send_admin_request(&ch, region_id, epoch, peer, req, None)
                 }
             })
             .map_err(|e| panic!("unexpected error: {:?}", e))
@@ -478,6 +491,13 @@ fn new_transfer_leader_request(peer: metapb::Peer) -> AdminRequest {
     req
 }
 
+fn new_prepare_merge_request(target: metapb::Region) -> AdminRequest {
+    let mut req = AdminRequest::new();
+    req.set_cmd_type(AdminCmdType::PrepareMerge);
+    req.mut_prepare_merge().set_target(target);
+    req
+}
+
 fn send_admin_request(
     ch: &SendCh<Msg>,
     region_id: u64,
@@ -11,18 +11,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use raftstore::store::keys;
-use raftstore::store::engine::Iterable;
-use util::worker::Runnable;
+use raft_engine::{LogBatch, RaftEngine};
+use util::worker::BatchRunnable;
 
-use rocksdb::{Writable, WriteBatch, DB};
 use std::sync::Arc;
 use std::fmt::{self, Display, Formatter};
 use std::error;
 use std::sync::mpsc::Sender;
 
 pub struct Task {
-    pub raft_engine: Arc<DB>,
+    pub raft_engine: Arc<RaftEngine>,
     pub region_id: u64,
     pub start_idx: u64,
     pub end_idx: u64,
@@ -68,30 +66,25 @@ impl Runner {
     /// Do the gc job and return the count of log collected.
     fn gc_raft_log(
         &mut self,
-        raft_engine: Arc<DB>,
+        raft_engine: &RaftEngine,
         region_id: u64,
         start_idx: u64,
         end_idx: u64,
     ) -> Result<u64, Error> {
         let mut first_idx = start_idx;
         if first_idx == 0 {
-            let start_key = keys::raft_log_key(region_id, 0);
-            first_idx = end_idx;
-            if let Some((k, _)) = box_try!(raft_engine.seek(&start_key)) {
-                first_idx = box_try!(keys::raft_log_index(&k));
-            }
+            first_idx = match box_try!(raft_engine.entry_range(region_id)) {
+                Some((first, _)) => first,
+                None => end_idx,
+            };
         }
         if first_idx >= end_idx {
             info!("[region {}] no need to gc", region_id);
             return Ok(0);
         }
-        let raft_wb = WriteBatch::new();
-        for idx in first_idx..end_idx {
-            let key = keys::raft_log_key(region_id, idx);
-            box_try!(raft_wb.delete(&key));
-        }
-        // TODO: disable WAL here.
-        raft_engine.write(raft_wb).unwrap();
+        let mut raft_wb = LogBatch::new();
+        raft_wb.compact_to(region_id, first_idx, end_idx);
+        box_try!(raft_engine.consume(&mut raft_wb, false));
         Ok(end_idx - first_idx)
     }
 
@@ -109,26 +102,38 @@ impl Runner {
     }
 }
 
-impl Runnable<Task> for Runner {
-    fn run(&mut self, task: Task) {
-        debug!(
-            "[region {}] execute gc log to {}",
-            task.region_id,
-            task.end_idx
-        );
-        match self.gc_raft_log(
-            task.raft_engine,
-            task.region_id,
-            task.start_idx,
-            task.end_idx,
-        ) {
-            Err(e) => {
-                error!("[region {}] failed to gc: {:?}", task.region_id, e);
-                self.report_collected(0);
+impl BatchRunnable<Task> for Runner {
+    fn run_batch(&mut self, tasks: &mut Vec<Task>) {
+        let mut raft_engine = None;
+        for task in tasks.drain(..) {
+            debug!(
+                "[region {}] execute gc log to {}",
+                task.region_id,
+                task.end_idx
+            );
+            match self.gc_raft_log(
+                &*task.raft_engine,
+                task.region_id,
+                task.start_idx,
+                task.end_idx,
+            ) {
+                Err(e) => {
+                    error!("[region {}] failed to gc: {:?}", task.region_id, e);
+                    self.report_collected(0);
+                }
+                Ok(n) => {
+                    debug!("[region {}] collected {} log entries", task.region_id, n);
+                    self.report_collected(n);
+                }
             }
-            Ok(n) => {
-                debug!("[region {}] collected {} log entries", task.region_id, n);
-                self.report_collected(n);
+            raft_engine = Some(task.raft_engine);
+        }
+
+        // The files of the raft log engine can be deleted once the logs in them are compacted
+        // by all the regions.
+        if let Some(raft_engine) = raft_engine {
+            if let Err(e) = raft_engine.purge_expired_files() {
+                error!("failed to purge raft log files: {:?}", e);
             }
         }
     }
@@ -138,6 +143,10 @@ impl Runnable<Task> for Runner {
 mod test {
     use std::sync::mpsc;
     use std::time::Duration;
+    use kvproto::eraftpb::Entry;
+    use rocksdb::{Writable, WriteBatch, DB};
+    use raft_engine::{Config as RaftLogConfig, RaftLogEngine};
+    use raftstore::store::keys;
     use util::rocksdb::new_engine;
     use tempdir::TempDir;
     use storage::CF_DEFAULT;
@@ -209,7 +218,7 @@ mod test {
         ];
 
         for (task, expected_collectd, not_exist_range, exist_range) in tbls {
-            runner.run(task);
+            runner.run_batch(&mut vec![task]);
             let res = rx.recv_timeout(Duration::from_secs(3)).unwrap();
             assert_eq!(res.collected, expected_collectd);
             raft_log_must_not_exist(&raft_db, 1, not_exist_range.0, not_exist_range.1);
@@ -217,6 +226,42 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_gc_raft_log_engine() {
+        let path = TempDir::new("gc-raft-log-engine-test").unwrap();
+        let cfg = RaftLogConfig {
+            dir: path.path().to_str().unwrap().to_owned(),
+            ..RaftLogConfig::default()
+        };
+        let raft_engine = Arc::new(RaftLogEngine::open(&cfg).unwrap());
+
+        let (tx, rx) = mpsc::channel();
+        let mut runner = Runner::new(Some(tx));
+
+        let mut raft_wb = LogBatch::new();
+        let entries = (1..100)
+            .map(|i| {
+                let mut e = Entry::new();
+                e.set_index(i);
+                e
+            })
+            .collect();
+        raft_wb.add_entries(1, 0, entries);
+        raft_engine.consume(&mut raft_wb, true).unwrap();
+
+        let task = Task {
+            raft_engine: raft_engine.clone(),
+            region_id: 1,
+            start_idx: 0,
+            end_idx: 50,
+        };
+        runner.run_batch(&mut vec![task]);
+        let res = rx.recv_timeout(Duration::from_secs(3)).unwrap();
+        assert_eq!(res.collected, 49);
+        assert_eq!(raft_engine.entry_range(1).unwrap(), Some((50, 99)));
+        assert_eq!(raft_engine.get_entry(1, 49).unwrap(), None);
+    }
+
     fn raft_log_must_not_exist(raft_engine: &DB, region_id: u64, start_idx: u64, end_idx: u64) {
         for i in start_idx..end_idx {
             let k = keys::raft_log_key(region_id, i);
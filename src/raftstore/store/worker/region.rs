@@ -22,6 +22,7 @@ use rocksdb::{Writable, WriteBatch, DB};
 use kvproto::raft_serverpb::{PeerState, RaftApplyState, RegionLocalState};
 use kvproto::eraftpb::Snapshot as RaftSnapshot;
 
+use raft_engine::RaftEngine;
 use util::threadpool::{DefaultContext, ThreadPool, ThreadPoolBuilder};
 use util::worker::Runnable;
 use util::{escape, rocksdb};
@@ -92,7 +93,7 @@ impl Display for Task {
 #[derive(Clone)]
 struct SnapContext {
     kv_db: Arc<DB>,
-    raft_db: Arc<DB>,
+    raft_engine: Arc<RaftEngine>,
     batch_size: usize,
     mgr: SnapManager,
 }
@@ -100,12 +101,11 @@ struct SnapContext {
 impl SnapContext {
     fn generate_snap(&self, region_id: u64, notifier: SyncSender<RaftSnapshot>) -> Result<()> {
         // do we need to check leader here?
-        let raft_db = self.raft_db.clone();
         let raw_snap = Snapshot::new(self.kv_db.clone());
 
         let snap = box_try!(store::do_snapshot(
             self.mgr.clone(),
-            &raft_db,
+            &*self.raft_engine,
             &raw_snap,
             region_id
         ));
@@ -267,14 +267,19 @@ pub struct Runner {
 }
 
 impl Runner {
-    pub fn new(kv_db: Arc<DB>, raft_db: Arc<DB>, mgr: SnapManager, batch_size: usize) -> Runner {
+    pub fn new(
+        kv_db: Arc<DB>,
+        raft_engine: Arc<RaftEngine>,
+        mgr: SnapManager,
+        batch_size: usize,
+    ) -> Runner {
         Runner {
             pool: ThreadPoolBuilder::with_default_factory(thd_name!("snap generator"))
                 .thread_count(GENERATE_POOL_SIZE)
                 .build(),
             ctx: SnapContext {
                 kv_db: kv_db,
-                raft_db: raft_db,
+                raft_engine: raft_engine,
                 mgr: mgr,
                 batch_size: batch_size,
             },
@@ -16,7 +16,7 @@ use std::ascii::AsciiExt;
 use sys_info;
 
 use util::collections::HashMap;
-use util::config::{self, ReadableSize};
+use util::config::{self, ReadableDuration, ReadableSize};
 
 use super::Result;
 
@@ -32,6 +32,7 @@ const DEFAULT_GRPC_CONCURRENT_STREAM: usize = 1024;
 const DEFAULT_GRPC_RAFT_CONN_NUM: usize = 10;
 const DEFAULT_GRPC_STREAM_INITIAL_WINDOW_SIZE: u64 = 2 * 1024 * 1024;
 const DEFAULT_MESSAGES_PER_TICK: usize = 4096;
+const DEFAULT_IMPORT_MODE_TIMEOUT_SECS: u64 = 10 * 60;
 
 // Assume a request can be finished in 1ms, a request at position x will wait about
 // 0.001 * x secs to be actual started. A server-is-busy error will trigger 2 seconds
@@ -60,6 +61,8 @@ pub struct Config {
     pub grpc_stream_initial_window_size: ReadableSize,
     pub end_point_concurrency: usize,
     pub end_point_max_tasks: usize,
+    // The storage is switched back to normal mode if import mode isn't renewed in time.
+    pub import_mode_timeout: ReadableDuration,
     // Server labels to specify some attributes about this server.
     #[serde(with = "config::order_map_serde")]
     pub labels: HashMap<String, String>,
@@ -86,6 +89,7 @@ impl Default for Config {
             grpc_stream_initial_window_size: ReadableSize(DEFAULT_GRPC_STREAM_INITIAL_WINDOW_SIZE),
             end_point_concurrency: concurrency,
             end_point_max_tasks: DEFAULT_MAX_RUNNING_TASK_COUNT,
+            import_mode_timeout: ReadableDuration::secs(DEFAULT_IMPORT_MODE_TIMEOUT_SECS),
         }
     }
 }
@@ -114,6 +118,10 @@ impl Config {
             return Err(box_err!("server.end-point-max-tasks should not be 0."));
         }
 
+        if self.import_mode_timeout.as_secs() == 0 {
+            return Err(box_err!("server.import-mode-timeout should not be 0."));
+        }
+
         for (k, v) in &self.labels {
             try!(validate_label(k, "key"));
             try!(validate_label(v, "value"));
@@ -173,6 +181,10 @@ mod tests {
         invalid_cfg.end_point_max_tasks = 0;
         assert!(invalid_cfg.validate().is_err());
 
+        let mut invalid_cfg = cfg.clone();
+        invalid_cfg.import_mode_timeout = ReadableDuration::secs(0);
+        assert!(invalid_cfg.validate().is_err());
+
         invalid_cfg = Config::default();
         invalid_cfg.addr = "0.0.0.0:1000".to_owned();
         assert!(invalid_cfg.validate().is_err());
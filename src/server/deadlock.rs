@@ -0,0 +1,519 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The deadlock detector of the cluster.
+//!
+//! A deadlock may span the regions on different stores, so the wait-for edges of all the stores
+//! are collected by a single detector, the one on the store holding the leader of the first
+//! region. The other stores forward their edges to it through a `Deadlock` stream, and the
+//! deadlocks it finds are sent back on the same stream. Every store keeps its own edges to send
+//! them again to a new leader, and the leader drops the edges kept longer than any lock wait in
+//! case their clean ups are lost.
+
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use futures::{Future, Sink, Stream};
+use futures::sync::mpsc::{self, UnboundedSender};
+use futures::sync::oneshot;
+use futures_cpupool::{Builder, CpuPool};
+use grpc::{ChannelBuilder, DuplexSink, EnvBuilder, Environment, Error as GrpcError, RequestStream,
+           RpcContext, RpcStatus, RpcStatusCode, UnarySink, WriteFlags};
+use kvproto::deadlock::{DeadlockRequest, DeadlockRequestType, DeadlockResponse,
+                        WaitForEntriesRequest, WaitForEntriesResponse, WaitForEntry};
+use kvproto::deadlock_grpc::{self, DeadlockClient};
+use protobuf::RepeatedField;
+use raft::StateRole;
+use tokio_core::reactor::Handle;
+
+use pd::PdClient;
+use raftstore::coprocessor::{Coprocessor, ObserverContext, RegionObserver};
+use storage::Msg as SchedMsg;
+use storage::txn::DetectTable;
+use util::transport::SyncSendCh;
+use util::worker::{FutureRunnable, FutureScheduler};
+use super::Result;
+
+// A waiter waits no longer than `wait-for-lock-timeout`, so an edge kept longer than this
+// lost its clean up.
+const WAIT_FOR_ENTRY_TTL_SECS: u64 = 60;
+const RESOLVE_LEADER_BACKOFF_MILLIS: u64 = 1000;
+
+pub enum Task {
+    /// A request of the local scheduler.
+    Detect {
+        tp: DeadlockRequestType,
+        txn_ts: u64,
+        lock_ts: u64,
+        key_hash: u64,
+    },
+    /// A request forwarded by another store, the deadlock found is replied with `sink`.
+    DetectRpc {
+        req: DeadlockRequest,
+        sink: UnboundedSender<DeadlockResponse>,
+    },
+    GetWaitForEntries {
+        cb: oneshot::Sender<Vec<WaitForEntry>>,
+    },
+    /// The role of the peer of the first region on this store is changed.
+    ChangeRole { is_leader: bool },
+    /// The address of the leader is resolved, `None` if it failed.
+    LeaderResolved { address: Option<String> },
+    /// The stream to the leader is closed.
+    LeaderClientClosed { id: u64 },
+}
+
+impl Display for Task {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Task::Detect {
+                tp,
+                txn_ts,
+                lock_ts,
+                key_hash,
+            } => write!(
+                f,
+                "{:?} txn {} waiting for txn {} on key {}",
+                tp,
+                txn_ts,
+                lock_ts,
+                key_hash
+            ),
+            Task::DetectRpc { ref req, .. } => write!(f, "detect rpc {:?}", req),
+            Task::GetWaitForEntries { .. } => write!(f, "get wait for entries"),
+            Task::ChangeRole { is_leader } => write!(f, "change role, leader: {}", is_leader),
+            Task::LeaderResolved { ref address } => write!(f, "leader resolved: {:?}", address),
+            Task::LeaderClientClosed { id } => write!(f, "leader client {} closed", id),
+        }
+    }
+}
+
+// Asks the scheduler to fail the waiter of `txn_ts` with a deadlock.
+fn report_deadlock(sched_ch: &SyncSendCh<SchedMsg>, entry: &WaitForEntry, deadlock_key_hash: u64) {
+    let msg = SchedMsg::Deadlock {
+        start_ts: entry.get_txn(),
+        lock_ts: entry.get_wait_for_txn(),
+        key_hash: entry.get_key_hash(),
+        deadlock_key_hash: deadlock_key_hash,
+    };
+    if let Err(e) = sched_ch.send(msg) {
+        error!("failed to report deadlock to the scheduler: {:?}", e);
+    }
+}
+
+// The stream of the requests to the detector on the leader.
+struct LeaderClient {
+    id: u64,
+    sender: UnboundedSender<DeadlockRequest>,
+    _client: DeadlockClient,
+}
+
+// Resolves the address of the store holding the leader of the first region.
+fn resolve_leader_addr<C: PdClient>(pd_client: &C) -> Result<String> {
+    let leader = match try!(pd_client.get_region_leader(b"")) {
+        Some(leader) => leader,
+        None => return Err(box_err!("the leader of the first region is unknown")),
+    };
+    let mut store = try!(pd_client.get_store(leader.get_store_id()));
+    Ok(store.take_address())
+}
+
+pub struct Detector<C: PdClient + 'static> {
+    pd_client: Arc<C>,
+    env: Arc<Environment>,
+    sched_ch: SyncSendCh<SchedMsg>,
+    scheduler: FutureScheduler<Task>,
+    // Shared with the service, which closes the streams of other stores once it steps down.
+    is_leader: Arc<AtomicBool>,
+    // The edges of the waiters on this store, sent again to every new leader.
+    local: DetectTable,
+    // Only used by the leader.
+    table: DetectTable,
+    leader_client: Option<LeaderClient>,
+    next_client_id: u64,
+    // Asking pd for the leader blocks, so it's done on the pool.
+    resolver: CpuPool,
+    resolving: bool,
+}
+
+impl<C: PdClient + 'static> Detector<C> {
+    pub fn new(
+        pd_client: Arc<C>,
+        sched_ch: SyncSendCh<SchedMsg>,
+        scheduler: FutureScheduler<Task>,
+        is_leader: Arc<AtomicBool>,
+    ) -> Detector<C> {
+        let env = EnvBuilder::new()
+            .cq_count(1)
+            .name_prefix(thd_name!("deadlock-detector"))
+            .build();
+        let resolver = Builder::new()
+            .name_prefix(thd_name!("deadlock-resolver"))
+            .pool_size(1)
+            .create();
+        Detector {
+            pd_client: pd_client,
+            env: Arc::new(env),
+            sched_ch: sched_ch,
+            scheduler: scheduler,
+            is_leader: is_leader,
+            local: DetectTable::new(),
+            table: DetectTable::with_ttl(Duration::from_secs(WAIT_FOR_ENTRY_TTL_SECS)),
+            leader_client: None,
+            next_client_id: 0,
+            resolver: resolver,
+            resolving: false,
+        }
+    }
+
+    fn is_leader(&self) -> bool {
+        self.is_leader.load(Ordering::SeqCst)
+    }
+
+    // Applies the request to the wait-for graph, returns the key hash of the deadlock if any.
+    fn detect_locally(&mut self, tp: DeadlockRequestType, entry: &WaitForEntry) -> Option<u64> {
+        let (txn_ts, lock_ts, key_hash) =
+            (entry.get_txn(), entry.get_wait_for_txn(), entry.get_key_hash());
+        match tp {
+            DeadlockRequestType::Detect => self.table.detect(txn_ts, lock_ts, key_hash),
+            DeadlockRequestType::CleanUpWaitFor => {
+                self.table.clean_up_wait_for(txn_ts, lock_ts, key_hash);
+                None
+            }
+            DeadlockRequestType::CleanUp => {
+                self.table.clean_up(txn_ts);
+                None
+            }
+        }
+    }
+
+    fn handle_detect(&mut self, tp: DeadlockRequestType, entry: WaitForEntry, handle: &Handle) {
+        let (txn_ts, lock_ts, key_hash) =
+            (entry.get_txn(), entry.get_wait_for_txn(), entry.get_key_hash());
+        match tp {
+            DeadlockRequestType::Detect => self.local.register(txn_ts, lock_ts, key_hash),
+            DeadlockRequestType::CleanUpWaitFor => {
+                self.local.clean_up_wait_for(txn_ts, lock_ts, key_hash);
+            }
+            DeadlockRequestType::CleanUp => self.local.clean_up(txn_ts),
+        }
+        if self.is_leader() {
+            if let Some(deadlock_key_hash) = self.detect_locally(tp, &entry) {
+                report_deadlock(&self.sched_ch, &entry, deadlock_key_hash);
+            }
+            return;
+        }
+        // Without a leader, the edge is sent along with the others once it's connected.
+        if !self.send_to_leader(tp, entry) {
+            self.resolve_leader(handle, false);
+        }
+    }
+
+    // Returns false if there is no stream to the leader.
+    fn send_to_leader(&mut self, tp: DeadlockRequestType, entry: WaitForEntry) -> bool {
+        let mut req = DeadlockRequest::new();
+        req.set_tp(tp);
+        req.set_entry(entry);
+        let sent = match self.leader_client {
+            Some(ref client) => client.sender.unbounded_send(req).is_ok(),
+            None => false,
+        };
+        if !sent {
+            self.leader_client = None;
+        }
+        sent
+    }
+
+    // Asks pd for the leader on the pool, the result comes back as `Task::LeaderResolved`.
+    fn resolve_leader(&mut self, handle: &Handle, backoff: bool) {
+        if self.resolving {
+            return;
+        }
+        self.resolving = true;
+        let pd_client = self.pd_client.clone();
+        let scheduler = self.scheduler.clone();
+        let resolved = self.resolver
+            .spawn_fn(move || {
+                if backoff {
+                    thread::sleep(Duration::from_millis(RESOLVE_LEADER_BACKOFF_MILLIS));
+                }
+                resolve_leader_addr(pd_client.as_ref())
+            })
+            .then(move |res| {
+                let address = match res {
+                    Ok(address) => Some(address),
+                    Err(e) => {
+                        warn!("failed to resolve the deadlock detector leader: {:?}", e);
+                        None
+                    }
+                };
+                if let Err(e) = scheduler.schedule(Task::LeaderResolved { address: address }) {
+                    error!("failed to schedule deadlock detector task: {:?}", e);
+                }
+                Ok(())
+            });
+        handle.spawn(resolved);
+    }
+
+    fn handle_leader_resolved(&mut self, address: Option<String>, handle: &Handle) {
+        self.resolving = false;
+        if self.is_leader() || self.leader_client.is_some() {
+            return;
+        }
+        match address {
+            Some(address) => self.connect_leader(&address, handle),
+            None => {
+                // Otherwise the next request resolves the leader again.
+                if !self.local.is_empty() {
+                    self.resolve_leader(handle, true);
+                }
+                return;
+            }
+        }
+        // The new leader knows nothing of the waiters so far.
+        for entry in self.local.wait_for_entries() {
+            if !self.send_to_leader(DeadlockRequestType::Detect, entry) {
+                break;
+            }
+        }
+    }
+
+    fn connect_leader(&mut self, address: &str, handle: &Handle) {
+        info!("connect the deadlock detector leader at {}", address);
+        let channel = ChannelBuilder::new(self.env.clone()).connect(address);
+        let client = DeadlockClient::new(channel);
+        let (sink, receiver) = client.detect();
+        let (tx, rx) = mpsc::unbounded();
+        let id = self.next_client_id;
+        self.next_client_id += 1;
+
+        let requests = rx.map(|req| (req, WriteFlags::default()))
+            .map_err(|()| GrpcError::RemoteStopped);
+        handle.spawn(sink.send_all(requests).map(|_| ()).map_err(move |e| {
+            warn!("failed to send to the deadlock detector leader: {:?}", e);
+        }));
+        let sched_ch = self.sched_ch.clone();
+        let scheduler = self.scheduler.clone();
+        handle.spawn(
+            receiver
+                .for_each(move |resp| {
+                    report_deadlock(&sched_ch, resp.get_entry(), resp.get_deadlock_key_hash());
+                    Ok(())
+                })
+                .then(move |res| {
+                    if let Err(e) = res {
+                        warn!("deadlock detector leader stream is closed: {:?}", e);
+                    }
+                    if let Err(e) = scheduler.schedule(Task::LeaderClientClosed { id: id }) {
+                        error!("failed to schedule deadlock detector task: {:?}", e);
+                    }
+                    Ok(())
+                }),
+        );
+        self.leader_client = Some(LeaderClient {
+            id: id,
+            sender: tx,
+            _client: client,
+        });
+    }
+
+    fn handle_leader_client_closed(&mut self, id: u64, handle: &Handle) {
+        if !self.leader_client.as_ref().map_or(false, |c| c.id == id) {
+            return;
+        }
+        self.leader_client = None;
+        // The leader may have changed, the waiting edges are sent to the new one right away.
+        if !self.is_leader() && !self.local.is_empty() {
+            self.resolve_leader(handle, true);
+        }
+    }
+
+    fn handle_detect_rpc(&mut self, req: DeadlockRequest, sink: UnboundedSender<DeadlockResponse>) {
+        // The stream is closed by the service if this store isn't the leader any more.
+        if !self.is_leader() {
+            return;
+        }
+        let entry = req.get_entry();
+        if let Some(deadlock_key_hash) = self.detect_locally(req.get_tp(), entry) {
+            let mut resp = DeadlockResponse::new();
+            resp.set_entry(entry.clone());
+            resp.set_deadlock_key_hash(deadlock_key_hash);
+            if let Err(e) = sink.unbounded_send(resp) {
+                warn!("failed to reply deadlock: {:?}", e);
+            }
+        }
+    }
+
+    fn change_role(&mut self, is_leader: bool, handle: &Handle) {
+        if is_leader == self.is_leader() {
+            return;
+        }
+        if is_leader {
+            info!("deadlock detector becomes the leader");
+        } else {
+            info!("deadlock detector steps down");
+        }
+        self.is_leader.store(is_leader, Ordering::SeqCst);
+        self.table = DetectTable::with_ttl(Duration::from_secs(WAIT_FOR_ENTRY_TTL_SECS));
+        self.leader_client = None;
+        if !is_leader {
+            if !self.local.is_empty() {
+                self.resolve_leader(handle, false);
+            }
+            return;
+        }
+        // The other stores send their edges again once their streams to the old leader are
+        // closed.
+        for entry in self.local.wait_for_entries() {
+            let tp = DeadlockRequestType::Detect;
+            if let Some(deadlock_key_hash) = self.detect_locally(tp, &entry) {
+                report_deadlock(&self.sched_ch, &entry, deadlock_key_hash);
+            }
+        }
+    }
+}
+
+impl<C: PdClient + 'static> FutureRunnable<Task> for Detector<C> {
+    fn run(&mut self, task: Task, handle: &Handle) {
+        match task {
+            Task::Detect {
+                tp,
+                txn_ts,
+                lock_ts,
+                key_hash,
+            } => {
+                let mut entry = WaitForEntry::new();
+                entry.set_txn(txn_ts);
+                entry.set_wait_for_txn(lock_ts);
+                entry.set_key_hash(key_hash);
+                self.handle_detect(tp, entry, handle);
+            }
+            Task::DetectRpc { req, sink } => self.handle_detect_rpc(req, sink),
+            Task::GetWaitForEntries { cb } => {
+                let _ = cb.send(self.table.wait_for_entries());
+            }
+            Task::ChangeRole { is_leader } => self.change_role(is_leader, handle),
+            Task::LeaderResolved { address } => self.handle_leader_resolved(address, handle),
+            Task::LeaderClientClosed { id } => self.handle_leader_client_closed(id, handle),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Service {
+    scheduler: FutureScheduler<Task>,
+    is_leader: Arc<AtomicBool>,
+}
+
+impl Service {
+    pub fn new(scheduler: FutureScheduler<Task>, is_leader: Arc<AtomicBool>) -> Service {
+        Service {
+            scheduler: scheduler,
+            is_leader: is_leader,
+        }
+    }
+}
+
+impl deadlock_grpc::Deadlock for Service {
+    fn get_wait_for_entries(
+        &self,
+        ctx: RpcContext,
+        _: WaitForEntriesRequest,
+        sink: UnarySink<WaitForEntriesResponse>,
+    ) {
+        let (tx, rx) = oneshot::channel();
+        if let Err(e) = self.scheduler.schedule(Task::GetWaitForEntries { cb: tx }) {
+            let status = RpcStatus::new(RpcStatusCode::Unavailable, Some(format!("{}", e)));
+            ctx.spawn(sink.fail(status).map_err(|e| {
+                error!("failed to fail get wait for entries: {:?}", e);
+            }));
+            return;
+        }
+        let future = rx.then(move |res| match res {
+            Ok(entries) => {
+                let mut resp = WaitForEntriesResponse::new();
+                resp.set_entries(RepeatedField::from_vec(entries));
+                sink.success(resp)
+            }
+            Err(e) => {
+                let status = RpcStatus::new(RpcStatusCode::Unavailable, Some(format!("{}", e)));
+                sink.fail(status)
+            }
+        });
+        ctx.spawn(future.map_err(|e| {
+            warn!("failed to reply wait for entries: {:?}", e);
+        }));
+    }
+
+    fn detect(
+        &self,
+        ctx: RpcContext,
+        stream: RequestStream<DeadlockRequest>,
+        sink: DuplexSink<DeadlockResponse>,
+    ) {
+        let (tx, rx) = mpsc::unbounded();
+        let scheduler = self.scheduler.clone();
+        let is_leader = self.is_leader.clone();
+        // Once the requests end, `tx` is dropped and so is the stream of the responses.
+        let requests = stream.map_err(|e| format!("{:?}", e)).for_each(move |req| {
+            if !is_leader.load(Ordering::SeqCst) {
+                return Err("not the leader of deadlock detectors".to_owned());
+            }
+            let task = Task::DetectRpc {
+                req: req,
+                sink: tx.clone(),
+            };
+            scheduler.schedule(task).map_err(|e| format!("{}", e))
+        });
+        ctx.spawn(requests.map_err(|e| {
+            warn!("deadlock detect stream is closed: {}", e);
+        }));
+        let responses = rx.map(|resp| (resp, WriteFlags::default()))
+            .map_err(|()| GrpcError::RemoteStopped);
+        ctx.spawn(sink.send_all(responses).map(|_| ()).map_err(|e| {
+            warn!("failed to send deadlock responses: {:?}", e);
+        }));
+    }
+}
+
+/// Tells the detector whether this store holds the leader of the first region.
+pub struct DeadlockObserver {
+    scheduler: FutureScheduler<Task>,
+}
+
+impl DeadlockObserver {
+    pub fn new(scheduler: FutureScheduler<Task>) -> DeadlockObserver {
+        DeadlockObserver {
+            scheduler: scheduler,
+        }
+    }
+}
+
+impl Coprocessor for DeadlockObserver {}
+
+impl RegionObserver for DeadlockObserver {
+    fn on_role_change(&self, ctx: &mut ObserverContext, role: StateRole) {
+        if !ctx.region().get_start_key().is_empty() {
+            return;
+        }
+        let task = Task::ChangeRole {
+            is_leader: role == StateRole::Leader,
+        };
+        if let Err(e) = self.scheduler.schedule(task) {
+            error!("failed to schedule deadlock detector task: {:?}", e);
+        }
+    }
+}
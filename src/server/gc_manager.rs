@@ -0,0 +1,561 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Automatic GC driven by the GC safe point in PD.
+//!
+//! Clients advance the safe point in PD, and the manager polls it. Every time it advances, the
+//! manager sends `Command::Gc` for the regions led by this store, one region at a time. The
+//! leaders are tracked by `GcObserver`, and the epoch of a region is read from its local state
+//! before collecting it. The regions failing to be collected are retried in the same round, and
+//! the round isn't finished until all of them are done. Regions with nothing to collect are
+//! skipped by their `MvccProperties` in the command.
+
+use std::io;
+use std::sync::{Arc, RwLock};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::thread::{Builder, JoinHandle};
+use std::time::Duration;
+
+use futures::Future;
+use kvproto::kvrpcpb::Context;
+use kvproto::metapb;
+use kvproto::raft_serverpb::{PeerState, RegionLocalState};
+use raft::StateRole;
+use rocksdb::DB;
+
+use pd::PdClient;
+use raftstore::coprocessor::{Coprocessor, ObserverContext, RegionObserver};
+use raftstore::store::keys;
+use raftstore::store::engine::Peekable;
+use raftstore::store::util::find_peer;
+use storage::{self, engine, mvcc, txn, Config as StorageConfig, Storage, CF_RAFT};
+use util::HandyRwLock;
+use util::collections::HashSet;
+use util::rocksdb::gc_filter::GcContext;
+use super::metrics::*;
+
+// The times a region is tried to be collected in a round.
+const GC_REGION_MAX_ATTEMPTS: usize = 3;
+
+/// Tracks the regions led by this store, which are collected by the `GcManager`.
+pub struct GcObserver {
+    leader_regions: Arc<RwLock<HashSet<u64>>>,
+}
+
+impl GcObserver {
+    pub fn new(leader_regions: Arc<RwLock<HashSet<u64>>>) -> GcObserver {
+        GcObserver {
+            leader_regions: leader_regions,
+        }
+    }
+}
+
+impl Coprocessor for GcObserver {}
+
+impl RegionObserver for GcObserver {
+    fn on_role_change(&self, ctx: &mut ObserverContext, role: StateRole) {
+        let region_id = ctx.region().get_id();
+        if role == StateRole::Leader {
+            self.leader_regions.wl().insert(region_id);
+        } else {
+            self.leader_regions.wl().remove(&region_id);
+        }
+    }
+}
+
+pub struct GcManager<C: PdClient + 'static> {
+    store_id: u64,
+    storage: Storage,
+    engine: Arc<DB>,
+    pd_client: Arc<C>,
+    leader_regions: Arc<RwLock<HashSet<u64>>>,
+    poll_interval: Duration,
+    max_regions_per_sec: u64,
+    gc_context: Option<GcContext>,
+    handle: Option<JoinHandle<()>>,
+    sender: Option<Sender<()>>,
+}
+
+impl<C: PdClient + 'static> GcManager<C> {
+    pub fn new(
+        cfg: &StorageConfig,
+        store_id: u64,
+        storage: Storage,
+        engine: Arc<DB>,
+        pd_client: Arc<C>,
+        leader_regions: Arc<RwLock<HashSet<u64>>>,
+    ) -> GcManager<C> {
+        GcManager {
+            store_id: store_id,
+            storage: storage,
+            engine: engine,
+            pd_client: pd_client,
+            leader_regions: leader_regions,
+            poll_interval: cfg.gc_poll_safe_point_interval.0,
+            max_regions_per_sec: cfg.gc_max_regions_per_sec,
+            gc_context: None,
+            handle: None,
+            sender: None,
+        }
+    }
+
+    /// Passes the safe points to the compaction filters of the write cf too, it must be set
+    /// before the manager starts.
+    pub fn set_gc_context(&mut self, ctx: GcContext) {
+        self.gc_context = Some(ctx);
+    }
+
+    pub fn start(&mut self) -> Result<(), io::Error> {
+        let (tx, rx) = mpsc::channel();
+        let mut runner = Runner {
+            store_id: self.store_id,
+            storage: self.storage.clone(),
+            engine: self.engine.clone(),
+            pd_client: self.pd_client.clone(),
+            leader_regions: self.leader_regions.clone(),
+            region_interval: if self.max_regions_per_sec == 0 {
+                None
+            } else {
+                Some(Duration::from_millis(1000 / self.max_regions_per_sec))
+            },
+            gc_context: self.gc_context.clone(),
+            safe_point: 0,
+            rx: rx,
+        };
+        let poll_interval = self.poll_interval;
+        self.sender = Some(tx);
+        let h = try!(
+            Builder::new()
+                .name(thd_name!("gc-manager"))
+                .spawn(move || loop {
+                    match runner.rx.recv_timeout(poll_interval) {
+                        Err(RecvTimeoutError::Timeout) => {}
+                        _ => return,
+                    }
+                    if runner.poll_and_gc().is_err() {
+                        return;
+                    }
+                })
+        );
+        self.handle = Some(h);
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        let h = self.handle.take();
+        if h.is_none() {
+            return;
+        }
+        drop(self.sender.take().unwrap());
+        if let Err(e) = h.unwrap().join() {
+            error!("join gc manager failed {:?}", e);
+        }
+    }
+}
+
+/// The manager is stopped during a GC round.
+struct Stopped;
+
+struct Runner<C: PdClient + 'static> {
+    store_id: u64,
+    storage: Storage,
+    engine: Arc<DB>,
+    pd_client: Arc<C>,
+    leader_regions: Arc<RwLock<HashSet<u64>>>,
+    // The min interval between two collected regions, `None` means no limit.
+    region_interval: Option<Duration>,
+    gc_context: Option<GcContext>,
+    // The safe point of the last finished round.
+    safe_point: u64,
+    rx: Receiver<()>,
+}
+
+impl<C: PdClient + 'static> Runner<C> {
+    fn poll_and_gc(&mut self) -> Result<(), Stopped> {
+        let safe_point = match self.pd_client.get_gc_safe_point().wait() {
+            Ok(safe_point) => safe_point,
+            Err(e) => {
+                warn!("failed to get gc safe point: {:?}", e);
+                return Ok(());
+            }
+        };
+        if safe_point <= self.safe_point {
+            if safe_point < self.safe_point {
+                warn!(
+                    "gc safe point {} is smaller than the last one {}, ignore it",
+                    safe_point,
+                    self.safe_point
+                );
+            }
+            return Ok(());
+        }
+
+        info!("start to gc with safe point {}", safe_point);
+        GC_SAFE_POINT_GAUGE.set(safe_point as f64);
+        // Compactions of the write cf can drop old versions too if the filter is enabled.
+        if let Some(ref ctx) = self.gc_context {
+            ctx.set_safe_point(safe_point);
+        }
+        let timer = GC_ROUND_HISTOGRAM.start_coarse_timer();
+        // The round is retried with the next poll if it fails halfway.
+        if try!(self.gc_regions(safe_point)) {
+            timer.observe_duration();
+            info!("finish gc with safe point {}", safe_point);
+            self.safe_point = safe_point;
+        }
+        Ok(())
+    }
+
+    // Returns whether all the regions led by this store are collected.
+    fn gc_regions(&self, safe_point: u64) -> Result<bool, Stopped> {
+        let mut region_ids: Vec<u64> = self.leader_regions.rl().iter().cloned().collect();
+        region_ids.sort();
+        let mut collected = false;
+        for _ in 0..GC_REGION_MAX_ATTEMPTS {
+            let mut failed = vec![];
+            for region_id in region_ids {
+                try!(self.check_stopped(false));
+                let key = keys::region_state_key(region_id);
+                let region = match self.engine.get_msg_cf::<RegionLocalState>(CF_RAFT, &key) {
+                    Ok(Some(ref state)) if state.get_state() != PeerState::Tombstone => {
+                        state.get_region().clone()
+                    }
+                    Ok(_) => {
+                        // The region is merged or removed, it never becomes leader here again.
+                        self.leader_regions.wl().remove(&region_id);
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!("failed to read the state of region {}: {:?}", region_id, e);
+                        failed.push(region_id);
+                        continue;
+                    }
+                };
+                if let Some(peer) = find_peer(&region, self.store_id) {
+                    // Only the regions which get collected are rate-limited.
+                    try!(self.check_stopped(collected));
+                    collected = true;
+                    if !self.gc_region(&region, peer, safe_point) {
+                        failed.push(region_id);
+                    }
+                }
+            }
+            if failed.is_empty() {
+                return Ok(true);
+            }
+            region_ids = failed;
+        }
+        warn!("failed to gc regions {:?} with safe point {}", region_ids, safe_point);
+        Ok(false)
+    }
+
+    // Returns false if the region should be collected again.
+    fn gc_region(&self, region: &metapb::Region, peer: &metapb::Peer, safe_point: u64) -> bool {
+        let mut ctx = Context::new();
+        ctx.set_region_id(region.get_id());
+        ctx.set_region_epoch(region.get_region_epoch().clone());
+        ctx.set_peer(peer.clone());
+
+        let (tx, rx) = mpsc::channel();
+        let res = self.storage
+            .async_gc(ctx, safe_point, box move |res| { let _ = tx.send(res); })
+            .and_then(|_| rx.recv().unwrap_or_else(|e| Err(box_err!("{:?}", e))));
+        let (label, done) = match res {
+            Ok(()) => ("success", true),
+            // The new leader collects it instead.
+            Err(ref e) if is_not_leader(e) => ("not_leader", true),
+            Err(e) => {
+                warn!("failed to gc region {}: {:?}", region.get_id(), e);
+                ("failed", false)
+            }
+        };
+        GC_REGION_COUNTER_VEC.with_label_values(&[label]).inc();
+        done
+    }
+
+    // Waits for the rate limit if `limited`, returns error if the manager is stopped.
+    fn check_stopped(&self, limited: bool) -> Result<(), Stopped> {
+        match self.region_interval {
+            Some(interval) if limited => match self.rx.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => Ok(()),
+                _ => Err(Stopped),
+            },
+            _ => match self.rx.try_recv() {
+                Err(TryRecvError::Empty) => Ok(()),
+                _ => Err(Stopped),
+            },
+        }
+    }
+}
+
+fn is_not_leader(e: &storage::Error) -> bool {
+    match *e {
+        storage::Error::Engine(engine::Error::Request(ref e)) |
+        storage::Error::Txn(txn::Error::Engine(engine::Error::Request(ref e))) |
+        storage::Error::Txn(
+            txn::Error::Mvcc(mvcc::Error::Engine(engine::Error::Request(ref e))),
+        ) => e.has_not_leader(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex, RwLock};
+    use std::sync::mpsc::{self, Receiver};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use futures::future;
+    use kvproto::metapb;
+    use kvproto::pdpb;
+    use kvproto::raft_serverpb::{PeerState, RegionLocalState};
+    use rocksdb::DB;
+    use tempdir::TempDir;
+
+    use pd::{PdClient, PdFuture, RegionStat, Result};
+    use raftstore::store::engine::Mutable;
+    use raftstore::store::keys;
+    use storage::{Config, Storage, ALL_CFS, CF_RAFT};
+    use util::collections::HashSet;
+    use util::rocksdb::{get_cf_handle, new_engine};
+    use super::*;
+
+    const STORE_ID: u64 = 1;
+
+    struct MockPdClient {
+        safe_point: Mutex<u64>,
+    }
+
+    impl MockPdClient {
+        fn new() -> MockPdClient {
+            MockPdClient {
+                safe_point: Mutex::new(0),
+            }
+        }
+
+        fn set_safe_point(&self, safe_point: u64) {
+            *self.safe_point.lock().unwrap() = safe_point;
+        }
+    }
+
+    impl PdClient for MockPdClient {
+        fn get_cluster_id(&self) -> Result<u64> {
+            unimplemented!();
+        }
+        fn bootstrap_cluster(&self, _: metapb::Store, _: metapb::Region) -> Result<()> {
+            unimplemented!();
+        }
+        fn is_cluster_bootstrapped(&self) -> Result<bool> {
+            unimplemented!();
+        }
+        fn alloc_id(&self) -> Result<u64> {
+            unimplemented!();
+        }
+        fn put_store(&self, _: metapb::Store) -> Result<()> {
+            unimplemented!();
+        }
+        fn get_store(&self, _: u64) -> Result<metapb::Store> {
+            unimplemented!();
+        }
+        fn get_cluster_config(&self) -> Result<metapb::Cluster> {
+            unimplemented!();
+        }
+        fn get_region(&self, _: &[u8]) -> Result<metapb::Region> {
+            unimplemented!();
+        }
+        fn get_region_leader(&self, _: &[u8]) -> Result<Option<metapb::Peer>> {
+            unimplemented!();
+        }
+        fn get_region_by_id(&self, _: u64) -> PdFuture<Option<metapb::Region>> {
+            unimplemented!();
+        }
+        fn region_heartbeat(
+            &self,
+            _: metapb::Region,
+            _: metapb::Peer,
+            _: RegionStat,
+        ) -> PdFuture<()> {
+            unimplemented!();
+        }
+
+        fn handle_region_heartbeat_response<F>(&self, _: u64, _: F) -> PdFuture<()>
+        where
+            F: Fn(pdpb::RegionHeartbeatResponse) + Send + 'static,
+        {
+            unimplemented!()
+        }
+
+        fn ask_split(&self, _: metapb::Region) -> PdFuture<pdpb::AskSplitResponse> {
+            unimplemented!();
+        }
+        fn store_heartbeat(&self, _: pdpb::StoreStats) -> PdFuture<()> {
+            unimplemented!();
+        }
+        fn report_split(&self, _: metapb::Region, _: metapb::Region) -> PdFuture<()> {
+            unimplemented!();
+        }
+        fn get_gc_safe_point(&self) -> PdFuture<u64> {
+            box future::ok(*self.safe_point.lock().unwrap())
+        }
+        fn get_tso(&self) -> PdFuture<u64> {
+            unimplemented!();
+        }
+    }
+
+    // Writes the local state of the region with a peer on `store_id`.
+    fn put_region(engine: &DB, region_id: u64, store_id: u64, state: PeerState) {
+        let mut peer = metapb::Peer::new();
+        peer.set_id(region_id * 10);
+        peer.set_store_id(store_id);
+        let mut region = metapb::Region::new();
+        region.set_id(region_id);
+        region.mut_peers().push(peer);
+        let mut region_state = RegionLocalState::new();
+        region_state.set_state(state);
+        region_state.set_region(region);
+        let handle = get_cf_handle(engine, CF_RAFT).unwrap();
+        let key = keys::region_state_key(region_id);
+        engine.put_msg_cf(handle, &key, &region_state).unwrap();
+    }
+
+    fn new_runner(
+        pd_client: Arc<MockPdClient>,
+        engine: Arc<DB>,
+        leader_regions: &[u64],
+        region_interval: Option<Duration>,
+        rx: Receiver<()>,
+    ) -> Runner<MockPdClient> {
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        Runner {
+            store_id: STORE_ID,
+            storage: storage,
+            engine: engine,
+            pd_client: pd_client,
+            leader_regions: Arc::new(RwLock::new(leader_regions.iter().cloned().collect())),
+            region_interval: region_interval,
+            gc_context: None,
+            safe_point: 0,
+            rx: rx,
+        }
+    }
+
+    #[test]
+    fn test_gc_safe_point() {
+        let path = TempDir::new("test_gc_safe_point").unwrap();
+        let engine = Arc::new(new_engine(path.path().to_str().unwrap(), ALL_CFS).unwrap());
+        // Region 5 is merged, region 6 is removed, and region 4 has no peer on this store.
+        put_region(&engine, 1, STORE_ID, PeerState::Normal);
+        put_region(&engine, 2, STORE_ID, PeerState::Normal);
+        put_region(&engine, 4, 2, PeerState::Normal);
+        put_region(&engine, 6, STORE_ID, PeerState::Tombstone);
+        let pd_client = Arc::new(MockPdClient::new());
+        let (_tx, rx) = mpsc::channel();
+        let mut runner = new_runner(pd_client.clone(), engine, &[6, 5, 4, 2, 1], None, rx);
+
+        // Nothing is collected before the safe point advances.
+        runner.poll_and_gc().unwrap_or_else(|_| panic!("stopped"));
+        assert_eq!(runner.leader_regions.rl().len(), 5);
+
+        pd_client.set_safe_point(10);
+        runner.poll_and_gc().unwrap_or_else(|_| panic!("stopped"));
+        assert_eq!(runner.safe_point, 10);
+        let leader_regions: HashSet<u64> = vec![1, 2, 4].into_iter().collect();
+        assert_eq!(*runner.leader_regions.rl(), leader_regions);
+
+        // The same or a regressed safe point is ignored.
+        runner.poll_and_gc().unwrap_or_else(|_| panic!("stopped"));
+        pd_client.set_safe_point(5);
+        runner.poll_and_gc().unwrap_or_else(|_| panic!("stopped"));
+        assert_eq!(runner.safe_point, 10);
+
+        pd_client.set_safe_point(20);
+        runner.poll_and_gc().unwrap_or_else(|_| panic!("stopped"));
+        assert_eq!(runner.safe_point, 20);
+    }
+
+    #[test]
+    fn test_gc_rate_limit() {
+        let path = TempDir::new("test_gc_rate_limit").unwrap();
+        let engine = Arc::new(new_engine(path.path().to_str().unwrap(), ALL_CFS).unwrap());
+        // Only regions 2 and 4 are collected, so the round waits once.
+        put_region(&engine, 2, STORE_ID, PeerState::Normal);
+        put_region(&engine, 3, 2, PeerState::Normal);
+        put_region(&engine, 4, STORE_ID, PeerState::Normal);
+        let pd_client = Arc::new(MockPdClient::new());
+        let (_tx, rx) = mpsc::channel();
+        let interval = Duration::from_millis(500);
+        let leader_regions: &[u64] = &[1, 2, 3, 4, 5];
+        let mut runner = new_runner(pd_client.clone(), engine, leader_regions, Some(interval), rx);
+
+        pd_client.set_safe_point(10);
+        let start = Instant::now();
+        runner.poll_and_gc().unwrap_or_else(|_| panic!("stopped"));
+        let elapsed = start.elapsed();
+        assert!(elapsed >= interval && elapsed < interval * 2, "{:?}", elapsed);
+        assert_eq!(runner.safe_point, 10);
+    }
+
+    #[test]
+    fn test_gc_stopped_halfway() {
+        let path = TempDir::new("test_gc_stopped_halfway").unwrap();
+        let engine = Arc::new(new_engine(path.path().to_str().unwrap(), ALL_CFS).unwrap());
+        for region_id in 1..4 {
+            put_region(&engine, region_id, STORE_ID, PeerState::Normal);
+        }
+        let pd_client = Arc::new(MockPdClient::new());
+        let (tx, rx) = mpsc::channel();
+        let interval = Duration::from_secs(10);
+        let mut runner = new_runner(pd_client.clone(), engine, &[1, 2, 3], Some(interval), rx);
+
+        // The manager is stopped while waiting to collect region 2, so the round isn't
+        // finished.
+        pd_client.set_safe_point(10);
+        let stopper = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            drop(tx);
+        });
+        let start = Instant::now();
+        assert!(runner.poll_and_gc().is_err());
+        assert!(start.elapsed() < interval);
+        assert_eq!(runner.safe_point, 0);
+        stopper.join().unwrap();
+    }
+
+    #[test]
+    fn test_gc_region_failed() {
+        let path = TempDir::new("test_gc_region_failed").unwrap();
+        let engine = Arc::new(new_engine(path.path().to_str().unwrap(), ALL_CFS).unwrap());
+        put_region(&engine, 1, STORE_ID, PeerState::Normal);
+        let pd_client = Arc::new(MockPdClient::new());
+        let (_tx, rx) = mpsc::channel();
+        let mut runner = new_runner(pd_client.clone(), engine, &[1], None, rx);
+
+        // The commands fail once the storage is stopped, so the round isn't finished and is
+        // tried again with the next poll.
+        runner.storage.stop().unwrap();
+        pd_client.set_safe_point(10);
+        runner.poll_and_gc().unwrap_or_else(|_| panic!("stopped"));
+        assert_eq!(runner.safe_point, 0);
+        assert!(runner.leader_regions.rl().contains(&1));
+
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        runner.storage = storage;
+        runner.poll_and_gc().unwrap_or_else(|_| panic!("stopped"));
+        assert_eq!(runner.safe_point, 10);
+    }
+}
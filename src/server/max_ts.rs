@@ -0,0 +1,164 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Syncs the max read ts of the new leaders with PD.
+//!
+//! The previous leader of a region may have served reads at a ts larger than the max read ts
+//! of this store, so the async-commit and 1PC prewrites on a new leader are rejected until the
+//! max read ts is bumped with a ts got from PD after it becomes leader.
+
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{future, Future};
+use raft::StateRole;
+use tokio_core::reactor::Handle;
+use tokio_timer::Timer;
+
+use pd::PdClient;
+use raftstore::coprocessor::{Coprocessor, ObserverContext, RegionObserver};
+use storage::txn::ConcurrencyManager;
+use util::worker::{FutureRunnable, FutureScheduler};
+
+// The interval to retry when failing to get a ts from PD.
+const RETRY_INTERVAL_MS: u64 = 100;
+
+pub enum Task {
+    Sync { region_id: u64, sync_id: u64 },
+}
+
+impl Display for Task {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Task::Sync { region_id, sync_id } => {
+                write!(f, "sync max ts of region {}, sync id {}", region_id, sync_id)
+            }
+        }
+    }
+}
+
+pub struct MaxTsSyncer<C: PdClient + 'static> {
+    pd_client: Arc<C>,
+    concurrency_manager: Arc<ConcurrencyManager>,
+    scheduler: FutureScheduler<Task>,
+    timer: Timer,
+}
+
+impl<C: PdClient + 'static> MaxTsSyncer<C> {
+    pub fn new(
+        pd_client: Arc<C>,
+        concurrency_manager: Arc<ConcurrencyManager>,
+        scheduler: FutureScheduler<Task>,
+    ) -> MaxTsSyncer<C> {
+        MaxTsSyncer {
+            pd_client: pd_client,
+            concurrency_manager: concurrency_manager,
+            scheduler: scheduler,
+            timer: Timer::default(),
+        }
+    }
+
+    fn sync(&self, region_id: u64, sync_id: u64, handle: &Handle) {
+        let cm = self.concurrency_manager.clone();
+        let scheduler = self.scheduler.clone();
+        let timer = self.timer.clone();
+        let f = self.pd_client
+            .get_tso()
+            .then(move |res| -> Box<Future<Item = (), Error = ()>> {
+                match res {
+                    Ok(ts) => {
+                        cm.finish_max_ts_sync(region_id, sync_id, ts);
+                        box future::ok(())
+                    }
+                    Err(e) => {
+                        // The region stays unsynced, so retry until it's synced or the peer
+                        // steps down.
+                        warn!("failed to get tso to sync max ts of region {}: {:?}", region_id, e);
+                        let retry = move |_| {
+                            let task = Task::Sync {
+                                region_id: region_id,
+                                sync_id: sync_id,
+                            };
+                            // Fails if the syncer is stopped.
+                            if let Err(e) = scheduler.schedule(task) {
+                                debug!("failed to retry syncing max ts: {:?}", e);
+                            }
+                            Ok(())
+                        };
+                        box timer
+                            .sleep(Duration::from_millis(RETRY_INTERVAL_MS))
+                            .then(retry)
+                    }
+                }
+            });
+        handle.spawn(f);
+    }
+}
+
+impl<C: PdClient + 'static> FutureRunnable<Task> for MaxTsSyncer<C> {
+    fn run(&mut self, task: Task, handle: &Handle) {
+        match task {
+            Task::Sync { region_id, sync_id } => {
+                // No need to sync if the peer has stepped down.
+                if self.concurrency_manager.is_max_ts_synced(region_id) {
+                    return;
+                }
+                self.sync(region_id, sync_id, handle)
+            }
+        }
+    }
+}
+
+/// Starts syncing the max read ts once a peer becomes leader.
+pub struct MaxTsObserver {
+    concurrency_manager: Arc<ConcurrencyManager>,
+    scheduler: FutureScheduler<Task>,
+}
+
+impl MaxTsObserver {
+    pub fn new(
+        concurrency_manager: Arc<ConcurrencyManager>,
+        scheduler: FutureScheduler<Task>,
+    ) -> MaxTsObserver {
+        MaxTsObserver {
+            concurrency_manager: concurrency_manager,
+            scheduler: scheduler,
+        }
+    }
+}
+
+impl Coprocessor for MaxTsObserver {}
+
+impl RegionObserver for MaxTsObserver {
+    fn on_role_change(&self, ctx: &mut ObserverContext, role: StateRole) {
+        let region_id = ctx.region().get_id();
+        if role != StateRole::Leader {
+            self.concurrency_manager.cancel_max_ts_sync(region_id);
+            return;
+        }
+        let sync_id = self.concurrency_manager.start_max_ts_sync(region_id);
+        let task = Task::Sync {
+            region_id: region_id,
+            sync_id: sync_id,
+        };
+        if let Err(e) = self.scheduler.schedule(task) {
+            error!("failed to schedule syncing max ts of region {}: {:?}", region_id, e);
+        }
+    }
+
+    fn on_replica_read_index(&self, _: &mut ObserverContext, start_ts: u64) {
+        // The replica reads at `start_ts` after the leader returns the read index.
+        self.concurrency_manager.update_max_read_ts(start_ts);
+    }
+}
@@ -11,7 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use prometheus::{Counter, CounterVec, Histogram, HistogramVec};
+use prometheus::{exponential_buckets, Counter, CounterVec, Gauge, Histogram, HistogramVec};
 
 lazy_static! {
     pub static ref SEND_SNAP_HISTOGRAM: Histogram =
@@ -60,4 +60,24 @@ lazy_static! {
             "Total number of reporting failure messages",
             &["type", "store_id"]
         ).unwrap();
+
+    pub static ref GC_SAFE_POINT_GAUGE: Gauge =
+        register_gauge!(
+            "tikv_gc_safe_point",
+            "The GC safe point used by the automatic GC"
+        ).unwrap();
+
+    pub static ref GC_REGION_COUNTER_VEC: CounterVec =
+        register_counter_vec!(
+            "tikv_gc_region_total",
+            "Total number of regions handled by the automatic GC",
+            &["result"]
+        ).unwrap();
+
+    pub static ref GC_ROUND_HISTOGRAM: Histogram =
+        register_histogram!(
+            "tikv_gc_round_duration_seconds",
+            "Bucketed histogram of the duration of GC over all the regions for a safe point",
+            exponential_buckets(1.0, 2.0, 16).unwrap()
+        ).unwrap();
 }
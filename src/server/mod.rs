@@ -24,6 +24,10 @@ pub mod transport;
 pub mod node;
 pub mod resolve;
 pub mod snap;
+pub mod gc_manager;
+pub mod resolved_ts;
+pub mod deadlock;
+pub mod max_ts;
 
 pub use self::config::{Config, DEFAULT_CLUSTER_ID, DEFAULT_LISTENING_ADDR};
 pub use self::errors::{Error, Result};
@@ -32,5 +36,7 @@ pub use self::transport::{ServerRaftStoreRouter, ServerTransport};
 pub use self::node::{create_raft_storage, Node};
 pub use self::resolve::{PdStoreAddrResolver, StoreAddrResolver};
 pub use self::raft_client::RaftClient;
+pub use self::gc_manager::{GcManager, GcObserver};
+pub use self::resolved_ts::ResolvedTsAdvancer;
 
 pub type OnResponse = Box<FnBox(Response) + Send>;
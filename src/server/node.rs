@@ -25,6 +25,8 @@ use kvproto::raft_serverpb::StoreIdent;
 use kvproto::metapb;
 use protobuf::RepeatedField;
 use util::transport::SendCh;
+use raftstore::coprocessor::CoprocessorHost;
+use import::SSTImporter;
 use raftstore::store::{self, keys, Config as StoreConfig, Engines, Msg, Peekable, SignificantMsg,
                        SnapManager, Store, StoreChannel, Transport};
 use super::Result;
@@ -117,6 +119,7 @@ where
         }
     }
 
+    #[allow(too_many_arguments)]
     pub fn start<T>(
         &mut self,
         event_loop: EventLoop<Store<T, C>>,
@@ -124,6 +127,8 @@ where
         trans: T,
         snap_mgr: SnapManager,
         significant_msg_receiver: Receiver<SignificantMsg>,
+        coprocessor_host: CoprocessorHost,
+        importer: Arc<SSTImporter>,
     ) -> Result<()>
     where
         T: Transport + 'static,
@@ -160,7 +165,9 @@ where
             engines,
             trans,
             snap_mgr,
-            significant_msg_receiver
+            significant_msg_receiver,
+            coprocessor_host,
+            importer
         ));
         Ok(())
     }
@@ -316,6 +323,7 @@ where
         Err(box_err!("check cluster bootstrapped failed"))
     }
 
+    #[allow(too_many_arguments)]
     fn start_store<T>(
         &mut self,
         mut event_loop: EventLoop<Store<T, C>>,
@@ -324,6 +332,8 @@ where
         trans: T,
         snap_mgr: SnapManager,
         significant_msg_receiver: Receiver<SignificantMsg>,
+        coprocessor_host: CoprocessorHost,
+        importer: Arc<SSTImporter>,
     ) -> Result<()>
     where
         T: Transport + 'static,
@@ -346,7 +356,17 @@ where
                 sender,
                 significant_msg_receiver,
             };
-            let mut store = match Store::new(ch, store, cfg, engines, trans, pd_client, snap_mgr) {
+            let mut store = match Store::new(
+                ch,
+                store,
+                cfg,
+                engines,
+                trans,
+                pd_client,
+                snap_mgr,
+                coprocessor_host,
+                importer,
+            ) {
                 Err(e) => panic!("construct store {} err {:?}", store_id, e),
                 Ok(s) => s,
             };
@@ -139,8 +139,10 @@ impl RaftClient {
     }
 
 
-    pub fn flush(&mut self) {
+    /// Sends the buffered messages, returns the stores whose connections are broken.
+    pub fn flush(&mut self) -> Vec<u64> {
         let addrs = &mut self.addrs;
+        let mut broken_stores = vec![];
         self.conns.retain(|&(addr, _), conn| {
             let store_id = conn.store_id;
             if !conn.alive.load(Ordering::SeqCst) {
@@ -149,6 +151,7 @@ impl RaftClient {
                         addrs.insert(store_id, addr_current);
                     }
                 }
+                broken_stores.push(store_id);
                 return false;
             }
 
@@ -170,12 +173,16 @@ impl RaftClient {
                         addrs.insert(store_id, addr_current);
                     }
                 }
+                broken_stores.push(store_id);
                 return false;
             }
 
             conn.buffer = Some(Vec::with_capacity(INITIAL_BUFFER_CAP));
             true
         });
+        broken_stores.sort();
+        broken_stores.dedup();
+        broken_stores
     }
 }
 
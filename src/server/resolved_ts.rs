@@ -0,0 +1,113 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Advances the resolved ts of the regions for stale reads.
+//!
+//! The advancer periodically gets a ts from PD and sends it to raftstore as the min ts. Every
+//! leader resolves its ts with the locks it has applied, and broadcasts the ts with its applied
+//! index to the followers. A replica serves the stale reads at or before the ts once it applies
+//! the logs up to the index.
+
+use std::cmp;
+use std::io;
+use std::sync::Arc;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{Builder, JoinHandle};
+use std::time::Duration;
+
+use futures::Future;
+
+use pd::PdClient;
+use raftstore::store::Msg as StoreMsg;
+use storage::txn::ConcurrencyManager;
+use super::transport::RaftStoreRouter;
+
+pub struct ResolvedTsAdvancer<C: PdClient + 'static, R: RaftStoreRouter + 'static> {
+    pd_client: Arc<C>,
+    concurrency_manager: Arc<ConcurrencyManager>,
+    router: R,
+    interval: Duration,
+    handle: Option<JoinHandle<()>>,
+    sender: Option<Sender<()>>,
+}
+
+impl<C: PdClient + 'static, R: RaftStoreRouter + 'static> ResolvedTsAdvancer<C, R> {
+    pub fn new(
+        interval: Duration,
+        pd_client: Arc<C>,
+        concurrency_manager: Arc<ConcurrencyManager>,
+        router: R,
+    ) -> ResolvedTsAdvancer<C, R> {
+        ResolvedTsAdvancer {
+            pd_client: pd_client,
+            concurrency_manager: concurrency_manager,
+            router: router,
+            interval: interval,
+            handle: None,
+            sender: None,
+        }
+    }
+
+    pub fn start(&mut self) -> Result<(), io::Error> {
+        let (tx, rx) = mpsc::channel();
+        let pd_client = self.pd_client.clone();
+        let cm = self.concurrency_manager.clone();
+        let router = self.router.clone();
+        let interval = self.interval;
+        self.sender = Some(tx);
+        let h = try!(
+            Builder::new()
+                .name(thd_name!("resolved-ts"))
+                .spawn(move || loop {
+                    match rx.recv_timeout(interval) {
+                        Err(RecvTimeoutError::Timeout) => {}
+                        _ => return,
+                    }
+                    let ts = match pd_client.get_tso().wait() {
+                        Ok(ts) => ts,
+                        Err(e) => {
+                            warn!("failed to get tso: {:?}", e);
+                            continue;
+                        }
+                    };
+                    let min_ts = advance_min_ts(&cm, ts);
+                    if let Err(e) = router.send(StoreMsg::AdvanceResolvedTs { min_ts: min_ts }) {
+                        warn!("failed to advance resolved ts: {:?}", e);
+                    }
+                })
+        );
+        self.handle = Some(h);
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        let h = self.handle.take();
+        if h.is_none() {
+            return;
+        }
+        drop(self.sender.take().unwrap());
+        if let Err(e) = h.unwrap().join() {
+            error!("join resolved ts advancer failed {:?}", e);
+        }
+    }
+}
+
+// Returns the ts before which no lock will be written later.
+fn advance_min_ts(cm: &ConcurrencyManager, ts: u64) -> u64 {
+    // New async commit locks will be committed after `ts`.
+    cm.update_max_read_ts(ts);
+    match cm.global_min_lock_ts() {
+        Some(lock_ts) => cmp::min(lock_ts, ts),
+        None => ts,
+    }
+}
@@ -19,10 +19,17 @@ use std::str::FromStr;
 use grpc::{ChannelBuilder, EnvBuilder, Environment, Server as GrpcServer, ServerBuilder};
 use kvproto::tikvpb_grpc::*;
 use kvproto::debugpb_grpc::create_debug;
-
-use util::worker::Worker;
+use kvproto::cdcpb_grpc::create_change_data;
+use kvproto::backup_grpc::create_backup;
+use kvproto::import_sstpb_grpc::create_import_sst;
+use kvproto::deadlock_grpc::create_deadlock;
+
+use util::worker::{FutureScheduler, Scheduler, Worker};
+use cdc::{Service as CdcService, Task as CdcTask};
+use backup::{Service as BackupService, Task as BackupTask};
+use import::ImportSSTService;
 use storage::Storage;
-use raftstore::store::{CopFlowStatistics, Engines, Msg, SignificantMsg, SnapManager};
+use raftstore::store::{CopFlowStatistics, Engines, LoadStats, Msg, SignificantMsg, SnapManager};
 
 use super::{Config, Result};
 use coprocessor::{CopRequestStatistics, CopSender, EndPointHost, EndPointTask, Result as CopResult};
@@ -31,6 +38,7 @@ use super::transport::{RaftStoreRouter, ServerTransport};
 use super::resolve::StoreAddrResolver;
 use super::snap::{Runner as SnapHandler, Task as SnapTask};
 use super::raft_client::RaftClient;
+use super::deadlock::Service as DeadlockService;
 
 const DEFAULT_COPROCESSOR_BATCH: usize = 256;
 const MAX_GRPC_RECV_MSG_LEN: usize = 10 * 1024 * 1024;
@@ -70,6 +78,11 @@ impl<R: RaftStoreRouter + 'static> CopSender for CopReport<R> {
         }));
         Ok(())
     }
+
+    fn send_load_stats(&self, stats: LoadStats) -> CopResult<()> {
+        box_try!(self.router.try_send(Msg::LoadStats { stats: stats }));
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
@@ -83,6 +96,10 @@ impl CopSender for MockCopSender {
     fn send(&self, _stats: CopRequestStatistics) -> CopResult<()> {
         Ok(())
     }
+
+    fn send_load_stats(&self, _stats: LoadStats) -> CopResult<()> {
+        Ok(())
+    }
 }
 
 impl<T: RaftStoreRouter, S: StoreAddrResolver + 'static> Server<T, S> {
@@ -96,6 +113,10 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver + 'static> Server<T, S> {
         resolver: S,
         snap_mgr: SnapManager,
         debug_engines: Option<Engines>,
+        cdc_scheduler: Option<FutureScheduler<CdcTask>>,
+        backup_scheduler: Option<Scheduler<BackupTask>>,
+        import_service: Option<ImportSSTService<T>>,
+        deadlock_service: Option<DeadlockService>,
     ) -> Result<Server<T, S>> {
         let env = Arc::new(
             EnvBuilder::new()
@@ -130,6 +151,18 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver + 'static> Server<T, S> {
             if let Some(engines) = debug_engines {
                 sb = sb.register_service(create_debug(DebugService::new(engines)));
             }
+            if let Some(scheduler) = cdc_scheduler {
+                sb = sb.register_service(create_change_data(CdcService::new(scheduler)));
+            }
+            if let Some(scheduler) = backup_scheduler {
+                sb = sb.register_service(create_backup(BackupService::new(scheduler)));
+            }
+            if let Some(service) = import_service {
+                sb = sb.register_service(create_import_sst(service));
+            }
+            if let Some(service) = deadlock_service {
+                sb = sb.register_service(create_deadlock(service));
+            }
             try!(sb.build())
         };
 
@@ -168,6 +201,7 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver + 'static> Server<T, S> {
     pub fn start(&mut self, cfg: &Config) -> Result<()> {
         let end_point = EndPointHost::new(
             self.storage.get_engine(),
+            self.storage.get_concurrency_manager(),
             self.end_point_worker.scheduler(),
             cfg,
             MockCopSender::new(),
@@ -275,6 +309,10 @@ mod tests {
             MockResolver { addr: addr.clone() },
             SnapManager::new("", None),
             None,
+            None,
+            None,
+            None,
+            None,
         ).unwrap();
         *addr.lock().unwrap() = Some(server.listening_addr());
 
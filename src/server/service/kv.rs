@@ -25,13 +25,13 @@ use kvproto::tikvpb_grpc;
 use kvproto::raft_serverpb::*;
 use kvproto::kvrpcpb::*;
 use kvproto::coprocessor::*;
-use kvproto::errorpb::{Error as RegionError, ServerIsBusy};
+use kvproto::errorpb::{Error as RegionError, MaxTimestampNotSynced, ServerIsBusy};
 
 use util::worker::Scheduler;
 use util::buf::PipeBuffer;
-use storage::{self, Key, Mutation, Options, Storage, Value};
+use storage::{self, Key, Mutation, Options, SecondaryLocksStatus, Storage, TxnStatus, Value};
 use storage::txn::Error as TxnError;
-use storage::mvcc::{Error as MvccError, Write as MvccWrite, WriteType};
+use storage::mvcc::{Error as MvccError, Lock as MvccLock, Write as MvccWrite, WriteType};
 use storage::engine::Error as EngineError;
 use server::transport::RaftStoreRouter;
 use server::snap::Task as SnapTask;
@@ -142,11 +142,25 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         let storage = self.storage.clone();
         let mut options = Options::default();
         options.key_only = req.get_key_only();
+        options.reverse_scan = req.get_reverse();
+
+        // An empty start key of a reverse scan means the end of the region.
+        let start_key = if options.reverse_scan && req.get_start_key().is_empty() {
+            Key::from_encoded(vec![])
+        } else {
+            Key::from_raw(req.get_start_key())
+        };
+        let end_key = if req.get_end_key().is_empty() {
+            None
+        } else {
+            Some(Key::from_raw(req.get_end_key()))
+        };
 
         let (cb, future) = make_callback();
         let res = storage.async_scan(
             req.take_context(),
-            Key::from_raw(req.get_start_key()),
+            start_key,
+            end_key,
             req.get_limit() as usize,
             req.get_version(),
             options,
@@ -201,14 +215,653 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         let mut options = Options::default();
         options.lock_ttl = req.get_lock_ttl();
         options.skip_constraint_check = req.get_skip_constraint_check();
+        options.for_update_ts = req.get_for_update_ts();
+        options.is_pessimistic_lock = req.take_is_pessimistic_lock();
+        options.use_async_commit = req.get_use_async_commit();
+        options.secondary_keys = req.take_secondaries().into_vec();
+        options.min_commit_ts = req.get_min_commit_ts();
+        options.try_one_pc = req.get_try_one_pc();
+
+        let (cb, future) = make_callback();
+        let res = self.storage.async_prewrite(
+            req.take_context(),
+            mutations,
+            req.take_primary_lock(),
+            req.get_start_version(),
+            options,
+            cb,
+        );
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future
+            .map_err(Error::from)
+            .map(|v| {
+                let mut resp = PrewriteResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else {
+                    let locks = v.map(|res| {
+                        resp.set_min_commit_ts(res.min_commit_ts);
+                        resp.set_one_pc_commit_ts(res.one_pc_commit_ts);
+                        res.locks
+                    });
+                    resp.set_errors(RepeatedField::from_vec(extract_key_errors(locks)));
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    fn kv_pessimistic_lock(
+        &self,
+        ctx: RpcContext,
+        mut req: PessimisticLockRequest,
+        sink: UnarySink<PessimisticLockResponse>,
+    ) {
+        let label = "kv_pessimistic_lock";
+        let timer = GRPC_MSG_HISTOGRAM_VEC
+            .with_label_values(&[label])
+            .start_coarse_timer();
+
+        let keys = req.get_mutations()
+            .into_iter()
+            .map(|x| match x.get_op() {
+                Op::PessimisticLock => Key::from_raw(x.get_key()),
+                _ => panic!("mismatch Op in pessimistic lock mutations"),
+            })
+            .collect();
+        let mut options = Options::default();
+        options.lock_ttl = req.get_lock_ttl();
+        options.for_update_ts = req.get_for_update_ts();
+        options.wait_timeout = req.get_wait_timeout();
+
+        let (cb, future) = make_callback();
+        let res = self.storage.async_acquire_pessimistic_lock(
+            req.take_context(),
+            keys,
+            req.take_primary_lock(),
+            req.get_start_version(),
+            options,
+            cb,
+        );
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future
+            .map_err(Error::from)
+            .map(|v| {
+                let mut resp = PessimisticLockResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else {
+                    resp.set_errors(RepeatedField::from_vec(extract_key_errors(v)));
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    fn kv_pessimistic_rollback(
+        &self,
+        ctx: RpcContext,
+        mut req: PessimisticRollbackRequest,
+        sink: UnarySink<PessimisticRollbackResponse>,
+    ) {
+        let label = "kv_pessimistic_rollback";
+        let timer = GRPC_MSG_HISTOGRAM_VEC
+            .with_label_values(&[label])
+            .start_coarse_timer();
+
+        let keys = req.get_keys().iter().map(|x| Key::from_raw(x)).collect();
+
+        let (cb, future) = make_callback();
+        let res = self.storage.async_pessimistic_rollback(
+            req.take_context(),
+            keys,
+            req.get_start_version(),
+            req.get_for_update_ts(),
+            cb,
+        );
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future
+            .map_err(Error::from)
+            .map(|v| {
+                let mut resp = PessimisticRollbackResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else if let Err(e) = v {
+                    resp.set_errors(RepeatedField::from_vec(vec![extract_key_error(&e)]));
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    fn kv_commit(&self, ctx: RpcContext, mut req: CommitRequest, sink: UnarySink<CommitResponse>) {
+        let label = "kv_commit";
+        let timer = GRPC_MSG_HISTOGRAM_VEC
+            .with_label_values(&[label])
+            .start_coarse_timer();
+
+        let keys = req.get_keys().iter().map(|x| Key::from_raw(x)).collect();
+
+        let (cb, future) = make_callback();
+        let res = self.storage.async_commit(
+            req.take_context(),
+            keys,
+            req.get_start_version(),
+            req.get_commit_version(),
+            cb,
+        );
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future
+            .map_err(Error::from)
+            .map(|v| {
+                let mut resp = CommitResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else if let Err(e) = v {
+                    resp.set_error(extract_key_error(&e));
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    fn kv_import(&self, ctx: RpcContext, _: ImportRequest, sink: UnarySink<ImportResponse>) {
+        // Data is imported by ingesting SST files through the `ImportSst` service.
+        let err = box_err!("kv_import is not supported, use the ImportSst service instead");
+        self.send_fail_status(ctx, sink, err, RpcStatusCode::Unimplemented);
+    }
+
+    fn kv_cleanup(
+        &self,
+        ctx: RpcContext,
+        mut req: CleanupRequest,
+        sink: UnarySink<CleanupResponse>,
+    ) {
+        let label = "kv_cleanup";
+        let timer = GRPC_MSG_HISTOGRAM_VEC
+            .with_label_values(&[label])
+            .start_coarse_timer();
+
+        let (cb, future) = make_callback();
+        let res = self.storage.async_cleanup(
+            req.take_context(),
+            Key::from_raw(req.get_key()),
+            req.get_start_version(),
+            req.get_current_ts(),
+            cb,
+        );
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future
+            .map_err(Error::from)
+            .map(|v| {
+                let mut resp = CleanupResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else if let Err(e) = v {
+                    if let Some(ts) = extract_committed(&e) {
+                        resp.set_commit_version(ts);
+                    } else {
+                        resp.set_error(extract_key_error(&e));
+                    }
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    fn kv_txn_heart_beat(
+        &self,
+        ctx: RpcContext,
+        mut req: TxnHeartBeatRequest,
+        sink: UnarySink<TxnHeartBeatResponse>,
+    ) {
+        let label = "kv_txn_heart_beat";
+        let timer = GRPC_MSG_HISTOGRAM_VEC
+            .with_label_values(&[label])
+            .start_coarse_timer();
+
+        let (cb, future) = make_callback();
+        let res = self.storage.async_txn_heart_beat(
+            req.take_context(),
+            Key::from_raw(req.get_primary_lock()),
+            req.get_start_version(),
+            req.get_advise_lock_ttl(),
+            cb,
+        );
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future
+            .map_err(Error::from)
+            .map(|v| {
+                let mut resp = TxnHeartBeatResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else {
+                    match v {
+                        Ok(TxnStatus::Uncommitted { lock }) => resp.set_lock_ttl(lock.ttl),
+                        Ok(_) => unreachable!(),
+                        Err(e) => resp.set_error(extract_key_error(&e)),
+                    }
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    fn kv_check_txn_status(
+        &self,
+        ctx: RpcContext,
+        mut req: CheckTxnStatusRequest,
+        sink: UnarySink<CheckTxnStatusResponse>,
+    ) {
+        let label = "kv_check_txn_status";
+        let timer = GRPC_MSG_HISTOGRAM_VEC
+            .with_label_values(&[label])
+            .start_coarse_timer();
+
+        let primary_key = req.get_primary_key().to_vec();
+        let (cb, future) = make_callback();
+        let res = self.storage.async_check_txn_status(
+            req.take_context(),
+            Key::from_raw(req.get_primary_key()),
+            req.get_lock_ts(),
+            req.get_current_ts(),
+            cb,
+        );
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future
+            .map_err(Error::from)
+            .map(move |v| {
+                let mut resp = CheckTxnStatusResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else {
+                    // Both `lock_ttl` and `commit_version` are 0 if it's rolled back. The
+                    // lock is returned so that async-commit transactions can be resolved by
+                    // checking the secondaries.
+                    match v {
+                        Ok(TxnStatus::Uncommitted { lock }) => {
+                            resp.set_lock_ttl(lock.ttl);
+                            resp.set_lock_info(extract_lock_info(primary_key, lock));
+                        }
+                        Ok(TxnStatus::Committed { commit_ts }) => {
+                            resp.set_commit_version(commit_ts)
+                        }
+                        Ok(TxnStatus::RolledBack) => {}
+                        Err(e) => resp.set_error(extract_key_error(&e)),
+                    }
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    fn kv_check_secondary_locks(
+        &self,
+        ctx: RpcContext,
+        mut req: CheckSecondaryLocksRequest,
+        sink: UnarySink<CheckSecondaryLocksResponse>,
+    ) {
+        let label = "kv_check_secondary_locks";
+        let timer = GRPC_MSG_HISTOGRAM_VEC
+            .with_label_values(&[label])
+            .start_coarse_timer();
+
+        let raw_keys = req.take_keys().into_vec();
+        let keys = raw_keys.iter().map(|k| Key::from_raw(k)).collect();
+        let (cb, future) = make_callback();
+        let res = self.storage.async_check_secondary_locks(
+            req.take_context(),
+            keys,
+            req.get_start_version(),
+            cb,
+        );
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future
+            .map_err(Error::from)
+            .map(move |v| {
+                let mut resp = CheckSecondaryLocksResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else {
+                    // Neither locks nor `commit_ts` is set if it's rolled back.
+                    match v {
+                        Ok(SecondaryLocksStatus::Locked(locks)) => {
+                            let locks = raw_keys
+                                .into_iter()
+                                .zip(locks)
+                                .map(|(key, lock)| extract_lock_info(key, lock))
+                                .collect();
+                            resp.set_locks(RepeatedField::from_vec(locks));
+                        }
+                        Ok(SecondaryLocksStatus::Committed(commit_ts)) => {
+                            resp.set_commit_ts(commit_ts)
+                        }
+                        Ok(SecondaryLocksStatus::RolledBack) => {}
+                        Err(e) => resp.set_error(extract_key_error(&e)),
+                    }
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    fn kv_batch_get(
+        &self,
+        ctx: RpcContext,
+        mut req: BatchGetRequest,
+        sink: UnarySink<BatchGetResponse>,
+    ) {
+        let label = "kv_batchget";
+        let timer = GRPC_MSG_HISTOGRAM_VEC
+            .with_label_values(&[label])
+            .start_coarse_timer();
+
+        let keys = req.get_keys()
+            .into_iter()
+            .map(|x| Key::from_raw(x))
+            .collect();
+
+        let (cb, future) = make_callback();
+        let res = self.storage
+            .async_batch_get(req.take_context(), keys, req.get_version(), cb);
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future
+            .map_err(Error::from)
+            .map(|v| {
+                let mut resp = BatchGetResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else {
+                    resp.set_pairs(RepeatedField::from_vec(extract_kv_pairs(v)))
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    fn kv_batch_rollback(
+        &self,
+        ctx: RpcContext,
+        mut req: BatchRollbackRequest,
+        sink: UnarySink<BatchRollbackResponse>,
+    ) {
+        let label = "kv_batch_rollback";
+        let timer = GRPC_MSG_HISTOGRAM_VEC
+            .with_label_values(&[label])
+            .start_coarse_timer();
+
+        let keys = req.get_keys()
+            .into_iter()
+            .map(|x| Key::from_raw(x))
+            .collect();
+
+        let (cb, future) = make_callback();
+        let res = self.storage
+            .async_rollback(req.take_context(), keys, req.get_start_version(), cb);
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future
+            .map_err(Error::from)
+            .map(|v| {
+                let mut resp = BatchRollbackResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else if let Err(e) = v {
+                    resp.set_error(extract_key_error(&e));
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    fn kv_scan_lock(
+        &self,
+        ctx: RpcContext,
+        mut req: ScanLockRequest,
+        sink: UnarySink<ScanLockResponse>,
+    ) {
+        let label = "kv_scan_lock";
+        let timer = GRPC_MSG_HISTOGRAM_VEC
+            .with_label_values(&[label])
+            .start_coarse_timer();
+
+        let (cb, future) = make_callback();
+        let res = self.storage
+            .async_scan_lock(req.take_context(), req.get_max_version(), cb);
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future
+            .map_err(Error::from)
+            .map(|v| {
+                let mut resp = ScanLockResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else {
+                    match v {
+                        Ok(locks) => resp.set_locks(RepeatedField::from_vec(locks)),
+                        Err(e) => resp.set_error(extract_key_error(&e)),
+                    }
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    fn kv_resolve_lock(
+        &self,
+        ctx: RpcContext,
+        mut req: ResolveLockRequest,
+        sink: UnarySink<ResolveLockResponse>,
+    ) {
+        let label = "kv_resolve_lock";
+        let timer = GRPC_MSG_HISTOGRAM_VEC
+            .with_label_values(&[label])
+            .start_coarse_timer();
+
+        let commit_ts = match req.get_commit_version() {
+            0 => None,
+            x => Some(x),
+        };
+
+        let (cb, future) = make_callback();
+        let res = self.storage
+            .async_resolve_lock(req.take_context(), req.get_start_version(), commit_ts, cb);
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future
+            .map_err(Error::from)
+            .map(|v| {
+                let mut resp = ResolveLockResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else if let Err(e) = v {
+                    resp.set_error(extract_key_error(&e));
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    fn kv_gc(&self, ctx: RpcContext, mut req: GCRequest, sink: UnarySink<GCResponse>) {
+        let label = "kv_gc";
+        let timer = GRPC_MSG_HISTOGRAM_VEC
+            .with_label_values(&[label])
+            .start_coarse_timer();
+
+        let (cb, future) = make_callback();
+        let res = self.storage
+            .async_gc(req.take_context(), req.get_safe_point(), cb);
+        if let Err(e) = res {
+            self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
+            return;
+        }
+
+        let future = future
+            .map_err(Error::from)
+            .map(|v| {
+                let mut resp = GCResponse::new();
+                if let Some(err) = extract_region_error(&v) {
+                    resp.set_region_error(err);
+                } else if let Err(e) = v {
+                    resp.set_error(extract_key_error(&e));
+                }
+                resp
+            })
+            .and_then(|res| sink.success(res).map_err(Error::from))
+            .map(|_| timer.observe_duration())
+            .map_err(move |e| {
+                debug!("{} failed: {:?}", label, e);
+                GRPC_MSG_FAIL_COUNTER.with_label_values(&[label]).inc();
+            });
+
+        ctx.spawn(future);
+    }
+
+    fn kv_delete_range(
+        &self,
+        ctx: RpcContext,
+        mut req: DeleteRangeRequest,
+        sink: UnarySink<DeleteRangeResponse>,
+    ) {
+        let label = "kv_delete_range";
+        let timer = GRPC_MSG_HISTOGRAM_VEC
+            .with_label_values(&[label])
+            .start_coarse_timer();
 
         let (cb, future) = make_callback();
-        let res = self.storage.async_prewrite(
+        let res = self.storage.async_delete_range(
             req.take_context(),
-            mutations,
-            req.take_primary_lock(),
-            req.get_start_version(),
-            options,
+            Key::from_raw(req.get_start_key()),
+            Key::from_raw(req.get_end_key()),
             cb,
         );
         if let Err(e) = res {
@@ -219,11 +872,11 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         let future = future
             .map_err(Error::from)
             .map(|v| {
-                let mut resp = PrewriteResponse::new();
+                let mut resp = DeleteRangeResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
-                } else {
-                    resp.set_errors(RepeatedField::from_vec(extract_key_errors(v)));
+                } else if let Err(e) = v {
+                    resp.set_error(format!("{}", e));
                 }
                 resp
             })
@@ -237,22 +890,15 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         ctx.spawn(future);
     }
 
-    fn kv_commit(&self, ctx: RpcContext, mut req: CommitRequest, sink: UnarySink<CommitResponse>) {
-        let label = "kv_commit";
+    fn raw_get(&self, ctx: RpcContext, mut req: RawGetRequest, sink: UnarySink<RawGetResponse>) {
+        let label = "raw_get";
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
 
-        let keys = req.get_keys().iter().map(|x| Key::from_raw(x)).collect();
-
         let (cb, future) = make_callback();
-        let res = self.storage.async_commit(
-            req.take_context(),
-            keys,
-            req.get_start_version(),
-            req.get_commit_version(),
-            cb,
-        );
+        let res = self.storage
+            .async_raw_get(req.take_context(), req.take_cf(), req.take_key(), cb);
         if let Err(e) = res {
             self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
             return;
@@ -261,11 +907,15 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         let future = future
             .map_err(Error::from)
             .map(|v| {
-                let mut resp = CommitResponse::new();
+                let mut resp = RawGetResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
-                } else if let Err(e) = v {
-                    resp.set_error(extract_key_error(&e));
+                } else {
+                    match v {
+                        Ok(Some(val)) => resp.set_value(val),
+                        Ok(None) => {}
+                        Err(e) => resp.set_error(format!("{}", e)),
+                    }
                 }
                 resp
             })
@@ -279,26 +929,22 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         ctx.spawn(future);
     }
 
-    fn kv_import(&self, _: RpcContext, _: ImportRequest, _: UnarySink<ImportResponse>) {
-        unimplemented!();
-    }
-
-    fn kv_cleanup(
+    fn raw_batch_get(
         &self,
         ctx: RpcContext,
-        mut req: CleanupRequest,
-        sink: UnarySink<CleanupResponse>,
+        mut req: RawBatchGetRequest,
+        sink: UnarySink<RawBatchGetResponse>,
     ) {
-        let label = "kv_cleanup";
+        let label = "raw_batch_get";
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
 
         let (cb, future) = make_callback();
-        let res = self.storage.async_cleanup(
+        let res = self.storage.async_raw_batch_get(
             req.take_context(),
-            Key::from_raw(req.get_key()),
-            req.get_start_version(),
+            req.take_cf(),
+            req.take_keys().into_vec(),
             cb,
         );
         if let Err(e) = res {
@@ -309,15 +955,11 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         let future = future
             .map_err(Error::from)
             .map(|v| {
-                let mut resp = CleanupResponse::new();
+                let mut resp = RawBatchGetResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
-                } else if let Err(e) = v {
-                    if let Some(ts) = extract_committed(&e) {
-                        resp.set_commit_version(ts);
-                    } else {
-                        resp.set_error(extract_key_error(&e));
-                    }
+                } else {
+                    resp.set_pairs(RepeatedField::from_vec(extract_kv_pairs(v)));
                 }
                 resp
             })
@@ -331,25 +973,20 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         ctx.spawn(future);
     }
 
-    fn kv_batch_get(
+    fn raw_get_key_ttl(
         &self,
         ctx: RpcContext,
-        mut req: BatchGetRequest,
-        sink: UnarySink<BatchGetResponse>,
+        mut req: RawGetKeyTTLRequest,
+        sink: UnarySink<RawGetKeyTTLResponse>,
     ) {
-        let label = "kv_batchget";
+        let label = "raw_get_key_ttl";
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
 
-        let keys = req.get_keys()
-            .into_iter()
-            .map(|x| Key::from_raw(x))
-            .collect();
-
         let (cb, future) = make_callback();
         let res = self.storage
-            .async_batch_get(req.take_context(), keys, req.get_version(), cb);
+            .async_raw_get_key_ttl(req.take_context(), req.take_cf(), req.take_key(), cb);
         if let Err(e) = res {
             self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
             return;
@@ -358,11 +995,15 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         let future = future
             .map_err(Error::from)
             .map(|v| {
-                let mut resp = BatchGetResponse::new();
+                let mut resp = RawGetKeyTTLResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
                 } else {
-                    resp.set_pairs(RepeatedField::from_vec(extract_kv_pairs(v)))
+                    match v {
+                        Ok(Some(ttl)) => resp.set_ttl(ttl),
+                        Ok(None) => resp.set_not_found(true),
+                        Err(e) => resp.set_error(format!("{}", e)),
+                    }
                 }
                 resp
             })
@@ -376,25 +1017,28 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         ctx.spawn(future);
     }
 
-    fn kv_batch_rollback(
-        &self,
-        ctx: RpcContext,
-        mut req: BatchRollbackRequest,
-        sink: UnarySink<BatchRollbackResponse>,
-    ) {
-        let label = "kv_batch_rollback";
+    fn raw_scan(&self, ctx: RpcContext, mut req: RawScanRequest, sink: UnarySink<RawScanResponse>) {
+        let label = "raw_scan";
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
 
-        let keys = req.get_keys()
-            .into_iter()
-            .map(|x| Key::from_raw(x))
-            .collect();
-
+        let end_key = if req.get_end_key().is_empty() {
+            None
+        } else {
+            Some(req.take_end_key())
+        };
         let (cb, future) = make_callback();
-        let res = self.storage
-            .async_rollback(req.take_context(), keys, req.get_start_version(), cb);
+        let res = self.storage.async_raw_scan(
+            req.take_context(),
+            req.take_cf(),
+            req.take_start_key(),
+            end_key,
+            req.get_limit() as usize,
+            req.get_key_only(),
+            req.get_reverse(),
+            cb,
+        );
         if let Err(e) = res {
             self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
             return;
@@ -403,11 +1047,11 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         let future = future
             .map_err(Error::from)
             .map(|v| {
-                let mut resp = BatchRollbackResponse::new();
+                let mut resp = RawScanResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
-                } else if let Err(e) = v {
-                    resp.set_error(extract_key_error(&e));
+                } else {
+                    resp.set_kvs(RepeatedField::from_vec(extract_kv_pairs(v)));
                 }
                 resp
             })
@@ -421,20 +1065,38 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         ctx.spawn(future);
     }
 
-    fn kv_scan_lock(
+    fn raw_batch_scan(
         &self,
         ctx: RpcContext,
-        mut req: ScanLockRequest,
-        sink: UnarySink<ScanLockResponse>,
+        mut req: RawBatchScanRequest,
+        sink: UnarySink<RawBatchScanResponse>,
     ) {
-        let label = "kv_scan_lock";
+        let label = "raw_batch_scan";
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
 
+        let ranges = req.take_ranges()
+            .into_iter()
+            .map(|mut range| {
+                let end_key = if range.get_end_key().is_empty() {
+                    None
+                } else {
+                    Some(range.take_end_key())
+                };
+                (range.take_start_key(), end_key)
+            })
+            .collect();
         let (cb, future) = make_callback();
-        let res = self.storage
-            .async_scan_lock(req.take_context(), req.get_max_version(), cb);
+        let res = self.storage.async_raw_batch_scan(
+            req.take_context(),
+            req.take_cf(),
+            ranges,
+            req.get_each_limit() as usize,
+            req.get_key_only(),
+            req.get_reverse(),
+            cb,
+        );
         if let Err(e) = res {
             self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
             return;
@@ -443,14 +1105,11 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         let future = future
             .map_err(Error::from)
             .map(|v| {
-                let mut resp = ScanLockResponse::new();
+                let mut resp = RawBatchScanResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
                 } else {
-                    match v {
-                        Ok(locks) => resp.set_locks(RepeatedField::from_vec(locks)),
-                        Err(e) => resp.set_error(extract_key_error(&e)),
-                    }
+                    resp.set_kvs(RepeatedField::from_vec(extract_kv_pairs(v)));
                 }
                 resp
             })
@@ -464,25 +1123,21 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         ctx.spawn(future);
     }
 
-    fn kv_resolve_lock(
-        &self,
-        ctx: RpcContext,
-        mut req: ResolveLockRequest,
-        sink: UnarySink<ResolveLockResponse>,
-    ) {
-        let label = "kv_resolve_lock";
+    fn raw_put(&self, ctx: RpcContext, mut req: RawPutRequest, sink: UnarySink<RawPutResponse>) {
+        let label = "raw_put";
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
 
-        let commit_ts = match req.get_commit_version() {
-            0 => None,
-            x => Some(x),
-        };
-
         let (cb, future) = make_callback();
-        let res = self.storage
-            .async_resolve_lock(req.take_context(), req.get_start_version(), commit_ts, cb);
+        let res = self.storage.async_raw_put(
+            req.take_context(),
+            req.take_cf(),
+            req.take_key(),
+            req.take_value(),
+            req.get_ttl(),
+            cb,
+        );
         if let Err(e) = res {
             self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
             return;
@@ -491,11 +1146,11 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         let future = future
             .map_err(Error::from)
             .map(|v| {
-                let mut resp = ResolveLockResponse::new();
+                let mut resp = RawPutResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
                 } else if let Err(e) = v {
-                    resp.set_error(extract_key_error(&e));
+                    resp.set_error(format!("{}", e));
                 }
                 resp
             })
@@ -509,15 +1164,24 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         ctx.spawn(future);
     }
 
-    fn kv_gc(&self, ctx: RpcContext, mut req: GCRequest, sink: UnarySink<GCResponse>) {
-        let label = "kv_gc";
+    fn raw_batch_put(
+        &self,
+        ctx: RpcContext,
+        mut req: RawBatchPutRequest,
+        sink: UnarySink<RawBatchPutResponse>,
+    ) {
+        let label = "raw_batch_put";
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
 
+        let pairs = req.take_pairs()
+            .into_iter()
+            .map(|mut x| (x.take_key(), x.take_value()))
+            .collect();
         let (cb, future) = make_callback();
         let res = self.storage
-            .async_gc(req.take_context(), req.get_safe_point(), cb);
+            .async_raw_batch_put(req.take_context(), req.take_cf(), pairs, cb);
         if let Err(e) = res {
             self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
             return;
@@ -526,11 +1190,11 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         let future = future
             .map_err(Error::from)
             .map(|v| {
-                let mut resp = GCResponse::new();
+                let mut resp = RawBatchPutResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
                 } else if let Err(e) = v {
-                    resp.set_error(extract_key_error(&e));
+                    resp.set_error(format!("{}", e));
                 }
                 resp
             })
@@ -544,24 +1208,20 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         ctx.spawn(future);
     }
 
-    fn kv_delete_range(
+    fn raw_delete(
         &self,
         ctx: RpcContext,
-        mut req: DeleteRangeRequest,
-        sink: UnarySink<DeleteRangeResponse>,
+        mut req: RawDeleteRequest,
+        sink: UnarySink<RawDeleteResponse>,
     ) {
-        let label = "kv_delete_range";
+        let label = "raw_delete";
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
 
         let (cb, future) = make_callback();
-        let res = self.storage.async_delete_range(
-            req.take_context(),
-            Key::from_raw(req.get_start_key()),
-            Key::from_raw(req.get_end_key()),
-            cb,
-        );
+        let res = self.storage
+            .async_raw_delete(req.take_context(), req.take_cf(), req.take_key(), cb);
         if let Err(e) = res {
             self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
             return;
@@ -570,7 +1230,7 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         let future = future
             .map_err(Error::from)
             .map(|v| {
-                let mut resp = DeleteRangeResponse::new();
+                let mut resp = RawDeleteResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
                 } else if let Err(e) = v {
@@ -588,15 +1248,24 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         ctx.spawn(future);
     }
 
-    fn raw_get(&self, ctx: RpcContext, mut req: RawGetRequest, sink: UnarySink<RawGetResponse>) {
-        let label = "raw_get";
+    fn raw_batch_delete(
+        &self,
+        ctx: RpcContext,
+        mut req: RawBatchDeleteRequest,
+        sink: UnarySink<RawBatchDeleteResponse>,
+    ) {
+        let label = "raw_batch_delete";
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
 
         let (cb, future) = make_callback();
-        let res = self.storage
-            .async_raw_get(req.take_context(), req.take_key(), cb);
+        let res = self.storage.async_raw_batch_delete(
+            req.take_context(),
+            req.take_cf(),
+            req.take_keys().into_vec(),
+            cb,
+        );
         if let Err(e) = res {
             self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
             return;
@@ -605,15 +1274,11 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         let future = future
             .map_err(Error::from)
             .map(|v| {
-                let mut resp = RawGetResponse::new();
+                let mut resp = RawBatchDeleteResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
-                } else {
-                    match v {
-                        Ok(Some(val)) => resp.set_value(val),
-                        Ok(None) => {}
-                        Err(e) => resp.set_error(format!("{}", e)),
-                    }
+                } else if let Err(e) = v {
+                    resp.set_error(format!("{}", e));
                 }
                 resp
             })
@@ -627,17 +1292,23 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         ctx.spawn(future);
     }
 
-    fn raw_scan(&self, ctx: RpcContext, mut req: RawScanRequest, sink: UnarySink<RawScanResponse>) {
-        let label = "raw_scan";
+    fn raw_delete_range(
+        &self,
+        ctx: RpcContext,
+        mut req: RawDeleteRangeRequest,
+        sink: UnarySink<RawDeleteRangeResponse>,
+    ) {
+        let label = "raw_delete_range";
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
 
         let (cb, future) = make_callback();
-        let res = self.storage.async_raw_scan(
+        let res = self.storage.async_raw_delete_range(
             req.take_context(),
+            req.take_cf(),
             req.take_start_key(),
-            req.get_limit() as usize,
+            req.take_end_key(),
             cb,
         );
         if let Err(e) = res {
@@ -648,11 +1319,11 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         let future = future
             .map_err(Error::from)
             .map(|v| {
-                let mut resp = RawScanResponse::new();
+                let mut resp = RawDeleteRangeResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
-                } else {
-                    resp.set_kvs(RepeatedField::from_vec(extract_kv_pairs(v)));
+                } else if let Err(e) = v {
+                    resp.set_error(format!("{}", e));
                 }
                 resp
             })
@@ -666,15 +1337,32 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         ctx.spawn(future);
     }
 
-    fn raw_put(&self, ctx: RpcContext, mut req: RawPutRequest, sink: UnarySink<RawPutResponse>) {
-        let label = "raw_put";
+    fn raw_compare_and_swap(
+        &self,
+        ctx: RpcContext,
+        mut req: RawCASRequest,
+        sink: UnarySink<RawCASResponse>,
+    ) {
+        let label = "raw_compare_and_swap";
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
 
+        let previous_value = if req.get_previous_not_exist() {
+            None
+        } else {
+            Some(req.take_previous_value())
+        };
         let (cb, future) = make_callback();
-        let res = self.storage
-            .async_raw_put(req.take_context(), req.take_key(), req.take_value(), cb);
+        let res = self.storage.async_raw_compare_and_swap(
+            req.take_context(),
+            req.take_cf(),
+            req.take_key(),
+            previous_value,
+            req.take_value(),
+            req.get_ttl(),
+            cb,
+        );
         if let Err(e) = res {
             self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
             return;
@@ -683,11 +1371,21 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         let future = future
             .map_err(Error::from)
             .map(|v| {
-                let mut resp = RawPutResponse::new();
+                let mut resp = RawCASResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
-                } else if let Err(e) = v {
-                    resp.set_error(format!("{}", e));
+                } else {
+                    match v {
+                        Ok((Some(val), succeed)) => {
+                            resp.set_previous_value(val);
+                            resp.set_succeed(succeed);
+                        }
+                        Ok((None, succeed)) => {
+                            resp.set_previous_not_exist(true);
+                            resp.set_succeed(succeed);
+                        }
+                        Err(e) => resp.set_error(format!("{}", e)),
+                    }
                 }
                 resp
             })
@@ -701,20 +1399,25 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         ctx.spawn(future);
     }
 
-    fn raw_delete(
+    fn raw_increment(
         &self,
         ctx: RpcContext,
-        mut req: RawDeleteRequest,
-        sink: UnarySink<RawDeleteResponse>,
+        mut req: RawIncrementRequest,
+        sink: UnarySink<RawIncrementResponse>,
     ) {
-        let label = "raw_delete";
+        let label = "raw_increment";
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .with_label_values(&[label])
             .start_coarse_timer();
 
         let (cb, future) = make_callback();
-        let res = self.storage
-            .async_raw_delete(req.take_context(), req.take_key(), cb);
+        let res = self.storage.async_raw_increment(
+            req.take_context(),
+            req.take_cf(),
+            req.take_key(),
+            req.get_delta(),
+            cb,
+        );
         if let Err(e) = res {
             self.send_fail_status(ctx, sink, Error::from(e), RpcStatusCode::ResourceExhausted);
             return;
@@ -723,11 +1426,14 @@ impl<T: RaftStoreRouter + 'static> tikvpb_grpc::Tikv for Service<T> {
         let future = future
             .map_err(Error::from)
             .map(|v| {
-                let mut resp = RawDeleteResponse::new();
+                let mut resp = RawIncrementResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
-                } else if let Err(e) = v {
-                    resp.set_error(format!("{}", e));
+                } else {
+                    match v {
+                        Ok(value) => resp.set_value(value),
+                        Err(e) => resp.set_error(format!("{}", e)),
+                    }
                 }
                 resp
             })
@@ -992,6 +1698,12 @@ fn extract_region_error<T>(res: &storage::Result<T>) -> Option<RegionError> {
             err.set_server_is_busy(server_is_busy_err);
             Some(err)
         }
+        Err(ref e @ Error::Txn(TxnError::MaxTimestampNotSynced { .. })) => {
+            let mut err = RegionError::new();
+            err.set_message(format!("{}", e));
+            err.set_max_timestamp_not_synced(MaxTimestampNotSynced::new());
+            Some(err)
+        }
         _ => None,
     }
 }
@@ -1021,11 +1733,42 @@ fn extract_key_error(err: &storage::Error) -> KeyError {
             lock_info.set_lock_ttl(ttl);
             key_error.set_locked(lock_info);
         }
+        storage::Error::Txn(
+            TxnError::Mvcc(MvccError::Deadlock {
+                lock_ts,
+                ref key,
+                deadlock_key_hash,
+                ..
+            }),
+        ) => {
+            warn!("txn deadlocks: {:?}", err);
+            let mut deadlock = Deadlock::new();
+            deadlock.set_lock_ts(lock_ts);
+            deadlock.set_lock_key(key.to_owned());
+            deadlock.set_deadlock_key_hash(deadlock_key_hash);
+            key_error.set_deadlock(deadlock);
+        }
         storage::Error::Txn(TxnError::Mvcc(MvccError::WriteConflict { .. })) |
         storage::Error::Txn(TxnError::Mvcc(MvccError::TxnLockNotFound { .. })) => {
             warn!("txn conflicts: {:?}", err);
             key_error.set_retryable(format!("{:?}", err));
         }
+        storage::Error::Txn(
+            TxnError::Mvcc(MvccError::CommitTsExpired {
+                start_ts,
+                commit_ts,
+                ref key,
+                min_commit_ts,
+            }),
+        ) => {
+            warn!("commit_ts is expired: {:?}", err);
+            let mut expired = CommitTsExpired::new();
+            expired.set_start_ts(start_ts);
+            expired.set_attempted_commit_ts(commit_ts);
+            expired.set_key(key.to_owned());
+            expired.set_min_commit_ts(min_commit_ts);
+            key_error.set_commit_ts_expired(expired);
+        }
         _ => {
             error!("txn aborts: {:?}", err);
             key_error.set_abort(format!("{:?}", err));
@@ -1106,6 +1849,18 @@ fn extract_2pc_writes(res: Vec<(u64, MvccWrite)>) -> Vec<WriteInfo> {
         .collect()
 }
 
+fn extract_lock_info(key: Vec<u8>, lock: MvccLock) -> LockInfo {
+    let mut lock_info = LockInfo::new();
+    lock_info.set_key(key);
+    lock_info.set_primary_lock(lock.primary);
+    lock_info.set_lock_version(lock.ts);
+    lock_info.set_lock_ttl(lock.ttl);
+    lock_info.set_use_async_commit(lock.use_async_commit);
+    lock_info.set_min_commit_ts(lock.min_commit_ts);
+    lock_info.set_secondaries(RepeatedField::from_vec(lock.secondaries));
+    lock_info
+}
+
 fn extract_key_errors(res: storage::Result<Vec<storage::Result<()>>>) -> Vec<KeyError> {
     match res {
         Ok(res) => res.into_iter()
@@ -193,7 +193,7 @@ impl<T: RaftStoreRouter + 'static, S: StoreAddrResolver + 'static> ServerTranspo
             trans.raft_client.wl().addrs.insert(store_id, addr);
             trans.write_data(store_id, addr, msg);
             // There may be no messages in the near future, so flush it immediately.
-            trans.raft_client.wl().flush();
+            trans.flush_raft_client();
         };
         if let Err(e) = self.resolver.resolve(store_id, cb) {
             error!("try to resolve err {:?}", e);
@@ -264,8 +264,18 @@ impl<T: RaftStoreRouter + 'static, S: StoreAddrResolver + 'static> ServerTranspo
         }
     }
 
-    pub fn flush_raft_client(&mut self) {
-        self.raft_client.wl().flush();
+    pub fn report_store_unreachable(&self, store_id: u64) {
+        let msg = SignificantMsg::StoreUnreachable { store_id: store_id };
+        if let Err(e) = self.significant_msg_sender.send(msg) {
+            error!("report store {} unreachable failed {:?}", store_id, e);
+        }
+    }
+
+    pub fn flush_raft_client(&self) {
+        let broken_stores = self.raft_client.wl().flush();
+        for store_id in broken_stores {
+            self.report_store_unreachable(store_id);
+        }
     }
 }
 
@@ -15,7 +15,7 @@ use std::error::Error;
 
 use sys_info;
 
-use util::config;
+use util::config::{self, ReadableDuration};
 
 pub const DEFAULT_DATA_DIR: &'static str = "";
 pub const DEFAULT_ROCKSDB_SUB_DIR: &'static str = "db";
@@ -24,6 +24,9 @@ const DEFAULT_SCHED_CAPACITY: usize = 10240;
 const DEFAULT_SCHED_MSG_PER_TICK: usize = 1024;
 const DEFAULT_SCHED_CONCURRENCY: usize = 102400;
 const DEFAULT_SCHED_TOO_BUSY_THRESHOLD: usize = 4000;
+const DEFAULT_WAIT_FOR_LOCK_TIMEOUT_SECS: u64 = 3;
+const DEFAULT_GC_POLL_SAFE_POINT_INTERVAL_SECS: u64 = 10;
+const DEFAULT_GC_MAX_REGIONS_PER_SEC: u64 = 50;
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(default)]
@@ -36,6 +39,15 @@ pub struct Config {
     pub scheduler_concurrency: usize,
     pub scheduler_worker_pool_size: usize,
     pub scheduler_too_busy_threshold: usize,
+    pub wait_for_lock_timeout: ReadableDuration,
+    // Whether raw values carry an expire time. It can't be changed once the data is written.
+    pub enable_ttl: bool,
+    // How often to poll the GC safe point from PD.
+    pub gc_poll_safe_point_interval: ReadableDuration,
+    // The max number of regions to GC per second, 0 means no limit.
+    pub gc_max_regions_per_sec: u64,
+    // Whether to drop old versions during the compaction of the write cf.
+    pub gc_enable_compaction_filter: bool,
 }
 
 impl Default for Config {
@@ -49,6 +61,13 @@ impl Default for Config {
             scheduler_concurrency: DEFAULT_SCHED_CONCURRENCY,
             scheduler_worker_pool_size: if total_cpu >= 16 { 8 } else { 4 },
             scheduler_too_busy_threshold: DEFAULT_SCHED_TOO_BUSY_THRESHOLD,
+            wait_for_lock_timeout: ReadableDuration::secs(DEFAULT_WAIT_FOR_LOCK_TIMEOUT_SECS),
+            enable_ttl: false,
+            gc_poll_safe_point_interval: ReadableDuration::secs(
+                DEFAULT_GC_POLL_SAFE_POINT_INTERVAL_SECS,
+            ),
+            gc_max_regions_per_sec: DEFAULT_GC_MAX_REGIONS_PER_SEC,
+            gc_enable_compaction_filter: false,
         }
     }
 }
@@ -58,6 +77,9 @@ impl Config {
         if self.data_dir != DEFAULT_DATA_DIR {
             self.data_dir = try!(config::canonicalize_path(&self.data_dir))
         }
+        if self.gc_poll_safe_point_interval.as_millis() == 0 {
+            return Err("storage.gc-poll-safe-point-interval should not be 0.".into());
+        }
         Ok(())
     }
 }
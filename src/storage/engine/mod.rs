@@ -27,6 +27,7 @@ mod rocksdb;
 pub mod raftkv;
 mod metrics;
 use super::super::raftstore::store::engine::IterOption;
+use super::super::raftstore::store::LoadStats;
 
 // only used for rocksdb without persistent.
 pub const TEMP_DIR: &'static str = "";
@@ -113,6 +114,9 @@ pub trait Engine: Send + Debug {
         self.write(ctx, vec![Modify::Delete(cf, key)])
     }
 
+    /// Reports the sampled load of the regions, which is used to split the hot regions.
+    fn report_load(&self, _: LoadStats) {}
+
     /// Create a share Engine pointer.
     fn clone(&self) -> Box<Engine + 'static>;
 }
@@ -263,6 +267,15 @@ impl Statistics {
         self.write.add(&other.write);
         self.data.add(&other.data);
     }
+
+    pub fn mut_cf_statistics(&mut self, cf: &str) -> &mut CFStatistics {
+        match cf {
+            CF_DEFAULT => &mut self.data,
+            CF_LOCK => &mut self.lock,
+            CF_WRITE => &mut self.write,
+            _ => unreachable!(),
+        }
+    }
 }
 
 #[derive(Default)]
@@ -17,6 +17,7 @@ use raftstore::store;
 use raftstore::errors::Error as RaftServerError;
 use raftstore::coprocessor::{RegionIterator, RegionSnapshot};
 use raftstore::store::engine::{Peekable, Snapshot as EngineSnapshot};
+use raftstore::store::LoadStats;
 use rocksdb::TablePropertiesCollection;
 use storage;
 use kvproto::raft_cmdpb::{CmdType, DeleteRangeRequest, DeleteRequest, PutRequest, RaftCmdRequest,
@@ -236,6 +237,11 @@ impl<S: RaftStoreRouter> RaftKv<S> {
             header.set_term(ctx.get_term());
         }
         header.set_sync_log(ctx.get_sync_log());
+        header.set_replica_read(ctx.get_replica_read());
+        header.set_stale_read(ctx.get_stale_read());
+        if ctx.get_stale_read() || ctx.get_replica_read() {
+            header.set_read_ts(ctx.get_read_ts());
+        }
         header
     }
 
@@ -460,19 +466,34 @@ impl<S: RaftStoreRouter> Engine for RaftKv<S> {
             })
     }
 
+    fn report_load(&self, stats: LoadStats) {
+        if let Err(e) = self.router.try_send(store::Msg::LoadStats { stats: stats }) {
+            warn!("failed to report load stats: {:?}", e);
+        }
+    }
+
     fn clone(&self) -> Box<Engine> {
         box RaftKv::new(self.db.clone(), self.router.clone())
     }
 }
 
+/// Reports reading a key out of the region as a region error, so the client can retry it
+/// with the latest region.
+fn read_error(e: RaftServerError) -> engine::Error {
+    match e {
+        e @ RaftServerError::KeyNotInRegion(..) => e.into(),
+        e => box_err!(e),
+    }
+}
+
 impl Snapshot for RegionSnapshot {
     fn get(&self, key: &Key) -> engine::Result<Option<Value>> {
-        let v = box_try!(self.get_value(key.encoded()));
+        let v = try!(self.get_value(key.encoded()).map_err(read_error));
         Ok(v.map(|v| v.to_vec()))
     }
 
     fn get_cf(&self, cf: CfName, key: &Key) -> engine::Result<Option<Value>> {
-        let v = box_try!(self.get_value_cf(cf, key.encoded()));
+        let v = try!(self.get_value_cf(cf, key.encoded()).map_err(read_error));
         Ok(v.map(|v| v.to_vec()))
     }
 
@@ -34,6 +34,12 @@ lazy_static! {
             "Total number of pending commands."
         ).unwrap();
 
+    pub static ref SCHED_WAITING_COMMANDS_GAUGE: Gauge =
+        register_gauge!(
+            "tikv_scheduler_waiting_commands_total",
+            "Total number of commands waiting for locks to be released."
+        ).unwrap();
+
     pub static ref SCHED_WORKER_COUNTER_VEC: CounterVec =
         register_counter_vec!(
             "tikv_scheduler_worker_command_total",
@@ -19,8 +19,10 @@ use std::error;
 use std::sync::{Arc, Mutex};
 use std::io::Error as IoError;
 use std::u64;
-use kvproto::kvrpcpb::{CommandPri, LockInfo};
+use std::time::Duration;
+use kvproto::kvrpcpb::{CommandPri, IsolationLevel, LockInfo};
 use kvproto::errorpb;
+use util::rocksdb::ttl;
 use self::metrics::*;
 
 pub mod engine;
@@ -35,7 +37,8 @@ pub use self::engine::{new_local_engine, CFStatistics, Cursor, Engine, Error as
                        FlowStatistics, Modify, ScanMode, Snapshot, Statistics, StatisticsSummary,
                        TEMP_DIR};
 pub use self::engine::raftkv::RaftKv;
-pub use self::txn::{Msg, Scheduler, SnapshotStore, StoreScanner};
+pub use self::mvcc::{SecondaryLocksStatus, TxnStatus};
+pub use self::txn::{ConcurrencyManager, Msg, Scheduler, SnapshotStore, StoreScanner};
 pub use self::types::{make_key, Key, KvPair, MvccInfo, Value};
 pub type Callback<T> = Box<FnBox(Result<T>) + Send>;
 
@@ -85,6 +88,12 @@ pub enum StorageCb {
     MvccInfoByKey(Callback<MvccInfo>),
     MvccInfoByStartTs(Callback<Option<(Key, MvccInfo)>>),
     Locks(Callback<Vec<LockInfo>>),
+    TTL(Callback<Option<u64>>),
+    CompareAndSwap(Callback<(Option<Value>, bool)>),
+    Counter(Callback<i64>),
+    TxnStatus(Callback<TxnStatus>),
+    Prewrite(Callback<PrewriteResult>),
+    SecondaryLocksStatus(Callback<SecondaryLocksStatus>),
 }
 
 pub enum Command {
@@ -101,6 +110,7 @@ pub enum Command {
     Scan {
         ctx: Context,
         start_key: Key,
+        end_key: Option<Key>,
         limit: usize,
         start_ts: u64,
         options: Options,
@@ -112,6 +122,13 @@ pub enum Command {
         start_ts: u64,
         options: Options,
     },
+    AcquirePessimisticLock {
+        ctx: Context,
+        keys: Vec<Key>,
+        primary: Vec<u8>,
+        start_ts: u64,
+        options: Options,
+    },
     Commit {
         ctx: Context,
         keys: Vec<Key>,
@@ -122,12 +139,36 @@ pub enum Command {
         ctx: Context,
         key: Key,
         start_ts: u64,
+        current_ts: u64,
+    },
+    TxnHeartBeat {
+        ctx: Context,
+        primary_key: Key,
+        start_ts: u64,
+        advise_ttl: u64,
+    },
+    CheckTxnStatus {
+        ctx: Context,
+        primary_key: Key,
+        lock_ts: u64,
+        current_ts: u64,
+    },
+    CheckSecondaryLocks {
+        ctx: Context,
+        keys: Vec<Key>,
+        start_ts: u64,
     },
     Rollback {
         ctx: Context,
         keys: Vec<Key>,
         start_ts: u64,
     },
+    PessimisticRollback {
+        ctx: Context,
+        keys: Vec<Key>,
+        start_ts: u64,
+        for_update_ts: u64,
+    },
     ScanLock { ctx: Context, max_ts: u64 },
     ResolveLock {
         ctx: Context,
@@ -143,11 +184,73 @@ pub enum Command {
         scan_key: Option<Key>,
         keys: Vec<Key>,
     },
-    RawGet { ctx: Context, key: Key },
+    RawGet {
+        ctx: Context,
+        cf: CfName,
+        key: Key,
+    },
+    RawBatchGet {
+        ctx: Context,
+        cf: CfName,
+        keys: Vec<Key>,
+    },
+    RawGetKeyTTL {
+        ctx: Context,
+        cf: CfName,
+        key: Key,
+    },
     RawScan {
         ctx: Context,
+        cf: CfName,
         start_key: Key,
+        end_key: Option<Key>,
         limit: usize,
+        key_only: bool,
+        reverse: bool,
+    },
+    RawBatchScan {
+        ctx: Context,
+        cf: CfName,
+        ranges: Vec<(Key, Option<Key>)>,
+        each_limit: usize,
+        key_only: bool,
+        reverse: bool,
+    },
+    RawPut {
+        ctx: Context,
+        cf: CfName,
+        key: Key,
+        value: Value,
+    },
+    RawBatchPut {
+        ctx: Context,
+        cf: CfName,
+        pairs: Vec<(Key, Value)>,
+    },
+    RawDelete { ctx: Context, cf: CfName, key: Key },
+    RawBatchDelete {
+        ctx: Context,
+        cf: CfName,
+        keys: Vec<Key>,
+    },
+    RawDeleteRange {
+        ctx: Context,
+        cf: CfName,
+        start_key: Key,
+        end_key: Key,
+    },
+    RawCompareAndSwap {
+        ctx: Context,
+        cf: CfName,
+        key: Key,
+        previous_value: Option<Value>,
+        value: Value,
+    },
+    RawIncrement {
+        ctx: Context,
+        cf: CfName,
+        key: Key,
+        delta: i64,
     },
     DeleteRange {
         ctx: Context,
@@ -206,6 +309,20 @@ impl Display for Command {
                 start_ts,
                 ctx
             ),
+            Command::AcquirePessimisticLock {
+                ref ctx,
+                ref keys,
+                start_ts,
+                ref options,
+                ..
+            } => write!(
+                f,
+                "kv::command::acquirepessimisticlock keys({}) @ {} {} | {:?}",
+                keys.len(),
+                start_ts,
+                options.for_update_ts,
+                ctx
+            ),
             Command::Commit {
                 ref ctx,
                 ref keys,
@@ -226,6 +343,43 @@ impl Display for Command {
                 start_ts,
                 ..
             } => write!(f, "kv::command::cleanup {} @ {} | {:?}", key, start_ts, ctx),
+            Command::TxnHeartBeat {
+                ref ctx,
+                ref primary_key,
+                start_ts,
+                advise_ttl,
+            } => write!(
+                f,
+                "kv::command::txn_heart_beat {} @ {} ttl {} | {:?}",
+                primary_key,
+                start_ts,
+                advise_ttl,
+                ctx
+            ),
+            Command::CheckTxnStatus {
+                ref ctx,
+                ref primary_key,
+                lock_ts,
+                current_ts,
+            } => write!(
+                f,
+                "kv::command::check_txn_status {} @ {} curr({}) | {:?}",
+                primary_key,
+                lock_ts,
+                current_ts,
+                ctx
+            ),
+            Command::CheckSecondaryLocks {
+                ref ctx,
+                ref keys,
+                start_ts,
+            } => write!(
+                f,
+                "kv::command::check_secondary_locks keys({}) @ {} | {:?}",
+                keys.len(),
+                start_ts,
+                ctx
+            ),
             Command::Rollback {
                 ref ctx,
                 ref keys,
@@ -238,6 +392,19 @@ impl Display for Command {
                 start_ts,
                 ctx
             ),
+            Command::PessimisticRollback {
+                ref ctx,
+                ref keys,
+                start_ts,
+                for_update_ts,
+            } => write!(
+                f,
+                "kv::command::pessimistic_rollback keys({}) @ {} {} | {:?}",
+                keys.len(),
+                start_ts,
+                for_update_ts,
+                ctx
+            ),
             Command::ScanLock {
                 ref ctx, max_ts, ..
             } => write!(f, "kv::scan_lock {} | {:?}", max_ts, ctx),
@@ -265,18 +432,124 @@ impl Display for Command {
                 safe_point,
                 ctx
             ),
-            Command::RawGet { ref ctx, ref key } => {
-                write!(f, "kv::command::rawget {:?} | {:?}", key, ctx)
-            }
+            Command::RawGet {
+                ref ctx,
+                cf,
+                ref key,
+            } => write!(f, "kv::command::rawget {} {:?} | {:?}", cf, key, ctx),
+            Command::RawBatchGet {
+                ref ctx,
+                cf,
+                ref keys,
+            } => write!(
+                f,
+                "kv::command::rawbatchget {} {} keys | {:?}",
+                cf,
+                keys.len(),
+                ctx
+            ),
+            Command::RawGetKeyTTL {
+                ref ctx,
+                cf,
+                ref key,
+            } => write!(f, "kv::command::rawgetkeyttl {} {:?} | {:?}", cf, key, ctx),
             Command::RawScan {
                 ref ctx,
+                cf,
                 ref start_key,
+                ref end_key,
                 limit,
+                reverse,
+                ..
             } => write!(
                 f,
-                "kv::command::rawscan {:?} {} | {:?}",
+                "kv::command::rawscan {} {:?} {:?} {} {} | {:?}",
+                cf,
                 start_key,
+                end_key,
                 limit,
+                reverse,
+                ctx
+            ),
+            Command::RawBatchScan {
+                ref ctx,
+                cf,
+                ref ranges,
+                each_limit,
+                reverse,
+                ..
+            } => write!(
+                f,
+                "kv::command::rawbatchscan {} {:?} {} {} | {:?}",
+                cf,
+                ranges,
+                each_limit,
+                reverse,
+                ctx
+            ),
+            Command::RawPut {
+                ref ctx,
+                cf,
+                ref key,
+                ..
+            } => write!(f, "kv::command::rawput {} {:?} | {:?}", cf, key, ctx),
+            Command::RawDelete {
+                ref ctx,
+                cf,
+                ref key,
+            } => write!(f, "kv::command::rawdelete {} {:?} | {:?}", cf, key, ctx),
+            Command::RawBatchPut {
+                ref ctx,
+                cf,
+                ref pairs,
+            } => write!(
+                f,
+                "kv::command::rawbatchput {} {} pairs | {:?}",
+                cf,
+                pairs.len(),
+                ctx
+            ),
+            Command::RawBatchDelete {
+                ref ctx,
+                cf,
+                ref keys,
+            } => write!(
+                f,
+                "kv::command::rawbatchdelete {} {} keys | {:?}",
+                cf,
+                keys.len(),
+                ctx
+            ),
+            Command::RawDeleteRange {
+                ref ctx,
+                cf,
+                ref start_key,
+                ref end_key,
+            } => write!(
+                f,
+                "kv::command::rawdeleterange {} [{:?}, {:?}) | {:?}",
+                cf,
+                start_key,
+                end_key,
+                ctx
+            ),
+            Command::RawCompareAndSwap {
+                ref ctx,
+                cf,
+                ref key,
+                ..
+            } => write!(f, "kv::command::rawcompareandswap {} {:?} | {:?}", cf, key, ctx),
+            Command::RawIncrement {
+                ref ctx,
+                cf,
+                ref key,
+                delta,
+            } => write!(
+                f,
+                "kv::command::rawincrement {} {:?} by {} | {:?}",
+                cf,
+                key,
+                delta,
                 ctx
             ),
             Command::DeleteRange {
@@ -320,7 +593,10 @@ impl Command {
             Command::Scan { .. } |
             Command::ScanLock { .. } |
             Command::RawGet { .. } |
+            Command::RawBatchGet { .. } |
+            Command::RawGetKeyTTL { .. } |
             Command::RawScan { .. } |
+            Command::RawBatchScan { .. } |
             // DeleteRange only called by DDL bg thread after table is dropped and
             // must guarantee that there is no other read or write on these keys, so
             // we can treat DeleteRange as readonly Command.
@@ -356,14 +632,29 @@ impl Command {
             Command::BatchGet { .. } => "batch_get",
             Command::Scan { .. } => "scan",
             Command::Prewrite { .. } => "prewrite",
+            Command::AcquirePessimisticLock { .. } => "acquire_pessimistic_lock",
             Command::Commit { .. } => "commit",
             Command::Cleanup { .. } => "cleanup",
+            Command::TxnHeartBeat { .. } => "txn_heart_beat",
+            Command::CheckTxnStatus { .. } => "check_txn_status",
+            Command::CheckSecondaryLocks { .. } => "check_secondary_locks",
             Command::Rollback { .. } => "rollback",
+            Command::PessimisticRollback { .. } => "pessimistic_rollback",
             Command::ScanLock { .. } => "scan_lock",
             Command::ResolveLock { .. } => "resolve_lock",
             Command::Gc { .. } => CMD_TAG_GC,
             Command::RawGet { .. } => "raw_get",
+            Command::RawBatchGet { .. } => "raw_batch_get",
+            Command::RawGetKeyTTL { .. } => "raw_get_key_ttl",
             Command::RawScan { .. } => "raw_scan",
+            Command::RawBatchScan { .. } => "raw_batch_scan",
+            Command::RawPut { .. } => "raw_put",
+            Command::RawBatchPut { .. } => "raw_batch_put",
+            Command::RawDelete { .. } => "raw_delete",
+            Command::RawBatchDelete { .. } => "raw_batch_delete",
+            Command::RawDeleteRange { .. } => "raw_delete_range",
+            Command::RawCompareAndSwap { .. } => "raw_compare_and_swap",
+            Command::RawIncrement { .. } => "raw_increment",
             Command::DeleteRange { .. } => "delete_range",
             Command::Pause { .. } => "pause",
             Command::MvccByKey { .. } => "key_mvcc",
@@ -377,15 +668,30 @@ impl Command {
             Command::BatchGet { start_ts, .. } |
             Command::Scan { start_ts, .. } |
             Command::Prewrite { start_ts, .. } |
+            Command::AcquirePessimisticLock { start_ts, .. } |
             Command::Cleanup { start_ts, .. } |
+            Command::TxnHeartBeat { start_ts, .. } |
+            Command::CheckSecondaryLocks { start_ts, .. } |
             Command::Rollback { start_ts, .. } |
+            Command::PessimisticRollback { start_ts, .. } |
             Command::ResolveLock { start_ts, .. } |
             Command::MvccByStartTs { start_ts, .. } => start_ts,
-            Command::Commit { lock_ts, .. } => lock_ts,
+            Command::Commit { lock_ts, .. } |
+            Command::CheckTxnStatus { lock_ts, .. } => lock_ts,
             Command::ScanLock { max_ts, .. } => max_ts,
             Command::Gc { safe_point, .. } => safe_point,
             Command::RawGet { .. } |
+            Command::RawBatchGet { .. } |
+            Command::RawGetKeyTTL { .. } |
             Command::RawScan { .. } |
+            Command::RawBatchScan { .. } |
+            Command::RawPut { .. } |
+            Command::RawBatchPut { .. } |
+            Command::RawDelete { .. } |
+            Command::RawBatchDelete { .. } |
+            Command::RawDeleteRange { .. } |
+            Command::RawCompareAndSwap { .. } |
+            Command::RawIncrement { .. } |
             Command::DeleteRange { .. } |
             Command::Pause { .. } |
             Command::MvccByKey { .. } => 0,
@@ -398,14 +704,29 @@ impl Command {
             Command::BatchGet { ref ctx, .. } |
             Command::Scan { ref ctx, .. } |
             Command::Prewrite { ref ctx, .. } |
+            Command::AcquirePessimisticLock { ref ctx, .. } |
             Command::Commit { ref ctx, .. } |
             Command::Cleanup { ref ctx, .. } |
+            Command::TxnHeartBeat { ref ctx, .. } |
+            Command::CheckTxnStatus { ref ctx, .. } |
+            Command::CheckSecondaryLocks { ref ctx, .. } |
             Command::Rollback { ref ctx, .. } |
+            Command::PessimisticRollback { ref ctx, .. } |
             Command::ScanLock { ref ctx, .. } |
             Command::ResolveLock { ref ctx, .. } |
             Command::Gc { ref ctx, .. } |
             Command::RawGet { ref ctx, .. } |
+            Command::RawBatchGet { ref ctx, .. } |
+            Command::RawGetKeyTTL { ref ctx, .. } |
             Command::RawScan { ref ctx, .. } |
+            Command::RawBatchScan { ref ctx, .. } |
+            Command::RawPut { ref ctx, .. } |
+            Command::RawBatchPut { ref ctx, .. } |
+            Command::RawDelete { ref ctx, .. } |
+            Command::RawBatchDelete { ref ctx, .. } |
+            Command::RawDeleteRange { ref ctx, .. } |
+            Command::RawCompareAndSwap { ref ctx, .. } |
+            Command::RawIncrement { ref ctx, .. } |
             Command::DeleteRange { ref ctx, .. } |
             Command::Pause { ref ctx, .. } |
             Command::MvccByKey { ref ctx, .. } |
@@ -413,20 +734,51 @@ impl Command {
         }
     }
 
+    /// Returns the first key a read command accesses, it's sampled for splitting the hot regions.
+    pub fn read_key(&self) -> Option<&Key> {
+        match *self {
+            Command::Get { ref key, .. } |
+            Command::RawGet { ref key, .. } |
+            Command::RawGetKeyTTL { ref key, .. } => Some(key),
+            Command::BatchGet { ref keys, .. } | Command::RawBatchGet { ref keys, .. } => {
+                keys.first()
+            }
+            Command::Scan { ref start_key, .. } | Command::RawScan { ref start_key, .. } => {
+                Some(start_key)
+            }
+            _ => None,
+        }
+    }
+
     pub fn mut_context(&mut self) -> &mut Context {
         match *self {
             Command::Get { ref mut ctx, .. } |
             Command::BatchGet { ref mut ctx, .. } |
             Command::Scan { ref mut ctx, .. } |
             Command::Prewrite { ref mut ctx, .. } |
+            Command::AcquirePessimisticLock { ref mut ctx, .. } |
             Command::Commit { ref mut ctx, .. } |
             Command::Cleanup { ref mut ctx, .. } |
+            Command::TxnHeartBeat { ref mut ctx, .. } |
+            Command::CheckTxnStatus { ref mut ctx, .. } |
+            Command::CheckSecondaryLocks { ref mut ctx, .. } |
             Command::Rollback { ref mut ctx, .. } |
+            Command::PessimisticRollback { ref mut ctx, .. } |
             Command::ScanLock { ref mut ctx, .. } |
             Command::ResolveLock { ref mut ctx, .. } |
             Command::Gc { ref mut ctx, .. } |
             Command::RawGet { ref mut ctx, .. } |
+            Command::RawBatchGet { ref mut ctx, .. } |
+            Command::RawGetKeyTTL { ref mut ctx, .. } |
             Command::RawScan { ref mut ctx, .. } |
+            Command::RawBatchScan { ref mut ctx, .. } |
+            Command::RawPut { ref mut ctx, .. } |
+            Command::RawBatchPut { ref mut ctx, .. } |
+            Command::RawDelete { ref mut ctx, .. } |
+            Command::RawBatchDelete { ref mut ctx, .. } |
+            Command::RawDeleteRange { ref mut ctx, .. } |
+            Command::RawCompareAndSwap { ref mut ctx, .. } |
+            Command::RawIncrement { ref mut ctx, .. } |
             Command::DeleteRange { ref mut ctx, .. } |
             Command::Pause { ref mut ctx, .. } |
             Command::MvccByKey { ref mut ctx, .. } |
@@ -436,12 +788,31 @@ impl Command {
 }
 
 use util::transport::SyncSendCh;
+use util::worker::FutureScheduler;
+use server::deadlock::Task as DetectorTask;
 
 #[derive(Clone, Default)]
 pub struct Options {
     pub lock_ttl: u64,
     pub skip_constraint_check: bool,
     pub key_only: bool,
+    pub reverse_scan: bool,
+    // Set by pessimistic transactions only, 0 means the transaction is optimistic.
+    pub for_update_ts: u64,
+    // For pessimistic prewrite, whether each mutation's key is locked by a pessimistic lock.
+    pub is_pessimistic_lock: Vec<bool>,
+    // How long a pessimistic lock request waits for a conflicting lock, in milliseconds.
+    // Negative means not to wait, 0 means to use the default timeout in the config.
+    pub wait_timeout: i64,
+    // Whether the prewrite is of an async-commit transaction, whose primary lock records
+    // the raw keys of `secondary_keys`.
+    pub use_async_commit: bool,
+    pub secondary_keys: Vec<Vec<u8>>,
+    // The lower bound of `min_commit_ts` of an async-commit prewrite.
+    pub min_commit_ts: u64,
+    // Whether to commit the transaction by the prewrite directly. It's only set when all the
+    // mutations are in the same region, and the prewrite falls back to 2PC if it can't.
+    pub try_one_pc: bool,
 }
 
 impl Options {
@@ -450,10 +821,29 @@ impl Options {
             lock_ttl: lock_ttl,
             skip_constraint_check: skip_constraint_check,
             key_only: key_only,
+            reverse_scan: false,
+            for_update_ts: 0,
+            is_pessimistic_lock: vec![],
+            wait_timeout: 0,
+            use_async_commit: false,
+            secondary_keys: vec![],
+            min_commit_ts: 0,
+            try_one_pc: false,
         }
     }
 }
 
+/// The result of a prewrite command.
+#[derive(Debug, Default)]
+pub struct PrewriteResult {
+    // The errors of the mutations, it's empty if the prewrite succeeds.
+    pub locks: Vec<Result<()>>,
+    // The `min_commit_ts` of the locks of an async-commit prewrite, 0 for other prewrites.
+    pub min_commit_ts: u64,
+    // The commit ts if the transaction is committed by 1PC, otherwise 0.
+    pub one_pc_commit_ts: u64,
+}
+
 struct StorageHandle {
     handle: Option<thread::JoinHandle<()>>,
     receiver: Option<Receiver<Msg>>,
@@ -463,9 +853,13 @@ pub struct Storage {
     engine: Box<Engine>,
     sendch: SyncSendCh<Msg>,
     handle: Arc<Mutex<StorageHandle>>,
+    concurrency_manager: Arc<ConcurrencyManager>,
+    detector_scheduler: Option<FutureScheduler<DetectorTask>>,
+    load_report_interval: Option<Duration>,
 
     // Storage configurations.
     gc_ratio_threshold: f64,
+    enable_ttl: bool,
 }
 
 impl Storage {
@@ -481,7 +875,11 @@ impl Storage {
                 handle: None,
                 receiver: Some(rx),
             })),
+            concurrency_manager: Arc::new(ConcurrencyManager::new()),
+            detector_scheduler: None,
+            load_report_interval: None,
             gc_ratio_threshold: config.gc_ratio_threshold,
+            enable_ttl: config.enable_ttl,
         })
     }
 
@@ -502,14 +900,24 @@ impl Storage {
         let sched_concurrency = config.scheduler_concurrency;
         let sched_worker_pool_size = config.scheduler_worker_pool_size;
         let sched_too_busy_threshold = config.scheduler_too_busy_threshold;
+        let wait_for_lock_timeout = config.wait_for_lock_timeout.0;
+        let enable_ttl = config.enable_ttl;
         let ch = self.sendch.clone();
+        let concurrency_manager = self.concurrency_manager.clone();
+        let detector_scheduler = self.detector_scheduler.clone();
+        let load_report_interval = self.load_report_interval;
         let h = try!(builder.spawn(move || {
             let mut sched = Scheduler::new(
                 engine,
                 ch,
+                concurrency_manager,
                 sched_concurrency,
                 sched_worker_pool_size,
                 sched_too_busy_threshold,
+                wait_for_lock_timeout,
+                detector_scheduler,
+                enable_ttl,
+                load_report_interval,
             );
             if let Err(e) = sched.run(rx) {
                 panic!("scheduler run err:{:?}", e);
@@ -541,22 +949,91 @@ impl Storage {
         Ok(())
     }
 
+    /// Detects the deadlocks with the detector of the cluster instead of the local wait-for
+    /// graph, it must be set before the storage starts.
+    pub fn set_deadlock_detector(&mut self, scheduler: FutureScheduler<DetectorTask>) {
+        self.detector_scheduler = Some(scheduler);
+    }
+
+    /// Reports the load of the reads to the engine at the interval, so the regions can be
+    /// split by their load. It must be set before the storage starts.
+    pub fn set_load_report_interval(&mut self, interval: Duration) {
+        self.load_report_interval = Some(interval);
+    }
+
+    /// Returns the channel of the scheduler, the deadlock detector reports the deadlocks to it.
+    pub fn get_sched_ch(&self) -> SyncSendCh<Msg> {
+        self.sendch.clone()
+    }
+
     pub fn get_engine(&self) -> Box<Engine> {
         self.engine.clone()
     }
 
+    pub fn get_concurrency_manager(&self) -> Arc<ConcurrencyManager> {
+        self.concurrency_manager.clone()
+    }
+
     fn send(&self, cmd: Command, cb: StorageCb) -> Result<()> {
         box_try!(self.sendch.try_send(Msg::RawCmd { cmd: cmd, cb: cb }));
         Ok(())
     }
 
+    /// Bumps the max read ts before the snapshot is taken, so async-commit prewrites that are
+    /// not written yet either block the read or get a larger `min_commit_ts`.
+    fn read_check(&self, ctx: &Context, keys: &[&Key], start_ts: u64) -> Result<()> {
+        if ctx.get_isolation_level() == IsolationLevel::RC {
+            self.concurrency_manager.update_max_read_ts(start_ts);
+            return Ok(());
+        }
+        for key in keys {
+            if let Err(e) = self.concurrency_manager.read_key_check(key, start_ts) {
+                return Err(Error::from(txn::Error::from(e)));
+            }
+        }
+        Ok(())
+    }
+
+    fn scan_check(
+        &self,
+        ctx: &Context,
+        start_key: &Key,
+        end_key: Option<&Key>,
+        start_ts: u64,
+        options: &Options,
+    ) -> Result<()> {
+        if ctx.get_isolation_level() == IsolationLevel::RC {
+            self.concurrency_manager.update_max_read_ts(start_ts);
+            return Ok(());
+        }
+        let res = if options.reverse_scan {
+            // An empty start key of a reverse scan means the end of the region.
+            let upper = if start_key.encoded().is_empty() {
+                None
+            } else {
+                Some(start_key)
+            };
+            self.concurrency_manager
+                .read_range_check(end_key, upper, start_ts)
+        } else {
+            self.concurrency_manager
+                .read_range_check(Some(start_key), end_key, start_ts)
+        };
+        res.map_err(|e| Error::from(txn::Error::from(e)))
+    }
+
     pub fn async_get(
         &self,
-        ctx: Context,
+        mut ctx: Context,
         key: Key,
         start_ts: u64,
         callback: Callback<Option<Value>>,
     ) -> Result<()> {
+        fill_read_ts(&mut ctx, start_ts);
+        if let Err(e) = self.read_check(&ctx, &[&key], start_ts) {
+            callback(Err(e));
+            return Ok(());
+        }
         let cmd = Command::Get {
             ctx: ctx,
             key: key,
@@ -570,11 +1047,20 @@ impl Storage {
 
     pub fn async_batch_get(
         &self,
-        ctx: Context,
+        mut ctx: Context,
         keys: Vec<Key>,
         start_ts: u64,
         callback: Callback<Vec<Result<KvPair>>>,
     ) -> Result<()> {
+        fill_read_ts(&mut ctx, start_ts);
+        let res = {
+            let key_refs: Vec<&Key> = keys.iter().collect();
+            self.read_check(&ctx, &key_refs, start_ts)
+        };
+        if let Err(e) = res {
+            callback(Err(e));
+            return Ok(());
+        }
         let cmd = Command::BatchGet {
             ctx: ctx,
             keys: keys,
@@ -586,18 +1072,29 @@ impl Storage {
         Ok(())
     }
 
+    /// Scans at most `limit` keys in [`start_key`, `end_key`). If `options.reverse_scan` is set,
+    /// the keys in [`end_key`, `start_key`) are returned in descending order instead, and an
+    /// empty `start_key` means scanning from the end of the region. A `None` `end_key` means
+    /// scanning to the region boundary.
     pub fn async_scan(
         &self,
-        ctx: Context,
+        mut ctx: Context,
         start_key: Key,
+        end_key: Option<Key>,
         limit: usize,
         start_ts: u64,
         options: Options,
         callback: Callback<Vec<Result<KvPair>>>,
     ) -> Result<()> {
+        fill_read_ts(&mut ctx, start_ts);
+        if let Err(e) = self.scan_check(&ctx, &start_key, end_key.as_ref(), start_ts, &options) {
+            callback(Err(e));
+            return Ok(());
+        }
         let cmd = Command::Scan {
             ctx: ctx,
             start_key: start_key,
+            end_key: end_key,
             limit: limit,
             start_ts: start_ts,
             options: options,
@@ -624,7 +1121,7 @@ impl Storage {
         primary: Vec<u8>,
         start_ts: u64,
         options: Options,
-        callback: Callback<Vec<Result<()>>>,
+        callback: Callback<PrewriteResult>,
     ) -> Result<()> {
         let cmd = Command::Prewrite {
             ctx: ctx,
@@ -634,6 +1131,28 @@ impl Storage {
             options: options,
         };
         let tag = cmd.tag();
+        try!(self.send(cmd, StorageCb::Prewrite(callback)));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
+    pub fn async_acquire_pessimistic_lock(
+        &self,
+        ctx: Context,
+        keys: Vec<Key>,
+        primary: Vec<u8>,
+        start_ts: u64,
+        options: Options,
+        callback: Callback<Vec<Result<()>>>,
+    ) -> Result<()> {
+        let cmd = Command::AcquirePessimisticLock {
+            ctx: ctx,
+            keys: keys,
+            primary: primary,
+            start_ts: start_ts,
+            options: options,
+        };
+        let tag = cmd.tag();
         try!(self.send(cmd, StorageCb::Booleans(callback)));
         KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
         Ok(())
@@ -692,17 +1211,21 @@ impl Storage {
         Ok(())
     }
 
+    /// Rolls back the transaction by its primary key, unless it's committed or its lock is
+    /// still alive at `current_ts`. A `current_ts` of 0 rolls back the alive lock too.
     pub fn async_cleanup(
         &self,
         ctx: Context,
         key: Key,
         start_ts: u64,
+        current_ts: u64,
         callback: Callback<()>,
     ) -> Result<()> {
         let cmd = Command::Cleanup {
             ctx: ctx,
             key: key,
             start_ts: start_ts,
+            current_ts: current_ts,
         };
         let tag = cmd.tag();
         try!(self.send(cmd, StorageCb::Boolean(callback)));
@@ -710,67 +1233,81 @@ impl Storage {
         Ok(())
     }
 
-    pub fn async_rollback(
+    /// Extends the ttl of the primary lock to `advise_ttl` if it's larger, so that a long
+    /// running transaction won't be rolled back by others.
+    pub fn async_txn_heart_beat(
         &self,
         ctx: Context,
-        keys: Vec<Key>,
+        primary_key: Key,
         start_ts: u64,
-        callback: Callback<()>,
+        advise_ttl: u64,
+        callback: Callback<TxnStatus>,
     ) -> Result<()> {
-        let cmd = Command::Rollback {
+        let cmd = Command::TxnHeartBeat {
             ctx: ctx,
-            keys: keys,
+            primary_key: primary_key,
             start_ts: start_ts,
+            advise_ttl: advise_ttl,
         };
         let tag = cmd.tag();
-        try!(self.send(cmd, StorageCb::Boolean(callback)));
+        try!(self.send(cmd, StorageCb::TxnStatus(callback)));
         KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
         Ok(())
     }
 
-    pub fn async_scan_lock(
+    /// Checks the status of the transaction by its primary key, and rolls it back if the
+    /// lock is expired at `current_ts`.
+    pub fn async_check_txn_status(
         &self,
         ctx: Context,
-        max_ts: u64,
-        callback: Callback<Vec<LockInfo>>,
+        primary_key: Key,
+        lock_ts: u64,
+        current_ts: u64,
+        callback: Callback<TxnStatus>,
     ) -> Result<()> {
-        let cmd = Command::ScanLock {
+        let cmd = Command::CheckTxnStatus {
             ctx: ctx,
-            max_ts: max_ts,
+            primary_key: primary_key,
+            lock_ts: lock_ts,
+            current_ts: current_ts,
         };
         let tag = cmd.tag();
-        try!(self.send(cmd, StorageCb::Locks(callback)));
+        try!(self.send(cmd, StorageCb::TxnStatus(callback)));
         KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
         Ok(())
     }
 
-    pub fn async_resolve_lock(
+    /// Checks the locks of an async-commit transaction on `keys`, and rolls back the keys
+    /// that are not locked yet, see `MvccTxn::check_secondary_locks`.
+    pub fn async_check_secondary_locks(
         &self,
         ctx: Context,
+        keys: Vec<Key>,
         start_ts: u64,
-        commit_ts: Option<u64>,
-        callback: Callback<()>,
+        callback: Callback<SecondaryLocksStatus>,
     ) -> Result<()> {
-        let cmd = Command::ResolveLock {
+        let cmd = Command::CheckSecondaryLocks {
             ctx: ctx,
+            keys: keys,
             start_ts: start_ts,
-            commit_ts: commit_ts,
-            scan_key: None,
-            keys: vec![],
         };
         let tag = cmd.tag();
-        try!(self.send(cmd, StorageCb::Boolean(callback)));
+        try!(self.send(cmd, StorageCb::SecondaryLocksStatus(callback)));
         KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
         Ok(())
     }
 
-    pub fn async_gc(&self, ctx: Context, safe_point: u64, callback: Callback<()>) -> Result<()> {
-        let cmd = Command::Gc {
+    pub fn async_rollback(
+        &self,
+        ctx: Context,
+        keys: Vec<Key>,
+        start_ts: u64,
+        callback: Callback<()>,
+    ) -> Result<()> {
+        let cmd = Command::Rollback {
             ctx: ctx,
-            safe_point: safe_point,
-            ratio_threshold: self.gc_ratio_threshold,
-            scan_key: None,
-            keys: vec![],
+            keys: keys,
+            start_ts: start_ts,
         };
         let tag = cmd.tag();
         try!(self.send(cmd, StorageCb::Boolean(callback)));
@@ -778,14 +1315,106 @@ impl Storage {
         Ok(())
     }
 
-    pub fn async_raw_get(
+    pub fn async_pessimistic_rollback(
         &self,
         ctx: Context,
-        key: Vec<u8>,
-        callback: Callback<Option<Vec<u8>>>,
+        keys: Vec<Key>,
+        start_ts: u64,
+        for_update_ts: u64,
+        callback: Callback<()>,
     ) -> Result<()> {
+        let cmd = Command::PessimisticRollback {
+            ctx: ctx,
+            keys: keys,
+            start_ts: start_ts,
+            for_update_ts: for_update_ts,
+        };
+        let tag = cmd.tag();
+        try!(self.send(cmd, StorageCb::Boolean(callback)));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
+    pub fn async_scan_lock(
+        &self,
+        ctx: Context,
+        max_ts: u64,
+        callback: Callback<Vec<LockInfo>>,
+    ) -> Result<()> {
+        let cmd = Command::ScanLock {
+            ctx: ctx,
+            max_ts: max_ts,
+        };
+        let tag = cmd.tag();
+        try!(self.send(cmd, StorageCb::Locks(callback)));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
+    pub fn async_resolve_lock(
+        &self,
+        ctx: Context,
+        start_ts: u64,
+        commit_ts: Option<u64>,
+        callback: Callback<()>,
+    ) -> Result<()> {
+        let cmd = Command::ResolveLock {
+            ctx: ctx,
+            start_ts: start_ts,
+            commit_ts: commit_ts,
+            scan_key: None,
+            keys: vec![],
+        };
+        let tag = cmd.tag();
+        try!(self.send(cmd, StorageCb::Boolean(callback)));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
+    pub fn async_gc(&self, ctx: Context, safe_point: u64, callback: Callback<()>) -> Result<()> {
+        let cmd = Command::Gc {
+            ctx: ctx,
+            safe_point: safe_point,
+            ratio_threshold: self.gc_ratio_threshold,
+            scan_key: None,
+            keys: vec![],
+        };
+        let tag = cmd.tag();
+        try!(self.send(cmd, StorageCb::Boolean(callback)));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
+    /// Returns the column family of raw requests, an empty name means the default one.
+    fn rawkv_cf(cf: &str) -> Result<CfName> {
+        if cf.is_empty() {
+            return Ok(CF_DEFAULT);
+        }
+        for c in DATA_CFS {
+            if cf == *c {
+                return Ok(*c);
+            }
+        }
+        Err(Error::InvalidCf(cf.to_owned()))
+    }
+
+    pub fn async_raw_get(
+        &self,
+        ctx: Context,
+        cf: String,
+        key: Vec<u8>,
+        callback: Callback<Option<Vec<u8>>>,
+    ) -> Result<()> {
+        let cf = match Storage::rawkv_cf(&cf) {
+            Ok(cf) => cf,
+            Err(e) => {
+                callback(Err(e));
+                return Ok(());
+            }
+        };
         let cmd = Command::RawGet {
             ctx: ctx,
+            cf: cf,
             key: Key::from_encoded(key),
         };
         try!(self.send(cmd, StorageCb::SingleValue(callback)));
@@ -793,57 +1422,369 @@ impl Storage {
         Ok(())
     }
 
+    /// Gets the raw keys, keys which are not found or expired are absent from the result.
+    pub fn async_raw_batch_get(
+        &self,
+        ctx: Context,
+        cf: String,
+        keys: Vec<Vec<u8>>,
+        callback: Callback<Vec<Result<KvPair>>>,
+    ) -> Result<()> {
+        let cf = match Storage::rawkv_cf(&cf) {
+            Ok(cf) => cf,
+            Err(e) => {
+                callback(Err(e));
+                return Ok(());
+            }
+        };
+        let cmd = Command::RawBatchGet {
+            ctx: ctx,
+            cf: cf,
+            keys: keys.into_iter().map(Key::from_encoded).collect(),
+        };
+        try!(self.send(cmd, StorageCb::KvPairs(callback)));
+        RAWKV_COMMAND_COUNTER_VEC
+            .with_label_values(&["batch_get"])
+            .inc();
+        Ok(())
+    }
+
+    pub fn async_raw_get_key_ttl(
+        &self,
+        ctx: Context,
+        cf: String,
+        key: Vec<u8>,
+        callback: Callback<Option<u64>>,
+    ) -> Result<()> {
+        let cf = match Storage::rawkv_cf(&cf) {
+            Ok(cf) => cf,
+            Err(e) => {
+                callback(Err(e));
+                return Ok(());
+            }
+        };
+        let cmd = Command::RawGetKeyTTL {
+            ctx: ctx,
+            cf: cf,
+            key: Key::from_encoded(key),
+        };
+        try!(self.send(cmd, StorageCb::TTL(callback)));
+        RAWKV_COMMAND_COUNTER_VEC
+            .with_label_values(&["get_key_ttl"])
+            .inc();
+        Ok(())
+    }
+
+    /// Puts a raw key, which expires after `ttl` seconds. 0 means it never expires.
     pub fn async_raw_put(
         &self,
         ctx: Context,
+        cf: String,
         key: Vec<u8>,
-        value: Vec<u8>,
+        mut value: Vec<u8>,
+        ttl: u64,
         callback: Callback<()>,
     ) -> Result<()> {
-        try!(self.engine
-            .async_write(&ctx,
-                         vec![Modify::Put(CF_DEFAULT, Key::from_encoded(key), value)],
-                         box |(_, res): (_, engine::Result<_>)| {
-                             callback(res.map_err(Error::from))
-                         }));
+        let cf = match Storage::rawkv_cf(&cf) {
+            Ok(cf) => cf,
+            Err(e) => {
+                callback(Err(e));
+                return Ok(());
+            }
+        };
+        if self.enable_ttl {
+            ttl::append_expire_ts(&mut value, ttl::ttl_to_expire_ts(ttl));
+        } else if ttl != 0 {
+            callback(Err(box_err!("ttl is not enabled")));
+            return Ok(());
+        }
+        let cmd = Command::RawPut {
+            ctx: ctx,
+            cf: cf,
+            key: Key::from_encoded(key),
+            value: value,
+        };
+        try!(self.send(cmd, StorageCb::Boolean(callback)));
         RAWKV_COMMAND_COUNTER_VEC.with_label_values(&["put"]).inc();
         Ok(())
     }
 
+    /// Puts the raw pairs atomically, the keys never expire.
+    pub fn async_raw_batch_put(
+        &self,
+        ctx: Context,
+        cf: String,
+        pairs: Vec<KvPair>,
+        callback: Callback<()>,
+    ) -> Result<()> {
+        let cf = match Storage::rawkv_cf(&cf) {
+            Ok(cf) => cf,
+            Err(e) => {
+                callback(Err(e));
+                return Ok(());
+            }
+        };
+        let enable_ttl = self.enable_ttl;
+        let pairs = pairs
+            .into_iter()
+            .map(|(key, mut value)| {
+                if enable_ttl {
+                    ttl::append_expire_ts(&mut value, 0);
+                }
+                (Key::from_encoded(key), value)
+            })
+            .collect();
+        let cmd = Command::RawBatchPut {
+            ctx: ctx,
+            cf: cf,
+            pairs: pairs,
+        };
+        try!(self.send(cmd, StorageCb::Boolean(callback)));
+        RAWKV_COMMAND_COUNTER_VEC
+            .with_label_values(&["batch_put"])
+            .inc();
+        Ok(())
+    }
+
     pub fn async_raw_delete(
         &self,
         ctx: Context,
+        cf: String,
         key: Vec<u8>,
         callback: Callback<()>,
     ) -> Result<()> {
-        try!(self.engine.async_write(
-            &ctx,
-            vec![Modify::Delete(CF_DEFAULT, Key::from_encoded(key))],
-            box |(_, res): (_, engine::Result<_>)| { callback(res.map_err(Error::from)) }
-        ));
+        let cf = match Storage::rawkv_cf(&cf) {
+            Ok(cf) => cf,
+            Err(e) => {
+                callback(Err(e));
+                return Ok(());
+            }
+        };
+        let cmd = Command::RawDelete {
+            ctx: ctx,
+            cf: cf,
+            key: Key::from_encoded(key),
+        };
+        try!(self.send(cmd, StorageCb::Boolean(callback)));
         RAWKV_COMMAND_COUNTER_VEC
             .with_label_values(&["delete"])
             .inc();
         Ok(())
     }
 
+    /// Deletes the raw keys atomically.
+    pub fn async_raw_batch_delete(
+        &self,
+        ctx: Context,
+        cf: String,
+        keys: Vec<Vec<u8>>,
+        callback: Callback<()>,
+    ) -> Result<()> {
+        let cf = match Storage::rawkv_cf(&cf) {
+            Ok(cf) => cf,
+            Err(e) => {
+                callback(Err(e));
+                return Ok(());
+            }
+        };
+        let cmd = Command::RawBatchDelete {
+            ctx: ctx,
+            cf: cf,
+            keys: keys.into_iter().map(Key::from_encoded).collect(),
+        };
+        try!(self.send(cmd, StorageCb::Boolean(callback)));
+        RAWKV_COMMAND_COUNTER_VEC
+            .with_label_values(&["batch_delete"])
+            .inc();
+        Ok(())
+    }
+
+    /// Deletes the raw keys in [`start_key`, `end_key`), an empty `end_key` means the end of
+    /// the region. It takes no latches, so a compare-and-swap or increment racing with it may
+    /// write its key back after the range is deleted.
+    pub fn async_raw_delete_range(
+        &self,
+        ctx: Context,
+        cf: String,
+        start_key: Vec<u8>,
+        end_key: Vec<u8>,
+        callback: Callback<()>,
+    ) -> Result<()> {
+        let cf = match Storage::rawkv_cf(&cf) {
+            Ok(cf) => cf,
+            Err(e) => {
+                callback(Err(e));
+                return Ok(());
+            }
+        };
+        if !end_key.is_empty() && start_key >= end_key {
+            callback(Err(box_err!(
+                "invalid delete range, start_key: {:?}, end_key: {:?}",
+                start_key,
+                end_key
+            )));
+            return Ok(());
+        }
+        let cmd = Command::RawDeleteRange {
+            ctx: ctx,
+            cf: cf,
+            start_key: Key::from_encoded(start_key),
+            end_key: Key::from_encoded(end_key),
+        };
+        try!(self.send(cmd, StorageCb::Boolean(callback)));
+        RAWKV_COMMAND_COUNTER_VEC
+            .with_label_values(&["delete_range"])
+            .inc();
+        Ok(())
+    }
+
+    /// Puts `value` if the current value of the key equals `previous_value`, `None` means the
+    /// key doesn't exist. Returns the current value and whether the swap succeeded.
+    #[allow(too_many_arguments)]
+    pub fn async_raw_compare_and_swap(
+        &self,
+        ctx: Context,
+        cf: String,
+        key: Vec<u8>,
+        previous_value: Option<Vec<u8>>,
+        mut value: Vec<u8>,
+        ttl: u64,
+        callback: Callback<(Option<Value>, bool)>,
+    ) -> Result<()> {
+        let cf = match Storage::rawkv_cf(&cf) {
+            Ok(cf) => cf,
+            Err(e) => {
+                callback(Err(e));
+                return Ok(());
+            }
+        };
+        if self.enable_ttl {
+            ttl::append_expire_ts(&mut value, ttl::ttl_to_expire_ts(ttl));
+        } else if ttl != 0 {
+            callback(Err(box_err!("ttl is not enabled")));
+            return Ok(());
+        }
+        let cmd = Command::RawCompareAndSwap {
+            ctx: ctx,
+            cf: cf,
+            key: Key::from_encoded(key),
+            previous_value: previous_value,
+            value: value,
+        };
+        try!(self.send(cmd, StorageCb::CompareAndSwap(callback)));
+        RAWKV_COMMAND_COUNTER_VEC
+            .with_label_values(&["compare_and_swap"])
+            .inc();
+        Ok(())
+    }
+
+    /// Adds `delta` to the counter of the key and returns the new value. The counter is an
+    /// 8-byte big-endian signed integer, and a missing key is treated as 0.
+    pub fn async_raw_increment(
+        &self,
+        ctx: Context,
+        cf: String,
+        key: Vec<u8>,
+        delta: i64,
+        callback: Callback<i64>,
+    ) -> Result<()> {
+        let cf = match Storage::rawkv_cf(&cf) {
+            Ok(cf) => cf,
+            Err(e) => {
+                callback(Err(e));
+                return Ok(());
+            }
+        };
+        let cmd = Command::RawIncrement {
+            ctx: ctx,
+            cf: cf,
+            key: Key::from_encoded(key),
+            delta: delta,
+        };
+        try!(self.send(cmd, StorageCb::Counter(callback)));
+        RAWKV_COMMAND_COUNTER_VEC
+            .with_label_values(&["increment"])
+            .inc();
+        Ok(())
+    }
+
+    /// Scans at most `limit` raw keys in [`key`, `end_key`). If `reverse` is set, the keys in
+    /// [`end_key`, `key`) are scanned backward instead, and an empty `key` means the end of the
+    /// region. Values are left empty if `key_only` is set.
+    #[allow(too_many_arguments)]
     pub fn async_raw_scan(
         &self,
         ctx: Context,
+        cf: String,
         key: Vec<u8>,
+        end_key: Option<Vec<u8>>,
         limit: usize,
+        key_only: bool,
+        reverse: bool,
         callback: Callback<Vec<Result<KvPair>>>,
     ) -> Result<()> {
+        let cf = match Storage::rawkv_cf(&cf) {
+            Ok(cf) => cf,
+            Err(e) => {
+                callback(Err(e));
+                return Ok(());
+            }
+        };
         let cmd = Command::RawScan {
             ctx: ctx,
+            cf: cf,
             start_key: Key::from_encoded(key),
+            end_key: end_key.map(Key::from_encoded),
             limit: limit,
+            key_only: key_only,
+            reverse: reverse,
         };
         try!(self.send(cmd, StorageCb::KvPairs(callback)));
         RAWKV_COMMAND_COUNTER_VEC.with_label_values(&["scan"]).inc();
         Ok(())
     }
 
+    /// Scans the ranges in one snapshot like `async_raw_scan`, with at most `each_limit` keys
+    /// per range. The pairs of all the ranges are returned in the order of the ranges.
+    #[allow(too_many_arguments)]
+    pub fn async_raw_batch_scan(
+        &self,
+        ctx: Context,
+        cf: String,
+        ranges: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+        each_limit: usize,
+        key_only: bool,
+        reverse: bool,
+        callback: Callback<Vec<Result<KvPair>>>,
+    ) -> Result<()> {
+        let cf = match Storage::rawkv_cf(&cf) {
+            Ok(cf) => cf,
+            Err(e) => {
+                callback(Err(e));
+                return Ok(());
+            }
+        };
+        let ranges = ranges
+            .into_iter()
+            .map(|(start_key, end_key)| {
+                (Key::from_encoded(start_key), end_key.map(Key::from_encoded))
+            })
+            .collect();
+        let cmd = Command::RawBatchScan {
+            ctx: ctx,
+            cf: cf,
+            ranges: ranges,
+            each_limit: each_limit,
+            key_only: key_only,
+            reverse: reverse,
+        };
+        try!(self.send(cmd, StorageCb::KvPairs(callback)));
+        RAWKV_COMMAND_COUNTER_VEC
+            .with_label_values(&["batch_scan"])
+            .inc();
+        Ok(())
+    }
+
     pub fn async_mvcc_by_key(
         &self,
         ctx: Context,
@@ -880,7 +1821,11 @@ impl Clone for Storage {
             engine: self.engine.clone(),
             sendch: self.sendch.clone(),
             handle: self.handle.clone(),
+            concurrency_manager: self.concurrency_manager.clone(),
+            detector_scheduler: self.detector_scheduler.clone(),
+            load_report_interval: self.load_report_interval,
             gc_ratio_threshold: self.gc_ratio_threshold,
+            enable_ttl: self.enable_ttl,
         }
     }
 }
@@ -919,11 +1864,23 @@ quick_error! {
         SchedTooBusy {
             description("scheduler is too busy")
         }
+        InvalidCf(cf: String) {
+            description("invalid cf name")
+            display("invalid cf name: {}", cf)
+        }
     }
 }
 
 pub type Result<T> = ::std::result::Result<T, Error>;
 
+/// A stale read can be served by any replica whose safe ts is at least `start_ts`, and the
+/// leader updates its max read ts with the `start_ts` of a replica read.
+pub fn fill_read_ts(ctx: &mut Context, start_ts: u64) {
+    if ctx.get_stale_read() || ctx.get_replica_read() {
+        ctx.set_read_ts(start_ts);
+    }
+}
+
 pub fn get_tag_from_header(header: &errorpb::Error) -> &'static str {
     if header.has_not_leader() {
         "not_leader"
@@ -935,6 +1892,10 @@ pub fn get_tag_from_header(header: &errorpb::Error) -> &'static str {
         "stale_epoch"
     } else if header.has_server_is_busy() {
         "server_is_busy"
+    } else if header.has_data_is_not_ready() {
+        "data_is_not_ready"
+    } else if header.has_read_index_not_ready() {
+        "read_index_not_ready"
     } else {
         "other"
     }
@@ -944,6 +1905,7 @@ pub fn get_tag_from_header(header: &errorpb::Error) -> &'static str {
 mod tests {
     use super::*;
     use std::sync::mpsc::{channel, Sender};
+    use std::time::Duration;
     use kvproto::kvrpcpb::Context;
 
     fn expect_get_none(done: Sender<i32>, id: i32) -> Callback<Option<Value>> {
@@ -997,6 +1959,27 @@ mod tests {
         })
     }
 
+    fn expect_key_errors(
+        done: Sender<i32>,
+        count: usize,
+        id: i32,
+    ) -> Callback<Vec<Result<()>>> {
+        Box::new(move |x: Result<Vec<Result<()>>>| {
+            assert_eq!(x.unwrap().iter().filter(|r| r.is_err()).count(), count);
+            done.send(id).unwrap();
+        })
+    }
+
+    fn expect_deadlock(done: Sender<i32>, id: i32) -> Callback<Vec<Result<()>>> {
+        Box::new(move |x: Result<Vec<Result<()>>>| {
+            match x.unwrap().pop() {
+                Some(Err(Error::Txn(txn::Error::Mvcc(mvcc::Error::Deadlock { .. })))) => {}
+                res => panic!("expect deadlock, got {:?}", res),
+            }
+            done.send(id).unwrap();
+        })
+    }
+
     fn expect_batch_get_vals(
         done: Sender<i32>,
         pairs: Vec<Option<KvPair>>,
@@ -1127,6 +2110,7 @@ mod tests {
             .async_scan(
                 Context::new(),
                 make_key(b"\x00"),
+                None,
                 1000,
                 5,
                 Options::default(),
@@ -1142,6 +2126,60 @@ mod tests {
             )
             .unwrap();
         rx.recv().unwrap();
+        storage
+            .async_scan(
+                Context::new(),
+                make_key(b"\x00"),
+                Some(make_key(b"c")),
+                1000,
+                5,
+                Options::default(),
+                expect_scan(
+                    tx.clone(),
+                    vec![
+                        Some((b"a".to_vec(), b"aa".to_vec())),
+                        Some((b"b".to_vec(), b"bb".to_vec())),
+                    ],
+                    3,
+                ),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        let mut options = Options::default();
+        options.reverse_scan = true;
+        storage
+            .async_scan(
+                Context::new(),
+                Key::from_encoded(vec![]),
+                None,
+                1000,
+                5,
+                options.clone(),
+                expect_scan(
+                    tx.clone(),
+                    vec![
+                        Some((b"c".to_vec(), b"cc".to_vec())),
+                        Some((b"b".to_vec(), b"bb".to_vec())),
+                        Some((b"a".to_vec(), b"aa".to_vec())),
+                    ],
+                    4,
+                ),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        options.key_only = true;
+        storage
+            .async_scan(
+                Context::new(),
+                make_key(b"c"),
+                Some(make_key(b"b")),
+                1000,
+                5,
+                options,
+                expect_scan(tx.clone(), vec![Some((b"b".to_vec(), vec![]))], 5),
+            )
+            .unwrap();
+        rx.recv().unwrap();
         storage.stop().unwrap();
     }
 
@@ -1276,6 +2314,183 @@ mod tests {
         storage.stop().unwrap();
     }
 
+    #[test]
+    fn test_pessimistic_txn() {
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        let mut options = Options::default();
+        options.for_update_ts = 100;
+        storage
+            .async_acquire_pessimistic_lock(
+                Context::new(),
+                vec![make_key(b"x"), make_key(b"y")],
+                b"x".to_vec(),
+                100,
+                options.clone(),
+                expect_ok(tx.clone(), 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // Release the pessimistic lock of y.
+        storage
+            .async_pessimistic_rollback(
+                Context::new(),
+                vec![make_key(b"y")],
+                100,
+                100,
+                expect_ok(tx.clone(), 1),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        options.is_pessimistic_lock = vec![true];
+        storage
+            .async_prewrite(
+                Context::new(),
+                vec![Mutation::Put((make_key(b"x"), b"100".to_vec()))],
+                b"x".to_vec(),
+                100,
+                options.clone(),
+                expect_ok(tx.clone(), 2),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        // The pessimistic lock of y is gone, prewrite must fail.
+        storage
+            .async_prewrite(
+                Context::new(),
+                vec![Mutation::Put((make_key(b"y"), b"100".to_vec()))],
+                b"x".to_vec(),
+                100,
+                options,
+                expect_fail(tx.clone(), 3),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_commit(
+                Context::new(),
+                vec![make_key(b"x")],
+                100,
+                110,
+                expect_ok(tx.clone(), 4),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_get(
+                Context::new(),
+                make_key(b"x"),
+                120,
+                expect_get_val(tx.clone(), b"100".to_vec(), 5),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    fn pessimistic_lock(
+        storage: &Storage,
+        key: &[u8],
+        start_ts: u64,
+        wait_timeout: i64,
+        cb: Callback<Vec<Result<()>>>,
+    ) {
+        let mut options = Options::default();
+        options.for_update_ts = start_ts;
+        options.wait_timeout = wait_timeout;
+        storage
+            .async_acquire_pessimistic_lock(
+                Context::new(),
+                vec![make_key(key)],
+                key.to_vec(),
+                start_ts,
+                options,
+                cb,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_pessimistic_lock_wait() {
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        pessimistic_lock(&storage, b"k", 10, 0, expect_key_errors(tx.clone(), 0, 0));
+        assert_eq!(rx.recv().unwrap(), 0);
+
+        // Don't wait.
+        pessimistic_lock(&storage, b"k", 15, -1, expect_key_errors(tx.clone(), 1, 1));
+        assert_eq!(rx.recv().unwrap(), 1);
+
+        // Both 30 and 20 wait for 10, 20 is woken up first because it's older.
+        pessimistic_lock(&storage, b"k", 30, 0, expect_key_errors(tx.clone(), 0, 2));
+        pessimistic_lock(&storage, b"k", 20, 0, expect_key_errors(tx.clone(), 0, 3));
+        storage
+            .async_pessimistic_rollback(
+                Context::new(),
+                vec![make_key(b"k")],
+                10,
+                10,
+                expect_ok(tx.clone(), 4),
+            )
+            .unwrap();
+        assert_eq!(rx.recv().unwrap(), 4);
+        assert_eq!(rx.recv().unwrap(), 3);
+
+        // 30 waits for 20 now.
+        storage
+            .async_pessimistic_rollback(
+                Context::new(),
+                vec![make_key(b"k")],
+                20,
+                20,
+                expect_ok(tx.clone(), 5),
+            )
+            .unwrap();
+        assert_eq!(rx.recv().unwrap(), 5);
+        assert_eq!(rx.recv().unwrap(), 2);
+
+        // Wait timeout.
+        pessimistic_lock(&storage, b"k", 40, 100, expect_key_errors(tx.clone(), 1, 6));
+        assert_eq!(rx.recv().unwrap(), 6);
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_deadlock() {
+        let config = Config::default();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        pessimistic_lock(&storage, b"k1", 10, 0, expect_key_errors(tx.clone(), 0, 0));
+        pessimistic_lock(&storage, b"k2", 20, 0, expect_key_errors(tx.clone(), 0, 1));
+        rx.recv().unwrap();
+        rx.recv().unwrap();
+
+        // 10 waits for 20, then 20 waits for 10.
+        pessimistic_lock(&storage, b"k2", 10, 0, expect_key_errors(tx.clone(), 0, 2));
+        // Make sure 10 is waiting before 20 is.
+        thread::sleep(Duration::from_millis(100));
+        pessimistic_lock(&storage, b"k1", 20, 0, expect_deadlock(tx.clone(), 3));
+        assert_eq!(rx.recv().unwrap(), 3);
+
+        storage
+            .async_pessimistic_rollback(
+                Context::new(),
+                vec![make_key(b"k2")],
+                20,
+                20,
+                expect_ok(tx.clone(), 4),
+            )
+            .unwrap();
+        assert_eq!(rx.recv().unwrap(), 4);
+        assert_eq!(rx.recv().unwrap(), 2);
+        storage.stop().unwrap();
+    }
+
     #[test]
     fn test_sched_too_busy() {
         let mut config = Config::default();
@@ -1350,6 +2565,7 @@ mod tests {
                 Context::new(),
                 make_key(b"x"),
                 100,
+                0,
                 expect_ok(tx.clone(), 1),
             )
             .unwrap();
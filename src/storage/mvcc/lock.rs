@@ -13,7 +13,7 @@
 
 use byteorder::ReadBytesExt;
 use storage::{Mutation, SHORT_VALUE_MAX_LEN, SHORT_VALUE_PREFIX};
-use util::codec::number::{MAX_VAR_U64_LEN, NumberDecoder, NumberEncoder};
+use util::codec::number::{self, MAX_VAR_U64_LEN, NumberDecoder, NumberEncoder};
 use util::codec::bytes::{BytesEncoder, CompactBytesDecoder};
 use super::{Error, Result};
 use super::super::types::Value;
@@ -23,11 +23,30 @@ pub enum LockType {
     Put,
     Delete,
     Lock,
+    Pessimistic,
 }
 
 const FLAG_PUT: u8 = b'P';
 const FLAG_DELETE: u8 = b'D';
 const FLAG_LOCK: u8 = b'L';
+const FLAG_PESSIMISTIC: u8 = b'S';
+
+const FOR_UPDATE_TS_PREFIX: u8 = b'f';
+const MIN_COMMIT_TS_PREFIX: u8 = b'c';
+const ASYNC_COMMIT_PREFIX: u8 = b'a';
+
+// The lower bits of a timestamp from pd are the logical part.
+const TSO_PHYSICAL_SHIFT_BITS: u64 = 18;
+
+/// Returns the physical part of the timestamp in milliseconds.
+pub fn extract_physical(ts: u64) -> u64 {
+    ts >> TSO_PHYSICAL_SHIFT_BITS
+}
+
+/// Composes the timestamp from the physical part in milliseconds and the logical part.
+pub fn compose_ts(physical: u64, logical: u64) -> u64 {
+    (physical << TSO_PHYSICAL_SHIFT_BITS) + logical
+}
 
 impl LockType {
     pub fn from_mutation(mutation: &Mutation) -> LockType {
@@ -43,6 +62,7 @@ impl LockType {
             FLAG_PUT => Some(LockType::Put),
             FLAG_DELETE => Some(LockType::Delete),
             FLAG_LOCK => Some(LockType::Lock),
+            FLAG_PESSIMISTIC => Some(LockType::Pessimistic),
             _ => None,
         }
     }
@@ -52,17 +72,28 @@ impl LockType {
             LockType::Put => FLAG_PUT,
             LockType::Delete => FLAG_DELETE,
             LockType::Lock => FLAG_LOCK,
+            LockType::Pessimistic => FLAG_PESSIMISTIC,
         }
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub struct Lock {
     pub lock_type: LockType,
     pub primary: Vec<u8>,
     pub ts: u64,
     pub ttl: u64,
     pub short_value: Option<Value>,
+    // `for_update_ts` is only set by pessimistic transactions, it's the ts used to
+    // read the latest data when the pessimistic lock was acquired. 0 means unset.
+    pub for_update_ts: u64,
+    // `min_commit_ts` is only set by async-commit transactions, the transaction can't be
+    // committed with a smaller commit_ts. 0 means unset.
+    pub min_commit_ts: u64,
+    pub use_async_commit: bool,
+    // The raw keys of the other locks of an async-commit transaction, only the primary lock
+    // records them so that the transaction status can be decided by checking them.
+    pub secondaries: Vec<Vec<u8>>,
 }
 
 impl Lock {
@@ -79,12 +110,38 @@ impl Lock {
             ts: ts,
             ttl: ttl,
             short_value: short_value,
+            for_update_ts: 0,
+            min_commit_ts: 0,
+            use_async_commit: false,
+            secondaries: vec![],
         }
     }
 
+    pub fn with_for_update_ts(mut self, for_update_ts: u64) -> Lock {
+        self.for_update_ts = for_update_ts;
+        self
+    }
+
+    pub fn with_min_commit_ts(mut self, min_commit_ts: u64) -> Lock {
+        self.min_commit_ts = min_commit_ts;
+        self
+    }
+
+    pub fn use_async_commit(mut self, secondaries: Vec<Vec<u8>>) -> Lock {
+        self.use_async_commit = true;
+        self.secondaries = secondaries;
+        self
+    }
+
+    /// Checks whether the lock is expired at `current_ts`, the ttl is in milliseconds.
+    pub fn is_expired(&self, current_ts: u64) -> bool {
+        extract_physical(self.ts).saturating_add(self.ttl) < extract_physical(current_ts)
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut b = Vec::with_capacity(
-            1 + MAX_VAR_U64_LEN + self.primary.len() + MAX_VAR_U64_LEN + SHORT_VALUE_MAX_LEN + 2,
+            1 + MAX_VAR_U64_LEN + self.primary.len() + MAX_VAR_U64_LEN + SHORT_VALUE_MAX_LEN + 2 +
+                1 + number::U64_SIZE,
         );
         b.push(self.lock_type.to_u8());
         b.encode_compact_bytes(&self.primary).unwrap();
@@ -95,6 +152,21 @@ impl Lock {
             b.push(v.len() as u8);
             b.extend_from_slice(v);
         }
+        if self.for_update_ts > 0 {
+            b.push(FOR_UPDATE_TS_PREFIX);
+            b.encode_u64(self.for_update_ts).unwrap();
+        }
+        if self.min_commit_ts > 0 {
+            b.push(MIN_COMMIT_TS_PREFIX);
+            b.encode_u64(self.min_commit_ts).unwrap();
+        }
+        if self.use_async_commit {
+            b.push(ASYNC_COMMIT_PREFIX);
+            b.encode_var_u64(self.secondaries.len() as u64).unwrap();
+            for k in &self.secondaries {
+                b.encode_compact_bytes(k).unwrap();
+            }
+        }
         b
     }
 
@@ -111,28 +183,45 @@ impl Lock {
             try!(b.decode_var_u64())
         };
 
-        if b.is_empty() {
-            return Ok(Lock::new(lock_type, primary, ts, ttl, None));
+        let mut short_value = None;
+        let mut for_update_ts = 0;
+        let mut min_commit_ts = 0;
+        let mut secondaries = None;
+        while !b.is_empty() {
+            match try!(b.read_u8()) {
+                SHORT_VALUE_PREFIX => {
+                    let len = try!(b.read_u8()) as usize;
+                    if b.len() < len {
+                        panic!(
+                            "content len [{}] shorter than short value len [{}]",
+                            b.len(),
+                            len
+                        );
+                    }
+                    short_value = Some(b[..len].to_vec());
+                    b = &b[len..];
+                }
+                FOR_UPDATE_TS_PREFIX => for_update_ts = try!(b.decode_u64()),
+                MIN_COMMIT_TS_PREFIX => min_commit_ts = try!(b.decode_u64()),
+                ASYNC_COMMIT_PREFIX => {
+                    let len = try!(b.decode_var_u64()) as usize;
+                    let mut keys = Vec::with_capacity(len);
+                    for _ in 0..len {
+                        keys.push(try!(b.decode_compact_bytes()));
+                    }
+                    secondaries = Some(keys);
+                }
+                flag => panic!("invalid flag [{:?}] in lock", flag),
+            }
         }
 
-        let flag = try!(b.read_u8());
-        assert_eq!(
-            flag,
-            SHORT_VALUE_PREFIX,
-            "invalid flag [{:?}] in write",
-            flag
-        );
-
-        let len = try!(b.read_u8());
-        if len as usize != b.len() {
-            panic!(
-                "short value len [{}] not equal to content len [{}]",
-                len,
-                b.len()
-            );
+        let mut lock = Lock::new(lock_type, primary, ts, ttl, short_value)
+            .with_for_update_ts(for_update_ts)
+            .with_min_commit_ts(min_commit_ts);
+        if let Some(secondaries) = secondaries {
+            lock = lock.use_async_commit(secondaries);
         }
-
-        Ok(Lock::new(lock_type, primary, ts, ttl, Some(b.to_vec())))
+        Ok(lock)
     }
 }
 
@@ -189,6 +278,13 @@ mod tests {
                 lt
             );
         }
+
+        // Pessimistic locks are not generated from mutations.
+        assert_eq!(LockType::Pessimistic.to_u8(), FLAG_PESSIMISTIC);
+        assert_eq!(
+            LockType::from_u8(FLAG_PESSIMISTIC).unwrap(),
+            LockType::Pessimistic
+        );
     }
 
     #[test]
@@ -203,6 +299,26 @@ mod tests {
                 10,
                 Some(b"short_value".to_vec()),
             ),
+            Lock::new(LockType::Pessimistic, b"pk".to_vec(), 1, 10, None).with_for_update_ts(10),
+            Lock::new(
+                LockType::Put,
+                b"pk".to_vec(),
+                1,
+                10,
+                Some(b"short_value".to_vec()),
+            ).with_for_update_ts(10),
+            Lock::new(LockType::Put, b"pk".to_vec(), 1, 10, None)
+                .with_min_commit_ts(20)
+                .use_async_commit(vec![b"k1".to_vec(), b"k2".to_vec()]),
+            Lock::new(
+                LockType::Lock,
+                b"pk".to_vec(),
+                1,
+                10,
+                Some(b"short_value".to_vec()),
+            ).with_for_update_ts(10)
+                .with_min_commit_ts(20)
+                .use_async_commit(vec![]),
         ];
         for (i, lock) in locks.drain(..).enumerate() {
             let v = lock.to_bytes();
@@ -223,4 +339,15 @@ mod tests {
         let v = lock.to_bytes();
         assert!(Lock::parse(&v[..4]).is_err());
     }
+
+    #[test]
+    fn test_lock_expired() {
+        let ts = |physical: u64| physical << TSO_PHYSICAL_SHIFT_BITS;
+        assert_eq!(extract_physical(ts(100) + 1), 100);
+
+        let lock = Lock::new(LockType::Put, b"pk".to_vec(), ts(100), 10, None);
+        assert!(!lock.is_expired(ts(100)));
+        assert!(!lock.is_expired(ts(110) + 1));
+        assert!(lock.is_expired(ts(111)));
+    }
 }
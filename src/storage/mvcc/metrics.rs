@@ -35,4 +35,11 @@ lazy_static! {
             "Total number of conflict error",
             &["type"]
         ).unwrap();
+
+    pub static ref MVCC_CHECK_TXN_STATUS_COUNTER: CounterVec =
+        register_counter_vec!(
+            "tikv_storage_mvcc_check_txn_status",
+            "Counter of different results of check_txn_status",
+            &["type"]
+        ).unwrap();
 }
@@ -19,9 +19,9 @@ mod metrics;
 
 use std::io;
 use std::error;
-pub use self::txn::{MvccTxn, MAX_TXN_WRITE_SIZE};
+pub use self::txn::{MvccTxn, SecondaryLocksStatus, TxnStatus, MAX_TXN_WRITE_SIZE};
 pub use self::reader::MvccReader;
-pub use self::lock::{Lock, LockType};
+pub use self::lock::{compose_ts, extract_physical, Lock, LockType};
 pub use self::write::{Write, WriteType};
 use util::escape;
 
@@ -67,6 +67,33 @@ quick_error! {
              start_ts, conflict_ts, key, primary)
         }
         KeyVersion {description("bad format key(version)")}
+        TxnNotFound { start_ts: u64, key: Vec<u8> } {
+            description("txn not found")
+            display("txn not found, start_ts:{}, key:{:?}", start_ts, key)
+        }
+        PessimisticLockRolledBack { start_ts: u64, key: Vec<u8> } {
+            description("pessimistic lock already rollbacked")
+            display("pessimistic lock already rollbacked, start_ts:{}, key:{:?}", start_ts, key)
+        }
+        PessimisticLockNotFound { start_ts: u64, key: Vec<u8> } {
+            description("pessimistic lock not found when prewrite")
+            display("pessimistic lock not found, start_ts:{}, key:{:?}", start_ts, key)
+        }
+        LockTypeNotMatch { start_ts: u64, key: Vec<u8>, pessimistic: bool } {
+            description("lock type not match")
+            display("lock type not match, start_ts:{}, key:{:?}, pessimistic:{}",
+             start_ts, key, pessimistic)
+        }
+        CommitTsExpired { start_ts: u64, commit_ts: u64, key: Vec<u8>, min_commit_ts: u64 } {
+            description("commit_ts is expired")
+            display("commit_ts {} is smaller than min_commit_ts {}, start_ts:{}, key:{:?}",
+             commit_ts, min_commit_ts, start_ts, key)
+        }
+        Deadlock { start_ts: u64, lock_ts: u64, key: Vec<u8>, deadlock_key_hash: u64 } {
+            description("deadlock")
+            display("deadlock occurs between txn:{} and txn:{}, key:{:?}, deadlock_key_hash:{}",
+             start_ts, lock_ts, key, deadlock_key_hash)
+        }
         Other(err: Box<error::Error + Sync + Send>) {
             from()
             cause(err.as_ref())
@@ -115,6 +142,53 @@ impl Error {
                 primary: primary.to_owned(),
             }),
             Error::KeyVersion => Some(Error::KeyVersion),
+            Error::TxnNotFound { start_ts, ref key } => Some(Error::TxnNotFound {
+                start_ts: start_ts,
+                key: key.to_owned(),
+            }),
+            Error::PessimisticLockRolledBack { start_ts, ref key } => {
+                Some(Error::PessimisticLockRolledBack {
+                    start_ts: start_ts,
+                    key: key.to_owned(),
+                })
+            }
+            Error::PessimisticLockNotFound { start_ts, ref key } => {
+                Some(Error::PessimisticLockNotFound {
+                    start_ts: start_ts,
+                    key: key.to_owned(),
+                })
+            }
+            Error::LockTypeNotMatch {
+                start_ts,
+                ref key,
+                pessimistic,
+            } => Some(Error::LockTypeNotMatch {
+                start_ts: start_ts,
+                key: key.to_owned(),
+                pessimistic: pessimistic,
+            }),
+            Error::CommitTsExpired {
+                start_ts,
+                commit_ts,
+                ref key,
+                min_commit_ts,
+            } => Some(Error::CommitTsExpired {
+                start_ts: start_ts,
+                commit_ts: commit_ts,
+                key: key.to_owned(),
+                min_commit_ts: min_commit_ts,
+            }),
+            Error::Deadlock {
+                start_ts,
+                lock_ts,
+                ref key,
+                deadlock_key_hash,
+            } => Some(Error::Deadlock {
+                start_ts: start_ts,
+                lock_ts: lock_ts,
+                key: key.to_owned(),
+                deadlock_key_hash: deadlock_key_hash,
+            }),
             Error::Committed { commit_ts } => Some(Error::Committed {
                 commit_ts: commit_ts,
             }),
@@ -11,10 +11,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use storage::engine::{Cursor, ScanMode, Snapshot, Statistics};
+use storage::engine::{CFStatistics, Cursor, ScanMode, Snapshot, Statistics};
 use storage::{Key, Value, CF_LOCK, CF_WRITE};
 use super::{Error, Result};
-use super::lock::Lock;
+use super::lock::{Lock, LockType};
 use super::write::{Write, WriteType};
 use raftstore::store::engine::IterOption;
 use std::u64;
@@ -193,7 +193,11 @@ impl<'a> MvccReader<'a> {
 
     fn check_lock(&mut self, key: &Key, mut ts: u64) -> Result<Option<u64>> {
         if let Some(lock) = try!(self.load_lock(key)) {
-            if lock.ts <= ts {
+            // A pessimistic lock carries no data, so it never blocks readers. Neither does an
+            // async-commit lock whose transaction must be committed after `ts`.
+            if lock.ts <= ts && lock.lock_type != LockType::Pessimistic &&
+                !(lock.use_async_commit && lock.min_commit_ts > ts)
+            {
                 if ts == u64::MAX && try!(key.raw()) == lock.primary {
                     // when ts==u64::MAX(which means to get latest committed version for
                     // primary key),and current key is the primary key, returns the latest
@@ -357,6 +361,8 @@ impl<'a> MvccReader<'a> {
         }
     }
 
+    /// Finds the largest key less than `key` that is visible at `ts`. An empty encoded `key`
+    /// means seeking from the last key.
     pub fn reverse_seek(&mut self, mut key: Key, ts: u64) -> Result<Option<(Key, Value)>> {
         assert!(self.scan_mode.is_some());
         try!(self.create_write_cursor());
@@ -370,7 +376,7 @@ impl<'a> MvccReader<'a> {
                 let l_cur = self.lock_cursor.as_mut().unwrap();
                 let (mut w_key, mut l_key) = (None, None);
                 if write_valid {
-                    if try!(w_cur.near_reverse_seek(&key, &mut self.statistics.write)) {
+                    if try!(reverse_seek_cursor(w_cur, &key, &mut self.statistics.write)) {
                         w_key = Some(w_cur.key());
                     } else {
                         w_key = None;
@@ -378,7 +384,7 @@ impl<'a> MvccReader<'a> {
                     }
                 }
                 if lock_valid {
-                    if try!(l_cur.near_reverse_seek(&key, &mut self.statistics.lock)) {
+                    if try!(reverse_seek_cursor(l_cur, &key, &mut self.statistics.lock)) {
                         l_key = Some(l_cur.key());
                     } else {
                         l_key = None;
@@ -548,6 +554,13 @@ impl<'a> MvccReader<'a> {
     }
 }
 
+fn reverse_seek_cursor(cursor: &mut Cursor, key: &Key, stats: &mut CFStatistics) -> Result<bool> {
+    if key.encoded().is_empty() {
+        return Ok(cursor.seek_to_last(stats));
+    }
+    Ok(try!(cursor.near_reverse_seek(key, stats)))
+}
+
 #[cfg(test)]
 mod tests {
     use std::u64;
@@ -11,7 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::fmt;
+use std::{fmt, mem};
 use storage::{is_short_value, Key, Mutation, Options, Statistics, Value, CF_DEFAULT, CF_LOCK,
               CF_WRITE};
 use storage::engine::{Modify, ScanMode, Snapshot};
@@ -24,11 +24,32 @@ use kvproto::kvrpcpb::IsolationLevel;
 
 pub const MAX_TXN_WRITE_SIZE: usize = 32 * 1024;
 
+/// The status of a transaction, checked by its primary lock.
+#[derive(Debug, PartialEq)]
+pub enum TxnStatus {
+    /// The primary lock is still alive.
+    Uncommitted { lock: Lock },
+    Committed { commit_ts: u64 },
+    RolledBack,
+}
+
+/// The status of an async-commit transaction, checked by its secondary locks.
+#[derive(Debug, PartialEq)]
+pub enum SecondaryLocksStatus {
+    /// All the keys are locked.
+    Locked(Vec<Lock>),
+    Committed(u64),
+    RolledBack,
+}
+
 pub struct MvccTxn<'a> {
     reader: MvccReader<'a>,
     start_ts: u64,
     writes: Vec<Modify>,
     write_size: usize,
+    // The locks of a prewrite with `try_one_pc`, which are not written to CF_LOCK unless
+    // the transaction falls back to 2PC.
+    locks_for_one_pc: Vec<(Key, Lock)>,
 }
 
 impl<'a> fmt::Debug for MvccTxn<'a> {
@@ -59,6 +80,7 @@ impl<'a> MvccTxn<'a> {
             start_ts: start_ts,
             writes: vec![],
             write_size: 0,
+            locks_for_one_pc: vec![],
         }
     }
 
@@ -70,15 +92,8 @@ impl<'a> MvccTxn<'a> {
         self.write_size
     }
 
-    fn lock_key(
-        &mut self,
-        key: Key,
-        lock_type: LockType,
-        primary: Vec<u8>,
-        ttl: u64,
-        short_value: Option<Value>,
-    ) {
-        let lock = Lock::new(lock_type, primary, self.start_ts, ttl, short_value).to_bytes();
+    fn lock_key(&mut self, key: Key, lock: &Lock) {
+        let lock = lock.to_bytes();
         self.write_size += CF_LOCK.len() + key.encoded().len() + lock.len();
         self.writes.push(Modify::Put(CF_LOCK, key, lock));
     }
@@ -158,7 +173,13 @@ impl<'a> MvccTxn<'a> {
             return Ok(());
         }
 
-        let short_value = if let Mutation::Put((_, ref value)) = mutation {
+        self.prewrite_key_value(&mutation, primary, options);
+        Ok(())
+    }
+
+    fn prewrite_key_value(&mut self, mutation: &Mutation, primary: &[u8], options: &Options) {
+        let key = mutation.key();
+        let short_value = if let Mutation::Put((_, ref value)) = *mutation {
             if is_short_value(value) {
                 Some(value.clone())
             } else {
@@ -168,26 +189,267 @@ impl<'a> MvccTxn<'a> {
             None
         };
 
-        self.lock_key(
-            key.clone(),
-            LockType::from_mutation(&mutation),
+        let mut lock = Lock::new(
+            LockType::from_mutation(mutation),
             primary.to_vec(),
+            self.start_ts,
             options.lock_ttl,
             short_value,
-        );
+        ).with_for_update_ts(options.for_update_ts);
+        if options.use_async_commit {
+            // Only the primary lock records the secondaries.
+            let secondaries = if *key == Key::from_raw(primary) {
+                options.secondary_keys.clone()
+            } else {
+                vec![]
+            };
+            lock = lock.use_async_commit(secondaries)
+                .with_min_commit_ts(options.min_commit_ts);
+        }
+        if options.try_one_pc {
+            self.locks_for_one_pc.push((key.clone(), lock));
+        } else {
+            self.lock_key(key.clone(), &lock);
+        }
 
-        if let Mutation::Put((_, ref value)) = mutation {
+        if let Mutation::Put((_, ref value)) = *mutation {
             if !is_short_value(value) {
                 let ts = self.start_ts;
                 self.put_value(key, ts, value.clone());
             }
         }
+    }
+
+    /// Returns the number of mutations prewritten with `try_one_pc`.
+    pub fn one_pc_locks_count(&self) -> usize {
+        self.locks_for_one_pc.len()
+    }
+
+    /// Commits the mutations prewritten with `try_one_pc` at `commit_ts` directly, without
+    /// writing any lock.
+    pub fn commit_one_pc(&mut self, commit_ts: u64) {
+        for (key, lock) in mem::replace(&mut self.locks_for_one_pc, vec![]) {
+            let write_type = WriteType::from_lock_type(lock.lock_type).unwrap();
+            let write = Write::new(write_type, self.start_ts, lock.short_value);
+            self.put_write(&key, commit_ts, write.to_bytes());
+        }
+    }
+
+    /// Writes the locks of the mutations prewritten with `try_one_pc`, so the transaction
+    /// is committed by 2PC.
+    pub fn fall_back_to_two_pc(&mut self) {
+        for (key, lock) in mem::replace(&mut self.locks_for_one_pc, vec![]) {
+            self.lock_key(key, &lock);
+        }
+    }
+
+    /// Acquires a pessimistic lock on `key` for a pessimistic transaction.
+    ///
+    /// Pessimistic transactions read data at `for_update_ts`, so any write committed after
+    /// `for_update_ts` is a conflict. The lock carries no value, the value is written by a
+    /// later prewrite which upgrades the lock.
+    pub fn acquire_pessimistic_lock(
+        &mut self,
+        key: Key,
+        primary: &[u8],
+        options: &Options,
+    ) -> Result<()> {
+        let for_update_ts = options.for_update_ts;
+        if let Some(lock) = try!(self.reader.load_lock(&key)) {
+            if lock.ts != self.start_ts {
+                return Err(Error::KeyIsLocked {
+                    key: try!(key.raw()),
+                    primary: lock.primary,
+                    ts: lock.ts,
+                    ttl: lock.ttl,
+                });
+            }
+            if lock.lock_type != LockType::Pessimistic {
+                return Err(Error::LockTypeNotMatch {
+                    start_ts: self.start_ts,
+                    key: key.encoded().to_owned(),
+                    pessimistic: false,
+                });
+            }
+            // Overwrite the lock with a larger for_update_ts so that rollbacks of
+            // earlier statements won't remove it.
+            if for_update_ts > lock.for_update_ts {
+                let lock = self.pessimistic_lock(primary, options);
+                self.lock_key(key, &lock);
+            } else {
+                info!(
+                    "duplicated acquire pessimistic lock with start_ts {}, ignore it.",
+                    self.start_ts
+                );
+            }
+            return Ok(());
+        }
+
+        if let Some((commit_ts, write)) = try!(self.reader.seek_write(&key, u64::max_value())) {
+            // The isolation level of pessimistic transactions is RC, `for_update_ts` is the
+            // commit_ts of the data this transaction read. If a newer version exists, the
+            // statement must retry to read the latest data.
+            if commit_ts > for_update_ts {
+                MVCC_CONFLICT_COUNTER
+                    .with_label_values(&["acquire_pessimistic_lock_conflict"])
+                    .inc();
+                return Err(Error::WriteConflict {
+                    start_ts: self.start_ts,
+                    conflict_ts: commit_ts,
+                    key: key.encoded().to_owned(),
+                    primary: primary.to_vec(),
+                });
+            }
+            // A rollback record written at start_ts means the lock has been rolled back.
+            if write.start_ts == self.start_ts && commit_ts == self.start_ts {
+                assert_eq!(write.write_type, WriteType::Rollback);
+                return Err(Error::PessimisticLockRolledBack {
+                    start_ts: self.start_ts,
+                    key: key.encoded().to_owned(),
+                });
+            }
+            // The rollback may be hidden behind newer versions.
+            if commit_ts > self.start_ts {
+                if let Some((commit_ts, write)) = try!(self.reader.seek_write(&key, self.start_ts))
+                {
+                    if write.start_ts == self.start_ts {
+                        assert!(
+                            commit_ts == self.start_ts && write.write_type == WriteType::Rollback
+                        );
+                        return Err(Error::PessimisticLockRolledBack {
+                            start_ts: self.start_ts,
+                            key: key.encoded().to_owned(),
+                        });
+                    }
+                }
+            }
+        }
+
+        let lock = self.pessimistic_lock(primary, options);
+        self.lock_key(key, &lock);
+        Ok(())
+    }
+
+    fn pessimistic_lock(&self, primary: &[u8], options: &Options) -> Lock {
+        Lock::new(
+            LockType::Pessimistic,
+            primary.to_vec(),
+            self.start_ts,
+            options.lock_ttl,
+            None,
+        ).with_for_update_ts(options.for_update_ts)
+    }
+
+    /// Prewrites a mutation of a pessimistic transaction.
+    ///
+    /// If `is_pessimistic_lock` is true, the key must have been locked by
+    /// `acquire_pessimistic_lock` and the pessimistic lock is upgraded to a normal lock.
+    /// Constraint checks are skipped because conflicts were resolved when locking. Other keys
+    /// are checked against the writes committed after `start_ts` like `prewrite` does.
+    pub fn pessimistic_prewrite(
+        &mut self,
+        mutation: Mutation,
+        primary: &[u8],
+        is_pessimistic_lock: bool,
+        options: &Options,
+    ) -> Result<()> {
+        if let Some(lock) = try!(self.reader.load_lock(mutation.key())) {
+            if lock.ts != self.start_ts {
+                // Abort on lock belonging to other transaction if prewrites a pessimistic lock.
+                if is_pessimistic_lock {
+                    warn!(
+                        "prewrite failed (pessimistic lock not found), key:{}, start_ts:{}",
+                        mutation.key(),
+                        self.start_ts
+                    );
+                    return Err(Error::PessimisticLockNotFound {
+                        start_ts: self.start_ts,
+                        key: mutation.key().encoded().to_owned(),
+                    });
+                }
+                return Err(Error::KeyIsLocked {
+                    key: try!(mutation.key().raw()),
+                    primary: lock.primary,
+                    ts: lock.ts,
+                    ttl: lock.ttl,
+                });
+            }
+            if lock.lock_type != LockType::Pessimistic {
+                // Duplicated command. No need to overwrite the lock and data.
+                info!(
+                    "duplicated prewrite with start_ts {}, ignore it.",
+                    self.start_ts
+                );
+                return Ok(());
+            }
+            // The lock is pessimistic and owned by this txn, go through to overwrite it.
+        } else if is_pessimistic_lock {
+            // Pessimistic lock does not exist, the transaction should be aborted.
+            warn!(
+                "prewrite failed (pessimistic lock not found), key:{}, start_ts:{}",
+                mutation.key(),
+                self.start_ts
+            );
+            return Err(Error::PessimisticLockNotFound {
+                start_ts: self.start_ts,
+                key: mutation.key().encoded().to_owned(),
+            });
+        }
+
+        if !is_pessimistic_lock {
+            let key = mutation.key();
+            if let Some((commit, _)) = try!(self.reader.seek_write(key, u64::max_value())) {
+                if commit > self.start_ts {
+                    MVCC_CONFLICT_COUNTER
+                        .with_label_values(&["pessimistic_prewrite_write_conflict"])
+                        .inc();
+                    return Err(Error::WriteConflict {
+                        start_ts: self.start_ts,
+                        conflict_ts: commit,
+                        key: key.encoded().to_owned(),
+                        primary: primary.to_vec(),
+                    });
+                }
+            }
+        }
+
+        self.prewrite_key_value(&mutation, primary, options);
+        Ok(())
+    }
+
+    /// Removes the pessimistic lock on `key` if it's acquired by this transaction with a
+    /// `for_update_ts` not larger than `for_update_ts`.
+    pub fn pessimistic_rollback(&mut self, key: Key, for_update_ts: u64) -> Result<()> {
+        if let Some(lock) = try!(self.reader.load_lock(&key)) {
+            if lock.lock_type == LockType::Pessimistic && lock.ts == self.start_ts &&
+                lock.for_update_ts <= for_update_ts
+            {
+                self.unlock_key(key);
+            }
+        }
         Ok(())
     }
 
     pub fn commit(&mut self, key: &Key, commit_ts: u64) -> Result<()> {
         let (lock_type, short_value) = match try!(self.reader.load_lock(key)) {
             Some(ref mut lock) if lock.ts == self.start_ts => {
+                // An async-commit transaction may be read by a larger ts after the lock is
+                // written, it can't be committed before `min_commit_ts`.
+                if commit_ts < lock.min_commit_ts {
+                    info!(
+                        "commit_ts {} is expired, key:{}, start_ts:{}, min_commit_ts:{}",
+                        commit_ts,
+                        key,
+                        self.start_ts,
+                        lock.min_commit_ts
+                    );
+                    return Err(Error::CommitTsExpired {
+                        start_ts: self.start_ts,
+                        commit_ts: commit_ts,
+                        key: key.encoded().to_owned(),
+                        min_commit_ts: lock.min_commit_ts,
+                    });
+                }
                 (lock.lock_type, lock.short_value.take())
             }
             _ => {
@@ -217,11 +479,18 @@ impl<'a> MvccTxn<'a> {
                 };
             }
         };
-        let write = Write::new(
-            WriteType::from_lock_type(lock_type),
-            self.start_ts,
-            short_value,
-        );
+        let write_type = match WriteType::from_lock_type(lock_type) {
+            Some(write_type) => write_type,
+            None => {
+                // A pessimistic lock must be prewritten before committing.
+                return Err(Error::LockTypeNotMatch {
+                    start_ts: self.start_ts,
+                    key: key.encoded().to_owned(),
+                    pessimistic: true,
+                });
+            }
+        };
+        let write = Write::new(write_type, self.start_ts, short_value);
         self.put_write(key, commit_ts, write.to_bytes());
         self.unlock_key(key.clone());
         Ok(())
@@ -271,6 +540,116 @@ impl<'a> MvccTxn<'a> {
         Ok(())
     }
 
+    fn txn_not_found(&self, primary_key: &Key) -> Error {
+        Error::TxnNotFound {
+            start_ts: self.start_ts,
+            key: primary_key.encoded().to_owned(),
+        }
+    }
+
+    /// Extends the ttl of the primary lock to `advise_ttl` if it's larger, returns the lock
+    /// with the updated ttl.
+    pub fn txn_heart_beat(&mut self, primary_key: Key, advise_ttl: u64) -> Result<Lock> {
+        let mut lock = match try!(self.reader.load_lock(&primary_key)) {
+            Some(lock) => lock,
+            None => return Err(self.txn_not_found(&primary_key)),
+        };
+        if lock.ts != self.start_ts {
+            return Err(self.txn_not_found(&primary_key));
+        }
+        if lock.ttl < advise_ttl {
+            lock.ttl = advise_ttl;
+            self.lock_key(primary_key, &lock);
+        }
+        Ok(lock)
+    }
+
+    /// Checks the status of the transaction by its primary lock, and rolls it back if the lock
+    /// is expired at `current_ts`. A `current_ts` of 0 rolls back the alive lock regardless
+    /// of its ttl.
+    ///
+    /// If neither the lock nor the commit record is found, a rollback record is written to
+    /// prevent the transaction from being committed later.
+    ///
+    /// An async-commit transaction is committed once all its locks are written, so its primary
+    /// lock is never rolled back here. The status must be decided by checking the secondaries
+    /// instead, see `check_secondary_locks`.
+    pub fn check_txn_status(&mut self, primary_key: &Key, current_ts: u64) -> Result<TxnStatus> {
+        if let Some(lock) = try!(self.reader.load_lock(primary_key)) {
+            if lock.ts == self.start_ts {
+                if lock.use_async_commit || (current_ts != 0 && !lock.is_expired(current_ts)) {
+                    return Ok(TxnStatus::Uncommitted { lock: lock });
+                }
+                MVCC_CHECK_TXN_STATUS_COUNTER
+                    .with_label_values(&["rollback"])
+                    .inc();
+                try!(self.rollback(primary_key));
+                return Ok(TxnStatus::RolledBack);
+            }
+        }
+        match try!(self.reader.get_txn_commit_info(primary_key, self.start_ts)) {
+            Some((_, WriteType::Rollback)) => Ok(TxnStatus::RolledBack),
+            Some((commit_ts, _)) => Ok(TxnStatus::Committed {
+                commit_ts: commit_ts,
+            }),
+            None => {
+                MVCC_CHECK_TXN_STATUS_COUNTER
+                    .with_label_values(&["lock_not_exist"])
+                    .inc();
+                try!(self.rollback(primary_key));
+                Ok(TxnStatus::RolledBack)
+            }
+        }
+    }
+
+    /// Checks the locks of an async-commit transaction on `keys`.
+    ///
+    /// If all the keys are locked, the transaction may still be committed and the locks are
+    /// returned, the commit_ts is the max `min_commit_ts` of all the locks. Otherwise the
+    /// transaction is either committed, or rolled back since a missing lock is never written
+    /// after a rollback record is left here.
+    pub fn check_secondary_locks(&mut self, keys: &[Key]) -> Result<SecondaryLocksStatus> {
+        let mut locks = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some(lock) = try!(self.reader.load_lock(key)) {
+                // A pessimistic lock means the key is not prewritten yet.
+                if lock.ts == self.start_ts && lock.lock_type != LockType::Pessimistic {
+                    locks.push(lock);
+                    continue;
+                }
+            }
+            match try!(self.reader.get_txn_commit_info(key, self.start_ts)) {
+                Some((_, WriteType::Rollback)) => return Ok(SecondaryLocksStatus::RolledBack),
+                Some((commit_ts, _)) => return Ok(SecondaryLocksStatus::Committed(commit_ts)),
+                None => {
+                    MVCC_CHECK_TXN_STATUS_COUNTER
+                        .with_label_values(&["secondary_lock_not_exist"])
+                        .inc();
+                    try!(self.rollback(key));
+                    return Ok(SecondaryLocksStatus::RolledBack);
+                }
+            }
+        }
+        Ok(SecondaryLocksStatus::Locked(locks))
+    }
+
+    /// Rolls back the transaction by its primary lock unless it's committed or still alive
+    /// at `current_ts`.
+    pub fn cleanup(&mut self, primary_key: &Key, current_ts: u64) -> Result<()> {
+        match try!(self.check_txn_status(primary_key, current_ts)) {
+            TxnStatus::RolledBack => Ok(()),
+            TxnStatus::Committed { commit_ts } => Err(Error::Committed {
+                commit_ts: commit_ts,
+            }),
+            TxnStatus::Uncommitted { lock } => Err(Error::KeyIsLocked {
+                key: try!(primary_key.raw()),
+                primary: lock.primary,
+                ts: lock.ts,
+                ttl: lock.ttl,
+            }),
+        }
+    }
+
     pub fn gc(&mut self, key: &Key, safe_point: u64) -> Result<()> {
         let mut remove_older = false;
         let mut ts: u64 = u64::max_value();
@@ -337,9 +716,10 @@ impl<'a> MvccTxn<'a> {
 mod tests {
     use tempdir::TempDir;
     use kvproto::kvrpcpb::{Context, IsolationLevel};
-    use super::MvccTxn;
+    use super::{MvccTxn, SecondaryLocksStatus, TxnStatus};
     use super::super::MvccReader;
     use super::super::write::{Write, WriteType};
+    use super::super::LockType;
     use storage::{make_key, Mutation, Options, ScanMode, Statistics, ALL_CFS, CF_WRITE,
                   SHORT_VALUE_MAX_LEN};
     use storage::engine::{self, Engine, TEMP_DIR};
@@ -850,6 +1230,518 @@ mod tests {
         must_get_rc(engine.as_ref(), key, 20, v1);
     }
 
+    #[test]
+    fn test_pessimistic_lock() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let (k, v) = (b"k1", b"v1");
+
+        // Normal.
+        must_acquire_pessimistic_lock(engine.as_ref(), k, k, 1, 1);
+        must_pessimistic_locked(engine.as_ref(), k, 1, 1);
+        // Pessimistic locks don't block readers.
+        must_get_none(engine.as_ref(), k, 2);
+        // Acquire again with a larger for_update_ts.
+        must_acquire_pessimistic_lock(engine.as_ref(), k, k, 1, 2);
+        must_pessimistic_locked(engine.as_ref(), k, 1, 2);
+        // Pessimistic lock can't be committed before prewrite.
+        must_commit_err(engine.as_ref(), k, 1, 3);
+        must_pessimistic_prewrite_put(engine.as_ref(), k, v, k, 1, 2, true);
+        must_locked(engine.as_ref(), k, 1);
+        // Duplicated prewrite is ignored.
+        must_pessimistic_prewrite_put(engine.as_ref(), k, v, k, 1, 2, true);
+        must_commit(engine.as_ref(), k, 1, 3);
+        must_unlocked(engine.as_ref(), k);
+        must_get(engine.as_ref(), k, 4, v);
+
+        // Lock conflict.
+        must_prewrite_put(engine.as_ref(), k, v, k, 5);
+        must_acquire_pessimistic_lock_err(engine.as_ref(), k, k, 6, 6);
+        must_commit(engine.as_ref(), k, 5, 7);
+
+        // Write conflict: a version newer than for_update_ts exists.
+        must_acquire_pessimistic_lock_err(engine.as_ref(), k, k, 6, 6);
+        must_unlocked(engine.as_ref(), k);
+        must_acquire_pessimistic_lock(engine.as_ref(), k, k, 6, 8);
+        must_pessimistic_locked(engine.as_ref(), k, 6, 8);
+
+        // Pessimistic rollback with a smaller for_update_ts is ignored.
+        must_pessimistic_rollback(engine.as_ref(), k, 6, 7);
+        must_pessimistic_locked(engine.as_ref(), k, 6, 8);
+        must_pessimistic_rollback(engine.as_ref(), k, 6, 8);
+        must_unlocked(engine.as_ref(), k);
+
+        // Prewrite of a pessimistic key fails if the pessimistic lock is missing.
+        must_pessimistic_prewrite_put_err(engine.as_ref(), k, v, k, 6, 8, true);
+        // Keys that need no pessimistic lock are checked for write conflicts, the version
+        // committed at 7 is newer than the start ts.
+        match must_pessimistic_prewrite_put_impl(engine.as_ref(), k, v, k, 6, 8, false) {
+            Err(::storage::mvcc::Error::WriteConflict { conflict_ts: 7, .. }) => {}
+            res => panic!("expect write conflict, got {:?}", res),
+        }
+        must_unlocked(engine.as_ref(), k);
+        must_rollback(engine.as_ref(), k, 6);
+
+        // Keys that need no pessimistic lock and have no newer version can be prewritten
+        // directly.
+        let k2 = b"k2";
+        must_pessimistic_prewrite_put(engine.as_ref(), k2, v, k2, 9, 9, false);
+        must_locked(engine.as_ref(), k2, 9);
+        must_commit(engine.as_ref(), k2, 9, 10);
+        must_get(engine.as_ref(), k2, 11, v);
+
+        // Acquiring the lock after rollback fails.
+        must_acquire_pessimistic_lock_err(engine.as_ref(), k, k, 6, 8);
+        must_unlocked(engine.as_ref(), k);
+    }
+
+    #[test]
+    fn test_txn_heart_beat_and_check_txn_status() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let (k, v) = (b"k1", b"v1");
+        let ts = |physical: u64| physical << 18;
+
+        // Heart beat only extends the ttl.
+        must_prewrite_put_with_ttl(engine.as_ref(), k, v, k, ts(10), 100);
+        must_txn_heart_beat(engine.as_ref(), k, ts(10), 50, 100);
+        must_txn_heart_beat(engine.as_ref(), k, ts(10), 200, 200);
+        must_txn_heart_beat_err(engine.as_ref(), k, ts(5), 300);
+
+        // The lock is alive until ts(10) + 200ms.
+        must_check_txn_status(engine.as_ref(), k, ts(10), ts(210), Some(200), None);
+        must_locked(engine.as_ref(), k, ts(10));
+        must_check_txn_status(engine.as_ref(), k, ts(10), ts(211), None, None);
+        must_unlocked(engine.as_ref(), k);
+        must_written(engine.as_ref(), k, ts(10), ts(10), WriteType::Rollback);
+        must_txn_heart_beat_err(engine.as_ref(), k, ts(10), 300);
+        must_commit_err(engine.as_ref(), k, ts(10), ts(20));
+
+        // Committed transactions.
+        must_prewrite_put_with_ttl(engine.as_ref(), k, v, k, ts(30), 100);
+        must_commit(engine.as_ref(), k, ts(30), ts(31));
+        must_check_txn_status(engine.as_ref(), k, ts(30), ts(500), None, Some(ts(31)));
+        must_written(engine.as_ref(), k, ts(30), ts(31), WriteType::Put);
+
+        // A rollback record prevents the missing transaction from being prewritten later.
+        must_check_txn_status(engine.as_ref(), k, ts(40), ts(41), None, None);
+        must_prewrite_lock_err(engine.as_ref(), k, k, ts(40));
+
+        // A current ts of 0 rolls back the alive lock.
+        must_prewrite_put_with_ttl(engine.as_ref(), k, v, k, ts(50), 100);
+        must_check_txn_status(engine.as_ref(), k, ts(50), 0, None, None);
+        must_unlocked(engine.as_ref(), k);
+    }
+
+    #[test]
+    fn test_async_commit() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let (k1, k2, v) = (b"k1", b"k2", b"v");
+
+        must_prewrite_put_async_commit(engine.as_ref(), k1, v, k1, vec![k2], 10, 20);
+        must_prewrite_put_async_commit(engine.as_ref(), k2, v, k1, vec![], 10, 20);
+        match must_check_secondary_locks(engine.as_ref(), vec![k1, k2], 10) {
+            SecondaryLocksStatus::Locked(locks) => {
+                assert_eq!(locks[0].secondaries, vec![k2.to_vec()]);
+                assert!(locks[1].secondaries.is_empty());
+                assert!(locks.iter().all(|l| l.use_async_commit && l.min_commit_ts == 20));
+            }
+            status => panic!("expect locked, but got {:?}", status),
+        }
+
+        // Readers with ts smaller than min_commit_ts ignore the locks.
+        must_get_none(engine.as_ref(), k2, 15);
+        must_get_err(engine.as_ref(), k2, 25);
+
+        // The primary lock is never rolled back even if it's expired.
+        must_check_txn_status(engine.as_ref(), k1, 10, 100 << 18, Some(0), None);
+        must_locked(engine.as_ref(), k1, 10);
+
+        must_commit_err(engine.as_ref(), k1, 10, 15);
+        must_commit(engine.as_ref(), k2, 10, 20);
+        assert_eq!(
+            must_check_secondary_locks(engine.as_ref(), vec![k1, k2], 10),
+            SecondaryLocksStatus::Committed(20)
+        );
+        must_commit(engine.as_ref(), k1, 10, 20);
+        must_get(engine.as_ref(), k1, 25, v);
+
+        // The secondary key which is not prewritten is rolled back.
+        must_prewrite_put_async_commit(engine.as_ref(), b"k3", v, b"k3", vec![b"k4"], 30, 31);
+        assert_eq!(
+            must_check_secondary_locks(engine.as_ref(), vec![b"k4"], 30),
+            SecondaryLocksStatus::RolledBack
+        );
+        must_written(engine.as_ref(), b"k4", 30, 30, WriteType::Rollback);
+        must_prewrite_lock_err(engine.as_ref(), b"k4", b"k3", 30);
+
+        // So is the pessimistic lock.
+        must_acquire_pessimistic_lock(engine.as_ref(), b"k5", b"k5", 40, 40);
+        assert_eq!(
+            must_check_secondary_locks(engine.as_ref(), vec![b"k5"], 40),
+            SecondaryLocksStatus::RolledBack
+        );
+        must_unlocked(engine.as_ref(), b"k5");
+    }
+
+    fn must_prewrite_put_with_ttl(
+        engine: &Engine,
+        key: &[u8],
+        value: &[u8],
+        pk: &[u8],
+        ts: u64,
+        ttl: u64,
+    ) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            ts,
+            None,
+            IsolationLevel::SI,
+            true,
+        );
+        txn.prewrite(
+            Mutation::Put((make_key(key), value.to_vec())),
+            pk,
+            &Options::new(ttl, false, false),
+        ).unwrap();
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    fn must_txn_heart_beat(
+        engine: &Engine,
+        pk: &[u8],
+        start_ts: u64,
+        advise_ttl: u64,
+        expect_ttl: u64,
+    ) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            start_ts,
+            None,
+            IsolationLevel::SI,
+            true,
+        );
+        let lock = txn.txn_heart_beat(make_key(pk), advise_ttl).unwrap();
+        assert_eq!(lock.ttl, expect_ttl);
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    fn must_txn_heart_beat_err(engine: &Engine, pk: &[u8], start_ts: u64, advise_ttl: u64) {
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            start_ts,
+            None,
+            IsolationLevel::SI,
+            true,
+        );
+        assert!(txn.txn_heart_beat(make_key(pk), advise_ttl).is_err());
+    }
+
+    // Checks the status of the transaction, `None` for both `lock_ttl` and `commit_ts` means
+    // it's rolled back.
+    fn must_check_txn_status(
+        engine: &Engine,
+        pk: &[u8],
+        lock_ts: u64,
+        current_ts: u64,
+        lock_ttl: Option<u64>,
+        commit_ts: Option<u64>,
+    ) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            lock_ts,
+            None,
+            IsolationLevel::SI,
+            true,
+        );
+        let status = txn.check_txn_status(&make_key(pk), current_ts).unwrap();
+        match (lock_ttl, commit_ts) {
+            (Some(ttl), _) => match status {
+                TxnStatus::Uncommitted { ref lock } => assert_eq!(lock.ttl, ttl),
+                _ => panic!("expect uncommitted, but got {:?}", status),
+            },
+            (_, Some(ts)) => assert_eq!(status, TxnStatus::Committed { commit_ts: ts }),
+            (None, None) => assert_eq!(status, TxnStatus::RolledBack),
+        }
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    #[test]
+    fn test_one_pc() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let (k, v) = (b"k", b"v");
+        let long_value = gen_value(b'v', SHORT_VALUE_MAX_LEN + 1);
+
+        must_prewrite_put_one_pc(engine.as_ref(), k, v, k, 10, Some(15));
+        must_unlocked(engine.as_ref(), k);
+        must_written(engine.as_ref(), k, 10, 15, WriteType::Put);
+        must_get_none(engine.as_ref(), k, 14);
+        must_get(engine.as_ref(), k, 15, v);
+
+        // The long value is written to the default cf as 2PC does.
+        must_prewrite_put_one_pc(engine.as_ref(), k, &long_value, k, 20, Some(25));
+        must_unlocked(engine.as_ref(), k);
+        must_get(engine.as_ref(), k, 25, &long_value);
+
+        // The locks are written if it falls back to 2PC.
+        must_prewrite_put_one_pc(engine.as_ref(), k, v, k, 30, None);
+        must_locked(engine.as_ref(), k, 30);
+        must_commit(engine.as_ref(), k, 30, 35);
+        must_get(engine.as_ref(), k, 35, v);
+    }
+
+    // Prewrites with `try_one_pc`, and commits it at `commit_ts`, or falls back to 2PC if
+    // `commit_ts` is `None`.
+    fn must_prewrite_put_one_pc(
+        engine: &Engine,
+        key: &[u8],
+        value: &[u8],
+        pk: &[u8],
+        ts: u64,
+        commit_ts: Option<u64>,
+    ) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            ts,
+            None,
+            IsolationLevel::SI,
+            true,
+        );
+        let mut options = Options::default();
+        options.try_one_pc = true;
+        txn.prewrite(Mutation::Put((make_key(key), value.to_vec())), pk, &options)
+            .unwrap();
+        assert_eq!(txn.one_pc_locks_count(), 1);
+        match commit_ts {
+            Some(commit_ts) => txn.commit_one_pc(commit_ts),
+            None => txn.fall_back_to_two_pc(),
+        }
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    fn must_prewrite_put_async_commit(
+        engine: &Engine,
+        key: &[u8],
+        value: &[u8],
+        pk: &[u8],
+        secondaries: Vec<&[u8]>,
+        ts: u64,
+        min_commit_ts: u64,
+    ) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            ts,
+            None,
+            IsolationLevel::SI,
+            true,
+        );
+        let mut options = Options::default();
+        options.use_async_commit = true;
+        options.secondary_keys = secondaries.iter().map(|k| k.to_vec()).collect();
+        options.min_commit_ts = min_commit_ts;
+        txn.prewrite(Mutation::Put((make_key(key), value.to_vec())), pk, &options)
+            .unwrap();
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    fn must_check_secondary_locks(
+        engine: &Engine,
+        keys: Vec<&[u8]>,
+        start_ts: u64,
+    ) -> SecondaryLocksStatus {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            start_ts,
+            None,
+            IsolationLevel::SI,
+            true,
+        );
+        let keys: Vec<_> = keys.iter().map(|k| make_key(k)).collect();
+        let status = txn.check_secondary_locks(&keys).unwrap();
+        engine.write(&ctx, txn.modifies()).unwrap();
+        status
+    }
+
+    fn must_acquire_pessimistic_lock_impl(
+        engine: &Engine,
+        key: &[u8],
+        pk: &[u8],
+        start_ts: u64,
+        for_update_ts: u64,
+    ) -> ::storage::mvcc::Result<()> {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            start_ts,
+            None,
+            IsolationLevel::SI,
+            true,
+        );
+        let mut options = Options::default();
+        options.for_update_ts = for_update_ts;
+        try!(txn.acquire_pessimistic_lock(make_key(key), pk, &options));
+        engine.write(&ctx, txn.modifies()).unwrap();
+        Ok(())
+    }
+
+    fn must_acquire_pessimistic_lock(
+        engine: &Engine,
+        key: &[u8],
+        pk: &[u8],
+        start_ts: u64,
+        for_update_ts: u64,
+    ) {
+        must_acquire_pessimistic_lock_impl(engine, key, pk, start_ts, for_update_ts).unwrap();
+    }
+
+    fn must_acquire_pessimistic_lock_err(
+        engine: &Engine,
+        key: &[u8],
+        pk: &[u8],
+        start_ts: u64,
+        for_update_ts: u64,
+    ) {
+        assert!(
+            must_acquire_pessimistic_lock_impl(engine, key, pk, start_ts, for_update_ts).is_err()
+        );
+    }
+
+    fn must_pessimistic_prewrite_put_impl(
+        engine: &Engine,
+        key: &[u8],
+        value: &[u8],
+        pk: &[u8],
+        start_ts: u64,
+        for_update_ts: u64,
+        is_pessimistic_lock: bool,
+    ) -> ::storage::mvcc::Result<()> {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            start_ts,
+            None,
+            IsolationLevel::SI,
+            true,
+        );
+        let mut options = Options::default();
+        options.for_update_ts = for_update_ts;
+        try!(txn.pessimistic_prewrite(
+            Mutation::Put((make_key(key), value.to_vec())),
+            pk,
+            is_pessimistic_lock,
+            &options,
+        ));
+        engine.write(&ctx, txn.modifies()).unwrap();
+        Ok(())
+    }
+
+    fn must_pessimistic_prewrite_put(
+        engine: &Engine,
+        key: &[u8],
+        value: &[u8],
+        pk: &[u8],
+        start_ts: u64,
+        for_update_ts: u64,
+        is_pessimistic_lock: bool,
+    ) {
+        must_pessimistic_prewrite_put_impl(
+            engine,
+            key,
+            value,
+            pk,
+            start_ts,
+            for_update_ts,
+            is_pessimistic_lock,
+        ).unwrap();
+    }
+
+    fn must_pessimistic_prewrite_put_err(
+        engine: &Engine,
+        key: &[u8],
+        value: &[u8],
+        pk: &[u8],
+        start_ts: u64,
+        for_update_ts: u64,
+        is_pessimistic_lock: bool,
+    ) {
+        assert!(
+            must_pessimistic_prewrite_put_impl(
+                engine,
+                key,
+                value,
+                pk,
+                start_ts,
+                for_update_ts,
+                is_pessimistic_lock,
+            ).is_err()
+        );
+    }
+
+    fn must_pessimistic_rollback(engine: &Engine, key: &[u8], start_ts: u64, for_update_ts: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut statistics = Statistics::default();
+        let mut txn = MvccTxn::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            start_ts,
+            None,
+            IsolationLevel::SI,
+            true,
+        );
+        txn.pessimistic_rollback(make_key(key), for_update_ts)
+            .unwrap();
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    fn must_pessimistic_locked(engine: &Engine, key: &[u8], start_ts: u64, for_update_ts: u64) {
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        let mut statistics = Statistics::default();
+        let mut reader = MvccReader::new(
+            snapshot.as_ref(),
+            &mut statistics,
+            None,
+            true,
+            None,
+            IsolationLevel::SI,
+        );
+        let lock = reader.load_lock(&make_key(key)).unwrap().unwrap();
+        assert_eq!(lock.ts, start_ts);
+        assert_eq!(lock.for_update_ts, for_update_ts);
+        assert_eq!(lock.lock_type, LockType::Pessimistic);
+    }
+
     fn must_get(engine: &Engine, key: &[u8], ts: u64, expect: &[u8]) {
         let ctx = Context::new();
         let snapshot = engine.snapshot(&ctx).unwrap();
@@ -32,11 +32,14 @@ const FLAG_LOCK: u8 = b'L';
 const FLAG_ROLLBACK: u8 = b'R';
 
 impl WriteType {
-    pub fn from_lock_type(tp: LockType) -> WriteType {
+    /// Returns the write type committed by a lock of type `tp`, pessimistic locks can't be
+    /// committed and return `None`.
+    pub fn from_lock_type(tp: LockType) -> Option<WriteType> {
         match tp {
-            LockType::Put => WriteType::Put,
-            LockType::Delete => WriteType::Delete,
-            LockType::Lock => WriteType::Lock,
+            LockType::Put => Some(WriteType::Put),
+            LockType::Delete => Some(WriteType::Delete),
+            LockType::Lock => Some(WriteType::Lock),
+            LockType::Pessimistic => None,
         }
     }
 
@@ -133,7 +136,7 @@ mod tests {
         ];
         for (i, (lock_type, write_type, flag)) in tests.drain(..).enumerate() {
             if lock_type.is_some() {
-                let wt = WriteType::from_lock_type(lock_type.unwrap());
+                let wt = WriteType::from_lock_type(lock_type.unwrap()).unwrap();
                 assert_eq!(
                     wt,
                     write_type,
@@ -167,6 +170,11 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_pessimistic_lock_type() {
+        assert!(WriteType::from_lock_type(LockType::Pessimistic).is_none());
+    }
+
     #[test]
     fn test_write() {
         // Test `Write::to_bytes()` and `Write::parse()` works as a pair.
@@ -0,0 +1,284 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Concurrency control between reads and async-commit prewrites.
+//!
+//! The commit ts of an async-commit transaction is the max `min_commit_ts` of its locks, so
+//! `min_commit_ts` must be larger than the ts of any read which may miss the lock. Readers bump
+//! `max_read_ts` and check the in-memory locks before getting the snapshot, while prewrites add
+//! the in-memory locks before reading `max_read_ts` and remove them after the locks are written.
+//! So a read either meets the lock, or makes the prewrite use a larger `min_commit_ts`.
+//!
+//! The reads served by the previous leader of a region are not tracked here, so a new leader
+//! syncs `max_read_ts` with a ts from PD before it accepts async-commit and 1PC prewrites.
+
+use std::collections::BTreeMap;
+use std::collections::Bound::{Excluded, Included, Unbounded};
+use std::sync::Mutex;
+use std::u64;
+
+use storage::Key;
+use storage::mvcc::{Error, Result};
+use util::collections::HashMap;
+
+struct MemoryLock {
+    primary: Vec<u8>,
+    ts: u64,
+    ttl: u64,
+}
+
+impl MemoryLock {
+    fn check(&self, key: &[u8], ts: u64) -> Result<()> {
+        if self.ts > ts {
+            return Ok(());
+        }
+        Err(Error::KeyIsLocked {
+            key: try!(Key::from_encoded(key.to_vec()).raw()),
+            primary: self.primary.clone(),
+            ts: self.ts,
+            ttl: self.ttl,
+        })
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    max_read_ts: u64,
+    // encoded key -> lock
+    locks: BTreeMap<Vec<u8>, MemoryLock>,
+    // region id -> sync id, the regions led by this store whose max read ts is not synced.
+    unsynced_regions: HashMap<u64, u64>,
+    next_sync_id: u64,
+}
+
+impl Inner {
+    fn update_max_read_ts(&mut self, ts: u64) {
+        // `u64::MAX` is used to read the latest data, which doesn't need to be tracked.
+        if ts != u64::MAX && ts > self.max_read_ts {
+            self.max_read_ts = ts;
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ConcurrencyManager {
+    inner: Mutex<Inner>,
+}
+
+impl ConcurrencyManager {
+    pub fn new() -> ConcurrencyManager {
+        ConcurrencyManager::default()
+    }
+
+    pub fn max_read_ts(&self) -> u64 {
+        self.inner.lock().unwrap().max_read_ts
+    }
+
+    pub fn update_max_read_ts(&self, ts: u64) {
+        self.inner.lock().unwrap().update_max_read_ts(ts);
+    }
+
+    /// Marks the max read ts of the region as not synced, it's called when a peer of the region
+    /// becomes leader. Returns the id of the sync, which is passed to `finish_max_ts_sync`.
+    pub fn start_max_ts_sync(&self, region_id: u64) -> u64 {
+        let mut inner = self.inner.lock().unwrap();
+        inner.next_sync_id += 1;
+        let sync_id = inner.next_sync_id;
+        inner.unsynced_regions.insert(region_id, sync_id);
+        sync_id
+    }
+
+    /// Bumps the max read ts to `ts` got from PD, the region is synced unless it's become leader
+    /// again since the sync started.
+    pub fn finish_max_ts_sync(&self, region_id: u64, sync_id: u64, ts: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.update_max_read_ts(ts);
+        if inner.unsynced_regions.get(&region_id) == Some(&sync_id) {
+            inner.unsynced_regions.remove(&region_id);
+        }
+    }
+
+    /// Stops tracking the region, it's called when the peer of the region steps down.
+    pub fn cancel_max_ts_sync(&self, region_id: u64) {
+        self.inner.lock().unwrap().unsynced_regions.remove(&region_id);
+    }
+
+    pub fn is_max_ts_synced(&self, region_id: u64) -> bool {
+        !self.inner
+            .lock()
+            .unwrap()
+            .unsynced_regions
+            .contains_key(&region_id)
+    }
+
+    /// Adds the in-memory locks of a prewrite, returns the max read ts at the time.
+    pub fn lock_keys(&self, keys: &[&Key], primary: &[u8], ts: u64, ttl: u64) -> u64 {
+        let mut inner = self.inner.lock().unwrap();
+        for key in keys {
+            let lock = MemoryLock {
+                primary: primary.to_vec(),
+                ts: ts,
+                ttl: ttl,
+            };
+            inner.locks.insert(key.encoded().clone(), lock);
+        }
+        inner.max_read_ts
+    }
+
+    /// Removes the in-memory locks added by the prewrite with `ts`.
+    pub fn unlock_keys(&self, keys: &[Key], ts: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        for key in keys {
+            let locked = inner
+                .locks
+                .get(key.encoded())
+                .map_or(false, |lock| lock.ts == ts);
+            if locked {
+                inner.locks.remove(key.encoded());
+            }
+        }
+    }
+
+    /// Returns the min ts of the prewrites which are not written yet.
+    pub fn global_min_lock_ts(&self) -> Option<u64> {
+        let inner = self.inner.lock().unwrap();
+        inner.locks.values().map(|lock| lock.ts).min()
+    }
+
+    /// Bumps the max read ts to `ts`, and checks whether `key` is locked by a prewrite which
+    /// is not written yet.
+    pub fn read_key_check(&self, key: &Key, ts: u64) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.update_max_read_ts(ts);
+        match inner.locks.get(key.encoded()) {
+            Some(lock) => lock.check(key.encoded(), ts),
+            None => Ok(()),
+        }
+    }
+
+    /// Bumps the max read ts to `ts`, and checks whether any key in [`start_key`, `end_key`)
+    /// is locked by a prewrite which is not written yet. `None` means unbounded.
+    pub fn read_range_check(
+        &self,
+        start_key: Option<&Key>,
+        end_key: Option<&Key>,
+        ts: u64,
+    ) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.update_max_read_ts(ts);
+        if let (Some(start), Some(end)) = (start_key, end_key) {
+            if start.encoded() >= end.encoded() {
+                return Ok(());
+            }
+        }
+        let lower = match start_key {
+            Some(k) => Included(k.encoded().clone()),
+            None => Unbounded,
+        };
+        let upper = match end_key {
+            Some(k) => Excluded(k.encoded().clone()),
+            None => Unbounded,
+        };
+        for (key, lock) in inner.locks.range((lower, upper)) {
+            try!(lock.check(key, ts));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::u64;
+
+    use storage::make_key;
+    use storage::mvcc::Error;
+    use super::*;
+
+    #[test]
+    fn test_max_read_ts() {
+        let cm = ConcurrencyManager::new();
+        cm.update_max_read_ts(10);
+        cm.update_max_read_ts(5);
+        assert_eq!(cm.max_read_ts(), 10);
+        cm.update_max_read_ts(u64::MAX);
+        assert_eq!(cm.max_read_ts(), 10);
+
+        cm.read_key_check(&make_key(b"k"), 20).unwrap();
+        cm.read_range_check(None, None, 30).unwrap();
+        assert_eq!(cm.max_read_ts(), 30);
+    }
+
+    #[test]
+    fn test_max_ts_sync() {
+        let cm = ConcurrencyManager::new();
+        cm.update_max_read_ts(10);
+        assert!(cm.is_max_ts_synced(1));
+
+        let sync_id = cm.start_max_ts_sync(1);
+        assert!(!cm.is_max_ts_synced(1));
+        assert!(cm.is_max_ts_synced(2));
+        cm.finish_max_ts_sync(1, sync_id, 20);
+        assert!(cm.is_max_ts_synced(1));
+        assert_eq!(cm.max_read_ts(), 20);
+
+        // The region steps down and becomes leader again before the first sync finishes.
+        let stale_id = cm.start_max_ts_sync(1);
+        cm.cancel_max_ts_sync(1);
+        assert!(cm.is_max_ts_synced(1));
+        let sync_id = cm.start_max_ts_sync(1);
+        cm.finish_max_ts_sync(1, stale_id, 30);
+        assert!(!cm.is_max_ts_synced(1));
+        assert_eq!(cm.max_read_ts(), 30);
+        cm.finish_max_ts_sync(1, sync_id, 25);
+        assert!(cm.is_max_ts_synced(1));
+        assert_eq!(cm.max_read_ts(), 30);
+    }
+
+    #[test]
+    fn test_memory_locks() {
+        let cm = ConcurrencyManager::new();
+        let (k1, k2, k3) = (make_key(b"k1"), make_key(b"k2"), make_key(b"k3"));
+        cm.update_max_read_ts(10);
+        assert_eq!(cm.lock_keys(&[&k1, &k3], b"k1", 20, 100), 10);
+        assert_eq!(cm.lock_keys(&[&k2], b"k2", 25, 100), 10);
+        assert_eq!(cm.global_min_lock_ts(), Some(20));
+        cm.unlock_keys(&[k2.clone()], 25);
+
+        match cm.read_key_check(&k1, 30) {
+            Err(Error::KeyIsLocked {
+                key, primary, ts, ..
+            }) => {
+                assert_eq!(key, b"k1".to_vec());
+                assert_eq!(primary, b"k1".to_vec());
+                assert_eq!(ts, 20);
+            }
+            res => panic!("expect key is locked, got {:?}", res),
+        }
+        // Locks with larger ts don't block readers.
+        cm.read_key_check(&k1, 15).unwrap();
+        cm.read_key_check(&k2, 30).unwrap();
+
+        assert!(cm.read_range_check(Some(&k2), None, 30).is_err());
+        assert!(cm.read_range_check(None, Some(&k2), 30).is_err());
+        cm.read_range_check(Some(&k2), Some(&k3), 30).unwrap();
+        cm.read_range_check(Some(&k3), Some(&k2), 30).unwrap();
+
+        // Only the locks of the same ts are removed.
+        cm.unlock_keys(&[k1.clone()], 25);
+        assert!(cm.read_key_check(&k1, 30).is_err());
+        cm.unlock_keys(&[k1.clone(), k3.clone()], 20);
+        cm.read_key_check(&k1, 30).unwrap();
+        cm.read_range_check(None, None, 30).unwrap();
+        assert_eq!(cm.global_min_lock_ts(), None);
+    }
+}
@@ -0,0 +1,256 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deadlock detection for pessimistic transactions.
+//!
+//! Every time a pessimistic lock request has to wait for a lock held by another transaction,
+//! an edge `txn_ts -> lock_ts` is added to the wait-for graph. Before adding the edge, the graph
+//! is searched for a path from `lock_ts` back to `txn_ts`; if one exists, waiting would form a
+//! cycle, which means a deadlock.
+//!
+//! A deadlock may span the regions on different stores, so a store serving a cluster doesn't
+//! detect with its own table but sends the edges to the detector in `server::deadlock`.
+
+use std::time::{Duration, Instant};
+
+use kvproto::deadlock::WaitForEntry;
+
+use util::collections::{HashMap, HashSet};
+
+// The keys a transaction is waiting for on the locks of another one.
+struct Locks {
+    key_hashes: Vec<u64>,
+    // Refreshed every time the edge is registered.
+    last_detect_time: Instant,
+}
+
+impl Locks {
+    fn is_expired(&self, now: Instant, ttl: Option<Duration>) -> bool {
+        ttl.map_or(false, |ttl| now.duration_since(self.last_detect_time) >= ttl)
+    }
+}
+
+/// The wait-for graph of the transactions.
+pub struct DetectTable {
+    // txn_ts -> (lock_ts -> keys txn_ts is waiting for)
+    wait_for_map: HashMap<u64, HashMap<u64, Locks>>,
+    // The edges not registered again within the ttl are dropped, `None` means never.
+    ttl: Option<Duration>,
+    last_active_expire: Instant,
+}
+
+impl DetectTable {
+    pub fn new() -> DetectTable {
+        DetectTable {
+            wait_for_map: HashMap::default(),
+            ttl: None,
+            last_active_expire: Instant::now(),
+        }
+    }
+
+    /// Creates a table which drops the edges not registered again within `ttl`, in case the
+    /// requests cleaning them up are lost.
+    pub fn with_ttl(ttl: Duration) -> DetectTable {
+        let mut table = DetectTable::new();
+        table.ttl = Some(ttl);
+        table
+    }
+
+    /// Checks whether `txn_ts` waiting for the lock of `lock_ts` on the key hashed as `key_hash`
+    /// would cause a deadlock.
+    ///
+    /// Returns the key hash the lock holder is waiting for if there is a deadlock; otherwise
+    /// registers the edge and returns `None`.
+    pub fn detect(&mut self, txn_ts: u64, lock_ts: u64, key_hash: u64) -> Option<u64> {
+        let now = Instant::now();
+        self.active_expire(now);
+        if let Some(deadlock_key_hash) = self.do_detect(txn_ts, lock_ts, now) {
+            return Some(deadlock_key_hash);
+        }
+        self.register(txn_ts, lock_ts, key_hash);
+        None
+    }
+
+    fn do_detect(&self, txn_ts: u64, wait_for_ts: u64, now: Instant) -> Option<u64> {
+        let mut visited = HashSet::default();
+        let mut stack = vec![wait_for_ts];
+        while let Some(wait_for_ts) = stack.pop() {
+            if !visited.insert(wait_for_ts) {
+                continue;
+            }
+            let wait_for = match self.wait_for_map.get(&wait_for_ts) {
+                Some(wait_for) => wait_for,
+                None => continue,
+            };
+            for (lock_ts, locks) in wait_for {
+                if locks.is_expired(now, self.ttl) {
+                    continue;
+                }
+                if *lock_ts == txn_ts {
+                    return Some(locks.key_hashes[0]);
+                }
+                stack.push(*lock_ts);
+            }
+        }
+        None
+    }
+
+    /// Adds the edge `txn_ts -> lock_ts` for the given key without detecting.
+    pub fn register(&mut self, txn_ts: u64, lock_ts: u64, key_hash: u64) {
+        let now = Instant::now();
+        let locks = self.wait_for_map
+            .entry(txn_ts)
+            .or_insert_with(HashMap::default)
+            .entry(lock_ts)
+            .or_insert_with(|| {
+                Locks {
+                    key_hashes: vec![],
+                    last_detect_time: now,
+                }
+            });
+        if !locks.key_hashes.contains(&key_hash) {
+            locks.key_hashes.push(key_hash);
+        }
+        locks.last_detect_time = now;
+    }
+
+    // Drops the expired edges once in a ttl, they're skipped by the detection anyway.
+    fn active_expire(&mut self, now: Instant) {
+        let ttl = match self.ttl {
+            Some(ttl) => ttl,
+            None => return,
+        };
+        if now.duration_since(self.last_active_expire) < ttl {
+            return;
+        }
+        self.last_active_expire = now;
+        for wait_for in self.wait_for_map.values_mut() {
+            wait_for.retain(|_, locks| !locks.is_expired(now, Some(ttl)));
+        }
+        self.wait_for_map.retain(|_, wait_for| !wait_for.is_empty());
+    }
+
+    /// Removes the edge `txn_ts -> lock_ts` for the given key.
+    pub fn clean_up_wait_for(&mut self, txn_ts: u64, lock_ts: u64, key_hash: u64) {
+        let mut remove_txn = false;
+        if let Some(wait_for) = self.wait_for_map.get_mut(&txn_ts) {
+            let mut remove_lock = false;
+            if let Some(locks) = wait_for.get_mut(&lock_ts) {
+                locks.key_hashes.retain(|h| *h != key_hash);
+                remove_lock = locks.key_hashes.is_empty();
+            }
+            if remove_lock {
+                wait_for.remove(&lock_ts);
+            }
+            remove_txn = wait_for.is_empty();
+        }
+        if remove_txn {
+            self.wait_for_map.remove(&txn_ts);
+        }
+    }
+
+    /// Removes all the edges starting from `txn_ts`.
+    pub fn clean_up(&mut self, txn_ts: u64) {
+        self.wait_for_map.remove(&txn_ts);
+    }
+
+    /// Returns all the edges of the graph which aren't expired.
+    pub fn wait_for_entries(&self) -> Vec<WaitForEntry> {
+        let now = Instant::now();
+        let mut entries = vec![];
+        for (txn_ts, wait_for) in &self.wait_for_map {
+            for (lock_ts, locks) in wait_for {
+                if locks.is_expired(now, self.ttl) {
+                    continue;
+                }
+                for key_hash in &locks.key_hashes {
+                    let mut entry = WaitForEntry::new();
+                    entry.set_txn(*txn_ts);
+                    entry.set_wait_for_txn(*lock_ts);
+                    entry.set_key_hash(*key_hash);
+                    entries.push(entry);
+                }
+            }
+        }
+        entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.wait_for_map.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let mut table = DetectTable::new();
+
+        // Deadlock: 1 -> 2 -> 1
+        assert_eq!(table.detect(1, 2, 2), None);
+        assert_eq!(table.detect(2, 1, 1).unwrap(), 2);
+        // Deadlock: 1 -> 2 -> 3 -> 1
+        assert_eq!(table.detect(2, 3, 3), None);
+        assert_eq!(table.detect(3, 1, 1).unwrap(), 3);
+        table.clean_up(2);
+        assert_eq!(table.detect(3, 1, 1), None);
+
+        // Waiting for the same lock on different keys.
+        assert_eq!(table.detect(4, 5, 5), None);
+        assert_eq!(table.detect(4, 5, 6), None);
+        table.clean_up_wait_for(4, 5, 5);
+        assert_eq!(table.detect(5, 4, 4).unwrap(), 6);
+        table.clean_up_wait_for(4, 5, 6);
+        assert_eq!(table.detect(5, 4, 4), None);
+
+        let mut entries: Vec<_> = table
+            .wait_for_entries()
+            .into_iter()
+            .map(|e| (e.get_txn(), e.get_wait_for_txn(), e.get_key_hash()))
+            .collect();
+        entries.sort();
+        assert_eq!(entries, vec![(1, 2, 2), (3, 1, 1), (5, 4, 4)]);
+
+        table.clean_up(1);
+        table.clean_up(3);
+        table.clean_up(5);
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn test_detect_with_ttl() {
+        let mut table = DetectTable::with_ttl(Duration::from_millis(300));
+        assert_eq!(table.detect(1, 2, 2), None);
+        assert_eq!(table.detect(3, 4, 4), None);
+        thread::sleep(Duration::from_millis(150));
+        // The edge is refreshed by registering it again.
+        assert_eq!(table.detect(3, 4, 4), None);
+        thread::sleep(Duration::from_millis(200));
+
+        // 1 -> 2 is expired, so there is no deadlock.
+        assert_eq!(table.wait_for_entries().len(), 1);
+        assert_eq!(table.detect(2, 1, 1), None);
+        assert_eq!(table.detect(4, 3, 3).unwrap(), 4);
+        let mut entries: Vec<_> = table
+            .wait_for_entries()
+            .into_iter()
+            .map(|e| (e.get_txn(), e.get_wait_for_txn(), e.get_key_hash()))
+            .collect();
+        entries.sort();
+        assert_eq!(entries, vec![(2, 1, 1), (3, 4, 4)]);
+    }
+}
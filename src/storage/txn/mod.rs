@@ -14,10 +14,15 @@
 mod store;
 mod scheduler;
 mod latch;
+mod deadlock;
+mod waiter_manager;
+mod concurrency_manager;
 
 use std::error;
 use std::io::Error as IoError;
 
+pub use self::concurrency_manager::ConcurrencyManager;
+pub use self::deadlock::DetectTable;
 pub use self::scheduler::{Msg, Scheduler, GC_BATCH_SIZE, RESOLVE_LOCK_BATCH_SIZE};
 pub use self::store::{SnapshotStore, StoreScanner};
 
@@ -55,6 +60,10 @@ quick_error! {
             cause(err)
             description(err.description())
         }
+        MaxTimestampNotSynced {region_id: u64} {
+            description("max timestamp is not synced")
+            display("max timestamp of region {} is not synced", region_id)
+        }
         InvalidTxnTso {start_ts: u64, commit_ts: u64} {
             description("Invalid transaction tso")
             display("Invalid transaction tso with start_ts:{},commit_ts:{}",
@@ -77,6 +86,9 @@ impl Error {
                 start_ts: start_ts,
                 commit_ts: commit_ts,
             }),
+            Error::MaxTimestampNotSynced { region_id } => Some(Error::MaxTimestampNotSynced {
+                region_id: region_id,
+            }),
             Error::Other(_) | Error::ProtoBuf(_) | Error::Io(_) => None,
         }
     }
@@ -31,33 +31,44 @@
 //! is ensured by the transaction protocol implemented in the client library, which is transparent
 //! to the scheduler.
 
+use std::cmp;
 use std::fmt::{self, Debug, Formatter};
-use std::sync::mpsc::Receiver;
-use std::time::Duration;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
 use std::thread;
 use std::hash::{Hash, Hasher};
-use std::u64;
+use std::{mem, u64};
+use std::sync::Arc;
 
+use byteorder::{BigEndian, ByteOrder};
 use prometheus::HistogramTimer;
 use kvproto::kvrpcpb::{CommandPri, Context, LockInfo};
+use kvproto::deadlock::DeadlockRequestType;
 
-use storage::{Command, Engine, Error as StorageError, Result as StorageResult, ScanMode, Snapshot,
-              Statistics, StatisticsSummary, StorageCb};
-use storage::mvcc::{Error as MvccError, Lock as MvccLock, MvccReader, MvccTxn, Write, WriteType,
-                    MAX_TXN_WRITE_SIZE};
-use storage::{Key, KvPair, MvccInfo, Value, CMD_TAG_GC};
+use storage::{Command, Engine, Error as StorageError, PrewriteResult, Result as StorageResult,
+              ScanMode, Snapshot, Statistics, StatisticsSummary, StorageCb};
+use storage::mvcc::{Error as MvccError, Lock as MvccLock, MvccReader, MvccTxn,
+                    SecondaryLocksStatus, TxnStatus, Write, WriteType, MAX_TXN_WRITE_SIZE};
+use storage::{CfName, Key, KvPair, MvccInfo, Value, CMD_TAG_GC};
 use storage::engine::{self, Callback as EngineCallback, CbContext, Error as EngineError, Modify,
                       Result as EngineResult};
 use raftstore::store::engine::IterOption;
+use raftstore::store::LoadStats;
+use server::deadlock::Task as DetectorTask;
 use util::transport::{Error as TransportError, SyncSendCh};
 use util::threadpool::{Context as ThreadContext, ThreadPool, ThreadPoolBuilder};
 use util::time::SlowTimer;
 use util::collections::HashMap;
+use util::rocksdb::ttl;
+use util::worker::FutureScheduler;
 
 use super::Result;
 use super::Error;
+use super::concurrency_manager::ConcurrencyManager;
 use super::store::SnapshotStore;
 use super::latch::{Latches, Lock};
+use super::deadlock::DetectTable;
+use super::waiter_manager::{key_hash, Waiter, WaiterManager};
 use super::super::metrics::*;
 
 // TODO: make it configurable.
@@ -65,6 +76,9 @@ pub const GC_BATCH_SIZE: usize = 512;
 
 pub const RESOLVE_LOCK_BATCH_SIZE: usize = 512;
 
+// Raw counters are 8-byte big-endian signed integers.
+const COUNTER_LEN: usize = 8;
+
 /// Process result of a command.
 pub enum ProcessResult {
     Res,
@@ -74,8 +88,23 @@ pub enum ProcessResult {
     MvccStartTs { mvcc: Option<(Key, MvccInfo)> },
     Value { value: Option<Value> },
     Locks { locks: Vec<LockInfo> },
+    TTL { ttl: Option<u64> },
+    CompareAndSwap {
+        previous_value: Option<Value>,
+        succeed: bool,
+    },
+    Counter { value: i64 },
+    TxnStatus { txn_status: TxnStatus },
+    PrewriteResult { result: PrewriteResult },
+    SecondaryLocksStatus { status: SecondaryLocksStatus },
     NextCommand { cmd: Command },
     Failed { err: StorageError },
+    // The command meets a lock and should wait for it to be released.
+    WaitForLock {
+        lock_ts: u64,
+        key: Key,
+        err: StorageError,
+    },
 }
 
 type SnapshotResult = (Vec<u64>, CbContext, EngineResult<Box<Snapshot>>);
@@ -106,6 +135,13 @@ pub enum Msg {
         cb_ctx: CbContext,
         result: EngineResult<()>,
     },
+    // The waiter of `start_ts` leads to a deadlock, which is found by the detector.
+    Deadlock {
+        start_ts: u64,
+        lock_ts: u64,
+        key_hash: u64,
+        deadlock_key_hash: u64,
+    },
 }
 
 /// Debug for messages.
@@ -130,6 +166,9 @@ impl Debug for Msg {
                 write!(f, "WritePrepareFailed [cid={}, err={:?}]", cid, err)
             }
             Msg::WriteFinished { cid, .. } => write!(f, "WriteFinished [cid={}]", cid),
+            Msg::Deadlock {
+                start_ts, lock_ts, ..
+            } => write!(f, "Deadlock [start_ts={}, lock_ts={}]", start_ts, lock_ts),
         }
     }
 }
@@ -172,6 +211,39 @@ fn execute_callback(callback: StorageCb, pr: ProcessResult) {
             ProcessResult::Failed { err } => cb(Err(err)),
             _ => panic!("process result mismatch"),
         },
+        StorageCb::TTL(cb) => match pr {
+            ProcessResult::TTL { ttl } => cb(Ok(ttl)),
+            ProcessResult::Failed { err } => cb(Err(err)),
+            _ => panic!("process result mismatch"),
+        },
+        StorageCb::CompareAndSwap(cb) => match pr {
+            ProcessResult::CompareAndSwap {
+                previous_value,
+                succeed,
+            } => cb(Ok((previous_value, succeed))),
+            ProcessResult::Failed { err } => cb(Err(err)),
+            _ => panic!("process result mismatch"),
+        },
+        StorageCb::Counter(cb) => match pr {
+            ProcessResult::Counter { value } => cb(Ok(value)),
+            ProcessResult::Failed { err } => cb(Err(err)),
+            _ => panic!("process result mismatch"),
+        },
+        StorageCb::TxnStatus(cb) => match pr {
+            ProcessResult::TxnStatus { txn_status } => cb(Ok(txn_status)),
+            ProcessResult::Failed { err } => cb(Err(err)),
+            _ => panic!("process result mismatch"),
+        },
+        StorageCb::Prewrite(cb) => match pr {
+            ProcessResult::PrewriteResult { result } => cb(Ok(result)),
+            ProcessResult::Failed { err } => cb(Err(err)),
+            _ => panic!("process result mismatch"),
+        },
+        StorageCb::SecondaryLocksStatus(cb) => match pr {
+            ProcessResult::SecondaryLocksStatus { status } => cb(Ok(status)),
+            ProcessResult::Failed { err } => cb(Err(err)),
+            _ => panic!("process result mismatch"),
+        },
     }
 }
 
@@ -187,6 +259,11 @@ pub struct RunningCtx {
     latch_timer: Option<HistogramTimer>,
     _timer: HistogramTimer,
     slow_timer: SlowTimer,
+    // (lock_ts, key hashes) of the locks released by the command once it's written.
+    released_locks: Option<(u64, Vec<u64>)>,
+    // (start_ts, keys) of the in-memory locks added by an async-commit or 1PC prewrite, which
+    // are removed once the command finishes.
+    memory_locks: Option<(u64, Vec<Key>)>,
 }
 
 impl RunningCtx {
@@ -195,6 +272,7 @@ impl RunningCtx {
         let tag = cmd.tag();
         let ts = cmd.ts();
         let region_id = cmd.get_context().get_region_id();
+        let memory_locks = memory_locks(&cmd);
         RunningCtx {
             cid: cid,
             cmd: Some(cmd),
@@ -212,6 +290,8 @@ impl RunningCtx {
                 .with_label_values(&[tag])
                 .start_coarse_timer(),
             slow_timer: SlowTimer::new(),
+            released_locks: None,
+            memory_locks: memory_locks,
         }
     }
 }
@@ -317,6 +397,24 @@ pub struct Scheduler {
 
     // used to control write flow
     running_write_count: usize,
+
+    // pessimistic lock requests waiting for locks to be released
+    waiter_mgr: WaiterManager,
+    // The deadlock detector of the cluster, the local wait-for graph is used if it's `None`.
+    detector_scheduler: Option<FutureScheduler<DetectorTask>>,
+    detector: DetectTable,
+    wait_for_lock_timeout: Duration,
+
+    // whether raw values carry an expire time
+    enable_ttl: bool,
+
+    concurrency_manager: Arc<ConcurrencyManager>,
+
+    // The sampled load of the reads, the writes are sampled by the raftstore. It's reported at
+    // the interval derived from the windows of load split, `None` if load split is disabled.
+    load_stats: LoadStats,
+    load_report_interval: Option<Duration>,
+    last_load_report: Instant,
 }
 
 // Make clippy happy.
@@ -358,12 +456,18 @@ fn find_mvcc_infos_by_key(
 
 impl Scheduler {
     /// Creates a scheduler.
+    #[allow(too_many_arguments)]
     pub fn new(
         engine: Box<Engine>,
         schedch: SyncSendCh<Msg>,
+        concurrency_manager: Arc<ConcurrencyManager>,
         concurrency: usize,
         worker_pool_size: usize,
         sched_too_busy_threshold: usize,
+        wait_for_lock_timeout: Duration,
+        detector_scheduler: Option<FutureScheduler<DetectorTask>>,
+        enable_ttl: bool,
+        load_report_interval: Option<Duration>,
     ) -> Scheduler {
         Scheduler {
             engine: engine,
@@ -384,6 +488,15 @@ impl Scheduler {
             ).build(),
             has_gc_command: false,
             running_write_count: 0,
+            waiter_mgr: WaiterManager::new(),
+            detector_scheduler: detector_scheduler,
+            detector: DetectTable::new(),
+            wait_for_lock_timeout: wait_for_lock_timeout,
+            enable_ttl: enable_ttl,
+            concurrency_manager: concurrency_manager,
+            load_stats: LoadStats::default(),
+            load_report_interval: load_report_interval,
+            last_load_report: Instant::now(),
         }
     }
 }
@@ -395,6 +508,7 @@ fn process_read(
     mut cmd: Command,
     ch: SyncSendCh<Msg>,
     snapshot: Box<Snapshot>,
+    enable_ttl: bool,
 ) -> Statistics {
     debug!("process read cmd(cid={}) in worker pool.", cid);
     SCHED_WORKER_COUNTER_VEC
@@ -462,10 +576,12 @@ fn process_read(
                 },
             }
         }
-        // Scans a range starting with `start_key` up to `limit` rows from the snapshot.
+        // Scans a range starting with `start_key` up to `limit` rows from the snapshot, in
+        // descending order if `options.reverse_scan` is set.
         Command::Scan {
             ref ctx,
             ref start_key,
+            ref end_key,
             limit,
             start_ts,
             ref options,
@@ -477,9 +593,22 @@ fn process_read(
                 ctx.get_isolation_level(),
                 !ctx.get_not_fill_cache(),
             );
+            let (mode, upper_bound) = if options.reverse_scan {
+                (ScanMode::Backward, None)
+            } else {
+                (
+                    ScanMode::Forward,
+                    end_key.as_ref().map(|k| k.encoded().clone()),
+                )
+            };
             let res = snap_store
-                .scanner(ScanMode::Forward, options.key_only, None, &mut statistics)
-                .and_then(|mut scanner| scanner.scan(start_key.clone(), limit))
+                .scanner(mode, options.key_only, upper_bound, &mut statistics)
+                .and_then(|mut scanner| if options.reverse_scan {
+                    scanner.set_lower_bound(end_key.clone());
+                    scanner.reverse_scan(start_key.clone(), limit)
+                } else {
+                    scanner.scan(start_key.clone(), limit)
+                })
                 .and_then(|mut results| {
                     KV_COMMAND_KEYREAD_HISTOGRAM_VEC
                         .with_label_values(&[tag])
@@ -685,22 +814,74 @@ fn process_read(
                 Err(e) => ProcessResult::Failed { err: e.into() },
             }
         }
-        Command::RawGet { ref key, .. } => {
+        Command::RawGet { cf, ref key, .. } => {
             KV_COMMAND_KEYREAD_HISTOGRAM_VEC
                 .with_label_values(&[tag])
                 .observe(1f64);
-            match snapshot.get(key) {
+            match process_rawget(snapshot.as_ref(), cf, key, enable_ttl) {
                 Ok(val) => ProcessResult::Value { value: val },
-                Err(e) => ProcessResult::Failed {
-                    err: StorageError::from(e),
-                },
+                Err(e) => ProcessResult::Failed { err: e },
+            }
+        }
+        Command::RawBatchGet { cf, ref keys, .. } => {
+            KV_COMMAND_KEYREAD_HISTOGRAM_VEC
+                .with_label_values(&[tag])
+                .observe(keys.len() as f64);
+            match process_raw_batch_get(snapshot.as_ref(), cf, keys, enable_ttl) {
+                Ok(pairs) => ProcessResult::MultiKvpairs { pairs: pairs },
+                Err(e) => ProcessResult::Failed { err: e },
+            }
+        }
+        Command::RawGetKeyTTL { cf, ref key, .. } => {
+            KV_COMMAND_KEYREAD_HISTOGRAM_VEC
+                .with_label_values(&[tag])
+                .observe(1f64);
+            match process_raw_get_key_ttl(snapshot.as_ref(), cf, key, enable_ttl) {
+                Ok(ttl) => ProcessResult::TTL { ttl: ttl },
+                Err(e) => ProcessResult::Failed { err: e },
             }
         }
         Command::RawScan {
+            cf,
             ref start_key,
+            ref end_key,
+            limit,
+            key_only,
+            reverse,
+            ..
+        } => match process_rawscan(
+            snapshot.as_ref(),
+            cf,
+            start_key,
+            end_key.as_ref(),
             limit,
+            key_only,
+            reverse,
+            enable_ttl,
+            &mut statistics,
+        ) {
+            Ok(val) => ProcessResult::MultiKvpairs { pairs: val },
+            Err(e) => ProcessResult::Failed {
+                err: StorageError::from(e),
+            },
+        },
+        Command::RawBatchScan {
+            cf,
+            ref ranges,
+            each_limit,
+            key_only,
+            reverse,
             ..
-        } => match process_rawscan(snapshot, start_key, limit, &mut statistics) {
+        } => match process_raw_batch_scan(
+            snapshot.as_ref(),
+            cf,
+            ranges,
+            each_limit,
+            key_only,
+            reverse,
+            enable_ttl,
+            &mut statistics,
+        ) {
             Ok(val) => ProcessResult::MultiKvpairs { pairs: val },
             Err(e) => ProcessResult::Failed {
                 err: StorageError::from(e),
@@ -720,24 +901,179 @@ fn process_read(
     statistics
 }
 
+/// Strips the expire time from the raw value if ttl is enabled, returns `None` if it's expired.
+fn decode_raw_value(value: Value, enable_ttl: bool, now: u64) -> Result<Option<Value>> {
+    if !enable_ttl {
+        return Ok(Some(value));
+    }
+    ttl::decode_value(value, now).map_err(|e| box_err!("failed to decode raw value: {:?}", e))
+}
+
+fn process_rawget(
+    snapshot: &Snapshot,
+    cf: CfName,
+    key: &Key,
+    enable_ttl: bool,
+) -> StorageResult<Option<Value>> {
+    match try!(snapshot.get_cf(cf, key)) {
+        Some(val) => Ok(try!(decode_raw_value(val, enable_ttl, ttl::current_ts()))),
+        None => Ok(None),
+    }
+}
+
+/// Gets the raw keys in one snapshot, fails as a whole if any of the keys is not in the region.
+fn process_raw_batch_get(
+    snapshot: &Snapshot,
+    cf: CfName,
+    keys: &[Key],
+    enable_ttl: bool,
+) -> StorageResult<Vec<StorageResult<KvPair>>> {
+    let now = ttl::current_ts();
+    let mut pairs = Vec::with_capacity(keys.len());
+    for key in keys {
+        let value = match try!(snapshot.get_cf(cf, key)) {
+            Some(val) => decode_raw_value(val, enable_ttl, now).map_err(StorageError::from),
+            None => continue,
+        };
+        match value {
+            Ok(Some(value)) => pairs.push(Ok((key.encoded().to_owned(), value))),
+            Ok(None) => {}
+            Err(e) => pairs.push(Err(e)),
+        }
+    }
+    Ok(pairs)
+}
+
+fn process_raw_get_key_ttl(
+    snapshot: &Snapshot,
+    cf: CfName,
+    key: &Key,
+    enable_ttl: bool,
+) -> StorageResult<Option<u64>> {
+    if !enable_ttl {
+        return Err(box_err!("ttl is not enabled"));
+    }
+    match try!(snapshot.get_cf(cf, key)) {
+        Some(val) => ttl::remaining_ttl(&val, ttl::current_ts())
+            .map_err(|e| box_err!("failed to decode raw value: {:?}", e)),
+        None => Ok(None),
+    }
+}
+
+/// Scans at most `limit` raw keys in [`start_key`, `end_key`), or in [`end_key`, `start_key`)
+/// backward if `reverse` is set. An empty `start_key` of a reverse scan means the end of the
+/// region.
+#[allow(too_many_arguments)]
 fn process_rawscan(
-    snapshot: Box<Snapshot>,
+    snapshot: &Snapshot,
+    cf: CfName,
     start_key: &Key,
+    end_key: Option<&Key>,
     limit: usize,
+    key_only: bool,
+    reverse: bool,
+    enable_ttl: bool,
     stats: &mut Statistics,
 ) -> Result<Vec<StorageResult<KvPair>>> {
-    let mut cursor = try!(snapshot.iter(IterOption::default(), ScanMode::Forward));
-    if !try!(cursor.seek(start_key, &mut stats.data)) {
+    let mode = if reverse {
+        ScanMode::Backward
+    } else {
+        ScanMode::Forward
+    };
+    let mut cursor = try!(snapshot.iter_cf(cf, IterOption::default(), mode));
+    let statistics = stats.mut_cf_statistics(cf);
+    let found = if !reverse {
+        try!(cursor.seek(start_key, statistics))
+    } else if start_key.encoded().is_empty() {
+        cursor.seek_to_last(statistics)
+    } else {
+        try!(cursor.reverse_seek(start_key, statistics))
+    };
+    if !found {
         return Ok(vec![]);
     }
+    let now = ttl::current_ts();
     let mut pairs = vec![];
     while cursor.valid() && pairs.len() < limit {
-        pairs.push(Ok((cursor.key().to_owned(), cursor.value().to_owned())));
-        cursor.next(&mut stats.data);
+        if let Some(end_key) = end_key {
+            let key = cursor.key();
+            if (!reverse && key >= end_key.encoded().as_slice()) ||
+                (reverse && key < end_key.encoded().as_slice())
+            {
+                break;
+            }
+        }
+        // The value is still needed to check whether the key is expired.
+        let value = if key_only && !enable_ttl {
+            Ok(Some(vec![]))
+        } else {
+            decode_raw_value(cursor.value().to_owned(), enable_ttl, now)
+        };
+        match value {
+            Ok(Some(value)) => {
+                let value = if key_only { vec![] } else { value };
+                pairs.push(Ok((cursor.key().to_owned(), value)))
+            }
+            // Expired keys are invisible.
+            Ok(None) => {}
+            Err(e) => pairs.push(Err(StorageError::from(e))),
+        }
+        if reverse {
+            cursor.prev(statistics);
+        } else {
+            cursor.next(statistics);
+        }
+    }
+    Ok(pairs)
+}
+
+/// Scans the ranges one by one with at most `each_limit` keys per range.
+#[allow(too_many_arguments)]
+fn process_raw_batch_scan(
+    snapshot: &Snapshot,
+    cf: CfName,
+    ranges: &[(Key, Option<Key>)],
+    each_limit: usize,
+    key_only: bool,
+    reverse: bool,
+    enable_ttl: bool,
+    stats: &mut Statistics,
+) -> Result<Vec<StorageResult<KvPair>>> {
+    let mut pairs = vec![];
+    for &(ref start_key, ref end_key) in ranges {
+        pairs.extend(try!(process_rawscan(
+            snapshot,
+            cf,
+            start_key,
+            end_key.as_ref(),
+            each_limit,
+            key_only,
+            reverse,
+            enable_ttl,
+            stats,
+        )));
     }
     Ok(pairs)
 }
 
+/// Decodes the raw counter and its expire time, an expired counter is treated as 0 which
+/// never expires.
+fn decode_raw_counter(value: &[u8], enable_ttl: bool, now: u64) -> Result<(i64, u64)> {
+    let (counter, expire_ts) = if enable_ttl {
+        let (counter, expire_ts) = try!(ttl::split_expire_ts(value));
+        if ttl::is_expired(expire_ts, now) {
+            return Ok((0, 0));
+        }
+        (counter, expire_ts)
+    } else {
+        (value, 0)
+    };
+    if counter.len() != COUNTER_LEN {
+        return Err(box_err!("value {:?} is not a counter", counter));
+    }
+    Ok((BigEndian::read_i64(counter), expire_ts))
+}
+
 /// Processes a write command within a worker thread, then posts either a `WritePrepareFinished`
 /// message if successful or a `WritePrepareFailed` message back to the event loop.
 fn process_write(
@@ -745,12 +1081,22 @@ fn process_write(
     cmd: Command,
     ch: SyncSendCh<Msg>,
     snapshot: Box<Snapshot>,
+    enable_ttl: bool,
+    concurrency_manager: &ConcurrencyManager,
 ) -> Statistics {
     let mut statistics = Statistics::default();
     SCHED_WORKER_COUNTER_VEC
         .with_label_values(&[cmd.tag(), "write"])
         .inc();
-    if let Err(e) = process_write_impl(cid, cmd, ch.clone(), snapshot.as_ref(), &mut statistics) {
+    if let Err(e) = process_write_impl(
+        cid,
+        cmd,
+        ch.clone(),
+        snapshot.as_ref(),
+        enable_ttl,
+        concurrency_manager,
+        &mut statistics,
+    ) {
         if let Err(err) = ch.send(Msg::WritePrepareFailed { cid: cid, err: e }) {
             // Todo: if this happens, lock will hold for ever
             panic!(
@@ -763,11 +1109,14 @@ fn process_write(
     statistics
 }
 
+#[allow(too_many_arguments)]
 fn process_write_impl(
     cid: u64,
     mut cmd: Command,
     ch: SyncSendCh<Msg>,
     snapshot: &Snapshot,
+    enable_ttl: bool,
+    concurrency_manager: &ConcurrencyManager,
     statistics: &mut Statistics,
 ) -> Result<()> {
     let (pr, modifies, rows) = match cmd {
@@ -787,10 +1136,45 @@ fn process_write_impl(
                 ctx.get_isolation_level(),
                 !ctx.get_not_fill_cache(),
             );
+            // The in-memory locks must be added before reading the max read ts, see
+            // `ConcurrencyManager`. The commit ts of 1PC is decided the same way as the
+            // `min_commit_ts` of async commit.
+            let mut prewrite_options = None;
+            if options.use_async_commit || options.try_one_pc {
+                // The reads served by the previous leader may be missed until it's synced.
+                if !concurrency_manager.is_max_ts_synced(ctx.get_region_id()) {
+                    return Err(Error::MaxTimestampNotSynced {
+                        region_id: ctx.get_region_id(),
+                    });
+                }
+                let keys: Vec<&Key> = mutations.iter().map(|m| m.key()).collect();
+                let max_read_ts =
+                    concurrency_manager.lock_keys(&keys, primary, start_ts, options.lock_ttl);
+                let min_commit_ts =
+                    cmp::max(cmp::max(max_read_ts, start_ts), options.for_update_ts) + 1;
+                let mut opts = options.clone();
+                opts.min_commit_ts = cmp::max(min_commit_ts, options.min_commit_ts);
+                // Pessimistic transactions always use 2PC.
+                opts.try_one_pc = options.try_one_pc && options.for_update_ts == 0;
+                prewrite_options = Some(opts);
+            }
+            let options = prewrite_options.as_ref().unwrap_or(options);
+
             let mut locks = vec![];
             let rows = mutations.len();
-            for m in mutations {
-                match txn.prewrite(m.clone(), primary, options) {
+            let pessimistic = options.for_update_ts != 0;
+            for (i, m) in mutations.iter().enumerate() {
+                let res = if pessimistic {
+                    let is_pessimistic_lock = options
+                        .is_pessimistic_lock
+                        .get(i)
+                        .cloned()
+                        .unwrap_or(false);
+                    txn.pessimistic_prewrite(m.clone(), primary, is_pessimistic_lock, options)
+                } else {
+                    txn.prewrite(m.clone(), primary, options)
+                };
+                match res {
                     Ok(_) => {}
                     e @ Err(MvccError::KeyIsLocked { .. }) => {
                         locks.push(e.map_err(Error::from).map_err(StorageError::from));
@@ -799,12 +1183,80 @@ fn process_write_impl(
                 }
             }
             if locks.is_empty() {
-                let pr = ProcessResult::MultiRes { results: vec![] };
+                let mut result = PrewriteResult::default();
+                // Keys prewritten by an earlier 2PC request have locks already, in which case
+                // the transaction has to go on with 2PC.
+                if options.try_one_pc && txn.one_pc_locks_count() == rows {
+                    txn.commit_one_pc(options.min_commit_ts);
+                    result.one_pc_commit_ts = options.min_commit_ts;
+                } else {
+                    txn.fall_back_to_two_pc();
+                    if options.use_async_commit {
+                        result.min_commit_ts = options.min_commit_ts;
+                    }
+                }
+                let pr = ProcessResult::PrewriteResult { result: result };
                 (pr, txn.modifies(), rows)
             } else {
+                // Skip write stage if some keys are locked.
+                let pr = ProcessResult::PrewriteResult {
+                    result: PrewriteResult {
+                        locks: locks,
+                        ..Default::default()
+                    },
+                };
+                (pr, vec![], 0)
+            }
+        }
+        Command::AcquirePessimisticLock {
+            ref ctx,
+            ref keys,
+            ref primary,
+            start_ts,
+            ref options,
+        } => {
+            let mut txn = MvccTxn::new(
+                snapshot,
+                statistics,
+                start_ts,
+                None,
+                ctx.get_isolation_level(),
+                !ctx.get_not_fill_cache(),
+            );
+            let mut locks = vec![];
+            let mut wait_for = None;
+            let rows = keys.len();
+            for k in keys {
+                match txn.acquire_pessimistic_lock(k.clone(), primary, options) {
+                    Ok(_) => {}
+                    e @ Err(MvccError::KeyIsLocked { .. }) => {
+                        if let Err(MvccError::KeyIsLocked { ts, .. }) = e {
+                            if wait_for.is_none() {
+                                wait_for = Some((ts, k.clone()));
+                            }
+                        }
+                        locks.push(e.map_err(Error::from).map_err(StorageError::from));
+                    }
+                    Err(e) => return Err(Error::from(e)),
+                }
+            }
+            if locks.is_empty() {
+                let pr = ProcessResult::MultiRes { results: vec![] };
+                (pr, txn.modifies(), rows)
+            } else if options.wait_timeout < 0 {
                 // Skip write stage if some keys are locked.
                 let pr = ProcessResult::MultiRes { results: locks };
                 (pr, vec![], 0)
+            } else {
+                // Wait for the first conflicting lock, the other keys are checked again
+                // when the command is woken up.
+                let (lock_ts, key) = wait_for.unwrap();
+                let pr = ProcessResult::WaitForLock {
+                    lock_ts: lock_ts,
+                    key: key,
+                    err: locks.swap_remove(0).unwrap_err(),
+                };
+                (pr, vec![], 0)
             }
         }
         Command::Commit {
@@ -840,7 +1292,7 @@ fn process_write_impl(
             ref ctx,
             ref key,
             start_ts,
-            ..
+            current_ts,
         } => {
             let mut txn = MvccTxn::new(
                 snapshot,
@@ -850,11 +1302,71 @@ fn process_write_impl(
                 ctx.get_isolation_level(),
                 !ctx.get_not_fill_cache(),
             );
-            try!(txn.rollback(key));
+            try!(txn.cleanup(key, current_ts));
 
             let pr = ProcessResult::Res;
             (pr, txn.modifies(), 1)
         }
+        Command::TxnHeartBeat {
+            ref ctx,
+            ref primary_key,
+            start_ts,
+            advise_ttl,
+        } => {
+            let mut txn = MvccTxn::new(
+                snapshot,
+                statistics,
+                start_ts,
+                None,
+                ctx.get_isolation_level(),
+                !ctx.get_not_fill_cache(),
+            );
+            let lock = try!(txn.txn_heart_beat(primary_key.clone(), advise_ttl));
+
+            let pr = ProcessResult::TxnStatus {
+                txn_status: TxnStatus::Uncommitted { lock: lock },
+            };
+            (pr, txn.modifies(), 1)
+        }
+        Command::CheckTxnStatus {
+            ref ctx,
+            ref primary_key,
+            lock_ts,
+            current_ts,
+        } => {
+            let mut txn = MvccTxn::new(
+                snapshot,
+                statistics,
+                lock_ts,
+                None,
+                ctx.get_isolation_level(),
+                !ctx.get_not_fill_cache(),
+            );
+            let txn_status = try!(txn.check_txn_status(primary_key, current_ts));
+
+            let pr = ProcessResult::TxnStatus {
+                txn_status: txn_status,
+            };
+            (pr, txn.modifies(), 1)
+        }
+        Command::CheckSecondaryLocks {
+            ref ctx,
+            ref keys,
+            start_ts,
+        } => {
+            let mut txn = MvccTxn::new(
+                snapshot,
+                statistics,
+                start_ts,
+                None,
+                ctx.get_isolation_level(),
+                !ctx.get_not_fill_cache(),
+            );
+            let status = try!(txn.check_secondary_locks(keys));
+
+            let pr = ProcessResult::SecondaryLocksStatus { status: status };
+            (pr, txn.modifies(), keys.len())
+        }
         Command::Rollback {
             ref ctx,
             ref keys,
@@ -877,6 +1389,28 @@ fn process_write_impl(
             let pr = ProcessResult::Res;
             (pr, txn.modifies(), rows)
         }
+        Command::PessimisticRollback {
+            ref ctx,
+            ref keys,
+            start_ts,
+            for_update_ts,
+        } => {
+            let mut txn = MvccTxn::new(
+                snapshot,
+                statistics,
+                start_ts,
+                None,
+                ctx.get_isolation_level(),
+                !ctx.get_not_fill_cache(),
+            );
+            let rows = keys.len();
+            for k in keys {
+                try!(txn.pessimistic_rollback(k.clone(), for_update_ts));
+            }
+
+            let pr = ProcessResult::Res;
+            (pr, txn.modifies(), rows)
+        }
         Command::ResolveLock {
             ref ctx,
             start_ts,
@@ -904,7 +1438,14 @@ fn process_write_impl(
             let rows = keys.len();
             for k in keys {
                 match commit_ts {
-                    Some(ts) => try!(txn.commit(k, ts)),
+                    Some(ts) => match txn.commit(k, ts) {
+                        // Pessimistic locks left by a finished transaction carry no
+                        // data, just remove them.
+                        Err(MvccError::LockTypeNotMatch {
+                            pessimistic: true, ..
+                        }) => try!(txn.pessimistic_rollback(k.clone(), u64::MAX)),
+                        res => try!(res),
+                    },
                     None => try!(txn.rollback(k)),
                 }
                 if txn.write_size() >= MAX_TXN_WRITE_SIZE {
@@ -966,6 +1507,100 @@ fn process_write_impl(
                 (pr, txn.modifies(), rows)
             }
         }
+        // All the raw modifications of a command are written in one raft command, so they either
+        // take effect together or fail together, e.g. when some keys are not in the region.
+        Command::RawPut {
+            cf,
+            ref key,
+            ref mut value,
+            ..
+        } => {
+            let modifies = vec![Modify::Put(cf, key.clone(), mem::replace(value, vec![]))];
+            (ProcessResult::Res, modifies, 1)
+        }
+        Command::RawDelete { cf, ref key, .. } => {
+            (ProcessResult::Res, vec![Modify::Delete(cf, key.clone())], 1)
+        }
+        Command::RawBatchPut {
+            cf,
+            ref mut pairs,
+            ..
+        } => {
+            let rows = pairs.len();
+            let modifies = pairs
+                .drain(..)
+                .map(|(key, value)| Modify::Put(cf, key, value))
+                .collect();
+            (ProcessResult::Res, modifies, rows)
+        }
+        Command::RawBatchDelete {
+            cf,
+            ref mut keys,
+            ..
+        } => {
+            let rows = keys.len();
+            let modifies = keys.drain(..).map(|key| Modify::Delete(cf, key)).collect();
+            (ProcessResult::Res, modifies, rows)
+        }
+        Command::RawDeleteRange {
+            cf,
+            ref start_key,
+            ref end_key,
+            ..
+        } => {
+            let modifies = vec![Modify::DeleteRange(cf, start_key.clone(), end_key.clone())];
+            (ProcessResult::Res, modifies, 1)
+        }
+        // The latch of the key is held, so no other compare-and-swap or increment on it can
+        // happen between the read and the write.
+        Command::RawCompareAndSwap {
+            cf,
+            ref key,
+            ref previous_value,
+            ref mut value,
+            ..
+        } => {
+            let current = match try!(snapshot.get_cf(cf, key)) {
+                Some(val) => try!(decode_raw_value(val, enable_ttl, ttl::current_ts())),
+                None => None,
+            };
+            if current == *previous_value {
+                let modifies = vec![Modify::Put(cf, key.clone(), mem::replace(value, vec![]))];
+                let pr = ProcessResult::CompareAndSwap {
+                    previous_value: current,
+                    succeed: true,
+                };
+                (pr, modifies, 1)
+            } else {
+                let pr = ProcessResult::CompareAndSwap {
+                    previous_value: current,
+                    succeed: false,
+                };
+                (pr, vec![], 0)
+            }
+        }
+        Command::RawIncrement {
+            cf,
+            ref key,
+            delta,
+            ..
+        } => {
+            let (counter, expire_ts) = match try!(snapshot.get_cf(cf, key)) {
+                Some(val) => try!(decode_raw_counter(&val, enable_ttl, ttl::current_ts())),
+                None => (0, 0),
+            };
+            let counter = match counter.checked_add(delta) {
+                Some(counter) => counter,
+                None => return Err(box_err!("counter {} overflows by adding {}", counter, delta)),
+            };
+            let mut value = vec![0; COUNTER_LEN];
+            BigEndian::write_i64(&mut value, counter);
+            if enable_ttl {
+                ttl::append_expire_ts(&mut value, expire_ts);
+            }
+            let modifies = vec![Modify::Put(cf, key.clone(), value)];
+            (ProcessResult::Counter { value: counter }, modifies, 1)
+        }
         _ => panic!("unsupported write command"),
     };
 
@@ -1031,6 +1666,10 @@ impl Scheduler {
     fn remove_ctx(&mut self, cid: u64) -> RunningCtx {
         let ctx = self.cmd_ctxs.remove(&cid).unwrap();
         assert_eq!(ctx.cid, cid);
+        // The command is finished, so are the writes of its locks.
+        if let Some((start_ts, ref keys)) = ctx.memory_locks {
+            self.concurrency_manager.unlock_keys(keys, start_ts);
+        }
         if ctx.lock.is_write_lock() {
             self.running_write_count -= 1;
         }
@@ -1075,14 +1714,16 @@ impl Scheduler {
         let readcmd = cmd.readonly();
         let worker_pool = self.fetch_worker_pool(cmd.priority());
         let tag = cmd.tag();
+        let enable_ttl = self.enable_ttl;
+        let concurrency_manager = self.concurrency_manager.clone();
         if readcmd {
             worker_pool.execute(move |ctx: &mut ScheContext| {
-                let s = process_read(cid, cmd, ch, snapshot);
+                let s = process_read(cid, cmd, ch, snapshot, enable_ttl);
                 ctx.add_statistics(tag, &s);
             });
         } else {
             worker_pool.execute(move |ctx: &mut ScheContext| {
-                let s = process_write(cid, cmd, ch, snapshot);
+                let s = process_write(cid, cmd, ch, snapshot, enable_ttl, &concurrency_manager);
                 ctx.add_statistics(tag, &s);
             });
         }
@@ -1169,9 +1810,23 @@ impl Scheduler {
             return;
 
         }
+        if let Some(key) = cmd.read_key() {
+            if self.load_report_interval.is_some() {
+                let region_id = cmd.get_context().get_region_id();
+                self.load_stats.add_request(region_id, Some(key.encoded().as_slice()));
+            }
+        }
         self.schedule_command(cmd, callback);
     }
 
+    fn report_load(&mut self) {
+        self.last_load_report = Instant::now();
+        if !self.load_stats.is_empty() {
+            let stats = mem::replace(&mut self.load_stats, LoadStats::default());
+            self.engine.report_load(stats);
+        }
+    }
+
     /// Tries to acquire all the required latches for a command.
     ///
     /// Returns true if successful; returns false otherwise.
@@ -1367,6 +2022,13 @@ impl Scheduler {
         SCHED_STAGE_COUNTER_VEC
             .with_label_values(&[self.get_ctx_tag(cid), "write"])
             .inc();
+        let pr = match pr {
+            ProcessResult::WaitForLock { lock_ts, key, err } => {
+                return self.on_wait_for_lock(cid, cmd, lock_ts, key, err);
+            }
+            pr => pr,
+        };
+        self.cmd_ctxs.get_mut(&cid).unwrap().released_locks = released_locks(&cmd);
         if to_be_write.is_empty() {
             return self.on_write_finished(cid, pr, Ok(()));
         }
@@ -1389,6 +2051,11 @@ impl Scheduler {
         debug!("write finished for command, cid={}", cid);
         let mut ctx = self.remove_ctx(cid);
         let cb = ctx.callback.take().unwrap();
+        let released_locks = if result.is_ok() {
+            ctx.released_locks.take()
+        } else {
+            None
+        };
         let pr = match result {
             Ok(()) => pr,
             Err(e) => ProcessResult::Failed {
@@ -1405,6 +2072,151 @@ impl Scheduler {
         }
 
         self.release_lock(&ctx.lock, cid);
+        if let Some((lock_ts, key_hashes)) = released_locks {
+            self.wake_up(lock_ts, &key_hashes);
+        }
+    }
+
+    /// Event handler for a pessimistic lock request which meets a lock.
+    ///
+    /// Fails the command at once if waiting for the lock leads to a deadlock; otherwise parks it
+    /// in the wait queue until the lock is released or the wait times out.
+    fn on_wait_for_lock(
+        &mut self,
+        cid: u64,
+        cmd: Command,
+        lock_ts: u64,
+        key: Key,
+        err: StorageError,
+    ) {
+        let mut ctx = self.remove_ctx(cid);
+        let cb = ctx.callback.take().unwrap();
+        let start_ts = cmd.ts();
+        let hash = key_hash(&key);
+        // The detector of the cluster detects asynchronously, the waiter is failed by a
+        // `Deadlock` message if it finds one.
+        let deadlock_key_hash = match self.detector_scheduler {
+            Some(ref scheduler) => {
+                let task = DetectorTask::Detect {
+                    tp: DeadlockRequestType::Detect,
+                    txn_ts: start_ts,
+                    lock_ts: lock_ts,
+                    key_hash: hash,
+                };
+                if let Err(e) = scheduler.schedule(task) {
+                    error!("failed to schedule deadlock detection: {:?}", e);
+                }
+                None
+            }
+            None => self.detector.detect(start_ts, lock_ts, hash),
+        };
+        if let Some(deadlock_key_hash) = deadlock_key_hash {
+            SCHED_STAGE_COUNTER_VEC
+                .with_label_values(&[ctx.tag, "deadlock"])
+                .inc();
+            let pr = deadlock_result(start_ts, lock_ts, &key, deadlock_key_hash);
+            execute_callback(cb, pr);
+        } else {
+            SCHED_STAGE_COUNTER_VEC
+                .with_label_values(&[ctx.tag, "wait_for_lock"])
+                .inc();
+            let wait_timeout = match cmd {
+                Command::AcquirePessimisticLock { ref options, .. } => options.wait_timeout,
+                _ => unreachable!(),
+            };
+            let timeout = if wait_timeout > 0 {
+                cmp::min(
+                    Duration::from_millis(wait_timeout as u64),
+                    self.wait_for_lock_timeout,
+                )
+            } else {
+                self.wait_for_lock_timeout
+            };
+            self.waiter_mgr.add_waiter(Waiter {
+                start_ts: start_ts,
+                cmd: cmd,
+                cb: cb,
+                lock_ts: lock_ts,
+                key: key,
+                key_hash: hash,
+                err: err,
+                deadline: Instant::now() + timeout,
+            });
+            SCHED_WAITING_COMMANDS_GAUGE.set(self.waiter_mgr.len() as f64);
+        }
+
+        self.release_lock(&ctx.lock, cid);
+    }
+
+    /// Wakes up the commands waiting for the locks of `lock_ts` on the given keys.
+    ///
+    /// The commands are scheduled again in ascending order of their start ts.
+    fn wake_up(&mut self, lock_ts: u64, key_hashes: &[u64]) {
+        let waiters = self.waiter_mgr.remove_waiters(lock_ts, key_hashes);
+        if waiters.is_empty() {
+            return;
+        }
+        SCHED_WAITING_COMMANDS_GAUGE.set(self.waiter_mgr.len() as f64);
+        for waiter in waiters {
+            self.clean_up_wait_for(waiter.start_ts, lock_ts, waiter.key_hash);
+            SCHED_STAGE_COUNTER_VEC
+                .with_label_values(&[waiter.cmd.tag(), "wake_up"])
+                .inc();
+            self.schedule_command(waiter.cmd, waiter.cb);
+        }
+    }
+
+    /// Fails the waiting commands whose wait timeout has expired with the lock they met.
+    fn on_wait_for_lock_timeout(&mut self) {
+        let waiters = self.waiter_mgr.remove_expired(Instant::now());
+        if waiters.is_empty() {
+            return;
+        }
+        SCHED_WAITING_COMMANDS_GAUGE.set(self.waiter_mgr.len() as f64);
+        for waiter in waiters {
+            self.clean_up_wait_for(waiter.start_ts, waiter.lock_ts, waiter.key_hash);
+            SCHED_STAGE_COUNTER_VEC
+                .with_label_values(&[waiter.cmd.tag(), "wait_timeout"])
+                .inc();
+            let pr = ProcessResult::MultiRes {
+                results: vec![Err(waiter.err)],
+            };
+            execute_callback(waiter.cb, pr);
+        }
+    }
+
+    /// Fails the waiting command which leads to a deadlock found by the detector of the cluster.
+    ///
+    /// The command may be woken up or timed out already, then the deadlock is ignored.
+    fn on_deadlock(&mut self, start_ts: u64, lock_ts: u64, key_hash: u64, deadlock_key_hash: u64) {
+        let waiter = match self.waiter_mgr.remove_waiter(lock_ts, start_ts, key_hash) {
+            Some(waiter) => waiter,
+            None => return,
+        };
+        SCHED_WAITING_COMMANDS_GAUGE.set(self.waiter_mgr.len() as f64);
+        SCHED_STAGE_COUNTER_VEC
+            .with_label_values(&[waiter.cmd.tag(), "deadlock"])
+            .inc();
+        let pr = deadlock_result(start_ts, lock_ts, &waiter.key, deadlock_key_hash);
+        execute_callback(waiter.cb, pr);
+    }
+
+    // Removes the edge of a waiter from the wait-for graph once it stops waiting.
+    fn clean_up_wait_for(&mut self, start_ts: u64, lock_ts: u64, key_hash: u64) {
+        match self.detector_scheduler {
+            Some(ref scheduler) => {
+                let task = DetectorTask::Detect {
+                    tp: DeadlockRequestType::CleanUpWaitFor,
+                    txn_ts: start_ts,
+                    lock_ts: lock_ts,
+                    key_hash: key_hash,
+                };
+                if let Err(e) = scheduler.schedule(task) {
+                    error!("failed to schedule deadlock clean up: {:?}", e);
+                }
+            }
+            None => self.detector.clean_up_wait_for(start_ts, lock_ts, key_hash),
+        }
     }
 
     /// Releases all the latches held by a command.
@@ -1432,8 +2244,30 @@ impl Scheduler {
     pub fn run(&mut self, receiver: Receiver<Msg>) -> Result<()> {
         let mut msgs = Vec::with_capacity(CMD_BATCH_SIZE);
         loop {
-            let msg = box_try!(receiver.recv());
-            msgs.push(msg);
+            let mut deadline = self.waiter_mgr.next_deadline();
+            // The sampled load is reported in time even if no more command comes.
+            if let Some(interval) = self.load_report_interval {
+                if !self.load_stats.is_empty() {
+                    let report = self.last_load_report + interval;
+                    deadline = Some(deadline.map_or(report, |d| cmp::min(d, report)));
+                }
+            }
+            let timeout = deadline.map(|deadline| {
+                let now = Instant::now();
+                if deadline > now {
+                    deadline - now
+                } else {
+                    Duration::from_millis(0)
+                }
+            });
+            match timeout {
+                None => msgs.push(box_try!(receiver.recv())),
+                Some(timeout) => match receiver.recv_timeout(timeout) {
+                    Ok(msg) => msgs.push(msg),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(e) => return Err(box_err!("{:?}", e)),
+                },
+            }
             while let Ok(msg) = receiver.try_recv() {
                 msgs.push(msg);
                 if msgs.len() >= CMD_BATCH_SIZE {
@@ -1468,6 +2302,22 @@ impl Scheduler {
                     Msg::WriteFinished {
                         cid, pr, result, ..
                     } => self.on_write_finished(cid, pr, result),
+                    Msg::Deadlock {
+                        start_ts,
+                        lock_ts,
+                        key_hash,
+                        deadlock_key_hash,
+                    } => self.on_deadlock(start_ts, lock_ts, key_hash, deadlock_key_hash),
+                }
+            }
+
+            if !self.waiter_mgr.is_empty() {
+                self.on_wait_for_lock_timeout();
+            }
+
+            if let Some(interval) = self.load_report_interval {
+                if self.last_load_report.elapsed() >= interval {
+                    self.report_load();
                 }
             }
 
@@ -1504,20 +2354,118 @@ impl Scheduler {
 
 const CMD_BATCH_SIZE: usize = 256;
 
+// The result of a pessimistic lock request failed by a deadlock.
+fn deadlock_result(
+    start_ts: u64,
+    lock_ts: u64,
+    key: &Key,
+    deadlock_key_hash: u64,
+) -> ProcessResult {
+    let err = MvccError::Deadlock {
+        start_ts: start_ts,
+        lock_ts: lock_ts,
+        key: key.raw().unwrap_or_default(),
+        deadlock_key_hash: deadlock_key_hash,
+    };
+    ProcessResult::MultiRes {
+        results: vec![Err(StorageError::from(Error::from(err)))],
+    }
+}
+
+/// Returns the start ts and the key hashes of the locks released by a write command, which
+/// are used to wake up the commands waiting for them.
+fn released_locks(cmd: &Command) -> Option<(u64, Vec<u64>)> {
+    match *cmd {
+        Command::Commit {
+            lock_ts, ref keys, ..
+        } |
+        Command::Rollback {
+            start_ts: lock_ts,
+            ref keys,
+            ..
+        } |
+        Command::PessimisticRollback {
+            start_ts: lock_ts,
+            ref keys,
+            ..
+        } |
+        Command::ResolveLock {
+            start_ts: lock_ts,
+            ref keys,
+            ..
+        } |
+        Command::CheckSecondaryLocks {
+            start_ts: lock_ts,
+            ref keys,
+            ..
+        } if !keys.is_empty() =>
+        {
+            Some((lock_ts, keys.iter().map(key_hash).collect()))
+        }
+        Command::Cleanup {
+            start_ts, ref key, ..
+        } |
+        Command::CheckTxnStatus {
+            lock_ts: start_ts,
+            primary_key: ref key,
+            ..
+        } => Some((start_ts, vec![key_hash(key)])),
+        _ => None,
+    }
+}
+
+/// Returns the start ts and the keys of the in-memory locks of an async-commit or 1PC prewrite.
+fn memory_locks(cmd: &Command) -> Option<(u64, Vec<Key>)> {
+    match *cmd {
+        Command::Prewrite {
+            ref mutations,
+            start_ts,
+            ref options,
+            ..
+        } if options.use_async_commit || options.try_one_pc =>
+        {
+            Some((start_ts, mutations.iter().map(|m| m.key().clone()).collect()))
+        }
+        _ => None,
+    }
+}
+
 /// Generates the lock for a command.
 ///
 /// Basically, read-only commands require no latches, write commands require latches hashed
-/// by the referenced keys.
+/// by the referenced keys. The raw writes take the latches too, so they're serialized with the
+/// compare-and-swaps and increments on the same keys, except `RawDeleteRange` whose range can't
+/// be hashed.
 pub fn gen_command_lock(latches: &Latches, cmd: &Command) -> Lock {
     match *cmd {
         Command::Prewrite { ref mutations, .. } => {
             let keys: Vec<&Key> = mutations.iter().map(|x| x.key()).collect();
             latches.gen_lock(&keys)
         }
+        Command::AcquirePessimisticLock { ref keys, .. } |
         Command::Commit { ref keys, .. } |
         Command::Rollback { ref keys, .. } |
-        Command::ResolveLock { ref keys, .. } => latches.gen_lock(keys),
-        Command::Cleanup { ref key, .. } => latches.gen_lock(&[key]),
+        Command::PessimisticRollback { ref keys, .. } |
+        Command::CheckSecondaryLocks { ref keys, .. } |
+        Command::ResolveLock { ref keys, .. } |
+        Command::RawBatchDelete { ref keys, .. } => latches.gen_lock(keys),
+        Command::RawBatchPut { ref pairs, .. } => {
+            let keys: Vec<&Key> = pairs.iter().map(|&(ref key, _)| key).collect();
+            latches.gen_lock(&keys)
+        }
+        Command::Cleanup { ref key, .. } |
+        Command::TxnHeartBeat {
+            primary_key: ref key,
+            ..
+        } |
+        Command::CheckTxnStatus {
+            primary_key: ref key,
+            ..
+        } |
+        Command::RawPut { ref key, .. } |
+        Command::RawDelete { ref key, .. } |
+        Command::RawCompareAndSwap { ref key, .. } |
+        Command::RawIncrement { ref key, .. } => latches.gen_lock(&[key]),
         _ => Lock::new(vec![]),
     }
 }
@@ -1527,7 +2475,7 @@ mod tests {
     use super::*;
     use kvproto::kvrpcpb::Context;
     use storage::txn::latch::*;
-    use storage::{make_key, Command, Mutation, Options};
+    use storage::{make_key, Command, Mutation, Options, CF_DEFAULT};
 
     #[test]
     fn test_command_latches() {
@@ -1584,6 +2532,13 @@ mod tests {
                 start_ts: 10,
                 options: Options::default(),
             },
+            Command::AcquirePessimisticLock {
+                ctx: Context::new(),
+                keys: vec![make_key(b"k")],
+                primary: b"k".to_vec(),
+                start_ts: 10,
+                options: Options::default(),
+            },
             Command::Commit {
                 ctx: Context::new(),
                 keys: vec![make_key(b"k")],
@@ -1594,12 +2549,36 @@ mod tests {
                 ctx: Context::new(),
                 key: make_key(b"k"),
                 start_ts: 10,
+                current_ts: 0,
+            },
+            Command::TxnHeartBeat {
+                ctx: Context::new(),
+                primary_key: make_key(b"k"),
+                start_ts: 10,
+                advise_ttl: 100,
+            },
+            Command::CheckTxnStatus {
+                ctx: Context::new(),
+                primary_key: make_key(b"k"),
+                lock_ts: 10,
+                current_ts: 20,
+            },
+            Command::CheckSecondaryLocks {
+                ctx: Context::new(),
+                keys: vec![make_key(b"k")],
+                start_ts: 10,
             },
             Command::Rollback {
                 ctx: Context::new(),
                 keys: vec![make_key(b"k")],
                 start_ts: 10,
             },
+            Command::PessimisticRollback {
+                ctx: Context::new(),
+                keys: vec![make_key(b"k")],
+                start_ts: 10,
+                for_update_ts: 10,
+            },
             Command::ResolveLock {
                 ctx: Context::new(),
                 start_ts: 10,
@@ -1607,6 +2586,40 @@ mod tests {
                 scan_key: None,
                 keys: vec![make_key(b"k")],
             },
+            Command::RawPut {
+                ctx: Context::new(),
+                cf: CF_DEFAULT,
+                key: make_key(b"k"),
+                value: b"v".to_vec(),
+            },
+            Command::RawBatchPut {
+                ctx: Context::new(),
+                cf: CF_DEFAULT,
+                pairs: vec![(make_key(b"k"), b"v".to_vec())],
+            },
+            Command::RawDelete {
+                ctx: Context::new(),
+                cf: CF_DEFAULT,
+                key: make_key(b"k"),
+            },
+            Command::RawBatchDelete {
+                ctx: Context::new(),
+                cf: CF_DEFAULT,
+                keys: vec![make_key(b"k")],
+            },
+            Command::RawCompareAndSwap {
+                ctx: Context::new(),
+                cf: CF_DEFAULT,
+                key: make_key(b"k"),
+                previous_value: None,
+                value: b"v".to_vec(),
+            },
+            Command::RawIncrement {
+                ctx: Context::new(),
+                cf: CF_DEFAULT,
+                key: make_key(b"k"),
+                delta: 1,
+            },
         ];
 
         let mut latches = Latches::new(1024);
@@ -93,6 +93,7 @@ impl<'a> SnapshotStore<'a> {
         Ok(StoreScanner {
             reader: reader,
             start_ts: self.start_ts,
+            lower_bound: None,
         })
     }
 }
@@ -100,9 +101,23 @@ impl<'a> SnapshotStore<'a> {
 pub struct StoreScanner<'a> {
     reader: MvccReader<'a>,
     start_ts: u64,
+    lower_bound: Option<Key>,
 }
 
 impl<'a> StoreScanner<'a> {
+    /// Sets the inclusive lower bound of `reverse_scan`, the upper bound of `scan` is
+    /// passed to `SnapshotStore::scanner` instead.
+    pub fn set_lower_bound(&mut self, lower_bound: Option<Key>) {
+        self.lower_bound = lower_bound;
+    }
+
+    #[inline]
+    fn below_lower_bound(&self, key: &Key) -> bool {
+        self.lower_bound
+            .as_ref()
+            .map_or(false, |bound| key.encoded() < bound.encoded())
+    }
+
     pub fn seek(&mut self, key: Key) -> Result<Option<(Key, Value)>> {
         Ok(try!(self.reader.seek(key, self.start_ts)))
     }
@@ -144,16 +159,28 @@ impl<'a> StoreScanner<'a> {
         Ok(results)
     }
 
+    /// Scans at most `limit` keys before `key` in descending order. If `key` is empty, scans
+    /// from the end of the snapshot.
     pub fn reverse_scan(&mut self, mut key: Key, limit: usize) -> Result<Vec<Result<KvPair>>> {
         let mut results = vec![];
         while results.len() < limit {
             match self.reverse_seek(key) {
                 Ok(Some((k, v))) => {
+                    if self.below_lower_bound(&k) {
+                        break;
+                    }
                     results.push(Ok((try!(k.raw()), v)));
                     key = k;
                 }
                 Ok(None) => break,
-                Err(Error::Mvcc(e)) => key = try!(StoreScanner::handle_mvcc_err(e, &mut results)),
+                Err(Error::Mvcc(e)) => {
+                    key = try!(StoreScanner::handle_mvcc_err(e, &mut results));
+                    // The lock is out of the range, so is the error.
+                    if self.below_lower_bound(&key) {
+                        results.pop();
+                        break;
+                    }
+                }
                 Err(e) => return Err(e),
             }
         }
@@ -170,7 +197,7 @@ mod test {
     use kvproto::kvrpcpb::{Context, IsolationLevel};
     use super::SnapshotStore;
     use storage::mvcc::MvccTxn;
-    use storage::{make_key, KvPair, Mutation, Options, ScanMode, Statistics, Value, ALL_CFS};
+    use storage::{make_key, Key, KvPair, Mutation, Options, ScanMode, Statistics, Value, ALL_CFS};
     use storage::engine::{self, Engine, Snapshot, TEMP_DIR};
 
     const KEY_PREFIX: &str = "key_prefix";
@@ -346,6 +373,29 @@ mod test {
         assert_eq!(result, expect, "expect {:?}, but got {:?}", expect, result);
     }
 
+    #[test]
+    fn test_snapshot_store_reverse_scan_with_bound() {
+        let key_num = 100;
+        let store = TestStore::new(key_num);
+        let snapshot_store = store.store();
+        let mut statistics = Statistics::default();
+        let mut scanner = snapshot_store
+            .scanner(ScanMode::Backward, false, None, &mut statistics)
+            .unwrap();
+
+        // Scan from the last key down to the inclusive lower bound.
+        let half = (key_num / 2) as usize;
+        scanner.set_lower_bound(Some(make_key(store.keys[half].as_bytes())));
+        let result = scanner.reverse_scan(Key::from_encoded(vec![]), key_num as usize).unwrap();
+        let result: Vec<Option<KvPair>> = result.into_iter().map(Result::ok).collect();
+        let mut expect: Vec<Option<KvPair>> = store.keys[half..]
+            .into_iter()
+            .map(|k| Some((k.clone().into_bytes(), k.clone().into_bytes())))
+            .collect();
+        expect.reverse();
+        assert_eq!(result, expect, "expect {:?}, but got {:?}", expect, result);
+    }
+
     #[test]
     fn test_snapshot_store_seek() {
         let key_num = 100;
@@ -0,0 +1,240 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Lock wait queue for pessimistic transactions.
+//!
+//! A pessimistic lock request which meets a lock of another transaction is parked here instead
+//! of returning `KeyIsLocked` to the client immediately. It is woken up when the lock is released,
+//! or fails with the original error when its wait timeout expires.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::time::Instant;
+
+use storage::{Command, Error as StorageError, Key, StorageCb};
+use util::collections::HashMap;
+
+/// Calculates the hash of a key which identifies it in the wait queue and the deadlock detector.
+pub fn key_hash(key: &Key) -> u64 {
+    let mut s = DefaultHasher::new();
+    key.hash(&mut s);
+    s.finish()
+}
+
+/// A command waiting for a lock to be released.
+pub struct Waiter {
+    pub start_ts: u64,
+    pub cmd: Command,
+    pub cb: StorageCb,
+    pub lock_ts: u64,
+    pub key: Key,
+    pub key_hash: u64,
+    /// The error returned to the client if the wait times out.
+    pub err: StorageError,
+    pub deadline: Instant,
+}
+
+/// Keeps all the waiting commands, grouped by the start ts of the transaction holding the lock.
+#[derive(Default)]
+pub struct WaiterManager {
+    wait_table: HashMap<u64, Vec<Waiter>>,
+    count: usize,
+}
+
+impl WaiterManager {
+    pub fn new() -> WaiterManager {
+        WaiterManager::default()
+    }
+
+    pub fn add_waiter(&mut self, waiter: Waiter) {
+        self.wait_table
+            .entry(waiter.lock_ts)
+            .or_insert_with(Vec::new)
+            .push(waiter);
+        self.count += 1;
+    }
+
+    /// Removes the waiters waiting for any of the keys in `key_hashes` locked by `lock_ts`.
+    ///
+    /// The waiters are returned in ascending order of their start ts, so that the oldest
+    /// transaction gets the chance to acquire the lock first.
+    pub fn remove_waiters(&mut self, lock_ts: u64, key_hashes: &[u64]) -> Vec<Waiter> {
+        let waiters = match self.wait_table.remove(&lock_ts) {
+            Some(waiters) => waiters,
+            None => return vec![],
+        };
+        let (mut ready, pending): (Vec<_>, Vec<_>) = waiters
+            .into_iter()
+            .partition(|w| key_hashes.contains(&w.key_hash));
+        if !pending.is_empty() {
+            self.wait_table.insert(lock_ts, pending);
+        }
+        self.count -= ready.len();
+        ready.sort_by_key(|w| w.start_ts);
+        ready
+    }
+
+    /// Removes the waiter of `start_ts` waiting for the key hashed as `key_hash` locked by
+    /// `lock_ts`, which is failed by a deadlock.
+    pub fn remove_waiter(&mut self, lock_ts: u64, start_ts: u64, key_hash: u64) -> Option<Waiter> {
+        let (waiter, is_empty) = {
+            let waiters = match self.wait_table.get_mut(&lock_ts) {
+                Some(waiters) => waiters,
+                None => return None,
+            };
+            let idx = match waiters
+                .iter()
+                .position(|w| w.start_ts == start_ts && w.key_hash == key_hash)
+            {
+                Some(idx) => idx,
+                None => return None,
+            };
+            (waiters.swap_remove(idx), waiters.is_empty())
+        };
+        if is_empty {
+            self.wait_table.remove(&lock_ts);
+        }
+        self.count -= 1;
+        Some(waiter)
+    }
+
+    /// Removes the waiters whose deadline is not later than `now`.
+    pub fn remove_expired(&mut self, now: Instant) -> Vec<Waiter> {
+        let mut expired = Vec::new();
+        for waiters in self.wait_table.values_mut() {
+            let (timeout, pending): (Vec<_>, Vec<_>) = mem::replace(waiters, vec![])
+                .into_iter()
+                .partition(|w| w.deadline <= now);
+            *waiters = pending;
+            expired.extend(timeout);
+        }
+        self.wait_table.retain(|_, waiters| !waiters.is_empty());
+        self.count -= expired.len();
+        expired
+    }
+
+    /// Returns the earliest deadline of all the waiters.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.wait_table
+            .values()
+            .flat_map(|waiters| waiters.iter().map(|w| w.deadline))
+            .min()
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use kvproto::kvrpcpb::Context;
+
+    use storage::{Options, Result as StorageResult};
+    use storage::mvcc::Error as MvccError;
+    use storage::txn::Error as TxnError;
+    use super::*;
+
+    fn new_waiter(start_ts: u64, lock_ts: u64, key: &[u8], deadline: Instant) -> Waiter {
+        let key = Key::from_raw(key);
+        let hash = key_hash(&key);
+        Waiter {
+            start_ts: start_ts,
+            cmd: Command::AcquirePessimisticLock {
+                ctx: Context::new(),
+                keys: vec![key.clone()],
+                primary: key.raw().unwrap(),
+                start_ts: start_ts,
+                options: Options::default(),
+            },
+            cb: StorageCb::Booleans(box |_: StorageResult<Vec<StorageResult<()>>>| {}),
+            lock_ts: lock_ts,
+            key: key,
+            key_hash: hash,
+            err: StorageError::from(TxnError::from(MvccError::BadFormatLock)),
+            deadline: deadline,
+        }
+    }
+
+    #[test]
+    fn test_remove_waiters() {
+        let mut manager = WaiterManager::new();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        manager.add_waiter(new_waiter(30, 10, b"k1", deadline));
+        manager.add_waiter(new_waiter(20, 10, b"k1", deadline));
+        manager.add_waiter(new_waiter(40, 10, b"k2", deadline));
+        manager.add_waiter(new_waiter(25, 15, b"k1", deadline));
+        assert_eq!(manager.len(), 4);
+
+        assert!(manager.remove_waiters(11, &[key_hash(&Key::from_raw(b"k1"))]).is_empty());
+        let waiters = manager.remove_waiters(10, &[key_hash(&Key::from_raw(b"k1"))]);
+        let ts: Vec<u64> = waiters.iter().map(|w| w.start_ts).collect();
+        assert_eq!(ts, vec![20, 30]);
+        assert_eq!(manager.len(), 2);
+
+        let waiters = manager.remove_waiters(10, &[key_hash(&Key::from_raw(b"k2"))]);
+        assert_eq!(waiters.len(), 1);
+        assert_eq!(waiters[0].start_ts, 40);
+        assert_eq!(manager.len(), 1);
+    }
+
+    #[test]
+    fn test_remove_waiter() {
+        let mut manager = WaiterManager::new();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        manager.add_waiter(new_waiter(20, 10, b"k1", deadline));
+        manager.add_waiter(new_waiter(20, 10, b"k2", deadline));
+        let hash = key_hash(&Key::from_raw(b"k1"));
+        assert!(manager.remove_waiter(11, 20, hash).is_none());
+        assert!(manager.remove_waiter(10, 30, hash).is_none());
+        let waiter = manager.remove_waiter(10, 20, hash).unwrap();
+        assert_eq!(waiter.key, Key::from_raw(b"k1"));
+        assert_eq!(manager.len(), 1);
+        assert!(manager.remove_waiter(10, 20, hash).is_none());
+        let hash = key_hash(&Key::from_raw(b"k2"));
+        assert!(manager.remove_waiter(10, 20, hash).is_some());
+        assert!(manager.is_empty());
+        assert!(manager.next_deadline().is_none());
+    }
+
+    #[test]
+    fn test_remove_expired() {
+        let mut manager = WaiterManager::new();
+        assert!(manager.next_deadline().is_none());
+        let now = Instant::now();
+        let early = now + Duration::from_millis(100);
+        let late = now + Duration::from_secs(10);
+        manager.add_waiter(new_waiter(20, 10, b"k1", late));
+        manager.add_waiter(new_waiter(30, 10, b"k2", early));
+        manager.add_waiter(new_waiter(40, 15, b"k3", early));
+        assert_eq!(manager.next_deadline(), Some(early));
+
+        assert!(manager.remove_expired(now).is_empty());
+        let mut ts: Vec<u64> = manager
+            .remove_expired(early)
+            .into_iter()
+            .map(|w| w.start_ts)
+            .collect();
+        ts.sort();
+        assert_eq!(ts, vec![30, 40]);
+        assert_eq!(manager.next_deadline(), Some(late));
+        assert_eq!(manager.remove_expired(late).len(), 1);
+        assert!(manager.is_empty());
+    }
+}
@@ -0,0 +1,108 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::{self, Read};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Limits the IO rate by blocking the caller, 0 means no limit.
+pub struct IOLimiter {
+    bytes_per_sec: u64,
+    start: Instant,
+    // The bytes requested since `start`.
+    bytes: u64,
+}
+
+impl IOLimiter {
+    pub fn new(bytes_per_sec: u64) -> IOLimiter {
+        IOLimiter {
+            bytes_per_sec: bytes_per_sec,
+            start: Instant::now(),
+            bytes: 0,
+        }
+    }
+
+    /// Blocks until `bytes` can be consumed within the rate.
+    pub fn request(&mut self, bytes: u64) {
+        if self.bytes_per_sec == 0 {
+            return;
+        }
+        self.bytes += bytes;
+        let expected = Duration::from_millis(self.bytes * 1000 / self.bytes_per_sec);
+        let elapsed = self.start.elapsed();
+        if expected > elapsed {
+            thread::sleep(expected - elapsed);
+        } else if elapsed - expected > Duration::from_secs(1) {
+            // The quota saved while being idle is dropped, or it bursts.
+            self.start = Instant::now();
+            self.bytes = 0;
+        }
+    }
+}
+
+/// A reader whose read rate is limited by the limiter.
+pub struct LimitReader<'a, R> {
+    limiter: &'a mut IOLimiter,
+    inner: R,
+}
+
+impl<'a, R: Read> LimitReader<'a, R> {
+    pub fn new(limiter: &'a mut IOLimiter, inner: R) -> LimitReader<'a, R> {
+        LimitReader {
+            limiter: limiter,
+            inner: inner,
+        }
+    }
+}
+
+impl<'a, R: Read> Read for LimitReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = try!(self.inner.read(buf));
+        self.limiter.request(n as u64);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    #[test]
+    fn test_io_limiter() {
+        let mut limiter = IOLimiter::new(0);
+        let start = Instant::now();
+        limiter.request(1024 * 1024 * 1024);
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        let mut limiter = IOLimiter::new(1024 * 1024);
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.request(100 * 1024);
+        }
+        assert!(start.elapsed() >= Duration::from_millis(250));
+
+        let data = vec![0; 200 * 1024];
+        let mut limiter = IOLimiter::new(1024 * 1024);
+        let start = Instant::now();
+        let mut buf = vec![];
+        {
+            let mut reader = LimitReader::new(&mut limiter, data.as_slice());
+            reader.read_to_end(&mut buf).unwrap();
+        }
+        assert_eq!(buf, data);
+        assert!(start.elapsed() >= Duration::from_millis(150));
+    }
+}
@@ -42,6 +42,7 @@ pub mod metrics;
 pub mod threadpool;
 pub mod collections;
 pub mod time;
+pub mod io_limiter;
 
 pub use self::rocksdb::properties;
 
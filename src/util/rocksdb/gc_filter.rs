@@ -0,0 +1,299 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! GC of MVCC versions during the compaction of the write cf.
+//!
+//! `GcCompactionFilter` drops the write records which `MvccTxn::gc` would delete with the
+//! current GC safe point. The versions of a key are compacted from the newest to the oldest,
+//! so once the latest `Put` or `Delete` before the safe point is met, all the older versions
+//! of the key are dropped. Values in the default cf can't be deleted during the compaction of
+//! the write cf, so they are sent to a worker and deleted in batches.
+//!
+//! Every replica drops the versions when its own files are compacted, outside raft, so the
+//! replicas may differ in the versions before the safe point for a while. They never differ in
+//! what a read at or after the safe point sees, which is the same as after `MvccTxn::gc`.
+//!
+//! The filters of a DB share a `GcContext`, they keep everything until the context is started
+//! and a safe point is set.
+
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use rocksdb::{CompactionFilter, Writable, WriteBatch, DB};
+
+use raftstore::store::keys;
+use storage::{split_encoded_key_on_ts, Key, CF_DEFAULT};
+use storage::mvcc::{Write, WriteType};
+use util::rocksdb::get_cf_handle;
+use util::worker::{BatchRunnable, Scheduler, Worker};
+
+const DELETE_BATCH_SIZE: usize = 256;
+
+#[derive(Default)]
+struct ContextInner {
+    safe_point: u64,
+    scheduler: Option<Scheduler<Task>>,
+}
+
+/// The GC safe point and the deleter of the default cf values, shared by the filters of the
+/// write cf of a DB. It's created with the options of the DB, and started once the DB is
+/// opened.
+#[derive(Clone, Default)]
+pub struct GcContext {
+    inner: Arc<Mutex<ContextInner>>,
+}
+
+impl GcContext {
+    pub fn new() -> GcContext {
+        GcContext::default()
+    }
+
+    /// Creates a filter for the write cf, which drops the versions with the context.
+    pub fn new_filter(&self) -> GcCompactionFilter {
+        GcCompactionFilter {
+            context: self.clone(),
+            state: Mutex::new(FilterState::default()),
+        }
+    }
+
+    /// Starts the worker deleting the default cf values of the dropped versions in `db`.
+    pub fn start(&self, db: Arc<DB>) -> Result<Worker<Task>, io::Error> {
+        let mut worker = Worker::new("gc-filter-deleter");
+        try!(worker.start_batch(Runner { db: db }, DELETE_BATCH_SIZE));
+        self.inner.lock().unwrap().scheduler = Some(worker.scheduler());
+        Ok(worker)
+    }
+
+    /// Updates the safe point used by the filters, it never goes back.
+    pub fn set_safe_point(&self, safe_point: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        if safe_point > inner.safe_point {
+            inner.safe_point = safe_point;
+        }
+    }
+}
+
+/// Deletes a value in the default cf, the key is a data key.
+pub struct Task {
+    key: Vec<u8>,
+}
+
+impl Display for Task {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "delete default value {:?}", self.key)
+    }
+}
+
+struct Runner {
+    db: Arc<DB>,
+}
+
+impl BatchRunnable<Task> for Runner {
+    fn run_batch(&mut self, tasks: &mut Vec<Task>) {
+        let handle = get_cf_handle(&self.db, CF_DEFAULT).unwrap();
+        let wb = WriteBatch::new();
+        for task in tasks.drain(..) {
+            wb.delete_cf(handle, &task.key).unwrap();
+        }
+        if let Err(e) = self.db.write(wb) {
+            error!("failed to delete default values: {:?}", e);
+        }
+    }
+}
+
+#[derive(Default)]
+struct FilterState {
+    // The encoded user key of the last version.
+    key: Vec<u8>,
+    commit_ts: u64,
+    // Whether the older versions of `key` can be dropped.
+    remove_older: bool,
+}
+
+pub struct GcCompactionFilter {
+    context: GcContext,
+    // Compactions may run concurrently with the same filter.
+    state: Mutex<FilterState>,
+}
+
+impl GcCompactionFilter {
+    // Returns whether the version should be dropped, and the default cf key to delete with it.
+    fn do_filter(&self, key: &[u8], value: &[u8], safe_point: u64) -> (bool, Option<Vec<u8>>) {
+        let (user_key, commit_ts) = match split_encoded_key_on_ts(keys::origin_key(key)) {
+            Ok(res) => res,
+            Err(e) => {
+                warn!("failed to decode write key {:?}: {:?}", key, e);
+                return (false, None);
+            }
+        };
+        let write = match Write::parse(value) {
+            Ok(write) => write,
+            Err(e) => {
+                warn!("failed to parse write of key {:?}: {:?}", key, e);
+                return (false, None);
+            }
+        };
+
+        let mut state = self.state.lock().unwrap();
+        // Versions of the same key come in descending order, otherwise it's another
+        // compaction or another key.
+        if state.key.as_slice() != user_key || commit_ts >= state.commit_ts {
+            state.key = user_key.to_vec();
+            state.remove_older = false;
+        }
+        state.commit_ts = commit_ts;
+
+        if state.remove_older {
+            let default_key = if write.write_type == WriteType::Put && write.short_value.is_none()
+            {
+                let k = Key::from_encoded(user_key.to_vec()).append_ts(write.start_ts);
+                Some(keys::data_key(k.encoded()))
+            } else {
+                None
+            };
+            return (true, default_key);
+        }
+        if commit_ts > safe_point {
+            return (false, None);
+        }
+        match write.write_type {
+            // The latest `Delete` is kept, or older versions in other files may show up again.
+            WriteType::Put | WriteType::Delete => {
+                state.remove_older = true;
+                (false, None)
+            }
+            WriteType::Rollback | WriteType::Lock => (true, None),
+        }
+    }
+}
+
+impl CompactionFilter for GcCompactionFilter {
+    fn filter(&mut self, _: usize, key: &[u8], value: &[u8]) -> bool {
+        if !keys::validate_data_key(key) {
+            return false;
+        }
+        let ctx = self.context.inner.lock().unwrap();
+        let scheduler = match ctx.scheduler {
+            Some(ref scheduler) if ctx.safe_point > 0 => scheduler,
+            _ => return false,
+        };
+        let (remove, default_key) = self.do_filter(key, value, ctx.safe_point);
+        if let Some(k) = default_key {
+            if let Err(e) = scheduler.schedule(Task { key: k }) {
+                // Keep the version so the value can be collected later.
+                warn!("failed to schedule deleting default value: {:?}", e);
+                return false;
+            }
+        }
+        remove
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocksdb::{ColumnFamilyOptions, DBOptions, Writable, DB};
+    use tempdir::TempDir;
+
+    use storage::{make_key, CF_WRITE};
+    use util::rocksdb::{self, CFOptions};
+    use super::*;
+
+    fn must_put_write(db: &DB, k: &[u8], commit_ts: u64, write: Write) {
+        let handle = get_cf_handle(db, CF_WRITE).unwrap();
+        let key = keys::data_key(make_key(k).append_ts(commit_ts).encoded());
+        db.put_cf(handle, &key, &write.to_bytes()).unwrap();
+    }
+
+    fn must_put_default(db: &DB, k: &[u8], start_ts: u64) {
+        let handle = get_cf_handle(db, CF_DEFAULT).unwrap();
+        let key = keys::data_key(make_key(k).append_ts(start_ts).encoded());
+        db.put_cf(handle, &key, b"long value").unwrap();
+    }
+
+    fn write_exists(db: &DB, k: &[u8], commit_ts: u64) -> bool {
+        let handle = get_cf_handle(db, CF_WRITE).unwrap();
+        let key = keys::data_key(make_key(k).append_ts(commit_ts).encoded());
+        db.get_cf(handle, &key).unwrap().is_some()
+    }
+
+    fn default_exists(db: &DB, k: &[u8], start_ts: u64) -> bool {
+        let handle = get_cf_handle(db, CF_DEFAULT).unwrap();
+        let key = keys::data_key(make_key(k).append_ts(start_ts).encoded());
+        db.get_cf(handle, &key).unwrap().is_some()
+    }
+
+    #[test]
+    fn test_gc_compaction_filter() {
+        let path = TempDir::new("_util_rocksdb_test_gc_compaction_filter").expect("");
+        let context = GcContext::new();
+        let mut cf_opts = ColumnFamilyOptions::new();
+        cf_opts
+            .set_compaction_filter("tikv.gc-compaction-filter", false, box context.new_filter())
+            .unwrap();
+        let db = Arc::new(
+            rocksdb::new_engine_opt(
+                path.path().to_str().unwrap(),
+                DBOptions::new(),
+                vec![
+                    CFOptions::new(CF_DEFAULT, ColumnFamilyOptions::new()),
+                    CFOptions::new(CF_WRITE, cf_opts),
+                ],
+            ).unwrap(),
+        );
+
+        let short = Some(b"v".to_vec());
+        // k1: Rollback and Lock before the latest Put are dropped, so are older versions.
+        must_put_default(&db, b"k1", 5);
+        must_put_write(&db, b"k1", 10, Write::new(WriteType::Put, 5, None));
+        must_put_write(&db, b"k1", 20, Write::new(WriteType::Put, 15, short.clone()));
+        must_put_write(&db, b"k1", 30, Write::new(WriteType::Lock, 25, None));
+        must_put_write(&db, b"k1", 35, Write::new(WriteType::Rollback, 35, None));
+        // k2: versions after the safe point are kept, the latest Delete is kept.
+        must_put_default(&db, b"k2", 5);
+        must_put_write(&db, b"k2", 10, Write::new(WriteType::Put, 5, None));
+        must_put_write(&db, b"k2", 20, Write::new(WriteType::Delete, 15, None));
+        must_put_write(&db, b"k2", 50, Write::new(WriteType::Put, 45, short.clone()));
+        // k3: only Rollback and Lock.
+        must_put_write(&db, b"k3", 10, Write::new(WriteType::Rollback, 10, None));
+        must_put_write(&db, b"k3", 50, Write::new(WriteType::Lock, 45, None));
+
+        // Nothing is dropped before the context is started.
+        let handle = get_cf_handle(&db, CF_WRITE).unwrap();
+        db.compact_range_cf(handle, None, None);
+        assert!(write_exists(&db, b"k3", 10));
+
+        // Nor before a safe point is set.
+        let mut worker = context.start(db.clone()).unwrap();
+        db.compact_range_cf(handle, None, None);
+        assert!(write_exists(&db, b"k3", 10));
+
+        context.set_safe_point(40);
+        db.compact_range_cf(handle, None, None);
+        worker.stop().unwrap().join().unwrap();
+
+        assert!(!write_exists(&db, b"k1", 10));
+        assert!(!default_exists(&db, b"k1", 5));
+        assert!(write_exists(&db, b"k1", 20));
+        assert!(!write_exists(&db, b"k1", 30));
+        assert!(!write_exists(&db, b"k1", 35));
+
+        assert!(!write_exists(&db, b"k2", 10));
+        assert!(!default_exists(&db, b"k2", 5));
+        assert!(write_exists(&db, b"k2", 20));
+        assert!(write_exists(&db, b"k2", 50));
+
+        assert!(!write_exists(&db, b"k3", 10));
+        assert!(write_exists(&db, b"k3", 50));
+    }
+}
@@ -15,6 +15,8 @@ pub mod properties;
 pub mod event_listener;
 pub mod engine_metrics;
 pub mod metrics_flusher;
+pub mod ttl;
+pub mod gc_filter;
 
 pub use self::event_listener::EventListener;
 pub use self::metrics_flusher::MetricsFlusher;
@@ -0,0 +1,174 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! TTL of raw keys.
+//!
+//! When TTL is enabled, every raw value is followed by an 8-byte expire time in unix
+//! seconds, and an expire time of 0 means the key never expires. Expired keys are hidden
+//! from reads and dropped physically by `TTLCompactionFilter`.
+//!
+//! Values written by transactions don't carry the expire time, so TTL can only be enabled
+//! for clusters serving raw requests only.
+
+use time;
+use rocksdb::CompactionFilter;
+
+use raftstore::store::keys;
+use util::codec::{Error, Result};
+use util::codec::number::{NumberDecoder, NumberEncoder};
+
+const EXPIRE_TS_LEN: usize = 8;
+
+pub fn current_ts() -> u64 {
+    time::get_time().sec as u64
+}
+
+/// Converts the ttl in seconds to the expire time, 0 means no ttl.
+pub fn ttl_to_expire_ts(ttl: u64) -> u64 {
+    if ttl == 0 {
+        0
+    } else {
+        current_ts().saturating_add(ttl)
+    }
+}
+
+pub fn append_expire_ts(value: &mut Vec<u8>, expire_ts: u64) {
+    value.encode_u64(expire_ts).unwrap();
+}
+
+/// Splits the value into the user value and its expire time.
+pub fn split_expire_ts(value: &[u8]) -> Result<(&[u8], u64)> {
+    if value.len() < EXPIRE_TS_LEN {
+        return Err(Error::InvalidDataType(format!(
+            "value with ttl is too short: {}",
+            value.len()
+        )));
+    }
+    let (user_value, mut ts) = value.split_at(value.len() - EXPIRE_TS_LEN);
+    let expire_ts = try!(ts.decode_u64());
+    Ok((user_value, expire_ts))
+}
+
+#[inline]
+pub fn is_expired(expire_ts: u64, now: u64) -> bool {
+    expire_ts != 0 && expire_ts <= now
+}
+
+/// Strips the expire time from the value, returns `None` if the value is expired.
+pub fn decode_value(mut value: Vec<u8>, now: u64) -> Result<Option<Vec<u8>>> {
+    let (len, expire_ts) = {
+        let (user_value, expire_ts) = try!(split_expire_ts(&value));
+        (user_value.len(), expire_ts)
+    };
+    if is_expired(expire_ts, now) {
+        return Ok(None);
+    }
+    value.truncate(len);
+    Ok(Some(value))
+}
+
+/// Returns the remaining ttl of the value, 0 means no ttl and `None` means it's expired.
+pub fn remaining_ttl(value: &[u8], now: u64) -> Result<Option<u64>> {
+    let (_, expire_ts) = try!(split_expire_ts(value));
+    if expire_ts == 0 {
+        Ok(Some(0))
+    } else if is_expired(expire_ts, now) {
+        Ok(None)
+    } else {
+        Ok(Some(expire_ts - now))
+    }
+}
+
+/// Drops the expired raw keys during compaction.
+#[derive(Default)]
+pub struct TTLCompactionFilter;
+
+impl CompactionFilter for TTLCompactionFilter {
+    fn filter(&mut self, _: usize, key: &[u8], value: &[u8]) -> bool {
+        // Local keys, like the store ident, are also in the default cf.
+        if !key.starts_with(keys::DATA_PREFIX_KEY) {
+            return false;
+        }
+        match split_expire_ts(value) {
+            Ok((_, expire_ts)) => is_expired(expire_ts, current_ts()),
+            Err(e) => {
+                warn!("failed to decode ttl of key {:?}: {:?}", key, e);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocksdb::{ColumnFamilyOptions, DBOptions, Writable, DB};
+    use tempdir::TempDir;
+
+    use storage::CF_DEFAULT;
+    use util::rocksdb::{self, CFOptions};
+    use super::*;
+
+    #[test]
+    fn test_ttl_value() {
+        let now = current_ts();
+        let mut value = b"v".to_vec();
+        append_expire_ts(&mut value, 0);
+        assert_eq!(remaining_ttl(&value, now).unwrap(), Some(0));
+        assert_eq!(decode_value(value, now).unwrap(), Some(b"v".to_vec()));
+
+        let mut value = b"v".to_vec();
+        append_expire_ts(&mut value, now + 10);
+        assert_eq!(remaining_ttl(&value, now).unwrap(), Some(10));
+        assert_eq!(remaining_ttl(&value, now + 10).unwrap(), None);
+        assert_eq!(decode_value(value.clone(), now).unwrap(), Some(b"v".to_vec()));
+        assert_eq!(decode_value(value, now + 11).unwrap(), None);
+
+        assert!(split_expire_ts(b"short").is_err());
+        assert!(ttl_to_expire_ts(10) >= now + 10);
+        assert_eq!(ttl_to_expire_ts(0), 0);
+    }
+
+    #[test]
+    fn test_ttl_compaction_filter() {
+        let path = TempDir::new("_util_rocksdb_test_ttl_compaction_filter").expect("");
+        let mut cf_opts = ColumnFamilyOptions::new();
+        cf_opts
+            .set_compaction_filter("tikv.ttl-compaction-filter", false, box TTLCompactionFilter)
+            .unwrap();
+        let db = rocksdb::new_engine_opt(
+            path.path().to_str().unwrap(),
+            DBOptions::new(),
+            vec![CFOptions::new(CF_DEFAULT, cf_opts)],
+        ).unwrap();
+
+        let now = current_ts();
+        let kvs = vec![
+            (keys::data_key(b"k1"), 0),
+            (keys::data_key(b"k2"), now - 1),
+            (keys::data_key(b"k3"), now + 100),
+        ];
+        for &(ref k, expire_ts) in &kvs {
+            let mut value = b"v".to_vec();
+            append_expire_ts(&mut value, expire_ts);
+            db.put(k, &value).unwrap();
+        }
+        // Local keys are never dropped.
+        db.put(keys::STORE_IDENT_KEY, b"").unwrap();
+        db.compact_range(None, None);
+
+        assert!(db.get(&kvs[0].0).unwrap().is_some());
+        assert!(db.get(&kvs[1].0).unwrap().is_none());
+        assert!(db.get(&kvs[2].0).unwrap().is_some());
+        assert!(db.get(keys::STORE_IDENT_KEY).unwrap().is_some());
+    }
+}
@@ -20,6 +20,7 @@ use rocksdb::{CompactionPriority, DBCompressionType, DBRecoveryMode};
 use tikv::server::Config as ServerConfig;
 use tikv::raftstore::store::Config as RaftstoreConfig;
 use tikv::config::*;
+use tikv::raft_engine::Config as RaftEngineConfig;
 use tikv::storage::Config as StorageConfig;
 use tikv::util::config::{ReadableDuration, ReadableSize};
 
@@ -63,6 +64,7 @@ fn test_serde_custom_tikv_config() {
         grpc_stream_initial_window_size: ReadableSize(12_345),
         end_point_concurrency: 12,
         end_point_max_tasks: 12,
+        import_mode_timeout: ReadableDuration::secs(12),
     };
     value.metric = MetricConfig {
         interval: ReadableDuration::secs(12),
@@ -106,6 +108,15 @@ fn test_serde_custom_tikv_config() {
         raft_store_max_leader_lease: ReadableDuration::secs(12),
         right_derive_when_split: false,
         allow_remove_leader: true,
+        merge_max_log_gap: 3,
+        merge_check_tick_interval: ReadableDuration::secs(11),
+        hibernate_regions: true,
+        hibernate_wake_up_interval: ReadableDuration::secs(12),
+        advance_resolved_ts_interval: ReadableDuration::secs(3),
+        apply_pool_size: 4,
+        load_split_qps_threshold: 1000,
+        load_split_check_interval: ReadableDuration::secs(2),
+        load_split_detect_times: 5,
     };
     value.pd = PdConfig {
         endpoints: vec!["example.com:443".to_owned()],
@@ -313,6 +324,17 @@ fn test_serde_custom_tikv_config() {
         scheduler_concurrency: 123,
         scheduler_worker_pool_size: 1,
         scheduler_too_busy_threshold: 123,
+        wait_for_lock_timeout: ReadableDuration::secs(1),
+        enable_ttl: true,
+        gc_poll_safe_point_interval: ReadableDuration::minutes(1),
+        gc_max_regions_per_sec: 10,
+        gc_enable_compaction_filter: true,
+    };
+
+    value.raft_engine = RaftEngineConfig {
+        enable: true,
+        dir: "/var".to_owned(),
+        target_file_size: ReadableSize::mb(1),
     };
 
     let custom = read_file_in_project_dir("tests/config/test-custom.toml");
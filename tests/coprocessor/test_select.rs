@@ -13,7 +13,7 @@
 
 use std::collections::{BTreeMap, HashMap};
 use std::mem;
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::i64;
 use std::thread;
@@ -25,7 +25,7 @@ use kvproto::kvrpcpb::Context;
 use tikv::coprocessor::codec::{datum, table, Datum};
 use tikv::coprocessor::codec::datum::DatumDecoder;
 use tikv::util::codec::number::*;
-use tikv::storage::{Key, Mutation, ALL_CFS};
+use tikv::storage::{ConcurrencyManager, Key, Mutation, ALL_CFS};
 use tikv::server::Config;
 use tikv::storage::engine::{self, Engine, TEMP_DIR};
 use tikv::util::worker::Worker;
@@ -41,6 +41,7 @@ use storage::sync_storage::SyncStorage;
 use storage::util::new_raft_engine;
 use tikv::coprocessor::select::xeval::evaluator::FLAG_IGNORE_TRUNCATE;
 use tikv::coprocessor::Result as CopResult;
+use tikv::raftstore::store::LoadStats;
 
 static ID_GENERATOR: AtomicUsize = AtomicUsize::new(1);
 
@@ -71,6 +72,10 @@ impl CopSender for MockCopSender {
     fn send(&self, _stats: CopRequestStatistics) -> CopResult<()> {
         Ok(())
     }
+
+    fn send_load_stats(&self, _stats: LoadStats) -> CopResult<()> {
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -582,6 +587,10 @@ impl Store {
         self.store.get_engine()
     }
 
+    fn get_concurrency_manager(&self) -> Arc<ConcurrencyManager> {
+        self.store.get_concurrency_manager()
+    }
+
     fn begin(&mut self) {
         self.current_ts = next_id() as u64;
         self.handles.clear();
@@ -698,6 +707,7 @@ fn init_data_with_engine_and_commit(
     cfg.end_point_concurrency = 1;
     let runner = EndPointHost::new(
         store.get_engine(),
+        store.get_concurrency_manager(),
         end_point.scheduler(),
         &cfg,
         MockCopSender::new(),
@@ -0,0 +1,50 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Mutex;
+
+use kvproto::pdpb::{GetGCSafePointRequest, GetGCSafePointResponse, ResponseHeader};
+
+use super::*;
+
+/// Serves an adjustable GC safe point.
+#[derive(Debug)]
+pub struct GcSafePoint {
+    safe_point: Mutex<u64>,
+}
+
+impl GcSafePoint {
+    pub fn new() -> GcSafePoint {
+        GcSafePoint {
+            safe_point: Mutex::new(0),
+        }
+    }
+
+    pub fn set_safe_point(&self, safe_point: u64) {
+        *self.safe_point.lock().unwrap() = safe_point;
+    }
+}
+
+impl PdMocker for GcSafePoint {
+    fn get_gc_safe_point(
+        &self,
+        _: &GetGCSafePointRequest,
+    ) -> Option<Result<GetGCSafePointResponse>> {
+        let mut header = ResponseHeader::new();
+        header.set_cluster_id(DEFAULT_CLUSTER_ID);
+        let mut resp = GetGCSafePointResponse::new();
+        resp.set_header(header);
+        resp.set_safe_point(*self.safe_point.lock().unwrap());
+        Some(Ok(resp))
+    }
+}
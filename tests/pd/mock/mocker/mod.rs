@@ -20,12 +20,14 @@ mod split;
 mod bootstrap;
 mod leader_change;
 mod retry;
+mod gc_safe_point;
 
 pub use self::service::Service;
 pub use self::split::Split;
 pub use self::bootstrap::AlreadyBootstrapped;
 pub use self::leader_change::LeaderChange;
 pub use self::retry::Retry;
+pub use self::gc_safe_point::GcSafePoint;
 
 pub const DEFAULT_CLUSTER_ID: u64 = 42;
 
@@ -101,5 +103,12 @@ pub trait PdMocker {
         None
     }
 
+    fn get_gc_safe_point(
+        &self,
+        _: &GetGCSafePointRequest,
+    ) -> Option<Result<GetGCSafePointResponse>> {
+        None
+    }
+
     fn set_endpoints(&self, _: Vec<String>) {}
 }
@@ -267,4 +267,13 @@ impl<C: PdMocker + Send + Sync + 'static> Pd for PdMock<C> {
     ) {
         hijack_unary(self, ctx, sink, |c| c.put_cluster_config(&req))
     }
+
+    fn get_gc_safe_point(
+        &self,
+        ctx: RpcContext,
+        req: GetGCSafePointRequest,
+        sink: UnarySink<GetGCSafePointResponse>,
+    ) {
+        hijack_unary(self, ctx, sink, |c| c.get_gc_safe_point(&req))
+    }
 }
@@ -12,4 +12,5 @@
 // limitations under the License.
 
 mod test_rpc_client;
+mod test_gc_manager;
 mod mock;
@@ -0,0 +1,111 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::thread;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use kvproto::metapb;
+use kvproto::raft_serverpb::RegionLocalState;
+use tempdir::TempDir;
+
+use tikv::pd::RpcClient;
+use tikv::raftstore::store::engine::Mutable;
+use tikv::raftstore::store::keys;
+use tikv::server::GcManager;
+use tikv::storage::{make_key, ALL_CFS, CF_RAFT};
+use tikv::storage::config::Config;
+use tikv::util::collections::HashSet;
+use tikv::util::config::ReadableDuration;
+use tikv::util::rocksdb::{get_cf_handle, new_engine};
+
+use storage::assert_storage::AssertionStorage;
+use super::mock::mocker::*;
+use super::mock::Server as MockServer;
+
+#[test]
+fn test_gc_manager() {
+    let store_id = 1;
+    let mut peer = metapb::Peer::new();
+    peer.set_id(2);
+    peer.set_store_id(store_id);
+    let mut region = metapb::Region::new();
+    region.set_id(3);
+    region.mut_peers().push(peer);
+    // The manager reads the region from its local state.
+    let path = TempDir::new("test_gc_manager").unwrap();
+    let engine = Arc::new(new_engine(path.path().to_str().unwrap(), ALL_CFS).unwrap());
+    let mut region_state = RegionLocalState::new();
+    region_state.set_region(region);
+    let handle = get_cf_handle(&engine, CF_RAFT).unwrap();
+    let key = keys::region_state_key(3);
+    engine.put_msg_cf(handle, &key, &region_state).unwrap();
+
+    let sp = Arc::new(GcSafePoint::new());
+    let server = MockServer::run(1, Arc::new(Service::new()), Some(sp.clone()));
+    let eps: Vec<String> = server
+        .bind_addrs()
+        .into_iter()
+        .map(|addr| format!("{}:{}", addr.0, addr.1))
+        .collect();
+
+    thread::sleep(Duration::from_secs(1));
+
+    let client = Arc::new(RpcClient::new(&eps).unwrap());
+    let store = AssertionStorage::default();
+    let keys: Vec<Vec<u8>> = (0..10).map(|i| format!("k{}", i).into_bytes()).collect();
+    for k in &keys {
+        store.put_ok(k, b"v1", 5, 10);
+        store.put_ok(k, b"v2", 15, 20);
+    }
+
+    let mut cfg = Config::default();
+    cfg.gc_poll_safe_point_interval = ReadableDuration::millis(100);
+    cfg.gc_max_regions_per_sec = 0;
+    let mut leader_regions = HashSet::default();
+    leader_regions.insert(3);
+    let mut gc_manager = GcManager::new(
+        &cfg,
+        store_id,
+        store.store.get_storage(),
+        engine,
+        client,
+        Arc::new(RwLock::new(leader_regions)),
+    );
+    gc_manager.start().unwrap();
+
+    // Nothing is collected before the safe point advances.
+    thread::sleep(Duration::from_millis(500));
+    for k in &keys {
+        store.get_ok(k, 15, b"v1");
+    }
+
+    sp.set_safe_point(30);
+    let mut collected = false;
+    for _ in 0..50 {
+        thread::sleep(Duration::from_millis(100));
+        collected = keys.iter().all(|k| {
+            let res = store.store.get(store.ctx.clone(), &make_key(k), 15);
+            res.unwrap().is_none()
+        });
+        if collected {
+            break;
+        }
+    }
+    assert!(collected, "old versions should be collected");
+    for k in &keys {
+        store.get_ok(k, 25, b"v2");
+    }
+
+    gc_manager.stop();
+}
@@ -15,5 +15,6 @@
 mod test_raft;
 mod test_raft_snap;
 mod test_raft_paper;
+mod test_raft_joint;
 mod test_raft_flow_control;
 mod test_raw_node;
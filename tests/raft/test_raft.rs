@@ -94,6 +94,19 @@ pub fn new_test_raft(
     ))
 }
 
+pub fn new_test_learner_raft(
+    id: u64,
+    peers: Vec<u64>,
+    learners: Vec<u64>,
+    election: usize,
+    heartbeat: usize,
+    storage: MemStorage,
+) -> Interface {
+    let mut config = new_test_config(id, peers, election, heartbeat);
+    config.learners = learners;
+    new_test_raft_with_config(&config, storage)
+}
+
 pub fn new_test_raft_with_prevote(
     id: u64,
     peers: Vec<u64>,
@@ -192,11 +205,13 @@ impl Interface {
     fn initial(&mut self, id: u64, ids: &[u64]) {
         if self.raft.is_some() {
             self.id = id;
+            let learners = self.learner_nodes();
             self.prs = RaftFlatMap::with_capacity(ids.len());
             for id in ids {
                 self.prs.insert(
                     *id,
                     Progress {
+                        is_learner: learners.contains(id),
                         ..Default::default()
                     },
                 );
@@ -3014,6 +3029,208 @@ fn test_raft_nodes() {
     }
 }
 
+// test_add_learner tests that add_learner could update pending_conf and learner nodes
+// correctly.
+#[test]
+fn test_add_learner() {
+    let mut r = new_test_raft(1, vec![1], 10, 1, new_storage());
+    r.pending_conf = true;
+    r.add_learner(2);
+    assert!(!r.pending_conf);
+    assert_eq!(r.nodes(), vec![1]);
+    assert_eq!(r.learner_nodes(), vec![2]);
+    assert!(r.prs[&2].is_learner);
+
+    // A voter can't be changed to a learner.
+    r.add_learner(1);
+    assert_eq!(r.nodes(), vec![1]);
+    assert_eq!(r.learner_nodes(), vec![2]);
+}
+
+// test_remove_learner tests that remove_node could update pending_conf, nodes and learners
+// correctly.
+#[test]
+fn test_remove_learner() {
+    let mut r = new_test_learner_raft(1, vec![1], vec![2], 10, 1, new_storage());
+    r.pending_conf = true;
+    r.remove_node(2);
+    assert!(!r.pending_conf);
+    assert_eq!(r.nodes(), vec![1]);
+    assert!(r.learner_nodes().is_empty());
+
+    // remove all nodes from cluster
+    r.remove_node(1);
+    assert!(r.nodes().is_empty());
+}
+
+// test_learner_election_timeout verifies that the learner never starts an election
+// even after the election timeout.
+#[test]
+fn test_learner_election_timeout() {
+    let mut n2 = new_test_learner_raft(2, vec![1], vec![2], 10, 1, new_storage());
+    n2.become_follower(1, INVALID_ID);
+    assert!(!n2.promotable());
+
+    let timeout = n2.get_election_timeout();
+    n2.set_randomized_election_timeout(timeout);
+    for _ in 0..timeout {
+        n2.tick();
+    }
+    assert_eq!(n2.state, StateRole::Follower);
+    assert!(n2.read_messages().is_empty());
+
+    // A local MsgHup is ignored too.
+    n2.step(new_message(2, 2, MessageType::MsgHup, 0)).expect("");
+    assert_eq!(n2.state, StateRole::Follower);
+    assert!(n2.read_messages().is_empty());
+}
+
+// test_learner_promotion verifies that the learner can campaign after it's
+// promoted to a voter.
+#[test]
+fn test_learner_promotion() {
+    let mut n1 = new_test_learner_raft(1, vec![1], vec![2], 10, 1, new_storage());
+    let mut n2 = new_test_learner_raft(2, vec![1], vec![2], 10, 1, new_storage());
+    n1.become_follower(1, INVALID_ID);
+    n2.become_follower(1, INVALID_ID);
+
+    let mut network = Network::new(vec![Some(n1), Some(n2)]);
+    assert_eq!(network.peers[&1].state, StateRole::Follower);
+
+    // n1 should become leader.
+    let timeout = network.peers[&1].get_election_timeout();
+    network.peers.get_mut(&1).unwrap().set_randomized_election_timeout(timeout);
+    for _ in 0..timeout {
+        network.peers.get_mut(&1).unwrap().tick();
+    }
+    assert_eq!(network.peers[&1].state, StateRole::Leader);
+    assert_eq!(network.peers[&2].state, StateRole::Follower);
+
+    network.send(vec![new_message(1, 1, MessageType::MsgBeat, 0)]);
+
+    network.peers.get_mut(&1).unwrap().add_node(2);
+    network.peers.get_mut(&2).unwrap().add_node(2);
+    assert!(!network.peers[&2].is_learner);
+    assert!(network.peers[&2].promotable());
+    assert_eq!(network.peers[&1].nodes(), vec![1, 2]);
+    assert!(network.peers[&1].learner_nodes().is_empty());
+
+    // n2 starts an election and should become leader.
+    let timeout = network.peers[&2].get_election_timeout();
+    network.peers.get_mut(&2).unwrap().set_randomized_election_timeout(timeout);
+    for _ in 0..timeout {
+        network.peers.get_mut(&2).unwrap().tick();
+    }
+    network.send(vec![new_message(2, 2, MessageType::MsgBeat, 0)]);
+
+    assert_eq!(network.peers[&1].state, StateRole::Follower);
+    assert_eq!(network.peers[&2].state, StateRole::Leader);
+}
+
+// test_learner_cannot_vote verifies that the learner ignores vote requests.
+#[test]
+fn test_learner_cannot_vote() {
+    for &msg_type in &[MessageType::MsgRequestVote, MessageType::MsgRequestPreVote] {
+        let mut n2 = new_test_learner_raft(2, vec![1], vec![2], 10, 1, new_storage());
+        n2.become_follower(1, INVALID_ID);
+
+        let mut msg = new_message(1, 2, msg_type, 0);
+        msg.set_term(2);
+        msg.set_log_term(11);
+        msg.set_index(11);
+        n2.step(msg).expect("");
+
+        assert!(n2.read_messages().is_empty(), "{:?}", msg_type);
+        assert_eq!(n2.vote, INVALID_ID);
+    }
+}
+
+// test_learner_log_replication verifies that the learner receives the logs from the
+// leader, while the logs are committed without it.
+#[test]
+fn test_learner_log_replication() {
+    let n1 = new_test_learner_raft(1, vec![1], vec![2], 10, 1, new_storage());
+    let n2 = new_test_learner_raft(2, vec![1], vec![2], 10, 1, new_storage());
+    let mut network = Network::new(vec![Some(n1), Some(n2)]);
+    network.peers.get_mut(&1).unwrap().become_follower(1, INVALID_ID);
+    network.peers.get_mut(&2).unwrap().become_follower(1, INVALID_ID);
+
+    let timeout = network.peers[&1].get_election_timeout();
+    network.peers.get_mut(&1).unwrap().set_randomized_election_timeout(timeout);
+    for _ in 0..timeout {
+        network.peers.get_mut(&1).unwrap().tick();
+    }
+    network.send(vec![new_message(1, 1, MessageType::MsgBeat, 0)]);
+
+    // n1 is leader and n2 is learner.
+    assert_eq!(network.peers[&1].state, StateRole::Leader);
+    assert!(network.peers[&2].is_learner);
+
+    let next_committed = network.peers[&1].raft_log.committed + 1;
+    network.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    assert_eq!(network.peers[&1].raft_log.committed, next_committed);
+    assert_eq!(
+        network.peers[&1].raft_log.committed,
+        network.peers[&2].raft_log.committed
+    );
+
+    let matched = network.peers[&1].prs[&2].matched;
+    assert_eq!(matched, network.peers[&2].raft_log.committed);
+}
+
+// test_learner_not_in_quorum verifies that the acknowledgement from learners
+// can't commit entries.
+#[test]
+fn test_learner_not_in_quorum() {
+    let mut r = new_test_learner_raft(1, vec![1, 2], vec![3], 10, 1, new_storage());
+    r.become_candidate();
+    r.become_leader();
+    r.read_messages();
+    let committed = r.raft_log.committed;
+
+    r.step(new_message(1, 1, MessageType::MsgPropose, 1)).expect("");
+    let last_index = r.raft_log.last_index();
+    let mut resp = new_message(3, 1, MessageType::MsgAppendResponse, 0);
+    resp.set_term(r.term);
+    resp.set_index(last_index);
+    r.step(resp).expect("");
+    assert_eq!(r.raft_log.committed, committed);
+
+    let mut resp = new_message(2, 1, MessageType::MsgAppendResponse, 0);
+    resp.set_term(r.term);
+    resp.set_index(last_index);
+    r.step(resp).expect("");
+    assert_eq!(r.raft_log.committed, last_index);
+}
+
+// test_restore_with_learner restores a snapshot which contains learners.
+#[test]
+fn test_restore_with_learner() {
+    let mut s = new_snapshot(11, 11, vec![1, 2]);
+    s.mut_metadata().mut_conf_state().set_learners(vec![3]);
+
+    let mut sm = new_test_learner_raft(3, vec![1, 2], vec![3], 10, 1, new_storage());
+    assert!(sm.restore(s.clone()));
+    assert_eq!(sm.raft_log.last_index(), 11);
+    assert_eq!(sm.raft_log.term(11).unwrap(), 11);
+    assert_eq!(sm.nodes(), s.get_metadata().get_conf_state().get_nodes());
+    assert_eq!(
+        sm.learner_nodes(),
+        s.get_metadata().get_conf_state().get_learners()
+    );
+    assert!(sm.is_learner);
+    assert!(!sm.promotable());
+    for n in s.get_metadata().get_conf_state().get_nodes() {
+        assert!(!sm.prs[n].is_learner);
+    }
+
+    // It should not campaign before actually applying data.
+    for _ in 0..sm.get_randomized_election_timeout() {
+        sm.tick();
+    }
+    assert_eq!(sm.state, StateRole::Follower);
+}
+
 #[test]
 fn test_campaign_while_leader() {
     test_campaign_while_leader_with_pre_vote(false);
@@ -0,0 +1,302 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeSet;
+
+use kvproto::eraftpb::{ConfChangeSingle, ConfChangeType, ConfChangeV2, MessageType};
+use rand::{self, Rng};
+
+use tikv::raft::*;
+
+use super::test_raft::*;
+
+fn new_change(change_type: ConfChangeType, node_id: u64) -> ConfChangeSingle {
+    let mut cc = ConfChangeSingle::new();
+    cc.set_change_type(change_type);
+    cc.set_node_id(node_id);
+    cc
+}
+
+fn new_leader(voters: Vec<u64>, learners: Vec<u64>) -> Interface {
+    let mut r = new_test_learner_raft(1, voters, learners, 10, 1, new_storage());
+    r.become_candidate();
+    r.become_leader();
+    r.read_messages();
+    r
+}
+
+fn ack(r: &mut Interface, from: u64, index: u64) {
+    let mut m = new_message(from, 1, MessageType::MsgAppendResponse, 0);
+    m.set_term(r.term);
+    m.set_index(index);
+    r.step(m).expect("");
+    r.read_messages();
+}
+
+fn vote(r: &mut Interface, from: u64, reject: bool) {
+    let mut m = new_message(from, 1, MessageType::MsgRequestVoteResponse, 0);
+    m.set_term(r.term);
+    m.set_reject(reject);
+    r.step(m).expect("");
+}
+
+fn propose(r: &mut Interface) -> u64 {
+    r.step(new_message(1, 1, MessageType::MsgPropose, 1))
+        .expect("");
+    r.read_messages();
+    r.raft_log.last_index()
+}
+
+fn ids(set: &BTreeSet<u64>) -> Vec<u64> {
+    set.iter().cloned().collect()
+}
+
+// test_enter_leave_joint tests that entering and leaving joint consensus updates
+// the incoming, outgoing voters and learners correctly.
+#[test]
+fn test_enter_leave_joint() {
+    let mut r = new_test_learner_raft(1, vec![1, 2, 3], vec![4], 10, 1, new_storage());
+    r.pending_conf = true;
+    r.enter_joint(&[
+        new_change(ConfChangeType::AddNode, 4),
+        new_change(ConfChangeType::AddLearnerNode, 3),
+        new_change(ConfChangeType::AddNode, 5),
+        new_change(ConfChangeType::RemoveNode, 2),
+    ]);
+    assert!(!r.pending_conf);
+    assert!(r.is_in_joint());
+    assert_eq!(r.nodes(), vec![1, 4, 5]);
+    assert_eq!(r.outgoing_nodes(), vec![1, 2, 3]);
+    assert!(r.learner_nodes().is_empty());
+    assert_eq!(r.learners_next_nodes(), vec![3]);
+
+    // Entering joint consensus again is ignored.
+    r.enter_joint(&[new_change(ConfChangeType::AddNode, 6)]);
+    assert_eq!(r.nodes(), vec![1, 4, 5]);
+    assert!(!r.prs.contains_key(&6));
+
+    r.leave_joint();
+    assert!(!r.is_in_joint());
+    assert_eq!(r.nodes(), vec![1, 4, 5]);
+    assert!(r.outgoing_nodes().is_empty());
+    assert_eq!(r.learner_nodes(), vec![3]);
+    assert!(r.learners_next_nodes().is_empty());
+    assert!(!r.prs.contains_key(&2));
+}
+
+// test_joint_self_demotion tests that a demoted node keeps voting until leaving
+// joint consensus.
+#[test]
+fn test_joint_self_demotion() {
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+    r.enter_joint(&[new_change(ConfChangeType::AddLearnerNode, 1)]);
+    assert!(!r.is_learner);
+    assert!(r.promotable());
+
+    r.leave_joint();
+    assert!(r.is_learner);
+    assert!(!r.promotable());
+    assert_eq!(r.learner_nodes(), vec![1]);
+}
+
+// test_joint_commit tests that an entry is committed only if it's replicated to
+// the quorum of both the incoming and outgoing configuration.
+#[test]
+fn test_joint_commit() {
+    let mut r = new_leader(vec![1, 2, 3], vec![]);
+    r.enter_joint(&[
+        new_change(ConfChangeType::AddNode, 4),
+        new_change(ConfChangeType::AddNode, 5),
+        new_change(ConfChangeType::RemoveNode, 2),
+        new_change(ConfChangeType::RemoveNode, 3),
+    ]);
+    let index = propose(&mut r);
+
+    // {1, 4, 5} has a quorum, but {1, 2, 3} doesn't.
+    ack(&mut r, 4, index);
+    ack(&mut r, 5, index);
+    assert!(r.raft_log.committed < index);
+
+    ack(&mut r, 2, index);
+    assert_eq!(r.raft_log.committed, index);
+
+    // After leaving joint, the outgoing voters don't count any more.
+    r.leave_joint();
+    assert_eq!(r.nodes(), vec![1, 4, 5]);
+    let index = propose(&mut r);
+    ack(&mut r, 4, index);
+    assert_eq!(r.raft_log.committed, index);
+}
+
+// test_joint_election tests that a candidate in joint consensus asks for votes of
+// both configurations, and wins only with the quorum of both.
+#[test]
+fn test_joint_election() {
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+    r.enter_joint(&[
+        new_change(ConfChangeType::AddNode, 4),
+        new_change(ConfChangeType::AddNode, 5),
+        new_change(ConfChangeType::AddLearnerNode, 3),
+    ]);
+    r.step(new_message(1, 1, MessageType::MsgHup, 0)).expect("");
+    assert_eq!(r.state, StateRole::Candidate);
+    let mut to: Vec<u64> = r.read_messages().iter().map(|m| m.get_to()).collect();
+    to.sort();
+    assert_eq!(to, vec![2, 3, 4, 5]);
+
+    // {1, 2} is the quorum of the outgoing {1, 2, 3}, but not of the incoming {1, 2, 4, 5}.
+    vote(&mut r, 2, false);
+    assert_eq!(r.state, StateRole::Candidate);
+    vote(&mut r, 3, true);
+    assert_eq!(r.state, StateRole::Candidate);
+    vote(&mut r, 4, false);
+    assert_eq!(r.state, StateRole::Leader);
+
+    // Lost the election once either configuration rejects it.
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+    r.enter_joint(&[
+        new_change(ConfChangeType::AddNode, 4),
+        new_change(ConfChangeType::AddNode, 5),
+    ]);
+    r.step(new_message(1, 1, MessageType::MsgHup, 0)).expect("");
+    vote(&mut r, 4, false);
+    vote(&mut r, 5, false);
+    assert_eq!(r.state, StateRole::Candidate);
+    vote(&mut r, 2, true);
+    assert_eq!(r.state, StateRole::Candidate);
+    vote(&mut r, 3, true);
+    assert_eq!(r.state, StateRole::Follower);
+}
+
+// test_raw_node_joint_conf_change tests that the ConfState reported by RawNode
+// follows the joint consensus.
+#[test]
+fn test_raw_node_joint_conf_change() {
+    let peers = vec![
+        Peer {
+            id: 1,
+            context: None,
+        },
+    ];
+    let config = new_test_config(1, vec![], 10, 1);
+    let mut raw_node = RawNode::new(&config, new_storage(), &peers).unwrap();
+
+    let mut cc = ConfChangeV2::new();
+    cc.mut_changes()
+        .push(new_change(ConfChangeType::AddNode, 2));
+    cc.mut_changes()
+        .push(new_change(ConfChangeType::AddLearnerNode, 3));
+    cc.mut_changes()
+        .push(new_change(ConfChangeType::AddLearnerNode, 1));
+
+    let cs = raw_node.apply_conf_change_v2(&cc);
+    assert_eq!(cs.get_nodes(), &[2]);
+    assert_eq!(cs.get_learners(), &[3]);
+    assert_eq!(cs.get_voters_outgoing(), &[1]);
+    assert_eq!(cs.get_learners_next(), &[1]);
+
+    let cs = raw_node.apply_conf_change_v2(&ConfChangeV2::new());
+    assert_eq!(cs.get_nodes(), &[2]);
+    assert_eq!(cs.get_learners(), &[1, 3]);
+    assert!(cs.get_voters_outgoing().is_empty());
+    assert!(cs.get_learners_next().is_empty());
+}
+
+// test_random_membership_changes applies random joint consensus changes to a leader,
+// and checks the configuration and commit index against a simple model.
+#[test]
+fn test_random_membership_changes() {
+    let mut rng = rand::thread_rng();
+    let mut voters: BTreeSet<u64> = (1..4).collect();
+    let mut learners: BTreeSet<u64> = BTreeSet::new();
+    let mut r = new_leader(ids(&voters), vec![]);
+    let index = propose(&mut r);
+    ack(&mut r, 2, index);
+    ack(&mut r, 3, index);
+
+    for _ in 0..200 {
+        // Node 1 is the leader and always stays a voter.
+        let mut candidates: Vec<u64> = (2..10).collect();
+        rng.shuffle(&mut candidates);
+        let count = rng.gen_range(1, 4);
+        let mut changes = vec![];
+        let (mut incoming, mut new_learners) = (voters.clone(), learners.clone());
+        let mut demoted = BTreeSet::new();
+        for id in candidates.into_iter().take(count) {
+            let change_type = match rng.gen_range(0, 3) {
+                0 => ConfChangeType::AddNode,
+                1 => ConfChangeType::AddLearnerNode,
+                _ => ConfChangeType::RemoveNode,
+            };
+            match change_type {
+                ConfChangeType::AddNode => {
+                    new_learners.remove(&id);
+                    incoming.insert(id);
+                }
+                ConfChangeType::AddLearnerNode => if incoming.remove(&id) {
+                    demoted.insert(id);
+                } else {
+                    new_learners.insert(id);
+                },
+                ConfChangeType::RemoveNode => {
+                    incoming.remove(&id);
+                    new_learners.remove(&id);
+                }
+            }
+            changes.push(new_change(change_type, id));
+        }
+
+        r.enter_joint(&changes);
+        assert_eq!(r.nodes(), ids(&incoming), "changes {:?}", changes);
+        assert_eq!(r.outgoing_nodes(), ids(&voters), "changes {:?}", changes);
+        assert_eq!(r.learner_nodes(), ids(&new_learners), "changes {:?}", changes);
+        assert_eq!(r.learners_next_nodes(), ids(&demoted), "changes {:?}", changes);
+
+        // Ack from a random subset of all the nodes, learners included.
+        let index = propose(&mut r);
+        let all: BTreeSet<u64> = r.prs.keys().cloned().collect();
+        let acked: BTreeSet<u64> = all.iter()
+            .cloned()
+            .filter(|id| *id == 1 || rng.gen())
+            .collect();
+        for id in acked.iter().filter(|id| **id != 1) {
+            ack(&mut r, *id, index);
+        }
+        let has_quorum = |config: &BTreeSet<u64>| {
+            config.iter().filter(|id| acked.contains(id)).count() >= quorum(config.len())
+        };
+        let expect_commit = has_quorum(&incoming) && has_quorum(&voters);
+        assert_eq!(
+            r.raft_log.committed == index,
+            expect_commit,
+            "incoming {:?}, outgoing {:?}, acked {:?}",
+            incoming,
+            voters,
+            acked
+        );
+        for id in all.iter().filter(|id| **id != 1) {
+            ack(&mut r, *id, index);
+        }
+        assert_eq!(r.raft_log.committed, index);
+
+        r.leave_joint();
+        new_learners.extend(demoted);
+        voters = incoming;
+        learners = new_learners;
+        assert!(!r.is_in_joint());
+        assert_eq!(r.nodes(), ids(&voters));
+        assert_eq!(r.learner_nodes(), ids(&learners));
+        let all: BTreeSet<u64> = voters.union(&learners).cloned().collect();
+        assert_eq!(r.prs.keys().cloned().collect::<BTreeSet<_>>(), all);
+    }
+}
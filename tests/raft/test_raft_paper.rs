@@ -278,6 +278,25 @@ fn test_leader_election_in_one_round_rpc() {
     }
 }
 
+// test_candidate_skip_learner tests that a candidate only asks voters for votes,
+// and wins the election with the votes from a majority of the voters.
+#[test]
+fn test_candidate_skip_learner() {
+    let mut r = new_test_learner_raft(1, vec![1, 2, 3], vec![4, 5], 10, 1, new_storage());
+    r.step(new_message(1, 1, MessageType::MsgHup, 0)).expect("");
+    assert_eq!(r.state, StateRole::Candidate);
+
+    let mut msgs = r.read_messages();
+    msgs.sort_by_key(|m| m.get_to());
+    let to: Vec<u64> = msgs.iter().map(|m| m.get_to()).collect();
+    assert_eq!(to, vec![2, 3]);
+
+    let mut m = new_message(2, 1, MessageType::MsgRequestVoteResponse, 0);
+    m.set_term(r.term);
+    r.step(m).expect("");
+    assert_eq!(r.state, StateRole::Leader);
+}
+
 // test_follower_vote tests that each follower will vote for at most one
 // candidate in a given term, on a first-come-first-served basis.
 // Reference: section 5.2
@@ -478,6 +497,31 @@ fn test_leader_start_replication() {
     assert_eq!(r.raft_log.unstable_entries(), Some(&*wents));
 }
 
+// test_leader_start_replication_to_learner tests that the leader replicates the
+// new entry to learners the same way as to followers.
+#[test]
+fn test_leader_start_replication_to_learner() {
+    let s = new_storage();
+    let mut r = new_test_learner_raft(1, vec![1, 2, 3], vec![4], 10, 1, s.clone());
+    r.become_candidate();
+    r.become_leader();
+    commit_noop_entry(&mut r, &s);
+    let li = r.raft_log.last_index();
+
+    r.step(new_message(1, 1, MessageType::MsgPropose, 1))
+        .expect("");
+
+    let mut msgs = r.read_messages();
+    msgs.sort_by_key(|m| m.get_to());
+    let to: Vec<u64> = msgs.iter().map(|m| m.get_to()).collect();
+    assert_eq!(to, vec![2, 3, 4]);
+    for m in msgs {
+        assert_eq!(m.get_msg_type(), MessageType::MsgAppend);
+        assert_eq!(m.get_index(), li);
+        assert_eq!(m.get_entries(), &[new_entry(1, li + 1, SOME_DATA)]);
+    }
+}
+
 // test_leader_commit_entry tests that when the entry has been safely replicated,
 // the leader gives out the applied entries, which can be applied to its state
 // machine.
@@ -551,6 +595,40 @@ fn test_leader_acknowledge_commit() {
     }
 }
 
+// test_leader_acknowledge_commit_with_learner tests that the acknowledgements
+// from learners are not counted when deciding whether a log entry is committed.
+#[test]
+fn test_leader_acknowledge_commit_with_learner() {
+    let mut tests = vec![
+        (vec![1], vec![2], map!(2 => true), true),
+        (vec![1, 2, 3], vec![4], map!(4 => true), false),
+        (vec![1, 2, 3], vec![4], map!(2 => true), true),
+        (vec![1, 2, 3], vec![4, 5], map!(4 => true, 5 => true), false),
+        (vec![1, 2, 3], vec![4, 5], map!(2 => true, 4 => true, 5 => true), true),
+    ];
+    for (i, (peers, learners, acceptors, wack)) in tests.drain(..).enumerate() {
+        let s = new_storage();
+        let mut r = new_test_learner_raft(1, peers, learners, 10, 1, s.clone());
+        r.become_candidate();
+        r.become_leader();
+        commit_noop_entry(&mut r, &s);
+        let li = r.raft_log.last_index();
+        r.step(new_message(1, 1, MessageType::MsgPropose, 1))
+            .expect("");
+
+        for m in r.read_messages() {
+            if acceptors.contains_key(&m.get_to()) && acceptors[&m.get_to()] {
+                r.step(accept_and_reply(m)).expect("");
+            }
+        }
+
+        let g = r.raft_log.committed > li;
+        if g ^ wack {
+            panic!("#{}: ack commit = {}, want {}", i, g, wack);
+        }
+    }
+}
+
 // test_leader_commit_preceding_entries tests that when leader commits a log entry,
 // it also commits all preceding entries in the leader’s log, including
 // entries created by previous leaders.
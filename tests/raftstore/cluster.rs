@@ -18,14 +18,14 @@ use std::time::*;
 use std::{result, thread};
 use std::path::Path;
 
-use rocksdb::DB;
+use rocksdb::{ColumnFamilyOptions, DBOptions, DB};
 use tempdir::TempDir;
 use futures::Future;
 
 use tikv::raftstore::{Error, Result};
 use tikv::raftstore::store::*;
 use tikv::config::TiKvConfig;
-use tikv::storage::{ALL_CFS, CF_DEFAULT};
+use tikv::storage::{ALL_CFS, CF_DEFAULT, CF_WRITE};
 use super::util::*;
 use kvproto::pdpb;
 use kvproto::raft_cmdpb::*;
@@ -34,6 +34,8 @@ use kvproto::raft_serverpb::RaftMessage;
 use kvproto::errorpb::Error as PbError;
 use tikv::pd::PdClient;
 use tikv::util::{escape, rocksdb, HandyRwLock};
+use tikv::util::rocksdb::CFOptions;
+use tikv::util::rocksdb::gc_filter::GcContext;
 use tikv::util::transport::SendCh;
 use super::pd::TestPdClient;
 use super::transport_simulate::*;
@@ -77,9 +79,13 @@ pub struct Cluster<T: Simulator> {
     leaders: HashMap<u64, metapb::Peer>,
     paths: Vec<TempDir>,
     dbs: Vec<Engines>,
+    gc_ctxs: Vec<GcContext>,
 
     // node id -> {db, raft_db} engine.
     pub engines: HashMap<u64, Engines>,
+    // node id -> the context of the GC compaction filter of the write cf, which keeps
+    // everything until it's started and given a safe point.
+    pub gc_contexts: HashMap<u64, GcContext>,
 
     pub sim: Arc<RwLock<T>>,
     pub pd_client: Arc<TestPdClient>,
@@ -99,7 +105,9 @@ impl<T: Simulator> Cluster<T> {
             leaders: HashMap::new(),
             paths: vec![],
             dbs: vec![],
+            gc_ctxs: vec![],
             engines: HashMap::new(),
+            gc_contexts: HashMap::new(),
             sim: sim,
             pd_client: pd_client,
         };
@@ -122,23 +130,42 @@ impl<T: Simulator> Cluster<T> {
         kv_cfs.extend_from_slice(ALL_CFS);
         kv_cfs.extend_from_slice(cfs);
         for item in &self.paths {
+            let gc_ctx = GcContext::new();
+            let mut db_opts = DBOptions::new();
+            db_opts.enable_statistics();
+            let cfs_opts = kv_cfs
+                .iter()
+                .map(|cf| {
+                    let mut cf_opts = ColumnFamilyOptions::new();
+                    if *cf == CF_WRITE {
+                        let f = box gc_ctx.new_filter();
+                        cf_opts
+                            .set_compaction_filter("tikv.gc-compaction-filter", false, f)
+                            .unwrap();
+                    }
+                    CFOptions::new(*cf, cf_opts)
+                })
+                .collect();
             let engine = Arc::new(
-                rocksdb::new_engine(item.path().to_str().unwrap(), &kv_cfs).unwrap(),
+                rocksdb::new_engine_opt(item.path().to_str().unwrap(), db_opts, cfs_opts)
+                    .unwrap(),
             );
             let raft_path = item.path().join(Path::new("raft"));
             let raft_engine = Arc::new(
                 rocksdb::new_engine(raft_path.to_str().unwrap(), &[CF_DEFAULT]).unwrap(),
             );
             self.dbs.push(Engines::new(engine, raft_engine));
+            self.gc_ctxs.push(gc_ctx);
         }
     }
 
     pub fn start(&mut self) {
         if self.engines.is_empty() {
             let mut sim = self.sim.wl();
-            for engines in &self.dbs {
+            for (engines, gc_ctx) in self.dbs.iter().zip(&self.gc_ctxs) {
                 let node_id = sim.run_node(0, self.cfg.clone(), engines.clone());
                 self.engines.insert(node_id, engines.clone());
+                self.gc_contexts.insert(node_id, gc_ctx.clone());
             }
         } else {
             // recover from last shutdown.
@@ -788,6 +815,35 @@ impl<T: Simulator> Cluster<T> {
         }
     }
 
+    pub fn try_merge(&mut self, source: u64, target: u64) -> RaftCmdResponse {
+        let region = self.pd_client
+            .get_region_by_id(target)
+            .wait()
+            .unwrap()
+            .unwrap();
+        let prepare_merge = new_prepare_merge(region);
+        let source = self.pd_client
+            .get_region_by_id(source)
+            .wait()
+            .unwrap()
+            .unwrap();
+        let req = new_admin_request(source.get_id(), source.get_region_epoch(), prepare_merge);
+        self.call_command_on_leader(req, Duration::from_secs(5))
+            .unwrap()
+    }
+
+    pub fn must_try_merge(&mut self, source: u64, target: u64) {
+        let resp = self.try_merge(source, target);
+        if is_error_response(&resp) {
+            panic!(
+                "{} failed to try merge to {}, resp {:?}",
+                source,
+                target,
+                resp
+            );
+        }
+    }
+
     /// Make sure region exists on that store.
     pub fn must_region_exist(&mut self, region_id: u64, store_id: u64) {
         let mut try_cnt = 0;
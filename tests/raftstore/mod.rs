@@ -25,6 +25,8 @@ mod test_compact_log;
 mod test_compact_lock_cf;
 mod test_compact_after_delete;
 mod test_split_region;
+mod test_merge;
+mod test_hibernate;
 mod test_status_command;
 mod test_tombstone;
 mod test_transport;
@@ -34,5 +36,10 @@ mod test_snap;
 mod test_region_heartbeat;
 mod test_stale_peer;
 mod test_lease_read;
+mod test_stale_read;
+mod test_replica_read;
 mod test_bootstrap;
 mod test_service;
+mod test_deadlock;
+mod test_cdc;
+mod test_import_sst;
@@ -66,6 +66,8 @@ struct Cluster {
     stores: HashMap<u64, Store>,
     regions: BTreeMap<Key, metapb::Region>,
     region_id_keys: HashMap<u64, Key>,
+    // The leaders reported by the region heartbeats.
+    leaders: HashMap<u64, metapb::Peer>,
     base_id: AtomicUsize,
     rule: Option<Rule>,
 
@@ -75,6 +77,8 @@ struct Cluster {
     down_peers: HashMap<u64, pdpb::PeerStats>,
     pending_peers: HashMap<u64, metapb::Peer>,
     is_bootstraped: bool,
+    gc_safe_point: u64,
+    tso: u64,
 }
 
 impl Cluster {
@@ -88,6 +92,7 @@ impl Cluster {
             stores: HashMap::new(),
             regions: BTreeMap::new(),
             region_id_keys: HashMap::new(),
+            leaders: HashMap::new(),
             base_id: AtomicUsize::new(1000),
             rule: None,
             store_stats: HashMap::new(),
@@ -95,6 +100,8 @@ impl Cluster {
             down_peers: HashMap::new(),
             pending_peers: HashMap::new(),
             is_bootstraped: false,
+            gc_safe_point: 0,
+            tso: 0,
         }
     }
 
@@ -195,7 +202,7 @@ impl Cluster {
         let conf_ver = region.get_region_epoch().get_conf_ver();
 
         let search_key = data_key(region.get_start_key());
-        let search_region = match self.get_region(search_key) {
+        let search_region = match self.get_region(search_key.clone()) {
             None => {
                 // Find no range after start key, insert directly.
                 self.add_region(&region);
@@ -207,9 +214,6 @@ impl Cluster {
         let search_start_key = enc_start_key(&search_region);
         let search_end_key = enc_end_key(&search_region);
 
-        let search_version = search_region.get_region_epoch().get_version();
-        let search_conf_ver = search_region.get_region_epoch().get_conf_ver();
-
         if start_key == search_start_key && end_key == search_end_key {
             // we are the same, must check epoch here.
             return check_stale_region(&search_region, &region);
@@ -221,12 +225,26 @@ impl Cluster {
         } else {
             // overlap, remove old, insert new.
             // E.g, 1 [a, c) -> 1 [a, b) + 2 [b, c), either new 1 or 2 reports, the region
-            // is overlapped with origin [a, c).
-            if version <= search_version || conf_ver < search_conf_ver {
-                return Err(box_err!("epoch {:?} is stale.", region.get_region_epoch()));
+            // is overlapped with origin [a, c). And for merge 1 [a, b) + 2 [b, c) -> 2 [a, c),
+            // the merged region is overlapped with both of them.
+            let overlaps: Vec<_> = self.regions
+                .range((Excluded(search_key), Unbounded))
+                .map(|(_, r)| r.clone())
+                .take_while(|r| enc_start_key(r) < end_key)
+                .collect();
+            for overlap in &overlaps {
+                let overlap_epoch = overlap.get_region_epoch();
+                if version <= overlap_epoch.get_version() ||
+                    (overlap.get_id() == region.get_id() &&
+                        conf_ver < overlap_epoch.get_conf_ver())
+                {
+                    return Err(box_err!("epoch {:?} is stale.", region.get_region_epoch()));
+                }
             }
 
-            self.remove_region(&search_region);
+            for overlap in &overlaps {
+                self.remove_region(overlap);
+            }
             self.add_region(&region);
         }
 
@@ -356,6 +374,7 @@ impl Cluster {
         }
 
         try!(self.handle_heartbeat_version(region.clone()));
+        self.leaders.insert(region.get_id(), leader.clone());
         self.handle_heartbeat_conf_ver(region, leader)
     }
 }
@@ -479,7 +498,7 @@ impl TestPdClient {
             };
 
             if let Some(p) = find_peer(&region, peer.get_store_id()) {
-                if p.get_id() == peer.get_id() {
+                if p.get_id() == peer.get_id() && p.get_is_learner() == peer.get_is_learner() {
                     return;
                 }
             }
@@ -530,6 +549,20 @@ impl TestPdClient {
         self.must_have_peer(region_id, peer);
     }
 
+    pub fn add_learner(&self, region_id: u64, peer: metapb::Peer) {
+        self.set_rule(box move |region: &metapb::Region, _: &metapb::Peer| {
+            if region.get_id() != region_id {
+                return None;
+            }
+            new_pd_add_learner_change_peer(region, peer.clone())
+        });
+    }
+
+    pub fn must_add_learner(&self, region_id: u64, peer: metapb::Peer) {
+        self.add_learner(region_id, peer.clone());
+        self.must_have_peer(region_id, peer);
+    }
+
     pub fn remove_peer(&self, region_id: u64, peer: metapb::Peer) {
         self.set_rule(box move |region: &metapb::Region, _: &metapb::Peer| {
             if region.get_id() != region_id {
@@ -591,6 +624,10 @@ impl TestPdClient {
     pub fn set_bootstrap(&self, is_bootstraped: bool) {
         self.cluster.wl().set_bootstrap(is_bootstraped);
     }
+
+    pub fn set_gc_safe_point(&self, safe_point: u64) {
+        self.cluster.wl().gc_safe_point = safe_point;
+    }
 }
 
 impl PdClient for TestPdClient {
@@ -639,6 +676,11 @@ impl PdClient for TestPdClient {
         Err(box_err!("no region contains key {:?}", escape(key)))
     }
 
+    fn get_region_leader(&self, key: &[u8]) -> Result<Option<metapb::Peer>> {
+        let region = try!(self.get_region(key));
+        Ok(self.cluster.rl().leaders.get(&region.get_id()).cloned())
+    }
+
     fn get_region_by_id(&self, region_id: u64) -> PdFuture<Option<metapb::Region>> {
         if let Err(e) = self.check_bootstrap() {
             return Box::new(err(e));
@@ -742,4 +784,20 @@ impl PdClient for TestPdClient {
         self.cluster.wl().split_count += 1;
         Box::new(ok(()))
     }
+
+    fn get_gc_safe_point(&self) -> PdFuture<u64> {
+        if let Err(e) = self.check_bootstrap() {
+            return Box::new(err(e));
+        }
+        Box::new(ok(self.cluster.rl().gc_safe_point))
+    }
+
+    fn get_tso(&self) -> PdFuture<u64> {
+        if let Err(e) = self.check_bootstrap() {
+            return Box::new(err(e));
+        }
+        let mut cluster = self.cluster.wl();
+        cluster.tso += 1;
+        Box::new(ok(cluster.tso))
+    }
 }
@@ -14,6 +14,7 @@
 use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
 use std::sync::{mpsc, Arc, RwLock};
+use std::sync::atomic::AtomicBool;
 use std::time::Duration;
 use std::boxed::FnBox;
 
@@ -21,22 +22,29 @@ use grpc::EnvBuilder;
 use tempdir::TempDir;
 
 use super::cluster::{Cluster, Simulator};
+use tikv::cdc::{self, CdcObserver, Task as CdcTask};
 use tikv::config::TiKvConfig;
 use tikv::server::{Server, ServerTransport};
 use tikv::server::{create_raft_storage, Config, Node, PdStoreAddrResolver, RaftClient};
 use tikv::server::resolve::{self, Task as ResolveTask};
 use tikv::server::transport::ServerRaftStoreRouter;
 use tikv::server::transport::RaftStoreRouter;
+use tikv::server::deadlock::{self, DeadlockObserver, Detector, Task as DetectorTask};
+use tikv::server::max_ts::{MaxTsObserver, MaxTsSyncer, Task as MaxTsTask};
 use tikv::raftstore::{store, Error, Result};
+use tikv::raftstore::coprocessor::CoprocessorHost;
 use tikv::raftstore::store::{Engines, Msg as StoreMsg, SnapManager};
+use tikv::util::collections::HashSet as FnvHashSet;
 use tikv::util::transport::SendCh;
-use tikv::util::worker::Worker;
+use tikv::util::worker::{FutureWorker, Worker};
 use tikv::storage::{CfName, Engine};
+use tikv::import::{ImportModeSwitcher, ImportSSTService, SSTImporter};
 use kvproto::raft_serverpb::{self, RaftMessage};
 use kvproto::raft_cmdpb::*;
 
 use super::pd::TestPdClient;
 use super::transport_simulate::*;
+use super::util::new_importer;
 
 type SimulateStoreTransport = SimulateTransport<StoreMsg, ServerRaftStoreRouter>;
 type SimulateServerTransport = SimulateTransport<
@@ -51,12 +59,16 @@ struct ServerMeta {
     sim_trans: SimulateServerTransport,
     store_ch: SendCh<StoreMsg>,
     worker: Worker<ResolveTask>,
+    detector_worker: FutureWorker<DetectorTask>,
+    max_ts_worker: FutureWorker<MaxTsTask>,
+    cdc_worker: FutureWorker<CdcTask>,
 }
 
 pub struct ServerCluster {
     metas: HashMap<u64, ServerMeta>,
     addrs: HashMap<u64, SocketAddr>,
     pub storages: HashMap<u64, Box<Engine>>,
+    pub importers: HashMap<u64, Arc<SSTImporter>>,
     snap_paths: HashMap<u64, TempDir>,
     pd_client: Arc<TestPdClient>,
     raft_client: RaftClient,
@@ -75,6 +87,7 @@ impl ServerCluster {
             addrs: HashMap::new(),
             pd_client: pd_client,
             storages: HashMap::new(),
+            importers: HashMap::new(),
             snap_paths: HashMap::new(),
             raft_client: RaftClient::new(env, Config::default()),
         }
@@ -115,12 +128,38 @@ impl Simulator for ServerCluster {
         let mut store =
             create_raft_storage(sim_router.clone(), engines.kv_engine.clone(), &cfg.storage)
                 .unwrap();
+        let mut detector_worker = FutureWorker::new("deadlock-detector");
+        let detector_leader = Arc::new(AtomicBool::new(false));
+        store.set_deadlock_detector(detector_worker.scheduler());
+        if let Some(interval) = cfg.raft_store.load_report_interval() {
+            store.set_load_report_interval(interval);
+        }
         store.start(&cfg.storage).unwrap();
+        let mut max_ts_worker = FutureWorker::new("max-ts-syncer");
+        let max_ts_syncer = MaxTsSyncer::new(
+            self.pd_client.clone(),
+            store.get_concurrency_manager(),
+            max_ts_worker.scheduler(),
+        );
+        max_ts_worker.start(max_ts_syncer).unwrap();
+        let mut cdc_worker = FutureWorker::new("cdc");
+        let cdc_observed_regions = Arc::new(RwLock::new(FnvHashSet::default()));
         self.storages.insert(node_id, store.get_engine());
 
         // Create pd client, snapshot manager, server.
         let (worker, resolver) = resolve::new_resolver(self.pd_client.clone()).unwrap();
         let snap_mgr = SnapManager::new(tmp_str, Some(store_sendch));
+        let importer = new_importer(&engines);
+        let switcher = Arc::new(ImportModeSwitcher::new(
+            engines.kv_engine.clone(),
+            cfg.server.import_mode_timeout.0,
+        ));
+        let import_service = ImportSSTService::new(
+            sim_router.clone(),
+            engines.kv_engine.clone(),
+            importer.clone(),
+            switcher,
+        );
         let mut server = Server::new(
             &cfg.server,
             cfg.raft_store.region_split_size.0 as usize,
@@ -130,6 +169,13 @@ impl Simulator for ServerCluster {
             resolver,
             snap_mgr.clone(),
             Some(engines.clone()),
+            Some(cdc_worker.scheduler()),
+            None,
+            Some(import_service),
+            Some(deadlock::Service::new(
+                detector_worker.scheduler(),
+                detector_leader.clone(),
+            )),
         ).unwrap();
         let addr = server.listening_addr();
         cfg.server.addr = format!("{}", addr);
@@ -143,19 +189,60 @@ impl Simulator for ServerCluster {
             &cfg.raft_store,
             self.pd_client.clone(),
         );
+        let mut coprocessor_host = CoprocessorHost::default();
+        coprocessor_host.registry.register_observer(
+            200,
+            Box::new(CdcObserver::new(
+                cdc_worker.scheduler(),
+                cdc_observed_regions.clone(),
+            )),
+        );
+        coprocessor_host.registry.register_observer(
+            300,
+            Box::new(DeadlockObserver::new(detector_worker.scheduler())),
+        );
+        coprocessor_host.registry.register_observer(
+            400,
+            Box::new(MaxTsObserver::new(
+                store.get_concurrency_manager(),
+                max_ts_worker.scheduler(),
+            )),
+        );
         node.start(
             event_loop,
             engines,
             simulate_trans.clone(),
             snap_mgr.clone(),
             snap_status_receiver,
+            coprocessor_host,
+            importer.clone(),
         ).unwrap();
         assert!(node_id == 0 || node_id == node.id());
         let node_id = node.id();
+        self.importers.insert(node_id, importer);
         if let Some(tmp) = tmp {
             self.snap_paths.insert(node_id, tmp);
         }
 
+        let detector = Detector::new(
+            self.pd_client.clone(),
+            store.get_sched_ch(),
+            detector_worker.scheduler(),
+            detector_leader,
+        );
+        detector_worker.start(detector).unwrap();
+
+        let cdc_endpoint = cdc::Endpoint::new(
+            node_id,
+            self.pd_client.clone(),
+            store.get_engine(),
+            store.get_concurrency_manager(),
+            cdc_worker.scheduler(),
+            cdc_observed_regions,
+        );
+        cdc_worker.start(cdc_endpoint).unwrap();
+        cdc_worker.schedule(cdc::Task::RegisterMinTsEvent).unwrap();
+
         server.start(&cfg.server).unwrap();
 
         self.metas.insert(
@@ -167,6 +254,9 @@ impl Simulator for ServerCluster {
                 router: sim_router,
                 sim_trans: simulate_trans,
                 worker: worker,
+                detector_worker: detector_worker,
+                max_ts_worker: max_ts_worker,
+                cdc_worker: cdc_worker,
             },
         );
         self.addrs.insert(node_id, addr);
@@ -187,6 +277,9 @@ impl Simulator for ServerCluster {
             meta.server.stop().unwrap();
             meta.node.stop().unwrap();
             meta.worker.stop().unwrap().join().unwrap();
+            meta.detector_worker.stop().unwrap().join().unwrap();
+            meta.max_ts_worker.stop().unwrap().join().unwrap();
+            meta.cdc_worker.stop().unwrap().join().unwrap();
         }
     }
 
@@ -13,6 +13,7 @@
 
 use std::sync::{mpsc, Arc};
 use std::path::Path;
+use tikv::raftstore::coprocessor::CoprocessorHost;
 use tikv::raftstore::store::{bootstrap_store, create_event_loop, keys, Engines, Peekable,
                              SnapManager};
 use tikv::server::Node;
@@ -93,12 +94,15 @@ fn test_node_bootstrap_with_prepared_data() {
     );
 
     // try to restart this node, will clear the prepare data
+    let importer = new_importer(&engines);
     node.start(
         event_loop,
         engines,
         simulate_trans,
         snap_mgr,
         snapshot_status_receiver,
+        CoprocessorHost::default(),
+        importer,
     ).unwrap();
     assert!(
         engine
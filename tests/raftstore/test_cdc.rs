@@ -0,0 +1,159 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use futures::{Future, Stream};
+use grpc::{self, ChannelBuilder, Environment};
+use kvproto::cdcpb::*;
+use kvproto::cdcpb_grpc::ChangeDataClient;
+use kvproto::kvrpcpb::*;
+use kvproto::tikvpb_grpc::TikvClient;
+use protobuf::RepeatedField;
+use tikv::pd::PdClient;
+use tikv::util::HandyRwLock;
+
+use super::cluster::Cluster;
+use super::server::{new_server_cluster, ServerCluster};
+use super::util::*;
+
+fn new_clients(
+    cluster: &Cluster<ServerCluster>,
+    env: Arc<Environment>,
+    store_id: u64,
+) -> (TikvClient, ChangeDataClient) {
+    let addr = cluster.sim.rl().get_addr(store_id);
+    let channel = ChannelBuilder::new(env).connect(&format!("{}", addr));
+    (TikvClient::new(channel.clone()), ChangeDataClient::new(channel))
+}
+
+fn new_context(cluster: &mut Cluster<ServerCluster>, region_id: u64) -> Context {
+    let mut ctx = Context::new();
+    ctx.set_region_id(region_id);
+    ctx.set_peer(cluster.leader_of_region(region_id).unwrap());
+    ctx.set_region_epoch(cluster.get_region_epoch(region_id));
+    ctx
+}
+
+fn must_prewrite(client: &TikvClient, ctx: Context, k: &[u8], v: &[u8], start_ts: u64) {
+    let mut mutation = Mutation::new();
+    mutation.set_op(Op::Put);
+    mutation.set_key(k.to_vec());
+    mutation.set_value(v.to_vec());
+    let mut req = PrewriteRequest::new();
+    req.set_context(ctx);
+    req.set_mutations(RepeatedField::from_vec(vec![mutation]));
+    req.set_primary_lock(k.to_vec());
+    req.set_start_version(start_ts);
+    req.set_lock_ttl(3000);
+    let resp = client.kv_prewrite(req).unwrap();
+    assert!(!resp.has_region_error(), "{:?}", resp);
+    assert!(resp.get_errors().is_empty(), "{:?}", resp);
+}
+
+fn must_commit(client: &TikvClient, ctx: Context, k: &[u8], start_ts: u64, commit_ts: u64) {
+    let mut req = CommitRequest::new();
+    req.set_context(ctx);
+    req.set_keys(RepeatedField::from_vec(vec![k.to_vec()]));
+    req.set_start_version(start_ts);
+    req.set_commit_version(commit_ts);
+    let resp = client.kv_commit(req).unwrap();
+    assert!(!resp.has_region_error(), "{:?}", resp);
+    assert!(!resp.has_error(), "{:?}", resp);
+}
+
+// Returns the next event, the resolved ts are skipped unless `resolved_ts` is true.
+fn recv_event<I>(events: &mut I, resolved_ts: bool) -> Event
+where
+    I: Iterator<Item = grpc::Result<ChangeDataEvent>>,
+{
+    loop {
+        let mut change = events.next().unwrap().unwrap();
+        let mut event = change.take_events().into_vec();
+        assert_eq!(event.len(), 1, "{:?}", event);
+        let event = event.pop().unwrap();
+        if resolved_ts || !event.has_resolved_ts() {
+            return event;
+        }
+    }
+}
+
+fn must_recv_row<I>(events: &mut I, k: &[u8], v: &[u8], start_ts: u64, commit_ts: u64)
+where
+    I: Iterator<Item = grpc::Result<ChangeDataEvent>>,
+{
+    let event = recv_event(events, false);
+    let rows = event.get_entries().get_entries();
+    assert_eq!(rows.len(), 1, "{:?}", event);
+    assert_eq!(rows[0].get_field_type(), Event_LogType::Committed);
+    assert_eq!(rows[0].get_key(), k);
+    assert_eq!(rows[0].get_value(), v);
+    assert_eq!(rows[0].get_start_ts(), start_ts);
+    assert_eq!(rows[0].get_commit_ts(), commit_ts);
+}
+
+#[test]
+fn test_cdc_leader_change() {
+    let mut cluster = new_server_cluster(0, 3);
+    cluster.run();
+    cluster.must_transfer_leader(1, new_peer(1, 1));
+
+    let env = Arc::new(Environment::new(1));
+    let (client, cdc_client) = new_clients(&cluster, env, 1);
+    let checkpoint_ts = cluster.pd_client.get_tso().wait().unwrap();
+
+    // Written before the registration, it's sent by the incremental scan.
+    let start_ts1 = cluster.pd_client.get_tso().wait().unwrap();
+    must_prewrite(&client, new_context(&mut cluster, 1), b"k1", b"v1", start_ts1);
+    let commit_ts1 = cluster.pd_client.get_tso().wait().unwrap();
+    must_commit(&client, new_context(&mut cluster, 1), b"k1", start_ts1, commit_ts1);
+
+    let mut req = ChangeDataRequest::new();
+    req.set_region_id(1);
+    req.set_region_epoch(cluster.get_region_epoch(1));
+    req.set_checkpoint_ts(checkpoint_ts);
+    req.set_request_id(1);
+    let mut events = cdc_client.event_feed(req).wait();
+
+    let event = recv_event(&mut events, false);
+    let rows = event.get_entries().get_entries();
+    assert_eq!(rows.len(), 2, "{:?}", event);
+    assert_eq!(rows[0].get_key(), b"k1");
+    assert_eq!(rows[0].get_commit_ts(), commit_ts1);
+    assert_eq!(rows[1].get_field_type(), Event_LogType::Initialized);
+
+    // Written after the registration.
+    let start_ts2 = cluster.pd_client.get_tso().wait().unwrap();
+    must_prewrite(&client, new_context(&mut cluster, 1), b"k2", b"v2", start_ts2);
+    // The resolved ts is blocked by the lock of k2.
+    let event = recv_event(&mut events, true);
+    assert!(event.has_resolved_ts(), "{:?}", event);
+    assert!(event.get_resolved_ts() <= start_ts2, "{:?}", event);
+    let commit_ts2 = cluster.pd_client.get_tso().wait().unwrap();
+    must_commit(&client, new_context(&mut cluster, 1), b"k2", start_ts2, commit_ts2);
+    must_recv_row(&mut events, b"k2", b"v2", start_ts2, commit_ts2);
+    // All the rows committed before the resolved ts have been sent.
+    let mut resolved_ts = 0;
+    while resolved_ts <= commit_ts2 {
+        let event = recv_event(&mut events, true);
+        assert!(event.has_resolved_ts(), "{:?}", event);
+        resolved_ts = event.get_resolved_ts();
+    }
+
+    // The downstream is deregistered once the peer steps down.
+    cluster.must_transfer_leader(1, new_peer(2, 2));
+    let event = recv_event(&mut events, false);
+    assert!(event.has_error(), "{:?}", event);
+    assert!(event.get_error().has_not_leader(), "{:?}", event);
+    assert!(events.next().is_none());
+}
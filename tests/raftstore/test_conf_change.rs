@@ -257,6 +257,126 @@ fn test_server_simple_conf_change() {
     test_simple_conf_change(&mut cluster);
 }
 
+fn test_learner_conf_change<T: Simulator>(cluster: &mut Cluster<T>) {
+    let pd_client = cluster.pd_client.clone();
+    pd_client.disable_default_rule();
+    let r1 = cluster.run_conf_change();
+
+    // Add (2, 2) as a learner, it should receive the logs as a follower.
+    cluster.must_put(b"k1", b"v1");
+    pd_client.must_add_learner(r1, new_learner_peer(2, 2));
+    let engine_2 = cluster.get_engine(2);
+    must_get_equal(&engine_2, b"k1", b"v1");
+
+    // The learner is not counted in the quorum, so the region is still
+    // available without it.
+    cluster.add_send_filter(IsolationFilterFactory::new(2));
+    cluster.must_put(b"k2", b"v2");
+    must_get_none(&engine_2, b"k2");
+    cluster.clear_send_filters();
+    must_get_equal(&engine_2, b"k2", b"v2");
+    assert_eq!(cluster.leader_of_region(r1), Some(new_peer(1, 1)));
+
+    // Promote the learner to voter.
+    pd_client.must_add_peer(r1, new_peer(2, 2));
+    cluster.must_put(b"k3", b"v3");
+    must_get_equal(&engine_2, b"k3", b"v3");
+
+    // The learner can be removed as well.
+    pd_client.must_add_learner(r1, new_learner_peer(3, 3));
+    let engine_3 = cluster.get_engine(3);
+    must_get_equal(&engine_3, b"k3", b"v3");
+    pd_client.must_remove_peer(r1, new_learner_peer(3, 3));
+    must_get_none(&engine_3, b"k3");
+}
+
+#[test]
+fn test_node_learner_conf_change() {
+    let count = 5;
+    let mut cluster = new_node_cluster(0, count);
+    test_learner_conf_change(&mut cluster);
+}
+
+#[test]
+fn test_server_learner_conf_change() {
+    let count = 5;
+    let mut cluster = new_server_cluster(0, count);
+    test_learner_conf_change(&mut cluster);
+}
+
+fn test_joint_conf_change<T: Simulator>(cluster: &mut Cluster<T>) {
+    let pd_client = cluster.pd_client.clone();
+    pd_client.disable_default_rule();
+    let r1 = cluster.run_conf_change();
+    pd_client.must_add_peer(r1, new_peer(2, 2));
+    pd_client.must_add_peer(r1, new_peer(3, 3));
+    pd_client.must_add_learner(r1, new_learner_peer(4, 4));
+    cluster.must_put(b"k1", b"v1");
+
+    // Promote (4, 4) and demote (3, 3) in one step.
+    let region = cluster.get_region(b"k1");
+    let change = new_change_peer_v2_request(vec![
+        (ConfChangeType::AddNode, new_peer(4, 4)),
+        (ConfChangeType::AddLearnerNode, new_peer(3, 3)),
+    ]);
+    let req = new_admin_request(r1, region.get_region_epoch(), change);
+    let resp = cluster
+        .call_command_on_leader(req, Duration::from_secs(5))
+        .unwrap();
+    assert!(!resp.get_header().has_error(), "{:?}", resp);
+
+    // The leader leaves the joint state automatically.
+    pd_client.must_have_peer(r1, new_peer(4, 4));
+    pd_client.must_have_peer(r1, new_learner_peer(3, 3));
+    let region = pd_client.get_region_by_id(r1).wait().unwrap().unwrap();
+    for p in region.get_peers() {
+        let role = p.get_role();
+        assert!(role == metapb::PeerRole::Voter || role == metapb::PeerRole::Learner, "{:?}", p);
+    }
+
+    // (3, 3) is a learner now, so the region is available without it.
+    cluster.add_send_filter(IsolationFilterFactory::new(3));
+    cluster.must_put(b"k2", b"v2");
+    must_get_equal(&cluster.get_engine(4), b"k2", b"v2");
+    must_get_none(&cluster.get_engine(3), b"k2");
+    cluster.clear_send_filters();
+
+    // A voter can't be removed in joint consensus before being demoted.
+    let region = cluster.get_region(b"k1");
+    let change = new_change_peer_v2_request(vec![(ConfChangeType::RemoveNode, new_peer(2, 2))]);
+    let req = new_admin_request(r1, region.get_region_epoch(), change);
+    let resp = cluster
+        .call_command_on_leader(req, Duration::from_secs(5))
+        .unwrap();
+    assert!(resp.get_header().has_error(), "{:?}", resp);
+
+    // But the learner can.
+    let change = new_change_peer_v2_request(vec![
+        (ConfChangeType::RemoveNode, new_learner_peer(3, 3)),
+    ]);
+    let req = new_admin_request(r1, region.get_region_epoch(), change);
+    let resp = cluster
+        .call_command_on_leader(req, Duration::from_secs(5))
+        .unwrap();
+    assert!(!resp.get_header().has_error(), "{:?}", resp);
+    pd_client.must_none_peer(r1, new_learner_peer(3, 3));
+    must_get_none(&cluster.get_engine(3), b"k1");
+}
+
+#[test]
+fn test_node_joint_conf_change() {
+    let count = 5;
+    let mut cluster = new_node_cluster(0, count);
+    test_joint_conf_change(&mut cluster);
+}
+
+#[test]
+fn test_server_joint_conf_change() {
+    let count = 5;
+    let mut cluster = new_server_cluster(0, count);
+    test_joint_conf_change(&mut cluster);
+}
+
 #[test]
 fn test_node_pd_conf_change() {
     let count = 5;
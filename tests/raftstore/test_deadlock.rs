@@ -0,0 +1,140 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use futures::Future;
+use grpc::{ChannelBuilder, Environment};
+use kvproto::kvrpcpb::*;
+use kvproto::metapb;
+use kvproto::tikvpb_grpc::TikvClient;
+use protobuf::RepeatedField;
+use tikv::pd::PdClient;
+use tikv::raftstore::store::util::find_peer;
+use tikv::util::HandyRwLock;
+
+use super::cluster::Cluster;
+use super::server::{new_server_cluster, ServerCluster};
+use super::util::*;
+
+fn new_client(
+    cluster: &Cluster<ServerCluster>,
+    env: Arc<Environment>,
+    store_id: u64,
+) -> TikvClient {
+    let addr = cluster.sim.rl().get_addr(store_id);
+    let channel = ChannelBuilder::new(env).connect(&format!("{}", addr));
+    TikvClient::new(channel)
+}
+
+fn new_context(cluster: &mut Cluster<ServerCluster>, key: &[u8]) -> Context {
+    let region = cluster.get_region(key);
+    let leader = cluster.leader_of_region(region.get_id()).unwrap();
+    let mut ctx = Context::new();
+    ctx.set_region_id(region.get_id());
+    ctx.set_peer(leader);
+    ctx.set_region_epoch(region.get_region_epoch().clone());
+    ctx
+}
+
+fn new_lock_req(
+    ctx: Context,
+    key: &[u8],
+    start_ts: u64,
+    wait_timeout: i64,
+) -> PessimisticLockRequest {
+    let mut mutation = Mutation::new();
+    mutation.set_op(Op::PessimisticLock);
+    mutation.set_key(key.to_vec());
+    let mut req = PessimisticLockRequest::new();
+    req.set_context(ctx);
+    req.set_mutations(RepeatedField::from_vec(vec![mutation]));
+    req.set_primary_lock(key.to_vec());
+    req.set_start_version(start_ts);
+    req.set_for_update_ts(start_ts);
+    req.set_lock_ttl(3000);
+    req.set_wait_timeout(wait_timeout);
+    req
+}
+
+fn must_lock(client: &TikvClient, ctx: Context, key: &[u8], start_ts: u64) {
+    let resp = client
+        .kv_pessimistic_lock(new_lock_req(ctx, key, start_ts, 0))
+        .unwrap();
+    assert!(!resp.has_region_error(), "{:?}", resp);
+    assert!(resp.get_errors().is_empty(), "{:?}", resp);
+}
+
+fn transfer_leader_to(cluster: &mut Cluster<ServerCluster>, key: &[u8], store_id: u64) {
+    let region = cluster.get_region(key);
+    let peer = find_peer(&region, store_id).unwrap().clone();
+    cluster.must_transfer_leader(region.get_id(), peer);
+}
+
+// Waits until pd knows the leader of the first region, the detectors connect to it.
+fn wait_detector_leader(cluster: &Cluster<ServerCluster>, store_id: u64) {
+    for _ in 0..100 {
+        let leader: Option<metapb::Peer> = cluster.pd_client.get_region_leader(b"").unwrap();
+        if leader.map_or(false, |l| l.get_store_id() == store_id) {
+            return;
+        }
+        sleep_ms(100);
+    }
+    panic!("pd doesn't know the leader of the first region");
+}
+
+#[test]
+fn test_deadlock_across_stores() {
+    let mut cluster = new_server_cluster(0, 3);
+    cluster.run();
+
+    // k1 is led by store 1, which runs the detector, while k2 is led by store 2.
+    let region = cluster.get_region(b"");
+    cluster.must_split(&region, b"k2");
+    transfer_leader_to(&mut cluster, b"k1", 1);
+    transfer_leader_to(&mut cluster, b"k2", 2);
+    wait_detector_leader(&cluster, 1);
+
+    let env = Arc::new(Environment::new(1));
+    let client1 = new_client(&cluster, env.clone(), 1);
+    let client2 = new_client(&cluster, env, 2);
+    let ctx1 = new_context(&mut cluster, b"k1");
+    let ctx2 = new_context(&mut cluster, b"k2");
+
+    must_lock(&client1, ctx1.clone(), b"k1", 10);
+    must_lock(&client2, ctx2.clone(), b"k2", 20);
+
+    // Txn 10 waits for txn 20 on store 2, the edge is forwarded to the detector on store 1.
+    let waiting = client2.kv_pessimistic_lock_async(new_lock_req(ctx2.clone(), b"k2", 10, 5000));
+    sleep_ms(500);
+
+    // Txn 20 waiting for txn 10 on store 1 closes the cycle.
+    let resp = client1
+        .kv_pessimistic_lock(new_lock_req(ctx1, b"k1", 20, 5000))
+        .unwrap();
+    assert_eq!(resp.get_errors().len(), 1, "{:?}", resp);
+    assert!(resp.get_errors()[0].has_deadlock(), "{:?}", resp);
+
+    // Txn 20 gives up, so txn 10 gets the lock.
+    let mut rollback = PessimisticRollbackRequest::new();
+    rollback.set_context(ctx2);
+    rollback.set_keys(RepeatedField::from_vec(vec![b"k2".to_vec()]));
+    rollback.set_start_version(20);
+    rollback.set_for_update_ts(20);
+    let rollback_resp = client2.kv_pessimistic_rollback(rollback).unwrap();
+    assert!(rollback_resp.get_errors().is_empty(), "{:?}", rollback_resp);
+
+    let resp = waiting.wait().unwrap();
+    assert!(!resp.has_region_error(), "{:?}", resp);
+    assert!(resp.get_errors().is_empty(), "{:?}", resp);
+}
@@ -0,0 +1,180 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+use std::sync::atomic::*;
+
+use kvproto::eraftpb::MessageType;
+use kvproto::metapb;
+use kvproto::raft_serverpb::RaftMessage;
+use tikv::raftstore::Result;
+use tikv::util::config::*;
+
+use super::cluster::{Cluster, Simulator};
+use super::node::new_node_cluster;
+use super::server::new_server_cluster;
+use super::transport_simulate::*;
+use super::util::*;
+
+/// Counts the raft messages of the given type without dropping any of them.
+#[derive(Clone)]
+struct CountFilter {
+    msg_type: MessageType,
+    count: Arc<AtomicUsize>,
+}
+
+impl Filter<RaftMessage> for CountFilter {
+    fn before(&self, msgs: &mut Vec<RaftMessage>) -> Result<()> {
+        let count = msgs.iter()
+            .filter(|m| !m.has_extra_msg() && m.get_message().get_msg_type() == self.msg_type)
+            .count();
+        self.count.fetch_add(count, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+fn configure_for_hibernate<T: Simulator>(cluster: &mut Cluster<T>, wake_up_interval: u64) {
+    cluster.cfg.raft_store.hibernate_regions = true;
+    cluster.cfg.raft_store.hibernate_wake_up_interval = ReadableDuration::millis(wake_up_interval);
+}
+
+fn count_heartbeats<T: Simulator>(cluster: &mut Cluster<T>) -> Arc<AtomicUsize> {
+    let count = Arc::new(AtomicUsize::new(0));
+    cluster.add_send_filter(CloneFilterFactory(CountFilter {
+        msg_type: MessageType::MsgHeartbeat,
+        count: count.clone(),
+    }));
+    count
+}
+
+/// Waits until the region hibernates, which means no heartbeat is sent for a while.
+fn must_hibernate(heartbeats: &AtomicUsize) {
+    for _ in 0..50 {
+        heartbeats.store(0, Ordering::SeqCst);
+        sleep_ms(300);
+        if heartbeats.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+    }
+    panic!("region is not hibernated");
+}
+
+fn must_elect_new_leader<T: Simulator>(
+    cluster: &mut Cluster<T>,
+    region_id: u64,
+    old_leader: &metapb::Peer,
+) -> metapb::Peer {
+    for _ in 0..100 {
+        cluster.reset_leader_of_region(region_id);
+        if let Some(leader) = cluster.leader_of_region(region_id) {
+            if leader != *old_leader {
+                return leader;
+            }
+        }
+        sleep_ms(100);
+    }
+    panic!("no new leader is elected for region {}", region_id);
+}
+
+fn test_hibernate_idle_region<T: Simulator>(cluster: &mut Cluster<T>) {
+    configure_for_hibernate(cluster, 60_000);
+    cluster.run();
+
+    cluster.must_put(b"k1", b"v1");
+    for i in 1..4 {
+        must_get_equal(&cluster.get_engine(i), b"k1", b"v1");
+    }
+    let heartbeats = count_heartbeats(cluster);
+    must_hibernate(&heartbeats);
+
+    // A proposal wakes up the region.
+    cluster.must_put(b"k2", b"v2");
+    for i in 1..4 {
+        must_get_equal(&cluster.get_engine(i), b"k2", b"v2");
+    }
+    sleep_ms(100);
+    assert!(heartbeats.load(Ordering::SeqCst) > 0);
+
+    // And it hibernates again after being idle.
+    must_hibernate(&heartbeats);
+}
+
+#[test]
+fn test_node_hibernate_idle_region() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_hibernate_idle_region(&mut cluster);
+}
+
+#[test]
+fn test_server_hibernate_idle_region() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_hibernate_idle_region(&mut cluster);
+}
+
+fn test_hibernated_region_leader_crash<T: Simulator>(cluster: &mut Cluster<T>) {
+    configure_for_hibernate(cluster, 2_000);
+    cluster.run();
+
+    cluster.must_put(b"k1", b"v1");
+    for i in 1..4 {
+        must_get_equal(&cluster.get_engine(i), b"k1", b"v1");
+    }
+    let heartbeats = count_heartbeats(cluster);
+    must_hibernate(&heartbeats);
+
+    let old_leader = cluster.leader_of_region(1).unwrap();
+    cluster.stop_node(old_leader.get_store_id());
+
+    // The followers wake up by themselves and elect a new leader.
+    must_elect_new_leader(cluster, 1, &old_leader);
+    cluster.must_put(b"k2", b"v2");
+    for i in 1..4 {
+        if i != old_leader.get_store_id() {
+            must_get_equal(&cluster.get_engine(i), b"k2", b"v2");
+        }
+    }
+}
+
+#[test]
+fn test_node_hibernated_region_leader_crash() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_hibernated_region_leader_crash(&mut cluster);
+}
+
+#[test]
+fn test_server_hibernated_region_leader_crash() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_hibernated_region_leader_crash(&mut cluster);
+}
+
+#[test]
+fn test_node_hibernated_region_leader_store_unreachable() {
+    let mut cluster = new_node_cluster(0, 3);
+    // The followers never wake up by themselves during the test.
+    configure_for_hibernate(&mut cluster, 60_000);
+    cluster.cfg.raft_store.pd_store_heartbeat_tick_interval = ReadableDuration::millis(100);
+    cluster.run();
+
+    cluster.must_put(b"k1", b"v1");
+    for i in 1..4 {
+        must_get_equal(&cluster.get_engine(i), b"k1", b"v1");
+    }
+    let heartbeats = count_heartbeats(&mut cluster);
+    must_hibernate(&heartbeats);
+
+    // The probe to the store of the stopped leader fails, so the followers wake up.
+    let old_leader = cluster.leader_of_region(1).unwrap();
+    cluster.stop_node(old_leader.get_store_id());
+    must_elect_new_leader(&mut cluster, 1, &old_leader);
+    cluster.must_put(b"k2", b"v2");
+}
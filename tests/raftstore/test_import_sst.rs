@@ -0,0 +1,168 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+use kvproto::backup::File;
+use kvproto::import_sstpb::{Pair, RewriteRule, SSTMeta, WriteBatch};
+use kvproto::metapb::Region;
+use tempdir::TempDir;
+
+use tikv::backup::{create_storage, BackupWriter};
+use tikv::storage::{make_key, CF_DEFAULT, CF_WRITE, SHORT_VALUE_MAX_LEN};
+use tikv::storage::mvcc::{Write, WriteType};
+use tikv::util::HandyRwLock;
+use tikv::util::io_limiter::IOLimiter;
+
+use super::server::new_server_cluster;
+use super::util::*;
+
+fn new_sst_meta(region: &Region, file: &File) -> SSTMeta {
+    let mut meta = SSTMeta::new();
+    meta.set_uuid(format!("{}_{}", region.get_id(), file.get_name()).into_bytes());
+    meta.set_crc32(file.get_crc32());
+    meta.set_cf_name(file.get_cf().to_owned());
+    meta.set_region_id(region.get_id());
+    meta.set_region_epoch(region.get_region_epoch().clone());
+    meta
+}
+
+#[test]
+fn test_import_sst() {
+    let mut cluster = new_server_cluster(0, 3);
+    cluster.run();
+
+    // Write the files of table 1 like a backup does.
+    let backup_dir = TempDir::new("test_import_sst_backup").unwrap();
+    let tmp_dir = TempDir::new("test_import_sst_tmp").unwrap();
+    let storage = create_storage(backup_dir.path().to_str().unwrap()).unwrap();
+    let write = Write::new(WriteType::Put, 10, Some(b"v".to_vec())).to_bytes();
+    let mut writer = BackupWriter::new(tmp_dir.path(), "1_1_1".to_owned()).unwrap();
+    for i in 0..10 {
+        let key = make_key(format!("t1_k{}", i).as_bytes()).append_ts(11);
+        writer.put(CF_WRITE, key.encoded(), &write).unwrap();
+    }
+    let files = writer.save(storage.as_ref(), &mut IOLimiter::new(0)).unwrap();
+    assert_eq!(files.len(), 1);
+    let file = &files[0];
+
+    // Restore it as table 2, the file is split into the two regions.
+    let mut rule = RewriteRule::new();
+    rule.set_old_key_prefix(b"t1_".to_vec());
+    rule.set_new_key_prefix(b"t2_".to_vec());
+    let region = cluster.get_region(b"");
+    cluster.must_split(&region, make_key(b"t2_k5").encoded());
+    let left = cluster.get_region(b"");
+    let right = cluster.get_region(make_key(b"t2_k5").encoded());
+    assert_ne!(left.get_id(), right.get_id());
+
+    // A file with a mismatched checksum is rejected.
+    {
+        let mut meta = new_sst_meta(&left, file);
+        meta.set_crc32(file.get_crc32() + 1);
+        let importer = cluster.sim.rl().importers[&left.get_peers()[0].get_store_id()].clone();
+        let res = importer.download(&meta, &left, storage.as_ref(), file.get_name(), &rule);
+        assert!(res.is_err());
+        assert!(!importer.exist(&meta));
+    }
+
+    // The meta doesn't limit the range, the file is cut to the region by the importer.
+    for region in &[left, right] {
+        let mut meta = new_sst_meta(region, file);
+        let mut ranges = vec![];
+        for peer in region.get_peers() {
+            let importer = cluster.sim.rl().importers[&peer.get_store_id()].clone();
+            let range = importer
+                .download(&meta, region, storage.as_ref(), file.get_name(), &rule)
+                .unwrap()
+                .unwrap();
+            assert!(range.get_start() >= region.get_start_key());
+            assert!(region.get_end_key().is_empty() || range.get_end() < region.get_end_key());
+            ranges.push(range);
+        }
+        // The range of the meta is only informative, the peers check the keys in the file.
+        meta.set_range(ranges.pop().unwrap());
+        let req = new_request(
+            region.get_id(),
+            region.get_region_epoch().clone(),
+            vec![new_ingest_sst_cmd(meta)],
+            false,
+        );
+        let resp = cluster
+            .call_command_on_leader(req, Duration::from_secs(5))
+            .unwrap();
+        assert!(!resp.get_header().has_error(), "{:?}", resp);
+    }
+
+    // Every replica has the data of table 2 only.
+    for engines in cluster.engines.values() {
+        for i in 0..10 {
+            let key = make_key(format!("t2_k{}", i).as_bytes()).append_ts(11);
+            must_get_cf_equal(&engines.kv_engine, CF_WRITE, key.encoded(), &write);
+            let key = make_key(format!("t1_k{}", i).as_bytes()).append_ts(11);
+            must_get_cf_none(&engines.kv_engine, CF_WRITE, key.encoded());
+        }
+    }
+}
+
+#[test]
+fn test_write_and_ingest() {
+    let mut cluster = new_server_cluster(0, 3);
+    cluster.run();
+
+    let region = cluster.get_region(b"");
+    let mut meta = SSTMeta::new();
+    meta.set_uuid(b"test_write_and_ingest".to_vec());
+    meta.set_region_id(region.get_id());
+    meta.set_region_epoch(region.get_region_epoch().clone());
+    let long_value = vec![b'v'; SHORT_VALUE_MAX_LEN + 1];
+    let mut batch = WriteBatch::new();
+    batch.set_commit_ts(10);
+    for i in 0..10 {
+        let mut pair = Pair::new();
+        pair.set_key(format!("k{}", i).into_bytes());
+        pair.set_value(long_value.clone());
+        batch.mut_pairs().push(pair);
+    }
+
+    // The pairs are uploaded to every store with a peer of the region.
+    let mut metas = vec![];
+    for peer in region.get_peers() {
+        let importer = cluster.sim.rl().importers[&peer.get_store_id()].clone();
+        let mut writer = importer.new_txn_writer(&meta).unwrap();
+        writer.write(&batch).unwrap();
+        metas = writer.finish().unwrap();
+    }
+    assert_eq!(metas.len(), 2);
+    for m in metas {
+        let req = new_request(
+            region.get_id(),
+            region.get_region_epoch().clone(),
+            vec![new_ingest_sst_cmd(m)],
+            false,
+        );
+        let resp = cluster
+            .call_command_on_leader(req, Duration::from_secs(5))
+            .unwrap();
+        assert!(!resp.get_header().has_error(), "{:?}", resp);
+    }
+
+    let write = Write::new(WriteType::Put, 10, None).to_bytes();
+    for engines in cluster.engines.values() {
+        for i in 0..10 {
+            let key = make_key(format!("k{}", i).as_bytes()).append_ts(10);
+            must_get_cf_equal(&engines.kv_engine, CF_DEFAULT, key.encoded(), &long_value);
+            must_get_cf_equal(&engines.kv_engine, CF_WRITE, key.encoded(), &write);
+        }
+    }
+}
@@ -0,0 +1,290 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::mpsc;
+use std::time::Duration;
+
+use kvproto::eraftpb::MessageType;
+use kvproto::metapb;
+use kvproto::raft_cmdpb::RaftCmdResponse;
+use tikv::pd::PdClient;
+use tikv::raftstore::store::Msg;
+use tikv::raftstore::store::util::find_peer;
+use tikv::util::HandyRwLock;
+use tikv::util::config::*;
+
+use super::cluster::{Cluster, Simulator};
+use super::node::new_node_cluster;
+use super::server::new_server_cluster;
+use super::transport_simulate::*;
+use super::util::*;
+
+fn configure_for_merge<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.cfg.raft_store.merge_check_tick_interval = ReadableDuration::millis(100);
+    cluster.cfg.raft_store.merge_max_log_gap = 10;
+}
+
+/// Splits the only region at `k2`, returns the left and right regions.
+fn split_for_merge<T: Simulator>(cluster: &mut Cluster<T>) -> (metapb::Region, metapb::Region) {
+    let pd_client = cluster.pd_client.clone();
+    cluster.must_put(b"k1", b"v1");
+    cluster.must_put(b"k3", b"v3");
+
+    let region = pd_client.get_region(b"k1").unwrap();
+    cluster.must_split(&region, b"k2");
+    let left = pd_client.get_region(b"k1").unwrap();
+    let right = pd_client.get_region(b"k3").unwrap();
+    assert_ne!(left.get_id(), right.get_id());
+
+    // Make sure both regions are created on all the stores.
+    for i in 1..4 {
+        must_get_equal(&cluster.get_engine(i), b"k1", b"v1");
+        must_get_equal(&cluster.get_engine(i), b"k3", b"v3");
+    }
+    (left, right)
+}
+
+/// Waits until pd reports that `key` belongs to region `region_id`.
+fn must_region_cover<T: Simulator>(cluster: &Cluster<T>, key: &[u8], region_id: u64) {
+    for _ in 0..200 {
+        if cluster.get_region_id(key) == region_id {
+            return;
+        }
+        sleep_ms(20);
+    }
+    panic!("{:?} is not covered by region {}", key, region_id);
+}
+
+fn must_put_eventually<T: Simulator>(cluster: &mut Cluster<T>, key: &[u8], value: &[u8]) {
+    for _ in 0..100 {
+        if cluster.put(key, value).is_ok() {
+            return;
+        }
+        sleep_ms(50);
+    }
+    panic!("failed to put {:?}", key);
+}
+
+fn test_base_merge<T: Simulator>(cluster: &mut Cluster<T>) {
+    configure_for_merge(cluster);
+    cluster.run();
+
+    let (left, right) = split_for_merge(cluster);
+    cluster.must_try_merge(left.get_id(), right.get_id());
+    must_region_cover(cluster, b"k1", right.get_id());
+
+    let region = cluster.get_region(b"k1");
+    assert_eq!(region.get_start_key(), left.get_start_key());
+    assert_eq!(region.get_end_key(), right.get_end_key());
+    assert!(region.get_region_epoch().get_version() > right.get_region_epoch().get_version());
+
+    cluster.must_put(b"k0", b"v0");
+    cluster.must_put(b"k4", b"v4");
+    for i in 1..4 {
+        must_get_equal(&cluster.get_engine(i), b"k0", b"v0");
+        must_get_equal(&cluster.get_engine(i), b"k1", b"v1");
+        must_get_equal(&cluster.get_engine(i), b"k4", b"v4");
+    }
+}
+
+#[test]
+fn test_node_base_merge() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_base_merge(&mut cluster);
+}
+
+#[test]
+fn test_server_base_merge() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_base_merge(&mut cluster);
+}
+
+fn test_merge_with_leader_transfer<T: Simulator>(cluster: &mut Cluster<T>) {
+    configure_for_merge(cluster);
+    cluster.run();
+
+    let (left, right) = split_for_merge(cluster);
+    let left_peer = find_peer(&left, 2).unwrap().clone();
+    let right_peer = find_peer(&right, 3).unwrap().clone();
+    cluster.must_transfer_leader(left.get_id(), left_peer);
+
+    // Transfer the leader of target region while merging.
+    cluster.transfer_leader(right.get_id(), right_peer);
+    cluster.must_try_merge(left.get_id(), right.get_id());
+    must_region_cover(cluster, b"k1", right.get_id());
+
+    cluster.must_put(b"k1", b"v11");
+    for i in 1..4 {
+        must_get_equal(&cluster.get_engine(i), b"k1", b"v11");
+        must_get_equal(&cluster.get_engine(i), b"k3", b"v3");
+    }
+}
+
+#[test]
+fn test_node_merge_with_leader_transfer() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_merge_with_leader_transfer(&mut cluster);
+}
+
+#[test]
+fn test_server_merge_with_leader_transfer() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_merge_with_leader_transfer(&mut cluster);
+}
+
+fn test_merge_with_stale_peer<T: Simulator>(cluster: &mut Cluster<T>) {
+    configure_for_merge(cluster);
+    cluster.run();
+
+    let (left, right) = split_for_merge(cluster);
+    let left_peer = find_peer(&left, 1).unwrap().clone();
+    let right_peer = find_peer(&right, 1).unwrap().clone();
+    cluster.must_transfer_leader(left.get_id(), left_peer);
+    cluster.must_transfer_leader(right.get_id(), right_peer);
+
+    // Peers on store 3 miss both the merge and the writes after it.
+    cluster.add_send_filter(IsolationFilterFactory::new(3));
+    cluster.must_put(b"k1", b"v11");
+    cluster.must_try_merge(left.get_id(), right.get_id());
+    must_region_cover(cluster, b"k1", right.get_id());
+    cluster.must_put(b"k4", b"v4");
+    must_get_equal(&cluster.get_engine(2), b"k4", b"v4");
+    must_get_none(&cluster.get_engine(3), b"k4");
+
+    // Stale peers should catch up the source logs and finish the merge too.
+    cluster.clear_send_filters();
+    must_get_equal(&cluster.get_engine(3), b"k1", b"v11");
+    must_get_equal(&cluster.get_engine(3), b"k4", b"v4");
+    cluster.must_put(b"k0", b"v0");
+    must_get_equal(&cluster.get_engine(3), b"k0", b"v0");
+}
+
+#[test]
+fn test_node_merge_with_stale_peer() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_merge_with_stale_peer(&mut cluster);
+}
+
+#[test]
+fn test_server_merge_with_stale_peer() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_merge_with_stale_peer(&mut cluster);
+}
+
+/// A merge carrying a target whose epoch is stale, e.g. one read before the target splits, is
+/// rolled back.
+fn test_merge_with_stale_target<T: Simulator>(cluster: &mut Cluster<T>) {
+    configure_for_merge(cluster);
+    cluster.run();
+
+    let (left, right) = split_for_merge(cluster);
+    // Split the target region, so a target with the previous epoch is stale.
+    cluster.must_split(&right, b"k4");
+    let right = cluster.get_region(b"k3");
+
+    let prepare_merge = new_prepare_merge(right.clone());
+    let mut stale_target = right.clone();
+    stale_target
+        .mut_region_epoch()
+        .set_version(right.get_region_epoch().get_version() - 1);
+    let prepare_merge_stale = new_prepare_merge(stale_target);
+    let req = new_admin_request(left.get_id(), left.get_region_epoch(), prepare_merge_stale);
+    let resp = cluster
+        .call_command_on_leader(req, Duration::from_secs(5))
+        .unwrap();
+    assert!(!resp.get_header().has_error(), "{:?}", resp);
+
+    // The merge should be rolled back and the source region becomes writable again.
+    must_put_eventually(cluster, b"k1", b"v11");
+    assert_eq!(cluster.get_region_id(b"k1"), left.get_id());
+    assert_eq!(cluster.get_region_id(b"k3"), right.get_id());
+
+    // Merging with the up-to-date target still works.
+    let left = cluster.get_region(b"k1");
+    let req = new_admin_request(left.get_id(), left.get_region_epoch(), prepare_merge);
+    let resp = cluster
+        .call_command_on_leader(req, Duration::from_secs(5))
+        .unwrap();
+    assert!(!resp.get_header().has_error(), "{:?}", resp);
+    must_region_cover(cluster, b"k1", right.get_id());
+    for i in 1..4 {
+        must_get_equal(&cluster.get_engine(i), b"k1", b"v11");
+    }
+}
+
+#[test]
+fn test_node_merge_with_stale_target() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_merge_with_stale_target(&mut cluster);
+}
+
+#[test]
+fn test_server_merge_with_stale_target() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_merge_with_stale_target(&mut cluster);
+}
+
+// Splits the target region while the PrepareMerge of the source region is in flight. The merge
+// is rolled back after the PrepareMerge is applied, because the target region has changed.
+fn test_merge_with_concurrent_split<T: Simulator>(cluster: &mut Cluster<T>) {
+    configure_for_merge(cluster);
+    cluster.run();
+
+    let (left, right) = split_for_merge(cluster);
+    let left_leader = find_peer(&left, 1).unwrap().clone();
+    cluster.must_transfer_leader(left.get_id(), left_leader.clone());
+
+    // The PrepareMerge can't be committed until the logs of the source region are sent.
+    cluster.add_send_filter(CloneFilterFactory(
+        RegionPacketFilter::new(left.get_id(), 1)
+            .direction(Direction::Send)
+            .msg_type(MessageType::MsgAppend),
+    ));
+    let prepare_merge = new_prepare_merge(right.clone());
+    let mut req = new_admin_request(left.get_id(), left.get_region_epoch(), prepare_merge);
+    req.mut_header().set_peer(left_leader);
+    let (tx, rx) = mpsc::channel();
+    let cb = Box::new(move |resp: RaftCmdResponse| {
+        let _ = tx.send(resp);
+    });
+    let ch = cluster.sim.rl().get_store_sendch(1).unwrap();
+    ch.try_send(Msg::new_raft_cmd(req, cb)).unwrap();
+    assert!(rx.recv_timeout(Duration::from_millis(300)).is_err());
+
+    cluster.must_split(&right, b"k4");
+    cluster.clear_send_filters();
+    let resp = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(!resp.get_header().has_error(), "{:?}", resp);
+
+    // The source region becomes writable again and no region is merged.
+    must_put_eventually(cluster, b"k1", b"v11");
+    assert_eq!(cluster.get_region_id(b"k1"), left.get_id());
+    assert_eq!(cluster.get_region_id(b"k3"), right.get_id());
+    assert_ne!(cluster.get_region_id(b"k4"), right.get_id());
+    for i in 1..4 {
+        must_get_equal(&cluster.get_engine(i), b"k1", b"v11");
+        must_get_equal(&cluster.get_engine(i), b"k3", b"v3");
+    }
+}
+
+#[test]
+fn test_node_merge_with_concurrent_split() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_merge_with_concurrent_split(&mut cluster);
+}
+
+#[test]
+fn test_server_merge_with_concurrent_split() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_merge_with_concurrent_split(&mut cluster);
+}
@@ -0,0 +1,219 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::Future;
+use grpc::{ChannelBuilder, Environment};
+use kvproto::eraftpb::MessageType;
+use kvproto::kvrpcpb::*;
+use kvproto::metapb;
+use kvproto::raft_cmdpb::RaftCmdResponse;
+use kvproto::tikvpb_grpc::TikvClient;
+use protobuf::RepeatedField;
+use tikv::pd::PdClient;
+use tikv::raftstore::Result;
+use tikv::util::HandyRwLock;
+
+use super::cluster::{Cluster, Simulator};
+use super::node::new_node_cluster;
+use super::server::{new_server_cluster, ServerCluster};
+use super::transport_simulate::*;
+use super::util::*;
+
+fn replica_read<T: Simulator>(
+    cluster: &mut Cluster<T>,
+    peer: &metapb::Peer,
+    key: &[u8],
+    timeout: Duration,
+) -> Result<RaftCmdResponse> {
+    let region_id = cluster.get_region_id(key);
+    let epoch = cluster.get_region_epoch(region_id);
+    let mut req = new_request(region_id, epoch, vec![new_get_cmd(key)], false);
+    req.mut_header().set_peer(peer.clone());
+    req.mut_header().set_replica_read(true);
+    cluster.call_command_on_node(peer.get_store_id(), req, timeout)
+}
+
+fn must_replica_read<T: Simulator>(
+    cluster: &mut Cluster<T>,
+    peer: &metapb::Peer,
+    key: &[u8],
+    value: &[u8],
+) {
+    let resp = replica_read(cluster, peer, key, Duration::from_secs(5)).unwrap();
+    assert!(!resp.get_header().has_error(), "{:?}", resp);
+    assert_eq!(resp.get_responses()[0].get_get().get_value(), value);
+}
+
+fn test_replica_read_on_follower<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.run();
+
+    cluster.must_put(b"k1", b"v1");
+    let leader = cluster.leader_of_region(1).unwrap();
+    let follower = cluster
+        .get_region(b"k1")
+        .get_peers()
+        .iter()
+        .find(|p| p.get_id() != leader.get_id())
+        .unwrap()
+        .clone();
+    must_replica_read(cluster, &follower, b"k1", b"v1");
+
+    // Writes are still rejected by the follower.
+    let region_id = cluster.get_region_id(b"k1");
+    let epoch = cluster.get_region_epoch(region_id);
+    let mut req = new_request(region_id, epoch, vec![new_put_cmd(b"k1", b"v")], false);
+    req.mut_header().set_peer(follower.clone());
+    req.mut_header().set_replica_read(true);
+    let resp = cluster
+        .call_command_on_node(follower.get_store_id(), req, Duration::from_secs(5))
+        .unwrap();
+    assert!(resp.get_header().get_error().has_not_leader(), "{:?}", resp);
+
+    // The follower can't catch up with the leader, so the read waits.
+    cluster.add_send_filter(CloneFilterFactory(
+        RegionPacketFilter::new(region_id, follower.get_store_id())
+            .msg_type(MessageType::MsgAppend)
+            .direction(Direction::Recv),
+    ));
+    cluster.must_put(b"k1", b"v2");
+    assert!(replica_read(cluster, &follower, b"k1", Duration::from_millis(500)).is_err());
+
+    // The latest value is read after the follower applies it.
+    cluster.clear_send_filters();
+    must_replica_read(cluster, &follower, b"k1", b"v2");
+}
+
+#[test]
+fn test_node_replica_read_on_follower() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_replica_read_on_follower(&mut cluster);
+}
+
+#[test]
+fn test_server_replica_read_on_follower() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_replica_read_on_follower(&mut cluster);
+}
+
+fn test_replica_read_timeout<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.run();
+    cluster.must_transfer_leader(1, new_peer(1, 1));
+    cluster.must_put(b"k1", b"v1");
+    let follower = new_peer(2, 2);
+    must_replica_read(cluster, &follower, b"k1", b"v1");
+
+    // The read index requests are dropped, the read fails instead of waiting forever.
+    cluster.add_send_filter(CloneFilterFactory(
+        RegionPacketFilter::new(1, 1)
+            .msg_type(MessageType::MsgReadIndex)
+            .direction(Direction::Recv),
+    ));
+    let resp = replica_read(cluster, &follower, b"k1", Duration::from_secs(5)).unwrap();
+    assert!(
+        resp.get_header().get_error().has_read_index_not_ready(),
+        "{:?}",
+        resp
+    );
+
+    // It succeeds after retrying.
+    cluster.clear_send_filters();
+    must_replica_read(cluster, &follower, b"k1", b"v1");
+}
+
+#[test]
+fn test_node_replica_read_timeout() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_replica_read_timeout(&mut cluster);
+}
+
+#[test]
+fn test_server_replica_read_timeout() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_replica_read_timeout(&mut cluster);
+}
+
+fn new_kv_client(cluster: &Cluster<ServerCluster>, store_id: u64) -> TikvClient {
+    let addr = cluster.sim.rl().get_addr(store_id);
+    let env = Arc::new(Environment::new(1));
+    let channel = ChannelBuilder::new(env).connect(&format!("{}", addr));
+    TikvClient::new(channel)
+}
+
+fn new_kv_context(cluster: &mut Cluster<ServerCluster>, peer: metapb::Peer) -> Context {
+    let mut ctx = Context::new();
+    ctx.set_region_id(1);
+    ctx.set_peer(peer);
+    ctx.set_region_epoch(cluster.get_region_epoch(1));
+    ctx
+}
+
+// Prewrites `key` with async commit on the leader, returns the min commit ts.
+fn must_async_commit_prewrite(
+    cluster: &mut Cluster<ServerCluster>,
+    client: &TikvClient,
+    key: &[u8],
+    start_ts: u64,
+) -> u64 {
+    let mut mutation = Mutation::new();
+    mutation.set_op(Op::Put);
+    mutation.set_key(key.to_vec());
+    mutation.set_value(b"v".to_vec());
+    // The prewrite is rejected until the leader syncs its max ts with PD.
+    for _ in 0..50 {
+        let mut req = PrewriteRequest::new();
+        req.set_context(new_kv_context(cluster, new_peer(1, 1)));
+        req.set_mutations(RepeatedField::from_vec(vec![mutation.clone()]));
+        req.set_primary_lock(key.to_vec());
+        req.set_start_version(start_ts);
+        req.set_lock_ttl(3000);
+        req.set_use_async_commit(true);
+        let resp = client.kv_prewrite(req).unwrap();
+        if resp.has_region_error() {
+            assert!(resp.get_region_error().has_max_timestamp_not_synced(), "{:?}", resp);
+            sleep_ms(100);
+            continue;
+        }
+        assert!(resp.get_errors().is_empty(), "{:?}", resp);
+        return resp.get_min_commit_ts();
+    }
+    panic!("the max ts of the leader is never synced");
+}
+
+#[test]
+fn test_server_replica_read_updates_leader_max_ts() {
+    let mut cluster = new_server_cluster(0, 3);
+    cluster.run();
+    cluster.must_transfer_leader(1, new_peer(1, 1));
+    let leader_client = new_kv_client(&cluster, 1);
+    let start_ts = cluster.pd_client.get_tso().wait().unwrap();
+    must_async_commit_prewrite(&mut cluster, &leader_client, b"k0", start_ts);
+
+    // The leader doesn't serve the read, it only returns the read index.
+    let start_ts = cluster.pd_client.get_tso().wait().unwrap();
+    let read_ts = cluster.pd_client.get_tso().wait().unwrap();
+    let mut ctx = new_kv_context(&mut cluster, new_peer(2, 2));
+    ctx.set_replica_read(true);
+    let mut get_req = GetRequest::new();
+    get_req.set_context(ctx);
+    get_req.set_key(b"k1".to_vec());
+    get_req.set_version(read_ts);
+    let get_resp = new_kv_client(&cluster, 2).kv_get(get_req).unwrap();
+    assert!(!get_resp.has_region_error(), "{:?}", get_resp);
+
+    // The transaction started before the read can't be committed at or before the read ts.
+    let min_commit_ts = must_async_commit_prewrite(&mut cluster, &leader_client, b"k1", start_ts);
+    assert!(min_commit_ts > read_ts, "{} <= {}", min_commit_ts, read_ts);
+}
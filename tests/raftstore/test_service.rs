@@ -20,7 +20,7 @@ use tikv::raftstore::store::{keys, Mutable, Peekable};
 use kvproto::kvrpcpb::*;
 use kvproto::raft_serverpb::*;
 use kvproto::coprocessor::*;
-use kvproto::{debugpb, eraftpb, metapb, raft_serverpb};
+use kvproto::{debugpb, eraftpb, kvrpcpb, metapb, raft_serverpb};
 use kvproto::tikvpb_grpc::TikvClient;
 use kvproto::debugpb_grpc::DebugClient;
 use rocksdb::Writable;
@@ -103,6 +103,93 @@ fn test_rawkv() {
     assert!(delete_resp.error.is_empty());
 }
 
+#[test]
+fn test_rawkv_batch() {
+    let (_cluster, client, ctx) = must_new_cluster_and_kv_client();
+    let pairs: Vec<KvPair> = (1..4)
+        .map(|i| {
+            let mut pair = KvPair::new();
+            pair.set_key(format!("key{}", i).into_bytes());
+            pair.set_value(format!("value{}", i).into_bytes());
+            pair
+        })
+        .collect();
+    let keys: Vec<Vec<u8>> = pairs.iter().map(|p| p.get_key().to_vec()).collect();
+
+    // Raw batch put
+    let mut put_req = RawBatchPutRequest::new();
+    put_req.set_context(ctx.clone());
+    put_req.set_cf(CF_WRITE.to_owned());
+    put_req.set_pairs(pairs.clone().into_iter().collect());
+    let put_resp = client.raw_batch_put(put_req).unwrap();
+    assert!(!put_resp.has_region_error());
+    assert!(put_resp.error.is_empty());
+
+    // Raw batch get
+    let mut get_req = RawBatchGetRequest::new();
+    get_req.set_context(ctx.clone());
+    get_req.set_cf(CF_WRITE.to_owned());
+    get_req.set_keys(keys.clone().into_iter().collect());
+    let get_resp = client.raw_batch_get(get_req.clone()).unwrap();
+    assert!(!get_resp.has_region_error());
+    assert_eq!(get_resp.get_pairs(), pairs.as_slice());
+
+    // Raw batch scan
+    let mut batch_scan_req = RawBatchScanRequest::new();
+    batch_scan_req.set_context(ctx.clone());
+    batch_scan_req.set_cf(CF_WRITE.to_owned());
+    let ranges = vec![(keys[2].clone(), keys[0].clone()), (keys[1].clone(), vec![])];
+    for (start_key, end_key) in ranges {
+        let mut range = kvrpcpb::KeyRange::new();
+        range.set_start_key(start_key);
+        range.set_end_key(end_key);
+        batch_scan_req.mut_ranges().push(range);
+    }
+    batch_scan_req.set_each_limit(1);
+    batch_scan_req.set_key_only(true);
+    batch_scan_req.set_reverse(true);
+    let batch_scan_resp = client.raw_batch_scan(batch_scan_req).unwrap();
+    assert!(!batch_scan_resp.has_region_error());
+    let scanned: Vec<Vec<u8>> = batch_scan_resp
+        .get_kvs()
+        .iter()
+        .map(|kv| kv.get_key().to_vec())
+        .collect();
+    assert_eq!(scanned, vec![keys[1].clone(), keys[0].clone()]);
+    assert!(batch_scan_resp.get_kvs().iter().all(|kv| kv.get_value().is_empty()));
+
+    // Raw batch delete
+    let mut delete_req = RawBatchDeleteRequest::new();
+    delete_req.set_context(ctx.clone());
+    delete_req.set_cf(CF_WRITE.to_owned());
+    delete_req.set_keys(keys[..1].to_vec().into_iter().collect());
+    let delete_resp = client.raw_batch_delete(delete_req).unwrap();
+    assert!(!delete_resp.has_region_error());
+    assert!(delete_resp.error.is_empty());
+
+    // Raw delete range
+    let mut delete_range_req = RawDeleteRangeRequest::new();
+    delete_range_req.set_context(ctx.clone());
+    delete_range_req.set_cf(CF_WRITE.to_owned());
+    delete_range_req.start_key = keys[1].clone();
+    delete_range_req.end_key = keys[2].clone();
+    let delete_range_resp = client.raw_delete_range(delete_range_req).unwrap();
+    assert!(!delete_range_resp.has_region_error());
+    assert!(delete_range_resp.error.is_empty());
+
+    let get_resp = client.raw_batch_get(get_req).unwrap();
+    assert_eq!(get_resp.get_pairs(), &pairs[2..]);
+
+    // Invalid cf
+    let mut get_req = RawGetRequest::new();
+    get_req.set_context(ctx.clone());
+    get_req.set_cf(CF_RAFT.to_owned());
+    get_req.key = keys[2].clone();
+    let get_resp = client.raw_get(get_req).unwrap();
+    assert!(!get_resp.has_region_error());
+    assert!(!get_resp.error.is_empty());
+}
+
 fn must_kv_prewrite(client: &TikvClient, ctx: Context, muts: Vec<Mutation>, pk: Vec<u8>, ts: u64) {
     let mut prewrite_req = PrewriteRequest::new();
     prewrite_req.set_context(ctx);
@@ -329,6 +416,72 @@ fn test_mvcc_rollback_and_cleanup() {
     assert_eq!(scan_lock_resp.locks.len(), 0);
 }
 
+#[test]
+fn test_txn_heart_beat_and_check_txn_status() {
+    let (_cluster, client, ctx) = must_new_cluster_and_kv_client();
+    let (k, v) = (b"key".to_vec(), b"value".to_vec());
+    let ts = |physical: u64| physical << 18;
+
+    let start_version = ts(1);
+    let mut mutation = Mutation::new();
+    mutation.op = Op::Put;
+    mutation.key = k.clone();
+    mutation.value = v.clone();
+    must_kv_prewrite(&client, ctx.clone(), vec![mutation], k.clone(), start_version);
+
+    // Heart beat
+    let advise_ttl = start_version * 2;
+    let mut heart_beat_req = TxnHeartBeatRequest::new();
+    heart_beat_req.set_context(ctx.clone());
+    heart_beat_req.set_primary_lock(k.clone());
+    heart_beat_req.set_start_version(start_version);
+    heart_beat_req.set_advise_lock_ttl(advise_ttl);
+    let heart_beat_resp = client.kv_txn_heart_beat(heart_beat_req).unwrap();
+    assert!(!heart_beat_resp.has_region_error());
+    assert!(!heart_beat_resp.has_error());
+    assert_eq!(heart_beat_resp.get_lock_ttl(), advise_ttl);
+
+    // Check txn status, the lock is alive.
+    let mut check_req = CheckTxnStatusRequest::new();
+    check_req.set_context(ctx.clone());
+    check_req.set_primary_key(k.clone());
+    check_req.set_lock_ts(start_version);
+    check_req.set_current_ts(ts(2));
+    let check_resp = client.kv_check_txn_status(check_req.clone()).unwrap();
+    assert!(!check_resp.has_region_error());
+    assert!(!check_resp.has_error());
+    assert_eq!(check_resp.get_lock_ttl(), advise_ttl);
+    assert_eq!(check_resp.get_commit_version(), 0);
+
+    // The lock is expired and rolled back.
+    check_req.set_current_ts(ts(advise_ttl + 2));
+    let check_resp = client.kv_check_txn_status(check_req).unwrap();
+    assert!(!check_resp.has_region_error());
+    assert!(!check_resp.has_error());
+    assert_eq!(check_resp.get_lock_ttl(), 0);
+    assert_eq!(check_resp.get_commit_version(), 0);
+
+    let mut get_req = GetRequest::new();
+    get_req.set_context(ctx.clone());
+    get_req.key = k.clone();
+    get_req.version = ts(3);
+    let get_resp = client.kv_get(get_req).unwrap();
+    assert!(!get_resp.has_region_error());
+    assert!(!get_resp.has_error());
+    assert!(get_resp.value.is_empty());
+}
+
+#[test]
+fn test_kv_import_unimplemented() {
+    let (_cluster, client, _) = must_new_cluster_and_kv_client();
+    match client.kv_import(ImportRequest::new()).unwrap_err() {
+        Error::RpcFailure(status) => {
+            assert_eq!(status.status, RpcStatusCode::Unimplemented);
+        }
+        _ => panic!("expect Unimplemented"),
+    }
+}
+
 #[test]
 fn test_mvcc_resolve_lock_gc_and_delete() {
     let (_cluster, client, ctx) = must_new_cluster_and_kv_client();
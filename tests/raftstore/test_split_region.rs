@@ -11,7 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{fs, thread};
 use std::sync::mpsc::channel;
 use rand::{self, Rng};
@@ -726,3 +726,49 @@ fn test_server_quick_election_after_split() {
     let mut cluster = new_server_cluster(0, 3);
     test_quick_election_after_split(&mut cluster);
 }
+
+fn test_load_split_region<T: Simulator>(cluster: &mut Cluster<T>) {
+    // A region is split after 3 windows with more than 1 write.
+    cluster.cfg.raft_store.load_split_qps_threshold = 10;
+    cluster.cfg.raft_store.load_split_check_interval = ReadableDuration::millis(100);
+    cluster.cfg.raft_store.load_split_detect_times = 3;
+    cluster.run();
+
+    let pd_client = cluster.pd_client.clone();
+    let region = pd_client.get_region(b"").unwrap();
+
+    // A single hot key can't be split.
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(1) {
+        cluster.must_put(b"k5", b"v");
+    }
+    assert_eq!(pd_client.get_region(b"k9").unwrap(), region);
+
+    // The load is spread over the keys, the region is split in the middle of them.
+    let keys: Vec<_> = (0..10).map(|i| format!("k{}", i).into_bytes()).collect();
+    let start = Instant::now();
+    while pd_client.get_region(b"k9").unwrap() == region {
+        assert!(start.elapsed() < Duration::from_secs(10), "region isn't split");
+        for key in &keys {
+            cluster.must_put(key, b"v");
+        }
+    }
+    let left = pd_client.get_region(b"k0").unwrap();
+    let right = pd_client.get_region(b"k9").unwrap();
+    assert_eq!(left.get_end_key(), right.get_start_key());
+    assert!(keys[1..].iter().any(|k| k.as_slice() == right.get_start_key()));
+    cluster.must_put(b"k0", b"v0");
+    cluster.must_put(b"k9", b"v9");
+}
+
+#[test]
+fn test_node_load_split_region() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_load_split_region(&mut cluster);
+}
+
+#[test]
+fn test_server_load_split_region() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_load_split_region(&mut cluster);
+}
@@ -0,0 +1,171 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+use kvproto::metapb;
+use kvproto::raft_cmdpb::RaftCmdResponse;
+use rocksdb::Writable;
+use tikv::raftstore::store::{keys, Msg};
+use tikv::storage::{make_key, CF_LOCK};
+use tikv::storage::mvcc::{Lock, LockType};
+use tikv::util::HandyRwLock;
+
+use super::cluster::{Cluster, Simulator};
+use super::node::new_node_cluster;
+use super::server::new_server_cluster;
+use super::util::*;
+
+fn stale_read<T: Simulator>(
+    cluster: &mut Cluster<T>,
+    peer: &metapb::Peer,
+    key: &[u8],
+    read_ts: u64,
+) -> RaftCmdResponse {
+    let region_id = cluster.get_region_id(key);
+    let epoch = cluster.get_region_epoch(region_id);
+    let mut req = new_request(region_id, epoch, vec![new_get_cmd(key)], false);
+    req.mut_header().set_peer(peer.clone());
+    req.mut_header().set_stale_read(true);
+    req.mut_header().set_read_ts(read_ts);
+    cluster
+        .call_command_on_node(peer.get_store_id(), req, Duration::from_secs(5))
+        .unwrap()
+}
+
+fn advance_resolved_ts<T: Simulator>(cluster: &mut Cluster<T>, min_ts: u64) {
+    let leader = cluster.leader_of_region(1).unwrap();
+    let ch = cluster
+        .sim
+        .rl()
+        .get_store_sendch(leader.get_store_id())
+        .unwrap();
+    ch.try_send(Msg::AdvanceResolvedTs { min_ts: min_ts })
+        .unwrap();
+}
+
+/// Advances the resolved ts until the peer can serve the stale read at `read_ts`.
+fn must_stale_read<T: Simulator>(
+    cluster: &mut Cluster<T>,
+    peer: &metapb::Peer,
+    min_ts: u64,
+    read_ts: u64,
+    key: &[u8],
+    value: &[u8],
+) {
+    for _ in 0..50 {
+        advance_resolved_ts(cluster, min_ts);
+        sleep_ms(100);
+        let resp = stale_read(cluster, peer, key, read_ts);
+        if resp.get_header().has_error() {
+            assert!(resp.get_header().get_error().has_data_is_not_ready(), "{:?}", resp);
+            continue;
+        }
+        assert_eq!(resp.get_responses()[0].get_get().get_value(), value);
+        return;
+    }
+    panic!("peer {:?} can't serve stale read at {}", peer, read_ts);
+}
+
+fn must_not_ready<T: Simulator>(cluster: &mut Cluster<T>, peer: &metapb::Peer, read_ts: u64) {
+    let resp = stale_read(cluster, peer, b"k1", read_ts);
+    assert!(
+        resp.get_header().get_error().has_data_is_not_ready(),
+        "{:?}",
+        resp
+    );
+}
+
+fn test_stale_read_on_follower<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.run();
+
+    cluster.must_put(b"k1", b"v1");
+    let leader = cluster.leader_of_region(1).unwrap();
+    let follower = cluster
+        .get_region(b"k1")
+        .get_peers()
+        .iter()
+        .find(|p| p.get_id() != leader.get_id())
+        .unwrap()
+        .clone();
+    must_get_equal(&cluster.get_engine(follower.get_store_id()), b"k1", b"v1");
+
+    // Nothing is resolved yet.
+    must_not_ready(cluster, &follower, 100);
+
+    must_stale_read(cluster, &follower, 100, 100, b"k1", b"v1");
+    must_stale_read(cluster, &leader, 100, 100, b"k1", b"v1");
+    must_not_ready(cluster, &follower, 101);
+
+    // The resolved ts is blocked by the lock.
+    let lock = Lock::new(LockType::Put, b"k2".to_vec(), 150, 0, None);
+    cluster.must_put_cf(CF_LOCK, make_key(b"k2").encoded(), &lock.to_bytes());
+    must_stale_read(cluster, &follower, 300, 150, b"k1", b"v1");
+    must_not_ready(cluster, &follower, 151);
+}
+
+#[test]
+fn test_node_stale_read_on_follower() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_stale_read_on_follower(&mut cluster);
+}
+
+#[test]
+fn test_server_stale_read_on_follower() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_stale_read_on_follower(&mut cluster);
+}
+
+fn test_stale_read_after_leader_change<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.run();
+    cluster.must_transfer_leader(1, new_peer(1, 1));
+    cluster.must_put(b"k1", b"v1");
+    let peer = new_peer(2, 2);
+    must_get_equal(&cluster.get_engine(2), b"k1", b"v1");
+    must_stale_read(cluster, &peer, 100, 100, b"k1", b"v1");
+
+    // The safe ts learned from the old leader is dropped.
+    cluster.must_transfer_leader(1, peer.clone());
+    must_not_ready(cluster, &peer, 100);
+    must_stale_read(cluster, &peer, 200, 200, b"k1", b"v1");
+
+    // A lock only in the engine of store 2 is found once the locks are reloaded, which
+    // happens before the peer publishes a safe ts as the new leader.
+    cluster.must_transfer_leader(1, new_peer(1, 1));
+    let engine = cluster.get_engine(2);
+    let lock = Lock::new(LockType::Put, b"k2".to_vec(), 250, 0, None);
+    let handle = engine.cf_handle(CF_LOCK).unwrap();
+    engine
+        .put_cf(
+            handle,
+            &keys::data_key(make_key(b"k2").encoded()),
+            &lock.to_bytes(),
+        )
+        .unwrap();
+    cluster.must_transfer_leader(1, peer.clone());
+    must_not_ready(cluster, &peer, 200);
+    must_stale_read(cluster, &peer, 300, 250, b"k1", b"v1");
+    must_not_ready(cluster, &peer, 251);
+}
+
+#[test]
+fn test_node_stale_read_after_leader_change() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_stale_read_after_leader_change(&mut cluster);
+}
+
+#[test]
+fn test_server_stale_read_after_leader_change() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_stale_read_after_leader_change(&mut cluster);
+}
@@ -11,17 +11,25 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::Arc;
 use std::time::Duration;
 use std::thread;
 
+use futures::Future;
+use grpc::{ChannelBuilder, Environment};
 use kvproto::eraftpb::MessageType;
+use kvproto::kvrpcpb::*;
+use kvproto::tikvpb_grpc::TikvClient;
+use protobuf::RepeatedField;
+use tikv::pd::PdClient;
+use tikv::util::HandyRwLock;
 use tikv::util::config::*;
 
 use super::util::*;
 use super::cluster::{Cluster, Simulator};
 use super::transport_simulate::*;
 use super::node::new_node_cluster;
-use super::server::new_server_cluster;
+use super::server::{new_server_cluster, ServerCluster};
 
 fn test_basic_transfer_leader<T: Simulator>(cluster: &mut Cluster<T>) {
     cluster.cfg.raft_store.raft_heartbeat_ticks = 20;
@@ -191,3 +199,65 @@ fn test_node_transfer_leader_during_snapshot() {
     let mut cluster = new_node_cluster(0, 3);
     test_transfer_leader_during_snapshot(&mut cluster);
 }
+
+fn new_kv_client(cluster: &Cluster<ServerCluster>, store_id: u64) -> TikvClient {
+    let addr = cluster.sim.rl().get_addr(store_id);
+    let env = Arc::new(Environment::new(1));
+    let channel = ChannelBuilder::new(env).connect(&format!("{}", addr));
+    TikvClient::new(channel)
+}
+
+fn new_kv_context(cluster: &mut Cluster<ServerCluster>, region_id: u64) -> Context {
+    let mut ctx = Context::new();
+    ctx.set_region_id(region_id);
+    ctx.set_peer(cluster.leader_of_region(region_id).unwrap());
+    ctx.set_region_epoch(cluster.get_region_epoch(region_id));
+    ctx
+}
+
+#[test]
+fn test_server_transfer_leader_syncs_max_ts() {
+    let mut cluster = new_server_cluster(0, 3);
+    cluster.run();
+    cluster.must_transfer_leader(1, new_peer(1, 1));
+
+    let start_ts = cluster.pd_client.get_tso().wait().unwrap();
+    let read_ts = cluster.pd_client.get_tso().wait().unwrap();
+
+    // The old leader serves a read at `read_ts`, which the new leader doesn't know.
+    let mut get_req = GetRequest::new();
+    get_req.set_context(new_kv_context(&mut cluster, 1));
+    get_req.set_key(b"k1".to_vec());
+    get_req.set_version(read_ts);
+    let get_resp = new_kv_client(&cluster, 1).kv_get(get_req).unwrap();
+    assert!(!get_resp.has_region_error(), "{:?}", get_resp);
+
+    cluster.must_transfer_leader(1, new_peer(2, 2));
+
+    // The async-commit prewrite is rejected until the new leader syncs its max ts with PD, then
+    // the min commit ts is larger than the read ts.
+    let client = new_kv_client(&cluster, 2);
+    let mut mutation = Mutation::new();
+    mutation.set_op(Op::Put);
+    mutation.set_key(b"k1".to_vec());
+    mutation.set_value(b"v1".to_vec());
+    for _ in 0..50 {
+        let mut req = PrewriteRequest::new();
+        req.set_context(new_kv_context(&mut cluster, 1));
+        req.set_mutations(RepeatedField::from_vec(vec![mutation.clone()]));
+        req.set_primary_lock(b"k1".to_vec());
+        req.set_start_version(start_ts);
+        req.set_lock_ttl(3000);
+        req.set_use_async_commit(true);
+        let resp = client.kv_prewrite(req).unwrap();
+        if resp.has_region_error() {
+            assert!(resp.get_region_error().has_max_timestamp_not_synced(), "{:?}", resp);
+            sleep_ms(100);
+            continue;
+        }
+        assert!(resp.get_errors().is_empty(), "{:?}", resp);
+        assert!(resp.get_min_commit_ts() > read_ts, "{:?}", resp);
+        return;
+    }
+    panic!("the max ts of the new leader is never synced");
+}
@@ -12,6 +12,7 @@
 // limitations under the License.
 
 
+use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
 use std::thread;
@@ -20,11 +21,14 @@ use rocksdb::DB;
 use protobuf;
 
 use kvproto::metapb::{self, RegionEpoch};
-use kvproto::raft_cmdpb::{AdminRequest, RaftCmdRequest, RaftCmdResponse, Request, StatusRequest};
+use kvproto::raft_cmdpb::{AdminRequest, ChangePeerRequest, RaftCmdRequest, RaftCmdResponse, Request,
+                          StatusRequest};
 use kvproto::raft_cmdpb::{AdminCmdType, CmdType, StatusCmdType};
 use kvproto::pdpb::{ChangePeer, RegionHeartbeatResponse, TransferLeader};
 use kvproto::eraftpb::ConfChangeType;
+use kvproto::import_sstpb::SSTMeta;
 
+use tikv::import::SSTImporter;
 use tikv::raftstore::store::*;
 use tikv::server::Config as ServerConfig;
 use tikv::storage::Config as StorageConfig;
@@ -185,6 +189,13 @@ pub fn new_delete_range_cmd(cf: &str, start: &[u8], end: &[u8]) -> Request {
     cmd
 }
 
+pub fn new_ingest_sst_cmd(meta: SSTMeta) -> Request {
+    let mut cmd = Request::new();
+    cmd.set_cmd_type(CmdType::IngestSST);
+    cmd.mut_ingest_sst().set_sst(meta);
+    cmd
+}
+
 pub fn new_status_request(
     region_id: u64,
     peer: metapb::Peer,
@@ -226,6 +237,18 @@ pub fn new_change_peer_request(change_type: ConfChangeType, peer: metapb::Peer)
     req
 }
 
+pub fn new_change_peer_v2_request(changes: Vec<(ConfChangeType, metapb::Peer)>) -> AdminRequest {
+    let mut req = AdminRequest::new();
+    req.set_cmd_type(AdminCmdType::ChangePeerV2);
+    for (change_type, peer) in changes {
+        let mut cp = ChangePeerRequest::new();
+        cp.set_change_type(change_type);
+        cp.set_peer(peer);
+        req.mut_change_peer_v2().mut_changes().push(cp);
+    }
+    req
+}
+
 pub fn new_transfer_leader_cmd(peer: metapb::Peer) -> AdminRequest {
     let mut cmd = AdminRequest::new();
     cmd.set_cmd_type(AdminCmdType::TransferLeader);
@@ -233,6 +256,13 @@ pub fn new_transfer_leader_cmd(peer: metapb::Peer) -> AdminRequest {
     cmd
 }
 
+pub fn new_prepare_merge(target_region: metapb::Region) -> AdminRequest {
+    let mut cmd = AdminRequest::new();
+    cmd.set_cmd_type(AdminCmdType::PrepareMerge);
+    cmd.mut_prepare_merge().set_target(target_region);
+    cmd
+}
+
 pub fn new_peer(store_id: u64, peer_id: u64) -> metapb::Peer {
     let mut peer = metapb::Peer::new();
     peer.set_store_id(store_id);
@@ -240,6 +270,12 @@ pub fn new_peer(store_id: u64, peer_id: u64) -> metapb::Peer {
     peer
 }
 
+pub fn new_learner_peer(store_id: u64, peer_id: u64) -> metapb::Peer {
+    let mut peer = new_peer(store_id, peer_id);
+    peer.set_is_learner(true);
+    peer
+}
+
 
 pub fn new_store(store_id: u64, addr: String) -> metapb::Store {
     let mut store = metapb::Store::new();
@@ -249,6 +285,11 @@ pub fn new_store(store_id: u64, addr: String) -> metapb::Store {
     store
 }
 
+pub fn new_importer(engines: &Engines) -> Arc<SSTImporter> {
+    let path = Path::new(engines.kv_engine.path()).join("import");
+    Arc::new(SSTImporter::new(&path).unwrap())
+}
+
 pub fn sleep_ms(ms: u64) {
     thread::sleep(Duration::from_millis(ms));
 }
@@ -276,12 +317,27 @@ pub fn new_pd_add_change_peer(
 ) -> Option<RegionHeartbeatResponse> {
     if let Some(p) = find_peer(region, peer.get_store_id()) {
         assert_eq!(p.get_id(), peer.get_id());
-        return None;
+        if !p.get_is_learner() {
+            return None;
+        }
+        // Promote the learner to voter.
     }
 
     Some(new_pd_change_peer(ConfChangeType::AddNode, peer))
 }
 
+pub fn new_pd_add_learner_change_peer(
+    region: &metapb::Region,
+    peer: metapb::Peer,
+) -> Option<RegionHeartbeatResponse> {
+    if let Some(p) = find_peer(region, peer.get_store_id()) {
+        assert_eq!(p.get_id(), peer.get_id());
+        return None;
+    }
+
+    Some(new_pd_change_peer(ConfChangeType::AddLearnerNode, peer))
+}
+
 pub fn new_pd_remove_change_peer(
     region: &metapb::Region,
     peer: metapb::Peer,
@@ -13,7 +13,8 @@
 
 use super::sync_storage::SyncStorage;
 use kvproto::kvrpcpb::{Context, LockInfo};
-use tikv::storage::{self, make_key, Key, KvPair, Mutation, Value};
+use tikv::storage::{self, make_key, Key, KvPair, Mutation, SecondaryLocksStatus, TxnStatus,
+                    Value};
 use tikv::storage::mvcc::{self, MAX_TXN_WRITE_SIZE};
 use tikv::storage::txn;
 use raftstore::cluster::Cluster;
@@ -275,6 +276,38 @@ impl AssertionStorage {
         assert_eq!(result, expect);
     }
 
+    /// Scans [`start_key`, `end_key`), or [`end_key`, `start_key`) in descending order if
+    /// `reverse` is set. An empty key means the region boundary.
+    pub fn scan_range_ok(
+        &self,
+        start_key: &[u8],
+        end_key: &[u8],
+        limit: usize,
+        ts: u64,
+        reverse: bool,
+        expect: Vec<Option<(&[u8], &[u8])>>,
+    ) {
+        let start_key = if reverse && start_key.is_empty() {
+            Key::from_encoded(vec![])
+        } else {
+            make_key(start_key)
+        };
+        let end_key = if end_key.is_empty() {
+            None
+        } else {
+            Some(make_key(end_key))
+        };
+        let result = self.store
+            .scan_range(self.ctx.clone(), start_key, end_key, limit, false, reverse, ts)
+            .unwrap();
+        let result: Vec<Option<KvPair>> = result.into_iter().map(Result::ok).collect();
+        let expect: Vec<Option<KvPair>> = expect
+            .into_iter()
+            .map(|x| x.map(|(k, v)| (k.to_vec(), v.to_vec())))
+            .collect();
+        assert_eq!(result, expect);
+    }
+
     pub fn scan_key_only_ok(
         &self,
         start_key: &[u8],
@@ -300,6 +333,19 @@ impl AssertionStorage {
             .unwrap();
     }
 
+    pub fn prewrite_with_ttl_ok(
+        &self,
+        mutations: Vec<Mutation>,
+        primary: &[u8],
+        start_ts: u64,
+        lock_ttl: u64,
+    ) {
+        let res = self.store
+            .prewrite_with_ttl(self.ctx.clone(), mutations, primary.to_vec(), start_ts, lock_ttl)
+            .unwrap();
+        assert!(res.iter().all(|r| r.is_ok()), "{:?}", res);
+    }
+
     pub fn prewrite_locked(
         &self,
         mutations: Vec<Mutation>,
@@ -346,20 +392,149 @@ impl AssertionStorage {
         self.expect_invalid_tso_err(resp, start_ts, commit_ts);
     }
 
-    pub fn cleanup_ok(&self, key: &[u8], start_ts: u64) {
+    pub fn cleanup_ok(&self, key: &[u8], start_ts: u64, current_ts: u64) {
         self.store
-            .cleanup(self.ctx.clone(), make_key(key), start_ts)
+            .cleanup(self.ctx.clone(), make_key(key), start_ts, current_ts)
+            .unwrap();
+    }
+
+    pub fn cleanup_err(&self, key: &[u8], start_ts: u64, current_ts: u64) {
+        assert!(
+            self.store
+                .cleanup(self.ctx.clone(), make_key(key), start_ts, current_ts)
+                .is_err()
+        );
+    }
+
+    pub fn txn_heart_beat_ok(&self, primary_key: &[u8], start_ts: u64, advise_ttl: u64, ttl: u64) {
+        match self.store
+            .txn_heart_beat(self.ctx.clone(), make_key(primary_key), start_ts, advise_ttl)
+            .unwrap()
+        {
+            TxnStatus::Uncommitted { lock } => assert_eq!(lock.ttl, ttl),
+            status => panic!("expect uncommitted, but got {:?}", status),
+        }
+    }
+
+    pub fn txn_heart_beat_err(&self, primary_key: &[u8], start_ts: u64, advise_ttl: u64) {
+        assert!(
+            self.store
+                .txn_heart_beat(self.ctx.clone(), make_key(primary_key), start_ts, advise_ttl)
+                .is_err()
+        );
+    }
+
+    /// Checks the transaction is still alive with the lock ttl `ttl`.
+    pub fn check_txn_status_uncommitted(
+        &self,
+        primary_key: &[u8],
+        lock_ts: u64,
+        current_ts: u64,
+        ttl: u64,
+    ) {
+        match self.store
+            .check_txn_status(self.ctx.clone(), make_key(primary_key), lock_ts, current_ts)
+            .unwrap()
+        {
+            TxnStatus::Uncommitted { lock } => assert_eq!(lock.ttl, ttl),
+            status => panic!("expect uncommitted, but got {:?}", status),
+        }
+    }
+
+    pub fn check_txn_status_ok(
+        &self,
+        primary_key: &[u8],
+        lock_ts: u64,
+        current_ts: u64,
+        expect: TxnStatus,
+    ) {
+        let status = self.store
+            .check_txn_status(self.ctx.clone(), make_key(primary_key), lock_ts, current_ts)
             .unwrap();
+        assert_eq!(status, expect);
     }
 
-    pub fn cleanup_err(&self, key: &[u8], start_ts: u64) {
+    /// Prewrites with async commit, returns the `min_commit_ts` of the transaction.
+    pub fn prewrite_async_commit_ok(
+        &self,
+        mutations: Vec<Mutation>,
+        primary: &[u8],
+        secondaries: Vec<&[u8]>,
+        start_ts: u64,
+    ) -> u64 {
+        let secondaries = secondaries.iter().map(|k| k.to_vec()).collect();
+        let res = self.store
+            .prewrite_async_commit(
+                self.ctx.clone(),
+                mutations,
+                primary.to_vec(),
+                secondaries,
+                start_ts,
+            )
+            .unwrap();
+        assert!(res.locks.iter().all(|r| r.is_ok()), "{:?}", res.locks);
+        res.min_commit_ts
+    }
+
+    /// Prewrites with 1PC, returns the commit ts or 0 if it falls back to 2PC.
+    pub fn prewrite_one_pc_ok(
+        &self,
+        mutations: Vec<Mutation>,
+        primary: &[u8],
+        start_ts: u64,
+    ) -> u64 {
+        let res = self.store
+            .prewrite_one_pc(self.ctx.clone(), mutations, primary.to_vec(), start_ts)
+            .unwrap();
+        assert!(res.locks.iter().all(|r| r.is_ok()), "{:?}", res.locks);
+        res.one_pc_commit_ts
+    }
+
+    pub fn prewrite_one_pc_err(&self, mutations: Vec<Mutation>, primary: &[u8], start_ts: u64) {
         assert!(
             self.store
-                .cleanup(self.ctx.clone(), make_key(key), start_ts)
+                .prewrite_one_pc(self.ctx.clone(), mutations, primary.to_vec(), start_ts)
                 .is_err()
         );
     }
 
+    pub fn commit_ts_expired(&self, keys: Vec<&[u8]>, start_ts: u64, commit_ts: u64) {
+        let keys: Vec<Key> = keys.iter().map(|x| make_key(x)).collect();
+        match self.store.commit(self.ctx.clone(), keys, start_ts, commit_ts) {
+            Err(storage::Error::Txn(txn::Error::Mvcc(mvcc::Error::CommitTsExpired { .. }))) => {}
+            res => panic!("expect commit ts expired, but got {:?}", res),
+        }
+    }
+
+    /// Checks all the `keys` are locked by the async-commit transaction.
+    pub fn check_secondary_locks_locked(&self, keys: Vec<&[u8]>, start_ts: u64) {
+        let count = keys.len();
+        let keys: Vec<Key> = keys.iter().map(|x| make_key(x)).collect();
+        match self.store
+            .check_secondary_locks(self.ctx.clone(), keys, start_ts)
+            .unwrap()
+        {
+            SecondaryLocksStatus::Locked(locks) => {
+                assert_eq!(locks.len(), count);
+                assert!(locks.iter().all(|l| l.ts == start_ts && l.use_async_commit));
+            }
+            status => panic!("expect locked, but got {:?}", status),
+        }
+    }
+
+    pub fn check_secondary_locks_ok(
+        &self,
+        keys: Vec<&[u8]>,
+        start_ts: u64,
+        expect: SecondaryLocksStatus,
+    ) {
+        let keys: Vec<Key> = keys.iter().map(|x| make_key(x)).collect();
+        let status = self.store
+            .check_secondary_locks(self.ctx.clone(), keys, start_ts)
+            .unwrap();
+        assert_eq!(status, expect);
+    }
+
     pub fn rollback_ok(&self, keys: Vec<&[u8]>, start_ts: u64) {
         let keys: Vec<Key> = keys.iter().map(|x| make_key(x)).collect();
         self.store
@@ -418,20 +593,20 @@ impl AssertionStorage {
     }
 
     pub fn raw_get_ok(&self, key: Vec<u8>, value: Option<Vec<u8>>) {
-        assert_eq!(self.store.raw_get(self.ctx.clone(), key).unwrap(), value);
+        assert_eq!(self.store.raw_get(self.ctx.clone(), "".to_owned(), key).unwrap(), value);
     }
 
     pub fn raw_put_ok(&self, key: Vec<u8>, value: Vec<u8>) {
-        self.store.raw_put(self.ctx.clone(), key, value).unwrap();
+        self.store.raw_put(self.ctx.clone(), "".to_owned(), key, value).unwrap();
     }
 
     pub fn raw_delete_ok(&self, key: Vec<u8>) {
-        self.store.raw_delete(self.ctx.clone(), key).unwrap()
+        self.store.raw_delete(self.ctx.clone(), "".to_owned(), key).unwrap()
     }
 
     pub fn raw_scan_ok(&self, start_key: Vec<u8>, limit: usize, expect: Vec<(&[u8], &[u8])>) {
         let result: Vec<KvPair> = self.store
-            .raw_scan(self.ctx.clone(), start_key, limit)
+            .raw_scan(self.ctx.clone(), "".to_owned(), start_key, limit)
             .unwrap()
             .into_iter()
             .map(|x| x.unwrap())
@@ -16,4 +16,6 @@ pub mod sync_storage;
 pub mod assert_storage;
 mod test_storage;
 mod test_raft_storage;
+mod test_backup;
+mod test_gc_filter;
 pub mod util;
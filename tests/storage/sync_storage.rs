@@ -14,7 +14,8 @@
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
-use tikv::storage::{Engine, Key, KvPair, Mutation, Options, Result, Storage, Value};
+use tikv::storage::{ConcurrencyManager, Engine, Key, KvPair, Mutation, Options, PrewriteResult,
+                    Result, SecondaryLocksStatus, Storage, TxnStatus, Value};
 use tikv::storage::config::Config;
 use kvproto::kvrpcpb::{Context, LockInfo};
 
@@ -61,6 +62,10 @@ impl SyncStorage {
         self.store.get_engine()
     }
 
+    pub fn get_concurrency_manager(&self) -> Arc<ConcurrencyManager> {
+        self.store.get_concurrency_manager()
+    }
+
     pub fn get(&self, ctx: Context, key: &Key, start_ts: u64) -> Result<Option<Value>> {
         wait_op!(|cb| {
             self.store
@@ -91,16 +96,25 @@ impl SyncStorage {
         key_only: bool,
         start_ts: u64,
     ) -> Result<Vec<Result<KvPair>>> {
+        self.scan_range(ctx, key, None, limit, key_only, false, start_ts)
+    }
+
+    #[allow(too_many_arguments)]
+    pub fn scan_range(
+        &self,
+        ctx: Context,
+        start_key: Key,
+        end_key: Option<Key>,
+        limit: usize,
+        key_only: bool,
+        reverse: bool,
+        start_ts: u64,
+    ) -> Result<Vec<Result<KvPair>>> {
+        let mut options = Options::new(0, false, key_only);
+        options.reverse_scan = reverse;
         wait_op!(|cb| {
             self.store
-                .async_scan(
-                    ctx,
-                    key,
-                    limit,
-                    start_ts,
-                    Options::new(0, false, key_only),
-                    cb,
-                )
+                .async_scan(ctx, start_key, end_key, limit, start_ts, options, cb)
                 .unwrap()
         }).unwrap()
     }
@@ -112,9 +126,69 @@ impl SyncStorage {
         primary: Vec<u8>,
         start_ts: u64,
     ) -> Result<Vec<Result<()>>> {
+        self.prewrite_with_ttl(ctx, mutations, primary, start_ts, 0)
+    }
+
+    pub fn prewrite_with_ttl(
+        &self,
+        ctx: Context,
+        mutations: Vec<Mutation>,
+        primary: Vec<u8>,
+        start_ts: u64,
+        lock_ttl: u64,
+    ) -> Result<Vec<Result<()>>> {
+        let options = Options::new(lock_ttl, false, false);
+        wait_op!(|cb| {
+            self.store
+                .async_prewrite(ctx, mutations, primary, start_ts, options, cb)
+                .unwrap()
+        }).unwrap()
+            .map(|res: PrewriteResult| res.locks)
+    }
+
+    pub fn prewrite_async_commit(
+        &self,
+        ctx: Context,
+        mutations: Vec<Mutation>,
+        primary: Vec<u8>,
+        secondary_keys: Vec<Vec<u8>>,
+        start_ts: u64,
+    ) -> Result<PrewriteResult> {
+        let mut options = Options::new(0, false, false);
+        options.use_async_commit = true;
+        options.secondary_keys = secondary_keys;
+        wait_op!(|cb| {
+            self.store
+                .async_prewrite(ctx, mutations, primary, start_ts, options, cb)
+                .unwrap()
+        }).unwrap()
+    }
+
+    pub fn prewrite_one_pc(
+        &self,
+        ctx: Context,
+        mutations: Vec<Mutation>,
+        primary: Vec<u8>,
+        start_ts: u64,
+    ) -> Result<PrewriteResult> {
+        let mut options = Options::new(0, false, false);
+        options.try_one_pc = true;
+        wait_op!(|cb| {
+            self.store
+                .async_prewrite(ctx, mutations, primary, start_ts, options, cb)
+                .unwrap()
+        }).unwrap()
+    }
+
+    pub fn check_secondary_locks(
+        &self,
+        ctx: Context,
+        keys: Vec<Key>,
+        start_ts: u64,
+    ) -> Result<SecondaryLocksStatus> {
         wait_op!(|cb| {
             self.store
-                .async_prewrite(ctx, mutations, primary, start_ts, Options::default(), cb)
+                .async_check_secondary_locks(ctx, keys, start_ts, cb)
                 .unwrap()
         }).unwrap()
     }
@@ -133,9 +207,39 @@ impl SyncStorage {
         }).unwrap()
     }
 
-    pub fn cleanup(&self, ctx: Context, key: Key, start_ts: u64) -> Result<()> {
+    pub fn cleanup(&self, ctx: Context, key: Key, start_ts: u64, current_ts: u64) -> Result<()> {
         wait_op!(|cb| {
-            self.store.async_cleanup(ctx, key, start_ts, cb).unwrap()
+            self.store
+                .async_cleanup(ctx, key, start_ts, current_ts, cb)
+                .unwrap()
+        }).unwrap()
+    }
+
+    pub fn txn_heart_beat(
+        &self,
+        ctx: Context,
+        primary_key: Key,
+        start_ts: u64,
+        advise_ttl: u64,
+    ) -> Result<TxnStatus> {
+        wait_op!(|cb| {
+            self.store
+                .async_txn_heart_beat(ctx, primary_key, start_ts, advise_ttl, cb)
+                .unwrap()
+        }).unwrap()
+    }
+
+    pub fn check_txn_status(
+        &self,
+        ctx: Context,
+        primary_key: Key,
+        lock_ts: u64,
+        current_ts: u64,
+    ) -> Result<TxnStatus> {
+        wait_op!(|cb| {
+            self.store
+                .async_check_txn_status(ctx, primary_key, lock_ts, current_ts, cb)
+                .unwrap()
         }).unwrap()
     }
 
@@ -161,27 +265,139 @@ impl SyncStorage {
         wait_op!(|cb| self.store.async_gc(ctx, safe_point, cb).unwrap()).unwrap()
     }
 
-    pub fn raw_get(&self, ctx: Context, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
-        wait_op!(|cb| self.store.async_raw_get(ctx, key, cb).unwrap()).unwrap()
+    pub fn raw_get(&self, ctx: Context, cf: String, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        wait_op!(|cb| self.store.async_raw_get(ctx, cf, key, cb).unwrap()).unwrap()
     }
 
-    pub fn raw_put(&self, ctx: Context, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
-        wait_op!(|cb| self.store.async_raw_put(ctx, key, value, cb).unwrap()).unwrap()
+    pub fn raw_batch_get(
+        &self,
+        ctx: Context,
+        cf: String,
+        keys: Vec<Vec<u8>>,
+    ) -> Result<Vec<Result<KvPair>>> {
+        wait_op!(|cb| self.store.async_raw_batch_get(ctx, cf, keys, cb).unwrap()).unwrap()
+    }
+
+    pub fn raw_put(&self, ctx: Context, cf: String, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.raw_put_with_ttl(ctx, cf, key, value, 0)
+    }
+
+    pub fn raw_put_with_ttl(
+        &self,
+        ctx: Context,
+        cf: String,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        ttl: u64,
+    ) -> Result<()> {
+        wait_op!(|cb| {
+            self.store
+                .async_raw_put(ctx, cf, key, value, ttl, cb)
+                .unwrap()
+        }).unwrap()
     }
 
-    pub fn raw_delete(&self, ctx: Context, key: Vec<u8>) -> Result<()> {
-        wait_op!(|cb| self.store.async_raw_delete(ctx, key, cb).unwrap()).unwrap()
+    pub fn raw_batch_put(&self, ctx: Context, cf: String, pairs: Vec<KvPair>) -> Result<()> {
+        wait_op!(|cb| self.store.async_raw_batch_put(ctx, cf, pairs, cb).unwrap()).unwrap()
+    }
+
+    pub fn raw_get_key_ttl(&self, ctx: Context, cf: String, key: Vec<u8>) -> Result<Option<u64>> {
+        wait_op!(|cb| {
+            self.store
+                .async_raw_get_key_ttl(ctx, cf, key, cb)
+                .unwrap()
+        }).unwrap()
+    }
+
+    pub fn raw_delete(&self, ctx: Context, cf: String, key: Vec<u8>) -> Result<()> {
+        wait_op!(|cb| self.store.async_raw_delete(ctx, cf, key, cb).unwrap()).unwrap()
+    }
+
+    pub fn raw_batch_delete(&self, ctx: Context, cf: String, keys: Vec<Vec<u8>>) -> Result<()> {
+        wait_op!(|cb| {
+            self.store
+                .async_raw_batch_delete(ctx, cf, keys, cb)
+                .unwrap()
+        }).unwrap()
+    }
+
+    pub fn raw_delete_range(
+        &self,
+        ctx: Context,
+        cf: String,
+        start_key: Vec<u8>,
+        end_key: Vec<u8>,
+    ) -> Result<()> {
+        wait_op!(|cb| {
+            self.store
+                .async_raw_delete_range(ctx, cf, start_key, end_key, cb)
+                .unwrap()
+        }).unwrap()
+    }
+
+    pub fn raw_compare_and_swap(
+        &self,
+        ctx: Context,
+        cf: String,
+        key: Vec<u8>,
+        previous_value: Option<Vec<u8>>,
+        value: Vec<u8>,
+    ) -> Result<(Option<Vec<u8>>, bool)> {
+        wait_op!(|cb| {
+            self.store
+                .async_raw_compare_and_swap(ctx, cf, key, previous_value, value, 0, cb)
+                .unwrap()
+        }).unwrap()
+    }
+
+    pub fn raw_increment(&self, ctx: Context, cf: String, key: Vec<u8>, delta: i64) -> Result<i64> {
+        wait_op!(|cb| {
+            self.store
+                .async_raw_increment(ctx, cf, key, delta, cb)
+                .unwrap()
+        }).unwrap()
     }
 
     pub fn raw_scan(
         &self,
         ctx: Context,
+        cf: String,
+        start_key: Vec<u8>,
+        limit: usize,
+    ) -> Result<Vec<Result<KvPair>>> {
+        self.raw_scan_with_options(ctx, cf, start_key, None, limit, false, false)
+    }
+
+    #[allow(too_many_arguments)]
+    pub fn raw_scan_with_options(
+        &self,
+        ctx: Context,
+        cf: String,
         start_key: Vec<u8>,
+        end_key: Option<Vec<u8>>,
         limit: usize,
+        key_only: bool,
+        reverse: bool,
+    ) -> Result<Vec<Result<KvPair>>> {
+        wait_op!(|cb| {
+            self.store
+                .async_raw_scan(ctx, cf, start_key, end_key, limit, key_only, reverse, cb)
+                .unwrap()
+        }).unwrap()
+    }
+
+    pub fn raw_batch_scan(
+        &self,
+        ctx: Context,
+        cf: String,
+        ranges: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+        each_limit: usize,
+        key_only: bool,
+        reverse: bool,
     ) -> Result<Vec<Result<KvPair>>> {
         wait_op!(|cb| {
             self.store
-                .async_raw_scan(ctx, start_key, limit, cb)
+                .async_raw_batch_scan(ctx, cf, ranges, each_limit, key_only, reverse, cb)
                 .unwrap()
         }).unwrap()
     }
@@ -0,0 +1,173 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+use crc::crc32::{self, Hasher32};
+use futures::Stream;
+use futures::sync::mpsc;
+use kvproto::backup::{BackupRequest, BackupResponse, File};
+use rocksdb::{IngestExternalFileOptions, SeekKey};
+use tempdir::TempDir;
+
+use tikv::backup::{create_storage, Endpoint, ExternalStorage, Task};
+use tikv::raftstore::store::keys;
+use tikv::raftstore::store::util::find_peer;
+use tikv::storage::{make_key, Key, Mutation, CF_DEFAULT, CF_WRITE};
+use tikv::storage::mvcc::{Write, WriteType};
+use tikv::util::HandyRwLock;
+use tikv::util::rocksdb::{self as rocksdb_util, get_cf_handle};
+use tikv::util::worker::Runnable;
+use super::util::new_raft_storage_with_store_count;
+
+// The rate limit of the backup, in bytes per second.
+const RATE_LIMIT: u64 = 64 * 1024;
+
+// Checks the checksum and the metadata of `file`, returns the keys with the values in it.
+fn check_file(
+    storage: &ExternalStorage,
+    dir: &TempDir,
+    file: &File,
+) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut buf = vec![];
+    storage
+        .read(file.get_name())
+        .unwrap()
+        .read_to_end(&mut buf)
+        .unwrap();
+    let mut digest = crc32::Digest::new(crc32::IEEE);
+    digest.write(&buf);
+    assert_eq!(digest.sum32(), file.get_crc32(), "{:?}", file);
+
+    let db_dir = TempDir::new("test_backup_check_file").unwrap();
+    let db = rocksdb_util::new_engine(db_dir.path().to_str().unwrap(), &[CF_DEFAULT, CF_WRITE])
+        .unwrap();
+    let handle = get_cf_handle(&db, file.get_cf()).unwrap();
+    let path = dir.path().join(file.get_name());
+    let opt = IngestExternalFileOptions::new();
+    db.ingest_external_file_cf(handle, &opt, &[path.to_str().unwrap()])
+        .unwrap();
+
+    let mut kvs = vec![];
+    let mut total_bytes = 0;
+    let mut iter = db.iter_cf(handle);
+    iter.seek(SeekKey::Start);
+    while iter.valid() {
+        let key = keys::origin_key(iter.key()).to_vec();
+        total_bytes += (key.len() + iter.value().len()) as u64;
+        kvs.push((key, iter.value().to_vec()));
+        iter.next();
+    }
+    assert_eq!(kvs.len() as u64, file.get_total_kvs(), "{:?}", file);
+    assert_eq!(total_bytes, file.get_total_bytes(), "{:?}", file);
+    let raw_key = |k: &[u8]| Key::from_encoded(k.to_vec()).truncate_ts().unwrap().raw().unwrap();
+    assert_eq!(raw_key(&kvs[0].0), file.get_start_key(), "{:?}", file);
+    assert_eq!(raw_key(&kvs[kvs.len() - 1].0), file.get_end_key(), "{:?}", file);
+    kvs
+}
+
+#[test]
+fn test_backup_cluster() {
+    let (mut cluster, storage, ctx) = new_raft_storage_with_store_count(3, "");
+
+    // The values are long, so they are saved in the default cf.
+    let value = vec![b'v'; 128];
+    let raw_keys: Vec<_> = (0..10).map(|i| format!("k{}", i).into_bytes()).collect();
+    let mutations = raw_keys.iter()
+        .map(|k| Mutation::Put((make_key(k), value.clone())))
+        .collect();
+    storage
+        .prewrite(ctx.clone(), mutations, raw_keys[0].clone(), 10)
+        .unwrap();
+    let encoded_keys: Vec<_> = raw_keys.iter().map(|k| make_key(k)).collect();
+    storage
+        .commit(ctx.clone(), encoded_keys, 10, 11)
+        .unwrap();
+    // Deleted before the backup ts.
+    storage
+        .prewrite(ctx.clone(), vec![Mutation::Delete(make_key(b"k0"))], b"k0".to_vec(), 20)
+        .unwrap();
+    storage
+        .commit(ctx.clone(), vec![make_key(b"k0")], 20, 21)
+        .unwrap();
+    // Written after the backup ts.
+    let mutations = vec![Mutation::Put((make_key(b"k1"), b"v".to_vec()))];
+    storage
+        .prewrite(ctx.clone(), mutations, b"k1".to_vec(), 40)
+        .unwrap();
+    storage
+        .commit(ctx.clone(), vec![make_key(b"k1")], 40, 41)
+        .unwrap();
+
+    // Both regions are led by the store running the backup.
+    let store_id = ctx.get_peer().get_store_id();
+    let region = cluster.get_region(b"");
+    cluster.must_split(&region, make_key(b"k5").encoded());
+    for key in &[b"k1", b"k5"] {
+        let region = cluster.get_region(make_key(*key).encoded());
+        let peer = find_peer(&region, store_id).unwrap().clone();
+        cluster.must_transfer_leader(region.get_id(), peer);
+    }
+
+    let engine = cluster.sim.rl().storages[&store_id].clone();
+    let tmp_dir = TempDir::new("test_backup_cluster_tmp").unwrap();
+    let backup_dir = TempDir::new("test_backup_cluster").unwrap();
+    let mut endpoint = Endpoint::new(store_id, cluster.pd_client.clone(), engine, tmp_dir.path());
+
+    let mut req = BackupRequest::new();
+    req.set_end_version(30);
+    req.set_path(format!("local://{}", backup_dir.path().display()));
+    req.set_rate_limit(RATE_LIMIT);
+    let (tx, rx) = mpsc::unbounded();
+    let start = Instant::now();
+    endpoint.run(Task::new(req, tx));
+    let elapsed = start.elapsed();
+    let resps: Vec<BackupResponse> = rx.wait().map(|r| r.unwrap()).collect();
+    assert_eq!(resps.len(), 2);
+    assert!(resps[0].get_start_key().is_empty());
+    assert_eq!(resps[0].get_end_key(), b"k5");
+    assert_eq!(resps[1].get_start_key(), b"k5");
+    assert!(resps[1].get_end_key().is_empty());
+
+    let backup_storage = create_storage(backup_dir.path().to_str().unwrap()).unwrap();
+    let mut backup_bytes = 0;
+    // The keys backed up in each region, k0 is deleted.
+    let expect_keys = vec![&raw_keys[1..5], &raw_keys[5..]];
+    for (resp, expect_keys) in resps.iter().zip(expect_keys) {
+        assert!(!resp.has_error(), "{:?}", resp);
+        let files = resp.get_files();
+        assert_eq!(files.len(), 2, "{:?}", resp);
+        for file in files {
+            let kvs = check_file(backup_storage.as_ref(), &backup_dir, file);
+            let path = backup_dir.path().join(file.get_name());
+            backup_bytes += path.metadata().unwrap().len();
+            assert_eq!(kvs.len(), expect_keys.len(), "{:?}", file);
+            for (&(ref key, ref v), raw) in kvs.iter().zip(expect_keys) {
+                if file.get_cf() == CF_DEFAULT {
+                    // The value of k1 written after the backup ts is not included.
+                    assert_eq!(key, make_key(raw).append_ts(10).encoded());
+                    assert_eq!(v, &value);
+                } else {
+                    assert_eq!(file.get_cf(), CF_WRITE);
+                    assert_eq!(key, make_key(raw).append_ts(11).encoded());
+                    let write = Write::parse(v).unwrap();
+                    assert_eq!(write.write_type, WriteType::Put);
+                    assert_eq!(write.start_ts, 10);
+                }
+            }
+        }
+    }
+    // The files are written within the rate limit.
+    assert!(elapsed >= Duration::from_millis(backup_bytes * 1000 / RATE_LIMIT));
+}
@@ -0,0 +1,67 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use tikv::raftstore::store::keys;
+use tikv::storage::{make_key, Mutation, CF_DEFAULT, CF_WRITE, SHORT_VALUE_MAX_LEN};
+use tikv::util::rocksdb::get_cf_handle;
+use raftstore::util::*;
+use super::util::new_raft_storage_with_store_count;
+
+fn long_value(start_ts: u64) -> Vec<u8> {
+    let mut value = vec![b'v'; SHORT_VALUE_MAX_LEN + 1];
+    value.extend_from_slice(format!("{}", start_ts).as_bytes());
+    value
+}
+
+#[test]
+fn test_gc_compaction_filter() {
+    let (cluster, storage, ctx) = new_raft_storage_with_store_count(1, "");
+    let store_id = ctx.get_peer().get_store_id();
+    let engine = cluster.engines[&store_id].kv_engine.clone();
+    let gc_context = cluster.gc_contexts[&store_id].clone();
+
+    let key = make_key(b"k");
+    for &(start_ts, commit_ts) in &[(5, 10), (15, 20), (45, 50)] {
+        let mutations = vec![Mutation::Put((key.clone(), long_value(start_ts)))];
+        storage
+            .prewrite(ctx.clone(), mutations, b"k".to_vec(), start_ts)
+            .unwrap();
+        storage
+            .commit(ctx.clone(), vec![key.clone()], start_ts, commit_ts)
+            .unwrap();
+    }
+
+    // The versions are dropped in the compaction of the write cf with the safe point.
+    let mut worker = gc_context.start(engine.clone()).unwrap();
+    gc_context.set_safe_point(30);
+    let handle = get_cf_handle(&engine, CF_WRITE).unwrap();
+    engine.compact_range_cf(handle, None, None);
+    worker.stop().unwrap().join().unwrap();
+
+    // The version older than the latest one before the safe point is gone with its value.
+    must_get_cf_none(&engine, CF_WRITE, key.append_ts(10).encoded());
+    must_get_cf_none(&engine, CF_DEFAULT, key.append_ts(5).encoded());
+    // The others are kept.
+    for &(start_ts, commit_ts) in &[(15, 20), (45, 50)] {
+        let write_key = keys::data_key(key.append_ts(commit_ts).encoded());
+        assert!(engine.get_cf(handle, &write_key).unwrap().is_some());
+        let value = long_value(start_ts);
+        must_get_cf_equal(&engine, CF_DEFAULT, key.append_ts(start_ts).encoded(), &value);
+    }
+
+    // The reads at and after the safe point see the same data as before.
+    assert_eq!(storage.get(ctx.clone(), &key, 30).unwrap(), Some(long_value(15)));
+    assert_eq!(storage.get(ctx.clone(), &key, 49).unwrap(), Some(long_value(15)));
+    assert_eq!(storage.get(ctx.clone(), &key, 50).unwrap(), Some(long_value(45)));
+    assert_eq!(storage.get(ctx.clone(), &key, 60).unwrap(), Some(long_value(45)));
+}
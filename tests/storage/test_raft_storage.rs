@@ -71,19 +71,19 @@ fn test_raft_storage_get_after_lease() {
     let (_cluster, storage, ctx) = new_raft_storage();
     let key = b"key";
     let value = b"value";
-    assert_eq!(storage.raw_get(ctx.clone(), key.to_vec()).unwrap(), None);
+    assert_eq!(storage.raw_get(ctx.clone(), "".to_owned(), key.to_vec()).unwrap(), None);
     storage
-        .raw_put(ctx.clone(), key.to_vec(), value.to_vec())
+        .raw_put(ctx.clone(), "".to_owned(), key.to_vec(), value.to_vec())
         .unwrap();
     assert_eq!(
-        storage.raw_get(ctx.clone(), key.to_vec()).unwrap().unwrap(),
+        storage.raw_get(ctx.clone(), "".to_owned(), key.to_vec()).unwrap().unwrap(),
         value.to_vec()
     );
 
     // Sleep until the leader lease is expired.
     thread::sleep(Duration::from_millis(MAX_LEADER_LEASE));
     assert_eq!(
-        storage.raw_get(ctx.clone(), key.to_vec()).unwrap().unwrap(),
+        storage.raw_get(ctx.clone(), "".to_owned(), key.to_vec()).unwrap().unwrap(),
         value.to_vec()
     );
 }
@@ -155,6 +155,58 @@ fn test_raft_storage_store_not_match() {
     assert!(storage.scan_lock(ctx.clone(), 20).is_err());
 }
 
+fn must_key_not_in_region<T: ::std::fmt::Debug>(res: storage::Result<T>) {
+    match res {
+        Err(storage::Error::Engine(engine::Error::Request(ref e))) |
+        Err(storage::Error::Txn(txn::Error::Engine(engine::Error::Request(ref e)))) => {
+            assert!(e.has_key_not_in_region(), "{:?}", e)
+        }
+        res => panic!("expect key_not_in_region, but got {:?}", res),
+    }
+}
+
+#[test]
+fn test_raft_storage_rawkv_batch_cross_region() {
+    let (mut cluster, storage, mut ctx) = new_raft_storage();
+    let region = cluster.get_region(b"");
+    cluster.must_split(&region, b"k2");
+    // Make sure the leader of the left region is elected.
+    cluster.must_put(b"k0", b"v0");
+    let left = cluster.get_region(b"k1");
+    ctx.set_region_id(left.get_id());
+    ctx.set_region_epoch(left.get_region_epoch().clone());
+    ctx.set_peer(left.get_peers()[0].clone());
+
+    let pairs = vec![
+        (b"k1".to_vec(), b"v1".to_vec()),
+        (b"k3".to_vec(), b"v3".to_vec()),
+    ];
+    // The whole batch fails if any key is out of the region.
+    must_key_not_in_region(storage.raw_batch_put(ctx.clone(), "".to_owned(), pairs.clone()));
+    assert_eq!(storage.raw_get(ctx.clone(), "".to_owned(), b"k1".to_vec()).unwrap(), None);
+    storage
+        .raw_batch_put(ctx.clone(), "".to_owned(), pairs[..1].to_vec())
+        .unwrap();
+
+    let keys = vec![b"k1".to_vec(), b"k3".to_vec()];
+    must_key_not_in_region(storage.raw_batch_get(ctx.clone(), "".to_owned(), keys.clone()));
+    must_key_not_in_region(storage.raw_batch_delete(ctx.clone(), "".to_owned(), keys));
+    must_key_not_in_region(storage.raw_delete_range(
+        ctx.clone(),
+        "".to_owned(),
+        b"k1".to_vec(),
+        b"k3".to_vec(),
+    ));
+    assert_eq!(
+        storage.raw_get(ctx.clone(), "".to_owned(), b"k1".to_vec()).unwrap(),
+        Some(b"v1".to_vec())
+    );
+    storage
+        .raw_delete_range(ctx.clone(), "".to_owned(), b"k1".to_vec(), b"k2".to_vec())
+        .unwrap();
+    assert_eq!(storage.raw_get(ctx.clone(), "".to_owned(), b"k1".to_vec()).unwrap(), None);
+}
+
 #[test]
 fn test_engine_leader_change_twice() {
     let mut cluster = new_server_cluster(0, 3);
@@ -12,14 +12,15 @@
 // limitations under the License.
 
 use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc::channel;
 use std::time::Duration;
 use std::thread;
 use rand::random;
 use super::sync_storage::SyncStorage;
 use kvproto::kvrpcpb::{Context, LockInfo};
-use tikv::storage::{self, make_key, Key, Mutation, Storage, ALL_CFS};
+use tikv::storage::{self, make_key, Key, KvPair, Mutation, SecondaryLocksStatus, Storage,
+                    TxnStatus, ALL_CFS, CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE};
 use tikv::storage::engine::{self, Engine, EngineRocksdb, TEMP_DIR};
 use tikv::storage::txn::{GC_BATCH_SIZE, RESOLVE_LOCK_BATCH_SIZE};
 use tikv::storage::mvcc::MAX_TXN_WRITE_SIZE;
@@ -28,7 +29,7 @@ use tikv::storage::config::Config;
 use super::util::new_raft_engine;
 use super::assert_storage::AssertionStorage;
 use storage::util;
-use std::u64;
+use std::{i64, u64};
 
 #[test]
 fn test_txn_store_get() {
@@ -69,7 +70,146 @@ fn test_txn_store_cleanup_rollback() {
     );
     store.get_err(b"secondary", 10);
     store.rollback_ok(vec![b"primary"], 5);
-    store.cleanup_ok(b"primary", 5);
+    store.cleanup_ok(b"primary", 5, 0);
+}
+
+#[test]
+fn test_txn_store_heart_beat_and_check_txn_status() {
+    let store = AssertionStorage::default();
+    // Lock ttls are in milliseconds, compared with the physical part of timestamps.
+    let ts = |physical: u64| physical << 18;
+    store.prewrite_with_ttl_ok(
+        vec![
+            Mutation::Put((make_key(b"primary"), b"p-10".to_vec())),
+            Mutation::Put((make_key(b"secondary"), b"s-10".to_vec())),
+        ],
+        b"primary",
+        ts(10),
+        100,
+    );
+    store.txn_heart_beat_ok(b"primary", ts(10), 50, 100);
+    store.txn_heart_beat_ok(b"primary", ts(10), 200, 200);
+    store.txn_heart_beat_err(b"primary", ts(9), 300);
+
+    // The alive lock is neither rolled back by checking nor by cleanup.
+    store.check_txn_status_uncommitted(b"primary", ts(10), ts(100), 200);
+    store.cleanup_err(b"primary", ts(10), ts(100));
+    store.get_err(b"primary", ts(20));
+
+    store.check_txn_status_ok(b"primary", ts(10), ts(211), TxnStatus::RolledBack);
+    store.get_none(b"primary", ts(20));
+    store.txn_heart_beat_err(b"primary", ts(10), 300);
+    store.cleanup_ok(b"primary", ts(10), ts(211));
+
+    store.prewrite_with_ttl_ok(
+        vec![Mutation::Put((make_key(b"primary"), b"p-20".to_vec()))],
+        b"primary",
+        ts(20),
+        100,
+    );
+    store.commit_ok(vec![b"primary"], ts(20), ts(21));
+    store.check_txn_status_ok(
+        b"primary",
+        ts(20),
+        ts(500),
+        TxnStatus::Committed { commit_ts: ts(21) },
+    );
+    store.cleanup_err(b"primary", ts(20), ts(500));
+}
+
+#[test]
+fn test_txn_store_async_commit() {
+    let store = AssertionStorage::default();
+    // The read at 30 makes the later prewrite use a larger min_commit_ts.
+    store.get_none(b"k1", 30);
+    let min_commit_ts = store.prewrite_async_commit_ok(
+        vec![
+            Mutation::Put((make_key(b"k1"), b"v1".to_vec())),
+            Mutation::Put((make_key(b"k2"), b"v2".to_vec())),
+        ],
+        b"k1",
+        vec![b"k2"],
+        10,
+    );
+    assert_eq!(min_commit_ts, 31);
+
+    // Readers with ts smaller than min_commit_ts can ignore the locks.
+    store.get_none(b"k2", 20);
+    store.get_err(b"k2", 40);
+
+    // The expired primary lock of an async-commit transaction is never rolled back.
+    store.check_txn_status_uncommitted(b"k1", 10, 100 << 18, 0);
+    store.check_secondary_locks_locked(vec![b"k2"], 10);
+
+    store.commit_ts_expired(vec![b"k1", b"k2"], 10, 20);
+    store.commit_ok(vec![b"k2"], 10, min_commit_ts);
+    store.check_secondary_locks_ok(
+        vec![b"k2"],
+        10,
+        SecondaryLocksStatus::Committed(min_commit_ts),
+    );
+    store.commit_ok(vec![b"k1"], 10, min_commit_ts);
+    store.get_ok(b"k1", 40, b"v1");
+    store.get_ok(b"k2", 40, b"v2");
+
+    // The secondary key which is not prewritten is rolled back by the check.
+    store.prewrite_async_commit_ok(
+        vec![Mutation::Put((make_key(b"k3"), b"v3".to_vec()))],
+        b"k3",
+        vec![b"k4"],
+        50,
+    );
+    store.check_secondary_locks_ok(vec![b"k3", b"k4"], 50, SecondaryLocksStatus::RolledBack);
+    store.check_secondary_locks_ok(vec![b"k4"], 50, SecondaryLocksStatus::RolledBack);
+    store.rollback_ok(vec![b"k3"], 50);
+    store.get_none(b"k3", 60);
+}
+
+#[test]
+fn test_txn_store_one_pc() {
+    let store = AssertionStorage::default();
+    // The commit ts must be larger than the reads before.
+    store.get_none(b"k1", 30);
+    let commit_ts = store.prewrite_one_pc_ok(
+        vec![
+            Mutation::Put((make_key(b"k1"), b"v1".to_vec())),
+            Mutation::Delete(make_key(b"k2")),
+        ],
+        b"k1",
+        10,
+    );
+    assert_eq!(commit_ts, 31);
+    store.get_none(b"k1", 30);
+    store.get_ok(b"k1", 31, b"v1");
+    store.get_none(b"k2", 31);
+    store.scan_lock_ok(u64::MAX, vec![]);
+
+    // Conflicts fail the prewrite as 2PC does.
+    store.prewrite_one_pc_err(
+        vec![Mutation::Put((make_key(b"k1"), b"v2".to_vec()))],
+        b"k1",
+        20,
+    );
+
+    // Falls back to 2PC if some keys have been prewritten.
+    store.prewrite_ok(
+        vec![Mutation::Put((make_key(b"k3"), b"v3".to_vec()))],
+        b"k3",
+        40,
+    );
+    let commit_ts = store.prewrite_one_pc_ok(
+        vec![
+            Mutation::Put((make_key(b"k3"), b"v3".to_vec())),
+            Mutation::Put((make_key(b"k4"), b"v4".to_vec())),
+        ],
+        b"k3",
+        40,
+    );
+    assert_eq!(commit_ts, 0);
+    store.get_err(b"k4", 50);
+    store.commit_ok(vec![b"k3", b"k4"], 40, 50);
+    store.get_ok(b"k3", 50, b"v3");
+    store.get_ok(b"k4", 50, b"v4");
 }
 
 #[test]
@@ -87,7 +227,7 @@ fn test_txn_store_cleanup_commit() {
     store.get_err(b"secondary", 8);
     store.get_err(b"secondary", 12);
     store.commit_ok(vec![b"primary"], 5, 10);
-    store.cleanup_err(b"primary", 5);
+    store.cleanup_err(b"primary", 5, 0);
     store.rollback_err(vec![b"primary"], 5);
 }
 
@@ -279,6 +419,48 @@ fn test_txn_store_scan() {
     check_v40();
 }
 
+#[test]
+fn test_txn_store_scan_range() {
+    let store = AssertionStorage::default();
+
+    // ver30: A(10) - B(locked) - C(deleted) - D(_) - E(10)
+    store.put_ok(b"A", b"A10", 5, 10);
+    store.put_ok(b"C", b"C10", 5, 10);
+    store.put_ok(b"E", b"E10", 5, 10);
+    store.delete_ok(b"C", 15, 20);
+    store.prewrite_ok(vec![Mutation::Put((make_key(b"B"), b"B25".to_vec()))], b"B", 25);
+
+    store.scan_range_ok(
+        b"",
+        b"",
+        10,
+        30,
+        false,
+        vec![Some((b"A", b"A10")), None, Some((b"E", b"E10"))],
+    );
+    store.scan_range_ok(b"A", b"E", 10, 30, false, vec![Some((b"A", b"A10")), None]);
+    store.scan_range_ok(b"C", b"E", 10, 30, false, vec![]);
+    store.scan_range_ok(b"C", b"", 10, 30, false, vec![Some((b"E", b"E10"))]);
+
+    store.scan_range_ok(
+        b"",
+        b"",
+        10,
+        30,
+        true,
+        vec![Some((b"E", b"E10")), None, Some((b"A", b"A10"))],
+    );
+    store.scan_range_ok(b"", b"", 1, 30, true, vec![Some((b"E", b"E10"))]);
+    // The start key is exclusive and the end key is inclusive for reverse scans.
+    store.scan_range_ok(b"E", b"", 10, 30, true, vec![None, Some((b"A", b"A10"))]);
+    store.scan_range_ok(b"E", b"A", 10, 30, true, vec![None, Some((b"A", b"A10"))]);
+    // The lock out of the range is ignored.
+    store.scan_range_ok(b"", b"C", 10, 30, true, vec![Some((b"E", b"E10"))]);
+    store.scan_range_ok(b"D", b"C", 10, 30, true, vec![]);
+    // The lock is invisible to older versions.
+    store.scan_range_ok(b"", b"", 10, 20, true, vec![Some((b"E", b"E10")), Some((b"A", b"A10"))]);
+}
+
 #[test]
 fn test_txn_store_scan_key_only() {
     let store = AssertionStorage::default();
@@ -545,6 +727,392 @@ fn test_txn_store_rawkv() {
     store.raw_scan_ok(b"k5".to_vec(), 1, vec![]);
 }
 
+fn raw_kv_pairs(pairs: Vec<storage::Result<KvPair>>) -> Vec<KvPair> {
+    pairs.into_iter().map(|p| p.unwrap()).collect()
+}
+
+#[test]
+fn test_txn_store_rawkv_batch() {
+    let store = SyncStorage::new(&Config::default());
+    let ctx = Context::new();
+    let pairs: Vec<KvPair> = (1..6)
+        .map(|i| (format!("k{}", i).into_bytes(), format!("v{}", i).into_bytes()))
+        .collect();
+    store
+        .raw_batch_put(ctx.clone(), "".to_owned(), pairs.clone())
+        .unwrap();
+    let keys: Vec<Vec<u8>> = vec![b"k1".to_vec(), b"k0".to_vec(), b"k3".to_vec()];
+    assert_eq!(
+        raw_kv_pairs(store.raw_batch_get(ctx.clone(), "".to_owned(), keys).unwrap()),
+        vec![pairs[0].clone(), pairs[2].clone()]
+    );
+
+    store
+        .raw_batch_delete(ctx.clone(), "".to_owned(), vec![b"k1".to_vec(), b"k2".to_vec()])
+        .unwrap();
+    assert_eq!(
+        raw_kv_pairs(store.raw_scan(ctx.clone(), "".to_owned(), b"".to_vec(), 10).unwrap()),
+        pairs[2..].to_vec()
+    );
+
+    store
+        .raw_delete_range(ctx.clone(), "".to_owned(), b"k3".to_vec(), b"k5".to_vec())
+        .unwrap();
+    assert_eq!(
+        raw_kv_pairs(store.raw_scan(ctx.clone(), "".to_owned(), b"".to_vec(), 10).unwrap()),
+        pairs[4..].to_vec()
+    );
+    assert!(
+        store
+            .raw_delete_range(ctx.clone(), "".to_owned(), b"k5".to_vec(), b"k3".to_vec())
+            .is_err()
+    );
+}
+
+#[test]
+fn test_txn_store_rawkv_scan() {
+    let store = SyncStorage::new(&Config::default());
+    let ctx = Context::new();
+    let pairs: Vec<KvPair> = (1..6)
+        .map(|i| (format!("k{}", i).into_bytes(), format!("v{}", i).into_bytes()))
+        .collect();
+    store
+        .raw_batch_put(ctx.clone(), "".to_owned(), pairs.clone())
+        .unwrap();
+    let scan = |start: &[u8], end: Option<&[u8]>, limit, key_only, reverse| {
+        let end = end.map(|e| e.to_vec());
+        let res = store
+            .raw_scan_with_options(
+                ctx.clone(),
+                "".to_owned(),
+                start.to_vec(),
+                end,
+                limit,
+                key_only,
+                reverse,
+            )
+            .unwrap();
+        raw_kv_pairs(res)
+    };
+
+    // Forward with an end key, which is exclusive.
+    assert_eq!(scan(b"k2", Some(b"k4"), 10, false, false), pairs[1..3].to_vec());
+    assert_eq!(scan(b"k2", None, 2, false, false), pairs[1..3].to_vec());
+    // Reverse starts before the start key, and the end key is inclusive.
+    let mut expected = pairs[1..3].to_vec();
+    expected.reverse();
+    assert_eq!(scan(b"k4", Some(b"k2"), 10, false, true), expected);
+    let mut expected = pairs.clone();
+    expected.reverse();
+    assert_eq!(scan(b"", None, 10, false, true), expected);
+    assert_eq!(scan(b"", None, 2, false, true), expected[..2].to_vec());
+    // Key only.
+    let keys: Vec<KvPair> = pairs.iter().map(|&(ref k, _)| (k.clone(), vec![])).collect();
+    assert_eq!(scan(b"", None, 10, true, false), keys);
+    assert_eq!(scan(b"k3", Some(b"k1"), 10, true, true), vec![keys[1].clone(), keys[0].clone()]);
+
+    let ranges = vec![
+        (b"k1".to_vec(), Some(b"k3".to_vec())),
+        (b"k4".to_vec(), None),
+    ];
+    assert_eq!(
+        raw_kv_pairs(
+            store
+                .raw_batch_scan(ctx.clone(), "".to_owned(), ranges, 1, false, false)
+                .unwrap()
+        ),
+        vec![pairs[0].clone(), pairs[3].clone()]
+    );
+    let ranges = vec![
+        (b"k5".to_vec(), Some(b"k3".to_vec())),
+        (b"k2".to_vec(), None),
+    ];
+    assert_eq!(
+        raw_kv_pairs(
+            store
+                .raw_batch_scan(ctx.clone(), "".to_owned(), ranges, 10, true, true)
+                .unwrap()
+        ),
+        vec![keys[3].clone(), keys[2].clone(), keys[0].clone()]
+    );
+}
+
+#[test]
+fn test_txn_store_rawkv_cf() {
+    let store = SyncStorage::new(&Config::default());
+    let ctx = Context::new();
+    let (k, v) = (b"k".to_vec(), b"v".to_vec());
+    store
+        .raw_put(ctx.clone(), CF_WRITE.to_owned(), k.clone(), v.clone())
+        .unwrap();
+    assert_eq!(
+        store.raw_get(ctx.clone(), CF_WRITE.to_owned(), k.clone()).unwrap(),
+        Some(v.clone())
+    );
+    assert_eq!(store.raw_get(ctx.clone(), "".to_owned(), k.clone()).unwrap(), None);
+    assert_eq!(
+        store.raw_get(ctx.clone(), CF_DEFAULT.to_owned(), k.clone()).unwrap(),
+        None
+    );
+
+    store
+        .raw_batch_put(ctx.clone(), CF_LOCK.to_owned(), vec![(k.clone(), v.clone())])
+        .unwrap();
+    assert_eq!(
+        raw_kv_pairs(
+            store
+                .raw_batch_get(ctx.clone(), CF_LOCK.to_owned(), vec![k.clone()])
+                .unwrap()
+        ),
+        vec![(k.clone(), v.clone())]
+    );
+    assert_eq!(
+        raw_kv_pairs(
+            store
+                .raw_scan(ctx.clone(), CF_LOCK.to_owned(), b"".to_vec(), 10)
+                .unwrap()
+        ),
+        vec![(k.clone(), v.clone())]
+    );
+    store
+        .raw_delete_range(ctx.clone(), CF_LOCK.to_owned(), b"a".to_vec(), b"z".to_vec())
+        .unwrap();
+    assert_eq!(store.raw_get(ctx.clone(), CF_LOCK.to_owned(), k.clone()).unwrap(), None);
+    assert_eq!(
+        store.raw_get(ctx.clone(), CF_WRITE.to_owned(), k.clone()).unwrap(),
+        Some(v.clone())
+    );
+
+    // Only the data cfs are allowed.
+    for cf in &[CF_RAFT, "foo"] {
+        match store.raw_get(ctx.clone(), cf.to_string(), k.clone()) {
+            Err(storage::Error::InvalidCf(ref name)) if name == cf => {}
+            res => panic!("expect invalid cf error, but got {:?}", res),
+        }
+        assert!(
+            store
+                .raw_batch_put(ctx.clone(), cf.to_string(), vec![(k.clone(), v.clone())])
+                .is_err()
+        );
+    }
+}
+
+#[test]
+fn test_txn_store_rawkv_compare_and_swap() {
+    let store = SyncStorage::new(&Config::default());
+    let ctx = Context::new();
+    let cas = |previous_value: Option<&[u8]>, value: &[u8]| {
+        store
+            .raw_compare_and_swap(
+                ctx.clone(),
+                "".to_owned(),
+                b"k".to_vec(),
+                previous_value.map(|v| v.to_vec()),
+                value.to_vec(),
+            )
+            .unwrap()
+    };
+    // Put if absent.
+    assert_eq!(cas(None, b"v1"), (None, true));
+    assert_eq!(cas(None, b"v2"), (Some(b"v1".to_vec()), false));
+    assert_eq!(cas(Some(b"v2"), b"v3"), (Some(b"v1".to_vec()), false));
+    assert_eq!(cas(Some(b"v1"), b"v2"), (Some(b"v1".to_vec()), true));
+    assert_eq!(
+        store.raw_get(ctx.clone(), "".to_owned(), b"k".to_vec()).unwrap(),
+        Some(b"v2".to_vec())
+    );
+}
+
+#[test]
+fn test_txn_store_rawkv_increment() {
+    let store = SyncStorage::new(&Config::default());
+    let ctx = Context::new();
+    let (k, cf) = (b"k".to_vec(), CF_WRITE.to_owned());
+    assert_eq!(store.raw_increment(ctx.clone(), cf.clone(), k.clone(), 5).unwrap(), 5);
+    assert_eq!(store.raw_increment(ctx.clone(), cf.clone(), k.clone(), -7).unwrap(), -2);
+    assert_eq!(
+        store.raw_get(ctx.clone(), cf.clone(), k.clone()).unwrap(),
+        Some(vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe])
+    );
+    assert!(
+        store
+            .raw_increment(ctx.clone(), cf.clone(), k.clone(), i64::MIN)
+            .is_err()
+    );
+
+    // Only 8-byte values are counters.
+    store
+        .raw_put(ctx.clone(), cf.clone(), k.clone(), b"v".to_vec())
+        .unwrap();
+    assert!(
+        store
+            .raw_increment(ctx.clone(), cf.clone(), k.clone(), 1)
+            .is_err()
+    );
+}
+
+#[test]
+fn test_txn_store_rawkv_concurrent_increment() {
+    const THREAD_NUM: usize = 4;
+    const INC_PER_THREAD: usize = 100;
+
+    let store = SyncStorage::new(&Config::default());
+    let mut threads = vec![];
+    for _ in 0..THREAD_NUM {
+        let store = store.clone();
+        threads.push(thread::spawn(move || {
+            let mut values = vec![];
+            for _ in 0..INC_PER_THREAD {
+                let value = store
+                    .raw_increment(Context::new(), "".to_owned(), b"k".to_vec(), 1)
+                    .unwrap();
+                values.push(value);
+            }
+            values
+        }));
+    }
+    let mut values = vec![];
+    for t in threads {
+        values.extend(t.join().unwrap());
+    }
+    // Every increment sees the result of all the increments before it.
+    values.sort();
+    let expect: Vec<i64> = (1..(THREAD_NUM * INC_PER_THREAD) as i64 + 1).collect();
+    assert_eq!(values, expect);
+}
+
+#[test]
+fn test_txn_store_rawkv_concurrent_compare_and_swap_and_put() {
+    const PUT_NUM: usize = 200;
+
+    // The putter puts `p{i}` in order, while the other thread keeps swapping the value it sees
+    // `v` with `{v}c`. So once `p{i}` is put, the value is `p{i}` or `p{i}c` until the next put,
+    // unless a swap which read an older value is written after the put.
+    let index = |value: &[u8]| -> usize {
+        String::from_utf8(value[1..7].to_vec())
+            .unwrap()
+            .parse()
+            .unwrap()
+    };
+    let store = SyncStorage::new(&Config::default());
+    let (cf, k) = ("".to_owned(), b"k".to_vec());
+    store
+        .raw_put(Context::new(), cf.clone(), k.clone(), b"p000000".to_vec())
+        .unwrap();
+
+    let stopped = Arc::new(AtomicBool::new(false));
+    let swapper = {
+        let (store, stopped) = (store.clone(), stopped.clone());
+        let (cf, k) = (cf.clone(), k.clone());
+        thread::spawn(move || {
+            let mut current = b"p000000".to_vec();
+            while !stopped.load(Ordering::SeqCst) {
+                let mut value = current.clone();
+                value.push(b'c');
+                let (previous_value, succeed) = store
+                    .raw_compare_and_swap(
+                        Context::new(),
+                        cf.clone(),
+                        k.clone(),
+                        Some(current),
+                        value.clone(),
+                    )
+                    .unwrap();
+                current = if succeed { value } else { previous_value.unwrap() };
+            }
+        })
+    };
+
+    for i in 1..PUT_NUM {
+        store
+            .raw_put(Context::new(), cf.clone(), k.clone(), format!("p{:06}", i).into_bytes())
+            .unwrap();
+        let value = store
+            .raw_get(Context::new(), cf.clone(), k.clone())
+            .unwrap()
+            .unwrap();
+        assert_eq!(index(&value), i, "{:?}", value);
+    }
+    stopped.store(true, Ordering::SeqCst);
+    swapper.join().unwrap();
+}
+
+#[test]
+fn test_txn_store_rawkv_ttl() {
+    let config = Config {
+        enable_ttl: true,
+        ..Config::default()
+    };
+    let store = SyncStorage::new(&config);
+    let ctx = Context::new();
+    store
+        .raw_put(ctx.clone(), "".to_owned(), b"k1".to_vec(), b"v1".to_vec())
+        .unwrap();
+    store
+        .raw_put_with_ttl(ctx.clone(), "".to_owned(), b"k2".to_vec(), b"v2".to_vec(), 1)
+        .unwrap();
+    store
+        .raw_put_with_ttl(ctx.clone(), "".to_owned(), b"k3".to_vec(), b"v3".to_vec(), 100)
+        .unwrap();
+    assert_eq!(
+        store.raw_get(ctx.clone(), "".to_owned(), b"k2".to_vec()).unwrap(),
+        Some(b"v2".to_vec())
+    );
+    assert_eq!(
+        store.raw_get_key_ttl(ctx.clone(), "".to_owned(), b"k1".to_vec()).unwrap(),
+        Some(0)
+    );
+    let ttl = store
+        .raw_get_key_ttl(ctx.clone(), "".to_owned(), b"k3".to_vec())
+        .unwrap()
+        .unwrap();
+    assert!(ttl > 0 && ttl <= 100);
+    assert_eq!(
+        store.raw_get_key_ttl(ctx.clone(), "".to_owned(), b"k4".to_vec()).unwrap(),
+        None
+    );
+
+    // Expired keys are invisible.
+    thread::sleep(Duration::from_secs(2));
+    assert_eq!(store.raw_get(ctx.clone(), "".to_owned(), b"k2".to_vec()).unwrap(), None);
+    assert_eq!(
+        store.raw_get_key_ttl(ctx.clone(), "".to_owned(), b"k2".to_vec()).unwrap(),
+        None
+    );
+    let pairs: Vec<_> = store
+        .raw_scan(ctx.clone(), "".to_owned(), b"".to_vec(), 10)
+        .unwrap()
+        .into_iter()
+        .map(|p| p.unwrap())
+        .collect();
+    assert_eq!(
+        pairs,
+        vec![
+            (b"k1".to_vec(), b"v1".to_vec()),
+            (b"k3".to_vec(), b"v3".to_vec()),
+        ]
+    );
+}
+
+#[test]
+fn test_txn_store_rawkv_ttl_disabled() {
+    let store = SyncStorage::new(&Config::default());
+    let ctx = Context::new();
+    assert!(
+        store
+            .raw_put_with_ttl(ctx.clone(), "".to_owned(), b"k1".to_vec(), b"v1".to_vec(), 10)
+            .is_err()
+    );
+    assert!(store.raw_get_key_ttl(ctx.clone(), "".to_owned(), b"k1".to_vec()).is_err());
+    store
+        .raw_put(ctx.clone(), "".to_owned(), b"k1".to_vec(), b"v1".to_vec())
+        .unwrap();
+    assert_eq!(
+        store.raw_get(ctx.clone(), "".to_owned(), b"k1".to_vec()).unwrap(),
+        Some(b"v1".to_vec())
+    );
+}
+
 #[test]
 fn test_txn_store_lock_primary() {
     let store = AssertionStorage::default();
@@ -854,7 +1422,7 @@ fn test_conflict_commands_on_fault_engine() {
         )
         .unwrap();
     async_storage
-        .async_cleanup(storage.ctx.clone(), make_key(&k), start_ts, box |_| {})
+        .async_cleanup(storage.ctx.clone(), make_key(&k), start_ts, 0, box |_| {})
         .unwrap();
     async_storage
         .async_rollback(